@@ -27,8 +27,9 @@ chown -R vmail:vmail /data/mail
 chown -R opendkim:opendkim /data/dkim
 
 echo "[entrypoint] INFO: starting services"
-# Trap signals for clean container shutdown
-trap 'trap - TERM; kill 0' SIGTERM SIGINT SIGQUIT
+# Trap signals for clean container shutdown; the admin server finishes its
+# in-flight requests and config writes before it exits, so wait for it
+trap 'trap "" TERM; kill 0; wait $MAILSERVER_PID; exit 0' SIGTERM SIGINT SIGQUIT
 
 # Postfix and Dovecot log to stdout directly (via /dev/stdout)
 # tee duplicates output to /var/log/mail.log for fail2ban monitoring
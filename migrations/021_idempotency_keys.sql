@@ -0,0 +1,14 @@
+-- Idempotency keys for the JSON API create endpoints.
+-- A retried POST carrying the same Idempotency-Key (scoped to the caller's
+-- credentials) replays the stored response instead of repeating the create.
+CREATE TABLE IF NOT EXISTS api_idempotency_keys (
+    id              BIGSERIAL PRIMARY KEY,
+    token           TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    response_status INTEGER NOT NULL,
+    response_body   TEXT NOT NULL,
+    created_at      TEXT NOT NULL,
+    UNIQUE(token, idempotency_key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_api_idempotency_keys_created_at ON api_idempotency_keys(created_at);
@@ -0,0 +1,4 @@
+-- Optional GeoIP enrichment of tracking-pixel opens (populated only when a
+-- GeoLite2 database is configured).
+ALTER TABLE pixel_opens ADD COLUMN IF NOT EXISTS country TEXT;
+ALTER TABLE pixel_opens ADD COLUMN IF NOT EXISTS city TEXT;
@@ -0,0 +1,20 @@
+-- Per-domain quiet hours for marketing mail.
+-- quiet_hours_start / quiet_hours_end are local "HH:MM" times in
+-- quiet_hours_timezone (an IANA name such as "Europe/Berlin"); empty values
+-- disable the schedule.  Marketing mail submitted inside the window is held
+-- in scheduled_messages and reinjected once the window closes.
+ALTER TABLE domains
+    ADD COLUMN IF NOT EXISTS quiet_hours_start TEXT DEFAULT '',
+    ADD COLUMN IF NOT EXISTS quiet_hours_end TEXT DEFAULT '',
+    ADD COLUMN IF NOT EXISTS quiet_hours_timezone TEXT DEFAULT 'UTC';
+
+CREATE TABLE IF NOT EXISTS scheduled_messages (
+    id         BIGSERIAL PRIMARY KEY,
+    sender     TEXT NOT NULL,
+    recipients TEXT NOT NULL,
+    body       TEXT NOT NULL,
+    send_at    TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_scheduled_messages_send_at ON scheduled_messages(send_at);
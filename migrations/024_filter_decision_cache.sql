@@ -0,0 +1,11 @@
+-- Short-lived verdicts cached by the content filter (e.g. DNSBL results per
+-- client IP) so repeated messages do not repeat external lookups.
+-- Rows are ignored once expires_at has passed and the whole table is cleared
+-- whenever mail configs are regenerated.
+CREATE TABLE IF NOT EXISTS filter_decision_cache (
+    cache_key  TEXT PRIMARY KEY,
+    verdict    TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_filter_decision_cache_expires_at ON filter_decision_cache(expires_at);
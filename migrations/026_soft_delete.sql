@@ -0,0 +1,3 @@
+-- Soft delete: deleted domains and accounts stay in the recycle bin until purged.
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS deleted_at TEXT;
+ALTER TABLE accounts ADD COLUMN IF NOT EXISTS deleted_at TEXT;
@@ -0,0 +1,12 @@
+-- Hashed API keys for the JSON REST API under /api/v1.
+CREATE TABLE IF NOT EXISTS api_keys (
+    id           BIGSERIAL PRIMARY KEY,
+    name         TEXT NOT NULL,
+    key_prefix   TEXT NOT NULL UNIQUE,
+    key_hash     TEXT NOT NULL,
+    read_only    BOOLEAN NOT NULL DEFAULT TRUE,
+    created_by   TEXT NOT NULL DEFAULT '',
+    created_at   TEXT NOT NULL,
+    last_used_at TEXT,
+    revoked_at   TEXT
+);
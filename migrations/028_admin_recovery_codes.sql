@@ -0,0 +1,9 @@
+-- Single-use 2FA recovery codes, stored as SHA-256 digests.
+CREATE TABLE IF NOT EXISTS admin_recovery_codes (
+    id         BIGSERIAL PRIMARY KEY,
+    admin_id   BIGINT NOT NULL REFERENCES admins(id) ON DELETE CASCADE,
+    code_hash  TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    used_at    TEXT,
+    UNIQUE (admin_id, code_hash)
+);
@@ -0,0 +1,4 @@
+-- Per-domain SMTP client identity.  When helo_name is set, outbound mail from
+-- the domain leaves through a dedicated smtp transport that announces this
+-- name in HELO/EHLO instead of the server HOSTNAME.  Empty means the default.
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS helo_name TEXT DEFAULT '';
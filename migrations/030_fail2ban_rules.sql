@@ -0,0 +1,11 @@
+-- Admin-defined fail2ban detection rules.  Each regex is tested against new
+-- mail log lines; its named `ip` capture group identifies the offending client
+-- and the match counts as a failed attempt for `service`.
+CREATE TABLE IF NOT EXISTS fail2ban_rules (
+    id         BIGSERIAL PRIMARY KEY,
+    name       TEXT NOT NULL,
+    service    TEXT NOT NULL,
+    regex      TEXT NOT NULL,
+    enabled    BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TEXT NOT NULL
+);
@@ -0,0 +1,3 @@
+-- Per-account override of the disabled-account delivery policy
+-- ('reject', 'discard' or 'forward'); empty uses the global setting.
+ALTER TABLE accounts ADD COLUMN IF NOT EXISTS disabled_policy TEXT DEFAULT '';
@@ -0,0 +1,22 @@
+-- Parsed rows of DMARC aggregate (RUA) reports, one per <record>.  Rescanning
+-- an inbox replaces the rows of each report, keyed by its report_id (or the
+-- message filename when the reporter left it empty).
+CREATE TABLE IF NOT EXISTS dmarc_report_rows (
+    id            BIGSERIAL PRIMARY KEY,
+    inbox_id      BIGINT NOT NULL REFERENCES dmarc_inboxes(id) ON DELETE CASCADE,
+    report_key    TEXT NOT NULL,
+    org_name      TEXT NOT NULL DEFAULT '',
+    policy_domain TEXT NOT NULL DEFAULT '',
+    date_begin    TEXT NOT NULL DEFAULT '',
+    date_end      TEXT NOT NULL DEFAULT '',
+    source_ip     TEXT NOT NULL DEFAULT '',
+    message_count BIGINT NOT NULL DEFAULT 0,
+    disposition   TEXT NOT NULL DEFAULT '',
+    dkim_result   TEXT NOT NULL DEFAULT '',
+    spf_result    TEXT NOT NULL DEFAULT '',
+    header_from   TEXT NOT NULL DEFAULT '',
+    created_at    TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS dmarc_report_rows_inbox_report_idx
+    ON dmarc_report_rows (inbox_id, report_key);
@@ -0,0 +1,36 @@
+-- DMARC aggregate reports sent by this server (RFC 7489 §7.2).  Domains opt in
+-- with dmarc_reports_enabled; the content filter then records the SPF/DKIM
+-- outcome of every inbound message for them, and a daily job reports each
+-- UTC day's results to the sending domain's published rua= address.
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS dmarc_reports_enabled BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE IF NOT EXISTS dmarc_auth_results (
+    id               BIGSERIAL PRIMARY KEY,
+    recipient_domain TEXT NOT NULL,
+    header_from      TEXT NOT NULL,
+    source_ip        TEXT NOT NULL,
+    dkim_domain      TEXT NOT NULL DEFAULT '',
+    dkim_result      TEXT NOT NULL DEFAULT 'none',
+    spf_domain       TEXT NOT NULL DEFAULT '',
+    spf_result       TEXT NOT NULL DEFAULT 'none',
+    report_date      TEXT NOT NULL,
+    created_at       TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS dmarc_auth_results_day_idx
+    ON dmarc_auth_results (recipient_domain, report_date, header_from);
+
+-- One row per (reporting domain, From: domain, day), so a report is never
+-- sent twice.  status is 'sent', or 'skipped' when no usable rua= exists.
+CREATE TABLE IF NOT EXISTS dmarc_sent_reports (
+    id            BIGSERIAL PRIMARY KEY,
+    domain        TEXT NOT NULL,
+    header_from   TEXT NOT NULL,
+    report_date   TEXT NOT NULL,
+    report_id     TEXT NOT NULL,
+    rua           TEXT NOT NULL DEFAULT '',
+    message_count BIGINT NOT NULL DEFAULT 0,
+    status        TEXT NOT NULL,
+    created_at    TEXT NOT NULL,
+    UNIQUE (domain, header_from, report_date)
+);
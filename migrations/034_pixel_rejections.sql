@@ -0,0 +1,10 @@
+-- Pixel requests whose message ID failed validation or the allowlist.
+CREATE TABLE IF NOT EXISTS pixel_rejections (
+    id BIGSERIAL PRIMARY KEY,
+    message_id TEXT NOT NULL,
+    client_ip TEXT,
+    reason TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_pixel_rejections_created_at ON pixel_rejections (created_at);
@@ -0,0 +1,15 @@
+-- Destructive admin actions waiting for a second admin's approval.
+CREATE TABLE IF NOT EXISTS pending_approvals (
+    id BIGSERIAL PRIMARY KEY,
+    action TEXT NOT NULL,
+    target_id BIGINT,
+    params TEXT NOT NULL DEFAULT '{}',
+    requested_by TEXT NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    decided_by TEXT,
+    decided_at TEXT,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_pending_approvals_status ON pending_approvals (status, expires_at);
@@ -0,0 +1,3 @@
+-- How the SMTP client secures each outbound relay connection:
+-- 'none', 'starttls' or 'smtps' (implicit TLS, usually port 465).
+ALTER TABLE outbound_relays ADD COLUMN IF NOT EXISTS tls_mode TEXT NOT NULL DEFAULT 'starttls';
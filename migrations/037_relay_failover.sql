@@ -0,0 +1,6 @@
+-- Failover order among relays assigned to the same pattern (lowest first),
+-- and the outcome of the periodic reachability probe.
+ALTER TABLE outbound_relays ADD COLUMN IF NOT EXISTS priority INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE outbound_relays ADD COLUMN IF NOT EXISTS last_ok TEXT;
+ALTER TABLE outbound_relays ADD COLUMN IF NOT EXISTS last_error TEXT;
+ALTER TABLE outbound_relays ADD COLUMN IF NOT EXISTS last_checked TEXT;
@@ -0,0 +1,16 @@
+-- Webhook deliveries waiting for their first attempt or a retry.  Each
+-- attempt is still recorded in webhook_logs; a row is removed once the
+-- delivery succeeds or is given up.
+CREATE TABLE IF NOT EXISTS webhook_queue (
+    id BIGSERIAL PRIMARY KEY,
+    url TEXT NOT NULL,
+    request_body TEXT NOT NULL,
+    sender TEXT NOT NULL DEFAULT '',
+    subject TEXT NOT NULL DEFAULT '',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_attempt_at TEXT NOT NULL,
+    last_error TEXT NOT NULL DEFAULT '',
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_queue_next_attempt ON webhook_queue (next_attempt_at);
@@ -0,0 +1,7 @@
+-- The DKIM selector and keys replaced by the last rotation.  They stay
+-- published until the grace period after dkim_rotated_at has passed, so mail
+-- signed with the old key still verifies.
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS dkim_selector_prev TEXT;
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS dkim_private_key_prev TEXT;
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS dkim_public_key_prev TEXT;
+ALTER TABLE domains ADD COLUMN IF NOT EXISTS dkim_rotated_at TEXT;
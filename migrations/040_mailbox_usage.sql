@@ -0,0 +1,8 @@
+-- Last measured Maildir size per mailbox.  Scanning a Maildir is expensive,
+-- so the content filter and the admin UI reuse a measurement until it is
+-- older than the cache TTL.
+CREATE TABLE IF NOT EXISTS mailbox_usage (
+    address     TEXT PRIMARY KEY,
+    bytes       BIGINT NOT NULL,
+    measured_at TEXT NOT NULL
+);
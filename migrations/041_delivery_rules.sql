@@ -0,0 +1,18 @@
+-- Per-account rules applied by the incoming content filter, in position
+-- order.  A rule compares one message field (subject, from, to, cc, body or
+-- a header name) with a pattern and either files the message into
+-- target_folder or adds the header line in header.
+CREATE TABLE IF NOT EXISTS delivery_rules (
+    id BIGSERIAL PRIMARY KEY,
+    account_id BIGINT NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL DEFAULT 0,
+    match_field TEXT NOT NULL,
+    match_op TEXT NOT NULL,
+    pattern TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target_folder TEXT NOT NULL DEFAULT '',
+    header TEXT NOT NULL DEFAULT '',
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_delivery_rules_account ON delivery_rules (account_id, position);
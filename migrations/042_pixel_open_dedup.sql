@@ -0,0 +1,3 @@
+-- Repeat opens from the same client within the dedup window are looked up
+-- by message, client address and time.
+CREATE INDEX IF NOT EXISTS idx_pixel_opens_message_client ON pixel_opens (message_id, client_ip, opened_at);
@@ -0,0 +1,12 @@
+-- Successful IMAP and POP3 logins, read from the Dovecot log by the
+-- fail2ban watcher.  Events older than the retention period are pruned.
+CREATE TABLE IF NOT EXISTS login_events (
+    id         BIGSERIAL PRIMARY KEY,
+    username   TEXT NOT NULL,
+    service    TEXT NOT NULL,
+    ip_address TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_login_events_username ON login_events (username, created_at);
+CREATE INDEX IF NOT EXISTS idx_login_events_created_at ON login_events (created_at);
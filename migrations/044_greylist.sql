@@ -0,0 +1,14 @@
+-- Greylisting triplets seen by the incoming content filter.  `passed_at` is
+-- set once a retry arrives after the delay; stale rows are pruned by the
+-- greylist cleanup.
+CREATE TABLE IF NOT EXISTS greylist (
+    client_ip  TEXT NOT NULL,
+    sender     TEXT NOT NULL,
+    recipient  TEXT NOT NULL,
+    first_seen TEXT NOT NULL,
+    passed_at  TEXT,
+    last_seen  TEXT NOT NULL,
+    PRIMARY KEY (client_ip, sender, recipient)
+);
+
+CREATE INDEX IF NOT EXISTS idx_greylist_last_seen ON greylist (last_seen);
@@ -0,0 +1,16 @@
+-- Messages submitted by each account, counted over a rolling window by the
+-- outgoing content filter to enforce the sending limits.
+CREATE TABLE IF NOT EXISTS send_counters (
+    id         BIGSERIAL PRIMARY KEY,
+    account_id BIGINT NOT NULL,
+    recipients INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_send_counters_account ON send_counters (account_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_send_counters_created_at ON send_counters (created_at);
+
+-- Per-account overrides of the global sending limits; NULL uses the global
+-- setting and 0 means unlimited.
+ALTER TABLE accounts ADD COLUMN IF NOT EXISTS send_limit_messages INTEGER;
+ALTER TABLE accounts ADD COLUMN IF NOT EXISTS send_limit_recipients INTEGER;
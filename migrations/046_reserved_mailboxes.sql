@@ -0,0 +1,19 @@
+-- Role addresses every domain should answer (RFC 2142), e.g. postmaster@
+-- and abuse@.  target is a mailbox name on the same domain or a full
+-- address; an empty target or a disabled role adds no alias.
+CREATE TABLE IF NOT EXISTS domain_reserved_mailboxes (
+    domain_id  BIGINT NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    role       TEXT NOT NULL,
+    target     TEXT NOT NULL DEFAULT '',
+    enabled    BOOLEAN NOT NULL DEFAULT TRUE,
+    updated_at TEXT NOT NULL,
+    PRIMARY KEY (domain_id, role)
+);
+
+-- Existing domains get the defaults of new ones: postmaster waits for a
+-- target and the other roles go to postmaster.
+INSERT INTO domain_reserved_mailboxes (domain_id, role, target, enabled, updated_at)
+SELECT d.id, r.role, CASE WHEN r.role = 'postmaster' THEN '' ELSE 'postmaster' END, TRUE, NOW()::TEXT
+FROM domains d
+CROSS JOIN (VALUES ('postmaster'), ('abuse'), ('hostmaster'), ('webmaster'), ('dmarc')) AS r(role)
+ON CONFLICT DO NOTHING;
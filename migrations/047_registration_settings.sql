@@ -0,0 +1,34 @@
+-- Per-domain self-registration rules, replacing the registration_enabled
+-- and registration_username_regex columns of domains (left in place but no
+-- longer read).
+CREATE TABLE IF NOT EXISTS registration_settings (
+    domain_id           BIGINT PRIMARY KEY REFERENCES domains(id) ON DELETE CASCADE,
+    enabled             BOOLEAN NOT NULL DEFAULT FALSE,
+    username_regex      TEXT NOT NULL DEFAULT '',
+    min_password_length INTEGER NOT NULL DEFAULT 8,
+    invite_only         BOOLEAN NOT NULL DEFAULT FALSE,
+    updated_at          TEXT
+);
+
+INSERT INTO registration_settings (domain_id, enabled, username_regex, updated_at)
+SELECT id, COALESCE(registration_enabled, FALSE), COALESCE(registration_username_regex, ''), NOW()::TEXT
+FROM domains
+ON CONFLICT (domain_id) DO NOTHING;
+
+-- Single-use invite codes for invite-only registration.
+CREATE TABLE IF NOT EXISTS registration_invites (
+    id         BIGSERIAL PRIMARY KEY,
+    domain_id  BIGINT NOT NULL REFERENCES domains(id) ON DELETE CASCADE,
+    code       TEXT UNIQUE NOT NULL,
+    note       TEXT NOT NULL DEFAULT '',
+    created_at TEXT NOT NULL,
+    used_at    TEXT,
+    used_by    TEXT NOT NULL DEFAULT ''
+);
+
+CREATE INDEX IF NOT EXISTS idx_registration_invites_domain ON registration_invites (domain_id);
+
+-- Registration attempts are counted per source IP like failed logins.
+INSERT INTO fail2ban_settings (service, max_attempts, ban_duration_minutes, find_time_minutes, enabled, created_at, updated_at)
+VALUES ('register', 5, 60, 60, true, NOW()::TEXT, NOW()::TEXT)
+ON CONFLICT (service) DO NOTHING;
@@ -0,0 +1,11 @@
+-- The last TOTP time step each admin signed in with and the client it came
+-- from, so a code cannot be used again from another client or after a newer
+-- one.
+ALTER TABLE admins ADD COLUMN IF NOT EXISTS totp_last_step BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE admins ADD COLUMN IF NOT EXISTS totp_last_ip TEXT NOT NULL DEFAULT '';
+
+-- Wrong or reused TOTP codes are counted per admin and lock verification
+-- once max_attempts is reached within find_time_minutes.
+INSERT INTO fail2ban_settings (service, max_attempts, ban_duration_minutes, find_time_minutes, enabled, created_at, updated_at)
+VALUES ('totp', 5, 15, 15, true, NOW()::TEXT, NOW()::TEXT)
+ON CONFLICT (service) DO NOTHING;
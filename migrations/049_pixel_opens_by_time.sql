@@ -0,0 +1,2 @@
+-- Opens per day for /api/stats are counted by time.
+CREATE INDEX IF NOT EXISTS idx_pixel_opens_opened_at ON pixel_opens (opened_at);
@@ -0,0 +1,14 @@
+-- WebDAV write locks (RFC 4918 class 2).  `owner` and `filename` name the
+-- locked file like webdav_files; a lock outlives neither its timeout nor the file.
+CREATE TABLE IF NOT EXISTS webdav_locks (
+    token TEXT PRIMARY KEY,
+    owner TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    exclusive BOOLEAN NOT NULL DEFAULT TRUE,
+    lock_owner TEXT NOT NULL DEFAULT '',
+    timeout_secs BIGINT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS idx_webdav_locks_file ON webdav_locks (lower(owner), filename);
+CREATE INDEX IF NOT EXISTS idx_webdav_locks_expires_at ON webdav_locks (expires_at);
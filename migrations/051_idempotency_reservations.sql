@@ -0,0 +1,7 @@
+-- Idempotency keys are reserved before the create runs, so a concurrent retry
+-- finds the reservation instead of creating a second entity.  The response is
+-- filled in once the first request finishes, and `request_hash` identifies the
+-- body the key was first used with.
+ALTER TABLE api_idempotency_keys ADD COLUMN IF NOT EXISTS request_hash TEXT NOT NULL DEFAULT '';
+ALTER TABLE api_idempotency_keys ALTER COLUMN response_status DROP NOT NULL;
+ALTER TABLE api_idempotency_keys ALTER COLUMN response_body DROP NOT NULL;
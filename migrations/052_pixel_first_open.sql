@@ -0,0 +1,7 @@
+-- When a tracked message was first opened.  Set with a conditional UPDATE so
+-- that exactly one of several concurrent opens is reported as the first.
+ALTER TABLE tracked_messages ADD COLUMN IF NOT EXISTS first_opened_at TEXT;
+
+UPDATE tracked_messages t SET first_opened_at = o.opened_at
+FROM (SELECT message_id, MIN(opened_at) AS opened_at FROM pixel_opens GROUP BY message_id) o
+WHERE o.message_id = t.message_id AND t.first_opened_at IS NULL;
@@ -0,0 +1,23 @@
+-- Rejected pixel requests are counted per day and reason instead of stored one
+-- row per request, so forged pixel URLs cannot grow the table without bound.
+-- The last rejected ID and client are kept as a sample.
+CREATE TABLE IF NOT EXISTS pixel_rejection_counts (
+    day TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    last_message_id TEXT NOT NULL DEFAULT '',
+    last_client_ip TEXT NOT NULL DEFAULT '',
+    last_seen_at TEXT NOT NULL,
+    PRIMARY KEY (day, reason)
+);
+
+INSERT INTO pixel_rejection_counts (day, reason, count, last_message_id, last_client_ip, last_seen_at)
+SELECT DISTINCT ON (LEFT(created_at, 10), reason)
+       LEFT(created_at, 10), reason,
+       COUNT(*) OVER (PARTITION BY LEFT(created_at, 10), reason),
+       message_id, COALESCE(client_ip, ''), created_at
+FROM pixel_rejections
+ORDER BY LEFT(created_at, 10), reason, id DESC
+ON CONFLICT (day, reason) DO NOTHING;
+
+DROP TABLE IF EXISTS pixel_rejections;
@@ -0,0 +1,382 @@
+//! Attachment scanning in the content filter.
+//!
+//! Every message passing the filter has its MIME tree walked for attachments.
+//! An attachment is blocked when its file extension, its declared or sniffed
+//! content type is on the blocklist, or when it is larger than the
+//! per-attachment limit.  Depending on the `attachment_action` setting the
+//! message is then bounced with the reason, or the offending parts are
+//! replaced by a short text note.  The blocklists and the limit live in
+//! settings; [`AttachmentPolicy::load`] is the one place that reads them.
+
+use log::{info, warn};
+
+use crate::db::Database;
+
+pub const ACTION_SETTING: &str = "attachment_action";
+pub const BLOCKED_EXTENSIONS_SETTING: &str = "attachment_blocked_extensions";
+pub const BLOCKED_TYPES_SETTING: &str = "attachment_blocked_types";
+pub const MAX_SIZE_SETTING: &str = "attachment_max_size_mb";
+
+pub const DEFAULT_BLOCKED_EXTENSIONS: &str =
+    "exe,scr,pif,com,bat,cmd,vbs,vbe,js,jse,wsf,wsh,hta,cpl,msc,msi,jar,lnk,ps1,reg";
+pub const DEFAULT_BLOCKED_TYPES: &str =
+    "application/x-msdownload,application/x-msdos-program,application/x-dosexec,application/hta";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AttachmentAction {
+    Off,
+    Reject,
+    Strip,
+}
+
+impl AttachmentAction {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(AttachmentAction::Off),
+            "reject" => Some(AttachmentAction::Reject),
+            "strip" => Some(AttachmentAction::Strip),
+            _ => None,
+        }
+    }
+}
+
+/// The attachment policy from settings.
+#[derive(Clone, Debug)]
+pub struct AttachmentPolicy {
+    pub action: AttachmentAction,
+    /// Lower-cased extensions without the leading dot.
+    pub blocked_extensions: Vec<String>,
+    /// Lower-cased MIME types.
+    pub blocked_types: Vec<String>,
+    /// Largest allowed decoded attachment in MB; 0 means unlimited.
+    pub max_size_mb: u64,
+}
+
+impl Default for AttachmentPolicy {
+    fn default() -> Self {
+        AttachmentPolicy {
+            action: AttachmentAction::Off,
+            blocked_extensions: parse_list(DEFAULT_BLOCKED_EXTENSIONS),
+            blocked_types: parse_list(DEFAULT_BLOCKED_TYPES),
+            max_size_mb: 0,
+        }
+    }
+}
+
+impl AttachmentPolicy {
+    pub fn load(db: &Database) -> Self {
+        let defaults = AttachmentPolicy::default();
+        AttachmentPolicy {
+            action: db
+                .get_setting(ACTION_SETTING)
+                .and_then(|v| AttachmentAction::parse(&v))
+                .unwrap_or(defaults.action),
+            blocked_extensions: db
+                .get_setting(BLOCKED_EXTENSIONS_SETTING)
+                .map(|v| parse_list(&v))
+                .unwrap_or(defaults.blocked_extensions),
+            blocked_types: db
+                .get_setting(BLOCKED_TYPES_SETTING)
+                .map(|v| parse_list(&v))
+                .unwrap_or(defaults.blocked_types),
+            max_size_mb: db
+                .get_setting(MAX_SIZE_SETTING)
+                .and_then(|v| v.trim().parse().ok())
+                .unwrap_or(defaults.max_size_mb),
+        }
+    }
+}
+
+/// Split a comma or whitespace separated list, lower-cased, with leading
+/// dots removed from extensions.
+pub fn parse_list(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|item| item.trim_start_matches('.').to_ascii_lowercase())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// One attachment found in the MIME tree.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub filename: String,
+    /// The `Content-Type` the sender declared.
+    pub content_type: String,
+    /// The type recognised from the content's leading bytes, if any.
+    pub detected_type: Option<&'static str>,
+    /// Decoded size in bytes.
+    pub size: usize,
+    /// Byte range of the whole part, headers included, in the message.
+    range: (usize, usize),
+}
+
+/// Content types recognised by their leading bytes.
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"MZ", "application/x-msdownload"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\xca\xfe\xba\xbe", "application/java-vm"),
+    (b"#!", "text/x-shellscript"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (
+        b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1",
+        "application/x-ole-storage",
+    ),
+    (b"%PDF-", "application/pdf"),
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF8", "image/gif"),
+];
+
+fn sniff(content: &[u8]) -> Option<&'static str> {
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| content.starts_with(magic))
+        .map(|(_, mime)| *mime)
+}
+
+/// The file name a part declares in `Content-Disposition` or `Content-Type`.
+pub(crate) fn filename(part: &mailparse::ParsedMail) -> Option<String> {
+    part.get_content_disposition()
+        .params
+        .get("filename")
+        .or_else(|| part.ctype.params.get("name"))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+fn collect(part: &mailparse::ParsedMail, base: usize, out: &mut Vec<Attachment>) {
+    if part.ctype.mimetype.starts_with("multipart/") {
+        for sub in &part.subparts {
+            collect(sub, base, out);
+        }
+        return;
+    }
+    let is_attachment =
+        part.get_content_disposition().disposition == mailparse::DispositionType::Attachment;
+    let name = filename(part);
+    if !is_attachment && name.is_none() {
+        return;
+    }
+    let content = part.get_body_raw().unwrap_or_default();
+    // `raw_bytes` borrows from the message, so its offset locates the part.
+    let start = part.raw_bytes.as_ptr() as usize - base;
+    out.push(Attachment {
+        filename: name.unwrap_or_default(),
+        content_type: part.ctype.mimetype.to_ascii_lowercase(),
+        detected_type: sniff(&content),
+        size: content.len(),
+        range: (start, start + part.raw_bytes.len()),
+    });
+}
+
+/// Every attachment of `email`, in MIME tree order.
+pub fn attachments(email: &str) -> Vec<Attachment> {
+    let Ok(parsed) = mailparse::parse_mail(email.as_bytes()) else {
+        return Vec::new();
+    };
+    let mut out = Vec::new();
+    collect(&parsed, email.as_ptr() as usize, &mut out);
+    out
+}
+
+/// Why `attachment` is blocked under `policy`, if it is.
+pub fn blocked_reason(attachment: &Attachment, policy: &AttachmentPolicy) -> Option<String> {
+    let name = attachment
+        .filename
+        .trim_end_matches(['.', ' '])
+        .to_ascii_lowercase();
+    if let Some((_, ext)) = name.rsplit_once('.') {
+        if policy.blocked_extensions.iter().any(|b| b == ext) {
+            return Some(format!("blocked file type .{}", ext));
+        }
+    }
+    let types = std::iter::once(attachment.content_type.as_str()).chain(attachment.detected_type);
+    for mime in types {
+        if policy.blocked_types.iter().any(|b| b == mime) {
+            return Some(format!("blocked content type {}", mime));
+        }
+    }
+    if policy.max_size_mb > 0 && attachment.size as u64 > policy.max_size_mb * 1024 * 1024 {
+        return Some(format!(
+            "larger than the {} MB attachment limit",
+            policy.max_size_mb
+        ));
+    }
+    None
+}
+
+/// Result of scanning a message.
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Clean,
+    /// The message with every blocked attachment replaced by a note.
+    Stripped(String),
+    /// Bounce reason for the sender.
+    Rejected(String),
+}
+
+/// The text part that takes the place of a stripped attachment.
+fn placeholder(filename: &str, reason: &str, eol: &str) -> String {
+    format!(
+        "Content-Type: text/plain; charset=utf-8{eol}Content-Disposition: inline{eol}{eol}The attachment \"{}\" was removed by the mail server: {}.{eol}",
+        filename.replace(['\r', '\n', '"'], ""),
+        reason,
+        eol = eol
+    )
+}
+
+/// Scan `email` against `policy`, logging every blocked attachment.
+pub fn scan(email: &str, policy: &AttachmentPolicy, message_id: &str) -> Verdict {
+    if policy.action == AttachmentAction::Off {
+        return Verdict::Clean;
+    }
+    let blocked: Vec<(Attachment, String)> = attachments(email)
+        .into_iter()
+        .filter_map(|a| blocked_reason(&a, policy).map(|reason| (a, reason)))
+        .collect();
+    let Some((first, first_reason)) = blocked.first() else {
+        return Verdict::Clean;
+    };
+    // A message that is nothing but the attachment cannot be stripped.
+    let whole_message = blocked.iter().any(|(a, _)| a.range.0 == 0);
+    if policy.action == AttachmentAction::Reject || whole_message {
+        for (a, reason) in &blocked {
+            warn!(
+                "[attachment-policy] blocked attachment message_id={} filename={:?} type={} size={}: {}",
+                message_id, a.filename, a.content_type, a.size, reason
+            );
+        }
+        return Verdict::Rejected(format!(
+            "attachment \"{}\" is not allowed ({})",
+            first.filename, first_reason
+        ));
+    }
+
+    let eol = if email.contains("\r\n") { "\r\n" } else { "\n" };
+    let mut stripped = email.to_string();
+    // Back to front so earlier ranges stay valid.
+    for (a, reason) in blocked.iter().rev() {
+        info!(
+            "[attachment-policy] stripped attachment message_id={} filename={:?} type={} size={}: {}",
+            message_id, a.filename, a.content_type, a.size, reason
+        );
+        let mut note = placeholder(&a.filename, reason, eol);
+        if !email[..a.range.1].ends_with(eol) {
+            note.truncate(note.len() - eol.len());
+        }
+        stripped.replace_range(a.range.0..a.range.1, &note);
+    }
+    Verdict::Stripped(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = concat!(
+        "From: a@example.com\r\n",
+        "Subject: files\r\n",
+        "MIME-Version: 1.0\r\n",
+        "Content-Type: multipart/mixed; boundary=\"b1\"\r\n",
+        "\r\n",
+        "--b1\r\n",
+        "Content-Type: text/plain\r\n",
+        "\r\n",
+        "See attached.\r\n",
+        "--b1\r\n",
+        "Content-Type: application/octet-stream; name=\"invoice.pdf.exe\"\r\n",
+        "Content-Disposition: attachment; filename=\"invoice.pdf.exe\"\r\n",
+        "Content-Transfer-Encoding: base64\r\n",
+        "\r\n",
+        "TVqQAAMAAAAEAAAA\r\n",
+        "--b1\r\n",
+        "Content-Type: application/pdf\r\n",
+        "Content-Disposition: attachment; filename=\"report.pdf\"\r\n",
+        "\r\n",
+        "%PDF-1.4 tiny\r\n",
+        "--b1--\r\n",
+    );
+
+    fn policy(action: AttachmentAction) -> AttachmentPolicy {
+        AttachmentPolicy {
+            action,
+            ..AttachmentPolicy::default()
+        }
+    }
+
+    #[test]
+    fn enumerates_attachments_with_detected_types() {
+        let found = attachments(MESSAGE);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].filename, "invoice.pdf.exe");
+        assert_eq!(found[0].content_type, "application/octet-stream");
+        assert_eq!(found[0].detected_type, Some("application/x-msdownload"));
+        assert_eq!(found[0].size, 12);
+        assert_eq!(found[1].detected_type, Some("application/pdf"));
+        assert_eq!(
+            parse_list(".EXE, js\nscr"),
+            vec!["exe".to_string(), "js".to_string(), "scr".to_string()]
+        );
+    }
+
+    #[test]
+    fn blocks_by_extension_type_and_size() {
+        let mut p = policy(AttachmentAction::Reject);
+        let found = attachments(MESSAGE);
+        assert_eq!(
+            blocked_reason(&found[0], &p).as_deref(),
+            Some("blocked file type .exe")
+        );
+        assert_eq!(blocked_reason(&found[1], &p), None);
+
+        // Renamed executables are still caught by their content.
+        p.blocked_extensions.clear();
+        assert_eq!(
+            blocked_reason(&found[0], &p).as_deref(),
+            Some("blocked content type application/x-msdownload")
+        );
+
+        let mut big = found[1].clone();
+        big.size = 3 * 1024 * 1024;
+        p.max_size_mb = 2;
+        assert_eq!(
+            blocked_reason(&big, &p).as_deref(),
+            Some("larger than the 2 MB attachment limit")
+        );
+        assert_eq!(
+            scan(MESSAGE, &policy(AttachmentAction::Off), "id"),
+            Verdict::Clean
+        );
+    }
+
+    #[test]
+    fn rejects_or_strips_blocked_attachments() {
+        assert_eq!(
+            scan(MESSAGE, &policy(AttachmentAction::Reject), "id"),
+            Verdict::Rejected(
+                "attachment \"invoice.pdf.exe\" is not allowed (blocked file type .exe)".into()
+            )
+        );
+
+        let Verdict::Stripped(stripped) = scan(MESSAGE, &policy(AttachmentAction::Strip), "id")
+        else {
+            panic!("expected the attachment to be stripped");
+        };
+        assert!(!stripped.contains("TVqQ"));
+        assert!(stripped.contains(
+            "The attachment \"invoice.pdf.exe\" was removed by the mail server: blocked file type .exe.\r\n--b1\r\n"
+        ));
+        let remaining = attachments(&stripped);
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].filename, "report.pdf");
+
+        let single = "Content-Type: application/x-msdownload; name=\"a.exe\"\r\n\r\nMZ\r\n";
+        assert!(matches!(
+            scan(single, &policy(AttachmentAction::Strip), "id"),
+            Verdict::Rejected(_)
+        ));
+    }
+}
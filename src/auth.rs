@@ -10,7 +10,10 @@ type HmacSha1 = Hmac<Sha1>;
 
 pub fn verify_password(password: &str, hash: &str) -> bool {
     debug!("[auth] verifying password hash");
-    let result = verify(password, hash).unwrap_or(false);
+    let result = match split_scheme(hash) {
+        Some((scheme, encoded)) => verify_with_scheme(password, scheme, encoded),
+        None => verify(password, hash).unwrap_or(false),
+    };
     if result {
         debug!("[auth] password verification succeeded");
     } else {
@@ -26,6 +29,166 @@ pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
     Ok(result)
 }
 
+// ── Imported password hashes ──
+//
+// Locally created passwords are stored as bare bcrypt hashes.  Hashes imported
+// from other systems keep their Dovecot scheme prefix (e.g. `{SSHA}...`), which
+// is written verbatim to the Dovecot passwd file and used by `verify_password`.
+
+/// Split a `{SCHEME}hash` string into its upper-cased scheme and the hash.
+fn split_scheme(hash: &str) -> Option<(&str, &str)> {
+    let rest = hash.strip_prefix('{')?;
+    let end = rest.find('}')?;
+    Some((&rest[..end], &rest[end + 1..]))
+}
+
+fn verify_with_scheme(password: &str, scheme: &str, encoded: &str) -> bool {
+    match scheme.to_ascii_uppercase().as_str() {
+        "BLF-CRYPT" => verify(password, encoded).unwrap_or(false),
+        "SHA512-CRYPT" | "SHA256-CRYPT" | "MD5-CRYPT" | "CRYPT" => {
+            pwhash::unix::verify(password, encoded)
+        }
+        "SSHA" => verify_salted_digest::<Sha1>(password, encoded),
+        "SSHA256" => verify_salted_digest::<sha2::Sha256>(password, encoded),
+        "SSHA512" => verify_salted_digest::<sha2::Sha512>(password, encoded),
+        "ARGON2I" | "ARGON2ID" => {
+            use argon2::{Argon2, PasswordHash, PasswordVerifier};
+            PasswordHash::new(encoded)
+                .map(|parsed| {
+                    Argon2::default()
+                        .verify_password(password.as_bytes(), &parsed)
+                        .is_ok()
+                })
+                .unwrap_or(false)
+        }
+        other => {
+            warn!("[auth] unsupported password scheme {{{}}}", other);
+            false
+        }
+    }
+}
+
+/// Verify an LDAP-style salted digest: base64(digest(password + salt) + salt).
+fn verify_salted_digest<D: sha2::Digest>(password: &str, encoded: &str) -> bool {
+    use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+    let raw = match BASE64.decode(encoded.trim()) {
+        Ok(r) => r,
+        Err(_) => return false,
+    };
+    let digest_len = <D as sha2::Digest>::output_size();
+    if raw.len() <= digest_len {
+        return false;
+    }
+    let (expected, salt) = raw.split_at(digest_len);
+    let mut hasher = D::new();
+    hasher.update(password.as_bytes());
+    hasher.update(salt);
+    hasher.finalize().as_slice() == expected
+}
+
+/// Scheme of a bare modular-crypt hash (`$6$...`, `$argon2id$...`).
+/// `None` means bcrypt, which is stored without a prefix.
+fn modular_crypt_scheme(hash: &str) -> Result<Option<&'static str>, String> {
+    let id = hash
+        .strip_prefix('$')
+        .and_then(|h| h.split('$').next())
+        .unwrap_or("");
+    match id {
+        "2a" | "2b" | "2y" => Ok(None),
+        "6" => Ok(Some("SHA512-CRYPT")),
+        "5" => Ok(Some("SHA256-CRYPT")),
+        "1" => Ok(Some("MD5-CRYPT")),
+        "argon2id" => Ok(Some("ARGON2ID")),
+        "argon2i" => Ok(Some("ARGON2I")),
+        _ => Err("unrecognized password hash format".to_string()),
+    }
+}
+
+/// Validate a password hash imported from another system and normalize it
+/// to the form stored in `accounts.password_hash`.
+///
+/// Accepts Dovecot-style `{SCHEME}` prefixes (`{CRYPT}`, `{BLF-CRYPT}`,
+/// `{SHA512-CRYPT}`, `{SHA256-CRYPT}`, `{MD5-CRYPT}`, `{SSHA}`, `{SSHA256}`,
+/// `{SSHA512}`, `{ARGON2I}`, `{ARGON2ID}`) as well as bare modular-crypt
+/// strings such as `$6$...` or argon2 PHC strings.
+pub fn normalize_imported_hash(input: &str) -> Result<String, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("password hash is empty".to_string());
+    }
+    if input.contains(':') || input.chars().any(char::is_whitespace) {
+        return Err("password hash contains invalid characters".to_string());
+    }
+    let (scheme, encoded) = match split_scheme(input) {
+        Some((scheme, encoded)) => (scheme.to_ascii_uppercase(), encoded),
+        None => {
+            return match modular_crypt_scheme(input)? {
+                None => Ok(input.to_string()),
+                Some(scheme) => normalize_imported_hash(&format!("{{{}}}{}", scheme, input)),
+            };
+        }
+    };
+    if encoded.is_empty() {
+        return Err(format!("{{{}}} hash is empty", scheme));
+    }
+    let expect_id = |ids: &[&str]| -> Result<(), String> {
+        let id = encoded
+            .strip_prefix('$')
+            .and_then(|h| h.split('$').next())
+            .unwrap_or("");
+        if ids.contains(&id) {
+            Ok(())
+        } else {
+            Err(format!("hash does not match the {{{}}} scheme", scheme))
+        }
+    };
+    match scheme.as_str() {
+        "BLF-CRYPT" => {
+            expect_id(&["2a", "2b", "2y"])?;
+            Ok(encoded.to_string())
+        }
+        "SHA512-CRYPT" => expect_id(&["6"]).map(|_| format!("{{{}}}{}", scheme, encoded)),
+        "SHA256-CRYPT" => expect_id(&["5"]).map(|_| format!("{{{}}}{}", scheme, encoded)),
+        "MD5-CRYPT" => expect_id(&["1"]).map(|_| format!("{{{}}}{}", scheme, encoded)),
+        "CRYPT" => {
+            // {CRYPT} wraps whatever crypt(3) understands; name the real scheme.
+            if encoded.starts_with('$') {
+                normalize_imported_hash(encoded)
+            } else if encoded.len() == 13
+                && encoded
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '/')
+            {
+                Ok(format!("{{CRYPT}}{}", encoded))
+            } else {
+                Err("unrecognized {CRYPT} hash".to_string())
+            }
+        }
+        "SSHA" | "SSHA256" | "SSHA512" => {
+            use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+            let digest_len = match scheme.as_str() {
+                "SSHA" => 20,
+                "SSHA256" => 32,
+                _ => 64,
+            };
+            let raw = BASE64
+                .decode(encoded)
+                .map_err(|_| format!("{{{}}} hash is not valid base64", scheme))?;
+            if raw.len() <= digest_len {
+                return Err(format!("{{{}}} hash is too short", scheme));
+            }
+            Ok(format!("{{{}}}{}", scheme, encoded))
+        }
+        "ARGON2I" | "ARGON2ID" => {
+            expect_id(&[if scheme == "ARGON2I" { "argon2i" } else { "argon2id" }])?;
+            argon2::PasswordHash::new(encoded)
+                .map_err(|e| format!("invalid argon2 hash: {}", e))?;
+            Ok(format!("{{{}}}{}", scheme, encoded))
+        }
+        other => Err(format!("unsupported password scheme {{{}}}", other)),
+    }
+}
+
 pub fn generate_totp_secret() -> String {
     info!("[auth] generating new TOTP secret");
     let mut rng = rand::thread_rng();
@@ -35,13 +198,15 @@ pub fn generate_totp_secret() -> String {
     encoded
 }
 
-pub fn verify_totp(secret_base32: &str, code: &str) -> bool {
+/// Check a TOTP code against the current time step and its neighbours,
+/// returning the step it belongs to so callers can refuse reused codes.
+pub fn verify_totp(secret_base32: &str, code: &str) -> Option<u64> {
     debug!("[auth] verifying TOTP code");
     let secret = match BASE32.decode(secret_base32.as_bytes()) {
         Ok(s) => s,
         Err(e) => {
             error!("[auth] failed to decode TOTP secret: {}", e);
-            return false;
+            return None;
         }
     };
 
@@ -49,21 +214,27 @@ pub fn verify_totp(secret_base32: &str, code: &str) -> bool {
         Ok(d) => d.as_secs(),
         Err(e) => {
             error!("[auth] system clock is before UNIX epoch: {}", e);
-            return false;
+            return None;
         }
     };
-    let current_step = now / 30;
-
-    for offset in [0, 1, u64::MAX] {
-        let step = current_step.wrapping_add(offset);
-        let generated = totp_code(&secret, step);
-        if generated == code {
-            info!("[auth] TOTP verification succeeded (offset={})", offset);
-            return true;
-        }
+    let step = matching_totp_step(&secret, code, now / 30);
+    match step {
+        Some(step) => info!(
+            "[auth] TOTP verification succeeded (offset={})",
+            step as i64 - (now / 30) as i64
+        ),
+        None => warn!("[auth] TOTP verification failed — code did not match any window"),
     }
-    warn!("[auth] TOTP verification failed — code did not match any window");
-    false
+    step
+}
+
+/// The step among `current_step` and its neighbours that `code` was
+/// generated for.
+fn matching_totp_step(secret: &[u8], code: &str, current_step: u64) -> Option<u64> {
+    [0, 1, u64::MAX]
+        .into_iter()
+        .map(|offset| current_step.wrapping_add(offset))
+        .find(|&step| totp_code(secret, step) == code)
 }
 
 fn totp_code(secret: &[u8], step: u64) -> String {
@@ -90,3 +261,176 @@ pub fn totp_uri(secret: &str, username: &str) -> String {
         username, secret
     )
 }
+
+// ── 2FA recovery codes ──
+//
+// Codes are shown to the admin once and stored as SHA-256 digests: they are
+// random enough that a slow password hash buys nothing, and a plain digest
+// lets login look a code up directly.
+
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Lower-case letters and digits without the easily confused `i`, `l`, `o`,
+/// `0` and `1`.
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// Generate a fresh set of `xxxxx-xxxxx` recovery codes.
+pub fn generate_recovery_codes() -> Vec<String> {
+    info!("[auth] generating {} recovery codes", RECOVERY_CODE_COUNT);
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let chars: String = (0..10)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char
+                })
+                .collect();
+            format!("{}-{}", &chars[..5], &chars[5..])
+        })
+        .collect()
+}
+
+/// A `xxxx-xxxx-xxxx` registration invite code from the recovery code
+/// alphabet.
+pub fn generate_invite_code() -> String {
+    let mut rng = rand::thread_rng();
+    let chars: Vec<char> = (0..12)
+        .map(|_| RECOVERY_CODE_ALPHABET[rng.gen_range(0..RECOVERY_CODE_ALPHABET.len())] as char)
+        .collect();
+    chars
+        .chunks(4)
+        .map(|c| c.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Digest stored for a recovery code; case and the dash are ignored.
+pub fn hash_recovery_code(code: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// Split `password` into the account password and a trailing
+/// `xxxxx-xxxxx` recovery code, the way a TOTP code is appended.
+pub fn split_recovery_code(password: &str) -> Option<(&str, &str)> {
+    let at = password.len().checked_sub(11)?;
+    if at == 0 || !password.is_char_boundary(at) {
+        return None;
+    }
+    let (base, code) = password.split_at(at);
+    let shaped = code.char_indices().all(|(i, c)| {
+        if i == 5 {
+            c == '-'
+        } else {
+            c.is_ascii_alphanumeric()
+        }
+    });
+    shaped.then_some((base, code))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn imported_legacy_hashes_verify() {
+        // {SSHA} of "secret" with salt "salt": base64(sha1("secretsalt") + "salt")
+        let ssha = {
+            use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+            use sha1::Digest;
+            let mut raw = Sha1::digest(b"secretsalt").to_vec();
+            raw.extend_from_slice(b"salt");
+            format!("{{SSHA}}{}", BASE64.encode(raw))
+        };
+        let stored = normalize_imported_hash(&ssha).unwrap();
+        assert_eq!(stored, ssha);
+        assert!(verify_password("secret", &stored));
+        assert!(!verify_password("wrong", &stored));
+
+        // {CRYPT} wrapping a SHA-512 crypt hash is stored under its real scheme.
+        let sha512 = pwhash::sha512_crypt::hash("secret").unwrap();
+        let stored = normalize_imported_hash(&format!("{{CRYPT}}{}", sha512)).unwrap();
+        assert_eq!(stored, format!("{{SHA512-CRYPT}}{}", sha512));
+        assert!(verify_password("secret", &stored));
+
+        // Bare bcrypt stays bare, exactly like locally created passwords.
+        let bcrypt = hash_password("secret").unwrap();
+        assert_eq!(normalize_imported_hash(&format!("{{BLF-CRYPT}}{}", bcrypt)).unwrap(), bcrypt);
+    }
+
+    #[test]
+    fn imported_argon2_phc_hash_verifies() {
+        use argon2::password_hash::{rand_core::OsRng, PasswordHasher, SaltString};
+        let salt = SaltString::generate(&mut OsRng);
+        let phc = argon2::Argon2::default()
+            .hash_password(b"secret", &salt)
+            .unwrap()
+            .to_string();
+        let stored = normalize_imported_hash(&phc).unwrap();
+        assert_eq!(stored, format!("{{ARGON2ID}}{}", phc));
+        assert!(verify_password("secret", &stored));
+        assert!(!verify_password("wrong", &stored));
+    }
+
+    #[test]
+    fn unknown_hash_formats_are_rejected() {
+        assert!(normalize_imported_hash("").is_err());
+        assert!(normalize_imported_hash("plaintext-password").is_err());
+        assert!(normalize_imported_hash("{PLAIN}secret").is_err());
+        assert!(normalize_imported_hash("{SSHA}not base64!").is_err());
+        assert!(normalize_imported_hash("{SSHA}c2hvcnQ=").is_err());
+        assert!(normalize_imported_hash("{SHA512-CRYPT}$1$abc$def").is_err());
+        assert!(normalize_imported_hash("$99$whatever").is_err());
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_and_hash_case_insensitively() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+        let unique: std::collections::HashSet<_> = codes.iter().collect();
+        assert_eq!(unique.len(), RECOVERY_CODE_COUNT);
+        for code in &codes {
+            assert_eq!(code.len(), 11);
+            assert_eq!(
+                split_recovery_code(&format!("secret{}", code)),
+                Some(("secret", code.as_str()))
+            );
+        }
+        assert_eq!(
+            hash_recovery_code("abcde-fghjk"),
+            hash_recovery_code("ABCDEFGHJK")
+        );
+        assert_ne!(
+            hash_recovery_code("abcde-fghjk"),
+            hash_recovery_code("abcde-fghjm")
+        );
+    }
+
+    #[test]
+    fn totp_codes_match_the_rfc_6238_vectors_and_report_their_step() {
+        // RFC 6238 appendix B, SHA-1, truncated to six digits.
+        let secret = b"12345678901234567890";
+        assert_eq!(totp_code(secret, 59 / 30), "287082");
+        assert_eq!(totp_code(secret, 1111111109 / 30), "081804");
+        let step = 1111111109 / 30;
+        assert_eq!(matching_totp_step(secret, "081804", step), Some(step));
+        assert_eq!(matching_totp_step(secret, "081804", step + 1), Some(step));
+        assert_eq!(matching_totp_step(secret, "081804", step - 1), Some(step));
+        assert_eq!(matching_totp_step(secret, "081804", step + 2), None);
+    }
+
+    #[test]
+    fn split_recovery_code_rejects_totp_style_passwords() {
+        assert_eq!(split_recovery_code("secret123456"), None);
+        assert_eq!(split_recovery_code("abcde-fghjk"), None);
+        assert_eq!(
+            split_recovery_code("pässwordabcde-fghjk"),
+            Some(("pässword", "abcde-fghjk"))
+        );
+    }
+}
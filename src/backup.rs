@@ -0,0 +1,103 @@
+//! Configuration backups: every admin-managed table in one JSON file.
+//!
+//! `mailserver backup` and `/settings/backup.json` write the file;
+//! `mailserver restore` and the upload form on the settings page read it
+//! back.  It holds domains with their DKIM keys, accounts, aliases,
+//! forwardings, reserved mailboxes, registration rules and invites,
+//! delivery, footer, tracking and unsubscribe rules, outbound relays, rate
+//! limits, fail2ban lists and settings:
+//!
+//! ```json
+//! {"format": 1, "schema": "047_registration_settings",
+//!  "created_at": "2026-01-01 00:00:00",
+//!  "tables": {"domains": [{"id": 1, "domain": "example.com", ...}], ...}}
+//! ```
+//!
+//! Nothing is redacted: DKIM private keys, password hashes and relay
+//! passwords are in the file, so it must be kept as safe as the database.
+//! Restoring upserts every row by its key in one transaction and deletes
+//! nothing.  A backup from a newer version is refused; one from an older
+//! version restores, with columns added since then at their defaults.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::db::{Database, RestoredTable};
+
+/// Version of the file layout.
+pub const FORMAT: u64 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct Backup {
+    pub format: u64,
+    /// Latest migration of the database the backup was made from.
+    pub schema: String,
+    pub created_at: String,
+    /// Rows of each table, keyed by table name.
+    pub tables: Map<String, Value>,
+}
+
+/// The configuration of `db` as a backup file.
+pub fn export(db: &Database) -> Result<String, String> {
+    let backup = Backup {
+        format: FORMAT,
+        schema: db.schema_version(),
+        created_at: chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+        tables: db
+            .export_backup_tables()?
+            .into_iter()
+            .map(|(name, rows)| (name.to_string(), Value::Array(rows)))
+            .collect(),
+    };
+    serde_json::to_string_pretty(&backup).map_err(|e| e.to_string())
+}
+
+/// Read a backup file, refusing one this version cannot restore into a
+/// database at migration `local_schema`.
+pub fn parse(input: &str, local_schema: &str) -> Result<Backup, String> {
+    let backup: Backup =
+        serde_json::from_str(input).map_err(|e| format!("not a mailserver backup: {}", e))?;
+    if backup.format != FORMAT {
+        return Err(format!(
+            "unsupported backup format {} (this version reads format {})",
+            backup.format, FORMAT
+        ));
+    }
+    if backup.schema.as_str() > local_schema {
+        return Err(format!(
+            "the backup was made by a newer version (migration {}, this server is at {}); upgrade first",
+            backup.schema, local_schema
+        ));
+    }
+    Ok(backup)
+}
+
+/// Restore the backup file `input` into `db`.
+pub fn restore(db: &Database, input: &str) -> Result<Vec<RestoredTable>, String> {
+    let backup = parse(input, &db.schema_version())?;
+    db.restore_backup_tables(&backup.tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCAL: &str = "047_registration_settings";
+
+    fn file(format: u64, schema: &str) -> String {
+        format!(
+            r#"{{"format": {}, "schema": "{}", "created_at": "2026-01-01 00:00:00", "tables": {{"domains": []}}}}"#,
+            format, schema
+        )
+    }
+
+    #[test]
+    fn backups_from_newer_versions_or_formats_are_refused() {
+        let backup = parse(&file(1, "046_reserved_mailboxes"), LOCAL).unwrap();
+        assert_eq!(backup.tables["domains"], Value::Array(Vec::new()));
+        assert!(parse(&file(1, LOCAL), LOCAL).is_ok());
+        assert!(parse(&file(1, "048_later"), LOCAL).is_err());
+        assert!(parse(&file(2, LOCAL), LOCAL).is_err());
+        assert!(parse("domain,active\n", LOCAL).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+//! Server-wide compliance disclaimer for outbound mail.
+//!
+//! Unlike the marketing footer, which is scoped by sender patterns and rules,
+//! the compliance disclaimer is added by the outbound filter to every message
+//! once `compliance_footer_enabled` is on: to each HTML part and each plain
+//! text part.  It goes below the message (and any marketing footer) by
+//! default, or above it with `compliance_footer_position = top`.  Mail whose
+//! sender and recipients are all on local domains is left alone unless
+//! `compliance_footer_skip_internal` is turned off.
+//!
+//! The HTML is sanitised before use: only simple formatting tags survive,
+//! links keep just an `http`, `https` or `mailto` target, and scripts,
+//! styles and event handlers are dropped.
+
+use log::debug;
+
+use crate::db::Database;
+
+pub const ENABLED_SETTING: &str = "compliance_footer_enabled";
+pub const HTML_SETTING: &str = "compliance_footer_html";
+pub const TEXT_SETTING: &str = "compliance_footer_text";
+pub const POSITION_SETTING: &str = "compliance_footer_position";
+pub const SKIP_INTERNAL_SETTING: &str = "compliance_footer_skip_internal";
+
+/// Tags kept by [`sanitize_html`]; all others are removed, keeping their text.
+const ALLOWED_TAGS: &[&str] = &[
+    "a", "b", "br", "div", "em", "hr", "i", "li", "ol", "p", "small", "span", "strong", "u", "ul",
+];
+/// Tags removed together with everything inside them.
+const DROPPED_ELEMENTS: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "head", "title", "template", "svg", "math",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Position {
+    Top,
+    Bottom,
+}
+
+impl Position {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "top" => Some(Position::Top),
+            "bottom" => Some(Position::Bottom),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Position::Top => "top",
+            Position::Bottom => "bottom",
+        }
+    }
+}
+
+/// The disclaimer as stored in settings, sanitised.
+#[derive(Clone, Debug)]
+pub struct ComplianceFooter {
+    pub html: String,
+    pub text: String,
+    pub position: Position,
+    pub skip_internal: bool,
+}
+
+impl ComplianceFooter {
+    /// The configured disclaimer, or `None` when it is off or empty.
+    pub fn load(db: &Database) -> Option<Self> {
+        if db.get_setting(ENABLED_SETTING).as_deref() != Some("true") {
+            return None;
+        }
+        let footer = ComplianceFooter::new(
+            &db.get_setting(HTML_SETTING).unwrap_or_default(),
+            &db.get_setting(TEXT_SETTING).unwrap_or_default(),
+            db.get_setting(POSITION_SETTING)
+                .and_then(|v| Position::parse(&v))
+                .unwrap_or(Position::Bottom),
+            db.get_setting(SKIP_INTERNAL_SETTING).as_deref() != Some("false"),
+        );
+        if footer.html.is_empty() && footer.text.is_empty() {
+            return None;
+        }
+        Some(footer)
+    }
+
+    /// Sanitise `html` and `text`; an empty `text` is derived from the HTML.
+    pub fn new(html: &str, text: &str, position: Position, skip_internal: bool) -> Self {
+        let html = sanitize_html(html).trim().to_string();
+        let mut text = sanitize_text(text).trim().to_string();
+        if text.is_empty() {
+            text = crate::filter::strip_html_tags(&html);
+        }
+        ComplianceFooter {
+            html,
+            text,
+            position,
+            skip_internal,
+        }
+    }
+
+    /// Whether a message from `sender` to `recipients` gets the disclaimer.
+    pub fn applies(
+        &self,
+        sender: &str,
+        recipients: &[String],
+        is_local: impl Fn(&str) -> bool,
+    ) -> bool {
+        if !self.skip_internal {
+            return true;
+        }
+        let domain = |address: &str| address.rsplit_once('@').map(|(_, d)| d.to_lowercase());
+        let internal = |address: &str| domain(address).map(|d| is_local(&d)).unwrap_or(false);
+        !(internal(sender) && recipients.iter().all(|r| internal(r)))
+    }
+
+    /// `email` with the disclaimer added to every inline text and HTML part.
+    /// Parts in charsets other than UTF-8 or ASCII are left unchanged.
+    pub fn apply(&self, email: &str) -> String {
+        let parsed = match mailparse::parse_mail(email.as_bytes()) {
+            Ok(p) => p,
+            Err(e) => {
+                debug!(
+                    "[compliance-footer] failed to parse message ({}), left unchanged",
+                    e
+                );
+                return email.to_string();
+            }
+        };
+        let mut parts = Vec::new();
+        collect_text_parts(&parsed, &mut parts);
+        let mut output = email.to_string();
+        // `raw_bytes` borrows from `email`; back to front so earlier offsets stay valid.
+        for part in parts.into_iter().rev() {
+            let start = part.raw_bytes.as_ptr() as usize - email.as_ptr() as usize;
+            let end = start + part.raw_bytes.len();
+            let html = part.ctype.mimetype.eq_ignore_ascii_case("text/html");
+            let addition = if html { &self.html } else { &self.text };
+            if addition.is_empty() {
+                continue;
+            }
+            let charset = part.ctype.charset.to_ascii_lowercase();
+            if !matches!(charset.as_str(), "utf-8" | "utf8" | "us-ascii" | "") {
+                debug!(
+                    "[compliance-footer] skipping {} part in charset {}",
+                    part.ctype.mimetype, charset
+                );
+                continue;
+            }
+            let rebuilt = crate::filter::rebuild_part(&email[start..end], part, |body| {
+                Some(if html {
+                    insert_html(body, &self.html, self.position)
+                } else {
+                    insert_text(body, &self.text, self.position)
+                })
+            });
+            let Some(mut rebuilt) = rebuilt else {
+                continue;
+            };
+            if charset == "us-ascii" && !addition.is_ascii() {
+                rebuilt = upgrade_charset(&rebuilt);
+            }
+            output.replace_range(start..end, &rebuilt);
+        }
+        output
+    }
+}
+
+/// Inline `text/plain` and `text/html` parts, depth first.
+fn collect_text_parts<'a>(
+    mail: &'a mailparse::ParsedMail<'a>,
+    out: &mut Vec<&'a mailparse::ParsedMail<'a>>,
+) {
+    if mail.ctype.mimetype.starts_with("multipart/") {
+        for sub in &mail.subparts {
+            collect_text_parts(sub, out);
+        }
+        return;
+    }
+    let attachment =
+        mail.get_content_disposition().disposition == mailparse::DispositionType::Attachment;
+    let mimetype = mail.ctype.mimetype.to_ascii_lowercase();
+    if !attachment && (mimetype == "text/plain" || mimetype == "text/html") {
+        out.push(mail);
+    }
+}
+
+fn insert_html(body: &[u8], html: &str, position: Position) -> Vec<u8> {
+    let block = format!(
+        r#"<div class="compliance-footer" style="margin:16px 0;font-size:0.85em;color:#64748b;line-height:1.4;">{}</div>"#,
+        html
+    );
+    let lower = body.to_ascii_lowercase();
+    let last = |needle: &[u8]| lower.windows(needle.len()).rposition(|w| w == needle);
+    let at = match position {
+        Position::Top => crate::filter::body_content_start(&lower).unwrap_or(0),
+        Position::Bottom => last(b"</body>")
+            .or_else(|| last(b"</html>"))
+            .unwrap_or(body.len()),
+    };
+    let mut output = Vec::with_capacity(body.len() + block.len());
+    output.extend_from_slice(&body[..at]);
+    output.extend_from_slice(block.as_bytes());
+    output.extend_from_slice(&body[at..]);
+    output
+}
+
+fn insert_text(body: &[u8], text: &str, position: Position) -> Vec<u8> {
+    let eol = if body.windows(2).any(|w| w == b"\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let text = text.replace('\n', eol);
+    let mut output = Vec::with_capacity(body.len() + text.len() + 8);
+    match position {
+        Position::Top => {
+            output.extend_from_slice(format!("{}{}{}", text, eol, eol).as_bytes());
+            output.extend_from_slice(body);
+        }
+        Position::Bottom => {
+            let mut end = body.len();
+            while end > 0 && (body[end - 1] == b'\n' || body[end - 1] == b'\r') {
+                end -= 1;
+            }
+            output.extend_from_slice(&body[..end]);
+            if end > 0 {
+                output.extend_from_slice(format!("{}{}", eol, eol).as_bytes());
+            }
+            output.extend_from_slice(format!("{}{}", text, eol).as_bytes());
+        }
+    }
+    output
+}
+
+/// Relabel a rebuilt part's `charset=us-ascii` as UTF-8.
+fn upgrade_charset(part: &str) -> String {
+    let header_end = part
+        .find("\r\n\r\n")
+        .or_else(|| part.find("\n\n"))
+        .unwrap_or(part.len());
+    let lower = part[..header_end].to_ascii_lowercase();
+    let mut output = part.to_string();
+    for needle in ["charset=\"us-ascii\"", "charset=us-ascii"] {
+        if let Some(pos) = lower.find(needle) {
+            output.replace_range(pos..pos + needle.len(), "charset=utf-8");
+            break;
+        }
+    }
+    output
+}
+
+/// Keep only simple formatting markup from `input`.
+pub fn sanitize_html(input: &str) -> String {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(open) = rest.find('<') {
+        output.push_str(&rest[..open]);
+        rest = &rest[open..];
+        if rest.starts_with("<!--") {
+            rest = rest.find("-->").map(|end| &rest[end + 3..]).unwrap_or("");
+            continue;
+        }
+        let Some(close) = tag_end(rest) else {
+            output.push_str("&lt;");
+            rest = &rest[1..];
+            continue;
+        };
+        let tag = &rest[1..close];
+        rest = &rest[close + 1..];
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+        if DROPPED_ELEMENTS.contains(&name.as_str()) {
+            if !closing && !tag.ends_with('/') {
+                let end_tag = format!("</{}", name);
+                rest = rest
+                    .to_ascii_lowercase()
+                    .find(&end_tag)
+                    .and_then(|pos| rest[pos..].find('>').map(|gt| &rest[pos + gt + 1..]))
+                    .unwrap_or("");
+            }
+            continue;
+        }
+        if !ALLOWED_TAGS.contains(&name.as_str()) {
+            continue;
+        }
+        if closing {
+            output.push_str(&format!("</{}>", name));
+        } else if name == "a" {
+            match attribute(tag, "href").filter(|h| safe_url(h)) {
+                Some(href) => output.push_str(&format!("<a href=\"{}\">", escape_attr(&href))),
+                None => output.push_str("<a>"),
+            }
+        } else {
+            output.push_str(&format!("<{}>", name));
+        }
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Offset of the `>` closing the tag that starts `s`, skipping quoted values.
+fn tag_end(s: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in s.char_indices().skip(1) {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '<') => return None,
+            (None, '>') => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Value of attribute `name` in the inside of a tag.
+fn attribute(tag: &str, name: &str) -> Option<String> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(rel) = lower[from..].find(name) {
+        let pos = from + rel;
+        from = pos + name.len();
+        let preceded = lower[..pos].ends_with(|c: char| c.is_whitespace());
+        let after = tag[from..].trim_start();
+        if !preceded || !after.starts_with('=') {
+            continue;
+        }
+        let value = after[1..].trim_start();
+        return Some(match value.chars().next() {
+            Some(q @ ('"' | '\'')) => value[1..].split(q).next().unwrap_or("").to_string(),
+            _ => value
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or("")
+                .to_string(),
+        });
+    }
+    None
+}
+
+fn safe_url(url: &str) -> bool {
+    let lower = url.trim().to_ascii_lowercase();
+    ["http://", "https://", "mailto:"]
+        .iter()
+        .any(|scheme| lower.starts_with(scheme))
+}
+
+fn escape_attr(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('"', "&quot;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Drop control characters other than line breaks and tabs.
+pub fn sanitize_text(input: &str) -> String {
+    input
+        .replace("\r\n", "\n")
+        .chars()
+        .filter(|&c| c == '\n' || c == '\t' || !c.is_control())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MESSAGE: &str = concat!(
+        "From: alice@corp.example\r\n",
+        "To: bob@partner.example\r\n",
+        "Subject: quarterly numbers\r\n",
+        "MIME-Version: 1.0\r\n",
+        "Content-Type: multipart/alternative; boundary=\"b1\"\r\n",
+        "\r\n",
+        "--b1\r\n",
+        "Content-Type: text/plain; charset=utf-8\r\n",
+        "\r\n",
+        "Numbers attached.\r\n",
+        "--b1\r\n",
+        "Content-Type: text/html; charset=utf-8\r\n",
+        "Content-Transfer-Encoding: quoted-printable\r\n",
+        "\r\n",
+        "<html><body><p>Numbers attached.</p></body></html>\r\n",
+        "--b1--\r\n",
+    );
+
+    fn footer(skip_internal: bool) -> ComplianceFooter {
+        ComplianceFooter::new(
+            "<p>This message is <b>confidential</b>.</p>",
+            "",
+            Position::Bottom,
+            skip_internal,
+        )
+    }
+
+    fn is_local(domain: &str) -> bool {
+        domain == "corp.example"
+    }
+
+    #[test]
+    fn external_mail_gets_disclaimer_and_internal_mail_does_not() {
+        let disclaimer = footer(true);
+        let external = vec!["bob@partner.example".to_string()];
+        let internal = vec!["carol@corp.example".to_string()];
+        assert!(disclaimer.applies("alice@corp.example", &external, is_local));
+        assert!(!disclaimer.applies("alice@corp.example", &internal, is_local));
+        let mixed = vec![internal[0].clone(), external[0].clone()];
+        assert!(disclaimer.applies("alice@corp.example", &mixed, is_local));
+        // Internal mail is covered too once the exclusion is turned off.
+        assert!(footer(false).applies("alice@corp.example", &internal, is_local));
+
+        let out = disclaimer.apply(MESSAGE);
+        let parsed = mailparse::parse_mail(out.as_bytes()).unwrap();
+        let text = parsed.subparts[0].get_body().unwrap();
+        assert_eq!(
+            text,
+            "Numbers attached.\r\n\r\nThis message is confidential.\r\n"
+        );
+        let html = parsed.subparts[1].get_body().unwrap();
+        assert!(html.contains(
+            "<p>Numbers attached.</p><div class=\"compliance-footer\" style=\"margin:16px 0;font-size:0.85em;color:#64748b;line-height:1.4;\"><p>This message is <b>confidential</b>.</p></div></body>"
+        ));
+        assert!(out.ends_with("--b1--\r\n"));
+    }
+
+    #[test]
+    fn disclaimer_goes_above_the_message_when_placed_at_top() {
+        let footer = ComplianceFooter::new("", "Disclaimer é", Position::Top, true);
+        let email = "From: a@x.example\nContent-Type: text/plain; charset=us-ascii\n\nHello\n";
+        let out = footer.apply(email);
+        assert_eq!(
+            out,
+            "From: a@x.example\nContent-Type: text/plain; charset=utf-8\n\nDisclaimer é\n\nHello\n"
+        );
+    }
+
+    #[test]
+    fn html_is_sanitised() {
+        assert_eq!(
+            sanitize_html(
+                "<p onclick=\"x()\">Hi <a href=\"javascript:alert(1)\">x</a> <a href='https://e.example/?a=1&b=\"2\"'>y</a></p><script>alert(1)</script><!-- c --><img src=x onerror=y><style>p{}</style>end"
+            ),
+            "<p>Hi <a>x</a> <a href=\"https://e.example/?a=1&amp;b=&quot;2&quot;\">y</a></p>end"
+        );
+        assert_eq!(sanitize_html("a < b"), "a &lt; b");
+        assert_eq!(sanitize_text("line\r\nnext\u{7}"), "line\nnext");
+    }
+}
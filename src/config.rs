@@ -1,6 +1,7 @@
 use crate::db::Database;
 use chrono::Utc;
 use log::{debug, error, info, warn};
+use std::cell::RefCell;
 use std::collections::HashSet;
 use std::fs;
 use std::io::Write;
@@ -12,30 +13,50 @@ use std::os::unix::fs::OpenOptionsExt;
 
 // ── Template Loading ──
 
-fn load_template(filename: &str) -> std::io::Result<String> {
-    let mut paths = vec![
-        PathBuf::from(format!("templates/config/{}", filename)),
-        PathBuf::from(format!("/app/templates/config/{}", filename)),
-    ];
+/// Places to look for `relative` (e.g. `migrations`): the working
+/// directory, `/app`, next to the executable, and a `mailserver/`
+/// checkout below either, without duplicates.  Templates and migrations are
+/// searched in the same places.
+pub(crate) fn search_paths(relative: &str) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from(relative), Path::new("/app").join(relative)];
 
     if let Ok(exe) = std::env::current_exe() {
         if let Some(dir) = exe.parent() {
-            paths.push(dir.join("templates/config").join(filename));
-            paths.push(dir.join("mailserver/templates/config").join(filename));
+            paths.push(dir.join(relative));
+            paths.push(dir.join("mailserver").join(relative));
         }
     }
 
     if let Ok(cwd) = std::env::current_dir() {
-        paths.push(cwd.join("mailserver/templates/config").join(filename));
+        paths.push(cwd.join("mailserver").join(relative));
     }
 
     let mut seen = HashSet::new();
     paths.retain(|p| seen.insert(p.to_string_lossy().into_owned()));
+    paths
+}
+
+/// Read a config template from the first template directory that has it,
+/// falling back to the copy built into the binary when none does (or the
+/// file cannot be read), so a missing templates directory never leaves a
+/// service unconfigured.
+fn load_template(filename: &str) -> std::io::Result<String> {
+    let paths = search_paths(&format!("templates/config/{}", filename));
 
     for path in &paths {
-        if path.exists() {
-            debug!("[config] loading template from {}", path.display());
-            return fs::read_to_string(path);
+        if !path.exists() {
+            continue;
+        }
+        match fs::read_to_string(path) {
+            Ok(content) => {
+                info!(
+                    "[config] using template {} from {}",
+                    filename,
+                    path.display()
+                );
+                return Ok(content);
+            }
+            Err(e) => error!("[config] failed to read template {}: {}", path.display(), e),
         }
     }
 
@@ -46,7 +67,10 @@ fn load_template(filename: &str) -> std::io::Result<String> {
         .join(", ");
 
     if let Some(content) = embedded_template(filename) {
-        debug!("[config] using embedded template for {}", filename);
+        warn!(
+            "[config] template {} not found (checked {}); using the embedded default",
+            filename, attempted_paths
+        );
         return Ok(content.to_string());
     }
 
@@ -59,19 +83,58 @@ fn load_template(filename: &str) -> std::io::Result<String> {
     ))
 }
 
+/// Every config template, as built into the binary.
+const EMBEDDED_TEMPLATES: &[(&str, &str)] = &[
+    (
+        "postfix-main.cf.txt",
+        include_str!("../templates/config/postfix-main.cf.txt"),
+    ),
+    (
+        "postfix-master.cf.txt",
+        include_str!("../templates/config/postfix-master.cf.txt"),
+    ),
+    (
+        "dovecot.conf.txt",
+        include_str!("../templates/config/dovecot.conf.txt"),
+    ),
+    (
+        "opendkim.conf.txt",
+        include_str!("../templates/config/opendkim.conf.txt"),
+    ),
+    (
+        "openssl.cnf.txt",
+        include_str!("../templates/config/openssl.cnf.txt"),
+    ),
+];
+
 fn embedded_template(filename: &str) -> Option<&'static str> {
-    match filename {
-        "postfix-main.cf.txt" => {
-            Some(include_str!("../templates/config/postfix-main.cf.txt"))
-        }
-        "postfix-master.cf.txt" => {
-            Some(include_str!("../templates/config/postfix-master.cf.txt"))
+    EMBEDDED_TEMPLATES
+        .iter()
+        .find(|(name, _)| *name == filename)
+        .map(|(_, content)| *content)
+}
+
+/// Write the built-in templates into `dir` for customization, creating it if
+/// needed.  Existing files are left alone.  Returns each template's path and
+/// whether it was written.
+pub fn dump_templates(dir: &Path) -> std::io::Result<Vec<(PathBuf, bool)>> {
+    fs::create_dir_all(dir)?;
+    let mut results = Vec::new();
+    for (name, content) in EMBEDDED_TEMPLATES {
+        let path = dir.join(name);
+        if path.exists() {
+            warn!(
+                "[config] {} already exists, not overwriting it",
+                path.display()
+            );
+            results.push((path, false));
+            continue;
         }
-        "dovecot.conf.txt" => Some(include_str!("../templates/config/dovecot.conf.txt")),
-        "opendkim.conf.txt" => Some(include_str!("../templates/config/opendkim.conf.txt")),
-        "openssl.cnf.txt" => Some(include_str!("../templates/config/openssl.cnf.txt")),
-        _ => None,
+        fs::write(&path, content)?;
+        info!("[config] wrote template {}", path.display());
+        results.push((path, true));
     }
+    Ok(results)
 }
 
 fn safe_filename(name: &str) -> String {
@@ -140,6 +203,49 @@ fn dovecot_config_version_line() -> String {
     String::new()
 }
 
+// ── Writing ──
+
+/// A generated file captured by [`preview_all_configs`].
+struct PreviewFile {
+    path: String,
+    content: String,
+    /// Written with owner-only permissions; its contents are not shown.
+    secret: bool,
+}
+
+thread_local! {
+    /// Set while [`preview_all_configs`] renders: generated files are
+    /// collected here instead of being written.
+    static PREVIEW: RefCell<Option<Vec<PreviewFile>>> = const { RefCell::new(None) };
+}
+
+fn previewing() -> bool {
+    PREVIEW.with(|p| p.borrow().is_some())
+}
+
+/// Collect `content` instead of writing it when a preview is running.
+fn capture_preview(path: &str, content: &str, secret: bool) -> bool {
+    PREVIEW.with(|p| match p.borrow_mut().as_mut() {
+        Some(files) => {
+            files.push(PreviewFile {
+                path: path.to_string(),
+                content: content.to_string(),
+                secret,
+            });
+            true
+        }
+        None => false,
+    })
+}
+
+/// Write a generated config file, or capture it during a preview.
+fn write_config(path: &str, content: &str) -> std::io::Result<()> {
+    if capture_preview(path, content, false) {
+        return Ok(());
+    }
+    fs::write(path, content)
+}
+
 /// Write content to a file with secure permissions (0600 - owner read/write only)
 /// This is used for sensitive files like DKIM private keys and password databases
 ///
@@ -153,6 +259,9 @@ fn write_secure_file(path: &str, content: &str) -> std::io::Result<()> {
     use std::fs::{OpenOptions, Permissions};
     use std::os::unix::fs::PermissionsExt;
 
+    if capture_preview(path, content, true) {
+        return Ok(());
+    }
     let mut file = OpenOptions::new()
         .write(true)
         .create(true)
@@ -177,6 +286,9 @@ fn write_secure_file(path: &str, content: &str) -> std::io::Result<()> {
 /// WARNING: This does not enforce secure file permissions
 #[cfg(not(unix))]
 fn write_secure_file(path: &str, content: &str) -> std::io::Result<()> {
+    if capture_preview(path, content, true) {
+        return Ok(());
+    }
     warn!(
         "[config] Writing {} without secure permissions - platform does not support Unix file modes",
         path
@@ -189,6 +301,9 @@ fn set_dovecot_passwd_permissions(path: &str) -> std::io::Result<()> {
     use std::fs::Permissions;
     use std::os::unix::fs::PermissionsExt;
 
+    if previewing() {
+        return Ok(());
+    }
     std::fs::set_permissions(path, Permissions::from_mode(0o640))?;
     let output = Command::new("chown")
         .args(["root:dovecot", path])
@@ -209,6 +324,17 @@ pub fn generate_all_configs(db: &Database, hostname: &str) {
         "[config] generating all configuration files for hostname={}",
         hostname
     );
+    render_configs(db, hostname);
+    generate_maildir_subscriptions(db);
+    // Cached filter verdicts may depend on the old configuration.
+    db.clear_filter_decision_cache();
+    postmap_files();
+    reload_services();
+    info!("[config] all configuration files generated successfully");
+}
+
+/// Generate every Postfix, Dovecot and OpenDKIM file.
+fn render_configs(db: &Database, hostname: &str) {
     generate_postfix_main_cf(db, hostname);
     generate_postfix_master_cf(db);
     generate_virtual_domains(db);
@@ -216,15 +342,77 @@ pub fn generate_all_configs(db: &Database, hostname: &str) {
     generate_virtual_aliases(db);
     generate_recipient_bcc_maps(db);
     generate_sender_login_maps(db);
+    generate_recipient_access(db);
     generate_transport_maps(db);
+    generate_tls_policy_maps(db);
+    generate_sender_transport_maps(db);
     generate_sasl_passwd(db);
     generate_dovecot_conf(hostname);
     generate_dovecot_passwd(db);
-    generate_opendkim_conf();
+    generate_opendkim_conf(db);
     generate_opendkim_tables(db);
-    postmap_files();
-    reload_services();
-    info!("[config] all configuration files generated successfully");
+}
+
+/// A generated config file that differs from the copy on disk.
+pub struct ConfigChange {
+    pub path: String,
+    /// Unified diff from the file on disk to the generated one.
+    pub diff: String,
+}
+
+/// Render every config file in memory and compare it with the file on
+/// disk, without writing anything, running postmap or reloading services.
+/// Files holding credentials or keys are reported without their contents.
+pub fn preview_all_configs(db: &Database, hostname: &str) -> Vec<ConfigChange> {
+    info!(
+        "[config] previewing configuration files for hostname={}",
+        hostname
+    );
+    PREVIEW.with(|p| *p.borrow_mut() = Some(Vec::new()));
+    render_configs(db, hostname);
+    let files = PREVIEW.with(|p| p.borrow_mut().take()).unwrap_or_default();
+
+    let mut changes = Vec::new();
+    for file in files {
+        let current = match fs::read_to_string(&file.path) {
+            Ok(content) => Some(content),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => {
+                warn!("[config] cannot read {} to compare: {}", file.path, e);
+                changes.push(ConfigChange {
+                    diff: format!("cannot read {}: {}\n", file.path, e),
+                    path: file.path,
+                });
+                continue;
+            }
+        };
+        let old_label = if current.is_some() {
+            file.path.as_str()
+        } else {
+            "/dev/null"
+        };
+        let new_label = format!("{} (generated)", file.path);
+        let diff = crate::config_diff::unified(
+            old_label,
+            &new_label,
+            current.as_deref().unwrap_or(""),
+            &file.content,
+        );
+        let diff = match diff {
+            None if current.is_some() => continue,
+            _ if file.secret => format!(
+                "--- {}\n+++ {}\n(contents not shown: the file holds credentials or keys)\n",
+                old_label, new_label
+            ),
+            Some(diff) => diff,
+            None => format!("--- {}\n+++ {}\n(new empty file)\n", old_label, new_label),
+        };
+        changes.push(ConfigChange {
+            path: file.path,
+            diff,
+        });
+    }
+    changes
 }
 
 pub fn generate_postfix_main_cf(db: &Database, hostname: &str) {
@@ -283,21 +471,34 @@ milter_default_action = accept"#
 
     let assignments = db.get_active_relay_assignments_with_relay();
     let has_assignments = !assignments.is_empty();
+    // transport_maps also routes disabled accounts to the discard transport.
+    let has_discards = !disabled_maps(db).discard.is_empty();
     let has_auth = assignments.iter().any(|(r, _)| r.auth_type != "none");
 
     let relay_config = if has_auth {
         r#"transport_maps = texthash:/etc/postfix/transport_maps
+smtp_tls_policy_maps = texthash:/etc/postfix/tls_policy
 smtp_sasl_auth_enable = yes
 smtp_sasl_password_maps = texthash:/etc/postfix/sasl_passwd
 smtp_sasl_security_options = noanonymous
 smtp_sasl_tls_security_options = noanonymous"#
             .to_string()
     } else if has_assignments {
+        "transport_maps = texthash:/etc/postfix/transport_maps\nsmtp_tls_policy_maps = texthash:/etc/postfix/tls_policy"
+            .to_string()
+    } else if has_discards {
         "transport_maps = texthash:/etc/postfix/transport_maps".to_string()
     } else {
         "# No outbound relay configured".to_string()
     };
 
+    let sender_identity_config = if build_sender_identity_entries(&db.list_domains()).is_empty() {
+        "# No per-domain HELO identities configured".to_string()
+    } else {
+        "sender_dependent_default_transport_maps = texthash:/etc/postfix/sender_transport"
+            .to_string()
+    };
+
     let maillog_file_line = if is_docker() {
         "maillog_file = /dev/stdout"
     } else {
@@ -311,16 +512,26 @@ smtp_sasl_tls_security_options = noanonymous"#
         .replace("{{ milter_config }}", &milter_config)
         .replace("{{ rbl_checks }}", &rbl_checks)
         .replace("{{ relay_config }}", &relay_config)
+        .replace("{{ sender_identity_config }}", &sender_identity_config)
         .replace("{{ message_size_limit }}", &message_size_limit)
         .replace("{{ maillog_file_line }}", maillog_file_line);
 
-    match fs::write("/etc/postfix/main.cf", config) {
+    let problems = crate::relay_guard::audit_main_cf(&config);
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("[config] main.cf would allow open relay: {}", problem);
+        }
+        error!("[config] REFUSING to write /etc/postfix/main.cf; fix the template and regenerate");
+        return;
+    }
+
+    match write_config("/etc/postfix/main.cf", &config) {
         Ok(_) => debug!("[config] wrote /etc/postfix/main.cf"),
         Err(e) => error!("[config] failed to write /etc/postfix/main.cf: {}", e),
     }
 }
 
-pub fn generate_postfix_master_cf(_db: &Database) {
+pub fn generate_postfix_master_cf(db: &Database) {
     info!("[config] generating /etc/postfix/master.cf");
     let template = match load_template("postfix-master.cf.txt") {
         Ok(t) => t,
@@ -333,9 +544,15 @@ pub fn generate_postfix_master_cf(_db: &Database) {
         }
     };
 
-    let config = template.replace("{{ generated_at }}", &generated_at());
+    let helo_transports = build_helo_transports(&db.list_domains());
+    let assignments = db.get_active_relay_assignments_with_relay();
+    let relay_transports = build_relay_transports(&relay_chains(&assignments));
+    let config = template
+        .replace("{{ generated_at }}", &generated_at())
+        .replace("{{ helo_transports }}", &helo_transports)
+        .replace("{{ relay_transports }}", &relay_transports);
 
-    match fs::write("/etc/postfix/master.cf", config) {
+    match write_config("/etc/postfix/master.cf", &config) {
         Ok(_) => debug!("[config] wrote /etc/postfix/master.cf"),
         Err(e) => error!("[config] failed to write /etc/postfix/master.cf: {}", e),
     }
@@ -351,7 +568,7 @@ pub fn generate_virtual_domains(db: &Database) {
             let _ = writeln!(lines, "{} OK", d.domain);
         }
     }
-    match fs::write("/etc/postfix/virtual_domains", lines) {
+    match write_config("/etc/postfix/virtual_domains", &lines) {
         Ok(_) => debug!(
             "[config] wrote /etc/postfix/virtual_domains ({} domains)",
             domains.len()
@@ -363,16 +580,28 @@ pub fn generate_virtual_domains(db: &Database) {
     }
 }
 
+/// Map entries for disabled accounts under the configured delivery policy.
+fn disabled_maps(db: &Database) -> crate::delivery_policy::DisabledMaps {
+    crate::delivery_policy::build_disabled_maps(
+        &db.list_all_accounts_with_domain(),
+        &db.list_account_disabled_policies(),
+        &crate::delivery_policy::DeliveryPolicy::load(db),
+    )
+}
+
 pub fn generate_virtual_mailboxes(db: &Database) {
     info!("[config] generating /etc/postfix/vmailbox");
     let accounts = db.list_all_accounts_with_domain();
+    // Disabled accounts whose mail is discarded must still be valid recipients.
+    let discard = disabled_maps(db).discard;
     let mut lines = generated_header();
     use std::fmt::Write;
     for a in &accounts {
-        if !a.active {
-            continue;
-        }
         if let Some(ref domain) = a.domain_name {
+            let address = format!("{}@{}", a.username, domain).to_lowercase();
+            if !a.active && !discard.contains(&address) {
+                continue;
+            }
             let _ = writeln!(
                 lines,
                 "{}@{} {}/{}/Maildir/",
@@ -380,7 +609,7 @@ pub fn generate_virtual_mailboxes(db: &Database) {
             );
         }
     }
-    match fs::write("/etc/postfix/vmailbox", lines) {
+    match write_config("/etc/postfix/vmailbox", &lines) {
         Ok(_) => debug!(
             "[config] wrote /etc/postfix/vmailbox ({} accounts)",
             accounts.len()
@@ -393,15 +622,15 @@ pub fn generate_virtual_mailboxes(db: &Database) {
 ///
 /// Rules:
 /// 1. Catch-all entries (`@domain`) are placed **before** specific entries (`user@domain`).
-///    In LMDB the last-written value for a key wins, so specific entries — written last —
-///    always override any same-key catch-all.
+///    Each alias key is written once, with the alias [`resolve_alias`] picks for it, so
+///    the file never depends on how Postfix treats duplicate keys.
 /// 2. For every account whose domain has a catch-all alias/forwarding but that does **not**
 ///    already have an explicit alias or forwarding, an identity entry
 ///    (`user@domain → user@domain`) is injected.  This prevents Postfix from falling
 ///    through to the catch-all for real mailboxes (Postfix checks `virtual_alias_maps`
 ///    before `virtual_mailbox_maps`; without the identity entry the catch-all matches
 ///    first and mail is silently mis-routed).
-fn build_virtual_alias_entries(
+pub fn build_virtual_alias_entries(
     aliases: &[crate::db::Alias],
     forwardings: &[crate::db::Forwarding],
     accounts: &[crate::db::Account],
@@ -410,14 +639,18 @@ fn build_virtual_alias_entries(
     let mut specific_entries: Vec<(String, String)> = Vec::new();
     let mut specific_sources: std::collections::HashSet<String> = std::collections::HashSet::new();
 
+    let mut alias_keys: std::collections::HashSet<String> = std::collections::HashSet::new();
     for a in aliases {
-        if a.active {
+        if a.active && alias_keys.insert(alias_key(a)) {
             let source = normalize_virtual_alias_source(&a.source, a.domain_name.as_deref());
+            let Some(winner) = resolve_alias(&source, aliases) else {
+                continue;
+            };
             if source.starts_with('@') {
-                catch_all_entries.push((source, a.destination.clone()));
+                catch_all_entries.push((source, winner.destination));
             } else {
                 specific_sources.insert(source.clone());
-                specific_entries.push((source, a.destination.clone()));
+                specific_entries.push((source, winner.destination));
             }
         }
     }
@@ -477,7 +710,32 @@ pub fn generate_virtual_aliases(db: &Database) {
     let forwardings = db.list_all_forwardings_with_domain();
     let accounts = db.list_all_accounts_with_domain();
 
-    let entries = build_virtual_alias_entries(&aliases, &forwardings, &accounts);
+    let mut entries = build_virtual_alias_entries(&aliases, &forwardings, &accounts);
+    // Disabled accounts: forward per policy, and keep discarded addresses out
+    // of any catch-all so the discard transport sees them.
+    let disabled = disabled_maps(db);
+    let disabled_entries: Vec<(String, String)> = disabled
+        .aliases
+        .into_iter()
+        .chain(disabled.discard.into_iter().map(|a| (a.clone(), a)))
+        .filter(|(source, _)| !entries.iter().any(|(s, _)| s.eq_ignore_ascii_case(source)))
+        .collect();
+    entries.extend(disabled_entries);
+    // Reserved role addresses, unless a mailbox, alias or forwarding already
+    // answers them.
+    let account_emails: HashSet<String> = accounts
+        .iter()
+        .filter_map(|a| Some(format!("{}@{}", a.username, a.domain_name.as_ref()?)))
+        .collect();
+    let reserved_entries: Vec<(String, String)> =
+        crate::reserved_mailboxes::alias_entries(&db.list_all_reserved_mailboxes())
+            .into_iter()
+            .filter(|(source, _)| {
+                !account_emails.contains(source)
+                    && !entries.iter().any(|(s, _)| s.eq_ignore_ascii_case(source))
+            })
+            .collect();
+    entries.extend(reserved_entries);
     let active_count = entries.len();
     let mut lines = generated_header();
 
@@ -561,6 +819,53 @@ fn normalize_virtual_alias_source(source: &str, domain: Option<&str>) -> String
     trimmed.to_string()
 }
 
+/// The `virtual_alias_maps` key `alias` is written under, lowercased the way
+/// Postfix folds lookup keys.
+pub fn alias_key(alias: &crate::db::Alias) -> String {
+    normalize_virtual_alias_source(&alias.source, alias.domain_name.as_deref()).to_lowercase()
+}
+
+/// Keys Postfix looks up in `virtual_alias_maps` for `address`, in order:
+/// the address itself, the address without its `+detail`, then the domain's
+/// catch-all.
+pub fn alias_lookup_keys(address: &str) -> Vec<String> {
+    let address = address.trim().to_lowercase();
+    let mut keys = vec![address.clone()];
+    if let Some((local, domain)) = address.rsplit_once('@') {
+        if let Some((base, _)) = local.split_once('+') {
+            keys.push(format!("{}@{}", base, domain));
+        }
+        keys.push(format!("@{}", domain));
+    }
+    keys.dedup();
+    keys
+}
+
+/// Aliases whose key matches `address`, in the order they are considered:
+/// by lookup key, then by `sort_order` and id.  Disabled aliases are listed
+/// too but never used.
+pub fn alias_candidates(address: &str, aliases: &[crate::db::Alias]) -> Vec<crate::db::Alias> {
+    let keys = alias_lookup_keys(address);
+    let mut candidates: Vec<(usize, &crate::db::Alias)> = aliases
+        .iter()
+        .filter_map(|a| {
+            let key = alias_key(a);
+            keys.iter().position(|k| *k == key).map(|rank| (rank, a))
+        })
+        .collect();
+    candidates.sort_by_key(|(rank, a)| (*rank, a.sort_order, a.id));
+    candidates.into_iter().map(|(_, a)| a.clone()).collect()
+}
+
+/// The alias mail for `address` follows: the first active candidate.  This
+/// is the alias written to `virtual_aliases` for each key, so the resolver
+/// preview and Postfix agree.
+pub fn resolve_alias(address: &str, aliases: &[crate::db::Alias]) -> Option<crate::db::Alias> {
+    alias_candidates(address, aliases)
+        .into_iter()
+        .find(|a| a.active)
+}
+
 /// Build the list of (normalized_from_address, allowed_sasl_login) pairs for sender_login_maps.
 ///
 /// The destination account of an alias — regardless of which domain it lives on — is granted
@@ -648,17 +953,52 @@ pub fn generate_sender_login_maps(db: &Database) {
     }
 }
 
+pub fn generate_recipient_access(db: &Database) {
+    info!("[config] generating /etc/postfix/recipient_access");
+    let entries = disabled_maps(db).access;
+    let mut lines = generated_header();
+    use std::fmt::Write;
+    for (address, action) in &entries {
+        let _ = writeln!(lines, "{} {}", address, action);
+    }
+    match write_config("/etc/postfix/recipient_access", &lines) {
+        Ok(_) => debug!(
+            "[config] wrote /etc/postfix/recipient_access ({} entries)",
+            entries.len()
+        ),
+        Err(e) => error!(
+            "[config] failed to write /etc/postfix/recipient_access: {}",
+            e
+        ),
+    }
+}
+
 pub fn generate_transport_maps(db: &Database) {
     info!("[config] generating /etc/postfix/transport_maps");
-    let assignments = db.get_active_relay_assignments_with_relay();
+    let relay_domains = crate::relay_guard::RelayDomains::load(db);
+    let assignments: Vec<_> = db
+        .get_active_relay_assignments_with_relay()
+        .into_iter()
+        .filter(|(relay, assignment)| {
+            let allowed =
+                crate::relay_guard::assignment_allowed(&assignment.pattern, &relay_domains);
+            if !allowed {
+                warn!(
+                    "[config] skipping relay assignment {} -> {}: not an accepted relay domain",
+                    assignment.pattern, relay.name
+                );
+            }
+            allowed
+        })
+        .collect();
+    let discard = disabled_maps(db).discard;
     let mut lines = generated_header();
     use std::fmt::Write;
-    for (relay, assignment) in &assignments {
-        let _ = writeln!(
-            lines,
-            "{} smtp:[{}]:{}",
-            assignment.pattern, relay.host, relay.port
-        );
+    for address in &discard {
+        let _ = writeln!(lines, "{} discard:", address);
+    }
+    for (pattern, chain) in relay_chains(&assignments) {
+        let _ = writeln!(lines, "{} {}", pattern, chain_next_hop(&chain));
     }
 
     match write_secure_file("/etc/postfix/transport_maps", &lines) {
@@ -673,6 +1013,245 @@ pub fn generate_transport_maps(db: &Database) {
     }
 }
 
+/// master.cf service that delivers to relays speaking implicit TLS (SMTPS).
+const SMTPS_RELAY_TRANSPORT: &str = "relay-smtps";
+
+/// transport_maps next hop for a relay: SMTPS relays go through the
+/// wrapper-mode service, the rest through the default smtp transport.
+fn relay_next_hop(relay: &crate::db::OutboundRelay) -> String {
+    let transport = if relay.tls_mode == "smtps" {
+        SMTPS_RELAY_TRANSPORT
+    } else {
+        "smtp"
+    };
+    format!("{}:[{}]:{}", transport, relay.host, relay.port)
+}
+
+/// Build (next hop → security level) pairs for tls_policy, one per relay
+/// destination.  STARTTLS and SMTPS relays require encryption; `none`
+/// disables TLS.  Ports that don't fit the mode are logged, since a
+/// STARTTLS client talking to 465 (or SMTPS to 587) times out.
+fn build_tls_policy_entries(relays: &[&crate::db::OutboundRelay]) -> Vec<(String, &'static str)> {
+    let mut entries = std::collections::BTreeMap::new();
+    for relay in relays {
+        let level = match relay.tls_mode.as_str() {
+            "none" => "none",
+            _ => "encrypt",
+        };
+        if (relay.tls_mode == "smtps") != (relay.port == 465) {
+            warn!(
+                "[config] relay {} uses tls_mode={} on port {}; SMTPS normally uses 465 and STARTTLS 587 or 25",
+                relay.name, relay.tls_mode, relay.port
+            );
+        }
+        entries.insert(format!("[{}]:{}", relay.host, relay.port), level);
+    }
+    entries.into_iter().collect()
+}
+
+/// Group relay assignments by pattern, keeping the failover order of the
+/// query: the first relay of each chain is the primary, the rest back it up.
+fn relay_chains(
+    assignments: &[(crate::db::OutboundRelay, crate::db::OutboundRelayAssignment)],
+) -> Vec<(String, Vec<&crate::db::OutboundRelay>)> {
+    let mut chains: Vec<(String, Vec<&crate::db::OutboundRelay>)> = Vec::new();
+    for (relay, assignment) in assignments {
+        match chains.last_mut() {
+            Some((pattern, relays)) if *pattern == assignment.pattern => relays.push(relay),
+            _ => chains.push((assignment.pattern.clone(), vec![relay])),
+        }
+    }
+    chains
+}
+
+/// master.cf service name for a primary relay with fallbacks.
+fn failover_transport_name(chain: &[&crate::db::OutboundRelay]) -> String {
+    let ids: Vec<String> = chain.iter().map(|r| r.id.to_string()).collect();
+    format!("relay-failover-{}", ids.join("-"))
+}
+
+/// transport_maps next hop for a chain: a single relay is reached directly,
+/// a primary with fallbacks through its failover service.
+fn chain_next_hop(chain: &[&crate::db::OutboundRelay]) -> String {
+    match chain {
+        [relay] => relay_next_hop(relay),
+        [primary, ..] => format!(
+            "{}:[{}]:{}",
+            failover_transport_name(chain),
+            primary.host,
+            primary.port
+        ),
+        [] => String::new(),
+    }
+}
+
+/// master.cf smtp services for relays: one wrapper-mode service for SMTPS
+/// relays used on their own, and one service per failover chain that lists
+/// the backup relays in `smtp_fallback_relay`.  A chain shares the primary's
+/// wrapper mode, so mixing SMTPS and non-SMTPS relays in one chain is logged.
+fn build_relay_transports(chains: &[(String, Vec<&crate::db::OutboundRelay>)]) -> String {
+    use std::fmt::Write;
+    let mut lines = String::new();
+    if chains
+        .iter()
+        .any(|(_, c)| c.len() == 1 && c[0].tls_mode == "smtps")
+    {
+        let _ = writeln!(
+            lines,
+            "{0} unix  -       -       n       -       -       smtp\n  -o smtp_tls_wrappermode=yes\n  -o smtp_tls_security_level=encrypt\n  -o syslog_name=postfix/{0}",
+            SMTPS_RELAY_TRANSPORT
+        );
+    }
+    let mut seen = HashSet::new();
+    for (pattern, chain) in chains.iter().filter(|(_, c)| c.len() > 1) {
+        let name = failover_transport_name(chain);
+        if !seen.insert(name.clone()) {
+            continue;
+        }
+        let smtps = chain[0].tls_mode == "smtps";
+        if chain.iter().any(|r| (r.tls_mode == "smtps") != smtps) {
+            warn!(
+                "[config] relays for {} mix SMTPS and STARTTLS; fallbacks use the primary's mode",
+                pattern
+            );
+        }
+        let fallbacks: Vec<String> = chain[1..]
+            .iter()
+            .map(|r| format!("[{}]:{}", r.host, r.port))
+            .collect();
+        let _ = writeln!(
+            lines,
+            "{} unix  -       -       n       -       -       smtp\n  -o smtp_fallback_relay={}",
+            name,
+            fallbacks.join(",")
+        );
+        if smtps {
+            let _ = writeln!(
+                lines,
+                "  -o smtp_tls_wrappermode=yes\n  -o smtp_tls_security_level=encrypt"
+            );
+        }
+        let _ = writeln!(lines, "  -o syslog_name=postfix/{}", name);
+    }
+    lines
+}
+
+pub fn generate_tls_policy_maps(db: &Database) {
+    info!("[config] generating /etc/postfix/tls_policy");
+    let assignments = db.get_active_relay_assignments_with_relay();
+    let relays: Vec<_> = assignments.iter().map(|(relay, _)| relay).collect();
+    let entries = build_tls_policy_entries(&relays);
+    let mut lines = generated_header();
+    use std::fmt::Write;
+    for (next_hop, level) in &entries {
+        let _ = writeln!(lines, "{} {}", next_hop, level);
+    }
+    match write_config("/etc/postfix/tls_policy", &lines) {
+        Ok(_) => debug!(
+            "[config] wrote /etc/postfix/tls_policy ({} entries)",
+            entries.len()
+        ),
+        Err(e) => error!("[config] failed to write /etc/postfix/tls_policy: {}", e),
+    }
+}
+
+/// Whether `name` looks like a fully qualified hostname usable as a HELO
+/// identity: at least two dot-separated labels of letters, digits and inner
+/// hyphens, with an alphabetic top-level label.
+pub fn is_valid_hostname(name: &str) -> bool {
+    let labels: Vec<&str> = name.split('.').collect();
+    name.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|l| {
+            !l.is_empty()
+                && l.len() <= 63
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| tld.chars().all(|c| c.is_ascii_alphabetic()))
+}
+
+/// master.cf service name of the smtp transport that HELOs as `helo_name`.
+fn helo_transport_name(helo_name: &str) -> String {
+    format!("helo-{}", helo_name.to_lowercase().replace('.', "-"))
+}
+
+/// Active domains with a valid HELO identity, as (domain, helo_name) pairs.
+fn domain_helo_names(domains: &[crate::db::Domain]) -> Vec<(String, String)> {
+    domains
+        .iter()
+        .filter(|d| d.active && !d.helo_name.is_empty())
+        .filter(|d| {
+            let valid = is_valid_hostname(&d.helo_name);
+            if !valid {
+                warn!(
+                    "[config] ignoring invalid HELO name {} for domain {}",
+                    d.helo_name, d.domain
+                );
+            }
+            valid
+        })
+        .map(|d| (d.domain.to_lowercase(), d.helo_name.to_lowercase()))
+        .collect()
+}
+
+/// Build (sender pattern → transport) pairs for sender_transport, mapping
+/// each sending domain to the smtp transport of its HELO identity.
+fn build_sender_identity_entries(domains: &[crate::db::Domain]) -> Vec<(String, String)> {
+    domain_helo_names(domains)
+        .into_iter()
+        .map(|(domain, helo)| {
+            (
+                format!("@{}", domain),
+                format!("{}:", helo_transport_name(&helo)),
+            )
+        })
+        .collect()
+}
+
+/// master.cf smtp services, one per distinct HELO identity.
+fn build_helo_transports(domains: &[crate::db::Domain]) -> String {
+    let helo_names: std::collections::BTreeSet<String> = domain_helo_names(domains)
+        .into_iter()
+        .map(|(_, helo)| helo)
+        .collect();
+    let mut lines = String::new();
+    use std::fmt::Write;
+    for helo in &helo_names {
+        let _ = writeln!(
+            lines,
+            "{} unix  -       -       n       -       -       smtp\n  -o smtp_helo_name={}\n  -o syslog_name=postfix/{}",
+            helo_transport_name(helo),
+            helo,
+            helo_transport_name(helo)
+        );
+    }
+    lines
+}
+
+pub fn generate_sender_transport_maps(db: &Database) {
+    info!("[config] generating /etc/postfix/sender_transport");
+    let entries = build_sender_identity_entries(&db.list_domains());
+    let mut lines = generated_header();
+    use std::fmt::Write;
+    for (sender, transport) in &entries {
+        let _ = writeln!(lines, "{} {}", sender, transport);
+    }
+    match write_config("/etc/postfix/sender_transport", &lines) {
+        Ok(_) => debug!(
+            "[config] wrote /etc/postfix/sender_transport ({} entries)",
+            entries.len()
+        ),
+        Err(e) => error!(
+            "[config] failed to write /etc/postfix/sender_transport: {}",
+            e
+        ),
+    }
+}
+
 pub fn generate_sasl_passwd(db: &Database) {
     let sasl_path = "/etc/postfix/sasl_passwd";
     info!("[config] generating {}", sasl_path);
@@ -733,7 +1312,7 @@ pub fn generate_dovecot_conf(hostname: &str) {
         .replace("{{ hostname }}", hostname)
         .replace("{{ log_path_line }}", log_path_line);
 
-    match fs::write("/etc/dovecot/dovecot.conf", config) {
+    match write_config("/etc/dovecot/dovecot.conf", &config) {
         Ok(_) => debug!("[config] wrote /etc/dovecot/dovecot.conf"),
         Err(e) => error!("[config] failed to write /etc/dovecot/dovecot.conf: {}", e),
     }
@@ -750,11 +1329,13 @@ pub fn generate_dovecot_passwd(db: &Database) {
             continue;
         }
         if let Some(ref domain) = a.domain_name {
-            let _ = writeln!(
-                lines,
-                "{}@{}:{{BLF-CRYPT}}{}:::::",
-                a.username, domain, a.password_hash
-            );
+            // Imported legacy hashes carry their own {SCHEME} prefix.
+            let password = if a.password_hash.starts_with('{') {
+                a.password_hash.clone()
+            } else {
+                format!("{{BLF-CRYPT}}{}", a.password_hash)
+            };
+            let _ = writeln!(lines, "{}@{}:{}:::::", a.username, domain, password);
         }
     }
     match write_secure_file(passwd_path, &lines) {
@@ -787,12 +1368,196 @@ pub fn generate_dovecot_passwd(db: &Database) {
     }
 }
 
-pub fn generate_opendkim_conf() {
-    info!("[config] generating /etc/opendkim/opendkim.conf");
-    if let Err(e) = fs::create_dir_all("/etc/opendkim") {
-        error!("[config] failed to create /etc/opendkim directory: {}", e);
+// ── Maildir folder subscriptions ──
+
+const MAILDIR_ROOT: &str = "/data/mail";
+
+/// Folders subscribed for every mailbox unless the `subscribed_folders`
+/// setting overrides the list.
+pub const DEFAULT_SUBSCRIBED_FOLDERS: &str = "Sent,Drafts,Trash,Junk";
+
+/// Parse a comma-separated folder list, dropping empty entries and names
+/// that could escape the Maildir (path separators, `..`, leading dots).
+pub(crate) fn parse_folder_list(value: &str) -> Vec<String> {
+    let mut folders: Vec<String> = Vec::new();
+    for name in value.split(',').map(str::trim) {
+        if name.is_empty()
+            || name.starts_with('.')
+            || name.contains('/')
+            || name.contains("..")
+            || name.chars().any(|c| c.is_control())
+        {
+            continue;
+        }
+        if !folders.iter().any(|f| f == name) {
+            folders.push(name.to_string());
+        }
+    }
+    folders
+}
+
+/// Merge `folders` into the contents of a Dovecot `subscriptions` file.
+///
+/// Existing lines (including a `V\t2` version header and any user-created
+/// subscriptions) are kept verbatim; only missing folders are appended.
+/// Returns `None` when every folder is already subscribed.
+fn merge_subscriptions(existing: &str, folders: &[String]) -> Option<String> {
+    let present: HashSet<&str> = existing.lines().map(str::trim).collect();
+    let missing: Vec<&String> = folders
+        .iter()
+        .filter(|f| !present.contains(f.as_str()))
+        .collect();
+    if missing.is_empty() {
+        return None;
+    }
+    let mut merged = existing.to_string();
+    if !merged.is_empty() && !merged.ends_with('\n') {
+        merged.push('\n');
+    }
+    for folder in missing {
+        merged.push_str(folder);
+        merged.push('\n');
+    }
+    Some(merged)
+}
+
+/// Create the standard folders inside `maildir` and subscribe them.
+///
+/// Returns `true` when anything on disk changed.
+pub(crate) fn write_maildir_subscriptions(maildir: &Path, folders: &[String]) -> std::io::Result<bool> {
+    let mut changed = false;
+    for folder in folders {
+        for sub in ["cur", "new", "tmp"] {
+            let dir = maildir.join(format!(".{}", folder)).join(sub);
+            if !dir.is_dir() {
+                fs::create_dir_all(&dir)?;
+                changed = true;
+            }
+        }
+    }
+    let path = maildir.join("subscriptions");
+    let existing = match fs::read_to_string(&path) {
+        Ok(s) => s,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    if let Some(merged) = merge_subscriptions(&existing, folders) {
+        fs::write(&path, merged)?;
+        changed = true;
+    }
+    Ok(changed)
+}
+
+pub fn generate_maildir_subscriptions(db: &Database) {
+    let setting = db
+        .get_setting("subscribed_folders")
+        .unwrap_or_else(|| DEFAULT_SUBSCRIBED_FOLDERS.to_string());
+    let folders = parse_folder_list(&setting);
+    info!(
+        "[config] provisioning Maildir subscriptions: {}",
+        folders.join(", ")
+    );
+    if folders.is_empty() {
         return;
     }
+    for a in db.list_all_accounts_with_domain() {
+        if !a.active {
+            continue;
+        }
+        let domain = match a.domain_name {
+            Some(ref d) => d,
+            None => continue,
+        };
+        let account_dir = format!("{}/{}/{}", MAILDIR_ROOT, domain, a.username);
+        let maildir = Path::new(&account_dir).join("Maildir");
+        match write_maildir_subscriptions(&maildir, &folders) {
+            Ok(true) => {
+                debug!("[config] updated subscriptions for {}@{}", a.username, domain);
+                // Newly created folders must belong to the Dovecot mail user
+                match Command::new("chown")
+                    .args(["-R", "vmail:vmail", &account_dir])
+                    .output()
+                {
+                    Ok(o) if o.status.success() => {}
+                    Ok(o) => warn!(
+                        "[config] chown vmail:vmail {} failed: {}",
+                        account_dir,
+                        String::from_utf8_lossy(&o.stderr).trim()
+                    ),
+                    Err(e) => warn!("[config] failed to run chown on {}: {}", account_dir, e),
+                }
+            }
+            Ok(false) => {}
+            Err(e) => error!(
+                "[config] failed to provision subscriptions for {}@{}: {}",
+                a.username, domain, e
+            ),
+        }
+    }
+}
+
+// ── DKIM signing options ──
+
+/// Header/body canonicalization used when no `dkim_canonicalization` setting exists.
+pub const DEFAULT_DKIM_CANONICALIZATION: &str = "relaxed/relaxed";
+
+/// Headers signed when no `dkim_sign_headers` setting exists.
+pub const DEFAULT_DKIM_SIGN_HEADERS: &str = "From,Reply-To,To,Cc,Subject,Date,Message-ID,In-Reply-To,References,MIME-Version,Content-Type,Content-Transfer-Encoding,List-Unsubscribe,List-Unsubscribe-Post";
+
+/// Validate a `header/body` canonicalization pair such as `relaxed/simple`.
+/// A single value applies to the header and the body alike.
+pub(crate) fn parse_dkim_canonicalization(value: &str) -> Result<String, String> {
+    let value = value.trim().to_ascii_lowercase();
+    let (header, body) = value.split_once('/').unwrap_or((&value, &value));
+    for part in [header, body] {
+        if part != "simple" && part != "relaxed" {
+            return Err(format!(
+                "invalid DKIM canonicalization '{}': use simple or relaxed",
+                part
+            ));
+        }
+    }
+    Ok(format!("{}/{}", header, body))
+}
+
+/// Parse a comma-separated list of header names to sign.  Names must be
+/// valid RFC 5322 field names; duplicates are dropped and `From` is required.
+pub(crate) fn parse_dkim_sign_headers(value: &str) -> Result<Vec<String>, String> {
+    let mut headers: Vec<String> = Vec::new();
+    for name in value.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        if !name.bytes().all(|b| (33..=126).contains(&b) && b != b':') {
+            return Err(format!("invalid header name '{}'", name));
+        }
+        if !headers.iter().any(|h| h.eq_ignore_ascii_case(name)) {
+            headers.push(name.to_string());
+        }
+    }
+    if !headers.iter().any(|h| h.eq_ignore_ascii_case("From")) {
+        return Err("the signed header list must include From".to_string());
+    }
+    Ok(headers)
+}
+
+fn render_opendkim_conf(
+    template: &str,
+    generated_at: &str,
+    canonicalization: &str,
+    sign_headers: &[String],
+) -> String {
+    template
+        .replace("{{ generated_at }}", generated_at)
+        .replace("{{ dkim_canonicalization }}", canonicalization)
+        .replace("{{ dkim_sign_headers }}", &sign_headers.join(","))
+}
+
+pub fn generate_opendkim_conf(db: &Database) {
+    info!("[config] generating /etc/opendkim/opendkim.conf");
+    if !previewing() {
+        if let Err(e) = fs::create_dir_all("/etc/opendkim") {
+            error!("[config] failed to create /etc/opendkim directory: {}", e);
+            return;
+        }
+    }
     let template = match load_template("opendkim.conf.txt") {
         Ok(t) => t,
         Err(e) => {
@@ -800,9 +1565,32 @@ pub fn generate_opendkim_conf() {
             return;
         }
     };
-    let config = template.replace("{{ generated_at }}", &generated_at());
+    let canonicalization = db
+        .get_setting("dkim_canonicalization")
+        .and_then(|v| {
+            parse_dkim_canonicalization(&v)
+                .map_err(|e| warn!("[config] ignoring stored setting: {}", e))
+                .ok()
+        })
+        .unwrap_or_else(|| DEFAULT_DKIM_CANONICALIZATION.to_string());
+    let sign_headers = db
+        .get_setting("dkim_sign_headers")
+        .and_then(|v| {
+            parse_dkim_sign_headers(&v)
+                .map_err(|e| warn!("[config] ignoring stored setting: {}", e))
+                .ok()
+        })
+        .unwrap_or_else(|| {
+            parse_dkim_sign_headers(DEFAULT_DKIM_SIGN_HEADERS).unwrap_or_default()
+        });
+    debug!(
+        "[config] DKIM canonicalization={}, signed headers={}",
+        canonicalization,
+        sign_headers.join(",")
+    );
+    let config = render_opendkim_conf(&template, &generated_at(), &canonicalization, &sign_headers);
 
-    match fs::write("/etc/opendkim/opendkim.conf", config) {
+    match write_config("/etc/opendkim/opendkim.conf", &config) {
         Ok(_) => debug!("[config] wrote /etc/opendkim/opendkim.conf"),
         Err(e) => error!(
             "[config] failed to write /etc/opendkim/opendkim.conf: {}",
@@ -813,9 +1601,11 @@ pub fn generate_opendkim_conf() {
 
 pub fn generate_opendkim_tables(db: &Database) {
     info!("[config] generating OpenDKIM key/signing/trusted tables");
-    if let Err(e) = fs::create_dir_all("/etc/opendkim") {
-        error!("[config] failed to create /etc/opendkim directory: {}", e);
-        return;
+    if !previewing() {
+        if let Err(e) = fs::create_dir_all("/etc/opendkim") {
+            error!("[config] failed to create /etc/opendkim directory: {}", e);
+            return;
+        }
     }
     let domains = db.list_domains();
 
@@ -842,10 +1632,13 @@ localhost
             let key_path = format!("/data/dkim/{}.private", safe_filename(domain));
 
             // Write the private key file with secure permissions (0600)
-            if let Err(e) = fs::create_dir_all("/data/dkim") {
-                error!("[config] failed to create /data/dkim directory: {}", e);
+            if !previewing() {
+                if let Err(e) = fs::create_dir_all("/data/dkim") {
+                    error!("[config] failed to create /data/dkim directory: {}", e);
+                }
             }
             match write_secure_file(&key_path, private_key) {
+                Ok(_) if previewing() => {}
                 Ok(_) => {
                     debug!(
                         "[config] wrote DKIM private key for domain={} to {} with secure permissions",
@@ -897,15 +1690,15 @@ localhost
         "[config] writing OpenDKIM tables ({} DKIM-enabled domains)",
         dkim_count
     );
-    match fs::write("/etc/opendkim/KeyTable", key_table) {
+    match write_config("/etc/opendkim/KeyTable", &key_table) {
         Ok(_) => debug!("[config] wrote /etc/opendkim/KeyTable"),
         Err(e) => error!("[config] failed to write /etc/opendkim/KeyTable: {}", e),
     }
-    match fs::write("/etc/opendkim/SigningTable", signing_table) {
+    match write_config("/etc/opendkim/SigningTable", &signing_table) {
         Ok(_) => debug!("[config] wrote /etc/opendkim/SigningTable"),
         Err(e) => error!("[config] failed to write /etc/opendkim/SigningTable: {}", e),
     }
-    match fs::write("/etc/opendkim/TrustedHosts", trusted_hosts) {
+    match write_config("/etc/opendkim/TrustedHosts", &trusted_hosts) {
         Ok(_) => debug!("[config] wrote /etc/opendkim/TrustedHosts"),
         Err(e) => error!("[config] failed to write /etc/opendkim/TrustedHosts: {}", e),
     }
@@ -1109,9 +1902,13 @@ fn extract_container_id_from_path(line: &str) -> Option<String> {
 #[cfg(test)]
 mod tests {
     use super::extract_container_id_from_path;
-    use super::load_template;
+    use super::{dump_templates, load_template, search_paths, EMBEDDED_TEMPLATES};
     use super::normalize_virtual_alias_source;
+    use super::{build_helo_transports, build_sender_identity_entries, is_valid_hostname};
+    use super::{build_relay_transports, build_tls_policy_entries, chain_next_hop, relay_chains};
     use super::parse_major_minor;
+    use super::{merge_subscriptions, parse_folder_list, write_maildir_subscriptions};
+    use super::{parse_dkim_canonicalization, parse_dkim_sign_headers, render_opendkim_conf};
 
     #[test]
     fn normalize_virtual_alias_source_rewrites_catch_all_patterns() {
@@ -1130,6 +1927,46 @@ mod tests {
         assert_eq!(normalize_virtual_alias_source("*", None), "*");
     }
 
+    #[test]
+    fn parse_folder_list_skips_unsafe_and_duplicate_names() {
+        assert_eq!(
+            parse_folder_list(" Sent, Drafts ,,../etc,.Trash,a/b,Sent,Archive"),
+            vec!["Sent", "Drafts", "Archive"]
+        );
+    }
+
+    #[test]
+    fn merge_subscriptions_keeps_user_entries() {
+        let folders = vec!["Sent".to_string(), "Trash".to_string()];
+        let merged = merge_subscriptions("Projects\nSent", &folders).unwrap();
+        assert_eq!(merged, "Projects\nSent\nTrash\n");
+        assert_eq!(merge_subscriptions(&merged, &folders), None);
+    }
+
+    #[test]
+    fn provisioning_writes_expected_subscriptions() {
+        let maildir = std::env::temp_dir()
+            .join(format!("subscriptions_test_{}", uuid::Uuid::new_v4()))
+            .join("Maildir");
+        let folders = parse_folder_list(super::DEFAULT_SUBSCRIBED_FOLDERS);
+
+        assert!(write_maildir_subscriptions(&maildir, &folders).unwrap());
+        let contents = std::fs::read_to_string(maildir.join("subscriptions")).unwrap();
+        assert_eq!(contents, "Sent\nDrafts\nTrash\nJunk\n");
+        for folder in &folders {
+            assert!(maildir.join(format!(".{}", folder)).join("cur").is_dir());
+        }
+
+        // A user subscription added later survives regeneration
+        std::fs::write(maildir.join("subscriptions"), "Sent\nDrafts\nTrash\nJunk\nClients\n")
+            .unwrap();
+        assert!(!write_maildir_subscriptions(&maildir, &folders).unwrap());
+        let contents = std::fs::read_to_string(maildir.join("subscriptions")).unwrap();
+        assert!(contents.contains("Clients"));
+
+        let _ = std::fs::remove_dir_all(maildir.parent().unwrap());
+    }
+
     #[test]
     fn extract_container_id_from_mountinfo_line() {
         let line = "1234 5678 0:123 / /sys/fs/cgroup rw - cgroup2 /docker/abc123def456abc123def456abc123def456abc123def456abc123def456abcd";
@@ -1165,6 +2002,59 @@ mod tests {
         assert_eq!(parse_major_minor(""), None);
     }
 
+    #[test]
+    fn search_paths_start_local_and_skip_duplicates() {
+        let paths = search_paths("templates/config/dovecot.conf.txt");
+        assert_eq!(paths[0], std::path::PathBuf::from("templates/config/dovecot.conf.txt"));
+        assert_eq!(
+            paths[1],
+            std::path::PathBuf::from("/app/templates/config/dovecot.conf.txt")
+        );
+        let unique: std::collections::HashSet<_> = paths.iter().collect();
+        assert_eq!(unique.len(), paths.len());
+    }
+
+    #[test]
+    fn missing_templates_without_an_embedded_copy_list_the_paths_checked() {
+        let err = load_template("no-such-template.txt").unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("/app/templates/config/no-such-template.txt"));
+    }
+
+    #[test]
+    fn every_template_is_embedded_and_dumped_without_overwriting() {
+        let mut on_disk: Vec<String> = std::fs::read_dir("templates/config")
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        on_disk.sort();
+        let mut embedded: Vec<String> = EMBEDDED_TEMPLATES
+            .iter()
+            .map(|(n, _)| n.to_string())
+            .collect();
+        embedded.sort();
+        assert_eq!(embedded, on_disk);
+
+        let dir =
+            std::env::temp_dir().join(format!("dump_templates_test_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("dovecot.conf.txt"), "custom").unwrap();
+        let results = dump_templates(&dir).unwrap();
+        assert_eq!(results.len(), EMBEDDED_TEMPLATES.len());
+        assert!(results
+            .iter()
+            .all(|(path, written)| *written != path.ends_with("dovecot.conf.txt")));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("dovecot.conf.txt")).unwrap(),
+            "custom"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("postfix-main.cf.txt")).unwrap(),
+            include_str!("../templates/config/postfix-main.cf.txt")
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
     #[test]
     fn master_cf_template_includes_smtp_unix_transport() {
         let template = load_template("postfix-master.cf.txt")
@@ -1288,6 +2178,7 @@ mod tests {
     use super::build_recipient_bcc_entries;
     use super::build_sender_login_entries;
     use super::build_virtual_alias_entries;
+    use super::{alias_candidates, resolve_alias};
     use crate::db::{Account, Alias, Forwarding};
 
     fn make_alias(source: &str, destination: &str, domain: &str) -> Alias {
@@ -1452,6 +2343,53 @@ mod tests {
         );
     }
 
+    #[test]
+    fn resolve_alias_prefers_the_address_then_its_base_then_the_catch_all() {
+        let mut aliases = vec![
+            make_alias("*@example.com", "catchall@tyyi.net", "example.com"),
+            make_alias("Info@example.com", "later@tyyi.net", "example.com"),
+            make_alias("info@example.com", "first@tyyi.net", "example.com"),
+        ];
+        (aliases[1].id, aliases[1].sort_order) = (2, 5);
+        (aliases[2].id, aliases[2].sort_order) = (3, 1);
+        let resolved = |address: &str, aliases: &[Alias]| {
+            resolve_alias(address, aliases).map(|a| a.destination)
+        };
+
+        assert_eq!(
+            resolved("INFO@example.com", &aliases).as_deref(),
+            Some("first@tyyi.net")
+        );
+        assert_eq!(
+            resolved("bob@example.com", &aliases).as_deref(),
+            Some("catchall@tyyi.net")
+        );
+        assert_eq!(resolved("bob@other.com", &aliases), None);
+        let order: Vec<String> = alias_candidates("info+news@example.com", &aliases)
+            .into_iter()
+            .map(|a| a.destination)
+            .collect();
+        assert_eq!(
+            order,
+            ["first@tyyi.net", "later@tyyi.net", "catchall@tyyi.net"]
+        );
+
+        // The generated map writes each key once, with the resolved alias.
+        aliases[2].active = false;
+        assert_eq!(
+            resolved("info@example.com", &aliases).as_deref(),
+            Some("later@tyyi.net")
+        );
+        let entries = build_virtual_alias_entries(&aliases, &[], &[]);
+        assert_eq!(
+            entries,
+            vec![
+                ("@example.com".to_string(), "catchall@tyyi.net".to_string()),
+                ("Info@example.com".to_string(), "later@tyyi.net".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn keep_copy_non_catch_all_forwarding_appears_in_recipient_bcc_entries() {
         let fwd = make_forwarding("newsletter@example.com", "external@other.com", true);
@@ -1600,6 +2538,167 @@ mod tests {
             "alias pointing to a non-local address must not appear in sender_login_maps"
         );
     }
+
+    #[test]
+    fn opendkim_conf_reflects_canonicalization_and_signed_headers() {
+        let template = load_template("opendkim.conf.txt").unwrap();
+        let canonicalization = parse_dkim_canonicalization("simple/relaxed").unwrap();
+        let headers = parse_dkim_sign_headers("From, Subject,Date, from ,List-Id").unwrap();
+        let conf = render_opendkim_conf(&template, "now", &canonicalization, &headers);
+        assert!(conf.contains("Canonicalization simple/relaxed\n"));
+        assert!(conf.contains("SignHeaders     From,Subject,Date,List-Id\n"));
+        assert!(!conf.contains("{{"));
+    }
+
+    #[test]
+    fn dkim_canonicalization_is_validated() {
+        assert_eq!(parse_dkim_canonicalization("Relaxed").unwrap(), "relaxed/relaxed");
+        assert_eq!(parse_dkim_canonicalization("relaxed/simple").unwrap(), "relaxed/simple");
+        assert!(parse_dkim_canonicalization("loose/simple").is_err());
+        assert!(parse_dkim_canonicalization("").is_err());
+    }
+
+    #[test]
+    fn dkim_sign_headers_are_validated() {
+        assert!(parse_dkim_sign_headers(super::DEFAULT_DKIM_SIGN_HEADERS).is_ok());
+        assert!(parse_dkim_sign_headers("Subject,Date").is_err(), "From is required");
+        assert!(parse_dkim_sign_headers("From,Bad Header").is_err());
+        assert!(parse_dkim_sign_headers("From,X-Bad:").is_err());
+    }
+
+    fn domain_with_helo(domain: &str, helo_name: &str, active: bool) -> crate::db::Domain {
+        crate::db::Domain {
+            id: 0,
+            domain: domain.to_string(),
+            active,
+            dkim_selector: "mail".to_string(),
+            dkim_private_key: None,
+            dkim_public_key: None,
+            footer_html: None,
+            bimi_svg: None,
+            unsubscribe_enabled: false,
+            registration_enabled: false,
+            quiet_hours_start: String::new(),
+            quiet_hours_end: String::new(),
+            quiet_hours_timezone: "UTC".to_string(),
+            helo_name: helo_name.to_string(),
+            dmarc_reports_enabled: false,
+            dkim_selector_prev: None,
+            dkim_private_key_prev: None,
+            dkim_public_key_prev: None,
+            dkim_rotated_at: None,
+        }
+    }
+
+    #[test]
+    fn sending_domains_map_to_their_helo_identity() {
+        let domains = vec![
+            domain_with_helo("a.com", "mail.a.com", true),
+            domain_with_helo("b.com", "Mail.B.com", true),
+            domain_with_helo("c.com", "", true),
+            domain_with_helo("d.com", "mail.d.com", false),
+            domain_with_helo("e.com", "not a host", true),
+        ];
+        assert_eq!(
+            build_sender_identity_entries(&domains),
+            vec![
+                ("@a.com".to_string(), "helo-mail-a-com:".to_string()),
+                ("@b.com".to_string(), "helo-mail-b-com:".to_string()),
+            ]
+        );
+        let transports = build_helo_transports(&domains);
+        assert!(transports.contains("helo-mail-a-com unix"));
+        assert!(transports.contains("-o smtp_helo_name=mail.a.com\n"));
+        assert!(transports.contains("-o smtp_helo_name=mail.b.com\n"));
+        assert!(!transports.contains("mail.d.com"));
+    }
+
+    #[test]
+    fn helo_names_must_look_like_hostnames() {
+        assert!(is_valid_hostname("mail.example.com"));
+        assert!(is_valid_hostname("mx-1.example.co"));
+        assert!(!is_valid_hostname("localhost"));
+        assert!(!is_valid_hostname("mail..example.com"));
+        assert!(!is_valid_hostname("-mail.example.com"));
+        assert!(!is_valid_hostname("mail.example.123"));
+        assert!(!is_valid_hostname("mail example.com"));
+        assert!(!is_valid_hostname("mail.example.com\n-o x=y"));
+    }
+
+    fn relay(host: &str, port: i32, tls_mode: &str) -> crate::db::OutboundRelay {
+        crate::db::OutboundRelay {
+            id: 1,
+            name: host.to_string(),
+            host: host.to_string(),
+            port,
+            auth_type: "none".to_string(),
+            username: None,
+            password: None,
+            active: true,
+            tls_mode: tls_mode.to_string(),
+            priority: 0,
+            last_ok: None,
+            last_error: None,
+            last_checked: None,
+        }
+    }
+
+    #[test]
+    fn relay_tls_modes_map_to_policy_and_transport() {
+        let starttls = relay("smtp.a.test", 587, "starttls");
+        let smtps = relay("smtp.b.test", 465, "smtps");
+        let plain = relay("smtp.c.test", 25, "none");
+        let relays = vec![&starttls, &smtps, &plain, &starttls];
+        assert_eq!(
+            build_tls_policy_entries(&relays),
+            vec![
+                ("[smtp.a.test]:587".to_string(), "encrypt"),
+                ("[smtp.b.test]:465".to_string(), "encrypt"),
+                ("[smtp.c.test]:25".to_string(), "none"),
+            ]
+        );
+        assert_eq!(chain_next_hop(&[&starttls]), "smtp:[smtp.a.test]:587");
+        assert_eq!(chain_next_hop(&[&smtps]), "relay-smtps:[smtp.b.test]:465");
+        let single = |r| ("@a.test".to_string(), vec![r]);
+        let transports = build_relay_transports(&[single(&starttls), single(&smtps)]);
+        assert!(transports.starts_with("relay-smtps unix"));
+        assert!(transports.contains("-o smtp_tls_wrappermode=yes\n"));
+        assert!(build_relay_transports(&[single(&starttls), single(&plain)]).is_empty());
+    }
+
+    #[test]
+    fn relays_sharing_a_pattern_fail_over_in_order() {
+        let assignment = |pattern: &str, relay: &crate::db::OutboundRelay| {
+            let a = crate::db::OutboundRelayAssignment {
+                id: 0,
+                relay_id: relay.id,
+                assignment_type: "domain".to_string(),
+                pattern: pattern.to_string(),
+                relay_name: None,
+            };
+            (relay.clone(), a)
+        };
+        let mut primary = relay("primary.test", 587, "starttls");
+        primary.id = 1;
+        let mut backup = relay("backup.test", 25, "starttls");
+        backup.id = 2;
+        let assignments = vec![
+            assignment("@a.test", &primary),
+            assignment("@a.test", &backup),
+            assignment("@b.test", &backup),
+        ];
+        let chains = relay_chains(&assignments);
+        assert_eq!(chains.len(), 2);
+        assert_eq!(
+            chain_next_hop(&chains[0].1),
+            "relay-failover-1-2:[primary.test]:587"
+        );
+        assert_eq!(chain_next_hop(&chains[1].1), "smtp:[backup.test]:25");
+        let transports = build_relay_transports(&chains);
+        assert!(transports.starts_with("relay-failover-1-2 unix"));
+        assert!(transports.contains("-o smtp_fallback_relay=[backup.test]:25\n"));
+        assert!(!transports.contains("wrappermode"));
+    }
 }
 
 // ── Certificate and DH parameter generation ──
@@ -0,0 +1,178 @@
+//! Unified diffs between config files on disk and freshly generated ones,
+//! for `genconfig --dry-run`.
+//!
+//! Lines are matched with a longest-common-subsequence table after trimming
+//! the common prefix and suffix, which keeps the table small for the usual
+//! few-line change.  The `# Generated by mailserver on ...` stamp changes on
+//! every run and never counts as a difference.
+
+const CONTEXT: usize = 3;
+/// Largest changed region (old lines × new lines) compared line by line;
+/// bigger rewrites are shown as removing every old line and adding every new
+/// one.
+const MAX_COMPARE_CELLS: usize = 4_000_000;
+const GENERATED_PREFIX: &str = "# Generated by mailserver on ";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Equal(usize),
+    Delete(usize),
+    Insert(usize),
+}
+
+fn same_line(a: &str, b: &str) -> bool {
+    a == b || (a.starts_with(GENERATED_PREFIX) && b.starts_with(GENERATED_PREFIX))
+}
+
+/// Edit script turning `old` into `new`.  `Equal` and `Delete` carry the old
+/// line index, `Insert` the new one.
+fn edit_script(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(a, b)| same_line(a, b))
+        .count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same_line(a, b))
+        .count();
+    let a = &old[prefix..old.len() - suffix];
+    let b = &new[prefix..new.len() - suffix];
+
+    let mut ops: Vec<Op> = (0..prefix).map(Op::Equal).collect();
+    let (mut i, mut j) = (0, 0);
+    if a.len().saturating_mul(b.len()) <= MAX_COMPARE_CELLS {
+        // lcs[i * w + j]: length of the common subsequence of a[i..] and b[j..].
+        let w = b.len() + 1;
+        let mut lcs = vec![0u32; (a.len() + 1) * w];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i * w + j] = if same_line(a[i], b[j]) {
+                    lcs[(i + 1) * w + j + 1] + 1
+                } else {
+                    lcs[(i + 1) * w + j].max(lcs[i * w + j + 1])
+                };
+            }
+        }
+        while i < a.len() && j < b.len() {
+            if same_line(a[i], b[j]) {
+                ops.push(Op::Equal(prefix + i));
+                i += 1;
+                j += 1;
+            } else if lcs[(i + 1) * w + j] >= lcs[i * w + j + 1] {
+                ops.push(Op::Delete(prefix + i));
+                i += 1;
+            } else {
+                ops.push(Op::Insert(prefix + j));
+                j += 1;
+            }
+        }
+    }
+    ops.extend((i..a.len()).map(|i| Op::Delete(prefix + i)));
+    ops.extend((j..b.len()).map(|j| Op::Insert(prefix + j)));
+    ops.extend((old.len() - suffix..old.len()).map(Op::Equal));
+    ops
+}
+
+/// `start,count` of a hunk side in unified diff notation, where `before` is
+/// the number of lines preceding the hunk.
+fn hunk_range(before: usize, count: usize) -> String {
+    if count == 0 {
+        format!("{},0", before)
+    } else {
+        format!("{},{}", before + 1, count)
+    }
+}
+
+/// A unified diff turning `old` into `new` with three lines of context, or
+/// `None` when they do not differ.
+pub fn unified(old_label: &str, new_label: &str, old: &str, new: &str) -> Option<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let ops = edit_script(&old_lines, &new_lines);
+    let changes: Vec<usize> = ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| !matches!(op, Op::Equal(_)))
+        .map(|(k, _)| k)
+        .collect();
+    if changes.is_empty() {
+        return None;
+    }
+
+    // Lines of each file before every op, for the hunk headers.
+    let mut before = Vec::with_capacity(ops.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for op in &ops {
+        before.push((o, n));
+        match op {
+            Op::Equal(_) => {
+                o += 1;
+                n += 1;
+            }
+            Op::Delete(_) => o += 1,
+            Op::Insert(_) => n += 1,
+        }
+    }
+    before.push((o, n));
+
+    let mut out = format!("--- {}\n+++ {}\n", old_label, new_label);
+    let mut k = 0;
+    while k < changes.len() {
+        let first = changes[k];
+        while k + 1 < changes.len() && changes[k + 1] - changes[k] <= 2 * CONTEXT + 1 {
+            k += 1;
+        }
+        let start = first.saturating_sub(CONTEXT);
+        let end = (changes[k] + 1 + CONTEXT).min(ops.len());
+        let (old_before, new_before) = before[start];
+        let (old_after, new_after) = before[end];
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(old_before, old_after - old_before),
+            hunk_range(new_before, new_after - new_before)
+        ));
+        for op in &ops[start..end] {
+            let (sign, line) = match *op {
+                Op::Equal(i) => (' ', old_lines[i]),
+                Op::Delete(i) => ('-', old_lines[i]),
+                Op::Insert(j) => ('+', new_lines[j]),
+            };
+            out.push(sign);
+            out.push_str(line);
+            out.push('\n');
+        }
+        k += 1;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified;
+
+    #[test]
+    fn unified_diff_shows_changes_with_context() {
+        let old = "# Generated by mailserver on 2026-01-01T00:00:00+00:00\n\
+                   a\nb\nc\nd\ne\nf\ng\nh\ni\nj\nk\nl\n";
+        let new = "# Generated by mailserver on 2026-02-02T00:00:00+00:00\n\
+                   a\nb\nC\nd\ne\nf\ng\nh\ni\nj\nk\nl\nm\n";
+        assert_eq!(
+            unified("main.cf", "main.cf (generated)", old, new).unwrap(),
+            "--- main.cf\n+++ main.cf (generated)\n\
+             @@ -1,7 +1,7 @@\n \
+             # Generated by mailserver on 2026-01-01T00:00:00+00:00\n a\n b\n-c\n+C\n d\n e\n f\n\
+             @@ -11,3 +11,4 @@\n j\n k\n l\n+m\n"
+        );
+        assert_eq!(
+            unified("a", "b", old, &old.replace("2026-01-01", "2027-01-01")),
+            None
+        );
+        assert_eq!(
+            unified("/dev/null", "new", "", "x\ny\n").unwrap(),
+            "--- /dev/null\n+++ new\n@@ -0,0 +1,2 @@\n+x\n+y\n"
+        );
+    }
+}
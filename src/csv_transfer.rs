@@ -0,0 +1,432 @@
+//! Bulk CSV import and export of domains and accounts.
+//!
+//! Exports use the same columns the importer reads, so a file exported from
+//! one server can be imported into another:
+//!
+//! ```text
+//! domain,active,unsubscribe_enabled
+//! email,name,password,password_hash,quota,active
+//! ```
+//!
+//! An account row needs either a plaintext `password` (hashed on import) or
+//! a `password_hash` in one of the formats accepted by
+//! [`crate::auth::normalize_imported_hash`].  Quotas are in MB.  Every row is
+//! validated first; if any row fails, nothing is created.
+
+use std::collections::{HashMap, HashSet};
+
+use log::{info, warn};
+
+use crate::db::{Account, Database, Domain, NewAccount, NewDomain};
+use crate::validation::{is_valid_domain, is_valid_localpart};
+
+pub const DOMAIN_COLUMNS: [&str; 3] = ["domain", "active", "unsubscribe_enabled"];
+pub const ACCOUNT_COLUMNS: [&str; 6] = [
+    "email",
+    "name",
+    "password",
+    "password_hash",
+    "quota",
+    "active",
+];
+
+/// Outcome of one CSV row.
+#[derive(Debug, PartialEq)]
+pub struct RowResult {
+    pub line: usize,
+    pub record: String,
+    pub error: Option<String>,
+}
+
+/// Outcome of a whole import.  `error` is set when nothing was created.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub rows: Vec<RowResult>,
+    pub created: usize,
+    pub error: Option<String>,
+}
+
+/// A CSV record with the line it starts on.
+pub type Record = (usize, Vec<String>);
+
+/// Split CSV text into records of fields (RFC 4180: quoted fields may
+/// contain commas, doubled quotes and line breaks).  Blank lines are
+/// skipped; each record carries its starting line number.
+pub fn parse_csv(input: &str) -> Result<Vec<Record>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut start_line = 1;
+    let mut chars = input.trim_start_matches('\u{feff}').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                '\n' => {
+                    line += 1;
+                    field.push(c);
+                }
+                _ => field.push(c),
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => in_quotes = true,
+            ',' => record.push(std::mem::take(&mut field)),
+            '\r' => {}
+            '\n' => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|f| !f.trim().is_empty()) {
+                    records.push((start_line, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start_line = line;
+            }
+            _ => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!(
+            "unterminated quoted field starting on line {}",
+            start_line
+        ));
+    }
+    record.push(field);
+    if record.iter().any(|f| !f.trim().is_empty()) {
+        records.push((start_line, record));
+    }
+    Ok(records)
+}
+
+/// Format one CSV record, quoting fields that need it.
+pub fn csv_record(fields: &[&str]) -> String {
+    let quoted: Vec<String> = fields
+        .iter()
+        .map(|f| {
+            if f.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", f.replace('"', "\"\""))
+            } else {
+                f.to_string()
+            }
+        })
+        .collect();
+    format!("{}\r\n", quoted.join(","))
+}
+
+/// Column positions from the header record.
+struct Columns(HashMap<String, usize>);
+
+impl Columns {
+    fn new(header: &[String], required: &[&str]) -> Result<Self, String> {
+        let map: HashMap<String, usize> = header
+            .iter()
+            .enumerate()
+            .map(|(i, h)| (h.trim().to_lowercase(), i))
+            .collect();
+        if let Some(missing) = required.iter().find(|c| !map.contains_key(**c)) {
+            return Err(format!("missing required column '{}'", missing));
+        }
+        Ok(Columns(map))
+    }
+
+    /// The field as written, e.g. for passwords.
+    fn raw<'a>(&self, record: &'a [String], column: &str) -> &'a str {
+        self.0
+            .get(column)
+            .and_then(|&i| record.get(i))
+            .map(String::as_str)
+            .unwrap_or("")
+    }
+
+    fn get<'a>(&self, record: &'a [String], column: &str) -> &'a str {
+        self.raw(record, column).trim()
+    }
+}
+
+fn parse_bool(value: &str, default: bool) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "" => Ok(default),
+        "true" | "yes" | "1" | "on" => Ok(true),
+        "false" | "no" | "0" | "off" => Ok(false),
+        other => Err(format!("'{}' is not a boolean", other)),
+    }
+}
+
+/// Split `input` into a header and data records.
+fn records(input: &str, required: &[&str]) -> Result<(Columns, Vec<Record>), String> {
+    let mut records = parse_csv(input)?.into_iter();
+    let (_, header) = records.next().ok_or("the file is empty")?;
+    let columns = Columns::new(&header, required)?;
+    Ok((columns, records.collect()))
+}
+
+fn parse_domain(
+    columns: &Columns,
+    record: &[String],
+    db: &Database,
+    seen: &mut HashSet<String>,
+) -> Result<NewDomain, String> {
+    let domain = columns.get(record, "domain").to_lowercase();
+    if !is_valid_domain(&domain) {
+        return Err(format!("'{}' is not a valid domain name", domain));
+    }
+    if !seen.insert(domain.clone()) {
+        return Err("duplicate domain in file".to_string());
+    }
+    if db.get_domain_by_name(&domain).is_some() {
+        return Err("domain already exists".to_string());
+    }
+    Ok(NewDomain {
+        active: parse_bool(columns.get(record, "active"), true)?,
+        unsubscribe_enabled: parse_bool(columns.get(record, "unsubscribe_enabled"), false)?,
+        domain,
+    })
+}
+
+fn parse_account(
+    columns: &Columns,
+    record: &[String],
+    db: &Database,
+    seen: &mut HashSet<String>,
+) -> Result<NewAccount, String> {
+    let email = columns.get(record, "email").to_lowercase();
+    let (username, domain_name) = email
+        .split_once('@')
+        .ok_or_else(|| format!("'{}' is not an email address", email))?;
+    if !is_valid_localpart(username) {
+        return Err(format!("'{}' is not a valid mailbox name", username));
+    }
+    let domain = db
+        .get_domain_by_name(domain_name)
+        .ok_or_else(|| format!("domain {} does not exist", domain_name))?;
+    if !seen.insert(email.clone()) {
+        return Err("duplicate account in file".to_string());
+    }
+    if db.get_account_by_email(&email).is_some() {
+        return Err("account already exists".to_string());
+    }
+
+    let password = columns.raw(record, "password");
+    let hash = columns.get(record, "password_hash");
+    let password_hash = match (password.is_empty(), hash.is_empty()) {
+        (false, true) => crate::auth::hash_password(password).map_err(|e| e.to_string())?,
+        (true, false) => crate::auth::normalize_imported_hash(hash)?,
+        (false, false) => return Err("give either password or password_hash, not both".into()),
+        (true, true) => return Err("password or password_hash is required".into()),
+    };
+    let quota = match columns.get(record, "quota") {
+        "" => 0,
+        q => q
+            .parse::<i64>()
+            .ok()
+            .filter(|q| *q >= 0)
+            .ok_or_else(|| format!("'{}' is not a valid quota", q))?,
+    };
+    Ok(NewAccount {
+        domain_id: domain.id,
+        username: username.to_string(),
+        password_hash,
+        name: columns.get(record, "name").to_string(),
+        quota,
+        active: parse_bool(columns.get(record, "active"), true)?,
+    })
+}
+
+/// Validate every record with `parse`, then hand the valid items to
+/// `create` only if all of them passed.
+fn import<T>(
+    kind: &str,
+    input: &str,
+    required: &[&str],
+    key: &str,
+    parse: impl Fn(&Columns, &[String], &mut HashSet<String>) -> Result<T, String>,
+    create: impl FnOnce(&[T]) -> Result<Vec<i64>, String>,
+) -> ImportReport {
+    let (columns, records) = match records(input, required) {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("[csv] rejected {} import: {}", kind, e);
+            return ImportReport {
+                error: Some(e),
+                ..ImportReport::default()
+            };
+        }
+    };
+
+    let mut seen = HashSet::new();
+    let mut items = Vec::new();
+    let mut rows = Vec::new();
+    for (line, record) in &records {
+        let result = parse(&columns, record, &mut seen);
+        let error = match result {
+            Ok(item) => {
+                items.push(item);
+                None
+            }
+            Err(e) => {
+                warn!("[csv] {} import line {}: {}", kind, line, e);
+                Some(e)
+            }
+        };
+        rows.push(RowResult {
+            line: *line,
+            record: columns.get(record, key).to_string(),
+            error,
+        });
+    }
+
+    let failed = rows.iter().filter(|r| r.error.is_some()).count();
+    if failed > 0 {
+        return ImportReport {
+            rows,
+            created: 0,
+            error: Some(format!(
+                "{} of {} rows are invalid; nothing was imported",
+                failed,
+                records.len()
+            )),
+        };
+    }
+    if items.is_empty() {
+        return ImportReport {
+            rows,
+            created: 0,
+            error: Some("the file has no data rows".to_string()),
+        };
+    }
+    match create(&items) {
+        Ok(ids) => {
+            info!("[csv] imported {} {}", ids.len(), kind);
+            ImportReport {
+                rows,
+                created: ids.len(),
+                error: None,
+            }
+        }
+        Err(e) => ImportReport {
+            rows,
+            created: 0,
+            error: Some(format!("import rolled back: {}", e)),
+        },
+    }
+}
+
+pub fn import_domains(db: &Database, input: &str) -> ImportReport {
+    import(
+        "domains",
+        input,
+        &["domain"],
+        "domain",
+        |columns, record, seen| parse_domain(columns, record, db, seen),
+        |items| db.create_domains(items),
+    )
+}
+
+pub fn import_accounts(db: &Database, input: &str) -> ImportReport {
+    import(
+        "accounts",
+        input,
+        &["email"],
+        "email",
+        |columns, record, seen| parse_account(columns, record, db, seen),
+        |items| db.create_accounts(items),
+    )
+}
+
+/// CSV records for `domains`, header first.
+pub fn export_domains(domains: &[Domain]) -> Vec<String> {
+    std::iter::once(csv_record(&DOMAIN_COLUMNS))
+        .chain(domains.iter().map(|d| {
+            csv_record(&[
+                &d.domain,
+                &d.active.to_string(),
+                &d.unsubscribe_enabled.to_string(),
+            ])
+        }))
+        .collect()
+}
+
+/// CSV records for `accounts`, header first.  System accounts are left out.
+pub fn export_accounts(accounts: &[Account]) -> Vec<String> {
+    std::iter::once(csv_record(&ACCOUNT_COLUMNS))
+        .chain(accounts.iter().filter(|a| !a.is_system).map(|a| {
+            let email = format!(
+                "{}@{}",
+                a.username,
+                a.domain_name.as_deref().unwrap_or_default()
+            );
+            csv_record(&[
+                &email,
+                &a.name,
+                "",
+                &a.password_hash,
+                &a.quota.to_string(),
+                &a.active.to_string(),
+            ])
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_quoted_fields_and_skips_blank_lines() {
+        let input = "\u{feff}email,name\r\n\r\njane@example.com,\"Doe, Jane \"\"JD\"\"\"\nbob@example.com,\"two\nlines\"\n";
+        let records = parse_csv(input).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[0],
+            (1, vec!["email".to_string(), "name".to_string()])
+        );
+        assert_eq!(records[1].0, 3);
+        assert_eq!(records[1].1[1], "Doe, Jane \"JD\"");
+        assert_eq!(
+            records[2],
+            (
+                4,
+                vec!["bob@example.com".to_string(), "two\nlines".to_string()]
+            )
+        );
+        assert!(parse_csv("a,\"open\n").is_err());
+    }
+
+    #[test]
+    fn exported_records_parse_back() {
+        let line = csv_record(&[
+            "jane@example.com",
+            "Doe, \"Jane\"",
+            "",
+            "$2b$12$x",
+            "0",
+            "true",
+        ]);
+        assert_eq!(
+            line,
+            "jane@example.com,\"Doe, \"\"Jane\"\"\",,$2b$12$x,0,true\r\n"
+        );
+        let parsed = parse_csv(&line).unwrap();
+        assert_eq!(parsed[0].1[1], "Doe, \"Jane\"");
+        assert_eq!(parsed[0].1.len(), 6);
+    }
+
+    #[test]
+    fn validates_booleans_and_local_parts() {
+        assert_eq!(parse_bool("", true), Ok(true));
+        assert_eq!(parse_bool("No", true), Ok(false));
+        assert!(parse_bool("maybe", true).is_err());
+        assert!(is_valid_localpart("jane.doe-1"));
+        assert!(!is_valid_localpart("jane doe"));
+        assert!(!is_valid_localpart(".jane"));
+        assert!(Columns::new(&["Name".to_string()], &["email"]).is_err());
+    }
+}
@@ -1,16 +1,219 @@
 use log::{debug, error, info, warn};
-use postgres::{Client, NoTls};
+use postgres::{Client, GenericClient, NoTls};
 use serde::{Deserialize, Serialize};
-use std::collections::HashSet;
-use std::sync::{Arc, Mutex, MutexGuard};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+mod backup;
+mod forwarding_loops;
+mod pool;
+mod replication;
+mod webdav_locks;
+
+pub use backup::RestoredTable;
+pub use forwarding_loops::{ForwardingLoop, MAX_FORWARDING_DEPTH};
+pub use webdav_locks::WebDavLock;
+
+/// Default number of pooled PostgreSQL connections, overridable with `DB_POOL_SIZE`.
+const DEFAULT_POOL_SIZE: usize = 8;
+/// How long a caller waits for a pooled connection before giving up.
+const POOL_CHECKOUT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+/// Connection timeout for `try_open`, overridable with `DB_CONNECT_TIMEOUT_SECONDS`.
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 5;
+/// Server-side `statement_timeout` for every pooled connection, overridable
+/// with `DB_STATEMENT_TIMEOUT_SECONDS` (`0` disables it).
+const DEFAULT_STATEMENT_TIMEOUT_SECS: u64 = 30;
+
+fn env_duration_secs(name: &str, default: u64) -> std::time::Duration {
+    let secs = std::env::var(name)
+        .ok()
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .unwrap_or(default);
+    std::time::Duration::from_secs(secs)
+}
+
+/// Parse `url` and apply the connect timeout and, unless zero, a
+/// `statement_timeout` startup option so stuck queries are cancelled by the
+/// server instead of holding a connection forever.
+fn connection_config(
+    url: &str,
+    connect_timeout: std::time::Duration,
+    statement_timeout: std::time::Duration,
+) -> Result<postgres::Config, String> {
+    use std::str::FromStr;
+
+    let mut config = postgres::Config::from_str(url)
+        .map_err(|e| format!("Failed to parse PostgreSQL URL: {}", e))?;
+    config.connect_timeout(connect_timeout);
+    if !statement_timeout.is_zero() {
+        let option = format!("-c statement_timeout={}", statement_timeout.as_millis());
+        let options = match config.get_options() {
+            Some(existing) if !existing.trim().is_empty() => format!("{} {}", existing, option),
+            _ => option,
+        };
+        config.options(&options);
+    }
+    Ok(config)
+}
+
+/// Ban and list entries are stored in canonical form so equal ranges written
+/// differently share one row.
+fn canonical_ip_entry(ip_address: &str) -> String {
+    crate::fail2ban::normalize_ip_or_cidr(ip_address).unwrap_or_else(|| ip_address.to_string())
+}
+
+/// Error of methods returning `Result` when no pooled connection could be
+/// checked out.
+const NO_CONNECTION: &str = "no database connection available";
 
 fn now() -> String {
     chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
 }
 
+/// Text of a failed query including the server's message, which the
+/// `postgres` error's own text ("db error") leaves out.  Callers match on it,
+/// e.g. for "duplicate key".
+fn error_text(e: &postgres::Error) -> String {
+    match e.as_db_error() {
+        Some(db) => format!("{}: {}", e, db),
+        None => e.to_string(),
+    }
+}
+
+/// Error of [`Database::register_account`] when its invite code is unknown
+/// or already used.
+pub const INVITE_UNAVAILABLE: &str = "invite code is invalid or already used";
+
+/// Part of the error of a create whose name belongs to an entry in the
+/// recycle bin.  Names stay unique until the entry is purged.
+pub const IN_RECYCLE_BIN: &str = "is in the recycle bin";
+
+/// Refuse to create `domain` while a deleted domain of that name is in the
+/// recycle bin, telling the admin to restore or purge it.
+fn check_domain_not_binned(client: &mut impl GenericClient, domain: &str) -> Result<(), String> {
+    let binned = client
+        .query_opt(
+            "SELECT 1 FROM domains WHERE domain = $1 AND deleted_at IS NOT NULL",
+            &[&domain],
+        )
+        .map_err(|e| error_text(&e))?
+        .is_some();
+    if binned {
+        return Err(format!(
+            "domain {} {}: restore or purge it there first",
+            domain, IN_RECYCLE_BIN
+        ));
+    }
+    Ok(())
+}
+
+/// Like [`check_domain_not_binned`], for an account of a domain.
+fn check_account_not_binned(
+    client: &mut impl GenericClient,
+    domain_id: i64,
+    username: &str,
+) -> Result<(), String> {
+    let binned = client
+        .query_opt(
+            "SELECT 1 FROM accounts
+             WHERE domain_id = $1 AND username = $2 AND deleted_at IS NOT NULL",
+            &[&domain_id, &username],
+        )
+        .map_err(|e| error_text(&e))?
+        .is_some();
+    if binned {
+        return Err(format!(
+            "account {} {}: restore or purge it there first",
+            username, IN_RECYCLE_BIN
+        ));
+    }
+    Ok(())
+}
+
+/// Give a new domain a row for every reserved role, with the default target.
+fn insert_default_reserved_mailboxes(
+    tx: &mut postgres::Transaction,
+    domain_id: i64,
+) -> Result<(), postgres::Error> {
+    let ts = now();
+    for role in crate::reserved_mailboxes::ROLES {
+        tx.execute(
+            "INSERT INTO domain_reserved_mailboxes (domain_id, role, target, enabled, updated_at)
+             VALUES ($1, $2, $3, TRUE, $4)
+             ON CONFLICT DO NOTHING",
+            &[
+                &domain_id,
+                &role.name,
+                &crate::reserved_mailboxes::default_target(role.name),
+                &ts,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn queued_webhook_from_row(row: &postgres::Row) -> QueuedWebhook {
+    QueuedWebhook {
+        id: row.get(0),
+        url: row.get(1),
+        request_body: row.get(2),
+        sender: row.get(3),
+        subject: row.get(4),
+        attempts: row.get(5),
+        next_attempt_at: row.get(6),
+        last_error: row.get(7),
+        created_at: row.get(8),
+    }
+}
+
+fn pending_approval_from_row(row: &postgres::Row) -> PendingApproval {
+    PendingApproval {
+        id: row.get(0),
+        action: row.get(1),
+        target_id: row.get(2),
+        params: row.get(3),
+        requested_by: row.get(4),
+        status: row.get(5),
+        created_at: row.get(6),
+        expires_at: row.get(7),
+    }
+}
+
+fn api_key_from_row(row: &postgres::Row) -> ApiKey {
+    ApiKey {
+        id: row.get(0),
+        name: row.get(1),
+        key_prefix: row.get(2),
+        key_hash: row.get(3),
+        read_only: row.get(4),
+        created_by: row.get(5),
+        created_at: row.get(6),
+        last_used_at: row.get(7),
+        revoked_at: row.get(8),
+    }
+}
+
+/// `ILIKE` pattern matching `search` anywhere, with wildcards in the search
+/// text escaped.  Blank searches yield `None` (no filtering).
+fn contains_pattern(search: Option<&str>) -> Option<String> {
+    let search = search.map(str::trim).filter(|s| !s.is_empty())?;
+    let mut pattern = String::with_capacity(search.len() + 2);
+    pattern.push('%');
+    for c in search.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    Some(pattern)
+}
+
 #[derive(Clone)]
 pub struct Database {
-    conn: Arc<Mutex<Client>>,
+    pool: Arc<pool::Pool>,
+    /// Set on handles from [`Database::without_statement_timeout`].
+    unbounded: bool,
 }
 
 #[derive(Clone, Serialize)]
@@ -33,8 +236,20 @@ pub struct Domain {
     pub footer_html: Option<String>,
     pub bimi_svg: Option<String>,
     pub unsubscribe_enabled: bool,
+    /// Whether `registration_settings` allows self-registration.
     pub registration_enabled: bool,
-    pub registration_username_regex: String,
+    pub quiet_hours_start: String,
+    pub quiet_hours_end: String,
+    pub quiet_hours_timezone: String,
+    pub helo_name: String,
+    /// Send DMARC aggregate reports for mail received by this domain.
+    pub dmarc_reports_enabled: bool,
+    /// Selector and keys replaced by the last DKIM rotation, published until
+    /// its grace period ends.
+    pub dkim_selector_prev: Option<String>,
+    pub dkim_private_key_prev: Option<String>,
+    pub dkim_public_key_prev: Option<String>,
+    pub dkim_rotated_at: Option<String>,
 }
 
 #[derive(Clone, Serialize)]
@@ -58,6 +273,25 @@ pub struct Account {
     pub is_system: bool,
 }
 
+/// A domain to create in a bulk import.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewDomain {
+    pub domain: String,
+    pub active: bool,
+    pub unsubscribe_enabled: bool,
+}
+
+/// An account to create in a bulk import.
+#[derive(Clone, Debug, PartialEq)]
+pub struct NewAccount {
+    pub domain_id: i64,
+    pub username: String,
+    pub password_hash: String,
+    pub name: String,
+    pub quota: i64,
+    pub active: bool,
+}
+
 #[derive(Clone, Serialize)]
 pub struct Alias {
     pub id: i64,
@@ -152,6 +386,16 @@ pub struct Forwarding {
     pub domain_name: Option<String>,
 }
 
+#[derive(Clone, Serialize)]
+pub struct ScheduledMessage {
+    pub id: i64,
+    pub sender: String,
+    pub recipients: Vec<String>,
+    pub body: String,
+    pub send_at: String,
+    pub created_at: String,
+}
+
 #[derive(Clone, Serialize)]
 pub struct TrackedMessage {
     pub id: i64,
@@ -163,6 +407,31 @@ pub struct TrackedMessage {
     pub created_at: String,
 }
 
+/// A destructive admin action held for a second admin's approval.
+#[derive(Clone, Serialize)]
+pub struct PendingApproval {
+    pub id: i64,
+    pub action: String,
+    pub target_id: Option<i64>,
+    pub params: String,
+    pub requested_by: String,
+    pub status: String,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+/// Pixel requests rejected for one reason on one day, with the last one as
+/// a sample.
+#[derive(Clone, Serialize)]
+pub struct PixelRejection {
+    pub day: String,
+    pub reason: String,
+    pub count: i64,
+    pub last_message_id: String,
+    pub last_client_ip: String,
+    pub last_seen_at: String,
+}
+
 #[derive(Clone, Serialize)]
 pub struct PixelOpen {
     pub id: i64,
@@ -170,9 +439,13 @@ pub struct PixelOpen {
     pub client_ip: String,
     pub user_agent: String,
     pub opened_at: String,
+    /// ISO country code resolved via GeoIP, if available.
+    pub country: Option<String>,
+    /// City name resolved via GeoIP, if available.
+    pub city: Option<String>,
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Default, Serialize)]
 pub struct Stats {
     pub domain_count: i64,
     pub account_count: i64,
@@ -184,6 +457,19 @@ pub struct Stats {
     pub webhook_count: i64,
     pub unsubscribe_count: i64,
     pub dkim_ready_count: i64,
+    /// IMAP and POP3 logins in the last 24 hours.
+    pub login_count: i64,
+    /// Accounts behind those logins.
+    pub login_account_count: i64,
+    /// Active accounts without a login in the last 30 days.
+    pub dormant_account_count: i64,
+}
+
+/// Rows logged on one UTC day (`%Y-%m-%d`).
+#[derive(Clone, Serialize)]
+pub struct DailyCount {
+    pub day: String,
+    pub count: i64,
 }
 
 #[derive(Clone, Serialize)]
@@ -216,6 +502,16 @@ pub struct Fail2banWhitelist {
     pub created_at: String,
 }
 
+#[derive(Clone, Serialize)]
+pub struct Fail2banRule {
+    pub id: i64,
+    pub name: String,
+    pub service: String,
+    pub regex: String,
+    pub enabled: bool,
+    pub created_at: String,
+}
+
 #[derive(Clone, Serialize)]
 pub struct Fail2banBlacklist {
     pub id: i64,
@@ -256,6 +552,101 @@ pub struct WebhookLog {
     pub created_at: String,
 }
 
+/// A per-account rule applied on delivery, see `crate::delivery_rules`.
+#[derive(Clone, Serialize)]
+pub struct DeliveryRule {
+    pub id: i64,
+    pub account_id: i64,
+    pub position: i32,
+    pub match_field: String, // "subject", "from", "to", "cc", "body" or a header name
+    pub match_op: String,    // "contains", "not_contains", "equals", "not_equals", "starts_with", "ends_with"
+    pub pattern: String,
+    pub action: String, // "move" or "add_header"
+    pub target_folder: String,
+    pub header: String,
+    pub created_at: String,
+}
+
+/// A reserved role address of a domain, see `crate::reserved_mailboxes`.
+#[derive(Clone, Serialize)]
+pub struct ReservedMailbox {
+    pub domain_id: i64,
+    pub domain_name: String,
+    pub role: String,   // "postmaster", "abuse", ...
+    pub target: String, // mailbox name on the domain, full address, or "" for none
+    pub enabled: bool,
+}
+
+/// Self-registration rules of a domain, see `/register/<domain>`.
+#[derive(Clone, Serialize)]
+pub struct RegistrationSettings {
+    pub domain_id: i64,
+    pub enabled: bool,
+    /// Usernames must also match this regex; empty allows any valid name.
+    pub username_regex: String,
+    pub min_password_length: i32,
+    /// Registration needs an unused code from `registration_invites`.
+    pub invite_only: bool,
+}
+
+#[derive(Clone, Serialize)]
+pub struct RegistrationInvite {
+    pub id: i64,
+    pub domain_id: i64,
+    pub code: String,
+    pub note: String,
+    pub created_at: String,
+    pub used_at: Option<String>,
+    pub used_by: String, // address registered with the code
+}
+
+#[derive(Clone, Serialize)]
+pub struct QueuedWebhook {
+    pub id: i64,
+    pub url: String,
+    pub request_body: String,
+    pub sender: String,
+    pub subject: String,
+    pub attempts: i32,
+    pub next_attempt_at: String,
+    pub last_error: String,
+    pub created_at: String,
+}
+
+#[derive(Clone, Serialize)]
+pub struct AuditLogEntry {
+    pub id: i64,
+    pub actor: String,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: Option<i64>,
+    pub details: String,
+    pub created_at: String,
+}
+
+/// A REST API key.  Only the prefix and a hash of the full key are stored.
+#[derive(Clone, Serialize)]
+pub struct ApiKey {
+    pub id: i64,
+    pub name: String,
+    pub key_prefix: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    pub read_only: bool,
+    pub created_by: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+    pub revoked_at: Option<String>,
+}
+
+/// A soft-deleted domain or account awaiting restore or purge.
+#[derive(Clone, Serialize)]
+pub struct RecycleBinItem {
+    pub id: i64,
+    pub name: String,
+    pub deleted_at: String,
+}
+
 /// Represents a configured DMARC report inbox.
 ///
 /// DMARC (RFC 7489) defines two reporting mechanisms published in the `_dmarc` TXT DNS record:
@@ -293,6 +684,67 @@ pub struct DmarcInbox {
     pub ruf_account_domain: Option<String>,
 }
 
+/// One `<record>` of a parsed DMARC aggregate report (RFC 7489 appendix C).
+#[derive(Clone, Debug, Default)]
+pub struct DmarcReportRow {
+    pub org_name: String,
+    pub policy_domain: String,
+    pub date_begin: String,
+    pub date_end: String,
+    pub source_ip: String,
+    pub message_count: i64,
+    pub disposition: String,
+    pub dkim_result: String,
+    pub spf_result: String,
+    pub header_from: String,
+}
+
+/// Stored DMARC results of one sending IP, summed over all reports.
+#[derive(Clone, Serialize)]
+pub struct DmarcSourceSummary {
+    pub source_ip: String,
+    pub reports: i64,
+    pub messages: i64,
+    /// Messages that passed DMARC, i.e. aligned DKIM or SPF passed.
+    pub passed: i64,
+    pub dkim_passed: i64,
+    pub spf_passed: i64,
+}
+
+impl DmarcSourceSummary {
+    pub fn failed(&self) -> i64 {
+        self.messages - self.passed
+    }
+}
+
+/// SPF and DKIM outcome of one inbound message, recorded for the DMARC
+/// aggregate reports this server sends.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DmarcAuthResult {
+    /// Local domain that received the message and signs the report.
+    pub recipient_domain: String,
+    /// Domain of the `From:` header, the subject of the report.
+    pub header_from: String,
+    pub source_ip: String,
+    pub dkim_domain: String,
+    pub dkim_result: String,
+    pub spf_domain: String,
+    pub spf_result: String,
+}
+
+/// A DMARC aggregate report this server sent, or skipped for lack of a
+/// usable `rua=` address.
+#[derive(Clone, Debug, Default)]
+pub struct DmarcSentReport {
+    pub domain: String,
+    pub header_from: String,
+    pub report_date: String,
+    pub report_id: String,
+    pub rua: String,
+    pub message_count: i64,
+    pub status: String,
+}
+
 #[derive(Clone, Serialize)]
 pub struct AbuseInbox {
     pub id: i64,
@@ -325,6 +777,23 @@ pub struct OutboundRelay {
     pub username: Option<String>,
     pub password: Option<String>,
     pub active: bool,
+    /// `none`, `starttls` or `smtps` (implicit TLS).
+    pub tls_mode: String,
+    /// Failover order among relays sharing an assignment; lowest is primary.
+    pub priority: i32,
+    /// When the reachability probe last succeeded.
+    pub last_ok: Option<String>,
+    /// Why the latest probe failed; cleared when a probe succeeds.
+    pub last_error: Option<String>,
+    pub last_checked: Option<String>,
+}
+
+/// What one WebDAV user stores.
+#[derive(Clone, Serialize)]
+pub struct WebDavUsage {
+    pub owner: String,
+    pub files: i64,
+    pub bytes: i64,
 }
 
 #[derive(Clone, Serialize)]
@@ -435,24 +904,7 @@ pub struct McpLog {
 
 fn load_available_migrations() -> Vec<(String, String)> {
     let mut migrations = Vec::new();
-    let mut paths = vec![
-        std::path::PathBuf::from("migrations"),
-        std::path::PathBuf::from("/app/migrations"),
-    ];
-
-    if let Ok(exe) = std::env::current_exe() {
-        if let Some(dir) = exe.parent() {
-            paths.push(dir.join("migrations"));
-            paths.push(dir.join("mailserver/migrations"));
-        }
-    }
-
-    if let Ok(cwd) = std::env::current_dir() {
-        paths.push(cwd.join("mailserver/migrations"));
-    }
-
-    let mut seen = HashSet::new();
-    paths.retain(|p| seen.insert(p.to_string_lossy().into_owned()));
+    let paths = crate::config::search_paths("migrations");
     let mut found_any = false;
 
     for path in &paths {
@@ -518,6 +970,39 @@ fn embedded_migrations() -> Vec<(String, String)> {
         ("018_carddav".into(), include_str!("../migrations/018_carddav.sql").into()),
         ("019_bounce_inboxes".into(), include_str!("../migrations/019_bounce_inboxes.sql").into()),
         ("020_jmap".into(), include_str!("../migrations/020_jmap.sql").into()),
+        ("021_idempotency_keys".into(), include_str!("../migrations/021_idempotency_keys.sql").into()),
+        ("022_pixel_open_geo".into(), include_str!("../migrations/022_pixel_open_geo.sql").into()),
+        ("023_quiet_hours".into(), include_str!("../migrations/023_quiet_hours.sql").into()),
+        ("024_filter_decision_cache".into(), include_str!("../migrations/024_filter_decision_cache.sql").into()),
+        ("025_audit_log".into(), include_str!("../migrations/025_audit_log.sql").into()),
+        ("026_soft_delete".into(), include_str!("../migrations/026_soft_delete.sql").into()),
+        ("027_api_keys".into(), include_str!("../migrations/027_api_keys.sql").into()),
+        ("028_admin_recovery_codes".into(), include_str!("../migrations/028_admin_recovery_codes.sql").into()),
+        ("029_domain_helo_name".into(), include_str!("../migrations/029_domain_helo_name.sql").into()),
+        ("030_fail2ban_rules".into(), include_str!("../migrations/030_fail2ban_rules.sql").into()),
+        ("031_account_disabled_policy".into(), include_str!("../migrations/031_account_disabled_policy.sql").into()),
+        ("032_dmarc_report_rows".into(), include_str!("../migrations/032_dmarc_report_rows.sql").into()),
+        ("033_dmarc_feedback".into(), include_str!("../migrations/033_dmarc_feedback.sql").into()),
+        ("034_pixel_rejections".into(), include_str!("../migrations/034_pixel_rejections.sql").into()),
+        ("035_pending_approvals".into(), include_str!("../migrations/035_pending_approvals.sql").into()),
+        ("036_relay_tls_mode".into(), include_str!("../migrations/036_relay_tls_mode.sql").into()),
+        ("037_relay_failover".into(), include_str!("../migrations/037_relay_failover.sql").into()),
+        ("038_webhook_queue".into(), include_str!("../migrations/038_webhook_queue.sql").into()),
+        ("039_dkim_rotation".into(), include_str!("../migrations/039_dkim_rotation.sql").into()),
+        ("040_mailbox_usage".into(), include_str!("../migrations/040_mailbox_usage.sql").into()),
+        ("041_delivery_rules".into(), include_str!("../migrations/041_delivery_rules.sql").into()),
+        ("042_pixel_open_dedup".into(), include_str!("../migrations/042_pixel_open_dedup.sql").into()),
+        ("043_login_events".into(), include_str!("../migrations/043_login_events.sql").into()),
+        ("044_greylist".into(), include_str!("../migrations/044_greylist.sql").into()),
+        ("045_send_limits".into(), include_str!("../migrations/045_send_limits.sql").into()),
+        ("046_reserved_mailboxes".into(), include_str!("../migrations/046_reserved_mailboxes.sql").into()),
+        ("047_registration_settings".into(), include_str!("../migrations/047_registration_settings.sql").into()),
+        ("048_totp_replay".into(), include_str!("../migrations/048_totp_replay.sql").into()),
+        ("049_pixel_opens_by_time".into(), include_str!("../migrations/049_pixel_opens_by_time.sql").into()),
+        ("050_webdav_locks".into(), include_str!("../migrations/050_webdav_locks.sql").into()),
+        ("051_idempotency_reservations".into(), include_str!("../migrations/051_idempotency_reservations.sql").into()),
+        ("052_pixel_first_open".into(), include_str!("../migrations/052_pixel_first_open.sql").into()),
+        ("053_pixel_rejection_counts".into(), include_str!("../migrations/053_pixel_rejection_counts.sql").into()),
     ];
     m.sort_by(|a, b| a.0.cmp(&b.0));
     m
@@ -541,6 +1026,11 @@ fn minimal_runtime_bootstrap_sql() -> [&'static str; 1] {
 fn run_migrations(client: &mut Client) {
     info!("[db] checking for database migrations");
 
+    // Migrations may legitimately run for a long time on big tables.
+    client
+        .batch_execute("SET statement_timeout = 0")
+        .expect("Failed to disable statement timeout for migrations");
+
     // 1. Create _migrations table if it doesn't exist
     client
         .execute(
@@ -586,9 +1076,14 @@ fn run_migrations(client: &mut Client) {
             debug!("[db] migration {} already applied", name);
         }
     }
+
+    client
+        .batch_execute("RESET statement_timeout")
+        .expect("Failed to restore statement timeout after migrations");
 }
 
-fn matches_from_pattern(pattern: &str, sender: &str) -> bool {
+/// Match a lower-cased address against a `*`, `*@domain` or exact pattern.
+pub(crate) fn matches_from_pattern(pattern: &str, sender: &str) -> bool {
     let p = pattern.trim().to_ascii_lowercase();
     if p == "*" {
         return true;
@@ -613,14 +1108,23 @@ fn evaluate_condition(
         "size" => size_bytes.to_string(),
         _ => return false,
     };
-    let cond_value = condition.value.to_ascii_lowercase();
-    match condition.operator.as_str() {
-        "contains" => field_value.contains(cond_value.as_str()),
-        "not_contains" => !field_value.contains(cond_value.as_str()),
+    compare_condition(
+        &condition.operator,
+        &field_value,
+        &condition.value.to_ascii_lowercase(),
+    )
+}
+
+/// Apply a rule `operator` to a lower-cased field value and condition value.
+/// Unknown operators never match.
+pub(crate) fn compare_condition(operator: &str, field_value: &str, cond_value: &str) -> bool {
+    match operator {
+        "contains" => field_value.contains(cond_value),
+        "not_contains" => !field_value.contains(cond_value),
         "equals" => field_value == cond_value,
         "not_equals" => field_value != cond_value,
-        "starts_with" => field_value.starts_with(cond_value.as_str()),
-        "ends_with" => field_value.ends_with(cond_value.as_str()),
+        "starts_with" => field_value.starts_with(cond_value),
+        "ends_with" => field_value.ends_with(cond_value),
         "larger_than" => {
             let threshold: usize = cond_value.parse().unwrap_or(0);
             let val: usize = field_value.parse().unwrap_or(0);
@@ -672,30 +1176,29 @@ impl Database {
             url,
             30,
             std::time::Duration::from_secs(1),
-            std::time::Duration::from_secs(5),
+            env_duration_secs("DB_CONNECT_TIMEOUT_SECONDS", DEFAULT_CONNECT_TIMEOUT_SECS),
         )
     }
 
     /// Try to open a database connection with configurable retry and timeout behavior.
     /// The `connect_timeout` is applied to each attempt to prevent long blocking when
-    /// PostgreSQL is unreachable.
+    /// PostgreSQL is unreachable.  Every connection also gets the
+    /// `DB_STATEMENT_TIMEOUT_SECONDS` statement timeout.
     pub fn try_open_with_options(
         url: &str,
         max_retries: u32,
         retry_delay: std::time::Duration,
         connect_timeout: std::time::Duration,
     ) -> Result<Self, String> {
-        use postgres::config::Config;
-        use std::str::FromStr;
-
         info!("[db] opening PostgreSQL database at url={}", url);
-        let base_config =
-            Config::from_str(url).map_err(|e| format!("Failed to parse PostgreSQL URL: {}", e))?;
+        let statement_timeout = env_duration_secs(
+            "DB_STATEMENT_TIMEOUT_SECONDS",
+            DEFAULT_STATEMENT_TIMEOUT_SECS,
+        );
+        let config = connection_config(url, connect_timeout, statement_timeout)?;
 
         let mut retry_count = 0;
         let mut client = loop {
-            let mut config = base_config.clone();
-            config.connect_timeout(connect_timeout);
             match config.connect(NoTls) {
                 Ok(c) => break c,
                 Err(e) => {
@@ -721,25 +1224,60 @@ impl Database {
 
         run_migrations(&mut client);
 
-        info!("[db] PostgreSQL database opened and schema initialized successfully");
+        let pool_size = std::env::var("DB_POOL_SIZE")
+            .ok()
+            .and_then(|v| v.trim().parse::<usize>().ok())
+            .filter(|&n| n > 0)
+            .unwrap_or(DEFAULT_POOL_SIZE);
+        info!(
+            "[db] PostgreSQL database opened and schema initialized successfully (pool size {})",
+            pool_size
+        );
         Ok(Database {
-            conn: Arc::new(Mutex::new(client)),
+            pool: Arc::new(pool::Pool::new(config, client, pool_size, POOL_CHECKOUT_TIMEOUT)),
+            unbounded: false,
         })
     }
 
-    /// Acquire the database connection, recovering from mutex poisoning.
-    fn conn(&self) -> MutexGuard<'_, Client> {
-        self.conn.lock().unwrap_or_else(|e| {
-            warn!("[db] mutex was poisoned, recovering connection");
-            e.into_inner()
-        })
+    /// A handle on the same pool whose queries run without the statement
+    /// timeout, for long but legitimate jobs such as bulk imports and exports.
+    pub fn without_statement_timeout(&self) -> Database {
+        Database {
+            pool: Arc::clone(&self.pool),
+            unbounded: true,
+        }
+    }
+
+    /// Check out a pooled database connection; it is returned when dropped.
+    /// `None` when no connection could be had within the checkout timeout;
+    /// callers then return what they return for a failed query.
+    fn conn(&self) -> Option<pool::PooledClient<'_>> {
+        let mut conn = self.pool.get()?;
+        if self.unbounded {
+            conn.disable_statement_timeout();
+        }
+        Some(conn)
+    }
+
+    /// Run a trivial query to check that PostgreSQL is reachable, failing
+    /// fast instead of waiting for a pooled connection.  Idle connections
+    /// left over from before a database restart are skipped.
+    pub fn ping(&self) -> Result<(), String> {
+        loop {
+            let mut conn = self.pool.try_get().map_err(|e| e.to_string())?;
+            match conn.query_one("SELECT 1", &[]) {
+                Ok(_) => return Ok(()),
+                Err(_) if conn.is_closed() => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
     }
 
     // ── Admin methods ──
 
     pub fn get_admin_by_username(&self, username: &str) -> Option<Admin> {
         debug!("[db] looking up admin username={}", username);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         let row = conn
             .query_opt(
                 "SELECT id, username, password_hash, totp_secret, totp_enabled FROM admins WHERE username = $1",
@@ -766,7 +1304,7 @@ impl Database {
 
     pub fn get_first_admin(&self) -> Option<Admin> {
         debug!("[db] looking up first admin");
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, username, password_hash, totp_secret, totp_enabled FROM admins LIMIT 1",
             &[],
@@ -784,7 +1322,9 @@ impl Database {
 
     pub fn update_admin_password(&self, id: i64, hash: &str) {
         info!("[db] updating admin password id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE admins SET password_hash = $1, updated_at = $2 WHERE id = $3",
             &[&hash, &now(), &id],
@@ -795,7 +1335,9 @@ impl Database {
 
     pub fn update_admin_totp(&self, id: i64, secret: Option<&str>, enabled: bool) {
         info!("[db] updating admin TOTP id={}, enabled={}", id, enabled);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE admins SET totp_secret = $1, totp_enabled = $2, updated_at = $3 WHERE id = $4",
             &[&secret, &enabled, &now(), &id],
@@ -804,9 +1346,124 @@ impl Database {
         }
     }
 
+    /// Record that admin `id` used the TOTP code of `step` from `ip`.  Fails
+    /// when a later step was already used, or this one from another client;
+    /// the same client may repeat it because Basic auth re-sends the
+    /// credentials with every request.
+    pub fn claim_totp_step(&self, id: i64, step: u64, ip: &str) -> bool {
+        let step = step as i64;
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        match conn.execute(
+            "UPDATE admins SET totp_last_step = $2, totp_last_ip = $3
+             WHERE id = $1 AND (totp_last_step < $2 OR (totp_last_step = $2 AND totp_last_ip = $3))",
+            &[&id, &step, &ip],
+        ) {
+            Ok(n) => n == 1,
+            Err(e) => {
+                error!("[db] failed to claim TOTP step for admin id={}: {}", id, e);
+                false
+            }
+        }
+    }
+
+    /// Replace all recovery codes of an admin with `code_hashes`.
+    pub fn replace_recovery_codes(
+        &self,
+        admin_id: i64,
+        code_hashes: &[String],
+    ) -> Result<(), String> {
+        info!(
+            "[db] replacing recovery codes for admin id={} ({} codes)",
+            admin_id,
+            code_hashes.len()
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "DELETE FROM admin_recovery_codes WHERE admin_id = $1",
+            &[&admin_id],
+        )
+        .map_err(|e| e.to_string())?;
+        let ts = now();
+        for hash in code_hashes {
+            tx.execute(
+                "INSERT INTO admin_recovery_codes (admin_id, code_hash, created_at) VALUES ($1, $2, $3)",
+                &[&admin_id, hash, &ts],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| {
+            error!(
+                "[db] failed to store recovery codes for admin id={}: {}",
+                admin_id, e
+            );
+            e.to_string()
+        })
+    }
+
+    pub fn delete_recovery_codes(&self, admin_id: i64) {
+        info!("[db] deleting recovery codes for admin id={}", admin_id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM admin_recovery_codes WHERE admin_id = $1",
+            &[&admin_id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    pub fn count_unused_recovery_codes(&self, admin_id: i64) -> i64 {
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
+        conn.query_one(
+            "SELECT COUNT(*) FROM admin_recovery_codes WHERE admin_id = $1 AND used_at IS NULL",
+            &[&admin_id],
+        )
+        .map(|row| row.get(0))
+        .unwrap_or(0)
+    }
+
+    /// Redeem a recovery code, marking it used.  A code redeemed less than
+    /// `grace_minutes` ago is still accepted so the browser can repeat the
+    /// same credentials on follow-up requests.
+    pub fn redeem_recovery_code(&self, admin_id: i64, code_hash: &str, grace_minutes: i64) -> bool {
+        debug!("[db] redeeming recovery code for admin id={}", admin_id);
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        let ts = now();
+        let cutoff = (chrono::Utc::now() - chrono::Duration::minutes(grace_minutes))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        match conn.query_opt(
+            "UPDATE admin_recovery_codes SET used_at = COALESCE(used_at, $3)
+             WHERE admin_id = $1 AND code_hash = $2 AND (used_at IS NULL OR used_at > $4)
+             RETURNING id",
+            &[&admin_id, &code_hash, &ts, &cutoff],
+        ) {
+            Ok(row) => row.is_some(),
+            Err(e) => {
+                error!(
+                    "[db] failed to redeem recovery code for admin id={}: {}",
+                    admin_id, e
+                );
+                false
+            }
+        }
+    }
+
     pub fn seed_admin(&self, username: &str, password_hash: &str) -> Result<(), String> {
         info!("[db] seeding admin user: {}", username);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         conn.execute(
             "INSERT INTO admins (username, password_hash, created_at, updated_at)
@@ -821,51 +1478,203 @@ impl Database {
         Ok(())
     }
 
-    // ── Domain methods ──
-
-    pub fn list_domains(&self) -> Vec<Domain> {
-        debug!("[db] listing all domains");
-        let mut conn = self.conn();
-        let rows = conn
-            .query(
-                "SELECT id, domain, active, dkim_selector, dkim_private_key, dkim_public_key, footer_html, bimi_svg, unsubscribe_enabled, registration_enabled, registration_username_regex
-                 FROM domains ORDER BY domain",
-                &[],
-            )
+    pub fn count_admins(&self) -> i64 {
+        debug!("[db] counting admins");
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
+        conn.query_one("SELECT COUNT(*) FROM admins", &[])
+            .map(|row| row.get(0))
             .unwrap_or_else(|e| {
-                error!("[db] failed to list domains: {}", e);
-                Vec::new()
-            });
-
-        rows.into_iter()
-            .map(|row| Domain {
-                id: row.get(0),
-                domain: row.get(1),
-                active: row.get(2),
-                dkim_selector: row.get(3),
-                dkim_private_key: row.get(4),
-                dkim_public_key: row.get(5),
-                footer_html: row.get(6),
-                bimi_svg: row.get(7),
-                unsubscribe_enabled: row.get(8),
-                registration_enabled: row.get::<_, Option<bool>>(9).unwrap_or(false),
-                registration_username_regex: row.get::<_, Option<String>>(10).unwrap_or_default(),
+                error!("[db] failed to count admins: {}", e);
+                0
             })
-            .collect()
     }
 
-    pub fn get_domain(&self, id: i64) -> Option<Domain> {
-        debug!("[db] getting domain id={}", id);
-        let mut conn = self.conn();
-        conn.query_opt(
-            "SELECT id, domain, active, dkim_selector, dkim_private_key, dkim_public_key, footer_html, bimi_svg, unsubscribe_enabled, registration_enabled, registration_username_regex
-             FROM domains WHERE id = $1",
-            &[&id],
-        )
-        .ok()
-        .flatten()
-        .map(|row| Domain {
-            id: row.get(0),
+    // ── Approval methods ──
+
+    pub fn create_pending_approval(
+        &self,
+        action: &str,
+        target_id: Option<i64>,
+        params: &str,
+        requested_by: &str,
+        ttl_hours: i64,
+    ) -> Option<i64> {
+        info!(
+            "[db] creating pending approval action={}, target_id={:?}, requested_by={}",
+            action, target_id, requested_by
+        );
+        let mut conn = self.conn()?;
+        let expires_at = (chrono::Utc::now() + chrono::Duration::hours(ttl_hours))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        conn.query_one(
+            "INSERT INTO pending_approvals (action, target_id, params, requested_by, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id",
+            &[&action, &target_id, &params, &requested_by, &now(), &expires_at],
+        )
+        .map(|row| row.get(0))
+        .map_err(|e| error!("[db] failed to create pending approval: {}", e))
+        .ok()
+    }
+
+    /// Pending approvals that have not expired, oldest first.  Expired ones
+    /// are marked as such on the way.
+    pub fn list_pending_approvals(&self) -> Vec<PendingApproval> {
+        debug!("[db] listing pending approvals");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let ts = now();
+        if let Err(e) = conn.execute(
+            "UPDATE pending_approvals SET status = 'expired'
+             WHERE status = 'pending' AND expires_at <= $1",
+            &[&ts],
+        ) {
+            error!("[db] failed to expire pending approvals: {}", e);
+        }
+        conn.query(
+            "SELECT id, action, target_id, params, requested_by, status, created_at, expires_at
+             FROM pending_approvals
+             WHERE status = 'pending'
+             ORDER BY id",
+            &[],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list pending approvals: {}", e);
+            Vec::new()
+        })
+        .iter()
+        .map(pending_approval_from_row)
+        .collect()
+    }
+
+    /// Mark a pending approval as approved by `approver`, who must not be its
+    /// requester.  Returns the record, or why it cannot be approved.
+    pub fn approve_pending_approval(
+        &self,
+        id: i64,
+        approver: &str,
+    ) -> Result<PendingApproval, String> {
+        info!("[db] approving pending approval id={} by {}", id, approver);
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let ts = now();
+        let claimed = conn
+            .query_opt(
+                "UPDATE pending_approvals SET status = 'approved', decided_by = $2, decided_at = $3
+                 WHERE id = $1 AND status = 'pending' AND expires_at > $3 AND requested_by <> $2
+                 RETURNING id, action, target_id, params, requested_by, status, created_at, expires_at",
+                &[&id, &approver, &ts],
+            )
+            .map_err(|e| {
+                error!("[db] failed to approve pending approval id={}: {}", id, e);
+                e.to_string()
+            })?;
+        if let Some(row) = claimed {
+            return Ok(pending_approval_from_row(&row));
+        }
+        let existing = conn
+            .query_opt(
+                "SELECT id, action, target_id, params, requested_by, status, created_at, expires_at
+                 FROM pending_approvals WHERE id = $1",
+                &[&id],
+            )
+            .map_err(|e| e.to_string())?
+            .map(|row| pending_approval_from_row(&row));
+        Err(match existing {
+            None => "No such approval request.".to_string(),
+            Some(a) if a.status != "pending" => format!("This request is already {}.", a.status),
+            Some(a) if a.requested_by == approver => {
+                "A request cannot be approved by the admin who made it.".to_string()
+            }
+            Some(_) => "This request has expired.".to_string(),
+        })
+    }
+
+    /// Withdraw or reject a pending approval.  Returns false if it was no
+    /// longer pending.
+    pub fn cancel_pending_approval(&self, id: i64, by: &str) -> bool {
+        info!("[db] cancelling pending approval id={} by {}", id, by);
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        conn.execute(
+            "UPDATE pending_approvals SET status = 'cancelled', decided_by = $2, decided_at = $3
+             WHERE id = $1 AND status = 'pending'",
+            &[&id, &by, &now()],
+        )
+        .map(|n| n > 0)
+        .unwrap_or_else(|e| {
+            error!("[db] failed to cancel pending approval id={}: {}", id, e);
+            false
+        })
+    }
+
+    // ── Domain methods ──
+
+    pub fn list_domains(&self) -> Vec<Domain> {
+        debug!("[db] listing all domains");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT id, domain, active, dkim_selector, dkim_private_key, dkim_public_key, footer_html, bimi_svg, unsubscribe_enabled,
+                 COALESCE((SELECT r.enabled FROM registration_settings r WHERE r.domain_id = domains.id), FALSE),
+                 quiet_hours_start, quiet_hours_end, quiet_hours_timezone, helo_name, dmarc_reports_enabled,
+                 dkim_selector_prev, dkim_private_key_prev, dkim_public_key_prev, dkim_rotated_at
+                 FROM domains WHERE deleted_at IS NULL ORDER BY domain",
+                &[],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list domains: {}", e);
+                Vec::new()
+            });
+
+        rows.into_iter()
+            .map(|row| Domain {
+                id: row.get(0),
+                domain: row.get(1),
+                active: row.get(2),
+                dkim_selector: row.get(3),
+                dkim_private_key: row.get(4),
+                dkim_public_key: row.get(5),
+                footer_html: row.get(6),
+                bimi_svg: row.get(7),
+                unsubscribe_enabled: row.get(8),
+                registration_enabled: row.get(9),
+                quiet_hours_start: row.get::<_, Option<String>>(10).unwrap_or_default(),
+                quiet_hours_end: row.get::<_, Option<String>>(11).unwrap_or_default(),
+                quiet_hours_timezone: row.get::<_, Option<String>>(12).unwrap_or_else(|| "UTC".to_string()),
+                helo_name: row.get::<_, Option<String>>(13).unwrap_or_default(),
+                dmarc_reports_enabled: row.get(14),
+                dkim_selector_prev: row.get(15),
+                dkim_private_key_prev: row.get(16),
+                dkim_public_key_prev: row.get(17),
+                dkim_rotated_at: row.get(18),
+            })
+            .collect()
+    }
+
+    pub fn get_domain(&self, id: i64) -> Option<Domain> {
+        debug!("[db] getting domain id={}", id);
+        let mut conn = self.conn()?;
+        conn.query_opt(
+            "SELECT id, domain, active, dkim_selector, dkim_private_key, dkim_public_key, footer_html, bimi_svg, unsubscribe_enabled,
+             COALESCE((SELECT r.enabled FROM registration_settings r WHERE r.domain_id = domains.id), FALSE),
+             quiet_hours_start, quiet_hours_end, quiet_hours_timezone, helo_name, dmarc_reports_enabled,
+             dkim_selector_prev, dkim_private_key_prev, dkim_public_key_prev, dkim_rotated_at
+             FROM domains WHERE id = $1 AND deleted_at IS NULL",
+            &[&id],
+        )
+        .ok()
+        .flatten()
+        .map(|row| Domain {
+            id: row.get(0),
             domain: row.get(1),
             active: row.get(2),
             dkim_selector: row.get(3),
@@ -874,17 +1683,28 @@ impl Database {
             footer_html: row.get(6),
             bimi_svg: row.get(7),
             unsubscribe_enabled: row.get(8),
-            registration_enabled: row.get::<_, Option<bool>>(9).unwrap_or(false),
-            registration_username_regex: row.get::<_, Option<String>>(10).unwrap_or_default(),
+            registration_enabled: row.get(9),
+            quiet_hours_start: row.get::<_, Option<String>>(10).unwrap_or_default(),
+            quiet_hours_end: row.get::<_, Option<String>>(11).unwrap_or_default(),
+            quiet_hours_timezone: row.get::<_, Option<String>>(12).unwrap_or_else(|| "UTC".to_string()),
+            helo_name: row.get::<_, Option<String>>(13).unwrap_or_default(),
+            dmarc_reports_enabled: row.get(14),
+            dkim_selector_prev: row.get(15),
+            dkim_private_key_prev: row.get(16),
+            dkim_public_key_prev: row.get(17),
+            dkim_rotated_at: row.get(18),
         })
     }
 
     pub fn get_domain_by_name(&self, domain_name: &str) -> Option<Domain> {
         debug!("[db] getting domain by name={}", domain_name);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
-            "SELECT id, domain, active, dkim_selector, dkim_private_key, dkim_public_key, footer_html, bimi_svg, unsubscribe_enabled, registration_enabled, registration_username_regex
-             FROM domains WHERE LOWER(domain) = LOWER($1)",
+            "SELECT id, domain, active, dkim_selector, dkim_private_key, dkim_public_key, footer_html, bimi_svg, unsubscribe_enabled,
+             COALESCE((SELECT r.enabled FROM registration_settings r WHERE r.domain_id = domains.id), FALSE),
+             quiet_hours_start, quiet_hours_end, quiet_hours_timezone, helo_name, dmarc_reports_enabled,
+             dkim_selector_prev, dkim_private_key_prev, dkim_public_key_prev, dkim_rotated_at
+             FROM domains WHERE LOWER(domain) = LOWER($1) AND deleted_at IS NULL",
             &[&domain_name],
         )
         .ok()
@@ -899,8 +1719,16 @@ impl Database {
             footer_html: row.get(6),
             bimi_svg: row.get(7),
             unsubscribe_enabled: row.get(8),
-            registration_enabled: row.get::<_, Option<bool>>(9).unwrap_or(false),
-            registration_username_regex: row.get::<_, Option<String>>(10).unwrap_or_default(),
+            registration_enabled: row.get(9),
+            quiet_hours_start: row.get::<_, Option<String>>(10).unwrap_or_default(),
+            quiet_hours_end: row.get::<_, Option<String>>(11).unwrap_or_default(),
+            quiet_hours_timezone: row.get::<_, Option<String>>(12).unwrap_or_else(|| "UTC".to_string()),
+            helo_name: row.get::<_, Option<String>>(13).unwrap_or_default(),
+            dmarc_reports_enabled: row.get(14),
+            dkim_selector_prev: row.get(15),
+            dkim_private_key_prev: row.get(16),
+            dkim_public_key_prev: row.get(17),
+            dkim_rotated_at: row.get(18),
         })
     }
 
@@ -912,9 +1740,13 @@ impl Database {
     ) -> Result<i64, String> {
         info!("[db] creating domain: {}", domain);
         let id = {
-            let mut conn = self.conn();
+            let Some(mut conn) = self.conn() else {
+                return Err(NO_CONNECTION.to_string());
+            };
+            let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+            check_domain_not_binned(&mut tx, domain)?;
             let ts = now();
-            let row = conn
+            let row = tx
                 .query_one(
                     "INSERT INTO domains (domain, footer_html, bimi_svg, unsubscribe_enabled, created_at, updated_at)
                      VALUES ($1, '', $2, $3, $4, $5)
@@ -922,15 +1754,60 @@ impl Database {
                     &[&domain, &bimi_svg, &unsubscribe_enabled, &ts, &ts],
                 )
                 .map_err(|e| {
-                    error!("[db] failed to create domain {}: {}", domain, e);
-                    e.to_string()
+                    let text = error_text(&e);
+                    error!("[db] failed to create domain {}: {}", domain, text);
+                    text
                 })?;
-            row.get::<_, i64>(0)
+            let id = row.get::<_, i64>(0);
+            insert_default_reserved_mailboxes(&mut tx, id).map_err(|e| {
+                error!("[db] failed to add reserved mailboxes for domain {}: {}", domain, e);
+                e.to_string()
+            })?;
+            tx.commit().map_err(|e| e.to_string())?;
+            id
         };
         info!("[db] domain created: {} (id={})", domain, id);
         Ok(id)
     }
 
+    /// Create all `domains` in one transaction; nothing is created if any
+    /// insert fails.
+    pub fn create_domains(&self, domains: &[NewDomain]) -> Result<Vec<i64>, String> {
+        info!("[db] bulk creating {} domains", domains.len());
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        let ts = now();
+        let mut ids = Vec::with_capacity(domains.len());
+        for d in domains {
+            check_domain_not_binned(&mut tx, &d.domain)?;
+            let row = tx
+                .query_one(
+                    "INSERT INTO domains (domain, footer_html, bimi_svg, unsubscribe_enabled, active, created_at, updated_at)
+                     VALUES ($1, '', '', $2, $3, $4, $5)
+                     RETURNING id",
+                    &[&d.domain, &d.unsubscribe_enabled, &d.active, &ts, &ts],
+                )
+                .map_err(|e| {
+                    error!("[db] failed to create domain {}: {}", d.domain, e);
+                    format!("{}: {}", d.domain, e)
+                })?;
+            let id = row.get::<_, i64>(0);
+            insert_default_reserved_mailboxes(&mut tx, id).map_err(|e| {
+                error!("[db] failed to add reserved mailboxes for domain {}: {}", d.domain, e);
+                format!("{}: {}", d.domain, e)
+            })?;
+            ids.push(id);
+        }
+        tx.commit().map_err(|e| {
+            error!("[db] failed to commit domain import: {}", e);
+            e.to_string()
+        })?;
+        info!("[db] bulk created {} domains", ids.len());
+        Ok(ids)
+    }
+
     pub fn update_domain(
         &self,
         id: i64,
@@ -938,27 +1815,24 @@ impl Database {
         active: bool,
         bimi_svg: &str,
         unsubscribe_enabled: bool,
-        registration_enabled: bool,
-        registration_username_regex: &str,
     ) {
         info!(
-            "[db] updating domain id={}, domain={}, active={}, bimi_present={}, unsubscribe_enabled={}, registration_enabled={}",
+            "[db] updating domain id={}, domain={}, active={}, bimi_present={}, unsubscribe_enabled={}",
             id,
             domain,
             active,
             !bimi_svg.trim().is_empty(),
-            unsubscribe_enabled,
-            registration_enabled
+            unsubscribe_enabled
         );
         {
-            let mut conn = self.conn();
+            let Some(mut conn) = self.conn() else {
+                return;
+            };
             if let Err(e) = conn.execute(
                 "UPDATE domains
-                 SET domain = $1, active = $2, bimi_svg = $3, unsubscribe_enabled = $4,
-                     registration_enabled = $5, registration_username_regex = $6, updated_at = $7
-                 WHERE id = $8",
-                &[&domain, &active, &bimi_svg, &unsubscribe_enabled,
-                  &registration_enabled, &registration_username_regex, &now(), &id],
+                 SET domain = $1, active = $2, bimi_svg = $3, unsubscribe_enabled = $4, updated_at = $5
+                 WHERE id = $6",
+                &[&domain, &active, &bimi_svg, &unsubscribe_enabled, &now(), &id],
             ) {
                 error!("[db] failed to execute query: {}", e);
                 return;
@@ -966,16 +1840,391 @@ impl Database {
         }
     }
 
+    /// Move a domain to the recycle bin.  Its accounts, aliases and
+    /// forwardings are hidden with it until it is restored or purged.
     pub fn delete_domain(&self, id: i64) {
-        warn!("[db] deleting domain id={}", id);
-        let _domain_name = self.get_domain(id).map(|d| d.domain);
-        {
-            let mut conn = self.conn();
-            if let Err(e) = conn.execute("DELETE FROM domains WHERE id = $1", &[&id]) {
-                error!("[db] failed to execute query: {}", e);
-                return;
+        warn!("[db] soft-deleting domain id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE domains SET deleted_at = $1, updated_at = $1 WHERE id = $2 AND deleted_at IS NULL",
+            &[&now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    pub fn restore_domain(&self, id: i64) {
+        info!("[db] restoring domain id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE domains SET deleted_at = NULL, updated_at = $1 WHERE id = $2",
+            &[&now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    /// Permanently delete a domain from the recycle bin (cascades).
+    pub fn purge_domain(&self, id: i64) {
+        warn!("[db] purging domain id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM domains WHERE id = $1 AND deleted_at IS NOT NULL",
+            &[&id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    pub fn list_deleted_domains(&self) -> Vec<RecycleBinItem> {
+        debug!("[db] listing soft-deleted domains");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "SELECT id, domain, deleted_at FROM domains
+             WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC",
+            &[],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list deleted domains: {}", e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|row| RecycleBinItem {
+            id: row.get(0),
+            name: row.get(1),
+            deleted_at: row.get(2),
+        })
+        .collect()
+    }
+
+    pub fn update_domain_quiet_hours(&self, id: i64, start: &str, end: &str, timezone: &str) {
+        info!(
+            "[db] updating quiet hours for domain id={}, start={}, end={}, timezone={}",
+            id, start, end, timezone
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE domains
+             SET quiet_hours_start = $1, quiet_hours_end = $2, quiet_hours_timezone = $3, updated_at = $4
+             WHERE id = $5",
+            &[&start, &end, &timezone, &now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    pub fn update_domain_helo_name(&self, id: i64, helo_name: &str) {
+        info!(
+            "[db] updating HELO name for domain id={}, helo_name={}",
+            id, helo_name
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE domains SET helo_name = $1, updated_at = $2 WHERE id = $3",
+            &[&helo_name, &now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    pub fn update_domain_dmarc_reports(&self, id: i64, enabled: bool) {
+        info!(
+            "[db] updating DMARC reporting for domain id={}, enabled={}",
+            id, enabled
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE domains SET dmarc_reports_enabled = $1, updated_at = $2 WHERE id = $3",
+            &[&enabled, &now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    // ── Reserved mailbox methods ──
+
+    pub fn list_reserved_mailboxes(&self, domain_id: i64) -> Vec<ReservedMailbox> {
+        debug!("[db] listing reserved mailboxes for domain id={}", domain_id);
+        self.query_reserved_mailboxes("WHERE r.domain_id = $1", &[&domain_id])
+    }
+
+    /// Reserved mailboxes of every domain that is not in the recycle bin.
+    pub fn list_all_reserved_mailboxes(&self) -> Vec<ReservedMailbox> {
+        debug!("[db] listing all reserved mailboxes");
+        self.query_reserved_mailboxes("WHERE d.deleted_at IS NULL", &[])
+    }
+
+    fn query_reserved_mailboxes(
+        &self,
+        filter: &str,
+        params: &[&(dyn postgres::types::ToSql + Sync)],
+    ) -> Vec<ReservedMailbox> {
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            &format!(
+                "SELECT r.domain_id, d.domain, r.role, r.target, r.enabled
+                 FROM domain_reserved_mailboxes r
+                 JOIN domains d ON d.id = r.domain_id
+                 {}
+                 ORDER BY d.domain, r.role",
+                filter
+            ),
+            params,
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list reserved mailboxes: {}", e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|row| ReservedMailbox {
+            domain_id: row.get(0),
+            domain_name: row.get(1),
+            role: row.get(2),
+            target: row.get(3),
+            enabled: row.get(4),
+        })
+        .collect()
+    }
+
+    pub fn set_reserved_mailbox(&self, domain_id: i64, role: &str, target: &str, enabled: bool) {
+        info!(
+            "[db] setting reserved mailbox {} for domain id={}: target={:?}, enabled={}",
+            role, domain_id, target, enabled
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO domain_reserved_mailboxes (domain_id, role, target, enabled, updated_at)
+             VALUES ($1, $2, $3, $4, $5)
+             ON CONFLICT (domain_id, role)
+             DO UPDATE SET target = EXCLUDED.target, enabled = EXCLUDED.enabled, updated_at = EXCLUDED.updated_at",
+            &[&domain_id, &role, &target, &enabled, &now()],
+        ) {
+            error!("[db] failed to set reserved mailbox {} for domain id={}: {}", role, domain_id, e);
+        }
+    }
+
+    /// Registration rules of a domain; domains without a row keep
+    /// registration disabled.
+    pub fn get_registration_settings(&self, domain_id: i64) -> RegistrationSettings {
+        debug!("[db] getting registration settings for domain id={}", domain_id);
+        self.conn()
+            .and_then(|mut conn| {
+                conn.query_opt(
+                    "SELECT enabled, username_regex, min_password_length, invite_only
+                     FROM registration_settings WHERE domain_id = $1",
+                    &[&domain_id],
+                )
+                .unwrap_or_else(|e| {
+                    error!(
+                        "[db] failed to load registration settings for domain id={}: {}",
+                        domain_id, e
+                    );
+                    None
+                })
+            })
+            .map(|row| RegistrationSettings {
+                domain_id,
+                enabled: row.get(0),
+                username_regex: row.get(1),
+                min_password_length: row.get(2),
+                invite_only: row.get(3),
+            })
+            .unwrap_or(RegistrationSettings {
+                domain_id,
+                enabled: false,
+                username_regex: String::new(),
+                min_password_length: 8,
+                invite_only: false,
+            })
+    }
+
+    pub fn update_registration_settings(&self, settings: &RegistrationSettings) {
+        info!(
+            "[db] updating registration settings for domain id={}: enabled={}, min_password_length={}, invite_only={}",
+            settings.domain_id, settings.enabled, settings.min_password_length, settings.invite_only
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO registration_settings (domain_id, enabled, username_regex, min_password_length, invite_only, updated_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             ON CONFLICT (domain_id)
+             DO UPDATE SET enabled = EXCLUDED.enabled, username_regex = EXCLUDED.username_regex,
+                           min_password_length = EXCLUDED.min_password_length,
+                           invite_only = EXCLUDED.invite_only, updated_at = EXCLUDED.updated_at",
+            &[
+                &settings.domain_id,
+                &settings.enabled,
+                &settings.username_regex,
+                &settings.min_password_length,
+                &settings.invite_only,
+                &now(),
+            ],
+        ) {
+            error!(
+                "[db] failed to update registration settings for domain id={}: {}",
+                settings.domain_id, e
+            );
+        }
+    }
+
+    pub fn list_registration_invites(&self, domain_id: i64) -> Vec<RegistrationInvite> {
+        debug!("[db] listing registration invites for domain id={}", domain_id);
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "SELECT id, domain_id, code, note, created_at, used_at, used_by
+             FROM registration_invites WHERE domain_id = $1 ORDER BY id DESC",
+            &[&domain_id],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list registration invites: {}", e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|row| RegistrationInvite {
+            id: row.get(0),
+            domain_id: row.get(1),
+            code: row.get(2),
+            note: row.get(3),
+            created_at: row.get(4),
+            used_at: row.get(5),
+            used_by: row.get(6),
+        })
+        .collect()
+    }
+
+    pub fn create_registration_invite(&self, domain_id: i64, code: &str, note: &str) -> Result<i64, String> {
+        info!("[db] creating registration invite for domain id={}", domain_id);
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        conn.query_one(
+            "INSERT INTO registration_invites (domain_id, code, note, created_at)
+             VALUES ($1, $2, $3, $4) RETURNING id",
+            &[&domain_id, &code, &note, &now()],
+        )
+        .map(|row| row.get(0))
+        .map_err(|e| {
+            error!("[db] failed to create registration invite: {}", e);
+            e.to_string()
+        })
+    }
+
+    pub fn delete_registration_invite(&self, domain_id: i64, id: i64) {
+        info!("[db] deleting registration invite id={} of domain id={}", id, domain_id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM registration_invites WHERE id = $1 AND domain_id = $2",
+            &[&id, &domain_id],
+        ) {
+            error!("[db] failed to delete registration invite id={}: {}", id, e);
+        }
+    }
+
+    /// Whether an unused invite `code` exists for the domain.
+    pub fn is_registration_invite_open(&self, domain_id: i64, code: &str) -> bool {
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        conn.query_one(
+            "SELECT EXISTS (SELECT 1 FROM registration_invites
+                            WHERE domain_id = $1 AND code = $2 AND used_at IS NULL)",
+            &[&domain_id, &code],
+        )
+        .map(|row| row.get(0))
+        .unwrap_or(false)
+    }
+
+    /// Whether an alias or forwarding already receives mail for `address`.
+    pub fn is_address_routed(&self, address: &str) -> bool {
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        conn.query_one(
+            "SELECT EXISTS (SELECT 1 FROM aliases WHERE LOWER(source) = LOWER($1))
+                 OR EXISTS (SELECT 1 FROM forwardings WHERE LOWER(source) = LOWER($1))",
+            &[&address],
+        )
+        .map(|row| row.get(0))
+        .unwrap_or(false)
+    }
+
+    /// Create a self-registered account, redeeming `invite` in the same
+    /// transaction.  Fails with [`INVITE_UNAVAILABLE`] when the invite was
+    /// used in the meantime.
+    pub fn register_account(
+        &self,
+        domain_id: i64,
+        username: &str,
+        password_hash: &str,
+        name: &str,
+        invite: Option<&str>,
+        address: &str,
+    ) -> Result<i64, String> {
+        info!(
+            "[db] registering account username={}, domain_id={}, invite={}",
+            username,
+            domain_id,
+            invite.is_some()
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        let ts = now();
+        if let Some(code) = invite {
+            let redeemed = tx
+                .execute(
+                    "UPDATE registration_invites SET used_at = $1, used_by = $2
+                     WHERE domain_id = $3 AND code = $4 AND used_at IS NULL",
+                    &[&ts, &address, &domain_id, &code],
+                )
+                .map_err(|e| e.to_string())?;
+            if redeemed == 0 {
+                return Err(INVITE_UNAVAILABLE.to_string());
             }
         }
+        let id: i64 = tx
+            .query_one(
+                "INSERT INTO accounts (domain_id, username, password_hash, name, quota, created_at, updated_at)
+                 VALUES ($1, $2, $3, $4, 0, $5, $5)
+                 RETURNING id",
+                &[&domain_id, &username, &password_hash, &name, &ts],
+            )
+            .map_err(|e| {
+                error!("[db] failed to register account {}: {}", username, e);
+                e.to_string()
+            })?
+            .get(0);
+        tx.commit().map_err(|e| {
+            error!("[db] failed to commit registration of {}: {}", username, e);
+            e.to_string()
+        })?;
+        info!("[db] account registered: {} (id={})", username, id);
+        Ok(id)
     }
 
     pub fn update_domain_dkim(&self, id: i64, selector: &str, private_key: &str, public_key: &str) {
@@ -983,7 +2232,9 @@ impl Database {
             "[db] updating DKIM for domain id={}, selector={}",
             id, selector
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE domains
              SET dkim_selector = $1, dkim_private_key = $2, dkim_public_key = $3, updated_at = $4
@@ -994,9 +2245,56 @@ impl Database {
         }
     }
 
+    /// Switch a domain to a new DKIM selector and key pair, keeping the
+    /// current ones as the previous selector until the grace period ends.
+    pub fn rotate_domain_dkim(&self, id: i64, selector: &str, private_key: &str, public_key: &str) {
+        info!(
+            "[db] rotating DKIM for domain id={} to selector={}",
+            id, selector
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE domains
+             SET dkim_selector_prev = dkim_selector, dkim_private_key_prev = dkim_private_key,
+                 dkim_public_key_prev = dkim_public_key, dkim_rotated_at = $4,
+                 dkim_selector = $1, dkim_private_key = $2, dkim_public_key = $3, updated_at = $4
+             WHERE id = $5",
+            &[&selector, &private_key, &public_key, &now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    /// Forget the previous DKIM selector of domains rotated at or before
+    /// `cutoff`.  Returns `(domain, selector)` for each one cleared.
+    pub fn clear_expired_dkim_prev(&self, cutoff: &str) -> Vec<(String, String)> {
+        debug!("[db] clearing previous DKIM selectors rotated before {}", cutoff);
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "UPDATE domains d
+             SET dkim_selector_prev = NULL, dkim_private_key_prev = NULL,
+                 dkim_public_key_prev = NULL, dkim_rotated_at = NULL, updated_at = $2
+             FROM (SELECT id, dkim_selector_prev FROM domains
+                   WHERE dkim_rotated_at IS NOT NULL AND dkim_rotated_at <= $1
+                   FOR UPDATE) old
+             WHERE d.id = old.id
+             RETURNING d.domain, COALESCE(old.dkim_selector_prev, '')",
+            &[&cutoff, &now()],
+        )
+        .map(|rows| rows.iter().map(|r| (r.get(0), r.get(1))).collect())
+        .unwrap_or_else(|e| {
+            error!("[db] failed to clear previous DKIM selectors: {}", e);
+            Vec::new()
+        })
+    }
+
     pub fn get_bimi_svg_for_domain(&self, domain: &str) -> Option<String> {
         debug!("[db] looking up BIMI SVG for domain={}", domain);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT bimi_svg FROM domains
              WHERE lower(domain) = lower($1)
@@ -1013,10 +2311,10 @@ impl Database {
 
     pub fn get_account(&self, id: i64) -> Option<Account> {
         debug!("[db] getting account id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, domain_id, username, password_hash, name, active, quota
-             FROM accounts WHERE id = $1",
+             FROM accounts WHERE id = $1 AND deleted_at IS NULL",
             &[&id],
         )
         .ok()
@@ -1036,12 +2334,12 @@ impl Database {
 
     pub fn get_account_with_domain(&self, id: i64) -> Option<Account> {
         debug!("[db] getting account with domain info id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT a.id, a.domain_id, a.username, a.password_hash, a.name, a.active, a.quota, d.domain
              FROM accounts a
              LEFT JOIN domains d ON a.domain_id = d.id
-             WHERE a.id = $1",
+             WHERE a.id = $1 AND a.deleted_at IS NULL AND d.deleted_at IS NULL",
             &[&id],
         )
         .ok()
@@ -1072,7 +2370,10 @@ impl Database {
             username, domain_id, quota
         );
         let id = {
-            let mut conn = self.conn();
+            let Some(mut conn) = self.conn() else {
+                return Err(NO_CONNECTION.to_string());
+            };
+            check_account_not_binned(&mut *conn, domain_id, username)?;
             let ts = now();
             let row = conn
                 .query_one(
@@ -1082,8 +2383,9 @@ impl Database {
                     &[&domain_id, &username, &password_hash, &name, &quota, &ts, &ts],
                 )
                 .map_err(|e| {
-                    error!("[db] failed to create account {}: {}", username, e);
-                    e.to_string()
+                    let text = error_text(&e);
+                    error!("[db] failed to create account {}: {}", username, text);
+                    text
                 })?;
             row.get::<_, i64>(0)
         };
@@ -1091,13 +2393,48 @@ impl Database {
         Ok(id)
     }
 
+    /// Create all `accounts` in one transaction; nothing is created if any
+    /// insert fails.
+    pub fn create_accounts(&self, accounts: &[NewAccount]) -> Result<Vec<i64>, String> {
+        info!("[db] bulk creating {} accounts", accounts.len());
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        let ts = now();
+        let mut ids = Vec::with_capacity(accounts.len());
+        for a in accounts {
+            check_account_not_binned(&mut tx, a.domain_id, &a.username)?;
+            let row = tx
+                .query_one(
+                    "INSERT INTO accounts (domain_id, username, password_hash, name, quota, active, created_at, updated_at)
+                     VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                     RETURNING id",
+                    &[&a.domain_id, &a.username, &a.password_hash, &a.name, &a.quota, &a.active, &ts, &ts],
+                )
+                .map_err(|e| {
+                    error!("[db] failed to create account {}: {}", a.username, e);
+                    format!("{}: {}", a.username, e)
+                })?;
+            ids.push(row.get::<_, i64>(0));
+        }
+        tx.commit().map_err(|e| {
+            error!("[db] failed to commit account import: {}", e);
+            e.to_string()
+        })?;
+        info!("[db] bulk created {} accounts", ids.len());
+        Ok(ids)
+    }
+
     pub fn update_account(&self, id: i64, name: &str, active: bool, quota: i64) {
         info!(
             "[db] updating account id={}, active={}, quota={}",
             id, active, quota
         );
         {
-            let mut conn = self.conn();
+            let Some(mut conn) = self.conn() else {
+                return;
+            };
             if let Err(e) = conn.execute(
                 "UPDATE accounts
                  SET name = $1, active = $2, quota = $3, updated_at = $4
@@ -1110,10 +2447,96 @@ impl Database {
         }
     }
 
+    pub fn get_account_disabled_policy(&self, id: i64) -> String {
+        debug!("[db] getting disabled policy for account id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return String::new();
+        };
+        conn.query_opt(
+            "SELECT disabled_policy FROM accounts WHERE id = $1",
+            &[&id],
+        )
+        .ok()
+        .flatten()
+        .and_then(|row| row.get::<_, Option<String>>(0))
+        .unwrap_or_default()
+    }
+
+    pub fn set_account_disabled_policy(&self, id: i64, policy: &str) {
+        info!(
+            "[db] setting disabled policy for account id={}, policy={}",
+            id, policy
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE accounts SET disabled_policy = $1, updated_at = $2 WHERE id = $3",
+            &[&policy, &now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    /// Accounts that override the global disabled-account policy, by id.
+    pub fn list_account_disabled_policies(&self) -> HashMap<i64, String> {
+        debug!("[db] listing per-account disabled policies");
+        let Some(mut conn) = self.conn() else {
+            return HashMap::new();
+        };
+        conn.query(
+            "SELECT id, disabled_policy FROM accounts
+             WHERE deleted_at IS NULL AND COALESCE(disabled_policy, '') <> ''",
+            &[],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list disabled policies: {}", e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect()
+    }
+
+    /// Per-account overrides of the message and recipient sending limits.
+    pub fn get_account_send_limits(&self, id: i64) -> (Option<i32>, Option<i32>) {
+        debug!("[db] getting send limits for account id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return (None, None);
+        };
+        conn.query_opt(
+            "SELECT send_limit_messages, send_limit_recipients FROM accounts WHERE id = $1",
+            &[&id],
+        )
+        .ok()
+        .flatten()
+        .map(|row| (row.get(0), row.get(1)))
+        .unwrap_or((None, None))
+    }
+
+    pub fn set_account_send_limits(&self, id: i64, messages: Option<i32>, recipients: Option<i32>) {
+        info!(
+            "[db] setting send limits for account id={}, messages={:?}, recipients={:?}",
+            id, messages, recipients
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE accounts SET send_limit_messages = $1, send_limit_recipients = $2, updated_at = $3
+             WHERE id = $4",
+            &[&messages, &recipients, &now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
     pub fn update_account_password(&self, id: i64, hash: &str) {
         info!("[db] updating account password id={}", id);
         {
-            let mut conn = self.conn();
+            let Some(mut conn) = self.conn() else {
+                return;
+            };
             if let Err(e) = conn.execute(
                 "UPDATE accounts SET password_hash = $1, updated_at = $2 WHERE id = $3",
                 &[&hash, &now(), &id],
@@ -1124,21 +2547,80 @@ impl Database {
         }
     }
 
+    /// Move an account to the recycle bin.
     pub fn delete_account(&self, id: i64) {
-        warn!("[db] deleting account id={}", id);
-        let _account_info = self.get_account_with_domain(id);
-        {
-            let mut conn = self.conn();
-            if let Err(e) = conn.execute("DELETE FROM accounts WHERE id = $1", &[&id]) {
-                error!("[db] failed to execute query: {}", e);
-                return;
-            }
+        warn!("[db] soft-deleting account id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE accounts SET deleted_at = $1, updated_at = $1 WHERE id = $2 AND deleted_at IS NULL",
+            &[&now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
         }
     }
 
+    pub fn restore_account(&self, id: i64) {
+        info!("[db] restoring account id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE accounts SET deleted_at = NULL, updated_at = $1 WHERE id = $2",
+            &[&now(), &id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    /// Permanently delete an account from the recycle bin.
+    pub fn purge_account(&self, id: i64) {
+        warn!("[db] purging account id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM accounts WHERE id = $1 AND deleted_at IS NOT NULL",
+            &[&id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    /// Soft-deleted accounts, named `user@domain`.  Accounts of a deleted
+    /// domain are listed with the domain rather than here.
+    pub fn list_deleted_accounts(&self) -> Vec<RecycleBinItem> {
+        debug!("[db] listing soft-deleted accounts");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "SELECT a.id, a.username || '@' || COALESCE(d.domain, '?'), a.deleted_at
+             FROM accounts a
+             LEFT JOIN domains d ON a.domain_id = d.id
+             WHERE a.deleted_at IS NOT NULL AND d.deleted_at IS NULL
+             ORDER BY a.deleted_at DESC",
+            &[],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list deleted accounts: {}", e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|row| RecycleBinItem {
+            id: row.get(0),
+            name: row.get(1),
+            deleted_at: row.get(2),
+        })
+        .collect()
+    }
+
     pub fn list_all_accounts_with_domain(&self) -> Vec<Account> {
         debug!("[db] listing all accounts with domain info");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT a.id, a.domain_id, a.username, a.password_hash, a.name, a.active, a.quota, d.domain,
@@ -1147,6 +2629,7 @@ impl Database {
                         OR EXISTS (SELECT 1 FROM dmarc_inboxes WHERE account_id = a.id) AS is_system
                  FROM accounts a
                  LEFT JOIN domains d ON a.domain_id = d.id
+                 WHERE a.deleted_at IS NULL AND d.deleted_at IS NULL
                  ORDER BY a.username",
                 &[],
             )
@@ -1170,9 +2653,81 @@ impl Database {
             .collect()
     }
 
+    /// One page of accounts ordered by username, optionally filtered by a
+    /// substring of the username, display name or domain.
+    pub fn list_accounts_paged(
+        &self,
+        limit: i64,
+        offset: i64,
+        search: Option<&str>,
+    ) -> Vec<Account> {
+        debug!(
+            "[db] listing accounts limit={} offset={} search={:?}",
+            limit, offset, search
+        );
+        let pattern = contains_pattern(search);
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT a.id, a.domain_id, a.username, a.password_hash, a.name, a.active, a.quota, d.domain,
+                        EXISTS (SELECT 1 FROM abuse_inboxes WHERE account_id = a.id)
+                        OR EXISTS (SELECT 1 FROM bounce_inboxes WHERE account_id = a.id)
+                        OR EXISTS (SELECT 1 FROM dmarc_inboxes WHERE account_id = a.id) AS is_system
+                 FROM accounts a
+                 LEFT JOIN domains d ON a.domain_id = d.id
+                 WHERE a.deleted_at IS NULL AND d.deleted_at IS NULL
+                   AND ($1::TEXT IS NULL
+                        OR a.username ILIKE $1 OR a.name ILIKE $1 OR d.domain ILIKE $1)
+                 ORDER BY a.username, d.domain
+                 LIMIT $2 OFFSET $3",
+                &[&pattern, &limit, &offset],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list accounts page: {}", e);
+                Vec::new()
+            });
+
+        rows.into_iter()
+            .map(|row| Account {
+                id: row.get(0),
+                domain_id: row.get(1),
+                username: row.get(2),
+                password_hash: row.get(3),
+                name: row.get(4),
+                active: row.get(5),
+                quota: row.get(6),
+                domain_name: row.get(7),
+                is_system: row.get(8),
+            })
+            .collect()
+    }
+
+    /// Number of accounts matching `search` (see [`Self::list_accounts_paged`]).
+    pub fn count_accounts(&self, search: Option<&str>) -> i64 {
+        let pattern = contains_pattern(search);
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
+        conn.query_one(
+            "SELECT COUNT(*) FROM accounts a
+             LEFT JOIN domains d ON a.domain_id = d.id
+             WHERE a.deleted_at IS NULL AND d.deleted_at IS NULL
+               AND ($1::TEXT IS NULL
+                    OR a.username ILIKE $1 OR a.name ILIKE $1 OR d.domain ILIKE $1)",
+            &[&pattern],
+        )
+        .map(|row| row.get(0))
+        .unwrap_or_else(|e| {
+            error!("[db] failed to count accounts: {}", e);
+            0
+        })
+    }
+
     pub fn get_alias(&self, id: i64) -> Option<Alias> {
         debug!("[db] getting alias id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, domain_id, source, destination, active, sort_order
              FROM aliases WHERE id = $1",
@@ -1201,7 +2756,9 @@ impl Database {
             "[db] creating alias source={}, destination={}",
             source, destination
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
 
         // Calculate sort_order: 0 for specific addresses, 1 for catch-alls
@@ -1237,7 +2794,9 @@ impl Database {
             "[db] updating alias id={}, source={}, destination={}, active={}",
             id, source, destination, active
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
 
         // Calculate sort_order: 0 for specific addresses, 1 for catch-alls
         let sort_order: i64 = if source.trim().starts_with('*') { 1 } else { 0 };
@@ -1254,7 +2813,9 @@ impl Database {
 
     pub fn delete_alias(&self, id: i64) {
         warn!("[db] deleting alias id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM aliases WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1262,12 +2823,15 @@ impl Database {
 
     pub fn list_all_aliases_with_domain(&self) -> Vec<Alias> {
         debug!("[db] listing all aliases with domain info");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT a.id, a.domain_id, a.source, a.destination, a.active, a.sort_order, d.domain
                  FROM aliases a
                  LEFT JOIN domains d ON a.domain_id = d.id
+                 WHERE d.deleted_at IS NULL
                  ORDER BY a.sort_order ASC, a.id ASC",
                 &[],
             )
@@ -1320,7 +2884,9 @@ impl Database {
 
     pub fn list_tracking_patterns(&self) -> Vec<TrackingPattern> {
         debug!("[db] listing tracking patterns");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, pattern, created_at FROM tracking_patterns ORDER BY id ASC",
@@ -1341,7 +2907,9 @@ impl Database {
 
     pub fn create_tracking_pattern(&self, pattern: &str) -> Result<i64, String> {
         info!("[db] creating tracking pattern: {}", pattern);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO tracking_patterns (pattern, created_at) VALUES ($1, $2) RETURNING id",
@@ -1358,7 +2926,9 @@ impl Database {
 
     pub fn delete_tracking_pattern(&self, id: i64) {
         warn!("[db] deleting tracking pattern id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM tracking_patterns WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1366,7 +2936,9 @@ impl Database {
 
     pub fn list_tracking_rules(&self) -> Vec<TrackingRule> {
         debug!("[db] listing tracking rules");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, name, match_mode, conditions_json, created_at FROM tracking_rules ORDER BY id ASC",
@@ -1401,7 +2973,9 @@ impl Database {
         info!("[db] creating tracking rule: {}", name);
         let conditions_json = serde_json::to_string(conditions)
             .map_err(|e| format!("failed to serialize conditions: {}", e))?;
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO tracking_rules (name, match_mode, conditions_json, created_at) VALUES ($1, $2, $3, $4) RETURNING id",
@@ -1418,7 +2992,9 @@ impl Database {
 
     pub fn delete_tracking_rule(&self, id: i64) {
         warn!("[db] deleting tracking rule id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM tracking_rules WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1426,7 +3002,9 @@ impl Database {
 
     pub fn list_unsubscribe_patterns(&self) -> Vec<UnsubscribePattern> {
         debug!("[db] listing unsubscribe patterns");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, pattern, created_at FROM unsubscribe_patterns ORDER BY id ASC",
@@ -1447,7 +3025,9 @@ impl Database {
 
     pub fn create_unsubscribe_pattern(&self, pattern: &str) -> Result<i64, String> {
         info!("[db] creating unsubscribe pattern: {}", pattern);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO unsubscribe_patterns (pattern, created_at) VALUES ($1, $2) RETURNING id",
@@ -1464,7 +3044,9 @@ impl Database {
 
     pub fn delete_unsubscribe_pattern(&self, id: i64) {
         warn!("[db] deleting unsubscribe pattern id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM unsubscribe_patterns WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1472,7 +3054,9 @@ impl Database {
 
     pub fn list_unsubscribe_rules(&self) -> Vec<UnsubscribeRule> {
         debug!("[db] listing unsubscribe rules");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, name, match_mode, conditions_json, created_at FROM unsubscribe_rules ORDER BY id ASC",
@@ -1507,7 +3091,9 @@ impl Database {
         info!("[db] creating unsubscribe rule: {}", name);
         let conditions_json = serde_json::to_string(conditions)
             .map_err(|e| format!("failed to serialize conditions: {}", e))?;
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO unsubscribe_rules (name, match_mode, conditions_json, created_at) VALUES ($1, $2, $3, $4) RETURNING id",
@@ -1524,7 +3110,9 @@ impl Database {
 
     pub fn delete_unsubscribe_rule(&self, id: i64) {
         warn!("[db] deleting unsubscribe rule id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM unsubscribe_rules WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1561,7 +3149,9 @@ impl Database {
 
     pub fn list_footer_patterns(&self) -> Vec<FooterPattern> {
         debug!("[db] listing footer patterns");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, pattern, created_at FROM footer_patterns ORDER BY id ASC",
@@ -1582,7 +3172,9 @@ impl Database {
 
     pub fn create_footer_pattern(&self, pattern: &str) -> Result<i64, String> {
         info!("[db] creating footer pattern: {}", pattern);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO footer_patterns (pattern, created_at) VALUES ($1, $2) RETURNING id",
@@ -1599,7 +3191,9 @@ impl Database {
 
     pub fn delete_footer_pattern(&self, id: i64) {
         warn!("[db] deleting footer pattern id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM footer_patterns WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1607,7 +3201,9 @@ impl Database {
 
     pub fn list_footer_rules(&self) -> Vec<FooterRule> {
         debug!("[db] listing footer rules");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, name, match_mode, conditions_json, created_at FROM footer_rules ORDER BY id ASC",
@@ -1642,7 +3238,9 @@ impl Database {
         info!("[db] creating footer rule: {}", name);
         let conditions_json = serde_json::to_string(conditions)
             .map_err(|e| format!("failed to serialize conditions: {}", e))?;
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO footer_rules (name, match_mode, conditions_json, created_at) VALUES ($1, $2, $3, $4) RETURNING id",
@@ -1659,7 +3257,9 @@ impl Database {
 
     pub fn delete_footer_rule(&self, id: i64) {
         warn!("[db] deleting footer rule id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM footer_rules WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1669,7 +3269,9 @@ impl Database {
 
     pub fn list_rate_limit_rules(&self) -> Vec<RateLimitRule> {
         debug!("[db] listing rate limit rules");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, name, match_mode, conditions_json, max_messages, window_seconds, enabled, created_at
@@ -1711,7 +3313,9 @@ impl Database {
         info!("[db] creating rate limit rule: {}", name);
         let conditions_json = serde_json::to_string(conditions)
             .map_err(|e| format!("Failed to serialise conditions: {}", e))?;
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO rate_limit_rules (name, match_mode, conditions_json, max_messages, window_seconds, enabled, created_at)
@@ -1729,7 +3333,9 @@ impl Database {
 
     pub fn delete_rate_limit_rule(&self, id: i64) {
         warn!("[db] deleting rate limit rule id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM rate_limit_rules WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1770,7 +3376,7 @@ impl Database {
             let window_start = ((now_secs / rule.window_seconds as i64) * rule.window_seconds as i64).to_string();
 
             let rule_id = rule.id;
-            let mut conn = self.conn();
+            let mut conn = self.conn()?;
 
             // Upsert: increment counter or start at 1.
             let count: i64 = conn
@@ -1800,18 +3406,203 @@ impl Database {
         self.get_setting("api_token")
     }
 
-    pub fn set_api_token(&self, token: &str) {
-        self.set_setting("api_token", token);
+    pub fn set_api_token(&self, token: &str) {
+        self.set_setting("api_token", token);
+    }
+
+    pub fn verify_api_token(&self, token: &str) -> bool {
+        self.get_api_token().map(|t| t == token).unwrap_or(false)
+    }
+
+    // ── API keys ──
+
+    pub fn create_api_key(
+        &self,
+        name: &str,
+        key_prefix: &str,
+        key_hash: &str,
+        read_only: bool,
+        created_by: &str,
+    ) -> Result<i64, String> {
+        info!(
+            "[db] creating api key name={} prefix={} read_only={}",
+            name, key_prefix, read_only
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        conn.query_one(
+            "INSERT INTO api_keys (name, key_prefix, key_hash, read_only, created_by, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)
+             RETURNING id",
+            &[&name, &key_prefix, &key_hash, &read_only, &created_by, &now()],
+        )
+        .map(|row| row.get(0))
+        .map_err(|e| {
+            error!("[db] failed to create api key {}: {}", name, e);
+            e.to_string()
+        })
+    }
+
+    pub fn list_api_keys(&self) -> Vec<ApiKey> {
+        debug!("[db] listing api keys");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "SELECT id, name, key_prefix, key_hash, read_only, created_by, created_at, last_used_at, revoked_at
+             FROM api_keys ORDER BY id DESC",
+            &[],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list api keys: {}", e);
+            Vec::new()
+        })
+        .iter()
+        .map(api_key_from_row)
+        .collect()
+    }
+
+    /// The unrevoked key with `key_prefix`, if any.
+    pub fn get_active_api_key(&self, key_prefix: &str) -> Option<ApiKey> {
+        let mut conn = self.conn()?;
+        conn.query_opt(
+            "SELECT id, name, key_prefix, key_hash, read_only, created_by, created_at, last_used_at, revoked_at
+             FROM api_keys WHERE key_prefix = $1 AND revoked_at IS NULL",
+            &[&key_prefix],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to look up api key: {}", e);
+            None
+        })
+        .as_ref()
+        .map(api_key_from_row)
+    }
+
+    pub fn touch_api_key(&self, id: i64) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE api_keys SET last_used_at = $1 WHERE id = $2",
+            &[&now(), &id],
+        ) {
+            error!("[db] failed to update api key last_used_at: {}", e);
+        }
+    }
+
+    pub fn revoke_api_key(&self, id: i64) {
+        warn!("[db] revoking api key id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE api_keys SET revoked_at = $1 WHERE id = $2 AND revoked_at IS NULL",
+            &[&now(), &id],
+        ) {
+            error!("[db] failed to revoke api key: {}", e);
+        }
+    }
+
+    // ── API idempotency keys ──
+
+    /// Look up a stored response for `(token, key)` recorded within the last
+    /// `window_hours`.  Expired keys are purged as a side effect.
+    /// Claim an idempotency key for a new request.  Returns `Some(false)` when
+    /// the key is already taken, and `None` when the database is unavailable.
+    pub fn reserve_idempotency_key(
+        &self,
+        token: &str,
+        key: &str,
+        request_hash: &str,
+        window_hours: i64,
+        pending_minutes: i64,
+    ) -> Option<bool> {
+        debug!("[db] reserving idempotency key={}", key);
+        let mut conn = self.conn()?;
+        let cutoff = (chrono::Utc::now() - chrono::Duration::hours(window_hours))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        // A reservation whose request never finished (e.g. the process died)
+        // must not block retries for the whole window.
+        let pending_cutoff = (chrono::Utc::now() - chrono::Duration::minutes(pending_minutes))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        if let Err(e) = conn.execute(
+            "DELETE FROM api_idempotency_keys
+             WHERE created_at <= $1 OR (response_status IS NULL AND created_at <= $2)",
+            &[&cutoff, &pending_cutoff],
+        ) {
+            error!("[db] failed to purge expired idempotency keys: {}", e);
+        }
+        match conn.execute(
+            "INSERT INTO api_idempotency_keys (token, idempotency_key, request_hash, created_at)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (token, idempotency_key) DO NOTHING",
+            &[&token, &key, &request_hash, &now()],
+        ) {
+            Ok(n) => Some(n == 1),
+            Err(e) => {
+                error!("[db] failed to reserve idempotency key: {}", e);
+                None
+            }
+        }
+    }
+
+    /// The request hash and, once the request has finished, the stored
+    /// status and body for an idempotency key.
+    pub fn get_idempotent_response(
+        &self,
+        token: &str,
+        key: &str,
+    ) -> Option<(String, Option<i32>, Option<String>)> {
+        debug!("[db] looking up idempotency key={}", key);
+        let mut conn = self.conn()?;
+        conn.query_opt(
+            "SELECT request_hash, response_status, response_body FROM api_idempotency_keys
+             WHERE token = $1 AND idempotency_key = $2",
+            &[&token, &key],
+        )
+        .ok()
+        .flatten()
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+    }
+
+    pub fn store_idempotent_response(&self, token: &str, key: &str, status: i32, body: &str) {
+        debug!("[db] storing idempotency key={} status={}", key, status);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE api_idempotency_keys SET response_status = $3, response_body = $4
+             WHERE token = $1 AND idempotency_key = $2",
+            &[&token, &key, &status, &body],
+        ) {
+            error!("[db] failed to store idempotency key: {}", e);
+        }
     }
 
-    pub fn verify_api_token(&self, token: &str) -> bool {
-        self.get_api_token().map(|t| t == token).unwrap_or(false)
+    /// Drop a reservation so that a retry of a failed request runs again.
+    pub fn release_idempotency_key(&self, token: &str, key: &str) {
+        debug!("[db] releasing idempotency key={}", key);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM api_idempotency_keys
+             WHERE token = $1 AND idempotency_key = $2 AND response_status IS NULL",
+            &[&token, &key],
+        ) {
+            error!("[db] failed to release idempotency key: {}", e);
+        }
     }
 
     /// Check if an email address exists as an active account
     pub fn email_exists(&self, email: &str) -> bool {
         debug!("[db] checking if email exists: {}", email);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
 
         // Parse email into username and domain
         let parts: Vec<&str> = email.split('@').collect();
@@ -1827,7 +3618,8 @@ impl Database {
             .query_one(
                 "SELECT COUNT(*) FROM accounts ac
                  JOIN domains d ON ac.domain_id = d.id
-                 WHERE ac.username = $1 AND d.domain = $2 AND ac.active = TRUE AND d.active = TRUE",
+                 WHERE ac.username = $1 AND d.domain = $2 AND ac.active = TRUE AND d.active = TRUE
+                   AND ac.deleted_at IS NULL AND d.deleted_at IS NULL",
                 &[&username, &domain],
             )
             .map(|row| row.get(0))
@@ -1842,12 +3634,15 @@ impl Database {
 
     pub fn list_all_forwardings_with_domain(&self) -> Vec<Forwarding> {
         debug!("[db] listing all forwardings with domain info");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT f.id, f.domain_id, f.source, f.destination, f.active, f.keep_copy, d.domain
                  FROM forwardings f
                  LEFT JOIN domains d ON f.domain_id = d.id
+                 WHERE d.deleted_at IS NULL
                  ORDER BY f.id ASC",
                 &[],
             )
@@ -1870,7 +3665,7 @@ impl Database {
 
     pub fn get_forwarding(&self, id: i64) -> Option<Forwarding> {
         debug!("[db] getting forwarding id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT f.id, f.domain_id, f.source, f.destination, f.active, f.keep_copy, d.domain
              FROM forwardings f
@@ -1902,7 +3697,9 @@ impl Database {
             "[db] creating forwarding source={}, destination={}, keep_copy={}",
             source, destination, keep_copy
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         let row = conn
             .query_one(
@@ -1935,7 +3732,9 @@ impl Database {
             "[db] updating forwarding id={}, source={}, destination={}, active={}, keep_copy={}",
             id, source, destination, active, keep_copy
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE forwardings
              SET source = $1, destination = $2, active = $3, keep_copy = $4, updated_at = $5
@@ -1948,7 +3747,9 @@ impl Database {
 
     pub fn delete_forwarding(&self, id: i64) {
         warn!("[db] deleting forwarding id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM forwardings WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -1968,7 +3769,9 @@ impl Database {
             "[db] creating tracked message id={}, sender={}, recipient={}",
             message_id, sender, recipient
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "INSERT INTO tracked_messages (message_id, sender, recipient, subject, alias_id, created_at)
              VALUES ($1, $2, $3, $4, $5, $6)",
@@ -1978,24 +3781,124 @@ impl Database {
         }
     }
 
-    pub fn record_pixel_open(&self, message_id: &str, client_ip: &str, user_agent: &str) {
+    /// Record an open and return whether it is the first for a tracked
+    /// message.  Returns `None` without recording anything when the same
+    /// client opened the message within the last `dedup_window_secs` seconds;
+    /// opens without a client address are never collapsed.  `Err` when the
+    /// open could not be stored.
+    #[allow(clippy::too_many_arguments)]
+    pub fn record_pixel_open(
+        &self,
+        message_id: &str,
+        client_ip: &str,
+        user_agent: &str,
+        country: Option<&str>,
+        city: Option<&str>,
+        dedup_window_secs: i64,
+    ) -> Result<Option<bool>, String> {
         info!(
             "[db] recording pixel open message_id={}, client_ip={}",
             message_id, client_ip
         );
-        let mut conn = self.conn();
-        if let Err(e) = conn.execute(
-            "INSERT INTO pixel_opens (message_id, client_ip, user_agent, opened_at)
-             VALUES ($1, $2, $3, $4)",
-            &[&message_id, &client_ip, &user_agent, &now()],
+        let window_start = (chrono::Utc::now()
+            - chrono::Duration::seconds(dedup_window_secs.max(0)))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let opened_at = now();
+        // The window starts in the past, so a window of 0 never matches.
+        match conn.execute(
+            "INSERT INTO pixel_opens (message_id, client_ip, user_agent, opened_at, country, city)
+             SELECT $1, $2, $3, $4, $5, $6
+             WHERE NOT EXISTS (
+                 SELECT 1 FROM pixel_opens
+                 WHERE message_id = $1 AND client_ip = $2 AND $2 <> '' AND opened_at > $7
+             )",
+            &[
+                &message_id,
+                &client_ip,
+                &user_agent,
+                &opened_at,
+                &country,
+                &city,
+                &window_start,
+            ],
         ) {
-            error!("[db] failed to execute query: {}", e);
+            Ok(0) => return Ok(None),
+            Ok(_) => {}
+            Err(e) => {
+                let text = error_text(&e);
+                error!("[db] failed to record pixel open: {}", text);
+                return Err(text);
+            }
+        }
+        // Only one open can set the marker, however many race for it.
+        match conn.execute(
+            "UPDATE tracked_messages SET first_opened_at = $2
+             WHERE message_id = $1 AND first_opened_at IS NULL",
+            &[&message_id, &opened_at],
+        ) {
+            Ok(n) => Ok(Some(n == 1)),
+            Err(e) => {
+                error!("[db] failed to mark first pixel open: {}", e);
+                Ok(Some(false))
+            }
         }
     }
 
+    /// Up to `limit` tracked messages with an id above `after_id`, oldest
+    /// first, created at or after `from` and before `to` (either may be
+    /// empty).  Paging by id lets exports walk the whole table in batches.
+    pub fn list_tracked_messages_after(
+        &self,
+        after_id: i64,
+        from: &str,
+        to: &str,
+        limit: i64,
+    ) -> Vec<TrackedMessage> {
+        debug!(
+            "[db] listing tracked messages after id={} from={:?} to={:?} limit={}",
+            after_id, from, to, limit
+        );
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT id, message_id, sender, recipient, subject, alias_id, created_at
+                 FROM tracked_messages
+                 WHERE id > $1
+                   AND ($2 = '' OR created_at >= $2)
+                   AND ($3 = '' OR created_at < $3)
+                 ORDER BY id
+                 LIMIT $4",
+                &[&after_id, &from, &to, &limit],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list tracked messages: {}", e);
+                Vec::new()
+            });
+
+        rows.into_iter()
+            .map(|row| TrackedMessage {
+                id: row.get(0),
+                message_id: row.get(1),
+                sender: row.get(2),
+                recipient: row.get(3),
+                subject: row.get(4),
+                alias_id: row.get(5),
+                created_at: row.get(6),
+            })
+            .collect()
+    }
+
     pub fn list_tracked_messages(&self, limit: i64) -> Vec<TrackedMessage> {
         debug!("[db] listing tracked messages limit={}", limit);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, message_id, sender, recipient, subject, alias_id, created_at
@@ -2024,7 +3927,7 @@ impl Database {
 
     pub fn get_tracked_message(&self, message_id: &str) -> Option<TrackedMessage> {
         debug!("[db] getting tracked message id={}", message_id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, message_id, sender, recipient, subject, alias_id, created_at
              FROM tracked_messages WHERE message_id = $1",
@@ -2043,12 +3946,89 @@ impl Database {
         })
     }
 
+    /// Count a rejected pixel request and drop counts older than
+    /// `retention_days`.
+    pub fn record_pixel_rejection(
+        &self,
+        message_id: &str,
+        client_ip: &str,
+        reason: &str,
+        retention_days: i64,
+    ) {
+        debug!(
+            "[db] recording pixel rejection message_id={}, reason={}",
+            message_id, reason
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        let today = chrono::Utc::now();
+        let day = today.format("%Y-%m-%d").to_string();
+        let cutoff = (today - chrono::Duration::days(retention_days))
+            .format("%Y-%m-%d")
+            .to_string();
+        if let Err(e) = conn.execute(
+            "DELETE FROM pixel_rejection_counts WHERE day < $1",
+            &[&cutoff],
+        ) {
+            error!("[db] failed to purge pixel rejections: {}", e);
+        }
+        if let Err(e) = conn.execute(
+            "INSERT INTO pixel_rejection_counts (day, reason, count, last_message_id, last_client_ip, last_seen_at)
+             VALUES ($1, $2, 1, $3, $4, $5)
+             ON CONFLICT (day, reason) DO UPDATE SET
+                 count = pixel_rejection_counts.count + 1,
+                 last_message_id = EXCLUDED.last_message_id,
+                 last_client_ip = EXCLUDED.last_client_ip,
+                 last_seen_at = EXCLUDED.last_seen_at",
+            &[&day, &reason, &message_id, &client_ip, &now()],
+        ) {
+            error!("[db] failed to record pixel rejection: {}", e);
+        }
+    }
+
+    /// Rejection counts for the `days` most recent days, newest first.
+    pub fn list_pixel_rejections(&self, days: i64) -> Vec<PixelRejection> {
+        debug!("[db] listing pixel rejections days={}", days);
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let since = (chrono::Utc::now() - chrono::Duration::days(days))
+            .format("%Y-%m-%d")
+            .to_string();
+        let rows = conn
+            .query(
+                "SELECT day, reason, count, last_message_id, last_client_ip, last_seen_at
+                 FROM pixel_rejection_counts
+                 WHERE day > $1
+                 ORDER BY day DESC, count DESC",
+                &[&since],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list pixel rejections: {}", e);
+                Vec::new()
+            });
+
+        rows.into_iter()
+            .map(|row| PixelRejection {
+                day: row.get(0),
+                reason: row.get(1),
+                count: row.get(2),
+                last_message_id: row.get(3),
+                last_client_ip: row.get(4),
+                last_seen_at: row.get(5),
+            })
+            .collect()
+    }
+
     pub fn get_opens_for_message(&self, message_id: &str) -> Vec<PixelOpen> {
         debug!("[db] getting opens for message id={}", message_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
-                "SELECT id, message_id, client_ip, user_agent, opened_at
+                "SELECT id, message_id, client_ip, user_agent, opened_at, country, city
                  FROM pixel_opens WHERE message_id = $1
                  ORDER BY opened_at DESC",
                 &[&message_id],
@@ -2065,6 +4045,8 @@ impl Database {
                 client_ip: row.get(2),
                 user_agent: row.get(3),
                 opened_at: row.get(4),
+                country: row.get(5),
+                city: row.get(6),
             })
             .collect()
     }
@@ -2072,7 +4054,9 @@ impl Database {
     // ── Generic settings storage (key/value) ──
 
     pub fn set_setting(&self, key: &str, value: &str) {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "INSERT INTO settings (key, value)
              VALUES ($1, $2)
@@ -2084,13 +4068,40 @@ impl Database {
     }
 
     pub fn get_setting(&self, key: &str) -> Option<String> {
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt("SELECT value FROM settings WHERE key = $1", &[&key])
             .ok()
             .flatten()
             .map(|row| row.get(0))
     }
 
+    pub fn delete_setting(&self, key: &str) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute("DELETE FROM settings WHERE key = $1", &[&key]) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    /// Settings whose key starts with `prefix`, as `(key, value)` pairs.
+    pub fn settings_with_prefix(&self, prefix: &str) -> Vec<(String, String)> {
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "SELECT key, value FROM settings WHERE starts_with(key, $1) ORDER BY key",
+            &[&prefix],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to execute query: {}", e);
+            Vec::new()
+        })
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect()
+    }
+
     pub fn is_fail2ban_enabled(&self) -> bool {
         self.get_setting("fail2ban_enabled")
             .map(|v| v == "true")
@@ -2099,14 +4110,21 @@ impl Database {
 
     pub fn get_stats(&self) -> Stats {
         debug!("[db] fetching aggregate stats");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Default::default();
+        };
 
         let domain_count: i64 = conn
-            .query_one("SELECT COUNT(*) FROM domains", &[])
+            .query_one("SELECT COUNT(*) FROM domains WHERE deleted_at IS NULL", &[])
             .map(|row| row.get(0))
             .unwrap_or(0);
         let account_count: i64 = conn
-            .query_one("SELECT COUNT(*) FROM accounts", &[])
+            .query_one(
+                "SELECT COUNT(*) FROM accounts a
+                 LEFT JOIN domains d ON a.domain_id = d.id
+                 WHERE a.deleted_at IS NULL AND d.deleted_at IS NULL",
+                &[],
+            )
             .map(|row| row.get(0))
             .unwrap_or(0);
         let alias_count: i64 = conn
@@ -2146,12 +4164,42 @@ impl Database {
 
         let dkim_ready_count: i64 = conn
             .query_one(
-                "SELECT COUNT(*) FROM domains WHERE dkim_public_key IS NOT NULL AND active = TRUE",
+                "SELECT COUNT(*) FROM domains
+                 WHERE dkim_public_key IS NOT NULL AND active = TRUE AND deleted_at IS NULL",
                 &[],
             )
             .map(|row| row.get(0))
             .unwrap_or(0);
 
+        let day_ago = (chrono::Utc::now() - chrono::Duration::days(1))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        let (login_count, login_account_count): (i64, i64) = conn
+            .query_one(
+                "SELECT COUNT(*), COUNT(DISTINCT username) FROM login_events WHERE created_at > $1",
+                &[&day_ago],
+            )
+            .map(|row| (row.get(0), row.get(1)))
+            .unwrap_or((0, 0));
+
+        let month_ago = (chrono::Utc::now() - chrono::Duration::days(30))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        let dormant_account_count: i64 = conn
+            .query_one(
+                "SELECT COUNT(*) FROM accounts a
+                 JOIN domains d ON a.domain_id = d.id
+                 WHERE a.active = TRUE AND a.deleted_at IS NULL AND d.deleted_at IS NULL
+                   AND NOT EXISTS (
+                       SELECT 1 FROM login_events l
+                       WHERE l.username = LOWER(a.username || '@' || d.domain)
+                         AND l.created_at > $1
+                   )",
+                &[&month_ago],
+            )
+            .map(|row| row.get(0))
+            .unwrap_or(0);
+
         Stats {
             domain_count,
             account_count,
@@ -2163,6 +4211,166 @@ impl Database {
             webhook_count,
             unsubscribe_count,
             dkim_ready_count,
+            login_count,
+            login_account_count,
+            dormant_account_count,
+        }
+    }
+
+    /// Rows of `table` per day over the last `days` days, today included,
+    /// counted by the text timestamp `column`.  Days without rows count 0.
+    fn daily_counts(&self, table: &str, column: &str, filter: &str, days: i64) -> Vec<DailyCount> {
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let today = chrono::Utc::now().date_naive();
+        let first = (today - chrono::Duration::days(days.max(1) - 1)).to_string();
+        conn.query(
+            &format!(
+                "SELECT day::date::text, COUNT(t.{column})
+                 FROM generate_series($1::text::date, $2::text::date, INTERVAL '1 day') day
+                 LEFT JOIN {table} t
+                   ON t.{column} >= day::date::text AND t.{column} < (day::date + 1)::text
+                  AND {filter}
+                 GROUP BY day ORDER BY day",
+                column = column,
+                table = table,
+                filter = filter
+            ),
+            &[&first, &today.to_string()],
+        )
+        .map(|rows| {
+            rows.iter()
+                .map(|row| DailyCount {
+                    day: row.get(0),
+                    count: row.get(1),
+                })
+                .collect()
+        })
+        .unwrap_or_else(|e| {
+            error!("[db] failed to count {} per day: {}", table, e);
+            Vec::new()
+        })
+    }
+
+    /// Tracking pixel opens per day over the last `days` days.
+    pub fn opens_per_day(&self, days: i64) -> Vec<DailyCount> {
+        debug!("[db] counting opens per day for {} days", days);
+        self.daily_counts("pixel_opens", "opened_at", "TRUE", days)
+    }
+
+    /// Fail2ban bans per day over the last `days` days.
+    pub fn bans_per_day(&self, days: i64) -> Vec<DailyCount> {
+        debug!("[db] counting bans per day for {} days", days);
+        self.daily_counts("fail2ban_log", "created_at", "t.action = 'ban'", days)
+    }
+
+    // ── Login event methods ──
+
+    pub fn record_login_event(&self, username: &str, service: &str, ip_address: &str) {
+        debug!(
+            "[db] recording login username={} service={} ip={}",
+            username, service, ip_address
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO login_events (username, service, ip_address, created_at) VALUES ($1, $2, $3, $4)",
+            &[&username.to_lowercase(), &service, &ip_address, &now()],
+        ) {
+            error!("[db] failed to record login for username={}: {}", username, e);
+        }
+    }
+
+    /// The last recorded login of each of `usernames` that has one.
+    pub fn last_logins(&self, usernames: &[String]) -> HashMap<String, String> {
+        debug!("[db] fetching last logins for {} account(s)", usernames.len());
+        let usernames: Vec<String> = usernames.iter().map(|u| u.to_lowercase()).collect();
+        let Some(mut conn) = self.conn() else {
+            return HashMap::new();
+        };
+        conn.query(
+            "SELECT username, MAX(created_at) FROM login_events
+             WHERE username = ANY($1)
+             GROUP BY username",
+            &[&usernames],
+        )
+        .map(|rows| rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+        .unwrap_or_else(|e| {
+            error!("[db] failed to fetch last logins: {}", e);
+            HashMap::new()
+        })
+    }
+
+    /// Drop login events recorded before `before`.
+    pub fn prune_login_events(&self, before: &str) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        match conn.execute("DELETE FROM login_events WHERE created_at < $1", &[&before]) {
+            Ok(n) if n > 0 => info!("[db] pruned {} login events before {}", n, before),
+            Ok(_) => {}
+            Err(e) => error!("[db] failed to prune login events: {}", e),
+        }
+    }
+
+    // ── Greylist methods ──
+
+    /// Record a delivery attempt for a greylisting triplet, returning when it
+    /// was first seen and whether it has already passed.
+    pub fn greylist_touch(
+        &self,
+        client_ip: &str,
+        sender: &str,
+        recipient: &str,
+    ) -> Option<(String, bool)> {
+        debug!(
+            "[db] greylist lookup client={} sender={} recipient={}",
+            client_ip, sender, recipient
+        );
+        let mut conn = self.conn()?;
+        let now = now();
+        conn.query_one(
+            "INSERT INTO greylist (client_ip, sender, recipient, first_seen, last_seen)
+             VALUES ($1, $2, $3, $4, $4)
+             ON CONFLICT (client_ip, sender, recipient) DO UPDATE SET last_seen = $4
+             RETURNING first_seen, passed_at IS NOT NULL",
+            &[&client_ip, &sender, &recipient, &now],
+        )
+        .map(|row| (row.get(0), row.get(1)))
+        .map_err(|e| error!("[db] failed to look up greylist entry: {}", e))
+        .ok()
+    }
+
+    pub fn greylist_pass(&self, client_ip: &str, sender: &str, recipient: &str) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE greylist SET passed_at = $4
+             WHERE client_ip = $1 AND sender = $2 AND recipient = $3 AND passed_at IS NULL",
+            &[&client_ip, &sender, &recipient, &now()],
+        ) {
+            error!("[db] failed to mark greylist entry as passed: {}", e);
+        }
+    }
+
+    /// Remove pending triplets not retried since `pending_before` and passed
+    /// ones unused since `passed_before`.
+    pub fn prune_greylist(&self, pending_before: &str, passed_before: &str) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        match conn.execute(
+            "DELETE FROM greylist
+             WHERE (passed_at IS NULL AND last_seen < $1)
+                OR (passed_at IS NOT NULL AND last_seen < $2)",
+            &[&pending_before, &passed_before],
+        ) {
+            Ok(n) if n > 0 => info!("[db] pruned {} stale greylist entries", n),
+            Ok(_) => {}
+            Err(e) => error!("[db] failed to prune greylist: {}", e),
         }
     }
 
@@ -2170,7 +4378,9 @@ impl Database {
 
     pub fn list_fail2ban_settings(&self) -> Vec<Fail2banSetting> {
         debug!("[db] listing fail2ban settings");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, service, max_attempts, ban_duration_minutes, find_time_minutes, enabled
@@ -2196,7 +4406,7 @@ impl Database {
 
     pub fn get_fail2ban_setting(&self, id: i64) -> Option<Fail2banSetting> {
         debug!("[db] getting fail2ban setting id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, service, max_attempts, ban_duration_minutes, find_time_minutes, enabled
              FROM fail2ban_settings WHERE id = $1",
@@ -2226,7 +4436,9 @@ impl Database {
             "[db] updating fail2ban setting id={}, max_attempts={}, ban_duration={}, find_time={}, enabled={}",
             id, max_attempts, ban_duration_minutes, find_time_minutes, enabled
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE fail2ban_settings SET max_attempts = $1, ban_duration_minutes = $2, find_time_minutes = $3, enabled = $4, updated_at = $5 WHERE id = $6",
             &[&max_attempts, &ban_duration_minutes, &find_time_minutes, &enabled, &now(), &id],
@@ -2236,8 +4448,19 @@ impl Database {
     }
 
     pub fn list_fail2ban_banned(&self) -> Vec<Fail2banBanned> {
+        self.active_fail2ban_bans().unwrap_or_else(|e| {
+            error!("[db] failed to list banned IPs: {}", e);
+            Vec::new()
+        })
+    }
+
+    /// Active bans, or the error when they cannot be read, for callers that
+    /// must not mistake a failed read for an empty list.
+    pub fn active_fail2ban_bans(&self) -> Result<Vec<Fail2banBanned>, String> {
         debug!("[db] listing banned IPs");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let rows = conn
             .query(
                 "SELECT id, ip_address, service, reason, attempts, banned_at, expires_at, permanent
@@ -2246,12 +4469,10 @@ impl Database {
                  ORDER BY banned_at DESC",
                 &[&now()],
             )
-            .unwrap_or_else(|e| {
-                error!("[db] failed to list banned IPs: {}", e);
-                Vec::new()
-            });
+            .map_err(|e| e.to_string())?;
 
-        rows.into_iter()
+        Ok(rows
+            .into_iter()
             .map(|row| Fail2banBanned {
                 id: row.get(0),
                 ip_address: row.get(1),
@@ -2262,7 +4483,7 @@ impl Database {
                 expires_at: row.get(6),
                 permanent: row.get(7),
             })
-            .collect()
+            .collect())
     }
 
     pub fn ban_ip(
@@ -2273,11 +4494,14 @@ impl Database {
         duration_minutes: i32,
         permanent: bool,
     ) -> Result<i64, String> {
+        let ip_address = &canonical_ip_entry(ip_address);
         info!(
             "[db] banning IP={} service={} permanent={}",
             ip_address, service, permanent
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         let expires = if permanent {
             None
@@ -2314,9 +4538,10 @@ impl Database {
         Ok(id)
     }
 
-    pub fn unban_ip(&self, id: i64) {
+    /// Lift a ban, returning the unbanned address.
+    pub fn unban_ip(&self, id: i64) -> Option<String> {
         info!("[db] unbanning IP id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         // Get IP for logging before delete
         let ip_info = conn
             .query_opt(
@@ -2328,21 +4553,23 @@ impl Database {
         if let Err(e) = conn.execute("DELETE FROM fail2ban_banned WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
-        if let Some(row) = ip_info {
-            let ip: String = row.get(0);
-            let service: String = row.get(1);
-            if let Err(e) = conn.execute(
-                "INSERT INTO fail2ban_log (ip_address, service, action, details, created_at) VALUES ($1, $2, 'unban', 'Manual unban from admin', $3)",
-                &[&ip, &service, &now()],
-            ) {
-                error!("[db] failed to execute query: {}", e);
-            }
+        let row = ip_info?;
+        let ip: String = row.get(0);
+        let service: String = row.get(1);
+        if let Err(e) = conn.execute(
+            "INSERT INTO fail2ban_log (ip_address, service, action, details, created_at) VALUES ($1, $2, 'unban', 'Manual unban from admin', $3)",
+            &[&ip, &service, &now()],
+        ) {
+            error!("[db] failed to execute query: {}", e);
         }
+        Some(ip)
     }
 
     pub fn list_fail2ban_whitelist(&self) -> Vec<Fail2banWhitelist> {
         debug!("[db] listing fail2ban whitelist");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, ip_address, description, created_at FROM fail2ban_whitelist ORDER BY created_at DESC",
@@ -2364,8 +4591,11 @@ impl Database {
     }
 
     pub fn add_to_whitelist(&self, ip_address: &str, description: &str) -> Result<i64, String> {
+        let ip_address = &canonical_ip_entry(ip_address);
         info!("[db] adding IP to whitelist: {}", ip_address);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         let row = conn
             .query_one(
@@ -2380,10 +4610,17 @@ impl Database {
             })?;
         let id: i64 = row.get(0);
 
-        // Also unban if currently banned
+        // Also lift any bans the new entry covers
+        let covered: Vec<i64> = conn
+            .query("SELECT id, ip_address FROM fail2ban_banned", &[])
+            .unwrap_or_default()
+            .iter()
+            .filter(|row| crate::fail2ban::entry_matches(ip_address, row.get(1)))
+            .map(|row| row.get(0))
+            .collect();
         if let Err(e) = conn.execute(
-            "DELETE FROM fail2ban_banned WHERE ip_address = $1",
-            &[&ip_address],
+            "DELETE FROM fail2ban_banned WHERE id = ANY($1)",
+            &[&covered],
         ) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -2400,7 +4637,9 @@ impl Database {
 
     pub fn remove_from_whitelist(&self, id: i64) {
         info!("[db] removing from whitelist id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM fail2ban_whitelist WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -2408,7 +4647,9 @@ impl Database {
 
     pub fn list_fail2ban_blacklist(&self) -> Vec<Fail2banBlacklist> {
         debug!("[db] listing fail2ban blacklist");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, ip_address, description, created_at FROM fail2ban_blacklist ORDER BY created_at DESC",
@@ -2429,54 +4670,136 @@ impl Database {
             .collect()
     }
 
-    pub fn add_to_blacklist(&self, ip_address: &str, description: &str) -> Result<i64, String> {
-        info!("[db] adding IP to blacklist: {}", ip_address);
-        let mut conn = self.conn();
-        let ts = now();
+    pub fn add_to_blacklist(&self, ip_address: &str, description: &str) -> Result<i64, String> {
+        let ip_address = &canonical_ip_entry(ip_address);
+        info!("[db] adding IP to blacklist: {}", ip_address);
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let ts = now();
+        let row = conn
+            .query_one(
+                "INSERT INTO fail2ban_blacklist (ip_address, description, created_at) VALUES ($1, $2, $3)
+                 ON CONFLICT (ip_address) DO UPDATE SET description = EXCLUDED.description
+                 RETURNING id",
+                &[&ip_address, &description, &ts],
+            )
+            .map_err(|e| {
+                error!("[db] failed to add to blacklist {}: {}", ip_address, e);
+                e.to_string()
+            })?;
+        let id: i64 = row.get(0);
+
+        // Also permanently ban this IP
+        if let Err(e) = conn.execute(
+            "INSERT INTO fail2ban_banned (ip_address, service, reason, attempts, banned_at, expires_at, permanent, created_at)
+             VALUES ($1, 'all', 'Blacklisted', 0, $2, NULL, TRUE, $2)
+             ON CONFLICT (ip_address, service) DO UPDATE SET permanent = TRUE, reason = 'Blacklisted', expires_at = NULL",
+            &[&ip_address, &ts],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+
+        if let Err(e) = conn.execute(
+            "INSERT INTO fail2ban_log (ip_address, service, action, details, created_at) VALUES ($1, 'all', 'blacklist', $2, $3)",
+            &[&ip_address, &description, &ts],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+
+        Ok(id)
+    }
+
+    pub fn remove_from_blacklist(&self, id: i64) {
+        info!("[db] removing from blacklist id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute("DELETE FROM fail2ban_blacklist WHERE id = $1", &[&id]) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    pub fn list_fail2ban_rules(&self) -> Vec<Fail2banRule> {
+        debug!("[db] listing fail2ban rules");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT id, name, service, regex, enabled, created_at FROM fail2ban_rules ORDER BY name, id",
+                &[],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list fail2ban rules: {}", e);
+                Vec::new()
+            });
+
+        rows.into_iter()
+            .map(|row| Fail2banRule {
+                id: row.get(0),
+                name: row.get(1),
+                service: row.get(2),
+                regex: row.get(3),
+                enabled: row.get(4),
+                created_at: row.get(5),
+            })
+            .collect()
+    }
+
+    pub fn create_fail2ban_rule(
+        &self,
+        name: &str,
+        service: &str,
+        regex: &str,
+    ) -> Result<i64, String> {
+        info!(
+            "[db] creating fail2ban rule name={}, service={}",
+            name, service
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
-                "INSERT INTO fail2ban_blacklist (ip_address, description, created_at) VALUES ($1, $2, $3)
-                 ON CONFLICT (ip_address) DO UPDATE SET description = EXCLUDED.description
-                 RETURNING id",
-                &[&ip_address, &description, &ts],
+                "INSERT INTO fail2ban_rules (name, service, regex, enabled, created_at) VALUES ($1, $2, $3, TRUE, $4) RETURNING id",
+                &[&name, &service, &regex, &now()],
             )
             .map_err(|e| {
-                error!("[db] failed to add to blacklist {}: {}", ip_address, e);
+                error!("[db] failed to create fail2ban rule {}: {}", name, e);
                 e.to_string()
             })?;
-        let id: i64 = row.get(0);
-
-        // Also permanently ban this IP
-        if let Err(e) = conn.execute(
-            "INSERT INTO fail2ban_banned (ip_address, service, reason, attempts, banned_at, expires_at, permanent, created_at)
-             VALUES ($1, 'all', 'Blacklisted', 0, $2, NULL, TRUE, $2)
-             ON CONFLICT (ip_address, service) DO UPDATE SET permanent = TRUE, reason = 'Blacklisted', expires_at = NULL",
-            &[&ip_address, &ts],
-        ) {
-            error!("[db] failed to execute query: {}", e);
-        }
+        Ok(row.get(0))
+    }
 
+    pub fn set_fail2ban_rule_enabled(&self, id: i64, enabled: bool) {
+        info!("[db] setting fail2ban rule id={} enabled={}", id, enabled);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
-            "INSERT INTO fail2ban_log (ip_address, service, action, details, created_at) VALUES ($1, 'all', 'blacklist', $2, $3)",
-            &[&ip_address, &description, &ts],
+            "UPDATE fail2ban_rules SET enabled = $1 WHERE id = $2",
+            &[&enabled, &id],
         ) {
             error!("[db] failed to execute query: {}", e);
         }
-
-        Ok(id)
     }
 
-    pub fn remove_from_blacklist(&self, id: i64) {
-        info!("[db] removing from blacklist id={}", id);
-        let mut conn = self.conn();
-        if let Err(e) = conn.execute("DELETE FROM fail2ban_blacklist WHERE id = $1", &[&id]) {
+    pub fn delete_fail2ban_rule(&self, id: i64) {
+        info!("[db] deleting fail2ban rule id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute("DELETE FROM fail2ban_rules WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
     }
 
     pub fn list_fail2ban_log(&self, limit: i64) -> Vec<Fail2banLogEntry> {
         debug!("[db] listing fail2ban log limit={}", limit);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, ip_address, service, action, details, created_at
@@ -2500,33 +4823,40 @@ impl Database {
             .collect()
     }
 
+    /// Entries may be single IPs or CIDR ranges, so containment is checked
+    /// in Rust rather than with an equality lookup.
     pub fn is_ip_whitelisted(&self, ip_address: &str) -> bool {
-        let mut conn = self.conn();
-        let count: i64 = conn
-            .query_one(
-                "SELECT COUNT(*) FROM fail2ban_whitelist WHERE ip_address = $1",
-                &[&ip_address],
-            )
-            .map(|row| row.get(0))
-            .unwrap_or(0);
-        count > 0
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        conn.query("SELECT ip_address FROM fail2ban_whitelist", &[])
+            .unwrap_or_else(|e| {
+                error!("[db] failed to load whitelist: {}", e);
+                Vec::new()
+            })
+            .iter()
+            .any(|row| crate::fail2ban::entry_matches(row.get(0), ip_address))
     }
 
     pub fn is_ip_banned(&self, ip_address: &str) -> bool {
-        let mut conn = self.conn();
-        let count: i64 = conn
-            .query_one(
-                "SELECT COUNT(*) FROM fail2ban_banned WHERE ip_address = $1 AND (permanent = TRUE OR expires_at > $2)",
-                &[&ip_address, &now()],
-            )
-            .map(|row| row.get(0))
-            .unwrap_or(0);
-        count > 0
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        conn.query(
+            "SELECT ip_address FROM fail2ban_banned WHERE permanent = TRUE OR expires_at > $1",
+            &[&now()],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to load banned IPs: {}", e);
+            Vec::new()
+        })
+        .iter()
+        .any(|row| crate::fail2ban::entry_matches(row.get(0), ip_address))
     }
 
     pub fn get_fail2ban_setting_by_service(&self, service: &str) -> Option<Fail2banSetting> {
         debug!("[db] getting fail2ban setting for service={}", service);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, service, max_attempts, ban_duration_minutes, find_time_minutes, enabled
              FROM fail2ban_settings WHERE service = $1",
@@ -2545,11 +4875,14 @@ impl Database {
     }
 
     pub fn record_fail2ban_attempt(&self, ip_address: &str, service: &str, details: &str) {
+        let ip_address = &canonical_ip_entry(ip_address);
         info!(
             "[db] recording fail2ban attempt ip={} service={}",
             ip_address, service
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "INSERT INTO fail2ban_log (ip_address, service, action, details, created_at) VALUES ($1, $2, 'attempt', $3, $4)",
             &[&ip_address, &service, &details, &now()],
@@ -2559,11 +4892,14 @@ impl Database {
     }
 
     pub fn count_recent_attempts(&self, ip_address: &str, service: &str, minutes: i32) -> i64 {
+        let ip_address = &canonical_ip_entry(ip_address);
         debug!(
             "[db] counting recent attempts ip={} service={} window={}min",
             ip_address, service, minutes
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
         let cutoff = (chrono::Utc::now() - chrono::Duration::minutes(minutes as i64))
             .format("%Y-%m-%d %H:%M:%S")
             .to_string();
@@ -2577,6 +4913,94 @@ impl Database {
         count
     }
 
+    /// Messages and recipients submitted by an account in the last `minutes`.
+    pub fn count_recent_sends(&self, account_id: i64, minutes: i64) -> (i64, i64) {
+        debug!(
+            "[db] counting recent sends account_id={} window={}min",
+            account_id, minutes
+        );
+        let Some(mut conn) = self.conn() else {
+            return (0, 0);
+        };
+        let cutoff = (chrono::Utc::now() - chrono::Duration::minutes(minutes))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        conn.query_one(
+            "SELECT COUNT(*), COALESCE(SUM(recipients), 0) FROM send_counters WHERE account_id = $1 AND created_at > $2",
+            &[&account_id, &cutoff],
+        )
+        .map(|row| (row.get(0), row.get(1)))
+        .unwrap_or((0, 0))
+    }
+
+    pub fn record_send(&self, account_id: i64, recipients: i32) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO send_counters (account_id, recipients, created_at) VALUES ($1, $2, $3)",
+            &[&account_id, &recipients, &now()],
+        ) {
+            error!("[db] failed to record send for account_id={}: {}", account_id, e);
+        }
+    }
+
+    pub fn prune_send_counters(&self, before: &str) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute("DELETE FROM send_counters WHERE created_at < $1", &[&before]) {
+            error!("[db] failed to prune send counters: {}", e);
+        }
+    }
+
+    pub fn count_recent_attempts_with_details(
+        &self,
+        service: &str,
+        details: &str,
+        minutes: i32,
+    ) -> i64 {
+        debug!(
+            "[db] counting recent attempts service={} details={} window={}min",
+            service, details, minutes
+        );
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
+        let cutoff = (chrono::Utc::now() - chrono::Duration::minutes(minutes as i64))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        conn.query_one(
+            "SELECT COUNT(*) FROM fail2ban_log WHERE service = $1 AND details = $2 AND action = 'attempt' AND created_at > $3",
+            &[&service, &details, &cutoff],
+        )
+        .map(|row| row.get(0))
+        .unwrap_or(0)
+    }
+
+    /// Mark outstanding attempts from `ip_address` or carrying `details` as
+    /// reset so they no longer count towards a ban.  The rows stay in the log.
+    pub fn reset_fail2ban_attempts(&self, ip_address: &str, service: &str, details: &str) {
+        let ip_address = &canonical_ip_entry(ip_address);
+        info!(
+            "[db] resetting fail2ban attempts ip={} service={}",
+            ip_address, service
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE fail2ban_log SET action = 'reset'
+             WHERE service = $1 AND action = 'attempt' AND (ip_address = $2 OR details = $3)",
+            &[&service, &ip_address, &details],
+        ) {
+            error!(
+                "[db] failed to reset fail2ban attempts for ip={}: {}",
+                ip_address, e
+            );
+        }
+    }
+
     pub fn create_unsubscribe_token(
         &self,
         token: &str,
@@ -2587,7 +5011,9 @@ impl Database {
             "[db] creating unsubscribe token for recipient={} domain={}",
             recipient_email, sender_domain
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "INSERT INTO unsubscribe_tokens (token, recipient_email, sender_domain, created_at)
              VALUES ($1, $2, $3, $4)
@@ -2600,7 +5026,7 @@ impl Database {
 
     pub fn get_unsubscribe_by_token(&self, token: &str) -> Option<(String, String)> {
         debug!("[db] looking up unsubscribe token={}", token);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT recipient_email, sender_domain FROM unsubscribe_tokens WHERE token = $1",
             &[&token],
@@ -2615,7 +5041,9 @@ impl Database {
             "[db] recording unsubscribe email={} domain={}",
             email, domain
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "INSERT INTO unsubscribe_list (email, domain, created_at)
              VALUES ($1, $2, $3)
@@ -2626,8 +5054,27 @@ impl Database {
         }
     }
 
+    /// Take `email` off the unsubscribe list of `domain` again.
+    pub fn remove_unsubscribe_by_email(&self, email: &str, domain: &str) {
+        info!(
+            "[db] removing unsubscribe email={} domain={}",
+            email, domain
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM unsubscribe_list WHERE LOWER(email) = LOWER($1) AND LOWER(domain) = LOWER($2)",
+            &[&email, &domain],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
     pub fn is_unsubscribed(&self, email: &str, domain: &str) -> bool {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
         let count: i64 = conn
             .query_one(
                 "SELECT COUNT(*) FROM unsubscribe_list WHERE LOWER(email) = LOWER($1) AND LOWER(domain) = LOWER($2)",
@@ -2640,7 +5087,9 @@ impl Database {
 
     pub fn list_unsubscribes(&self) -> Vec<UnsubscribeEntry> {
         debug!("[db] listing all unsubscribe entries");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, email, domain, created_at FROM unsubscribe_list ORDER BY created_at DESC",
@@ -2662,17 +5111,21 @@ impl Database {
 
     pub fn delete_unsubscribe(&self, id: i64) {
         warn!("[db] deleting unsubscribe entry id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM unsubscribe_list WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
     }
 
     pub fn is_unsubscribe_enabled_for_domain(&self, sender_domain: &str) -> bool {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
         let count: i64 = conn
             .query_one(
-                "SELECT COUNT(*) FROM domains WHERE LOWER(domain) = LOWER($1) AND unsubscribe_enabled = TRUE AND active = TRUE",
+                "SELECT COUNT(*) FROM domains WHERE LOWER(domain) = LOWER($1) AND unsubscribe_enabled = TRUE AND active = TRUE AND deleted_at IS NULL",
                 &[&sender_domain],
             )
             .map(|row| row.get(0))
@@ -2680,11 +5133,326 @@ impl Database {
         count > 0
     }
 
+    pub fn is_dmarc_reporting_enabled(&self, domain: &str) -> bool {
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        let count: i64 = conn
+            .query_one(
+                "SELECT COUNT(*) FROM domains WHERE LOWER(domain) = LOWER($1) AND dmarc_reports_enabled = TRUE AND active = TRUE AND deleted_at IS NULL",
+                &[&domain],
+            )
+            .map(|row| row.get(0))
+            .unwrap_or(0);
+        count > 0
+    }
+
+    /// Quiet-hours schedule `(start, end, timezone)` of an active domain, if one is set.
+    pub fn get_quiet_hours_for_domain(&self, sender_domain: &str) -> Option<(String, String, String)> {
+        let mut conn = self.conn()?;
+        conn.query_opt(
+            "SELECT quiet_hours_start, quiet_hours_end, quiet_hours_timezone FROM domains
+             WHERE LOWER(domain) = LOWER($1) AND active = TRUE AND deleted_at IS NULL
+               AND COALESCE(quiet_hours_start, '') <> '' AND COALESCE(quiet_hours_end, '') <> ''",
+            &[&sender_domain],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to get quiet hours for domain {}: {}", sender_domain, e);
+            None
+        })
+        .map(|row| {
+            (
+                row.get(0),
+                row.get(1),
+                row.get::<_, Option<String>>(2).unwrap_or_else(|| "UTC".to_string()),
+            )
+        })
+    }
+
+    // ── Filter decision cache methods ──
+
+    /// Cached verdict for `key`, if one exists and has not expired.
+    pub fn get_filter_decision(&self, key: &str) -> Option<String> {
+        let mut conn = self.conn()?;
+        conn.query_opt(
+            "SELECT verdict FROM filter_decision_cache WHERE cache_key = $1 AND expires_at > $2",
+            &[&key, &now()],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to read filter decision cache: {}", e);
+            None
+        })
+        .map(|row| row.get(0))
+    }
+
+    pub fn put_filter_decision(&self, key: &str, verdict: &str, ttl_seconds: i64) {
+        debug!(
+            "[db] caching filter decision key={} verdict={} ttl={}s",
+            key, verdict, ttl_seconds
+        );
+        let expires_at = (chrono::Utc::now() + chrono::Duration::seconds(ttl_seconds))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        // Keys are per client or message, so expired ones would pile up.
+        if let Err(e) = conn.execute(
+            "DELETE FROM filter_decision_cache WHERE expires_at <= $1",
+            &[&now()],
+        ) {
+            error!("[db] failed to purge expired filter decisions: {}", e);
+        }
+        if let Err(e) = conn.execute(
+            "INSERT INTO filter_decision_cache (cache_key, verdict, expires_at)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (cache_key) DO UPDATE SET verdict = EXCLUDED.verdict, expires_at = EXCLUDED.expires_at",
+            &[&key, &verdict, &expires_at],
+        ) {
+            error!("[db] failed to cache filter decision: {}", e);
+        }
+    }
+
+    pub fn clear_filter_decision_cache(&self) {
+        info!("[db] clearing filter decision cache");
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute("DELETE FROM filter_decision_cache", &[]) {
+            error!("[db] failed to clear filter decision cache: {}", e);
+        }
+    }
+
+    // ── Delivery rule methods ──
+
+    pub fn list_delivery_rules(&self, account_id: i64) -> Vec<DeliveryRule> {
+        debug!("[db] listing delivery rules for account id={}", account_id);
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "SELECT id, account_id, position, match_field, match_op, pattern, action,
+                    target_folder, header, created_at
+             FROM delivery_rules WHERE account_id = $1 ORDER BY position, id",
+            &[&account_id],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list delivery rules for account id={}: {}", account_id, e);
+            Vec::new()
+        })
+        .into_iter()
+        .map(|row| DeliveryRule {
+            id: row.get(0),
+            account_id: row.get(1),
+            position: row.get(2),
+            match_field: row.get(3),
+            match_op: row.get(4),
+            pattern: row.get(5),
+            action: row.get(6),
+            target_folder: row.get(7),
+            header: row.get(8),
+            created_at: row.get(9),
+        })
+        .collect()
+    }
+
+    /// Add a rule after the account's existing rules.
+    #[allow(clippy::too_many_arguments)]
+    pub fn create_delivery_rule(
+        &self,
+        account_id: i64,
+        match_field: &str,
+        match_op: &str,
+        pattern: &str,
+        action: &str,
+        target_folder: &str,
+        header: &str,
+    ) -> Option<i64> {
+        info!(
+            "[db] creating delivery rule for account id={}: {} {} {:?} -> {}",
+            account_id, match_field, match_op, pattern, action
+        );
+        let mut conn = self.conn()?;
+        conn.query_one(
+            "INSERT INTO delivery_rules
+                 (account_id, position, match_field, match_op, pattern, action, target_folder, header, created_at)
+             VALUES ($1, (SELECT COALESCE(MAX(position), 0) + 1 FROM delivery_rules WHERE account_id = $1),
+                     $2, $3, $4, $5, $6, $7, $8)
+             RETURNING id",
+            &[&account_id, &match_field, &match_op, &pattern, &action, &target_folder, &header, &now()],
+        )
+        .map(|row| row.get(0))
+        .map_err(|e| error!("[db] failed to create delivery rule: {}", e))
+        .ok()
+    }
+
+    pub fn delete_delivery_rule(&self, account_id: i64, id: i64) {
+        warn!("[db] deleting delivery rule id={} of account id={}", id, account_id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM delivery_rules WHERE id = $1 AND account_id = $2",
+            &[&id, &account_id],
+        ) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
+    /// Swap a rule with its neighbour above (`up`) or below, renumbering the
+    /// account's rules in their new order.
+    pub fn move_delivery_rule(&self, account_id: i64, id: i64, up: bool) -> Result<(), String> {
+        info!(
+            "[db] moving delivery rule id={} of account id={} {}",
+            id,
+            account_id,
+            if up { "up" } else { "down" }
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        let mut ids: Vec<i64> = tx
+            .query(
+                "SELECT id FROM delivery_rules WHERE account_id = $1 ORDER BY position, id FOR UPDATE",
+                &[&account_id],
+            )
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        let Some(index) = ids.iter().position(|r| *r == id) else {
+            return Err("rule not found".to_string());
+        };
+        let other = if up { index.checked_sub(1) } else { Some(index + 1) };
+        let Some(other) = other.filter(|o| *o < ids.len()) else {
+            return Ok(());
+        };
+        ids.swap(index, other);
+        for (position, rule_id) in ids.iter().enumerate() {
+            tx.execute(
+                "UPDATE delivery_rules SET position = $1 WHERE id = $2",
+                &[&(position as i32 + 1), rule_id],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    // ── Mailbox usage cache methods ──
+
+    /// Bytes last measured for `address`, if measured after `fresh_after`.
+    pub fn get_mailbox_usage(&self, address: &str, fresh_after: &str) -> Option<i64> {
+        let mut conn = self.conn()?;
+        conn.query_opt(
+            "SELECT bytes FROM mailbox_usage WHERE address = $1 AND measured_at > $2",
+            &[&address, &fresh_after],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to read mailbox usage for {}: {}", address, e);
+            None
+        })
+        .map(|row| row.get(0))
+    }
+
+    pub fn put_mailbox_usage(&self, address: &str, bytes: i64) {
+        debug!("[db] caching mailbox usage address={} bytes={}", address, bytes);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO mailbox_usage (address, bytes, measured_at)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (address) DO UPDATE SET bytes = EXCLUDED.bytes, measured_at = EXCLUDED.measured_at",
+            &[&address, &bytes, &now()],
+        ) {
+            error!("[db] failed to cache mailbox usage for {}: {}", address, e);
+        }
+    }
+
+    // ── Scheduled message methods ──
+
+    pub fn create_scheduled_message(
+        &self,
+        sender: &str,
+        recipients: &[String],
+        body: &str,
+        send_at: &str,
+    ) -> Result<i64, String> {
+        info!(
+            "[db] scheduling message sender={} recipients={} send_at={}",
+            sender,
+            recipients.join(","),
+            send_at
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let recipients = recipients.join(",");
+        conn.query_one(
+            "INSERT INTO scheduled_messages (sender, recipients, body, send_at, created_at)
+             VALUES ($1, $2, $3, $4, $5)
+             RETURNING id",
+            &[&sender, &recipients, &body, &send_at, &now()],
+        )
+        .map(|row| row.get::<_, i64>(0))
+        .map_err(|e| {
+            error!("[db] failed to schedule message: {}", e);
+            e.to_string()
+        })
+    }
+
+    /// Scheduled messages whose `send_at` is at or before the current time.
+    pub fn list_due_scheduled_messages(&self) -> Vec<ScheduledMessage> {
+        debug!("[db] listing due scheduled messages");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT id, sender, recipients, body, send_at, created_at
+                 FROM scheduled_messages WHERE send_at <= $1 ORDER BY send_at, id",
+                &[&now()],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list due scheduled messages: {}", e);
+                Vec::new()
+            });
+
+        rows.into_iter()
+            .map(|row| ScheduledMessage {
+                id: row.get(0),
+                sender: row.get(1),
+                recipients: row
+                    .get::<_, String>(2)
+                    .split(',')
+                    .filter(|r| !r.is_empty())
+                    .map(|r| r.to_string())
+                    .collect(),
+                body: row.get(3),
+                send_at: row.get(4),
+                created_at: row.get(5),
+            })
+            .collect()
+    }
+
+    pub fn delete_scheduled_message(&self, id: i64) {
+        debug!("[db] deleting scheduled message id={}", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute("DELETE FROM scheduled_messages WHERE id = $1", &[&id]) {
+            error!("[db] failed to execute query: {}", e);
+        }
+    }
+
     // ── Spambl methods ──
 
     pub fn list_spambl_lists(&self) -> Vec<SpamblList> {
         debug!("[db] listing spambl lists");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, name, hostname, enabled FROM spambl_lists ORDER BY id",
@@ -2707,7 +5475,9 @@ impl Database {
 
     pub fn set_spambl_enabled(&self, id: i64, enabled: bool) {
         info!("[db] setting spambl id={} enabled={}", id, enabled);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE spambl_lists SET enabled = $1, updated_at = $2 WHERE id = $3",
             &[&enabled, &now(), &id],
@@ -2721,7 +5491,9 @@ impl Database {
 
     pub fn list_enabled_spambl_hostnames(&self) -> Vec<String> {
         debug!("[db] listing enabled spambl hostnames");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT hostname FROM spambl_lists WHERE enabled = TRUE ORDER BY id",
@@ -2749,7 +5521,9 @@ impl Database {
         subject: &str,
     ) {
         debug!("[db] logging webhook execution url={}", url);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "INSERT INTO webhook_logs (url, request_body, response_status, response_body, error, duration_ms, sender, subject, created_at)
              VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
@@ -2760,7 +5534,9 @@ impl Database {
     }
 
     pub fn count_webhook_logs(&self) -> i64 {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
         conn.query_one("SELECT COUNT(*) FROM webhook_logs", &[])
             .map(|row| row.get(0))
             .unwrap_or(0)
@@ -2771,7 +5547,9 @@ impl Database {
             "[db] listing webhook logs limit={} offset={}",
             limit, offset
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, url, request_body, response_status, response_body, error, duration_ms, sender, subject, created_at
@@ -2801,7 +5579,7 @@ impl Database {
 
     pub fn get_webhook_log(&self, id: i64) -> Option<WebhookLog> {
         debug!("[db] getting webhook log id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, url, request_body, response_status, response_body, error, duration_ms, sender, subject, created_at
              FROM webhook_logs WHERE id = $1",
@@ -2823,11 +5601,172 @@ impl Database {
         })
     }
 
+    // ── Webhook queue methods ──
+
+    pub fn enqueue_webhook(
+        &self,
+        url: &str,
+        request_body: &str,
+        sender: &str,
+        subject: &str,
+        attempts: i32,
+        next_attempt_at: &str,
+    ) -> Option<i64> {
+        debug!(
+            "[db] queueing webhook url={} attempts={} next={}",
+            url, attempts, next_attempt_at
+        );
+        let mut conn = self.conn()?;
+        conn.query_one(
+            "INSERT INTO webhook_queue (url, request_body, sender, subject, attempts, next_attempt_at, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7)
+             RETURNING id",
+            &[&url, &request_body, &sender, &subject, &attempts, &next_attempt_at, &now()],
+        )
+        .map(|row| row.get(0))
+        .map_err(|e| error!("[db] failed to queue webhook for {}: {}", url, e))
+        .ok()
+    }
+
+    /// Take up to `limit` due deliveries and push their next attempt to
+    /// `lease_until`, so a delivery that crashes mid-way is retried later and
+    /// no other worker picks the same rows meanwhile.
+    pub fn claim_due_webhooks(&self, limit: i64, lease_until: &str) -> Vec<QueuedWebhook> {
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "UPDATE webhook_queue SET next_attempt_at = $1
+                 WHERE id IN (
+                     SELECT id FROM webhook_queue WHERE next_attempt_at <= $2
+                     ORDER BY next_attempt_at, id LIMIT $3 FOR UPDATE SKIP LOCKED
+                 )
+                 RETURNING id, url, request_body, sender, subject, attempts, next_attempt_at, last_error, created_at",
+                &[&lease_until, &now(), &limit],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to claim due webhooks: {}", e);
+                Vec::new()
+            });
+        rows.iter().map(queued_webhook_from_row).collect()
+    }
+
+    pub fn list_queued_webhooks(&self) -> Vec<QueuedWebhook> {
+        debug!("[db] listing queued webhooks");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT id, url, request_body, sender, subject, attempts, next_attempt_at, last_error, created_at
+                 FROM webhook_queue ORDER BY next_attempt_at, id",
+                &[],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list queued webhooks: {}", e);
+                Vec::new()
+            });
+        rows.iter().map(queued_webhook_from_row).collect()
+    }
+
+    pub fn reschedule_webhook(&self, id: i64, attempts: i32, next_attempt_at: &str, last_error: &str) {
+        debug!(
+            "[db] rescheduling webhook id={} attempts={} next={}",
+            id, attempts, next_attempt_at
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "UPDATE webhook_queue SET attempts = $1, next_attempt_at = $2, last_error = $3 WHERE id = $4",
+            &[&attempts, &next_attempt_at, &last_error, &id],
+        ) {
+            error!("[db] failed to reschedule webhook id={}: {}", id, e);
+        }
+    }
+
+    pub fn delete_queued_webhook(&self, id: i64) {
+        debug!("[db] removing webhook id={} from the queue", id);
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute("DELETE FROM webhook_queue WHERE id = $1", &[&id]) {
+            error!("[db] failed to remove queued webhook id={}: {}", id, e);
+        }
+    }
+
+    // ── Audit log methods ──
+
+    pub fn log_audit(
+        &self,
+        actor: &str,
+        action: &str,
+        target_type: &str,
+        target_id: Option<i64>,
+        details: &str,
+    ) {
+        debug!(
+            "[db] audit actor={} action={} target={}:{:?}",
+            actor, action, target_type, target_id
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO audit_log (actor, action, target_type, target_id, details, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6)",
+            &[&actor, &action, &target_type, &target_id, &details, &now()],
+        ) {
+            error!("[db] failed to write audit log entry: {}", e);
+        }
+    }
+
+    pub fn count_audit_log(&self) -> i64 {
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
+        conn.query_one("SELECT COUNT(*) FROM audit_log", &[])
+            .map(|row| row.get(0))
+            .unwrap_or(0)
+    }
+
+    pub fn list_audit_log(&self, limit: i64, offset: i64) -> Vec<AuditLogEntry> {
+        debug!("[db] listing audit log limit={} offset={}", limit, offset);
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT id, actor, action, target_type, target_id, details, created_at
+                 FROM audit_log ORDER BY id DESC LIMIT $1 OFFSET $2",
+                &[&limit, &offset],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list audit log: {}", e);
+                Vec::new()
+            });
+
+        rows.into_iter()
+            .map(|row| AuditLogEntry {
+                id: row.get(0),
+                actor: row.get(1),
+                action: row.get(2),
+                target_type: row.get(3),
+                target_id: row.get(4),
+                details: row.get(5),
+                created_at: row.get(6),
+            })
+            .collect()
+    }
+
     // ── DMARC inbox methods ──
 
     pub fn list_dmarc_inboxes(&self) -> Vec<DmarcInbox> {
         debug!("[db] listing dmarc inboxes");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT di.id, di.account_id, di.label, di.created_at, a.username, d.domain,
@@ -2861,7 +5800,7 @@ impl Database {
 
     pub fn get_dmarc_inbox(&self, id: i64) -> Option<DmarcInbox> {
         debug!("[db] getting dmarc inbox id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT di.id, di.account_id, di.label, di.created_at, a.username, d.domain,
                     di.ruf_account_id, ra.username, rd.domain
@@ -2891,7 +5830,7 @@ impl Database {
     #[allow(dead_code)]
     pub fn get_dmarc_inbox_by_domain(&self, domain: &str) -> Option<DmarcInbox> {
         debug!("[db] getting dmarc inbox for domain={}", domain);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT di.id, di.account_id, di.label, di.created_at, a.username, d.domain,
                     di.ruf_account_id, ra.username, rd.domain
@@ -2921,7 +5860,7 @@ impl Database {
 
     pub fn get_dmarc_inbox_by_domain_id(&self, domain_id: i64) -> Option<DmarcInbox> {
         debug!("[db] getting dmarc inbox for domain_id={}", domain_id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT di.id, di.account_id, di.label, di.created_at, a.username, d.domain,
                     di.ruf_account_id, ra.username, rd.domain
@@ -2951,7 +5890,9 @@ impl Database {
 
     pub fn list_accounts_by_domain(&self, domain_id: i64) -> Vec<Account> {
         debug!("[db] listing accounts for domain_id={}", domain_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT a.id, a.domain_id, a.username, a.password_hash, a.name, a.active, a.quota, d.domain,
@@ -2985,7 +5926,9 @@ impl Database {
 
     pub fn create_dmarc_inbox(&self, account_id: i64, label: &str) -> Result<i64, String> {
         info!("[db] creating dmarc inbox account_id={}", account_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         conn.query_one(
             "INSERT INTO dmarc_inboxes (account_id, label, created_at)
@@ -3001,7 +5944,9 @@ impl Database {
 
     pub fn delete_dmarc_inbox(&self, id: i64) {
         warn!("[db] deleting dmarc inbox id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM dmarc_inboxes WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -3016,7 +5961,9 @@ impl Database {
     /// explicit destination so the record falls back to `postmaster@<domain>` (RFC 5321 §4.5.1).
     pub fn set_dmarc_inbox_ruf(&self, id: i64, ruf_account_id: Option<i64>) {
         info!("[db] setting dmarc inbox ruf id={} ruf_account_id={:?}", id, ruf_account_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE dmarc_inboxes SET ruf_account_id = $1 WHERE id = $2",
             &[&ruf_account_id, &id],
@@ -3024,12 +5971,267 @@ impl Database {
             error!("[db] failed to set dmarc inbox ruf: {}", e);
         }
     }
+
+    /// Replace the stored rows of one aggregate report.
+    pub fn replace_dmarc_report_rows(
+        &self,
+        inbox_id: i64,
+        report_key: &str,
+        rows: &[DmarcReportRow],
+    ) -> Result<(), String> {
+        debug!(
+            "[db] storing dmarc report rows inbox_id={} report={} ({} rows)",
+            inbox_id,
+            report_key,
+            rows.len()
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        tx.execute(
+            "DELETE FROM dmarc_report_rows WHERE inbox_id = $1 AND report_key = $2",
+            &[&inbox_id, &report_key],
+        )
+        .map_err(|e| e.to_string())?;
+        let ts = now();
+        for row in rows {
+            tx.execute(
+                "INSERT INTO dmarc_report_rows (inbox_id, report_key, org_name, policy_domain,
+                        date_begin, date_end, source_ip, message_count, disposition,
+                        dkim_result, spf_result, header_from, created_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)",
+                &[
+                    &inbox_id,
+                    &report_key,
+                    &row.org_name,
+                    &row.policy_domain,
+                    &row.date_begin,
+                    &row.date_end,
+                    &row.source_ip,
+                    &row.message_count,
+                    &row.disposition,
+                    &row.dkim_result,
+                    &row.spf_result,
+                    &row.header_from,
+                    &ts,
+                ],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| {
+            error!(
+                "[db] failed to store dmarc report {} for inbox id={}: {}",
+                report_key, inbox_id, e
+            );
+            e.to_string()
+        })
+    }
+
+    /// Pass/fail totals per source IP over every stored report of an inbox,
+    /// busiest senders first.
+    pub fn dmarc_source_summary(&self, inbox_id: i64) -> Vec<DmarcSourceSummary> {
+        debug!("[db] summarising dmarc report rows inbox_id={}", inbox_id);
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT source_ip,
+                        COUNT(DISTINCT report_key),
+                        COALESCE(SUM(message_count), 0)::BIGINT,
+                        COALESCE(SUM(CASE WHEN dkim_result = 'pass' OR spf_result = 'pass'
+                                          THEN message_count ELSE 0 END), 0)::BIGINT,
+                        COALESCE(SUM(CASE WHEN dkim_result = 'pass' THEN message_count ELSE 0 END), 0)::BIGINT,
+                        COALESCE(SUM(CASE WHEN spf_result = 'pass' THEN message_count ELSE 0 END), 0)::BIGINT
+                 FROM dmarc_report_rows
+                 WHERE inbox_id = $1
+                 GROUP BY source_ip
+                 ORDER BY 3 DESC, source_ip",
+                &[&inbox_id],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to summarise dmarc report rows: {}", e);
+                Vec::new()
+            });
+        rows.into_iter()
+            .map(|row| DmarcSourceSummary {
+                source_ip: row.get(0),
+                reports: row.get(1),
+                messages: row.get(2),
+                passed: row.get(3),
+                dkim_passed: row.get(4),
+                spf_passed: row.get(5),
+            })
+            .collect()
+    }
+
+    // ── DMARC feedback (reports we send) ──
+
+    pub fn record_dmarc_auth_result(&self, result: &DmarcAuthResult) {
+        debug!(
+            "[db] recording dmarc auth result domain={} header_from={} ip={} dkim={} spf={}",
+            result.recipient_domain,
+            result.header_from,
+            result.source_ip,
+            result.dkim_result,
+            result.spf_result
+        );
+        let ts = chrono::Utc::now();
+        let report_date = ts.format("%Y-%m-%d").to_string();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO dmarc_auth_results (recipient_domain, header_from, source_ip, dkim_domain,
+                    dkim_result, spf_domain, spf_result, report_date, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            &[
+                &result.recipient_domain,
+                &result.header_from,
+                &result.source_ip,
+                &result.dkim_domain,
+                &result.dkim_result,
+                &result.spf_domain,
+                &result.spf_result,
+                &report_date,
+                &ts.to_rfc3339(),
+            ],
+        ) {
+            error!("[db] failed to record dmarc auth result: {}", e);
+        }
+    }
+
+    /// `(domain, header_from, report_date)` groups of recorded results from
+    /// `since` up to (excluding) `before` that have no sent-report entry yet.
+    /// Only domains that still have reporting enabled are returned.
+    pub fn list_pending_dmarc_feedback(
+        &self,
+        since: &str,
+        before: &str,
+    ) -> Vec<(String, String, String)> {
+        debug!(
+            "[db] listing pending dmarc feedback since={} before={}",
+            since, before
+        );
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT DISTINCT r.recipient_domain, r.header_from, r.report_date
+                 FROM dmarc_auth_results r
+                 JOIN domains d ON LOWER(d.domain) = r.recipient_domain
+                 WHERE d.dmarc_reports_enabled = TRUE AND d.active = TRUE AND d.deleted_at IS NULL
+                   AND r.report_date >= $1 AND r.report_date < $2
+                   AND NOT EXISTS (
+                       SELECT 1 FROM dmarc_sent_reports s
+                       WHERE s.domain = r.recipient_domain
+                         AND s.header_from = r.header_from
+                         AND s.report_date = r.report_date)
+                 ORDER BY r.report_date, r.recipient_domain, r.header_from",
+                &[&since, &before],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to list pending dmarc feedback: {}", e);
+                Vec::new()
+            });
+        rows.into_iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2)))
+            .collect()
+    }
+
+    /// Results of one report group, collapsed into `(result, message count)`.
+    pub fn dmarc_feedback_rows(
+        &self,
+        domain: &str,
+        header_from: &str,
+        report_date: &str,
+    ) -> Vec<(DmarcAuthResult, i64)> {
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        let rows = conn
+            .query(
+                "SELECT source_ip, dkim_domain, dkim_result, spf_domain, spf_result, COUNT(*)
+                 FROM dmarc_auth_results
+                 WHERE recipient_domain = $1 AND header_from = $2 AND report_date = $3
+                 GROUP BY source_ip, dkim_domain, dkim_result, spf_domain, spf_result
+                 ORDER BY 6 DESC, source_ip",
+                &[&domain, &header_from, &report_date],
+            )
+            .unwrap_or_else(|e| {
+                error!("[db] failed to load dmarc feedback rows: {}", e);
+                Vec::new()
+            });
+        rows.into_iter()
+            .map(|row| {
+                (
+                    DmarcAuthResult {
+                        recipient_domain: domain.to_string(),
+                        header_from: header_from.to_string(),
+                        source_ip: row.get(0),
+                        dkim_domain: row.get(1),
+                        dkim_result: row.get(2),
+                        spf_domain: row.get(3),
+                        spf_result: row.get(4),
+                    },
+                    row.get(5),
+                )
+            })
+            .collect()
+    }
+
+    pub fn record_dmarc_report_sent(&self, report: &DmarcSentReport) {
+        info!(
+            "[db] recording dmarc report {} for domain={} header_from={} date={}: {}",
+            report.report_id, report.domain, report.header_from, report.report_date, report.status
+        );
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "INSERT INTO dmarc_sent_reports (domain, header_from, report_date, report_id, rua,
+                    message_count, status, created_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+             ON CONFLICT (domain, header_from, report_date) DO NOTHING",
+            &[
+                &report.domain,
+                &report.header_from,
+                &report.report_date,
+                &report.report_id,
+                &report.rua,
+                &report.message_count,
+                &report.status,
+                &now(),
+            ],
+        ) {
+            error!("[db] failed to record sent dmarc report: {}", e);
+        }
+    }
+
+    /// Drop recorded results older than `before` (a `YYYY-MM-DD` date).
+    pub fn prune_dmarc_auth_results(&self, before: &str) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        match conn.execute(
+            "DELETE FROM dmarc_auth_results WHERE report_date < $1",
+            &[&before],
+        ) {
+            Ok(n) if n > 0 => info!("[db] pruned {} dmarc auth results before {}", n, before),
+            Ok(_) => {}
+            Err(e) => error!("[db] failed to prune dmarc auth results: {}", e),
+        }
+    }
   
     // ── Abuse inbox methods ──
 
     pub fn list_abuse_inboxes(&self) -> Vec<AbuseInbox> {
         debug!("[db] listing abuse inboxes");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT ai.id, ai.account_id, ai.label, ai.created_at, a.username, d.domain
@@ -3057,7 +6259,7 @@ impl Database {
 
     pub fn get_abuse_inbox(&self, id: i64) -> Option<AbuseInbox> {
         debug!("[db] getting abuse inbox id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT ai.id, ai.account_id, ai.label, ai.created_at, a.username, d.domain
              FROM abuse_inboxes ai
@@ -3080,7 +6282,7 @@ impl Database {
 
     pub fn get_abuse_inbox_by_domain_id(&self, domain_id: i64) -> Option<AbuseInbox> {
         debug!("[db] getting abuse inbox for domain_id={}", domain_id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT ai.id, ai.account_id, ai.label, ai.created_at, a.username, d.domain
              FROM abuse_inboxes ai
@@ -3104,7 +6306,9 @@ impl Database {
 
     pub fn create_abuse_inbox(&self, account_id: i64, label: &str) -> Result<i64, String> {
         info!("[db] creating abuse inbox account_id={}", account_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         conn.query_one(
             "INSERT INTO abuse_inboxes (account_id, label, created_at)
@@ -3120,7 +6324,9 @@ impl Database {
 
     pub fn delete_abuse_inbox(&self, id: i64) {
         warn!("[db] deleting abuse inbox id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM abuse_inboxes WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -3130,7 +6336,9 @@ impl Database {
 
     pub fn list_bounce_inboxes(&self) -> Vec<BounceInbox> {
         debug!("[db] listing bounce inboxes");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT bi.id, bi.account_id, bi.label, bi.created_at, a.username, d.domain
@@ -3158,7 +6366,7 @@ impl Database {
 
     pub fn get_bounce_inbox(&self, id: i64) -> Option<BounceInbox> {
         debug!("[db] getting bounce inbox id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT bi.id, bi.account_id, bi.label, bi.created_at, a.username, d.domain
              FROM bounce_inboxes bi
@@ -3181,7 +6389,7 @@ impl Database {
 
     pub fn get_bounce_inbox_by_domain_id(&self, domain_id: i64) -> Option<BounceInbox> {
         debug!("[db] getting bounce inbox for domain_id={}", domain_id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT bi.id, bi.account_id, bi.label, bi.created_at, a.username, d.domain
              FROM bounce_inboxes bi
@@ -3205,7 +6413,9 @@ impl Database {
 
     pub fn create_bounce_inbox(&self, account_id: i64, label: &str) -> Result<i64, String> {
         info!("[db] creating bounce inbox account_id={}", account_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         conn.query_one(
             "INSERT INTO bounce_inboxes (account_id, label, created_at)
@@ -3221,7 +6431,9 @@ impl Database {
 
     pub fn delete_bounce_inbox(&self, id: i64) {
         warn!("[db] deleting bounce inbox id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM bounce_inboxes WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -3231,11 +6443,14 @@ impl Database {
 
     pub fn list_outbound_relays(&self) -> Vec<OutboundRelay> {
         debug!("[db] listing outbound relays");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
-                "SELECT id, name, host, port, auth_type, username, password, active
-                 FROM outbound_relays ORDER BY name",
+                "SELECT id, name, host, port, auth_type, username, password, active, tls_mode,
+                        priority, last_ok, last_error, last_checked
+                 FROM outbound_relays ORDER BY priority, name",
                 &[],
             )
             .unwrap_or_else(|e| {
@@ -3252,15 +6467,21 @@ impl Database {
                 username: row.get(5),
                 password: row.get(6),
                 active: row.get(7),
+                tls_mode: row.get(8),
+                priority: row.get(9),
+                last_ok: row.get(10),
+                last_error: row.get(11),
+                last_checked: row.get(12),
             })
             .collect()
     }
 
     pub fn get_outbound_relay(&self, id: i64) -> Option<OutboundRelay> {
         debug!("[db] getting outbound relay id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
-            "SELECT id, name, host, port, auth_type, username, password, active
+            "SELECT id, name, host, port, auth_type, username, password, active, tls_mode,
+                    priority, last_ok, last_error, last_checked
              FROM outbound_relays WHERE id = $1",
             &[&id],
         )
@@ -3275,9 +6496,15 @@ impl Database {
             username: row.get(5),
             password: row.get(6),
             active: row.get(7),
+            tls_mode: row.get(8),
+            priority: row.get(9),
+            last_ok: row.get(10),
+            last_error: row.get(11),
+            last_checked: row.get(12),
         })
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn create_outbound_relay(
         &self,
         name: &str,
@@ -3286,19 +6513,23 @@ impl Database {
         auth_type: &str,
         username: Option<&str>,
         password: Option<&str>,
+        tls_mode: &str,
+        priority: i32,
     ) -> Result<i64, String> {
         info!(
-            "[db] creating outbound relay name={} host={}:{}",
-            name, host, port
+            "[db] creating outbound relay name={} host={}:{} tls={} priority={}",
+            name, host, port, tls_mode, priority
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         let row = conn
             .query_one(
-                "INSERT INTO outbound_relays (name, host, port, auth_type, username, password, created_at, updated_at)
-                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                "INSERT INTO outbound_relays (name, host, port, auth_type, username, password, tls_mode, priority, created_at, updated_at)
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
                  RETURNING id",
-                &[&name, &host, &port, &auth_type, &username, &password, &ts, &ts],
+                &[&name, &host, &port, &auth_type, &username, &password, &tls_mode, &priority, &ts, &ts],
             )
             .map_err(|e| {
                 error!("[db] failed to create outbound relay {}: {}", name, e);
@@ -3309,6 +6540,7 @@ impl Database {
         Ok(id)
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn update_outbound_relay(
         &self,
         id: i64,
@@ -3318,26 +6550,56 @@ impl Database {
         auth_type: &str,
         username: Option<&str>,
         password: Option<&str>,
+        tls_mode: &str,
+        priority: i32,
         active: bool,
     ) {
         info!(
-            "[db] updating outbound relay id={} name={} host={}:{} active={}",
-            id, name, host, port, active
+            "[db] updating outbound relay id={} name={} host={}:{} tls={} priority={} active={}",
+            id, name, host, port, tls_mode, priority, active
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE outbound_relays
-             SET name = $1, host = $2, port = $3, auth_type = $4, username = $5, password = $6, active = $7, updated_at = $8
-             WHERE id = $9",
-            &[&name, &host, &port, &auth_type, &username, &password, &active, &now(), &id],
+             SET name = $1, host = $2, port = $3, auth_type = $4, username = $5, password = $6,
+                 tls_mode = $7, priority = $8, active = $9, updated_at = $10
+             WHERE id = $11",
+            &[&name, &host, &port, &auth_type, &username, &password, &tls_mode, &priority, &active, &now(), &id],
         ) {
             error!("[db] failed to execute query: {}", e);
         }
     }
 
+    /// Record the outcome of a reachability probe: `error` is `None` when the
+    /// relay answered.
+    pub fn record_relay_probe(&self, id: i64, error: Option<&str>) {
+        debug!("[db] recording probe of outbound relay id={} ok={}", id, error.is_none());
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        let ts = now();
+        let result = match error {
+            None => conn.execute(
+                "UPDATE outbound_relays SET last_ok = $1, last_error = NULL, last_checked = $1 WHERE id = $2",
+                &[&ts, &id],
+            ),
+            Some(e) => conn.execute(
+                "UPDATE outbound_relays SET last_error = $1, last_checked = $2 WHERE id = $3",
+                &[&e, &ts, &id],
+            ),
+        };
+        if let Err(e) = result {
+            error!("[db] failed to record relay probe id={}: {}", id, e);
+        }
+    }
+
     pub fn delete_outbound_relay(&self, id: i64) {
         warn!("[db] deleting outbound relay id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM outbound_relays WHERE id = $1", &[&id]) {
             error!("[db] failed to execute query: {}", e);
         }
@@ -3345,7 +6607,9 @@ impl Database {
 
     pub fn list_relay_assignments(&self, relay_id: i64) -> Vec<OutboundRelayAssignment> {
         debug!("[db] listing assignments for relay id={}", relay_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT a.id, a.relay_id, a.assignment_type, a.pattern, r.name
@@ -3380,7 +6644,9 @@ impl Database {
             "[db] creating relay assignment relay_id={} type={} pattern={}",
             relay_id, assignment_type, pattern
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let row = conn
             .query_one(
                 "INSERT INTO outbound_relay_assignments (relay_id, assignment_type, pattern, created_at)
@@ -3399,7 +6665,9 @@ impl Database {
 
     pub fn delete_relay_assignment(&self, id: i64) {
         warn!("[db] deleting relay assignment id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "DELETE FROM outbound_relay_assignments WHERE id = $1",
             &[&id],
@@ -3412,7 +6680,9 @@ impl Database {
 
     pub fn list_webdav_files(&self) -> Vec<WebDavFile> {
         debug!("[db] listing all webdav files");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, account_id, owner, filename, content_type, size, token, created_at, updated_at
@@ -3440,7 +6710,9 @@ impl Database {
 
     pub fn list_webdav_files_for_owner(&self, owner: &str) -> Vec<WebDavFile> {
         debug!("[db] listing webdav files for owner={}", owner);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, account_id, owner, filename, content_type, size, token, created_at, updated_at
@@ -3468,7 +6740,7 @@ impl Database {
 
     pub fn get_webdav_file_by_token(&self, token: &str) -> Option<WebDavFile> {
         debug!("[db] getting webdav file by token={}", token);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, account_id, owner, filename, content_type, size, token, created_at, updated_at
              FROM webdav_files WHERE token = $1",
@@ -3491,7 +6763,7 @@ impl Database {
 
     pub fn get_webdav_file_by_owner_and_name(&self, owner: &str, filename: &str) -> Option<WebDavFile> {
         debug!("[db] getting webdav file owner={} filename={}", owner, filename);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, account_id, owner, filename, content_type, size, token, created_at, updated_at
              FROM webdav_files WHERE owner = $1 AND filename = $2",
@@ -3525,7 +6797,9 @@ impl Database {
             "[db] upserting webdav file owner={} filename={} size={}",
             owner, filename, size
         );
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         let row = conn
             .query_one(
@@ -3547,7 +6821,7 @@ impl Database {
 
     pub fn delete_webdav_file(&self, id: i64) -> Option<WebDavFile> {
         warn!("[db] deleting webdav file id={}", id);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         let file = conn
             .query_opt(
                 "DELETE FROM webdav_files WHERE id = $1
@@ -3572,7 +6846,7 @@ impl Database {
 
     pub fn delete_webdav_file_by_owner_and_name(&self, owner: &str, filename: &str) -> Option<WebDavFile> {
         warn!("[db] deleting webdav file owner={} filename={}", owner, filename);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "DELETE FROM webdav_files WHERE owner = $1 AND filename = $2
              RETURNING id, account_id, owner, filename, content_type, size, token, created_at, updated_at",
@@ -3594,15 +6868,51 @@ impl Database {
     }
 
     pub fn count_webdav_usage_for_owner(&self, owner: &str) -> i64 {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
         conn.query_one(
-            "SELECT COALESCE(SUM(size), 0) FROM webdav_files WHERE owner = $1",
+            "SELECT COALESCE(SUM(size), 0)::BIGINT FROM webdav_files WHERE lower(owner) = lower($1)",
             &[&owner],
         )
         .map(|row| row.get(0))
         .unwrap_or(0)
     }
 
+    /// Bytes stored by all WebDAV users together.
+    pub fn count_webdav_usage_total(&self) -> i64 {
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
+        conn.query_one("SELECT COALESCE(SUM(size), 0)::BIGINT FROM webdav_files", &[])
+            .map(|row| row.get(0))
+            .unwrap_or(0)
+    }
+
+    /// Files and bytes stored by each WebDAV user, largest first.
+    pub fn webdav_usage_by_owner(&self) -> Vec<WebDavUsage> {
+        debug!("[db] summing webdav usage per owner");
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            "SELECT lower(owner), COUNT(*), COALESCE(SUM(size), 0)::BIGINT
+             FROM webdav_files GROUP BY lower(owner) ORDER BY 3 DESC, 1",
+            &[],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to sum webdav usage: {}", e);
+            Vec::new()
+        })
+        .iter()
+        .map(|row| WebDavUsage {
+            owner: row.get(0),
+            files: row.get(1),
+            bytes: row.get(2),
+        })
+        .collect()
+    }
+
     /// Returns the account_id and password_hash for an email address used for WebDAV auth.
     pub fn get_account_for_webdav_auth(&self, email: &str) -> Option<(i64, String)> {
         debug!("[db] webdav auth lookup for email={}", email);
@@ -3612,12 +6922,13 @@ impl Database {
         }
         let username = parts[0];
         let domain = parts[1];
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT a.id, a.password_hash
              FROM accounts a
              JOIN domains d ON a.domain_id = d.id
-             WHERE a.username = $1 AND d.domain = $2 AND a.active = TRUE AND d.active = TRUE",
+             WHERE a.username = $1 AND d.domain = $2 AND a.active = TRUE AND d.active = TRUE
+               AND a.deleted_at IS NULL AND d.deleted_at IS NULL",
             &[&username, &domain],
         )
         .ok()
@@ -3625,20 +6936,24 @@ impl Database {
         .map(|row| (row.get(0), row.get(1)))
     }
 
-    /// Returns all active relay assignments joined with relay info for config generation.
+    /// Returns all active relay assignments joined with relay info for config
+    /// generation, with the relays of each pattern in failover order.
     pub fn get_active_relay_assignments_with_relay(
         &self,
     ) -> Vec<(OutboundRelay, OutboundRelayAssignment)> {
         debug!("[db] getting active relay assignments with relay info");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT r.id, r.name, r.host, r.port, r.auth_type, r.username, r.password, r.active,
-                        a.id, a.relay_id, a.assignment_type, a.pattern
+                        a.id, a.relay_id, a.assignment_type, a.pattern, r.tls_mode,
+                        r.priority, r.last_ok, r.last_error, r.last_checked
                  FROM outbound_relay_assignments a
                  JOIN outbound_relays r ON a.relay_id = r.id
                  WHERE r.active = TRUE
-                 ORDER BY a.assignment_type, a.pattern",
+                 ORDER BY a.assignment_type, a.pattern, r.priority, r.id",
                 &[],
             )
             .unwrap_or_else(|e| {
@@ -3656,6 +6971,11 @@ impl Database {
                     username: row.get(5),
                     password: row.get(6),
                     active: row.get(7),
+                    tls_mode: row.get(12),
+                    priority: row.get(13),
+                    last_ok: row.get(14),
+                    last_error: row.get(15),
+                    last_checked: row.get(16),
                 };
                 let assignment = OutboundRelayAssignment {
                     id: row.get(8),
@@ -3679,7 +6999,10 @@ impl Database {
 
     pub fn create_jmap_token(&self, account_id: i64, token: &str, created_at: &str) {
         debug!("[db] creating JMAP token for account_id={}", account_id);
-        if let Err(e) = self.conn().execute(
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
             "INSERT INTO jmap_tokens (account_id, token, created_at) VALUES ($1, $2, $3)",
             &[&account_id, &token, &created_at],
         ) {
@@ -3689,7 +7012,7 @@ impl Database {
 
     pub fn verify_jmap_token(&self, token: &str) -> Option<Account> {
         debug!("[db] verifying JMAP token");
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT a.id, a.domain_id, a.username, a.password_hash, a.name, a.active, a.quota, d.domain
              FROM jmap_tokens t
@@ -3714,7 +7037,9 @@ impl Database {
     }
 
     pub fn get_jmap_state(&self, account_id: i64) -> (String, String) {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return ("1".to_string(), "{}".to_string());
+        };
         match conn.query_opt(
             "SELECT state, snapshot FROM jmap_state WHERE account_id = $1",
             &[&account_id],
@@ -3725,7 +7050,10 @@ impl Database {
     }
 
     pub fn update_jmap_state(&self, account_id: i64, state: &str, snapshot: &str) {
-        if let Err(e) = self.conn().execute(
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
             "INSERT INTO jmap_state (account_id, state, snapshot)
              VALUES ($1, $2, $3)
              ON CONFLICT (account_id) DO UPDATE SET state = $2, snapshot = $3",
@@ -3743,12 +7071,13 @@ impl Database {
         }
         let username = parts[0];
         let domain = parts[1];
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT a.id, a.domain_id, a.username, a.password_hash, a.name, a.active, a.quota, d.domain
              FROM accounts a
              JOIN domains d ON a.domain_id = d.id
-             WHERE LOWER(a.username) = LOWER($1) AND LOWER(d.domain) = LOWER($2)",
+             WHERE LOWER(a.username) = LOWER($1) AND LOWER(d.domain) = LOWER($2)
+               AND a.deleted_at IS NULL AND d.deleted_at IS NULL",
             &[&username, &domain],
         )
         .ok()
@@ -3770,7 +7099,9 @@ impl Database {
 
     pub fn list_all_caldav_calendars(&self) -> Vec<CalDavCalendarWithAccount> {
         debug!("[db] listing all CalDAV calendars");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT c.id, c.account_id, c.slug, c.display_name, c.description, c.color, c.ctag,
@@ -3805,7 +7136,9 @@ impl Database {
 
     pub fn list_caldav_calendars_for_account(&self, account_id: i64) -> Vec<CalDavCalendar> {
         debug!("[db] listing CalDAV calendars for account_id={}", account_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, account_id, slug, display_name, description, color, ctag, created_at
@@ -3832,7 +7165,7 @@ impl Database {
 
     pub fn get_caldav_calendar_by_slug(&self, account_id: i64, slug: &str) -> Option<CalDavCalendar> {
         debug!("[db] getting CalDAV calendar account_id={} slug={}", account_id, slug);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, account_id, slug, display_name, description, color, ctag, created_at
              FROM caldav_calendars WHERE account_id = $1 AND slug = $2",
@@ -3861,7 +7194,9 @@ impl Database {
         color: &str,
     ) -> Result<i64, String> {
         info!("[db] creating CalDAV calendar account_id={} slug={}", account_id, slug);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         let ctag = uuid::Uuid::new_v4().to_string();
         let row = conn
@@ -3882,7 +7217,9 @@ impl Database {
 
     pub fn update_caldav_calendar_ctag(&self, id: i64) {
         let ctag = uuid::Uuid::new_v4().to_string();
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "UPDATE caldav_calendars SET ctag = $1, updated_at = $2 WHERE id = $3",
             &[&ctag, &now(), &id],
@@ -3893,7 +7230,9 @@ impl Database {
 
     pub fn delete_caldav_calendar(&self, id: i64) {
         warn!("[db] deleting CalDAV calendar id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM caldav_calendars WHERE id = $1", &[&id]) {
             error!("[db] failed to delete CalDAV calendar: {}", e);
         }
@@ -3901,7 +7240,9 @@ impl Database {
 
     pub fn list_caldav_objects(&self, calendar_id: i64) -> Vec<CalDavObject> {
         debug!("[db] listing CalDAV objects for calendar_id={}", calendar_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, calendar_id, uid, filename, etag, data, created_at
@@ -3927,7 +7268,7 @@ impl Database {
 
     pub fn get_caldav_object_by_filename(&self, calendar_id: i64, filename: &str) -> Option<CalDavObject> {
         debug!("[db] getting CalDAV object calendar_id={} filename={}", calendar_id, filename);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, calendar_id, uid, filename, etag, data, created_at
              FROM caldav_objects WHERE calendar_id = $1 AND filename = $2",
@@ -3955,7 +7296,9 @@ impl Database {
         data: &str,
     ) -> Result<i64, String> {
         info!("[db] upserting CalDAV object calendar_id={} filename={}", calendar_id, filename);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ts = now();
         let row = conn
             .query_one(
@@ -3975,7 +7318,9 @@ impl Database {
 
     pub fn delete_caldav_object(&self, id: i64) {
         warn!("[db] deleting CalDAV object id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM caldav_objects WHERE id = $1", &[&id]) {
             error!("[db] failed to delete CalDAV object: {}", e);
         }
@@ -3983,7 +7328,9 @@ impl Database {
 
     pub fn delete_caldav_object_by_filename(&self, calendar_id: i64, filename: &str) {
         warn!("[db] deleting CalDAV object calendar_id={} filename={}", calendar_id, filename);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "DELETE FROM caldav_objects WHERE calendar_id = $1 AND filename = $2",
             &[&calendar_id, &filename],
@@ -3996,7 +7343,9 @@ impl Database {
 
     pub fn list_all_carddav_addressbooks(&self) -> Vec<CardDavAddressBookWithAccount> {
         debug!("[db] listing all CardDAV address books");
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT ab.id, ab.account_id, ab.slug, ab.display_name, ab.description, ab.ctag,
@@ -4030,7 +7379,9 @@ impl Database {
 
     pub fn list_carddav_addressbooks_for_account(&self, account_id: i64) -> Vec<CardDavAddressBook> {
         debug!("[db] listing CardDAV address books for account_id={}", account_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, account_id, slug, display_name, description, ctag, created_at
@@ -4056,7 +7407,7 @@ impl Database {
 
     pub fn get_carddav_addressbook_by_slug(&self, account_id: i64, slug: &str) -> Option<CardDavAddressBook> {
         debug!("[db] getting CardDAV address book account_id={} slug={}", account_id, slug);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, account_id, slug, display_name, description, ctag, created_at
              FROM carddav_addressbooks WHERE account_id = $1 AND slug = $2",
@@ -4082,7 +7433,9 @@ impl Database {
         description: &str,
     ) -> Result<i64, String> {
         info!("[db] creating CardDAV address book account_id={} slug={}", account_id, slug);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         let ctag = uuid::Uuid::new_v4().to_string();
         conn.query_one(
             "INSERT INTO carddav_addressbooks (account_id, slug, display_name, description, ctag, created_at, updated_at)
@@ -4101,7 +7454,9 @@ impl Database {
     }
 
     pub fn update_carddav_addressbook_ctag(&self, id: i64) {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         let ctag = uuid::Uuid::new_v4().to_string();
         if let Err(e) = conn.execute(
             "UPDATE carddav_addressbooks SET ctag = $1, updated_at = $2 WHERE id = $3",
@@ -4113,7 +7468,9 @@ impl Database {
 
     pub fn delete_carddav_addressbook(&self, id: i64) {
         warn!("[db] deleting CardDAV address book id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM carddav_addressbooks WHERE id = $1", &[&id]) {
             error!("[db] failed to delete CardDAV address book: {}", e);
         }
@@ -4121,7 +7478,9 @@ impl Database {
 
     pub fn list_carddav_objects(&self, addressbook_id: i64) -> Vec<CardDavObject> {
         debug!("[db] listing CardDAV objects for addressbook_id={}", addressbook_id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, addressbook_id, uid, filename, etag, data, created_at
@@ -4147,7 +7506,7 @@ impl Database {
 
     pub fn get_carddav_object_by_filename(&self, addressbook_id: i64, filename: &str) -> Option<CardDavObject> {
         debug!("[db] getting CardDAV object addressbook_id={} filename={}", addressbook_id, filename);
-        let mut conn = self.conn();
+        let mut conn = self.conn()?;
         conn.query_opt(
             "SELECT id, addressbook_id, uid, filename, etag, data, created_at
              FROM carddav_objects WHERE addressbook_id = $1 AND filename = $2",
@@ -4174,7 +7533,9 @@ impl Database {
         data: &str,
     ) -> Result<(), String> {
         info!("[db] upserting CardDAV object addressbook_id={} filename={}", addressbook_id, filename);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
         conn.execute(
             "INSERT INTO carddav_objects (addressbook_id, uid, filename, etag, data, created_at, updated_at)
              VALUES ($1, $2, $3, $4, $5, $6, $6)
@@ -4191,7 +7552,9 @@ impl Database {
 
     pub fn delete_carddav_object(&self, id: i64) {
         warn!("[db] deleting CardDAV object id={}", id);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute("DELETE FROM carddav_objects WHERE id = $1", &[&id]) {
             error!("[db] failed to delete CardDAV object: {}", e);
         }
@@ -4199,7 +7562,9 @@ impl Database {
 
     pub fn delete_carddav_object_by_filename(&self, addressbook_id: i64, filename: &str) {
         warn!("[db] deleting CardDAV object addressbook_id={} filename={}", addressbook_id, filename);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "DELETE FROM carddav_objects WHERE addressbook_id = $1 AND filename = $2",
             &[&addressbook_id, &filename],
@@ -4220,7 +7585,9 @@ impl Database {
         request_body: Option<&str>,
     ) {
         debug!("[db] logging mcp call method={}", method);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
         if let Err(e) = conn.execute(
             "INSERT INTO mcp_logs (method, tool, success, error, duration_ms, created_at, request_body)
              VALUES ($1, $2, $3, $4, $5, $6, $7)",
@@ -4231,7 +7598,9 @@ impl Database {
     }
 
     pub fn count_mcp_logs(&self) -> i64 {
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
         conn.query_one("SELECT COUNT(*) FROM mcp_logs", &[])
             .map(|row| row.get(0))
             .unwrap_or(0)
@@ -4239,7 +7608,9 @@ impl Database {
 
     pub fn list_mcp_logs(&self, limit: i64, offset: i64) -> Vec<McpLog> {
         debug!("[db] listing mcp logs limit={} offset={}", limit, offset);
-        let mut conn = self.conn();
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
         let rows = conn
             .query(
                 "SELECT id, method, tool, success, error, duration_ms, created_at, request_body
@@ -4270,9 +7641,63 @@ impl Database {
 #[cfg(test)]
 mod tests {
     use super::{
-        evaluate_condition, evaluate_rule, matches_from_pattern, minimal_runtime_bootstrap_sql,
-        TrackingCondition, TrackingRule,
+        connection_config, contains_pattern, evaluate_condition, evaluate_rule,
+        matches_from_pattern, minimal_runtime_bootstrap_sql, TrackingCondition, TrackingRule,
     };
+    use std::time::Duration;
+
+    #[test]
+    fn connection_config_adds_statement_timeout_option() {
+        let config = connection_config(
+            "postgres://u:p@localhost/db?options=-c%20search_path%3Dmail",
+            Duration::from_secs(3),
+            Duration::from_millis(1500),
+        )
+        .unwrap();
+        assert_eq!(config.get_connect_timeout(), Some(&Duration::from_secs(3)));
+        assert_eq!(
+            config.get_options(),
+            Some("-c search_path=mail -c statement_timeout=1500")
+        );
+
+        let config = connection_config(
+            "postgres://u:p@localhost/db",
+            Duration::from_secs(3),
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert_eq!(config.get_options(), None);
+    }
+
+    /// Needs a reachable server: run with `TEST_DATABASE_URL` set and
+    /// `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn statement_timeout_aborts_slow_query() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let mut client =
+            connection_config(&url, Duration::from_secs(5), Duration::from_millis(200))
+                .unwrap()
+                .connect(postgres::NoTls)
+                .unwrap();
+
+        let err = client.batch_execute("SELECT pg_sleep(2)").unwrap_err();
+        assert_eq!(err.code(), Some(&postgres::error::SqlState::QUERY_CANCELED));
+
+        client.batch_execute("SET statement_timeout = 0").unwrap();
+        client.batch_execute("SELECT pg_sleep(0.5)").unwrap();
+    }
+
+    #[test]
+    fn contains_pattern_escapes_like_wildcards() {
+        assert_eq!(contains_pattern(None), None);
+        assert_eq!(contains_pattern(Some("  ")), None);
+        assert_eq!(contains_pattern(Some(" jane ")).as_deref(), Some("%jane%"));
+        assert_eq!(
+            contains_pattern(Some("50%_off\\")).as_deref(),
+            Some("%50\\%\\_off\\\\%")
+        );
+    }
 
     #[test]
     fn minimal_runtime_bootstrap_includes_node_state_table() {
@@ -0,0 +1,195 @@
+//! Reading and writing the tables in a configuration backup, see
+//! [`crate::backup`].
+//!
+//! Rows travel as JSON objects keyed by column name, read with `json_agg`
+//! and written back with the same upsert replication uses.  Columns a row
+//! lacks keep their defaults, so a backup made before a migration still
+//! restores after it.
+
+use log::info;
+use postgres::IsolationLevel;
+use serde_json::Value;
+
+use super::replication::{sync_id_sequence, table_columns, upsert_statement};
+use super::{Database, NO_CONNECTION};
+
+/// A table in the backup.
+struct Table {
+    name: &'static str,
+    /// Columns a restored row is matched on.
+    keys: &'static [&'static str],
+    /// Columns that are not restored; the local row keeps its own.
+    skip: &'static [&'static str],
+    /// Rows that are left out of the backup.
+    filter: &'static str,
+}
+
+const fn table(name: &'static str) -> Table {
+    Table {
+        name,
+        keys: &["id"],
+        skip: &[],
+        filter: "TRUE",
+    }
+}
+
+/// Parents before children, the order rows are restored in.
+const TABLES: &[Table] = &[
+    table("domains"),
+    table("accounts"),
+    table("aliases"),
+    table("forwardings"),
+    Table {
+        keys: &["domain_id", "role"],
+        ..table("domain_reserved_mailboxes")
+    },
+    Table {
+        keys: &["domain_id"],
+        ..table("registration_settings")
+    },
+    table("registration_invites"),
+    table("delivery_rules"),
+    table("outbound_relays"),
+    table("outbound_relay_assignments"),
+    table("rate_limit_rules"),
+    table("footer_rules"),
+    table("footer_patterns"),
+    table("tracking_rules"),
+    table("tracking_patterns"),
+    table("unsubscribe_rules"),
+    table("unsubscribe_patterns"),
+    // Every install seeds these, under ids that need not match.
+    Table {
+        keys: &["hostname"],
+        skip: &["id"],
+        ..table("spambl_lists")
+    },
+    Table {
+        keys: &["service"],
+        skip: &["id"],
+        ..table("fail2ban_settings")
+    },
+    table("fail2ban_rules"),
+    table("fail2ban_whitelist"),
+    table("fail2ban_blacklist"),
+    // Restoring must not lock or unlock the admin panel.
+    Table {
+        keys: &["key"],
+        filter: "key NOT LIKE 'replication\\_%' AND key <> 'maintenance_mode'",
+        ..table("settings")
+    },
+];
+
+/// Rows restored into one table.
+pub struct RestoredTable {
+    pub table: &'static str,
+    /// Rows inserted or changed; identical rows are not counted.
+    pub changed: u64,
+}
+
+impl Database {
+    /// The latest migration this database has applied.
+    pub fn schema_version(&self) -> String {
+        self.conn()
+            .and_then(|mut conn| {
+                conn.query_one("SELECT COALESCE(MAX(name), '') FROM _migrations", &[])
+                    .ok()
+            })
+            .map(|row| row.get(0))
+            .unwrap_or_default()
+    }
+
+    /// Every backed-up table with its rows, read in one snapshot.
+    pub fn export_backup_tables(&self) -> Result<Vec<(&'static str, Vec<Value>)>, String> {
+        let db = self.without_statement_timeout();
+        let Some(mut conn) = db.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn
+            .build_transaction()
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()
+            .map_err(|e| e.to_string())?;
+        let mut tables = Vec::with_capacity(TABLES.len());
+        for table in TABLES {
+            let json: String = tx
+                .query_one(
+                    &format!(
+                        "SELECT COALESCE(json_agg(t ORDER BY {}), '[]')::text FROM {} t WHERE {}",
+                        table.keys.join(", "),
+                        table.name,
+                        table.filter
+                    ),
+                    &[],
+                )
+                .map_err(|e| format!("{}: {}", table.name, e))?
+                .get(0);
+            let rows: Vec<Value> =
+                serde_json::from_str(&json).map_err(|e| format!("{}: {}", table.name, e))?;
+            tables.push((table.name, rows));
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        Ok(tables)
+    }
+
+    /// Upsert the rows of a backup in one transaction; nothing is deleted.
+    /// Tables the backup does not know are left alone, and tables this
+    /// version does not know are ignored.
+    pub fn restore_backup_tables(
+        &self,
+        tables: &serde_json::Map<String, Value>,
+    ) -> Result<Vec<RestoredTable>, String> {
+        let db = self.without_statement_timeout();
+        let Some(mut conn) = db.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        let mut restored = Vec::new();
+        for table in TABLES {
+            let Some(rows) = tables.get(table.name) else {
+                continue;
+            };
+            let rows = rows
+                .as_array()
+                .ok_or_else(|| format!("{}: expected a list of rows", table.name))?;
+            let mut report = RestoredTable {
+                table: table.name,
+                changed: 0,
+            };
+            if let Some(first) = rows.first() {
+                let present = first
+                    .as_object()
+                    .ok_or_else(|| format!("{}: rows must be objects", table.name))?;
+                let columns: Vec<String> = table_columns(&mut tx, table.name)?
+                    .into_iter()
+                    .filter(|c| present.contains_key(c) && !table.skip.contains(&c.as_str()))
+                    .collect();
+                if let Some(key) = table.keys.iter().find(|k| !columns.iter().any(|c| c == *k)) {
+                    return Err(format!("{}: rows have no {} column", table.name, key));
+                }
+                let upsert = tx
+                    .prepare(&upsert_statement(table.name, table.keys, &columns))
+                    .map_err(|e| format!("{}: {}", table.name, e))?;
+                for row in rows {
+                    if !row.is_object() {
+                        return Err(format!("{}: rows must be objects", table.name));
+                    }
+                    report.changed += tx
+                        .execute(&upsert, &[&row.to_string()])
+                        .map_err(|e| format!("{}: {}", table.name, e))?;
+                }
+                if table.keys == ["id"] {
+                    sync_id_sequence(&mut tx, table.name)?;
+                }
+            }
+            restored.push(report);
+        }
+        tx.commit().map_err(|e| e.to_string())?;
+        info!(
+            "[db] restored backup: {} rows changed",
+            restored.iter().map(|t| t.changed).sum::<u64>()
+        );
+        Ok(restored)
+    }
+}
@@ -0,0 +1,255 @@
+//! Forwarding loops: aliases and forwardings that send mail round in a
+//! circle (`a → b`, `b → a`) or hand it along a long chain.
+//!
+//! The graph is the `virtual_aliases` map the server generates, so catch-alls,
+//! shadowed aliases and mailbox identity entries count exactly as Postfix sees
+//! them.  Forwardings that keep a copy are delivered through
+//! `recipient_bcc_maps`, which Postfix does not expand again, and are left out.
+
+use std::collections::{HashMap, HashSet};
+
+use super::{Alias, Database, Forwarding};
+
+/// Longest route through aliases and forwardings, in hops, that
+/// [`Database::detect_forwarding_loops`] accepts.
+pub const MAX_FORWARDING_DEPTH: usize = 5;
+
+/// A route mail takes through aliases and forwardings that never ends, or
+/// that is longer than [`MAX_FORWARDING_DEPTH`] hops.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForwardingLoop {
+    /// Addresses in the order mail follows them.  A cycle ends with the
+    /// address it returns to.
+    pub path: Vec<String>,
+    pub cycle: bool,
+}
+
+impl ForwardingLoop {
+    pub fn route(&self) -> String {
+        self.path.join(" → ")
+    }
+}
+
+/// Where each `virtual_aliases` key sends mail.  Self-references are
+/// dropped: Postfix delivers those to the mailbox.
+fn forwarding_graph(entries: &[(String, String)]) -> HashMap<String, Vec<String>> {
+    let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+    for (source, destination) in entries {
+        let source = source.trim().to_lowercase();
+        let targets = graph.entry(source.clone()).or_default();
+        for target in destination.split(|c: char| c == ',' || c.is_whitespace()) {
+            let target = target.to_lowercase();
+            if !target.is_empty() && target != source && !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    graph
+}
+
+/// The key Postfix finds for `address`, if any.
+fn lookup_key<'a>(graph: &'a HashMap<String, Vec<String>>, address: &str) -> Option<&'a String> {
+    crate::config::alias_lookup_keys(address)
+        .iter()
+        .find_map(|key| graph.get_key_value(key).map(|(k, _)| k))
+}
+
+/// Depth-first walk that records every cycle it closes and the longest route
+/// from each address.
+struct Walk<'a> {
+    graph: &'a HashMap<String, Vec<String>>,
+    /// Addresses on the route being followed.
+    stack: Vec<&'a str>,
+    /// Hops of the longest route from each address, with its next address.
+    longest: HashMap<&'a str, (usize, Option<&'a str>)>,
+    cycles: Vec<ForwardingLoop>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(graph: &'a HashMap<String, Vec<String>>) -> Self {
+        Walk {
+            graph,
+            stack: Vec::new(),
+            longest: HashMap::new(),
+            cycles: Vec::new(),
+        }
+    }
+
+    fn visit(&mut self, address: &'a str) {
+        if self.longest.contains_key(address) {
+            return;
+        }
+        let graph = self.graph;
+        let next_hops = lookup_key(graph, address)
+            .map(|key| graph[key].as_slice())
+            .unwrap_or_default();
+        self.stack.push(address);
+        let mut best = (0, None);
+        for next in next_hops {
+            if let Some(start) = self.stack.iter().position(|a| *a == next) {
+                let mut path: Vec<String> =
+                    self.stack[start..].iter().map(|a| a.to_string()).collect();
+                path.push(next.clone());
+                self.cycles.push(ForwardingLoop { path, cycle: true });
+                continue;
+            }
+            self.visit(next);
+            let hops = self.longest[next.as_str()].0 + 1;
+            if hops > best.0 {
+                best = (hops, Some(next.as_str()));
+            }
+        }
+        self.stack.pop();
+        self.longest.insert(address, best);
+    }
+
+    fn route_from(&self, address: &'a str) -> Vec<String> {
+        let mut path = vec![address.to_string()];
+        let mut at = address;
+        while let Some((_, Some(next))) = self.longest.get(at) {
+            path.push(next.to_string());
+            at = next;
+        }
+        path
+    }
+}
+
+/// Cycles in the `virtual_aliases` `entries`, and routes longer than
+/// `max_depth` hops from addresses nothing else forwards to.
+pub fn find_forwarding_loops(
+    entries: &[(String, String)],
+    max_depth: usize,
+) -> Vec<ForwardingLoop> {
+    let graph = forwarding_graph(entries);
+    let mut sources: Vec<&String> = graph.keys().collect();
+    sources.sort();
+    let mut walk = Walk::new(&graph);
+    for source in &sources {
+        walk.visit(source);
+    }
+    let reached: HashSet<&String> = graph
+        .values()
+        .flatten()
+        .filter_map(|target| lookup_key(&graph, target))
+        .collect();
+    let mut found = std::mem::take(&mut walk.cycles);
+    for source in sources {
+        if !reached.contains(source) && walk.longest[source.as_str()].0 > max_depth {
+            found.push(ForwardingLoop {
+                path: walk.route_from(source),
+                cycle: false,
+            });
+        }
+    }
+    found
+}
+
+/// The cycle in `entries` that leads mail for `source` back to it, if any.
+pub fn forwarding_cycle_through(
+    entries: &[(String, String)],
+    source: &str,
+) -> Option<ForwardingLoop> {
+    let graph = forwarding_graph(entries);
+    let source = source.trim().to_lowercase();
+    let mut walk = Walk::new(&graph);
+    walk.visit(&source);
+    walk.cycles.into_iter().find(|l| l.path[0] == source)
+}
+
+impl Database {
+    fn virtual_alias_entries(
+        &self,
+        aliases: &[Alias],
+        forwardings: &[Forwarding],
+    ) -> Vec<(String, String)> {
+        let accounts = self.list_all_accounts_with_domain();
+        crate::config::build_virtual_alias_entries(aliases, forwardings, &accounts)
+    }
+
+    /// Forwarding loops among the current aliases and forwardings.
+    pub fn detect_forwarding_loops(&self) -> Vec<ForwardingLoop> {
+        let entries = self.virtual_alias_entries(
+            &self.list_all_aliases_with_domain(),
+            &self.list_all_forwardings_with_domain(),
+        );
+        find_forwarding_loops(&entries, MAX_FORWARDING_DEPTH)
+    }
+
+    /// The cycle saving `alias` would leave mail in.  An alias with an
+    /// existing id replaces that alias.
+    pub fn alias_cycle(&self, alias: Alias) -> Option<ForwardingLoop> {
+        let source = crate::config::alias_key(&alias);
+        let mut aliases = self.list_all_aliases_with_domain();
+        aliases.retain(|a| a.id != alias.id);
+        aliases.push(alias);
+        let entries =
+            self.virtual_alias_entries(&aliases, &self.list_all_forwardings_with_domain());
+        forwarding_cycle_through(&entries, &source)
+    }
+
+    /// The cycle saving `forwarding` would leave mail in.  A forwarding with
+    /// an existing id replaces that forwarding.
+    pub fn forwarding_cycle(&self, forwarding: Forwarding) -> Option<ForwardingLoop> {
+        let source = forwarding.source.clone();
+        let mut forwardings = self.list_all_forwardings_with_domain();
+        forwardings.retain(|f| f.id != forwarding.id);
+        forwardings.push(forwarding);
+        let entries =
+            self.virtual_alias_entries(&self.list_all_aliases_with_domain(), &forwardings);
+        forwarding_cycle_through(&entries, &source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(s, d)| (s.to_string(), d.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn cycles_and_long_chains_are_reported() {
+        let looped = entries(&[
+            ("a@one.test", "b@two.test"),
+            ("b@two.test", "c@two.test"),
+            ("@two.test", "a@one.test"),
+            ("jane@one.test", "jane@one.test"),
+        ]);
+        assert_eq!(
+            find_forwarding_loops(&looped, MAX_FORWARDING_DEPTH),
+            vec![ForwardingLoop {
+                path: vec![
+                    "a@one.test".to_string(),
+                    "b@two.test".to_string(),
+                    "c@two.test".to_string(),
+                    "a@one.test".to_string(),
+                ],
+                cycle: true,
+            }]
+        );
+        let cycle = forwarding_cycle_through(&looped, "B@two.test").unwrap();
+        assert_eq!(
+            cycle.route(),
+            "b@two.test → c@two.test → a@one.test → b@two.test"
+        );
+        assert_eq!(forwarding_cycle_through(&looped, "jane@one.test"), None);
+
+        let chain = entries(&[
+            ("h1@x.test", "h2@x.test"),
+            ("h2@x.test", "h3@x.test, h3b@y.test"),
+            ("h3@x.test", "h4@x.test"),
+        ]);
+        assert!(find_forwarding_loops(&chain, 3).is_empty());
+        let long = find_forwarding_loops(&chain, 2);
+        assert_eq!(long.len(), 1);
+        assert!(!long[0].cycle);
+        assert_eq!(
+            long[0].route(),
+            "h1@x.test → h2@x.test → h3@x.test → h4@x.test"
+        );
+    }
+}
@@ -0,0 +1,241 @@
+//! Small blocking connection pool for the synchronous `postgres` client.
+//!
+//! Connections are opened lazily up to the configured size and handed out as
+//! [`PooledClient`] guards that return them to the pool on drop.  The pool
+//! never holds more connections than its size: when none is free by the
+//! checkout timeout, or none can be opened because the database is down, the
+//! checkout fails rather than blocking the caller indefinitely.
+
+use log::{debug, error, warn};
+use postgres::{Client, Config, NoTls};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+pub(super) struct Pool {
+    config: Config,
+    size: usize,
+    checkout_timeout: Duration,
+    state: Mutex<PoolState>,
+    available: Condvar,
+}
+
+struct PoolState {
+    idle: Vec<Client>,
+    /// Connections currently open, whether idle or checked out.
+    open: usize,
+}
+
+impl Pool {
+    /// Create a pool seeded with an already-open connection.
+    pub(super) fn new(config: Config, first: Client, size: usize, checkout_timeout: Duration) -> Self {
+        Pool {
+            config,
+            size: size.max(1),
+            checkout_timeout,
+            state: Mutex::new(PoolState {
+                idle: vec![first],
+                open: 1,
+            }),
+            available: Condvar::new(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, PoolState> {
+        self.state.lock().unwrap_or_else(|e| {
+            warn!("[db] pool mutex was poisoned, recovering");
+            e.into_inner()
+        })
+    }
+
+    fn connect(&self) -> Result<Client, postgres::Error> {
+        self.config.connect(NoTls)
+    }
+
+    /// Check out a connection, waiting for one to be returned if the pool is
+    /// at capacity.  Returns `None` once the checkout timeout passes, so the
+    /// caller can give up instead of waiting for the database to come back.
+    pub(super) fn get(&self) -> Option<PooledClient<'_>> {
+        let deadline = Instant::now() + self.checkout_timeout;
+        let mut state = self.lock();
+        loop {
+            while let Some(client) = state.idle.pop() {
+                if client.is_closed() {
+                    debug!("[db] discarding closed pooled connection");
+                    state.open -= 1;
+                    continue;
+                }
+                return Some(PooledClient::new(self, client));
+            }
+
+            if state.open < self.size {
+                state.open += 1;
+                drop(state);
+                match self.connect() {
+                    Ok(client) => {
+                        debug!("[db] opened pooled connection");
+                        return Some(PooledClient::new(self, client));
+                    }
+                    Err(e) => {
+                        error!("[db] failed to open pooled connection: {}", e);
+                        state = self.lock();
+                        state.open -= 1;
+                    }
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                warn!(
+                    "[db] no pooled connection available after {:?}, giving up",
+                    self.checkout_timeout
+                );
+                return None;
+            }
+            state = self
+                .available
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(|e| e.into_inner())
+                .0;
+        }
+    }
+
+    /// Check out an idle connection or open one, without waiting as
+    /// [`Pool::get`] does.  A connection opened beyond the pool size is
+    /// closed when it is returned.
+    pub(super) fn try_get(&self) -> Result<PooledClient<'_>, postgres::Error> {
+        let mut state = self.lock();
+        while let Some(client) = state.idle.pop() {
+            if client.is_closed() {
+                debug!("[db] discarding closed pooled connection");
+                state.open -= 1;
+                continue;
+            }
+            return Ok(PooledClient::new(self, client));
+        }
+        state.open += 1;
+        drop(state);
+        self.connect()
+            .map(|client| PooledClient::new(self, client))
+            .inspect_err(|_| self.lock().open -= 1)
+    }
+
+    /// Drop a connection that must not be handed out again.
+    fn discard(&self, client: Client) {
+        drop(client);
+        self.lock().open -= 1;
+        self.available.notify_one();
+    }
+
+    fn release(&self, client: Client) {
+        let mut state = self.lock();
+        if client.is_closed() || state.open > self.size {
+            state.open -= 1;
+        } else {
+            state.idle.push(client);
+        }
+        drop(state);
+        self.available.notify_one();
+    }
+}
+
+/// A checked-out connection; returned to its pool when dropped.
+pub(super) struct PooledClient<'a> {
+    pool: &'a Pool,
+    client: Option<Client>,
+    /// The session's statement timeout was lifted and must be restored
+    /// before the connection goes back to the pool.
+    reset_statement_timeout: bool,
+}
+
+impl<'a> PooledClient<'a> {
+    fn new(pool: &'a Pool, client: Client) -> Self {
+        PooledClient {
+            pool,
+            client: Some(client),
+            reset_statement_timeout: false,
+        }
+    }
+
+    /// Lift the statement timeout for as long as this connection is checked out.
+    pub(super) fn disable_statement_timeout(&mut self) {
+        match self.batch_execute("SET statement_timeout = 0") {
+            Ok(()) => self.reset_statement_timeout = true,
+            Err(e) => error!("[db] failed to disable statement timeout: {}", e),
+        }
+    }
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("pooled client already released")
+    }
+}
+
+impl DerefMut for PooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("pooled client already released")
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(mut client) = self.client.take() {
+            if self.reset_statement_timeout {
+                if let Err(e) = client.batch_execute("RESET statement_timeout") {
+                    error!("[db] failed to restore statement timeout, closing connection: {}", e);
+                    self.pool.discard(client);
+                    return;
+                }
+            }
+            self.pool.release(client);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs a reachable server: run with `TEST_DATABASE_URL` set and
+    /// `cargo test -- --ignored`.
+    #[test]
+    #[ignore]
+    fn checkout_gives_up_at_the_deadline_without_growing_the_pool() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let config = url.parse::<Config>().unwrap();
+        let first = config.connect(NoTls).unwrap();
+        let pool = Pool::new(config, first, 1, Duration::from_millis(200));
+
+        let held = pool.get().expect("the seeded connection is idle");
+        let started = Instant::now();
+        assert!(pool.get().is_none());
+        assert!(started.elapsed() >= Duration::from_millis(200));
+        assert_eq!(pool.lock().open, 1);
+
+        drop(held);
+        assert!(pool.get().is_some());
+    }
+
+    #[test]
+    #[ignore]
+    fn checkout_gives_up_when_the_database_is_unreachable() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let first = url.parse::<Config>().unwrap().connect(NoTls).unwrap();
+        let mut unreachable = Config::new();
+        unreachable
+            .host("127.0.0.1")
+            .port(1)
+            .user("postgres")
+            .connect_timeout(Duration::from_millis(200));
+        let pool = Pool::new(unreachable, first, 2, Duration::from_millis(200));
+
+        let _held = pool.get().expect("the seeded connection is idle");
+        let started = Instant::now();
+        assert!(pool.get().is_none());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(pool.lock().open, 1);
+    }
+}
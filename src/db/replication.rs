@@ -0,0 +1,371 @@
+//! Warm-standby replication: copy the configuration tables from a primary
+//! database into this one so a secondary MX can be promoted at any time.
+//!
+//! Each run reads the primary in one repeatable-read snapshot and applies it
+//! here in one transaction.  Rows whose `updated_at` (or `created_at`) is at
+//! or after the table's cursor are upserted by primary key; keys that no
+//! longer exist on the primary are deleted, so purged rows disappear too.
+//! The cursors live in `settings` under `replication_cursor_<table>`, and
+//! each run re-reads a few minutes before them so rows committed late with
+//! an older timestamp are not missed.  Upserts only touch rows that differ,
+//! which makes repeated runs harmless.
+//!
+//! Both databases must be on the same migration, otherwise rows could not
+//! be copied column for column.
+
+use log::{debug, info};
+use postgres::{GenericClient, IsolationLevel, NoTls};
+
+use super::{
+    connection_config, env_duration_secs, now, Database, DEFAULT_CONNECT_TIMEOUT_SECS,
+    NO_CONNECTION,
+};
+
+/// A replicated table.
+struct Table {
+    name: &'static str,
+    keys: &'static [&'static str],
+    /// Column holding the last change time; `None` copies the whole table
+    /// every run.
+    stamp: Option<&'static str>,
+    /// Rows on either side that are left alone.
+    filter: &'static str,
+}
+
+/// Parents before children: upserts run in this order, deletes in reverse.
+const TABLES: &[Table] = &[
+    Table {
+        name: "domains",
+        keys: &["id"],
+        stamp: Some("COALESCE(updated_at, created_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "accounts",
+        keys: &["id"],
+        stamp: Some("COALESCE(updated_at, created_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "aliases",
+        keys: &["id"],
+        stamp: Some("COALESCE(updated_at, created_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "forwardings",
+        keys: &["id"],
+        stamp: Some("COALESCE(updated_at, created_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "domain_reserved_mailboxes",
+        keys: &["domain_id", "role"],
+        stamp: Some("COALESCE(updated_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "registration_settings",
+        keys: &["domain_id"],
+        stamp: Some("COALESCE(updated_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "outbound_relays",
+        keys: &["id"],
+        stamp: Some("COALESCE(updated_at, created_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "outbound_relay_assignments",
+        keys: &["id"],
+        stamp: Some("COALESCE(created_at, '')"),
+        filter: "TRUE",
+    },
+    Table {
+        name: "settings",
+        keys: &["key"],
+        stamp: None,
+        // Each server locks its own admin panel.
+        filter: "key NOT LIKE 'replication\\_%' AND key <> 'maintenance_mode'",
+    },
+];
+
+const CURSOR_PREFIX: &str = "replication_cursor_";
+/// How far before its cursor each run re-reads a table.
+const REPLAY_MINUTES: i64 = 5;
+
+/// What one run changed in a table.
+pub struct TableReport {
+    pub table: &'static str,
+    /// Rows inserted or updated.
+    pub changed: u64,
+    pub deleted: u64,
+}
+
+/// The lower bound to read changes from for a stored `cursor`.  Timestamps
+/// start with `%Y-%m-%d %H:%M:%S`; an empty or unreadable cursor reads
+/// everything.
+fn replay_from(cursor: &str) -> String {
+    cursor
+        .get(..19)
+        .and_then(|ts| chrono::NaiveDateTime::parse_from_str(ts, "%Y-%m-%d %H:%M:%S").ok())
+        .map(|ts| {
+            (ts - chrono::Duration::minutes(REPLAY_MINUTES))
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        })
+        .unwrap_or_default()
+}
+
+fn key_tuple(table: &Table) -> String {
+    format!("json_build_array({})::text", table.keys.join(", "))
+}
+
+/// The columns of `table` here, in table order.
+pub(super) fn table_columns(
+    client: &mut impl GenericClient,
+    table: &str,
+) -> Result<Vec<String>, String> {
+    Ok(client
+        .query(
+            "SELECT column_name::text FROM information_schema.columns
+             WHERE table_schema = current_schema() AND table_name = $1
+             ORDER BY ordinal_position",
+            &[&table],
+        )
+        .map_err(|e| e.to_string())?
+        .iter()
+        .map(|row| row.get(0))
+        .collect())
+}
+
+/// An upsert of one row given as a JSON object in `$1`, writing `columns`.
+/// An existing row with the same `keys` is only updated when a value
+/// differs, so the affected row count says whether anything changed.
+pub(super) fn upsert_statement(table: &str, keys: &[&str], columns: &[String]) -> String {
+    let values: Vec<&String> = columns
+        .iter()
+        .filter(|c| !keys.contains(&c.as_str()))
+        .collect();
+    let on_conflict = if values.is_empty() {
+        "DO NOTHING".to_string()
+    } else {
+        format!(
+            "DO UPDATE SET {} WHERE ({}) IS DISTINCT FROM ({})",
+            values
+                .iter()
+                .map(|c| format!("{0} = EXCLUDED.{0}", c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            values
+                .iter()
+                .map(|c| format!("{}.{}", table, c))
+                .collect::<Vec<_>>()
+                .join(", "),
+            values
+                .iter()
+                .map(|c| format!("EXCLUDED.{}", c))
+                .collect::<Vec<_>>()
+                .join(", ")
+        )
+    };
+    format!(
+        "INSERT INTO {table} ({columns}) SELECT {columns} FROM json_populate_record(NULL::{table}, $1::text::json)
+         ON CONFLICT ({keys}) {on_conflict}",
+        table = table,
+        columns = columns.join(", "),
+        keys = keys.join(", "),
+        on_conflict = on_conflict
+    )
+}
+
+/// Move the `id` sequence of `table` past its largest id, after rows were
+/// written with explicit ids.
+pub(super) fn sync_id_sequence(client: &mut impl GenericClient, table: &str) -> Result<(), String> {
+    client
+        .execute(
+            &format!(
+                "SELECT setval(pg_get_serial_sequence('{0}', 'id'), GREATEST(MAX(id), 1)) FROM {0}",
+                table
+            ),
+            &[],
+        )
+        .map(|_| ())
+        .map_err(|e| format!("{}: {}", table, e))
+}
+
+impl Database {
+    /// Bring the replicated tables up to date with the database at
+    /// `primary_url`.  Nothing is written to the primary.
+    pub fn replicate_from(&self, primary_url: &str) -> Result<Vec<TableReport>, String> {
+        let config = connection_config(
+            primary_url,
+            env_duration_secs("DB_CONNECT_TIMEOUT_SECONDS", DEFAULT_CONNECT_TIMEOUT_SECS),
+            std::time::Duration::ZERO,
+        )?;
+        let mut primary = config
+            .connect(NoTls)
+            .map_err(|e| format!("failed to connect to the primary: {}", e))?;
+        let mut source = primary
+            .build_transaction()
+            .isolation_level(IsolationLevel::RepeatableRead)
+            .read_only(true)
+            .start()
+            .map_err(|e| format!("failed to start a snapshot on the primary: {}", e))?;
+
+        let db = self.without_statement_timeout();
+        let Some(mut conn) = db.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+
+        let latest = "SELECT COALESCE(MAX(name), '') FROM _migrations";
+        let primary_schema: String = source
+            .query_one(latest, &[])
+            .map_err(|e| e.to_string())?
+            .get(0);
+        let local_schema: String = tx.query_one(latest, &[]).map_err(|e| e.to_string())?.get(0);
+        if primary_schema != local_schema {
+            return Err(format!(
+                "schema mismatch: the primary is at migration {:?}, this server at {:?}; run the same version on both",
+                primary_schema, local_schema
+            ));
+        }
+
+        let mut reports: Vec<TableReport> = TABLES
+            .iter()
+            .map(|t| TableReport {
+                table: t.name,
+                changed: 0,
+                deleted: 0,
+            })
+            .collect();
+
+        // Deletes first, children before parents, so a row re-created on the
+        // primary under a new key cannot clash with its old copy here.
+        for (table, report) in TABLES.iter().zip(reports.iter_mut()).rev() {
+            let keys_sql = format!(
+                "SELECT {} FROM {} WHERE {}",
+                key_tuple(table),
+                table.name,
+                table.filter
+            );
+            let primary_keys: std::collections::HashSet<String> = source
+                .query(&keys_sql, &[])
+                .map_err(|e| format!("{}: {}", table.name, e))?
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+            let gone: Vec<String> = tx
+                .query(&keys_sql, &[])
+                .map_err(|e| format!("{}: {}", table.name, e))?
+                .iter()
+                .map(|row| row.get::<_, String>(0))
+                .filter(|key| !primary_keys.contains(key))
+                .collect();
+            if !gone.is_empty() {
+                report.deleted = tx
+                    .execute(
+                        &format!(
+                            "DELETE FROM {} WHERE {} = ANY($1)",
+                            table.name,
+                            key_tuple(table)
+                        ),
+                        &[&gone],
+                    )
+                    .map_err(|e| format!("{}: {}", table.name, e))?;
+            }
+        }
+
+        for (table, report) in TABLES.iter().zip(reports.iter_mut()) {
+            let cursor_key = format!("{}{}", CURSOR_PREFIX, table.name);
+            let cursor: String = tx
+                .query_opt("SELECT value FROM settings WHERE key = $1", &[&cursor_key])
+                .map_err(|e| e.to_string())?
+                .map(|row| row.get(0))
+                .unwrap_or_default();
+            let rows = match table.stamp {
+                Some(stamp) => source.query(
+                    &format!(
+                        "SELECT row_to_json(t)::text, {stamp} FROM {name} t
+                         WHERE {filter} AND {stamp} >= $1 ORDER BY 2",
+                        stamp = stamp,
+                        name = table.name,
+                        filter = table.filter
+                    ),
+                    &[&replay_from(&cursor)],
+                ),
+                None => source.query(
+                    &format!(
+                        "SELECT row_to_json(t)::text, '' FROM {} t WHERE {}",
+                        table.name, table.filter
+                    ),
+                    &[],
+                ),
+            }
+            .map_err(|e| format!("{}: {}", table.name, e))?;
+            debug!(
+                "[replicate] {}: {} rows since {:?}",
+                table.name,
+                rows.len(),
+                cursor
+            );
+
+            let columns = table_columns(&mut tx, table.name)?;
+            let upsert = tx
+                .prepare(&upsert_statement(table.name, table.keys, &columns))
+                .map_err(|e| format!("{}: {}", table.name, e))?;
+            for row in &rows {
+                let json: String = row.get(0);
+                report.changed += tx
+                    .execute(&upsert, &[&json])
+                    .map_err(|e| format!("{}: {}", table.name, e))?;
+            }
+
+            if table.keys == ["id"] {
+                sync_id_sequence(&mut tx, table.name)?;
+            }
+            if let Some(newest) = rows.last().map(|row| row.get::<_, String>(1)) {
+                if newest > cursor {
+                    tx.execute(
+                        "INSERT INTO settings (key, value) VALUES ($1, $2)
+                         ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value",
+                        &[&cursor_key, &newest],
+                    )
+                    .map_err(|e| e.to_string())?;
+                }
+            }
+        }
+
+        tx.execute(
+            "INSERT INTO settings (key, value) VALUES ('replication_last_run', $1)
+             ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value",
+            &[&now()],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        info!(
+            "[db] replicated {} tables from the primary at migration {}",
+            TABLES.len(),
+            primary_schema
+        );
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::replay_from;
+
+    #[test]
+    fn cursors_replay_a_few_minutes() {
+        assert_eq!(replay_from(""), "");
+        assert_eq!(replay_from("garbage"), "");
+        assert_eq!(replay_from("2026-01-01 00:02:00"), "2025-12-31 23:57:00");
+        assert_eq!(
+            replay_from("2026-03-04 10:20:30.123456+00"),
+            "2026-03-04 10:15:30"
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! WebDAV write locks (RFC 4918 class 2).
+//!
+//! A lock belongs to one file of one user's drive and lasts until it is
+//! unlocked or its timeout passes.  Expired locks are ignored by every query
+//! here and removed by [`Database::purge_expired_webdav_locks`].
+
+use log::{debug, error, info};
+
+use super::{now, Database, NO_CONNECTION};
+
+#[derive(Clone, Debug)]
+pub struct WebDavLock {
+    /// `opaquelocktoken:<uuid>`.
+    pub token: String,
+    pub owner: String,
+    pub filename: String,
+    /// Exclusive, or shared with other shared locks.
+    pub exclusive: bool,
+    /// What the client said about who holds the lock, as XML.
+    pub lock_owner: String,
+    pub timeout_secs: i64,
+    pub expires_at: String,
+}
+
+impl WebDavLock {
+    /// Whether this lock keeps `other` from being taken on the same file.
+    pub fn conflicts_with(&self, other: &WebDavLock) -> bool {
+        self.exclusive || other.exclusive
+    }
+}
+
+const COLUMNS: &str = "token, owner, filename, exclusive, lock_owner, timeout_secs, expires_at";
+
+fn row_to_lock(row: &postgres::Row) -> WebDavLock {
+    WebDavLock {
+        token: row.get(0),
+        owner: row.get(1),
+        filename: row.get(2),
+        exclusive: row.get(3),
+        lock_owner: row.get(4),
+        timeout_secs: row.get(5),
+        expires_at: row.get(6),
+    }
+}
+
+fn expires_at(timeout_secs: i64) -> String {
+    (chrono::Utc::now() + chrono::Duration::seconds(timeout_secs))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+impl Database {
+    /// Active locks on `owner`'s file `filename`.
+    pub fn webdav_locks_on(&self, owner: &str, filename: &str) -> Vec<WebDavLock> {
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            &format!(
+                "SELECT {COLUMNS} FROM webdav_locks
+                 WHERE lower(owner) = lower($1) AND filename = $2 AND expires_at > $3
+                 ORDER BY created_at"
+            ),
+            &[&owner, &filename, &now()],
+        )
+        .unwrap_or_else(|e| {
+            error!(
+                "[db] failed to list webdav locks on {}/{}: {}",
+                owner, filename, e
+            );
+            Vec::new()
+        })
+        .iter()
+        .map(row_to_lock)
+        .collect()
+    }
+
+    /// Active locks on any of `owner`'s files.
+    pub fn webdav_locks_for_owner(&self, owner: &str) -> Vec<WebDavLock> {
+        let Some(mut conn) = self.conn() else {
+            return Vec::new();
+        };
+        conn.query(
+            &format!(
+                "SELECT {COLUMNS} FROM webdav_locks
+                 WHERE lower(owner) = lower($1) AND expires_at > $2
+                 ORDER BY filename, created_at"
+            ),
+            &[&owner, &now()],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to list webdav locks for {}: {}", owner, e);
+            Vec::new()
+        })
+        .iter()
+        .map(row_to_lock)
+        .collect()
+    }
+
+    /// Take `lock` unless an active lock on the same file conflicts with it,
+    /// in which case `None` is returned.  Its expiry is set from its timeout.
+    pub fn create_webdav_lock(&self, lock: &WebDavLock) -> Result<Option<WebDavLock>, String> {
+        debug!(
+            "[db] locking webdav file {}/{} exclusive={}",
+            lock.owner, lock.filename, lock.exclusive
+        );
+        let Some(mut conn) = self.conn() else {
+            return Err(NO_CONNECTION.to_string());
+        };
+        let mut tx = conn.transaction().map_err(|e| e.to_string())?;
+        // Serialize lock requests for one file so two cannot both succeed.
+        tx.execute(
+            "SELECT pg_advisory_xact_lock(hashtext(lower($1) || '/' || $2))",
+            &[&lock.owner, &lock.filename],
+        )
+        .map_err(|e| e.to_string())?;
+        let held = tx
+            .query(
+                &format!(
+                    "SELECT {COLUMNS} FROM webdav_locks
+                     WHERE lower(owner) = lower($1) AND filename = $2 AND expires_at > $3"
+                ),
+                &[&lock.owner, &lock.filename, &now()],
+            )
+            .map_err(|e| e.to_string())?;
+        if held.iter().map(row_to_lock).any(|h| h.conflicts_with(lock)) {
+            info!(
+                "[db] webdav lock on {}/{} refused: already locked",
+                lock.owner, lock.filename
+            );
+            return Ok(None);
+        }
+        let taken = WebDavLock {
+            expires_at: expires_at(lock.timeout_secs),
+            ..lock.clone()
+        };
+        tx.execute(
+            "INSERT INTO webdav_locks
+               (token, owner, filename, exclusive, lock_owner, timeout_secs, created_at, expires_at)
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            &[
+                &taken.token,
+                &taken.owner,
+                &taken.filename,
+                &taken.exclusive,
+                &taken.lock_owner,
+                &taken.timeout_secs,
+                &now(),
+                &taken.expires_at,
+            ],
+        )
+        .map_err(|e| e.to_string())?;
+        tx.commit().map_err(|e| e.to_string())?;
+        info!(
+            "[db] webdav lock {} taken on {}/{}",
+            taken.token, taken.owner, taken.filename
+        );
+        Ok(Some(taken))
+    }
+
+    /// Restart the timeout of the active lock `token` on `owner`'s file
+    /// `filename`, with `timeout_secs` as its new timeout.
+    pub fn refresh_webdav_lock(
+        &self,
+        owner: &str,
+        filename: &str,
+        token: &str,
+        timeout_secs: i64,
+    ) -> Option<WebDavLock> {
+        debug!("[db] refreshing webdav lock {}", token);
+        let mut conn = self.conn()?;
+        conn.query_opt(
+            &format!(
+                "UPDATE webdav_locks SET timeout_secs = $4, expires_at = $5
+                 WHERE lower(owner) = lower($1) AND filename = $2 AND token = $3 AND expires_at > $6
+                 RETURNING {COLUMNS}"
+            ),
+            &[
+                &owner,
+                &filename,
+                &token,
+                &timeout_secs,
+                &expires_at(timeout_secs),
+                &now(),
+            ],
+        )
+        .unwrap_or_else(|e| {
+            error!("[db] failed to refresh webdav lock {}: {}", token, e);
+            None
+        })
+        .as_ref()
+        .map(row_to_lock)
+    }
+
+    /// Release the active lock `token` on `owner`'s file `filename`.
+    pub fn delete_webdav_lock(&self, owner: &str, filename: &str, token: &str) -> bool {
+        let Some(mut conn) = self.conn() else {
+            return false;
+        };
+        match conn.execute(
+            "DELETE FROM webdav_locks
+             WHERE lower(owner) = lower($1) AND filename = $2 AND token = $3 AND expires_at > $4",
+            &[&owner, &filename, &token, &now()],
+        ) {
+            Ok(n) => {
+                if n > 0 {
+                    info!("[db] webdav lock {} released", token);
+                }
+                n > 0
+            }
+            Err(e) => {
+                error!("[db] failed to release webdav lock {}: {}", token, e);
+                false
+            }
+        }
+    }
+
+    /// Drop every lock on `owner`'s file `filename`, once the file is gone.
+    pub fn delete_webdav_locks_on(&self, owner: &str, filename: &str) {
+        let Some(mut conn) = self.conn() else {
+            return;
+        };
+        if let Err(e) = conn.execute(
+            "DELETE FROM webdav_locks WHERE lower(owner) = lower($1) AND filename = $2",
+            &[&owner, &filename],
+        ) {
+            error!(
+                "[db] failed to drop webdav locks on {}/{}: {}",
+                owner, filename, e
+            );
+        }
+    }
+
+    /// Delete locks whose timeout has passed; returns how many.
+    pub fn purge_expired_webdav_locks(&self) -> u64 {
+        let Some(mut conn) = self.conn() else {
+            return 0;
+        };
+        conn.execute("DELETE FROM webdav_locks WHERE expires_at <= $1", &[&now()])
+            .unwrap_or_else(|e| {
+                error!("[db] failed to purge expired webdav locks: {}", e);
+                0
+            })
+    }
+}
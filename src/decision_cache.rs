@@ -0,0 +1,161 @@
+//! Short-TTL cache of content-filter verdicts.
+//!
+//! The filter runs as one process per message, so verdicts are shared through
+//! the `filter_decision_cache` table rather than process memory.  Entries are
+//! keyed by what the decision depends on (e.g. `rbl:<client ip>`).  Expired
+//! entries are deleted whenever a verdict is stored, and the table is cleared
+//! whenever mail configs are regenerated.
+
+use log::debug;
+
+use crate::db::Database;
+
+/// Default lifetime of a cached verdict, overridable with the
+/// `filter_cache_ttl_seconds` setting (0 disables caching).
+pub const DEFAULT_TTL_SECS: i64 = 300;
+
+/// Upper bound for cached `Defer` verdicts so that temporary failures such as
+/// greylisting can pass on a later retry.
+pub const MAX_DEFER_TTL_SECS: i64 = 60;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Accept,
+    /// Deliver, but flagged as spam (to the Junk folder); not a bounce.
+    FlagSpam,
+    Defer,
+}
+
+impl Verdict {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Verdict::Accept => "accept",
+            Verdict::FlagSpam => "spam",
+            Verdict::Defer => "defer",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "accept" => Some(Verdict::Accept),
+            "spam" => Some(Verdict::FlagSpam),
+            "defer" => Some(Verdict::Defer),
+            _ => None,
+        }
+    }
+}
+
+/// Storage backend for cached verdicts.
+pub trait DecisionStore {
+    fn get(&self, key: &str) -> Option<Verdict>;
+    fn put(&self, key: &str, verdict: Verdict, ttl_secs: i64);
+}
+
+impl DecisionStore for Database {
+    fn get(&self, key: &str) -> Option<Verdict> {
+        self.get_filter_decision(key)
+            .as_deref()
+            .and_then(Verdict::parse)
+    }
+
+    fn put(&self, key: &str, verdict: Verdict, ttl_secs: i64) {
+        self.put_filter_decision(key, verdict.as_str(), ttl_secs);
+    }
+}
+
+/// Configured cache lifetime in seconds.
+pub fn ttl_secs(db: &Database) -> i64 {
+    db.get_setting("filter_cache_ttl_seconds")
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .map(|v| v.max(0))
+        .unwrap_or(DEFAULT_TTL_SECS)
+}
+
+/// Return the cached verdict for `key`, or run `compute` and cache its result
+/// for `ttl_secs` (capped at [`MAX_DEFER_TTL_SECS`] for `Defer`).
+pub fn cached<S, F>(store: &S, key: &str, ttl_secs: i64, compute: F) -> Verdict
+where
+    S: DecisionStore,
+    F: FnOnce() -> Verdict,
+{
+    if ttl_secs <= 0 {
+        return compute();
+    }
+    if let Some(verdict) = store.get(key) {
+        debug!("[filter] decision cache hit key={} verdict={}", key, verdict.as_str());
+        return verdict;
+    }
+    let verdict = compute();
+    let ttl = if verdict == Verdict::Defer {
+        ttl_secs.min(MAX_DEFER_TTL_SECS)
+    } else {
+        ttl_secs
+    };
+    store.put(key, verdict, ttl);
+    verdict
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    type MemoryStore = crate::test_support::MemoryStore<String, (Verdict, i64)>;
+
+    impl DecisionStore for MemoryStore {
+        fn get(&self, key: &str) -> Option<Verdict> {
+            self.entry(&key.to_string()).map(|(v, _)| v)
+        }
+
+        fn put(&self, key: &str, verdict: Verdict, ttl_secs: i64) {
+            self.insert(key.to_string(), (verdict, ttl_secs));
+        }
+    }
+
+    #[test]
+    fn repeated_check_within_ttl_skips_lookup() {
+        let store = MemoryStore::default();
+        let lookups = Cell::new(0);
+        let lookup = || {
+            lookups.set(lookups.get() + 1);
+            Verdict::FlagSpam
+        };
+
+        assert_eq!(
+            cached(&store, "rbl:1.2.3.4", 300, lookup),
+            Verdict::FlagSpam
+        );
+        assert_eq!(
+            cached(&store, "rbl:1.2.3.4", 300, lookup),
+            Verdict::FlagSpam
+        );
+        assert_eq!(lookups.get(), 1);
+
+        cached(&store, "rbl:5.6.7.8", 300, lookup);
+        assert_eq!(lookups.get(), 2);
+    }
+
+    #[test]
+    fn zero_ttl_disables_caching() {
+        let store = MemoryStore::default();
+        let lookups = Cell::new(0);
+        let lookup = || {
+            lookups.set(lookups.get() + 1);
+            Verdict::Accept
+        };
+        cached(&store, "k", 0, lookup);
+        cached(&store, "k", 0, lookup);
+        assert_eq!(lookups.get(), 2);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn defer_is_cached_briefly() {
+        let store = MemoryStore::default();
+        cached(&store, "grey", 3600, || Verdict::Defer);
+        cached(&store, "ok", 3600, || Verdict::Accept);
+        let ttl = |key: &str| store.entry(&key.to_string()).map(|(_, ttl)| ttl);
+        assert_eq!(ttl("grey"), Some(MAX_DEFER_TTL_SECS));
+        assert_eq!(ttl("ok"), Some(3600));
+    }
+}
@@ -0,0 +1,399 @@
+//! Delivery policy for disabled and over-quota accounts.
+//!
+//! Mail for a disabled account is rejected at SMTP time with a custom message
+//! (the default), silently discarded, or forwarded to an admin address.  The
+//! global policy lives in settings and each account may override it; config
+//! generation turns the result into Postfix access, alias and transport
+//! entries.  Over-quota mailboxes are checked by the incoming content filter,
+//! which defers (the default) or bounces the message.
+//!
+//! Usage is the total size of the files in a mailbox's Maildir.  Scans are
+//! cached in the `mailbox_usage` table for `USAGE_CACHE_TTL_SECS`, shared by
+//! the filter processes and the admin UI.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use log::{info, warn};
+
+use crate::db::{Account, Database};
+
+pub const DISABLED_POLICY_SETTING: &str = "disabled_account_policy";
+pub const DISABLED_MESSAGE_SETTING: &str = "disabled_account_message";
+pub const DISABLED_FORWARD_SETTING: &str = "disabled_account_forward_to";
+pub const OVER_QUOTA_POLICY_SETTING: &str = "over_quota_policy";
+
+pub const DEFAULT_DISABLED_MESSAGE: &str = "This mailbox is disabled";
+
+const MAILDIR_ROOT: &str = "/data/mail";
+/// How long a measured mailbox size is reused before the Maildir is scanned
+/// again.
+pub const USAGE_CACHE_TTL_SECS: i64 = 60;
+
+/// Postfix EX_TEMPFAIL exit code — the message is queued for retry.
+const EX_TEMPFAIL: i32 = 75;
+/// Postfix EX_UNAVAILABLE exit code — the message is bounced.
+const EX_UNAVAILABLE: i32 = 69;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DisabledPolicy {
+    Reject,
+    Discard,
+    Forward,
+}
+
+impl DisabledPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "reject" => Some(DisabledPolicy::Reject),
+            "discard" => Some(DisabledPolicy::Discard),
+            "forward" => Some(DisabledPolicy::Forward),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DisabledPolicy::Reject => "reject",
+            DisabledPolicy::Discard => "discard",
+            DisabledPolicy::Forward => "forward",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverQuotaPolicy {
+    Defer,
+    Bounce,
+}
+
+impl OverQuotaPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "defer" => Some(OverQuotaPolicy::Defer),
+            "bounce" => Some(OverQuotaPolicy::Bounce),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OverQuotaPolicy::Defer => "defer",
+            OverQuotaPolicy::Bounce => "bounce",
+        }
+    }
+
+    /// Exit code the content filter returns to Postfix.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            OverQuotaPolicy::Defer => EX_TEMPFAIL,
+            OverQuotaPolicy::Bounce => EX_UNAVAILABLE,
+        }
+    }
+
+    /// Enhanced status code (RFC 3463 "mailbox full") for the bounce or
+    /// deferral notice.
+    pub fn status_code(self) -> &'static str {
+        match self {
+            OverQuotaPolicy::Defer => "4.2.2",
+            OverQuotaPolicy::Bounce => "5.2.2",
+        }
+    }
+}
+
+/// The global policy from settings.
+#[derive(Clone, Debug)]
+pub struct DeliveryPolicy {
+    pub disabled: DisabledPolicy,
+    pub disabled_message: String,
+    pub forward_to: String,
+    pub over_quota: OverQuotaPolicy,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        DeliveryPolicy {
+            disabled: DisabledPolicy::Reject,
+            disabled_message: DEFAULT_DISABLED_MESSAGE.to_string(),
+            forward_to: String::new(),
+            over_quota: OverQuotaPolicy::Defer,
+        }
+    }
+}
+
+impl DeliveryPolicy {
+    pub fn load(db: &Database) -> Self {
+        let defaults = DeliveryPolicy::default();
+        DeliveryPolicy {
+            disabled: db
+                .get_setting(DISABLED_POLICY_SETTING)
+                .and_then(|v| DisabledPolicy::parse(&v))
+                .unwrap_or(defaults.disabled),
+            disabled_message: db
+                .get_setting(DISABLED_MESSAGE_SETTING)
+                .map(|v| single_line(&v))
+                .filter(|v| !v.is_empty())
+                .unwrap_or(defaults.disabled_message),
+            forward_to: db
+                .get_setting(DISABLED_FORWARD_SETTING)
+                .map(|v| v.trim().to_string())
+                .unwrap_or_default(),
+            over_quota: db
+                .get_setting(OVER_QUOTA_POLICY_SETTING)
+                .and_then(|v| OverQuotaPolicy::parse(&v))
+                .unwrap_or(defaults.over_quota),
+        }
+    }
+}
+
+/// Collapse a message to one line so it cannot break a Postfix map entry.
+pub fn single_line(message: &str) -> String {
+    message.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Postfix map entries for the disabled accounts.
+#[derive(Debug, Default, PartialEq)]
+pub struct DisabledMaps {
+    /// `check_recipient_access` entries: (address, `REJECT ...`).
+    pub access: Vec<(String, String)>,
+    /// Virtual alias entries: (address, forward target).
+    pub aliases: Vec<(String, String)>,
+    /// Addresses accepted and delivered to the `discard` transport.
+    pub discard: Vec<String>,
+}
+
+/// Apply the global `policy` (or an account's entry in `overrides`) to every
+/// inactive account.  Forwarding without a target address falls back to
+/// rejecting.
+pub fn build_disabled_maps(
+    accounts: &[Account],
+    overrides: &HashMap<i64, String>,
+    policy: &DeliveryPolicy,
+) -> DisabledMaps {
+    let mut maps = DisabledMaps::default();
+    for a in accounts.iter().filter(|a| !a.active) {
+        let Some(domain) = a.domain_name.as_deref() else {
+            continue;
+        };
+        let address = format!("{}@{}", a.username, domain).to_lowercase();
+        let mut action = overrides
+            .get(&a.id)
+            .and_then(|p| DisabledPolicy::parse(p))
+            .unwrap_or(policy.disabled);
+        if action == DisabledPolicy::Forward && policy.forward_to.is_empty() {
+            warn!(
+                "[delivery-policy] no forward address configured, rejecting mail for disabled {}",
+                address
+            );
+            action = DisabledPolicy::Reject;
+        }
+        match action {
+            DisabledPolicy::Reject => maps.access.push((
+                address,
+                format!("REJECT 5.2.1 {}", single_line(&policy.disabled_message)),
+            )),
+            DisabledPolicy::Discard => maps.discard.push(address),
+            DisabledPolicy::Forward => maps.aliases.push((address, policy.forward_to.clone())),
+        }
+    }
+    maps
+}
+
+/// Total size in bytes of the files below `dir`.
+pub fn maildir_usage(dir: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => maildir_usage(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+/// Bytes used by the Maildir of `username@domain` below `root`.  Names that
+/// could leave the mail root count as empty.
+fn mailbox_usage_in(root: &Path, domain: &str, username: &str) -> u64 {
+    if [domain, username]
+        .iter()
+        .any(|p| p.is_empty() || p.contains('/') || p.contains(".."))
+    {
+        return 0;
+    }
+    maildir_usage(&root.join(domain).join(username).join("Maildir"))
+}
+
+/// Bytes used by the Maildir of `username@domain`, scanned from disk.
+pub fn mailbox_usage(domain: &str, username: &str) -> u64 {
+    mailbox_usage_in(Path::new(MAILDIR_ROOT), domain, username)
+}
+
+/// Like [`mailbox_usage`], but reuses a measurement younger than
+/// `USAGE_CACHE_TTL_SECS` unless `refresh` is set.
+pub fn cached_mailbox_usage(db: &Database, domain: &str, username: &str, refresh: bool) -> u64 {
+    let address = format!("{}@{}", username, domain).to_lowercase();
+    if !refresh {
+        let fresh_after = (chrono::Utc::now() - chrono::Duration::seconds(USAGE_CACHE_TTL_SECS))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string();
+        if let Some(bytes) = db.get_mailbox_usage(&address, &fresh_after) {
+            return bytes.max(0) as u64;
+        }
+    }
+    let bytes = mailbox_usage(domain, username);
+    db.put_mailbox_usage(&address, i64::try_from(bytes).unwrap_or(i64::MAX));
+    bytes
+}
+
+/// Exit code for a mailbox using `used_bytes` of a `quota_mb` quota, or
+/// `None` while it is within quota.  A quota of 0 means unlimited.
+pub fn quota_exit_code(used_bytes: u64, quota_mb: i64, policy: OverQuotaPolicy) -> Option<i32> {
+    if quota_mb <= 0 || used_bytes < (quota_mb as u64).saturating_mul(1024 * 1024) {
+        return None;
+    }
+    Some(policy.exit_code())
+}
+
+/// Check the local recipients of an incoming message against their quotas.
+/// Returns the exit code and the bounce or deferral reason for the first
+/// recipient that is over quota.
+pub fn check_quota(db: &Database, recipients: &[String]) -> Option<(i32, String)> {
+    let policy = DeliveryPolicy::load(db).over_quota;
+    recipients.iter().find_map(|rcpt| {
+        let (local, domain) = rcpt.rsplit_once('@')?;
+        let local = local.split('+').next().unwrap_or(local);
+        let account = db.get_account_by_email(&format!("{}@{}", local, domain))?;
+        if account.quota <= 0 {
+            return None;
+        }
+        let domain = account.domain_name.as_deref().unwrap_or(domain);
+        let used = cached_mailbox_usage(db, domain, &account.username, false);
+        let code = quota_exit_code(used, account.quota, policy)?;
+        info!(
+            "[delivery-policy] {} is over quota ({} bytes used of {} MB): {}",
+            rcpt,
+            used,
+            account.quota,
+            policy.as_str()
+        );
+        Some((
+            code,
+            format!(
+                "{} Mailbox full: {} is over its {} MB quota",
+                policy.status_code(),
+                rcpt,
+                account.quota
+            ),
+        ))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(id: i64, username: &str, active: bool) -> Account {
+        Account {
+            id,
+            domain_id: 1,
+            username: username.to_string(),
+            password_hash: String::new(),
+            name: String::new(),
+            active,
+            quota: 0,
+            domain_name: Some("example.com".to_string()),
+            is_system: false,
+        }
+    }
+
+    #[test]
+    fn disabled_accounts_follow_the_configured_policy() {
+        let accounts = vec![
+            account(1, "alice", true),
+            account(2, "bob", false),
+            account(3, "carol", false),
+            account(4, "dave", false),
+        ];
+        let overrides = HashMap::from([(3, "discard".to_string()), (4, "forward".to_string())]);
+
+        let reject = DeliveryPolicy {
+            disabled_message: "Bob has\nleft".to_string(),
+            ..DeliveryPolicy::default()
+        };
+        let maps = build_disabled_maps(&accounts, &overrides, &reject);
+        assert_eq!(
+            maps.access,
+            vec![
+                (
+                    "bob@example.com".to_string(),
+                    "REJECT 5.2.1 Bob has left".to_string()
+                ),
+                // Forwarding without a target falls back to rejecting.
+                (
+                    "dave@example.com".to_string(),
+                    "REJECT 5.2.1 Bob has left".to_string()
+                ),
+            ]
+        );
+        assert_eq!(maps.discard, vec!["carol@example.com".to_string()]);
+        assert!(maps.aliases.is_empty());
+
+        let forward = DeliveryPolicy {
+            disabled: DisabledPolicy::Forward,
+            forward_to: "postmaster@example.com".to_string(),
+            ..DeliveryPolicy::default()
+        };
+        let maps = build_disabled_maps(&accounts, &HashMap::new(), &forward);
+        assert!(maps.access.is_empty() && maps.discard.is_empty());
+        assert_eq!(maps.aliases.len(), 3);
+        assert!(maps
+            .aliases
+            .iter()
+            .all(|(_, target)| target == "postmaster@example.com"));
+    }
+
+    #[test]
+    fn over_quota_defers_or_bounces_as_configured() {
+        let mb = 1024 * 1024;
+        assert_eq!(quota_exit_code(5 * mb, 10, OverQuotaPolicy::Defer), None);
+        assert_eq!(
+            quota_exit_code(10 * mb, 10, OverQuotaPolicy::Defer),
+            Some(EX_TEMPFAIL)
+        );
+        assert_eq!(
+            quota_exit_code(11 * mb, 10, OverQuotaPolicy::Bounce),
+            Some(EX_UNAVAILABLE)
+        );
+        assert_eq!(quota_exit_code(u64::MAX, 0, OverQuotaPolicy::Defer), None);
+        assert_eq!(DeliveryPolicy::default().over_quota, OverQuotaPolicy::Defer);
+    }
+
+    #[test]
+    fn maildir_usage_sums_nested_files() {
+        let root = std::env::temp_dir().join(format!("maildir-usage-{}", std::process::id()));
+        fs::create_dir_all(root.join("cur")).unwrap();
+        fs::create_dir_all(root.join(".Sent/new")).unwrap();
+        fs::write(root.join("cur/1"), vec![0u8; 100]).unwrap();
+        fs::write(root.join(".Sent/new/2"), vec![0u8; 50]).unwrap();
+        assert_eq!(maildir_usage(&root), 150);
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(maildir_usage(&root), 0);
+    }
+
+    #[test]
+    fn mailbox_usage_reads_the_account_maildir_only() {
+        let root = std::env::temp_dir().join(format!("mailbox-usage-{}", std::process::id()));
+        fs::create_dir_all(root.join("example.com/alice/Maildir/cur")).unwrap();
+        fs::write(root.join("example.com/alice/Maildir/cur/1"), vec![0u8; 70]).unwrap();
+        fs::write(root.join("example.com/alice/sieve"), vec![0u8; 30]).unwrap();
+        assert_eq!(mailbox_usage_in(&root, "example.com", "alice"), 70);
+        assert_eq!(mailbox_usage_in(&root, "example.com", "bob"), 0);
+        assert_eq!(mailbox_usage_in(&root, "example.com", "../example.com/alice"), 0);
+        assert_eq!(mailbox_usage_in(&root, "..", "alice"), 0);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
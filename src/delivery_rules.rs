@@ -0,0 +1,271 @@
+//! Per-account delivery rules, a small sieve-like filter.
+//!
+//! Each account has an ordered list of rules that compare one field of an
+//! incoming message with a pattern, using the operators of the tracking
+//! rules (case-insensitive).  The incoming content filter evaluates them for
+//! every local recipient: each matching `add_header` rule adds its header
+//! line and the first matching `move` rule files the message into a folder
+//! by delivering it to `user+Folder@domain`.
+//!
+//! [`field_text`] is also what the webmail search matches against, so rules
+//! and search see the same decoded text.
+
+use log::debug;
+
+use crate::db::{Database, DeliveryRule};
+
+/// Fields a rule can match besides any header name.
+pub const FIELDS: &[&str] = &["subject", "from", "to", "cc", "body"];
+pub const OPERATORS: &[&str] = &[
+    "contains",
+    "not_contains",
+    "equals",
+    "not_equals",
+    "starts_with",
+    "ends_with",
+];
+
+/// Decoded text of `field` in a message: a header (the first one, with
+/// encoded words decoded) or, for `body`, the text the webmail shows.
+pub fn field_text(parsed: &mailparse::ParsedMail, field: &str) -> String {
+    use mailparse::MailHeaderMap;
+
+    if field.eq_ignore_ascii_case("body") {
+        return crate::web::routes::webmail::extract_body(parsed);
+    }
+    parsed.headers.get_first_value(field).unwrap_or_default()
+}
+
+/// Whether `rule` matches the message.
+pub fn matches(parsed: &mailparse::ParsedMail, rule: &DeliveryRule) -> bool {
+    crate::db::compare_condition(
+        &rule.match_op,
+        &field_text(parsed, &rule.match_field).to_lowercase(),
+        &rule.pattern.to_lowercase(),
+    )
+}
+
+/// What the rules of one account do with a message.
+#[derive(Debug, Default, PartialEq)]
+pub struct Outcome {
+    /// Folder to deliver into instead of the Inbox.
+    pub folder: Option<String>,
+    /// Header lines to add, in rule order.
+    pub headers: Vec<String>,
+}
+
+/// Apply `rules` in order: matching `add_header` rules accumulate and the
+/// first matching `move` rule picks the folder.
+pub fn evaluate(parsed: &mailparse::ParsedMail, rules: &[DeliveryRule]) -> Outcome {
+    let mut outcome = Outcome::default();
+    for rule in rules.iter().filter(|r| matches(parsed, r)) {
+        debug!(
+            "[delivery-rules] rule id={} matched ({} {} {:?})",
+            rule.id, rule.match_field, rule.match_op, rule.pattern
+        );
+        match rule.action.as_str() {
+            "move" if outcome.folder.is_none() => {
+                outcome.folder = Some(rule.target_folder.clone());
+            }
+            "add_header" => outcome.headers.push(rule.header.clone()),
+            _ => {}
+        }
+    }
+    outcome
+}
+
+/// The rules of each local recipient that has any, keyed by the envelope
+/// recipient.
+pub fn load_for_recipients(
+    db: &Database,
+    recipients: &[String],
+) -> Vec<(String, Vec<DeliveryRule>)> {
+    recipients
+        .iter()
+        .filter_map(|rcpt| {
+            let (local, domain) = rcpt.rsplit_once('@')?;
+            let local = local.split('+').next().unwrap_or(local);
+            let account = db.get_account_by_email(&format!("{}@{}", local, domain))?;
+            let rules = db.list_delivery_rules(account.id);
+            (!rules.is_empty()).then(|| (rcpt.clone(), rules))
+        })
+        .collect()
+}
+
+/// A Maildir++ folder name that is safe in a path and in the detail part of
+/// an address: letters, digits, `-` and `_`, with `.` between levels.
+pub fn is_valid_folder(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 100
+        && name.split('.').all(|level| {
+            !level.is_empty()
+                && level
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        })
+        && !name.eq_ignore_ascii_case("inbox")
+}
+
+/// A single `Name: value` header line.
+pub fn is_valid_header(line: &str) -> bool {
+    let Some((name, value)) = line.split_once(':') else {
+        return false;
+    };
+    !name.is_empty()
+        && name.bytes().all(|b| b.is_ascii_graphic() && b != b':')
+        && !value.trim().is_empty()
+        && !line.chars().any(|c| c.is_control())
+}
+
+/// Check a rule from the admin form, returning a message for the admin.
+pub fn validate(
+    match_field: &str,
+    match_op: &str,
+    action: &str,
+    target_folder: &str,
+    header: &str,
+) -> Result<(), &'static str> {
+    let is_header_name = !match_field.is_empty()
+        && match_field
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-');
+    if !FIELDS.contains(&match_field) && !is_header_name {
+        return Err("Match on subject, from, to, cc, body or a header name such as List-Id.");
+    }
+    if !OPERATORS.contains(&match_op) {
+        return Err("Unknown match operator.");
+    }
+    match action {
+        "move" if !is_valid_folder(target_folder) => {
+            Err("Folder names may contain letters, digits, '-' and '_', with '.' between levels.")
+        }
+        "add_header" if !is_valid_header(header) => {
+            Err("The header must be a single line such as \"X-Label: important\".")
+        }
+        "move" | "add_header" => Ok(()),
+        _ => Err("Unknown rule action."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(
+        id: i64,
+        field: &str,
+        op: &str,
+        pattern: &str,
+        action: &str,
+        arg: &str,
+    ) -> DeliveryRule {
+        DeliveryRule {
+            id,
+            account_id: 1,
+            position: id as i32,
+            match_field: field.to_string(),
+            match_op: op.to_string(),
+            pattern: pattern.to_string(),
+            action: action.to_string(),
+            target_folder: if action == "move" {
+                arg.to_string()
+            } else {
+                String::new()
+            },
+            header: if action == "add_header" {
+                arg.to_string()
+            } else {
+                String::new()
+            },
+            created_at: String::new(),
+        }
+    }
+
+    const MESSAGE: &[u8] = b"From: Build Bot <ci@example.com>\r\n\
+        To: alice@example.org\r\n\
+        List-Id: <rust.lists.example.com>\r\n\
+        Subject: =?UTF-8?B?QnVpbGQgZmFpbGVkIOKAkyBtYWlu?=\r\n\
+        \r\n\
+        The nightly build failed.\r\n";
+
+    #[test]
+    fn field_text_decodes_headers_and_body() {
+        let parsed = mailparse::parse_mail(MESSAGE).unwrap();
+        assert_eq!(field_text(&parsed, "subject"), "Build failed – main");
+        assert_eq!(field_text(&parsed, "List-ID"), "<rust.lists.example.com>");
+        assert!(field_text(&parsed, "body").contains("nightly build"));
+        assert_eq!(field_text(&parsed, "cc"), "");
+    }
+
+    #[test]
+    fn headers_accumulate_and_the_first_move_wins() {
+        let parsed = mailparse::parse_mail(MESSAGE).unwrap();
+        let rules = vec![
+            rule(
+                1,
+                "from",
+                "ends_with",
+                "@EXAMPLE.COM>",
+                "add_header",
+                "X-Label: ci",
+            ),
+            rule(2, "list-id", "contains", "rust.lists", "move", "Lists.Rust"),
+            rule(
+                3,
+                "subject",
+                "starts_with",
+                "build failed",
+                "move",
+                "Alerts",
+            ),
+            rule(
+                4,
+                "body",
+                "not_contains",
+                "nightly",
+                "add_header",
+                "X-Never: yes",
+            ),
+            rule(
+                5,
+                "subject",
+                "contains",
+                "failed",
+                "add_header",
+                "X-Priority: 1",
+            ),
+        ];
+        assert_eq!(
+            evaluate(&parsed, &rules),
+            Outcome {
+                folder: Some("Lists.Rust".to_string()),
+                headers: vec!["X-Label: ci".to_string(), "X-Priority: 1".to_string()],
+            }
+        );
+        assert_eq!(evaluate(&parsed, &rules[3..4]), Outcome::default());
+    }
+
+    #[test]
+    fn rules_are_validated() {
+        assert!(validate("subject", "contains", "move", "Lists.Rust", "").is_ok());
+        assert!(validate("X-Mailer", "equals", "add_header", "", "X-Label: ci").is_ok());
+        assert!(validate("x mailer", "equals", "move", "Alerts", "").is_err());
+        assert!(validate("subject", "regex", "move", "Alerts", "").is_err());
+        assert!(validate("subject", "contains", "discard", "", "").is_err());
+        for folder in [
+            "",
+            "../etc",
+            ".Junk",
+            "a..b",
+            "Junk/x",
+            "a+b",
+            "INBOX",
+            "With space",
+        ] {
+            assert!(!is_valid_folder(folder), "{:?}", folder);
+        }
+        for header in ["X-Label", "X-Label:", "X Label: a", "X-Label: a\r\nBcc: b"] {
+            assert!(!is_valid_header(header), "{:?}", header);
+        }
+    }
+}
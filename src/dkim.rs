@@ -0,0 +1,684 @@
+//! DKIM verification of inbound mail (RFC 6376, RFC 8463).
+//!
+//! The incoming content filter verifies every `DKIM-Signature` of a message
+//! and stamps it with an `Authentication-Results` header (RFC 8601).  The
+//! per-signature outcome is a [`DkimResult`], which the DMARC reporter reads
+//! back from that header.  `rsa-sha256` and `ed25519-sha256` signatures are
+//! checked; `rsa-sha1` is a permanent error as required by RFC 8301.
+//!
+//! The `dkim_policy` setting selects `tag` (the default), `reject` to bounce
+//! messages whose signatures all fail, or `off`.
+
+use base64::Engine;
+use log::debug;
+use ring::signature::{UnparsedPublicKey, ED25519, RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY};
+use sha2::{Digest, Sha256};
+
+use crate::spf::Resolver;
+
+pub const POLICY_SETTING: &str = "dkim_policy";
+
+/// Signatures verified per message; any beyond are ignored.
+const MAX_SIGNATURES: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DkimOutcome {
+    Pass,
+    Fail,
+    TempError,
+    PermError,
+}
+
+impl DkimOutcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DkimOutcome::Pass => "pass",
+            DkimOutcome::Fail => "fail",
+            DkimOutcome::TempError => "temperror",
+            DkimOutcome::PermError => "permerror",
+        }
+    }
+}
+
+/// Outcome of one `DKIM-Signature` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DkimResult {
+    pub outcome: DkimOutcome,
+    /// Signing domain (`d=`), empty when the signature could not be parsed.
+    pub domain: String,
+    /// Key selector (`s=`).
+    pub selector: String,
+    /// Agent or user identifier (`i=`), `@<domain>` by default.
+    pub identity: String,
+    /// Why the signature did not pass; empty on pass.
+    pub reason: String,
+}
+
+impl DkimResult {
+    fn error(outcome: DkimOutcome, reason: &str) -> Self {
+        DkimResult {
+            outcome,
+            domain: String::new(),
+            selector: String::new(),
+            identity: String::new(),
+            reason: reason.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Algorithm {
+    RsaSha256,
+    Ed25519Sha256,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Canonicalization {
+    Simple,
+    Relaxed,
+}
+
+struct Signature {
+    algorithm: Algorithm,
+    signature: Vec<u8>,
+    body_hash: Vec<u8>,
+    header_canon: Canonicalization,
+    body_canon: Canonicalization,
+    domain: String,
+    selector: String,
+    identity: String,
+    signed_headers: Vec<String>,
+    body_length: Option<usize>,
+    expires: Option<i64>,
+}
+
+// ── Message parsing ──
+
+/// `text` with every line ending turned into CRLF.
+fn to_crlf(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + text.len() / 40);
+    let mut prev = '\0';
+    for c in text.chars() {
+        if c == '\n' && prev != '\r' {
+            out.push('\r');
+        }
+        out.push(c);
+        prev = c;
+    }
+    out
+}
+
+/// Header fields (continuation lines included, final CRLF excluded) and body.
+fn split_message(message: &str) -> (Vec<&str>, &str) {
+    let (head, body) = match message.find("\r\n\r\n") {
+        Some(pos) => (&message[..pos], &message[pos + 4..]),
+        None => (message.trim_end_matches("\r\n"), ""),
+    };
+    let mut fields = Vec::new();
+    let mut start = 0;
+    for (pos, _) in head.match_indices("\r\n") {
+        if !head[pos + 2..].starts_with([' ', '\t']) {
+            fields.push(&head[start..pos]);
+            start = pos + 2;
+        }
+    }
+    if start < head.len() {
+        fields.push(&head[start..]);
+    }
+    (fields, body)
+}
+
+fn field_name(field: &str) -> String {
+    field
+        .split_once(':')
+        .map(|(name, _)| name.trim_end_matches([' ', '\t']))
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+/// Parse a `tag=value; ...` list (RFC 6376 §3.2), whitespace removed from
+/// the values of `b`, `bh` and `p`.
+fn parse_tags(value: &str) -> Result<Vec<(String, String)>, &'static str> {
+    let mut tags: Vec<(String, String)> = Vec::new();
+    for spec in value.split(';') {
+        let spec = spec.trim_matches([' ', '\t', '\r', '\n']);
+        if spec.is_empty() {
+            continue;
+        }
+        let (name, value) = spec.split_once('=').ok_or("malformed tag list")?;
+        let name = name.trim_matches([' ', '\t', '\r', '\n']).to_string();
+        let mut value = value.trim_matches([' ', '\t', '\r', '\n']).to_string();
+        if matches!(name.as_str(), "b" | "bh" | "p") {
+            value.retain(|c| !c.is_ascii_whitespace());
+        }
+        if tags.iter().any(|(n, _)| *n == name) {
+            return Err("duplicate tag");
+        }
+        tags.push((name, value));
+    }
+    Ok(tags)
+}
+
+fn tag<'a>(tags: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    tags.iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+fn decode_base64(value: &str) -> Result<Vec<u8>, &'static str> {
+    base64::engine::general_purpose::STANDARD
+        .decode(value)
+        .map_err(|_| "invalid base64")
+}
+
+fn parse_signature(field: &str) -> Result<Signature, &'static str> {
+    let value = field.split_once(':').map(|(_, v)| v).unwrap_or_default();
+    let tags = parse_tags(value)?;
+    let required = |name| tag(&tags, name).ok_or("missing required tag");
+
+    if required("v")? != "1" {
+        return Err("unsupported version");
+    }
+    let algorithm = match required("a")?.to_ascii_lowercase().as_str() {
+        "rsa-sha256" => Algorithm::RsaSha256,
+        "ed25519-sha256" => Algorithm::Ed25519Sha256,
+        "rsa-sha1" => return Err("rsa-sha1 is not accepted"),
+        _ => return Err("unsupported algorithm"),
+    };
+    let canon = |s: &str| match s.to_ascii_lowercase().as_str() {
+        "simple" => Ok(Canonicalization::Simple),
+        "relaxed" => Ok(Canonicalization::Relaxed),
+        _ => Err("unsupported canonicalization"),
+    };
+    let (header_canon, body_canon) = match tag(&tags, "c").unwrap_or("simple").split_once('/') {
+        Some((h, b)) => (canon(h)?, canon(b)?),
+        None => (
+            canon(tag(&tags, "c").unwrap_or("simple"))?,
+            Canonicalization::Simple,
+        ),
+    };
+    let domain = required("d")?.trim_end_matches('.').to_ascii_lowercase();
+    let identity = tag(&tags, "i")
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("@{}", domain));
+    let identity_domain = identity
+        .rsplit_once('@')
+        .map(|(_, d)| d.to_ascii_lowercase())
+        .ok_or("invalid identity")?;
+    if identity_domain != domain && !identity_domain.ends_with(&format!(".{}", domain)) {
+        return Err("identity is outside the signing domain");
+    }
+    let signed_headers: Vec<String> = required("h")?
+        .split(':')
+        .map(|h| h.trim_matches([' ', '\t', '\r', '\n']).to_ascii_lowercase())
+        .collect();
+    if !signed_headers.iter().any(|h| h == "from") {
+        return Err("From is not signed");
+    }
+    let number = |name| {
+        tag(&tags, name)
+            .map(|v| v.parse::<u64>().map_err(|_| "invalid number"))
+            .transpose()
+    };
+    Ok(Signature {
+        algorithm,
+        signature: decode_base64(required("b")?)?,
+        body_hash: decode_base64(required("bh")?)?,
+        header_canon,
+        body_canon,
+        selector: required("s")?.to_ascii_lowercase(),
+        domain,
+        identity,
+        signed_headers,
+        body_length: number("l")?.map(|l| l as usize),
+        expires: number("x")?.map(|x| x as i64),
+    })
+}
+
+// ── Canonicalization (RFC 6376 §3.4) ──
+
+/// Collapse runs of spaces and tabs into one space.
+fn collapse_wsp(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut in_wsp = false;
+    for c in text.chars() {
+        if c == ' ' || c == '\t' {
+            if !in_wsp {
+                out.push(' ');
+            }
+            in_wsp = true;
+        } else {
+            out.push(c);
+            in_wsp = false;
+        }
+    }
+    out
+}
+
+fn canonical_header(field: &str, canon: Canonicalization) -> String {
+    match canon {
+        Canonicalization::Simple => field.to_string(),
+        Canonicalization::Relaxed => {
+            let (name, value) = field.split_once(':').unwrap_or((field, ""));
+            let value = collapse_wsp(&value.replace("\r\n", ""));
+            format!(
+                "{}:{}",
+                name.trim_matches([' ', '\t']).to_ascii_lowercase(),
+                value.trim_matches(' ')
+            )
+        }
+    }
+}
+
+fn canonical_body(body: &str, canon: Canonicalization) -> String {
+    let mut lines: Vec<String> = body
+        .split("\r\n")
+        .map(|line| match canon {
+            Canonicalization::Simple => line.to_string(),
+            Canonicalization::Relaxed => collapse_wsp(line).trim_end_matches(' ').to_string(),
+        })
+        .collect();
+    while lines.last().is_some_and(|l| l.is_empty()) {
+        lines.pop();
+    }
+    if lines.is_empty() && canon == Canonicalization::Simple {
+        return "\r\n".to_string();
+    }
+    lines.iter().map(|l| format!("{}\r\n", l)).collect()
+}
+
+/// The signature header with the value of its `b=` tag removed.
+fn without_signature_value(field: &str) -> String {
+    let Some((name, value)) = field.split_once(':') else {
+        return field.to_string();
+    };
+    let specs: Vec<String> = value
+        .split(';')
+        .map(|spec| match spec.split_once('=') {
+            Some((tag, _)) if tag.trim_matches([' ', '\t', '\r', '\n']) == "b" => {
+                format!("{}=", tag)
+            }
+            _ => spec.to_string(),
+        })
+        .collect();
+    format!("{}:{}", name, specs.join(";"))
+}
+
+/// The data covered by the signature: the `h=` headers, each taken bottom-up,
+/// followed by the signature header itself without its trailing CRLF.
+fn signed_data(fields: &[&str], sig_field: &str, sig: &Signature) -> String {
+    let mut used = vec![false; fields.len()];
+    let mut data = String::new();
+    for name in &sig.signed_headers {
+        let found = (0..fields.len())
+            .rev()
+            .find(|&i| !used[i] && field_name(fields[i]) == *name);
+        if let Some(i) = found {
+            used[i] = true;
+            data.push_str(&canonical_header(fields[i], sig.header_canon));
+            data.push_str("\r\n");
+        }
+    }
+    data.push_str(&canonical_header(
+        &without_signature_value(sig_field),
+        sig.header_canon,
+    ));
+    data
+}
+
+// ── Keys ──
+
+/// One DER element: `(tag, contents, rest)`.
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let n = (first & 0x7f) as usize;
+        if n == 0 || n > 4 || rest.len() < n {
+            return None;
+        }
+        let len = rest[..n]
+            .iter()
+            .fold(0usize, |acc, &b| acc << 8 | b as usize);
+        (len, &rest[n..])
+    };
+    (rest.len() >= len).then(|| (tag, &rest[..len], &rest[len..]))
+}
+
+/// The PKCS#1 `RSAPublicKey` in a DKIM `p=` value, which is normally wrapped
+/// in a `SubjectPublicKeyInfo`.
+fn rsa_public_key(der: &[u8]) -> Option<&[u8]> {
+    let (tag, body, _) = der_element(der)?;
+    if tag != 0x30 {
+        return None;
+    }
+    let (inner_tag, _, rest) = der_element(body)?;
+    match inner_tag {
+        // SEQUENCE { INTEGER n, INTEGER e }: already PKCS#1.
+        0x02 => Some(der),
+        0x30 => {
+            let (bits_tag, bits, _) = der_element(rest)?;
+            (bits_tag == 0x03 && bits.first() == Some(&0)).then(|| &bits[1..])
+        }
+        _ => None,
+    }
+}
+
+/// Fetch the public key of `sig` from `<selector>._domainkey.<domain>`.
+fn public_key(sig: &Signature, resolver: &dyn Resolver) -> Result<Vec<u8>, DkimResult> {
+    let fail = |outcome, reason: &str| DkimResult {
+        outcome,
+        domain: sig.domain.clone(),
+        selector: sig.selector.clone(),
+        identity: sig.identity.clone(),
+        reason: reason.to_string(),
+    };
+    let name = format!("{}._domainkey.{}", sig.selector, sig.domain);
+    let records = resolver
+        .txt(&name)
+        .map_err(|_| fail(DkimOutcome::TempError, "key lookup failed"))?;
+    let tags = records
+        .iter()
+        .find_map(|r| parse_tags(r).ok())
+        .ok_or_else(|| fail(DkimOutcome::PermError, "no key record"))?;
+
+    if tag(&tags, "v").is_some_and(|v| v != "DKIM1") {
+        return Err(fail(DkimOutcome::PermError, "invalid key record version"));
+    }
+    let expected = match sig.algorithm {
+        Algorithm::RsaSha256 => "rsa",
+        Algorithm::Ed25519Sha256 => "ed25519",
+    };
+    if !tag(&tags, "k")
+        .unwrap_or("rsa")
+        .eq_ignore_ascii_case(expected)
+    {
+        return Err(fail(
+            DkimOutcome::PermError,
+            "key type does not match algorithm",
+        ));
+    }
+    if tag(&tags, "h").is_some_and(|h| !h.split(':').any(|a| a.trim() == "sha256")) {
+        return Err(fail(DkimOutcome::PermError, "key does not allow sha256"));
+    }
+    let strict = tag(&tags, "t").is_some_and(|t| t.split(':').any(|f| f.trim() == "s"));
+    if strict
+        && !sig
+            .identity
+            .to_ascii_lowercase()
+            .ends_with(&format!("@{}", sig.domain))
+    {
+        return Err(fail(
+            DkimOutcome::PermError,
+            "identity must match the signing domain",
+        ));
+    }
+    match tag(&tags, "p") {
+        None => Err(fail(DkimOutcome::PermError, "key record has no p= tag")),
+        Some("") => Err(fail(DkimOutcome::PermError, "key revoked")),
+        Some(p) => decode_base64(p).map_err(|e| fail(DkimOutcome::PermError, e)),
+    }
+}
+
+// ── Verification ──
+
+fn verify_signature(
+    fields: &[&str],
+    body: &str,
+    sig_field: &str,
+    resolver: &dyn Resolver,
+) -> DkimResult {
+    let sig = match parse_signature(sig_field) {
+        Ok(sig) => sig,
+        Err(reason) => return DkimResult::error(DkimOutcome::PermError, reason),
+    };
+    let result = |outcome, reason: &str| DkimResult {
+        outcome,
+        domain: sig.domain.clone(),
+        selector: sig.selector.clone(),
+        identity: sig.identity.clone(),
+        reason: reason.to_string(),
+    };
+    if sig
+        .expires
+        .is_some_and(|x| x < chrono::Utc::now().timestamp())
+    {
+        return result(DkimOutcome::Fail, "signature expired");
+    }
+
+    let canonical = canonical_body(body, sig.body_canon);
+    let hashed = match sig.body_length {
+        Some(l) if l > canonical.len() => {
+            return result(DkimOutcome::Fail, "body shorter than l= tag");
+        }
+        Some(l) => &canonical.as_bytes()[..l],
+        None => canonical.as_bytes(),
+    };
+    if Sha256::digest(hashed).as_slice() != sig.body_hash.as_slice() {
+        return result(DkimOutcome::Fail, "body hash mismatch");
+    }
+
+    let key = match public_key(&sig, resolver) {
+        Ok(key) => key,
+        Err(result) => return result,
+    };
+    let data = signed_data(fields, sig_field, &sig);
+    let verified = match sig.algorithm {
+        Algorithm::RsaSha256 => match rsa_public_key(&key) {
+            Some(pkcs1) => {
+                UnparsedPublicKey::new(&RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, pkcs1)
+                    .verify(data.as_bytes(), &sig.signature)
+                    .is_ok()
+            }
+            None => return result(DkimOutcome::PermError, "invalid RSA key"),
+        },
+        // RFC 8463 §3: Ed25519 signs the SHA-256 hash of the header data.
+        Algorithm::Ed25519Sha256 => UnparsedPublicKey::new(&ED25519, &key)
+            .verify(&Sha256::digest(data.as_bytes()), &sig.signature)
+            .is_ok(),
+    };
+    if verified {
+        result(DkimOutcome::Pass, "")
+    } else {
+        result(DkimOutcome::Fail, "signature did not verify")
+    }
+}
+
+/// Verify every `DKIM-Signature` of `message`, top to bottom.  A message
+/// without signatures yields no results.
+pub fn verify(message: &str, resolver: &dyn Resolver) -> Vec<DkimResult> {
+    let message = to_crlf(message);
+    let (fields, body) = split_message(&message);
+    fields
+        .iter()
+        .filter(|f| field_name(f) == "dkim-signature")
+        .take(MAX_SIGNATURES)
+        .map(|sig_field| {
+            let result = verify_signature(&fields, body, sig_field, resolver);
+            debug!(
+                "[dkim] d={} s={}: {} {}",
+                result.domain,
+                result.selector,
+                result.outcome.as_str(),
+                result.reason
+            );
+            result
+        })
+        .collect()
+}
+
+/// Whether `results` call for rejection under the `reject` policy: at least
+/// one signature failed and none passed.  Temporary errors never reject.
+pub fn should_reject(results: &[DkimResult]) -> bool {
+    results.iter().any(|r| r.outcome == DkimOutcome::Fail)
+        && !results.iter().any(|r| r.outcome == DkimOutcome::Pass)
+}
+
+/// The `Authentication-Results` header (RFC 8601) for `results`.
+pub fn authentication_results_header(authserv_id: &str, results: &[DkimResult]) -> String {
+    let clean = |s: &str| s.replace(['\r', '\n', ';', '(', ')'], "");
+    let mut header = format!("Authentication-Results: {}", clean(authserv_id));
+    if results.is_empty() {
+        header.push_str("; dkim=none");
+    }
+    for r in results {
+        header.push_str(&format!("; dkim={}", r.outcome.as_str()));
+        if !r.reason.is_empty() {
+            header.push_str(&format!(" ({})", clean(&r.reason)));
+        }
+        if !r.domain.is_empty() {
+            header.push_str(&format!(
+                " header.d={} header.i={} header.s={}",
+                clean(&r.domain),
+                clean(&r.identity).replace(' ', ""),
+                clean(&r.selector)
+            ));
+        }
+    }
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{Ed25519KeyPair, KeyPair};
+    use std::net::IpAddr;
+
+    struct KeyResolver(String);
+
+    impl Resolver for KeyResolver {
+        fn txt(&self, name: &str) -> Result<Vec<String>, String> {
+            match name {
+                "sel._domainkey.example.com" => Ok(vec![self.0.clone()]),
+                "down._domainkey.example.com" => Err("timeout".to_string()),
+                _ => Ok(Vec::new()),
+            }
+        }
+
+        fn addrs(&self, _: &str) -> Result<Vec<IpAddr>, String> {
+            Ok(Vec::new())
+        }
+
+        fn mx(&self, _: &str) -> Result<Vec<String>, String> {
+            Ok(Vec::new())
+        }
+
+        fn ptr(&self, _: IpAddr) -> Result<Vec<String>, String> {
+            Ok(Vec::new())
+        }
+    }
+
+    const MESSAGE: &str = "Received: from mx.example.com\nFrom: Jane <jane@example.com>\nTo: bob@example.net\nSubject:  Hello   there \n\nHi Bob,  \n\nsee you.\n\n\n";
+
+    /// Sign `MESSAGE` with a fresh Ed25519 key, returning it and the key record.
+    fn signed(selector: &str) -> (String, String) {
+        let b64 = base64::engine::general_purpose::STANDARD;
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let record = format!("v=DKIM1; k=ed25519; p={}", b64.encode(pair.public_key()));
+
+        let message = to_crlf(MESSAGE);
+        let (_, body) = split_message(&message);
+        let bh = b64.encode(Sha256::digest(canonical_body(
+            body,
+            Canonicalization::Relaxed,
+        )));
+        let unsigned = format!(
+            "DKIM-Signature: v=1; a=ed25519-sha256; c=relaxed/relaxed; d=example.com;\r\n s={}; h=from:to:subject; bh={}; b=",
+            selector, bh
+        );
+        let with_sig = format!("{}\r\n{}", unsigned, message);
+        let (fields, _) = split_message(&with_sig);
+        let sig = parse_signature(&format!("{}AAAA", unsigned)).unwrap();
+        let data = signed_data(&fields, fields[0], &sig);
+        let b = b64.encode(pair.sign(&Sha256::digest(data.as_bytes())).as_ref());
+        (format!("{}{}\r\n{}", unsigned, b, message), record)
+    }
+
+    #[test]
+    fn canonicalization_matches_rfc_6376_examples() {
+        let message = to_crlf("A: X\nB : Y\t\n\tZ  \n\n C \nD \t E\n\n\n");
+        let (fields, body) = split_message(&message);
+        assert_eq!(fields, vec!["A: X", "B : Y\t\r\n\tZ  "]);
+        assert_eq!(
+            canonical_header(fields[0], Canonicalization::Relaxed),
+            "a:X"
+        );
+        assert_eq!(
+            canonical_header(fields[1], Canonicalization::Relaxed),
+            "b:Y Z"
+        );
+        assert_eq!(
+            canonical_body(body, Canonicalization::Relaxed),
+            " C\r\nD E\r\n"
+        );
+        assert_eq!(
+            canonical_body(body, Canonicalization::Simple),
+            " C \r\nD \t E\r\n"
+        );
+        assert_eq!(canonical_body("", Canonicalization::Simple), "\r\n");
+        assert_eq!(canonical_body("", Canonicalization::Relaxed), "");
+        assert_eq!(
+            without_signature_value("DKIM-Signature: a=x; bh=abc; b=sig\r\n ned; d=e"),
+            "DKIM-Signature: a=x; bh=abc; b=; d=e"
+        );
+    }
+
+    #[test]
+    fn valid_signature_passes_and_tampering_fails() {
+        let (message, record) = signed("sel");
+        let resolver = KeyResolver(record);
+        let results = verify(&message, &resolver);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].outcome, DkimOutcome::Pass, "{:?}", results[0]);
+        assert_eq!(results[0].identity, "@example.com");
+        assert!(!should_reject(&results));
+
+        let body_changed = message.replace("see you", "see you soon");
+        let results = verify(&body_changed, &resolver);
+        assert_eq!(results[0].outcome, DkimOutcome::Fail);
+        assert_eq!(results[0].reason, "body hash mismatch");
+        assert!(should_reject(&results));
+
+        let header_changed = message.replace("Hello", "Invoice");
+        assert_eq!(
+            verify(&header_changed, &resolver)[0].reason,
+            "signature did not verify"
+        );
+
+        // Whitespace changes survive relaxed canonicalization.
+        let rewrapped = message.replace("Subject:  Hello   there ", "Subject: Hello there");
+        assert_eq!(verify(&rewrapped, &resolver)[0].outcome, DkimOutcome::Pass);
+    }
+
+    #[test]
+    fn key_lookup_errors_and_header_format() {
+        let (message, _) = signed("down");
+        let results = verify(&message, &KeyResolver(String::new()));
+        assert_eq!(results[0].outcome, DkimOutcome::TempError);
+        assert!(!should_reject(&results));
+
+        let (message, _) = signed("missing");
+        assert_eq!(
+            verify(&message, &KeyResolver(String::new()))[0].reason,
+            "no key record"
+        );
+        let (message, _) = signed("sel");
+        let revoked = KeyResolver("v=DKIM1; k=ed25519; p=".to_string());
+        assert_eq!(verify(&message, &revoked)[0].reason, "key revoked");
+
+        let header = authentication_results_header("mx.example.net", &verify(&message, &revoked));
+        assert_eq!(
+            header,
+            "Authentication-Results: mx.example.net; dkim=permerror (key revoked) header.d=example.com header.i=@example.com header.s=sel"
+        );
+        assert_eq!(
+            authentication_results_header("mx.example.net", &[]),
+            "Authentication-Results: mx.example.net; dkim=none"
+        );
+        assert!(verify(MESSAGE, &revoked).is_empty());
+    }
+}
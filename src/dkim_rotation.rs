@@ -0,0 +1,196 @@
+//! DKIM key generation and rotation with a grace period.
+//!
+//! Rotating a domain's key switches signing to a new selector and key pair at
+//! once, while the previous selector stays on the domain so its DNS record
+//! can remain published.  Mail already signed with the old key keeps
+//! verifying until the grace period in the `dkim_rotation_grace_days`
+//! setting has passed; an hourly cleanup then forgets the old selector and
+//! logs that its record can be removed.
+
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, error, info};
+
+use crate::db::Database;
+
+pub const GRACE_SETTING: &str = "dkim_rotation_grace_days";
+pub const DEFAULT_GRACE_DAYS: i64 = 7;
+pub const MAX_GRACE_DAYS: i64 = 365;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Days the previous selector stays published after a rotation.
+pub fn grace_days(db: &Database) -> i64 {
+    db.get_setting(GRACE_SETTING)
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|d| (0..=MAX_GRACE_DAYS).contains(d))
+        .unwrap_or(DEFAULT_GRACE_DAYS)
+}
+
+/// Generate an RSA 2048 key pair with openssl, returning the PEM private
+/// and public keys, or a message for the admin.
+pub fn generate_keypair(domain: &str) -> Result<(String, String), &'static str> {
+    debug!(
+        "[dkim] generating RSA 2048 private key for domain={}",
+        domain
+    );
+    let private_key = match Command::new("openssl").args(["genrsa", "2048"]).output() {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout).to_string(),
+        Ok(o) => {
+            error!(
+                "[dkim] openssl genrsa failed for domain={}: {}",
+                domain,
+                String::from_utf8_lossy(&o.stderr)
+            );
+            return Err("Failed to generate DKIM private key.");
+        }
+        Err(e) => {
+            error!(
+                "[dkim] failed to run openssl genrsa for domain={}: {}",
+                domain, e
+            );
+            return Err("Failed to generate DKIM private key.");
+        }
+    };
+
+    debug!("[dkim] extracting public key for domain={}", domain);
+    let pub_output = Command::new("openssl")
+        .args(["rsa", "-pubout"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            use std::io::Write;
+            if let Some(ref mut stdin) = child.stdin {
+                stdin.write_all(private_key.as_bytes()).ok();
+            }
+            child.wait_with_output()
+        });
+    match pub_output {
+        Ok(o) if o.status.success() => {
+            Ok((private_key, String::from_utf8_lossy(&o.stdout).to_string()))
+        }
+        Ok(o) => {
+            error!(
+                "[dkim] openssl rsa -pubout failed for domain={}: {}",
+                domain,
+                String::from_utf8_lossy(&o.stderr)
+            );
+            Err("Failed to extract DKIM public key.")
+        }
+        Err(e) => {
+            error!(
+                "[dkim] failed to run openssl rsa -pubout for domain={}: {}",
+                domain, e
+            );
+            Err("Failed to extract DKIM public key.")
+        }
+    }
+}
+
+/// The base64 body of a PEM public key, as used in the DKIM `p=` tag.
+pub fn dns_public_key(pem: &str) -> String {
+    pem.lines()
+        .filter(|l| !l.starts_with("-----"))
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+/// Selector for the key that replaces `current`: its name without an earlier
+/// date suffix, followed by today's date, e.g. `mail` → `mail-20260101`.
+/// A second rotation on the same day adds the time so the selector differs
+/// from both `current` and `previous`.
+pub fn next_selector(current: &str, previous: Option<&str>, now: DateTime<Utc>) -> String {
+    let base = match current.rsplit_once('-') {
+        Some((base, suffix))
+            if !base.is_empty()
+                && (suffix.len() == 8 || suffix.len() == 14)
+                && suffix.bytes().all(|b| b.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => current,
+    };
+    let selector = format!("{}-{}", base, now.format("%Y%m%d"));
+    if selector == current || Some(selector.as_str()) == previous {
+        format!("{}-{}", base, now.format("%Y%m%d%H%M%S"))
+    } else {
+        selector
+    }
+}
+
+/// When the previous selector of a domain rotated at `rotated_at` expires.
+pub fn grace_ends(rotated_at: &str, grace_days: i64) -> Option<String> {
+    let rotated = chrono::NaiveDateTime::parse_from_str(rotated_at, "%Y-%m-%d %H:%M:%S").ok()?;
+    Some(
+        (rotated + chrono::Duration::days(grace_days))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string(),
+    )
+}
+
+/// Forget previous selectors whose grace period has passed.
+fn clear_expired(db: &Database) {
+    let cutoff = (Utc::now() - chrono::Duration::days(grace_days(db)))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    for (domain, selector) in db.clear_expired_dkim_prev(&cutoff) {
+        info!(
+            "[dkim] grace period over for domain={}: the {}._domainkey.{} TXT record can be removed",
+            domain, selector, domain
+        );
+    }
+}
+
+/// Start the background thread that ends DKIM rotation grace periods.
+pub fn start_cleanup(db: Database) {
+    info!(
+        "[dkim] starting rotation cleanup (every {}s)",
+        CLEANUP_INTERVAL.as_secs()
+    );
+    crate::shutdown::spawn("DKIM rotation cleanup", move || loop {
+        clear_expired(&db);
+        if crate::shutdown::wait(CLEANUP_INTERVAL) {
+            break;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn next_selector_replaces_the_date_suffix() {
+        let day = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(next_selector("mail", None, day), "mail-20260102");
+        assert_eq!(
+            next_selector("mail-20250101", Some("mail"), day),
+            "mail-20260102"
+        );
+        assert_eq!(
+            next_selector("mail-20260102", Some("mail-20250101"), day),
+            "mail-20260102030405"
+        );
+        assert_eq!(next_selector("s-1", None, day), "s-1-20260102");
+    }
+
+    #[test]
+    fn grace_ends_after_the_configured_days() {
+        assert_eq!(
+            grace_ends("2026-01-30 12:00:00", 7).as_deref(),
+            Some("2026-02-06 12:00:00")
+        );
+        assert_eq!(grace_ends("", 7), None);
+    }
+
+    #[test]
+    fn dns_public_key_strips_pem_armor() {
+        let pem = "-----BEGIN PUBLIC KEY-----\nMIIB\nIjAN\n-----END PUBLIC KEY-----\n";
+        assert_eq!(dns_public_key(pem), "MIIBIjAN");
+    }
+}
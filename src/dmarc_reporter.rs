@@ -0,0 +1,609 @@
+//! DMARC aggregate reports sent by this server (RFC 7489 §7.2).
+//!
+//! For domains with `dmarc_reports_enabled`, the incoming content filter
+//! records the SPF and DKIM outcome of every message from the
+//! `Authentication-Results` and `Received-SPF` headers the filter itself
+//! adds.  Once an hour a background job collects the complete
+//! UTC days that have not been reported yet, looks up the `_dmarc` record of
+//! each `From:` domain, and mails a gzip'd XML report to its `rua=` addresses
+//! through the local Postfix, so the outbound relay configuration and DKIM
+//! signing apply.  Every report, sent or skipped, is recorded so it is never
+//! sent twice.
+
+use std::io::Write;
+use std::time::Duration;
+
+use chrono::{NaiveDate, Utc};
+use log::{debug, error, info, warn};
+use quick_xml::escape::escape;
+
+use crate::db::{Database, DmarcAuthResult, DmarcSentReport};
+
+const RUN_INTERVAL: Duration = Duration::from_secs(3600);
+/// Days that are still reported if the job was down; older results are pruned.
+const LOOKBACK_DAYS: i64 = 7;
+
+// ── Inbound results ──
+
+/// The value of each header `name` in the header block of `email`, topmost first.
+fn header_values(email: &str, name: &str) -> Vec<String> {
+    let Ok((headers, _)) = mailparse::parse_headers(email.as_bytes()) else {
+        return Vec::new();
+    };
+    headers
+        .iter()
+        .filter(|h| h.get_key().eq_ignore_ascii_case(name))
+        .map(|h| h.get_value())
+        .collect()
+}
+
+/// The first header `name` above the topmost `Received:` header.  Milters
+/// insert their results at the top, above the `Received:` header Postfix
+/// added for this hop, so anything further down came from the sender.
+fn local_header(email: &str, name: &str) -> Option<String> {
+    let (headers, _) = mailparse::parse_headers(email.as_bytes()).ok()?;
+    headers
+        .iter()
+        .take_while(|h| !h.get_key().eq_ignore_ascii_case("Received"))
+        .find(|h| h.get_key().eq_ignore_ascii_case(name))
+        .map(|h| h.get_value())
+}
+
+/// Lowercase domain of an address, `Name <user@domain>` or bare domain.
+fn domain_of(value: &str) -> String {
+    let addr = match (value.rfind('<'), value.rfind('>')) {
+        (Some(start), Some(end)) if start < end => &value[start + 1..end],
+        _ => value,
+    };
+    addr.rsplit('@')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .trim_end_matches('.')
+        .to_lowercase()
+}
+
+/// Remove RFC 5322 comments, e.g. `(2048-bit key)`.
+fn strip_comments(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut depth = 0usize;
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            _ if depth == 0 => out.push(c),
+            _ => {}
+        }
+    }
+    out
+}
+
+/// `(method, result, properties)` of one RFC 8601 result.
+type ResInfo = (String, String, Vec<(String, String)>);
+
+/// Each resinfo of an `Authentication-Results` value, skipping the authserv-id.
+fn parse_authentication_results(value: &str) -> Vec<ResInfo> {
+    strip_comments(value)
+        .split(';')
+        .skip(1)
+        .filter_map(|resinfo| {
+            let mut tokens = resinfo.split_whitespace();
+            let (method, result) = tokens.next()?.split_once('=')?;
+            let props = tokens
+                .filter_map(|t| t.split_once('='))
+                .map(|(k, v)| (k.to_lowercase(), v.trim_matches('"').to_string()))
+                .collect();
+            Some((method.to_lowercase(), result.to_lowercase(), props))
+        })
+        .collect()
+}
+
+/// First public address in the topmost `Received:` header: the client that
+/// handed the message to us.
+fn source_ip(email: &str) -> Option<String> {
+    let received = header_values(email, "Received").into_iter().next()?;
+    let start = received.find('[')?;
+    let end = received[start..].find(']')? + start;
+    let literal = &received[start + 1..end];
+    let ip: std::net::IpAddr = literal
+        .strip_prefix("IPv6:")
+        .unwrap_or(literal)
+        .parse()
+        .ok()?;
+    let private = match ip {
+        std::net::IpAddr::V4(v4) => v4.is_loopback() || v4.is_private() || v4.is_link_local(),
+        std::net::IpAddr::V6(v6) => v6.is_loopback() || (v6.segments()[0] & 0xfe00) == 0xfc00,
+    };
+    (!private).then(|| ip.to_string())
+}
+
+/// SPF and DKIM outcome of an inbound message, or `None` when there is no
+/// usable `From:` domain or public client address to report.
+pub fn auth_result(email: &str, sender: &str, recipient_domain: &str) -> Option<DmarcAuthResult> {
+    let header_from = domain_of(&header_values(email, "From").into_iter().next()?);
+    if header_from.is_empty() || !header_from.contains('.') {
+        return None;
+    }
+    let mut result = DmarcAuthResult {
+        recipient_domain: recipient_domain.to_lowercase(),
+        header_from,
+        source_ip: source_ip(email)?,
+        dkim_result: "none".to_string(),
+        spf_domain: domain_of(sender),
+        spf_result: "none".to_string(),
+        ..Default::default()
+    };
+
+    if let Some(value) = local_header(email, "Authentication-Results") {
+        let results = parse_authentication_results(&value);
+        let dkim = results
+            .iter()
+            .filter(|(method, _, _)| method == "dkim")
+            .max_by_key(|(_, result, _)| result == "pass");
+        if let Some((_, outcome, props)) = dkim {
+            result.dkim_result = outcome.clone();
+            result.dkim_domain = props
+                .iter()
+                .find(|(k, _)| k == "header.d" || k == "header.i")
+                .map(|(_, v)| domain_of(v))
+                .unwrap_or_default();
+        }
+        if let Some((_, outcome, props)) = results.iter().find(|(method, _, _)| method == "spf") {
+            result.spf_result = outcome.clone();
+            if let Some((_, v)) = props.iter().find(|(k, _)| k == "smtp.mailfrom") {
+                result.spf_domain = domain_of(v);
+            }
+        }
+    }
+    if let Some(value) = local_header(email, "Received-SPF") {
+        if let Some(outcome) = value.split_whitespace().next() {
+            result.spf_result = outcome.to_lowercase();
+        }
+    }
+    if result.spf_domain.is_empty() {
+        // Null reverse-path: SPF checks the HELO identity (RFC 7208 §2.4).
+        result.spf_domain = header_values(email, "Received")
+            .first()
+            .and_then(|r| r.split_whitespace().nth(1).map(|helo| helo.to_lowercase()))
+            .unwrap_or_default();
+    }
+    Some(result)
+}
+
+/// Record the authentication results of an inbound message for every
+/// recipient domain that sends DMARC reports.
+pub fn record_inbound(db: &Database, email: &str, sender: &str, recipients: &[String]) {
+    let mut domains: Vec<String> = recipients
+        .iter()
+        .filter_map(|r| r.rsplit_once('@').map(|(_, d)| d.to_lowercase()))
+        .collect();
+    domains.sort();
+    domains.dedup();
+    for domain in domains {
+        if !db.is_dmarc_reporting_enabled(&domain) {
+            continue;
+        }
+        match auth_result(email, sender, &domain) {
+            Some(result) => db.record_dmarc_auth_result(&result),
+            None => debug!(
+                "[dmarc-report] no From: domain or public client address, not recording for {}",
+                domain
+            ),
+        }
+    }
+}
+
+// ── Published policy ──
+
+/// The tags of a `_dmarc` TXT record that a report needs.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PublishedPolicy {
+    pub domain: String,
+    pub adkim: String,
+    pub aspf: String,
+    pub p: String,
+    pub sp: String,
+    pub pct: String,
+    /// `mailto:` addresses of the `rua=` tag.
+    pub rua: Vec<String>,
+}
+
+pub fn parse_dmarc_record(domain: &str, txt: &str) -> Option<PublishedPolicy> {
+    let mut tags = txt.split(';').filter_map(|t| t.split_once('='));
+    let (v, version) = tags.next()?;
+    if v.trim() != "v" || !version.trim().eq_ignore_ascii_case("DMARC1") {
+        return None;
+    }
+    let mut policy = PublishedPolicy {
+        domain: domain.to_string(),
+        adkim: "r".to_string(),
+        aspf: "r".to_string(),
+        p: "none".to_string(),
+        sp: String::new(),
+        pct: "100".to_string(),
+        rua: Vec::new(),
+    };
+    for (tag, value) in tags {
+        let value = value.trim().to_string();
+        match tag.trim() {
+            "adkim" => policy.adkim = value,
+            "aspf" => policy.aspf = value,
+            "p" => policy.p = value,
+            "sp" => policy.sp = value,
+            "pct" => policy.pct = value,
+            "rua" => {
+                policy.rua = value
+                    .split(',')
+                    .filter_map(|uri| uri.trim().strip_prefix("mailto:"))
+                    // Drop the optional size limit, e.g. `!10m`.
+                    .map(|addr| addr.split('!').next().unwrap_or(addr).to_string())
+                    .filter(|addr| addr.contains('@'))
+                    .collect()
+            }
+            _ => {}
+        }
+    }
+    if policy.sp.is_empty() {
+        policy.sp = policy.p.clone();
+    }
+    Some(policy)
+}
+
+/// Organizational domain: the last two labels.  Without the Public Suffix
+/// List this is wrong for names like `example.co.uk`, where it only means
+/// the lookup falls through to no record.
+fn org_domain(domain: &str) -> &str {
+    let mut dots = domain.rmatch_indices('.');
+    match (dots.next(), dots.next()) {
+        (Some(_), Some((i, _))) => &domain[i + 1..],
+        _ => domain,
+    }
+}
+
+/// The DMARC policy covering `header_from`: its own record, else that of the
+/// organizational domain (RFC 7489 §6.6.3).
+fn lookup_policy(header_from: &str) -> Option<PublishedPolicy> {
+    let lookup = |domain: &str| {
+        crate::web::routes::domains::query_txt_records(&format!("_dmarc.{}", domain))
+            .iter()
+            .find_map(|txt| parse_dmarc_record(domain, txt))
+    };
+    lookup(header_from).or_else(|| {
+        let org = org_domain(header_from);
+        (org != header_from).then(|| lookup(org)).flatten()
+    })
+}
+
+/// Whether a report destination outside the policy domain agreed to receive
+/// its reports (RFC 7489 §7.1).
+fn rua_authorized(policy_domain: &str, rua: &str) -> bool {
+    let rua_domain = domain_of(rua);
+    if org_domain(&rua_domain) == org_domain(policy_domain) {
+        return true;
+    }
+    let name = format!("{}._report._dmarc.{}", policy_domain, rua_domain);
+    crate::web::routes::domains::query_txt_records(&name)
+        .iter()
+        .any(|txt| txt.trim().to_ascii_lowercase().starts_with("v=dmarc1"))
+}
+
+// ── Report ──
+
+fn aligned(auth_domain: &str, header_from: &str, mode: &str) -> bool {
+    if auth_domain.is_empty() {
+        return false;
+    }
+    if mode == "s" {
+        auth_domain == header_from
+    } else {
+        org_domain(auth_domain) == org_domain(header_from)
+    }
+}
+
+/// RFC 7489 appendix C aggregate report XML for one day's results.
+pub fn build_report_xml(
+    reporter: &str,
+    report_id: &str,
+    begin: i64,
+    end: i64,
+    policy: &PublishedPolicy,
+    rows: &[(DmarcAuthResult, i64)],
+) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<feedback>\n");
+    xml.push_str("  <version>1.0</version>\n");
+    xml.push_str(&format!(
+        "  <report_metadata>\n    <org_name>{}</org_name>\n    <email>postmaster@{}</email>\n    <report_id>{}</report_id>\n    <date_range>\n      <begin>{}</begin>\n      <end>{}</end>\n    </date_range>\n  </report_metadata>\n",
+        escape(reporter),
+        escape(reporter),
+        escape(report_id),
+        begin,
+        end
+    ));
+    xml.push_str(&format!(
+        "  <policy_published>\n    <domain>{}</domain>\n    <adkim>{}</adkim>\n    <aspf>{}</aspf>\n    <p>{}</p>\n    <sp>{}</sp>\n    <pct>{}</pct>\n  </policy_published>\n",
+        escape(&policy.domain),
+        escape(&policy.adkim),
+        escape(&policy.aspf),
+        escape(&policy.p),
+        escape(&policy.sp),
+        escape(&policy.pct)
+    ));
+    for (r, count) in rows {
+        let pass_or_fail = |ok: bool| if ok { "pass" } else { "fail" };
+        let dkim =
+            r.dkim_result == "pass" && aligned(&r.dkim_domain, &r.header_from, &policy.adkim);
+        let spf = r.spf_result == "pass" && aligned(&r.spf_domain, &r.header_from, &policy.aspf);
+        xml.push_str("  <record>\n    <row>\n");
+        xml.push_str(&format!(
+            "      <source_ip>{}</source_ip>\n      <count>{}</count>\n",
+            escape(&r.source_ip),
+            count
+        ));
+        // Mail is delivered whatever the DMARC outcome.
+        xml.push_str(&format!(
+            "      <policy_evaluated>\n        <disposition>none</disposition>\n        <dkim>{}</dkim>\n        <spf>{}</spf>\n      </policy_evaluated>\n    </row>\n",
+            pass_or_fail(dkim),
+            pass_or_fail(spf)
+        ));
+        xml.push_str(&format!(
+            "    <identifiers>\n      <header_from>{}</header_from>\n    </identifiers>\n    <auth_results>\n",
+            escape(&r.header_from)
+        ));
+        if !r.dkim_domain.is_empty() {
+            xml.push_str(&format!(
+                "      <dkim>\n        <domain>{}</domain>\n        <result>{}</result>\n      </dkim>\n",
+                escape(&r.dkim_domain),
+                escape(&r.dkim_result)
+            ));
+        }
+        xml.push_str(&format!(
+            "      <spf>\n        <domain>{}</domain>\n        <result>{}</result>\n      </spf>\n    </auth_results>\n  </record>\n",
+            escape(&r.spf_domain),
+            escape(&r.spf_result)
+        ));
+    }
+    xml.push_str("</feedback>\n");
+    xml
+}
+
+fn gzip(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+/// Build the report email (RFC 7489 §7.2.1.1) and hand it to Postfix.
+fn send_report(
+    reporter: &str,
+    policy: &PublishedPolicy,
+    report_id: &str,
+    begin: i64,
+    end: i64,
+    gz: Vec<u8>,
+) -> Result<(), String> {
+    use lettre::message::header::ContentType;
+    use lettre::message::{Attachment, MultiPart, SinglePart};
+
+    let from = format!("postmaster@{}", reporter);
+    let mut builder = lettre::Message::builder()
+        .from(
+            from.parse()
+                .map_err(|e| format!("invalid sender {}: {}", from, e))?,
+        )
+        .subject(format!(
+            "Report Domain: {} Submitter: {} Report-ID: <{}>",
+            policy.domain, reporter, report_id
+        ));
+    for rua in &policy.rua {
+        builder = builder.to(rua
+            .parse()
+            .map_err(|e| format!("invalid rua {}: {}", rua, e))?);
+    }
+    let filename = format!("{}!{}!{}!{}.xml.gz", reporter, policy.domain, begin, end);
+    let email = builder
+        .multipart(
+            MultiPart::mixed()
+                .singlepart(SinglePart::plain(format!(
+                    "This is a DMARC aggregate report for {} from {}.",
+                    policy.domain, reporter
+                )))
+                .singlepart(
+                    Attachment::new(filename)
+                        .body(gz, ContentType::parse("application/gzip").unwrap()),
+                ),
+        )
+        .map_err(|e| format!("failed to build report email: {}", e))?;
+    let raw = String::from_utf8_lossy(&email.formatted()).into_owned();
+    crate::filter::reinject_smtp(&raw, &from, &policy.rua).map_err(|e| e.to_string())
+}
+
+/// Send the report of one `(domain, header_from, day)` group.  Returns
+/// `false` when sending failed and should be retried on the next run.
+fn report_group(db: &Database, domain: &str, header_from: &str, report_date: &str) -> bool {
+    let Ok(day) = NaiveDate::parse_from_str(report_date, "%Y-%m-%d") else {
+        warn!(
+            "[dmarc-report] ignoring results with invalid date {}",
+            report_date
+        );
+        return true;
+    };
+    let begin = day.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+    let end = begin + 86_399;
+    let rows = db.dmarc_feedback_rows(domain, header_from, report_date);
+    let mut sent = DmarcSentReport {
+        domain: domain.to_string(),
+        header_from: header_from.to_string(),
+        report_date: report_date.to_string(),
+        report_id: format!("{}.{}.{}", domain, begin, uuid::Uuid::new_v4().simple()),
+        message_count: rows.iter().map(|(_, count)| count).sum(),
+        ..Default::default()
+    };
+
+    let Some(mut policy) = lookup_policy(header_from) else {
+        debug!(
+            "[dmarc-report] {} publishes no DMARC record, skipping",
+            header_from
+        );
+        sent.status = "skipped".to_string();
+        db.record_dmarc_report_sent(&sent);
+        return true;
+    };
+    policy.rua.retain(|rua| rua_authorized(&policy.domain, rua));
+    if policy.rua.is_empty() {
+        debug!(
+            "[dmarc-report] {} publishes no usable rua= address, skipping",
+            policy.domain
+        );
+        sent.status = "skipped".to_string();
+        db.record_dmarc_report_sent(&sent);
+        return true;
+    }
+
+    let xml = build_report_xml(domain, &sent.report_id, begin, end, &policy, &rows);
+    let gz = match gzip(xml.as_bytes()) {
+        Ok(gz) => gz,
+        Err(e) => {
+            error!("[dmarc-report] failed to compress report: {}", e);
+            return false;
+        }
+    };
+    sent.rua = policy.rua.join(", ");
+    match send_report(domain, &policy, &sent.report_id, begin, end, gz) {
+        Ok(()) => {
+            info!(
+                "[dmarc-report] sent report {} for {} ({} messages on {}) to {}",
+                sent.report_id, header_from, sent.message_count, report_date, sent.rua
+            );
+            sent.status = "sent".to_string();
+            db.record_dmarc_report_sent(&sent);
+            true
+        }
+        Err(e) => {
+            error!(
+                "[dmarc-report] failed to send report for {} on {} to {}: {}",
+                header_from, report_date, sent.rua, e
+            );
+            false
+        }
+    }
+}
+
+/// Report every complete day that has not been reported yet.
+pub fn run_once(db: &Database) {
+    let today = Utc::now().date_naive();
+    let since = today - chrono::Duration::days(LOOKBACK_DAYS);
+    let today = today.format("%Y-%m-%d").to_string();
+    let since = since.format("%Y-%m-%d").to_string();
+    for (domain, header_from, report_date) in db.list_pending_dmarc_feedback(&since, &today) {
+        report_group(db, &domain, &header_from, &report_date);
+    }
+    db.prune_dmarc_auth_results(&since);
+}
+
+/// Start the background thread that sends the daily reports.
+pub fn start_scheduler(db: Database) {
+    info!(
+        "[dmarc-report] starting aggregate report sender (every {}s)",
+        RUN_INTERVAL.as_secs()
+    );
+    crate::shutdown::spawn("DMARC report sender", move || loop {
+        run_once(&db);
+        if crate::shutdown::wait(RUN_INTERVAL) {
+            break;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INBOUND: &str = concat!(
+        "Authentication-Results: mx.example.net;\r\n",
+        "\tdkim=pass (2048-bit key; unprotected) header.d=news.sender.com header.i=@news.sender.com header.b=\"abc\";\r\n",
+        "\tspf=pass smtp.mailfrom=bounce@mail.sender.com\r\n",
+        "Received: from out.sender.com (out.sender.com [198.51.100.7])\r\n",
+        "\tby mx.example.net (Postfix) with ESMTPS id ABC;\r\n",
+        "Received-SPF: fail (forged by the sender)\r\n",
+        "From: \"Sender News\" <news@sender.com>\r\n",
+        "To: alice@example.net\r\n",
+        "\r\n",
+        "Body.\r\n"
+    );
+
+    #[test]
+    fn inbound_results_come_from_local_headers_only() {
+        let r = auth_result(INBOUND, "bounce@mail.sender.com", "Example.net").unwrap();
+        assert_eq!(r.recipient_domain, "example.net");
+        assert_eq!(r.header_from, "sender.com");
+        assert_eq!(r.source_ip, "198.51.100.7");
+        assert_eq!(
+            (r.dkim_domain.as_str(), r.dkim_result.as_str()),
+            ("news.sender.com", "pass")
+        );
+        assert_eq!(
+            (r.spf_domain.as_str(), r.spf_result.as_str()),
+            ("mail.sender.com", "pass")
+        );
+
+        // Local submissions carry no public client address and are not reported.
+        let local = INBOUND.replace("198.51.100.7", "127.0.0.1");
+        assert_eq!(
+            auth_result(&local, "bounce@mail.sender.com", "example.net"),
+            None
+        );
+    }
+
+    #[test]
+    fn dmarc_record_tags_and_rua_addresses() {
+        let policy = parse_dmarc_record(
+            "sender.com",
+            "v=DMARC1; p=quarantine; adkim=s; rua=mailto:dmarc@sender.com!10m, https://x.example/r,mailto:agg@reports.example",
+        )
+        .unwrap();
+        assert_eq!(policy.p, "quarantine");
+        assert_eq!(policy.sp, "quarantine");
+        assert_eq!(policy.adkim, "s");
+        assert_eq!(policy.aspf, "r");
+        assert_eq!(policy.rua, vec!["dmarc@sender.com", "agg@reports.example"]);
+        assert_eq!(parse_dmarc_record("sender.com", "v=spf1 -all"), None);
+        assert_eq!(org_domain("a.b.sender.com"), "sender.com");
+        assert_eq!(org_domain("sender.com"), "sender.com");
+    }
+
+    #[test]
+    fn report_xml_round_trips_through_the_aggregate_parser() {
+        let policy = parse_dmarc_record("sender.com", "v=DMARC1; p=reject; adkim=s").unwrap();
+        let r = auth_result(INBOUND, "bounce@mail.sender.com", "example.net").unwrap();
+        let forged = DmarcAuthResult {
+            source_ip: "203.0.113.9".to_string(),
+            dkim_result: "none".to_string(),
+            dkim_domain: String::new(),
+            spf_domain: "spoof.example".to_string(),
+            spf_result: "pass".to_string(),
+            ..r.clone()
+        };
+        let xml = build_report_xml(
+            "example.net",
+            "example.net.1708387200.1",
+            1708387200,
+            1708473599,
+            &policy,
+            &[(r, 12), (forged, 1)],
+        );
+        let gz = gzip(xml.as_bytes()).unwrap();
+
+        let report = crate::web::routes::dmarc::parse_aggregate_report(&gz).unwrap();
+        assert_eq!(report.meta.org_name, "example.net");
+        assert_eq!(report.policy.p, "reject");
+        assert_eq!(report.records.len(), 2);
+        // Strict DKIM alignment fails for news.sender.com; relaxed SPF passes.
+        assert_eq!(report.records[0].dkim_result, "fail");
+        assert_eq!(report.records[0].spf_result, "pass");
+        assert_eq!(report.records[0].messages(), 12);
+        assert_eq!(report.records[0].auth_dkim_domain, "news.sender.com");
+        // SPF passed for an unaligned domain.
+        assert_eq!(report.records[1].spf_result, "fail");
+        assert!(!report.records[1].passed());
+    }
+}
@@ -0,0 +1,408 @@
+//! Server DNS validation for domain setup.
+//!
+//! Checks that a domain's MX points at this server's `hostname`, that the
+//! hostname's A/AAAA records resolve to the server's public IP, and that the
+//! reverse DNS of that IP names the hostname again.  Each check carries
+//! remediation guidance for the domain check page.
+//!
+//! The public IP is discovered by the method in the `public_ip_method`
+//! setting (or the `PUBLIC_IP_METHOD` environment variable): `interface`
+//! (the default) uses the address of the outbound interface, a literal IP is
+//! taken as is, and an `http(s)://` URL is fetched and expected to answer with
+//! the bare address, as services like `https://api.ipify.org` do.
+
+use std::net::{IpAddr, UdpSocket};
+use std::time::Duration;
+
+use log::{debug, warn};
+
+use crate::db::Database;
+use crate::spf::Resolver;
+
+pub const PUBLIC_IP_SETTING: &str = "public_ip_method";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PublicIpMethod {
+    Interface,
+    Fixed(IpAddr),
+    Url(String),
+}
+
+impl PublicIpMethod {
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.is_empty() || s.eq_ignore_ascii_case("interface") {
+            return Some(PublicIpMethod::Interface);
+        }
+        if s.starts_with("http://") || s.starts_with("https://") {
+            return Some(PublicIpMethod::Url(s.to_string()));
+        }
+        s.parse().ok().map(PublicIpMethod::Fixed)
+    }
+
+    /// The configured method, from the setting, then the environment.
+    pub fn configured(db: &Database) -> Self {
+        db.get_setting(PUBLIC_IP_SETTING)
+            .or_else(|| std::env::var("PUBLIC_IP_METHOD").ok())
+            .and_then(|v| PublicIpMethod::parse(&v))
+            .unwrap_or(PublicIpMethod::Interface)
+    }
+
+    /// The server's public IP, or why it could not be determined.
+    pub fn discover(&self) -> Result<IpAddr, String> {
+        match self {
+            PublicIpMethod::Fixed(ip) => Ok(*ip),
+            PublicIpMethod::Interface => {
+                // Connecting a UDP socket only selects a route; nothing is sent.
+                let local = UdpSocket::bind("0.0.0.0:0")
+                    .and_then(|s| s.connect("8.8.8.8:53").map(|_| s))
+                    .and_then(|s| s.local_addr())
+                    .map_err(|e| format!("no outbound interface: {}", e))?
+                    .ip();
+                crate::geoip::parse_public_ip(&local.to_string()).ok_or_else(|| {
+                    format!(
+                        "outbound interface address {} is private (is the server behind NAT?)",
+                        local
+                    )
+                })
+            }
+            PublicIpMethod::Url(url) => {
+                let body = reqwest::blocking::Client::builder()
+                    .timeout(Duration::from_secs(5))
+                    .build()
+                    .and_then(|c| c.get(url).send())
+                    .and_then(|r| r.error_for_status())
+                    .and_then(|r| r.text())
+                    .map_err(|e| format!("fetching {} failed: {}", url, e))?;
+                body.trim()
+                    .parse()
+                    .map_err(|_| format!("{} did not answer with an IP address", url))
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for PublicIpMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PublicIpMethod::Interface => f.write_str("interface"),
+            PublicIpMethod::Fixed(ip) => write!(f, "{}", ip),
+            PublicIpMethod::Url(url) => f.write_str(url),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CheckStatus::Ok => "ok",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        }
+    }
+}
+
+/// Outcome of one record check.  `remedy` is empty when nothing needs doing.
+#[derive(Clone, Debug)]
+pub struct SetupCheck {
+    pub record: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+    pub remedy: String,
+}
+
+impl SetupCheck {
+    fn ok(record: &'static str, detail: String) -> Self {
+        SetupCheck {
+            record,
+            status: CheckStatus::Ok,
+            detail,
+            remedy: String::new(),
+        }
+    }
+
+    fn problem(record: &'static str, status: CheckStatus, detail: String, remedy: String) -> Self {
+        SetupCheck {
+            record,
+            status,
+            detail,
+            remedy,
+        }
+    }
+}
+
+fn same_host(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+fn join<T: ToString>(items: &[T]) -> String {
+    items
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Run the MX, A/AAAA and PTR checks for `domain` served by `hostname`.
+/// `public_ip` is the discovered address, or why discovery failed.
+pub fn check(
+    domain: &str,
+    hostname: &str,
+    public_ip: &Result<IpAddr, String>,
+    resolver: &dyn Resolver,
+) -> Vec<SetupCheck> {
+    debug!(
+        "[dns-setup] checking domain={} hostname={} public_ip={:?}",
+        domain, hostname, public_ip
+    );
+    let mx_remedy = format!("Publish \"{}. MX 10 {}.\"", domain, hostname);
+    let mx = match resolver.mx(domain) {
+        Err(e) => SetupCheck::problem(
+            "MX",
+            CheckStatus::Warn,
+            format!("MX lookup for {} failed: {}", domain, e),
+            "Retry later; if it persists, check that the domain's nameservers answer.".to_string(),
+        ),
+        Ok(hosts) if hosts.is_empty() => SetupCheck::problem(
+            "MX",
+            CheckStatus::Fail,
+            format!("{} has no MX records", domain),
+            mx_remedy,
+        ),
+        Ok(hosts) if hosts.iter().any(|h| same_host(h, hostname)) => {
+            SetupCheck::ok("MX", format!("{} → {}", domain, join(&hosts)))
+        }
+        Ok(hosts) => SetupCheck::problem(
+            "MX",
+            CheckStatus::Fail,
+            format!("MX points at {}, not {}", join(&hosts), hostname),
+            mx_remedy,
+        ),
+    };
+
+    let address_remedy = match public_ip {
+        Ok(ip) if ip.is_ipv6() => format!("Publish \"{}. AAAA {}\"", hostname, ip),
+        Ok(ip) => format!("Publish \"{}. A {}\"", hostname, ip),
+        Err(_) => format!(
+            "Publish an A (or AAAA) record for {} with the server's public IP",
+            hostname
+        ),
+    };
+    let resolved = resolver.addrs(hostname);
+    let address = match (&resolved, public_ip) {
+        (Err(e), _) => SetupCheck::problem(
+            "A/AAAA",
+            CheckStatus::Warn,
+            format!("address lookup for {} failed: {}", hostname, e),
+            "Retry later; if it persists, check that the hostname's nameservers answer."
+                .to_string(),
+        ),
+        (Ok(addrs), _) if addrs.is_empty() => SetupCheck::problem(
+            "A/AAAA",
+            CheckStatus::Fail,
+            format!("{} has no A or AAAA records", hostname),
+            address_remedy,
+        ),
+        (Ok(addrs), Ok(ip)) if addrs.contains(ip) => {
+            SetupCheck::ok("A/AAAA", format!("{} → {}", hostname, join(addrs)))
+        }
+        (Ok(addrs), Ok(ip)) => SetupCheck::problem(
+            "A/AAAA",
+            CheckStatus::Fail,
+            format!(
+                "{} resolves to {}, but the server's public IP is {}",
+                hostname,
+                join(addrs),
+                ip
+            ),
+            address_remedy,
+        ),
+        (Ok(addrs), Err(e)) => SetupCheck::problem(
+            "A/AAAA",
+            CheckStatus::Warn,
+            format!(
+                "{} resolves to {}; not compared, public IP unknown ({})",
+                hostname,
+                join(addrs),
+                e
+            ),
+            format!(
+                "Set {} to the server's IP or a URL that returns it.",
+                PUBLIC_IP_SETTING
+            ),
+        ),
+    };
+
+    // Without a discovered IP, the reverse zone of the published address is
+    // the best available guess.
+    let ptr_ip = match (public_ip, &resolved) {
+        (Ok(ip), _) => Some(*ip),
+        (Err(_), Ok(addrs)) => addrs.first().copied(),
+        (Err(_), Err(_)) => None,
+    };
+    let ptr = match ptr_ip {
+        None => SetupCheck::problem(
+            "PTR",
+            CheckStatus::Warn,
+            "skipped: no IP address to look up".to_string(),
+            format!("Fix the A/AAAA record or set {} first.", PUBLIC_IP_SETTING),
+        ),
+        Some(ip) => {
+            let ptr_remedy = format!(
+                "Ask your hosting provider to set the reverse DNS of {} to {}.",
+                ip, hostname
+            );
+            match resolver.ptr(ip) {
+                Err(e) => SetupCheck::problem(
+                    "PTR",
+                    CheckStatus::Warn,
+                    format!("PTR lookup for {} failed: {}", ip, e),
+                    "Retry later.".to_string(),
+                ),
+                Ok(names) if names.is_empty() => SetupCheck::problem(
+                    "PTR",
+                    CheckStatus::Fail,
+                    format!("{} has no PTR record", ip),
+                    ptr_remedy,
+                ),
+                Ok(names) if names.iter().any(|n| same_host(n, hostname)) => {
+                    SetupCheck::ok("PTR", format!("{} → {}", ip, join(&names)))
+                }
+                Ok(names) => SetupCheck::problem(
+                    "PTR",
+                    CheckStatus::Fail,
+                    format!("PTR of {} is {}, not {}", ip, join(&names), hostname),
+                    ptr_remedy,
+                ),
+            }
+        }
+    };
+
+    let checks = vec![mx, address, ptr];
+    for c in checks.iter().filter(|c| c.status != CheckStatus::Ok) {
+        warn!(
+            "[dns-setup] {} check for {}: {}",
+            c.record, domain, c.detail
+        );
+    }
+    checks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[derive(Default)]
+    struct FakeResolver {
+        addrs: HashMap<&'static str, &'static str>,
+        mx: HashMap<&'static str, &'static str>,
+        ptr: HashMap<&'static str, &'static str>,
+    }
+
+    fn split(list: Option<&&str>) -> Vec<String> {
+        list.map(|l| l.split(',').map(String::from).collect())
+            .unwrap_or_default()
+    }
+
+    impl Resolver for FakeResolver {
+        fn txt(&self, _: &str) -> Result<Vec<String>, String> {
+            Ok(Vec::new())
+        }
+
+        fn addrs(&self, name: &str) -> Result<Vec<IpAddr>, String> {
+            if name == "down.example.com" {
+                return Err("timeout".to_string());
+            }
+            Ok(split(self.addrs.get(name))
+                .iter()
+                .map(|ip| ip.parse().unwrap())
+                .collect())
+        }
+
+        fn mx(&self, name: &str) -> Result<Vec<String>, String> {
+            Ok(split(self.mx.get(name)))
+        }
+
+        fn ptr(&self, ip: IpAddr) -> Result<Vec<String>, String> {
+            Ok(split(self.ptr.get(ip.to_string().as_str())))
+        }
+    }
+
+    fn statuses(checks: &[SetupCheck]) -> Vec<CheckStatus> {
+        checks.iter().map(|c| c.status).collect()
+    }
+
+    #[test]
+    fn all_records_matching_pass() {
+        let mut r = FakeResolver::default();
+        r.mx.insert("example.com", "MAIL.example.com.");
+        r.addrs
+            .insert("mail.example.com", "2001:db8::25,203.0.113.5");
+        r.ptr.insert("203.0.113.5", "mail.example.com.");
+        let ip = Ok("203.0.113.5".parse().unwrap());
+        let checks = check("example.com", "mail.example.com", &ip, &r);
+        assert_eq!(statuses(&checks), vec![CheckStatus::Ok; 3]);
+        assert!(checks.iter().all(|c| c.remedy.is_empty()));
+    }
+
+    #[test]
+    fn mismatches_fail_with_remediation() {
+        let mut r = FakeResolver::default();
+        r.mx.insert("example.com", "mx.other.net");
+        r.addrs.insert("mail.example.com", "198.51.100.7");
+        r.ptr.insert("203.0.113.5", "host-5.isp.example");
+        let ip = Ok("203.0.113.5".parse().unwrap());
+        let checks = check("example.com", "mail.example.com", &ip, &r);
+        assert_eq!(statuses(&checks), vec![CheckStatus::Fail; 3]);
+        assert_eq!(
+            checks[0].remedy,
+            "Publish \"example.com. MX 10 mail.example.com.\""
+        );
+        assert_eq!(
+            checks[1].remedy,
+            "Publish \"mail.example.com. A 203.0.113.5\""
+        );
+        assert!(checks[2].remedy.contains("reverse DNS of 203.0.113.5"));
+    }
+
+    #[test]
+    fn unknown_public_ip_and_lookup_failures_degrade() {
+        let mut r = FakeResolver::default();
+        r.addrs.insert("mail.example.com", "203.0.113.5");
+        r.ptr.insert("203.0.113.5", "mail.example.com");
+        let unknown = Err("behind NAT".to_string());
+        let checks = check("example.com", "mail.example.com", &unknown, &r);
+        // No MX records; the address is not compared; PTR uses the A record.
+        assert_eq!(
+            statuses(&checks),
+            vec![CheckStatus::Fail, CheckStatus::Warn, CheckStatus::Ok]
+        );
+
+        let checks = check("example.com", "down.example.com", &unknown, &r);
+        assert_eq!(checks[1].status, CheckStatus::Warn);
+        assert!(checks[2].detail.starts_with("skipped"));
+    }
+
+    #[test]
+    fn public_ip_method_parses() {
+        assert_eq!(PublicIpMethod::parse(""), Some(PublicIpMethod::Interface));
+        assert_eq!(
+            PublicIpMethod::parse(" 203.0.113.5 "),
+            Some(PublicIpMethod::Fixed("203.0.113.5".parse().unwrap()))
+        );
+        assert_eq!(
+            PublicIpMethod::parse("https://api.ipify.org"),
+            Some(PublicIpMethod::Url("https://api.ipify.org".to_string()))
+        );
+        assert_eq!(PublicIpMethod::parse("dns"), None);
+    }
+}
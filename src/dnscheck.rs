@@ -0,0 +1,499 @@
+//! Published DNS record verification for a domain.
+//!
+//! Looks up the MX, SPF, DKIM, DMARC and BIMI records of a domain and
+//! compares each with what this server expects to be published, as listed on
+//! the domain's DNS page.  Every record gets a pass/warn/fail status with the
+//! observed and expected values side by side.
+//!
+//! Lookups go through [`Resolver`] and results are kept in a [`CheckCache`]
+//! for a few minutes, so refreshing the DNS page does not repeat them.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::dns_setup::CheckStatus;
+use crate::spf::Resolver;
+
+/// How long checks for a domain are reused.
+pub const CACHE_TTL: Duration = Duration::from_secs(300);
+
+/// What the domain should publish, from the domain's settings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Expected {
+    pub domain: String,
+    pub hostname: String,
+    pub dkim_selector: String,
+    /// Base64 public key for the DKIM `p=` tag; `None` before a key exists.
+    pub dkim_public_key: Option<String>,
+    /// The full `_dmarc` TXT record shown on the DNS page.
+    pub dmarc_record: String,
+    /// The BIMI logo URL; `None` when no logo is configured.
+    pub bimi_logo_url: Option<String>,
+}
+
+/// Outcome of one record check.
+#[derive(Clone, Debug)]
+pub struct RecordCheck {
+    pub record: &'static str,
+    pub name: String,
+    pub status: CheckStatus,
+    /// What DNS answered, or `—` when nothing was found.
+    pub observed: String,
+    pub expected: String,
+    pub detail: String,
+}
+
+impl RecordCheck {
+    fn new(
+        record: &'static str,
+        name: &str,
+        expected: &str,
+        observed: &[String],
+        status: CheckStatus,
+        detail: String,
+    ) -> Self {
+        RecordCheck {
+            record,
+            name: name.to_string(),
+            status,
+            observed: if observed.is_empty() {
+                "—".to_string()
+            } else {
+                observed.join("\n")
+            },
+            expected: expected.to_string(),
+            detail,
+        }
+    }
+}
+
+fn same_host(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+/// TXT records at `name` whose text starts with `prefix` (case-insensitive),
+/// or the lookup error.
+fn txt_with_prefix(
+    resolver: &dyn Resolver,
+    name: &str,
+    prefix: &str,
+) -> Result<Vec<String>, String> {
+    Ok(resolver
+        .txt(name)?
+        .into_iter()
+        .filter(|t| {
+            t.trim_start()
+                .to_ascii_lowercase()
+                .starts_with(&prefix.to_ascii_lowercase())
+        })
+        .collect())
+}
+
+/// The value of `tag` in a `tag=value; …` record such as DKIM or DMARC.
+fn tag_value<'a>(record: &'a str, tag: &str) -> Option<&'a str> {
+    record.split(';').find_map(|part| {
+        let (k, v) = part.split_once('=')?;
+        k.trim().eq_ignore_ascii_case(tag).then(|| v.trim())
+    })
+}
+
+fn lookup_failed(record: &'static str, name: &str, expected: &str, e: String) -> RecordCheck {
+    RecordCheck::new(
+        record,
+        name,
+        expected,
+        &[],
+        CheckStatus::Warn,
+        format!("lookup failed: {}", e),
+    )
+}
+
+fn check_mx(exp: &Expected, resolver: &dyn Resolver) -> RecordCheck {
+    let expected = format!("10 {}.", exp.hostname);
+    match resolver.mx(&exp.domain) {
+        Err(e) => lookup_failed("MX", &exp.domain, &expected, e),
+        Ok(hosts) => {
+            let (status, detail) = if hosts.is_empty() {
+                (CheckStatus::Fail, "no MX records".to_string())
+            } else if hosts.iter().any(|h| same_host(h, &exp.hostname)) {
+                (
+                    CheckStatus::Ok,
+                    format!("mail is routed to {}", exp.hostname),
+                )
+            } else {
+                (
+                    CheckStatus::Fail,
+                    format!("no MX record points at {}", exp.hostname),
+                )
+            };
+            RecordCheck::new("MX", &exp.domain, &expected, &hosts, status, detail)
+        }
+    }
+}
+
+fn check_spf(exp: &Expected, resolver: &dyn Resolver) -> RecordCheck {
+    let expected = format!("v=spf1 a mx include:{} ~all", exp.hostname);
+    let records = match txt_with_prefix(resolver, &exp.domain, "v=spf1") {
+        Ok(r) => r,
+        Err(e) => return lookup_failed("SPF", &exp.domain, &expected, e),
+    };
+    let (status, detail) = match records.as_slice() {
+        [] => (CheckStatus::Fail, "no SPF record".to_string()),
+        [record] => {
+            let terms: Vec<String> = record
+                .split_whitespace()
+                .skip(1)
+                .map(|t| t.to_ascii_lowercase())
+                .collect();
+            let authorizes = terms.iter().any(|t| {
+                let t = t.trim_start_matches(['+', '?', '~']);
+                t == "a"
+                    || t == "mx"
+                    || t.strip_prefix("include:")
+                        .is_some_and(|h| same_host(h, &exp.hostname))
+            });
+            if terms.iter().any(|t| t == "+all" || t == "all") {
+                (
+                    CheckStatus::Fail,
+                    "\"+all\" lets any server send for the domain".to_string(),
+                )
+            } else if authorizes {
+                (
+                    CheckStatus::Ok,
+                    "authorizes the domain's A or MX hosts".to_string(),
+                )
+            } else {
+                (
+                    CheckStatus::Warn,
+                    format!(
+                        "has no a, mx or include:{} term; check that it covers this server",
+                        exp.hostname
+                    ),
+                )
+            }
+        }
+        _ => (
+            CheckStatus::Fail,
+            "more than one SPF record; receivers treat this as an error".to_string(),
+        ),
+    };
+    RecordCheck::new("SPF", &exp.domain, &expected, &records, status, detail)
+}
+
+fn check_dkim(exp: &Expected, resolver: &dyn Resolver) -> RecordCheck {
+    let name = format!("{}._domainkey.{}", exp.dkim_selector, exp.domain);
+    let expected = exp
+        .dkim_public_key
+        .as_ref()
+        .map(|key| format!("v=DKIM1; k=rsa; p={}", key))
+        .unwrap_or_else(|| "—".to_string());
+    let records: Vec<String> = match resolver.txt(&name) {
+        Ok(r) => r
+            .into_iter()
+            .filter(|t| tag_value(t, "p").is_some())
+            .collect(),
+        Err(e) => return lookup_failed("DKIM", &name, &expected, e),
+    };
+    let published_key = |r: &String| {
+        tag_value(r, "p")
+            .unwrap_or_default()
+            .split_whitespace()
+            .collect::<String>()
+    };
+    let (status, detail) = match (&exp.dkim_public_key, records.as_slice()) {
+        (None, []) => (
+            CheckStatus::Warn,
+            "no DKIM key generated and none published".to_string(),
+        ),
+        (None, _) => (
+            CheckStatus::Warn,
+            "a key is published but none is generated here, so mail is not signed with it"
+                .to_string(),
+        ),
+        (Some(_), []) => (
+            CheckStatus::Fail,
+            format!("no DKIM record for selector {}", exp.dkim_selector),
+        ),
+        (Some(key), records) if records.iter().any(|r| published_key(r) == *key) => {
+            (CheckStatus::Ok, "published key matches".to_string())
+        }
+        (Some(_), _) => (
+            CheckStatus::Fail,
+            "published key differs from the generated key".to_string(),
+        ),
+    };
+    RecordCheck::new("DKIM", &name, &expected, &records, status, detail)
+}
+
+fn check_dmarc(exp: &Expected, resolver: &dyn Resolver) -> RecordCheck {
+    let name = format!("_dmarc.{}", exp.domain);
+    let records = match txt_with_prefix(resolver, &name, "v=DMARC1") {
+        Ok(r) => r,
+        Err(e) => return lookup_failed("DMARC", &name, &exp.dmarc_record, e),
+    };
+    let (status, detail) = match records.as_slice() {
+        [] => (CheckStatus::Fail, "no DMARC record".to_string()),
+        [record] => match tag_value(record, "p").map(|p| p.to_ascii_lowercase()) {
+            Some(p) if p == "reject" || p == "quarantine" => {
+                (CheckStatus::Ok, format!("policy p={}", p))
+            }
+            Some(p) if p == "none" => (
+                CheckStatus::Warn,
+                "policy p=none only monitors; failing mail is still delivered".to_string(),
+            ),
+            _ => (
+                CheckStatus::Fail,
+                "missing or invalid p= policy".to_string(),
+            ),
+        },
+        _ => (
+            CheckStatus::Fail,
+            "more than one DMARC record; receivers ignore all of them".to_string(),
+        ),
+    };
+    RecordCheck::new("DMARC", &name, &exp.dmarc_record, &records, status, detail)
+}
+
+fn check_bimi(exp: &Expected, resolver: &dyn Resolver) -> RecordCheck {
+    let name = format!("default._bimi.{}", exp.domain);
+    let expected = exp
+        .bimi_logo_url
+        .as_ref()
+        .map(|url| format!("v=BIMI1; l={}", url))
+        .unwrap_or_else(|| "—".to_string());
+    let records = match txt_with_prefix(resolver, &name, "v=BIMI1") {
+        Ok(r) => r,
+        Err(e) => return lookup_failed("BIMI", &name, &expected, e),
+    };
+    let (status, detail) = match (&exp.bimi_logo_url, records.as_slice()) {
+        (None, []) => (
+            CheckStatus::Ok,
+            "no logo configured; BIMI is optional".to_string(),
+        ),
+        (None, _) => (
+            CheckStatus::Warn,
+            "a BIMI record is published but no logo is configured here".to_string(),
+        ),
+        (Some(_), []) => (CheckStatus::Fail, "no BIMI record".to_string()),
+        (Some(url), records)
+            if records
+                .iter()
+                .any(|r| tag_value(r, "l") == Some(url.as_str())) =>
+        {
+            (CheckStatus::Ok, "logo URL matches".to_string())
+        }
+        (Some(_), _) => (
+            CheckStatus::Warn,
+            "published logo URL differs from this server's".to_string(),
+        ),
+    };
+    RecordCheck::new("BIMI", &name, &expected, &records, status, detail)
+}
+
+/// Check the MX, SPF, DKIM, DMARC and BIMI records of `exp.domain`.
+pub fn check(exp: &Expected, resolver: &dyn Resolver) -> Vec<RecordCheck> {
+    debug!("[dnscheck] checking records for domain={}", exp.domain);
+    let checks = vec![
+        check_mx(exp, resolver),
+        check_spf(exp, resolver),
+        check_dkim(exp, resolver),
+        check_dmarc(exp, resolver),
+        check_bimi(exp, resolver),
+    ];
+    for c in checks.iter().filter(|c| c.status != CheckStatus::Ok) {
+        warn!(
+            "[dnscheck] {} record for {}: {}",
+            c.record, exp.domain, c.detail
+        );
+    }
+    checks
+}
+
+struct CacheEntry {
+    expected: Expected,
+    checked_at: Instant,
+    checks: Vec<RecordCheck>,
+}
+
+/// Recent checks keyed by domain.  An entry is reused only while it is
+/// younger than the TTL and the expected records have not changed, e.g.
+/// after a new DKIM key is generated.
+pub struct CheckCache {
+    ttl: Duration,
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl CheckCache {
+    pub fn new(ttl: Duration) -> Self {
+        CheckCache {
+            ttl,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// The cached checks for `exp` and when they were made.
+    pub fn get(&self, exp: &Expected, now: Instant) -> Option<(Vec<RecordCheck>, Instant)> {
+        let entry = self.entries.get(&exp.domain.to_ascii_lowercase())?;
+        (entry.expected == *exp && now.duration_since(entry.checked_at) < self.ttl)
+            .then(|| (entry.checks.clone(), entry.checked_at))
+    }
+
+    pub fn put(&mut self, exp: &Expected, checks: Vec<RecordCheck>, now: Instant) {
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, e| now.duration_since(e.checked_at) < ttl);
+        self.entries.insert(
+            exp.domain.to_ascii_lowercase(),
+            CacheEntry {
+                expected: exp.clone(),
+                checked_at: now,
+                checks,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    #[derive(Default)]
+    struct FakeResolver {
+        txt: HashMap<&'static str, Vec<&'static str>>,
+        mx: HashMap<&'static str, Vec<&'static str>>,
+    }
+
+    impl Resolver for FakeResolver {
+        fn txt(&self, name: &str) -> Result<Vec<String>, String> {
+            if name.starts_with("_dmarc.down") {
+                return Err("timeout".to_string());
+            }
+            Ok(self
+                .txt
+                .get(name)
+                .map(|v| v.iter().map(|s| s.to_string()).collect())
+                .unwrap_or_default())
+        }
+
+        fn addrs(&self, _: &str) -> Result<Vec<IpAddr>, String> {
+            Ok(Vec::new())
+        }
+
+        fn mx(&self, name: &str) -> Result<Vec<String>, String> {
+            Ok(self
+                .mx
+                .get(name)
+                .map(|v| v.iter().map(|s| s.to_string()).collect())
+                .unwrap_or_default())
+        }
+
+        fn ptr(&self, _: IpAddr) -> Result<Vec<String>, String> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn expected(domain: &str) -> Expected {
+        Expected {
+            domain: domain.to_string(),
+            hostname: "mail.example.com".to_string(),
+            dkim_selector: "mail".to_string(),
+            dkim_public_key: Some("MIIBkey".to_string()),
+            dmarc_record: "v=DMARC1; p=reject".to_string(),
+            bimi_logo_url: Some("https://mail.example.com/bimi/logo.svg".to_string()),
+        }
+    }
+
+    fn statuses(checks: &[RecordCheck]) -> Vec<CheckStatus> {
+        checks.iter().map(|c| c.status).collect()
+    }
+
+    #[test]
+    fn matching_records_pass() {
+        let mut r = FakeResolver::default();
+        r.mx.insert("example.com", vec!["mail.example.com."]);
+        r.txt.insert(
+            "example.com",
+            vec!["google-site-verification=x", "v=spf1 mx -all"],
+        );
+        r.txt.insert(
+            "mail._domainkey.example.com",
+            vec!["v=DKIM1; k=rsa; p=MIIB key"],
+        );
+        r.txt.insert(
+            "_dmarc.example.com",
+            vec!["v=DMARC1; p=Quarantine; rua=mailto:x@y"],
+        );
+        r.txt.insert(
+            "default._bimi.example.com",
+            vec!["v=BIMI1; l=https://mail.example.com/bimi/logo.svg"],
+        );
+        let checks = check(&expected("example.com"), &r);
+        assert_eq!(statuses(&checks), vec![CheckStatus::Ok; 5]);
+        assert_eq!(checks[1].observed, "v=spf1 mx -all");
+    }
+
+    #[test]
+    fn missing_and_mismatched_records_are_reported() {
+        let mut r = FakeResolver::default();
+        r.mx.insert("example.com", vec!["mx.other.net"]);
+        r.txt
+            .insert("example.com", vec!["v=spf1 mx ~all", "v=spf1 a ~all"]);
+        r.txt
+            .insert("mail._domainkey.example.com", vec!["v=DKIM1; p=OLDKEY"]);
+        r.txt.insert("_dmarc.example.com", vec!["v=DMARC1; p=none"]);
+        let checks = check(&expected("example.com"), &r);
+        assert_eq!(
+            statuses(&checks),
+            vec![
+                CheckStatus::Fail,
+                CheckStatus::Fail,
+                CheckStatus::Fail,
+                CheckStatus::Warn,
+                CheckStatus::Fail,
+            ]
+        );
+        assert_eq!(checks[2].observed, "v=DKIM1; p=OLDKEY");
+        assert_eq!(checks[2].expected, "v=DKIM1; k=rsa; p=MIIBkey");
+        assert_eq!(checks[4].observed, "—");
+    }
+
+    #[test]
+    fn optional_records_and_lookup_failures_warn() {
+        let mut r = FakeResolver::default();
+        r.txt
+            .insert("down.example", vec!["v=spf1 include:other.net ~all"]);
+        let mut exp = expected("down.example");
+        exp.dkim_public_key = None;
+        exp.bimi_logo_url = None;
+        let checks = check(&exp, &r);
+        assert_eq!(
+            statuses(&checks)[1..],
+            [
+                CheckStatus::Warn,
+                CheckStatus::Warn,
+                CheckStatus::Warn,
+                CheckStatus::Ok,
+            ]
+        );
+        assert!(checks[3].detail.starts_with("lookup failed"));
+    }
+
+    #[test]
+    fn cache_expires_and_follows_expectations() {
+        let mut cache = CheckCache::new(Duration::from_secs(60));
+        let exp = expected("Example.com");
+        let t0 = Instant::now();
+        assert!(cache.get(&exp, t0).is_none());
+        cache.put(&exp, check(&exp, &FakeResolver::default()), t0);
+        assert!(cache.get(&exp, t0 + Duration::from_secs(59)).is_some());
+        assert!(cache.get(&exp, t0 + Duration::from_secs(60)).is_none());
+
+        let mut rotated = exp.clone();
+        rotated.dkim_public_key = Some("NEWKEY".to_string());
+        assert!(cache.get(&rotated, t0).is_none());
+    }
+}
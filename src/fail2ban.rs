@@ -1,7 +1,9 @@
+use ipnetwork::IpNetwork;
 use log::{debug, error, info, warn};
 use regex::Regex;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::net::IpAddr;
 use std::path::Path;
 use std::sync::OnceLock;
 use std::time::{Duration, Instant};
@@ -11,6 +13,9 @@ use crate::db::Database;
 const MAIL_LOG_PATH: &str = "/var/log/mail.log";
 const POLL_INTERVAL: Duration = Duration::from_secs(5);
 const ENABLED_CACHE_TTL: Duration = Duration::from_secs(30);
+/// How long successful logins are kept for the last-login column.
+pub const LOGIN_RETENTION_DAYS: i64 = 180;
+const LOGIN_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
 
 /// A parsed authentication failure from a mail service log line.
 #[derive(Debug, Clone, PartialEq)]
@@ -20,12 +25,21 @@ pub struct AuthFailure {
     pub detail: String,
 }
 
+/// A successful IMAP or POP3 login from the Dovecot log.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginEvent {
+    pub username: String,
+    pub service: String,
+    pub ip: String,
+}
+
 // Lazily compiled regex patterns for Postfix and Dovecot log parsing.
 
 static POSTFIX_SASL: OnceLock<Regex> = OnceLock::new();
 static DOVECOT_AUTH: OnceLock<Regex> = OnceLock::new();
 static DOVECOT_INVALID: OnceLock<Regex> = OnceLock::new();
 static POSTFIX_ERRORS: OnceLock<Regex> = OnceLock::new();
+static DOVECOT_LOGIN: OnceLock<Regex> = OnceLock::new();
 
 fn postfix_sasl_re() -> &'static Regex {
     POSTFIX_SASL.get_or_init(|| {
@@ -57,6 +71,32 @@ fn postfix_errors_re() -> &'static Regex {
     })
 }
 
+fn dovecot_login_re() -> &'static Regex {
+    DOVECOT_LOGIN.get_or_init(|| {
+        Regex::new(r"dovecot: (imap|pop3)-login: Login: user=<([^>]+)>.*?rip=([0-9a-fA-F.:]+)")
+            .expect("Invalid regex")
+    })
+}
+
+/// Parse a successful Dovecot login, e.g.
+///   `... dovecot: imap-login: Login: user=<user@example.com>, method=PLAIN, rip=1.2.3.4, ...`
+pub fn parse_login_line(line: &str) -> Option<LoginEvent> {
+    let caps = dovecot_login_re().captures(line)?;
+    Some(LoginEvent {
+        username: caps[2].to_lowercase(),
+        service: caps[1].to_string(),
+        ip: normalize_ip(&caps[3]),
+    })
+}
+
+/// Forget logins older than [`LOGIN_RETENTION_DAYS`].
+fn prune_login_events(db: &Database) {
+    let cutoff = (chrono::Utc::now() - chrono::Duration::days(LOGIN_RETENTION_DAYS))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    db.prune_login_events(&cutoff);
+}
+
 /// Parse a single log line for authentication failures from Postfix or Dovecot.
 ///
 /// Postfix SASL auth failures look like:
@@ -71,7 +111,7 @@ pub fn parse_log_line(line: &str) -> Option<AuthFailure> {
     // Postfix SASL authentication failure
     if let Some(caps) = postfix_sasl_re().captures(line) {
         return Some(AuthFailure {
-            ip: caps[1].to_string(),
+            ip: normalize_ip(&caps[1]),
             service: "smtp".to_string(),
             detail: line.to_string(),
         });
@@ -82,7 +122,7 @@ pub fn parse_log_line(line: &str) -> Option<AuthFailure> {
         let proto = &caps[1];
         let service = if proto == "pop3" { "pop3" } else { "imap" };
         return Some(AuthFailure {
-            ip: caps[2].to_string(),
+            ip: normalize_ip(&caps[2]),
             service: service.to_string(),
             detail: line.to_string(),
         });
@@ -93,7 +133,7 @@ pub fn parse_log_line(line: &str) -> Option<AuthFailure> {
         let proto = &caps[1];
         let service = if proto == "pop3" { "pop3" } else { "imap" };
         return Some(AuthFailure {
-            ip: caps[2].to_string(),
+            ip: normalize_ip(&caps[2]),
             service: service.to_string(),
             detail: line.to_string(),
         });
@@ -102,7 +142,7 @@ pub fn parse_log_line(line: &str) -> Option<AuthFailure> {
     // Postfix: too many connection errors
     if let Some(caps) = postfix_errors_re().captures(line) {
         return Some(AuthFailure {
-            ip: caps[1].to_string(),
+            ip: normalize_ip(&caps[1]),
             service: "smtp".to_string(),
             detail: line.to_string(),
         });
@@ -111,6 +151,134 @@ pub fn parse_log_line(line: &str) -> Option<AuthFailure> {
     None
 }
 
+/// An enabled admin-defined detection rule from `fail2ban_rules`, compiled.
+pub struct CustomRule {
+    pub name: String,
+    pub service: String,
+    pub regex: Regex,
+}
+
+/// Compile a custom rule pattern.  The pattern must name the client address
+/// with an `ip` capture group, e.g. `(?P<ip>[0-9a-fA-F.:]+)`.
+pub fn compile_rule_regex(pattern: &str) -> Result<Regex, String> {
+    let re = Regex::new(pattern).map_err(|e| format!("The pattern does not compile: {}", e))?;
+    if !re.capture_names().flatten().any(|name| name == "ip") {
+        return Err(
+            "The pattern needs a named capture group for the client address, e.g. (?P<ip>[0-9a-fA-F.:]+)."
+                .to_string(),
+        );
+    }
+    Ok(re)
+}
+
+/// Load and compile the enabled custom rules, skipping any that no longer compile.
+pub fn load_custom_rules(db: &Database) -> Vec<CustomRule> {
+    db.list_fail2ban_rules()
+        .into_iter()
+        .filter(|r| r.enabled)
+        .filter_map(|r| match compile_rule_regex(&r.regex) {
+            Ok(regex) => Some(CustomRule {
+                name: r.name,
+                service: r.service,
+                regex,
+            }),
+            Err(e) => {
+                warn!("[fail2ban] skipping custom rule {}: {}", r.name, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Test a log line against the custom rules.  The first rule whose `ip`
+/// group captures a valid IP address produces the failure.
+pub fn match_custom_rules(line: &str, rules: &[CustomRule]) -> Option<AuthFailure> {
+    rules.iter().find_map(|rule| {
+        let caps = rule.regex.captures(line)?;
+        let captured = caps.name("ip")?.as_str();
+        let Some(ip) = canonical_ip(captured) else {
+            debug!(
+                "[fail2ban] custom rule {} captured non-IP value {:?}",
+                rule.name, captured
+            );
+            return None;
+        };
+        Some(AuthFailure {
+            ip: ip.to_string(),
+            service: rule.service.clone(),
+            detail: format!("rule={}: {}", rule.name, line),
+        })
+    })
+}
+
+/// Parse a client address as it appears in logs and proxy headers:
+/// `2001:db8::1`, `[2001:db8::1]:25`, `fe80::1%eth0`, `192.0.2.1:4321`.
+/// Ports and zone ids are dropped, and IPv4-mapped IPv6 addresses
+/// (`::ffff:192.0.2.1`) become plain IPv4, so one client always has one form.
+pub fn canonical_ip(input: &str) -> Option<IpAddr> {
+    let input = input.trim();
+    let host = match input.strip_prefix('[') {
+        Some(rest) => rest.split_once(']')?.0,
+        // Exactly one colon: IPv4 with a port.
+        None if input.matches(':').count() == 1 => input.split_once(':')?.0,
+        None => input,
+    };
+    let host = host.split_once('%').map_or(host, |(addr, _zone)| addr);
+    Some(unmap(host.parse().ok()?))
+}
+
+fn unmap(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+        IpAddr::V4(_) => ip,
+    }
+}
+
+/// The canonical text of a client address, or the trimmed input when it is
+/// not an address (such as `unknown`).
+pub fn normalize_ip(input: &str) -> String {
+    canonical_ip(input).map_or_else(|| input.trim().to_string(), |ip| ip.to_string())
+}
+
+/// Parse a ban/whitelist/blacklist entry: a single IP address or a CIDR range.
+/// IPv4-mapped IPv6 ranges become the IPv4 range they cover.
+pub fn parse_ip_or_cidr(input: &str) -> Option<IpNetwork> {
+    let input = input.trim();
+    let Some((addr, prefix)) = input.split_once('/') else {
+        return canonical_ip(input).map(IpNetwork::from);
+    };
+    let prefix: u8 = prefix.parse().ok()?;
+    let net = IpNetwork::new(addr.parse().ok()?, prefix).ok()?;
+    match (net.ip(), unmap(net.ip())) {
+        (IpAddr::V6(_), IpAddr::V4(v4)) if prefix >= 96 => {
+            IpNetwork::new(IpAddr::V4(v4), prefix - 96).ok()
+        }
+        _ => Some(net),
+    }
+}
+
+/// Canonical form stored for an entry: plain IPs as-is, ranges with the host
+/// bits cleared (`203.0.113.7/24` becomes `203.0.113.0/24`).
+pub fn normalize_ip_or_cidr(input: &str) -> Option<String> {
+    let input = input.trim();
+    let net = parse_ip_or_cidr(input)?;
+    if !input.contains('/') {
+        return Some(net.ip().to_string());
+    }
+    IpNetwork::new(net.network(), net.prefix())
+        .ok()
+        .map(|n| n.to_string())
+}
+
+/// Whether the stored `entry` (IP or CIDR range) covers `ip`.  Entries that
+/// do not parse fall back to an exact string comparison.
+pub fn entry_matches(entry: &str, ip: &str) -> bool {
+    match (parse_ip_or_cidr(entry), canonical_ip(ip)) {
+        (Some(net), Some(addr)) => net.contains(addr),
+        _ => entry.trim() == ip.trim(),
+    }
+}
+
 /// Process a detected auth failure: record, count, and potentially ban the IP.
 fn handle_auth_failure(db: &Database, failure: &AuthFailure) {
     // Check whitelist first
@@ -177,6 +345,7 @@ fn handle_auth_failure(db: &Database, failure: &AuthFailure) {
                     "[fail2ban] BANNED IP {} for service {} — {} attempts exceeded threshold of {} (ban duration: {} min)",
                     failure.ip, failure.service, recent_count, setting.max_attempts, setting.ban_duration_minutes
                 );
+                crate::firewall::block(db, &failure.ip);
             }
             Err(e) => {
                 error!("[fail2ban] failed to ban IP {}: {}", failure.ip, e);
@@ -190,14 +359,16 @@ fn handle_auth_failure(db: &Database, failure: &AuthFailure) {
 pub fn start_watcher(db: Database) {
     info!("[fail2ban] starting log watcher for {}", MAIL_LOG_PATH);
 
-    std::thread::spawn(move || {
+    crate::shutdown::spawn("fail2ban log watcher", move || {
         // Wait for the log file to be created (syslog may start after us)
         loop {
             if Path::new(MAIL_LOG_PATH).exists() {
                 break;
             }
             debug!("[fail2ban] waiting for {} to appear...", MAIL_LOG_PATH);
-            std::thread::sleep(Duration::from_secs(2));
+            if crate::shutdown::wait(Duration::from_secs(2)) {
+                return;
+            }
         }
 
         info!(
@@ -207,6 +378,7 @@ pub fn start_watcher(db: Database) {
 
         loop {
             match tail_log_file(&db) {
+                Ok(()) if crate::shutdown::requested() => return,
                 Ok(()) => {
                     warn!("[fail2ban] log watcher loop exited, restarting in 5s");
                 }
@@ -214,12 +386,16 @@ pub fn start_watcher(db: Database) {
                     error!("[fail2ban] log watcher error: {}, restarting in 5s", e);
                 }
             }
-            std::thread::sleep(POLL_INTERVAL);
+            if crate::shutdown::wait(POLL_INTERVAL) {
+                return;
+            }
         }
     });
 }
 
 /// Tail the mail log file, seeking to the end and then processing new lines.
+/// Successful Dovecot logins are recorded as login events, whether or not
+/// fail2ban is enabled.  Returns when the file is rotated or on shutdown.
 fn tail_log_file(db: &Database) -> Result<(), std::io::Error> {
     let mut file = File::open(MAIL_LOG_PATH)?;
     // Seek to end — we only process new log lines
@@ -227,9 +403,13 @@ fn tail_log_file(db: &Database) -> Result<(), std::io::Error> {
     let mut reader = BufReader::new(file);
     let mut line = String::new();
 
-    // Cache the global enabled state to avoid querying the DB on every log line
+    // Cache the global enabled state and custom rules to avoid querying the DB
+    // on every log line
     let mut enabled_cache = db.is_fail2ban_enabled();
+    let mut custom_rules = load_custom_rules(db);
     let mut cache_refreshed = Instant::now();
+    prune_login_events(db);
+    let mut logins_pruned = Instant::now();
 
     info!("[fail2ban] tailing {} from end of file", MAIL_LOG_PATH);
 
@@ -252,17 +432,31 @@ fn tail_log_file(db: &Database) -> Result<(), std::io::Error> {
                 // Refresh cache during idle periods
                 if cache_refreshed.elapsed() >= ENABLED_CACHE_TTL {
                     enabled_cache = db.is_fail2ban_enabled();
+                    custom_rules = load_custom_rules(db);
                     cache_refreshed = Instant::now();
                 }
-                std::thread::sleep(POLL_INTERVAL);
+                if logins_pruned.elapsed() >= LOGIN_PRUNE_INTERVAL {
+                    prune_login_events(db);
+                    logins_pruned = Instant::now();
+                }
+                if crate::shutdown::wait(POLL_INTERVAL) {
+                    return Ok(());
+                }
             }
             Ok(_) => {
                 let trimmed = line.trim();
+                if let Some(login) = parse_login_line(trimmed) {
+                    db.record_login_event(&login.username, &login.service, &login.ip);
+                    continue;
+                }
                 if !trimmed.is_empty() {
-                    if let Some(failure) = parse_log_line(trimmed) {
+                    let failure = parse_log_line(trimmed)
+                        .or_else(|| match_custom_rules(trimmed, &custom_rules));
+                    if let Some(failure) = failure {
                         // Refresh the cached enabled state periodically
                         if cache_refreshed.elapsed() >= ENABLED_CACHE_TTL {
                             enabled_cache = db.is_fail2ban_enabled();
+                            custom_rules = load_custom_rules(db);
                             cache_refreshed = Instant::now();
                         }
                         if !enabled_cache {
@@ -362,6 +556,23 @@ mod tests {
         assert!(parse_log_line(line).is_none());
     }
 
+    #[test]
+    fn parse_dovecot_successful_logins() {
+        let line = "Feb 18 10:15:23 mail dovecot: imap-login: Login: user=<User@Example.com>, method=PLAIN, rip=1.2.3.4, lip=10.0.0.1, mpid=4242, TLS";
+        assert_eq!(
+            parse_login_line(line),
+            Some(LoginEvent {
+                username: "user@example.com".to_string(),
+                service: "imap".to_string(),
+                ip: "1.2.3.4".to_string(),
+            })
+        );
+        let line = "Feb 18 10:15:23 mail dovecot: pop3-login: Login: user=<bob@example.com>, method=LOGIN, rip=2001:db8::1, lip=::1";
+        assert_eq!(parse_login_line(line).unwrap().service, "pop3");
+        let line = "Feb 18 10:15:23 mail dovecot: pop3-login: Aborted login (auth failed, 1 attempts in 2 secs): user=<user@example.com>, method=PLAIN, rip=172.16.0.10, lip=10.0.0.1";
+        assert!(parse_login_line(line).is_none());
+    }
+
     #[test]
     fn parse_empty_line_returns_none() {
         assert!(parse_log_line("").is_none());
@@ -376,4 +587,92 @@ mod tests {
         assert_eq!(f.ip, "192.0.2.1");
         assert_eq!(f.service, "smtp");
     }
+
+    #[test]
+    fn custom_rules_capture_the_ip_group() {
+        let rules = vec![CustomRule {
+            name: "gitea".to_string(),
+            service: "smtp".to_string(),
+            regex: compile_rule_regex(r"Failed authentication attempt from (?P<ip>[0-9a-fA-F.:]+)")
+                .unwrap(),
+        }];
+        let line = "Feb 18 10:15:23 git gitea[77]: Failed authentication attempt from 198.51.100.9";
+        let f = match_custom_rules(line, &rules).unwrap();
+        assert_eq!(f.ip, "198.51.100.9");
+        assert_eq!(f.service, "smtp");
+        assert!(f.detail.starts_with("rule=gitea: "));
+
+        let bogus = "Failed authentication attempt from ::::::::::";
+        assert!(match_custom_rules(bogus, &rules).is_none());
+        assert!(match_custom_rules("unrelated line", &rules).is_none());
+    }
+
+    #[test]
+    fn custom_rule_patterns_are_validated() {
+        assert!(compile_rule_regex(r"from (?P<ip>\S+)").is_ok());
+        assert!(compile_rule_regex(r"from (\S+)").is_err());
+        assert!(compile_rule_regex(r"from (?P<addr>\S+)").is_err());
+        assert!(compile_rule_regex(r"from (?P<ip>\S+").is_err());
+    }
+
+    #[test]
+    fn cidr_entries_match_contained_addresses() {
+        assert!(entry_matches("203.0.113.0/24", "203.0.113.42"));
+        assert!(!entry_matches("203.0.113.0/24", "203.0.114.1"));
+        assert!(entry_matches("2001:db8::/32", "2001:db8:1::5"));
+        assert!(!entry_matches("2001:db8::/32", "192.0.2.1"));
+        assert!(entry_matches("192.0.2.1", "192.0.2.1"));
+        assert!(!entry_matches("192.0.2.1", "192.0.2.2"));
+        assert!(entry_matches("legacy-entry", "legacy-entry"));
+    }
+
+    #[test]
+    fn normalizes_entries() {
+        assert_eq!(
+            normalize_ip_or_cidr(" 203.0.113.7/24 ").as_deref(),
+            Some("203.0.113.0/24")
+        );
+        assert_eq!(normalize_ip_or_cidr("192.0.2.1").as_deref(), Some("192.0.2.1"));
+        assert_eq!(
+            normalize_ip_or_cidr("2001:db8::1/64").as_deref(),
+            Some("2001:db8::/64")
+        );
+        assert_eq!(normalize_ip_or_cidr("10.0.0.0/33"), None);
+        assert_eq!(normalize_ip_or_cidr("example.com"), None);
+    }
+
+    #[test]
+    fn ipv6_spellings_are_one_address() {
+        for spelling in [
+            "2001:db8::1",
+            "2001:0db8:0000:0000:0000:0000:0000:0001",
+            "2001:DB8:0:0::1",
+            "[2001:db8::1]",
+            "[2001:db8::1]:587",
+            " 2001:db8::1%eth0 ",
+        ] {
+            assert_eq!(normalize_ip(spelling), "2001:db8::1", "{}", spelling);
+            assert!(entry_matches("2001:db8::1", spelling), "{}", spelling);
+        }
+        assert_eq!(normalize_ip("::ffff:192.0.2.1"), "192.0.2.1");
+        assert_eq!(normalize_ip("[::ffff:c000:0201]:25"), "192.0.2.1");
+        assert_eq!(normalize_ip("192.0.2.1:4321"), "192.0.2.1");
+        assert_eq!(normalize_ip("unknown"), "unknown");
+
+        assert!(entry_matches("192.0.2.0/24", "::ffff:192.0.2.9"));
+        assert!(entry_matches("::ffff:192.0.2.0/120", "192.0.2.9"));
+        assert!(entry_matches("2001:0db8:0000::/48", "2001:db8:0:ffff::1"));
+        assert_eq!(
+            normalize_ip_or_cidr("2001:0DB8:0000:0000:0000:0000:0000:0001").as_deref(),
+            Some("2001:db8::1")
+        );
+        assert_eq!(
+            normalize_ip_or_cidr("::ffff:192.0.2.0/120").as_deref(),
+            Some("192.0.2.0/24")
+        );
+        assert_eq!(
+            normalize_ip_or_cidr("[::ffff:192.0.2.1]").as_deref(),
+            Some("192.0.2.1")
+        );
+    }
 }
@@ -1,12 +1,258 @@
 use log::{debug, error, info, warn};
 use std::io::{self, Read};
 use std::fs;
-use std::sync::mpsc;
+use std::sync::{mpsc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::compliance_footer::ComplianceFooter;
 use crate::db::Database;
+use crate::decision_cache::{self, Verdict};
+use crate::filter_hooks::{FilterHooks, HookContext, HookVerdict, Stage};
+use crate::quiet_hours::{self, QuietHours};
 
 /// Postfix EX_TEMPFAIL exit code — tells Postfix to queue the message for retry.
 const EX_TEMPFAIL: i32 = 75;
+/// Postfix EX_NOPERM exit code — the message is bounced as not permitted.
+const EX_NOPERM: i32 = 77;
+
+/// Default cap on message bytes buffered in memory by the filter (25 MB).
+pub const DEFAULT_MAX_BODY_BYTES: usize = 25 * 1024 * 1024;
+
+/// Setting holding the largest outbound message accepted, in bytes; zero or
+/// absent means unlimited.
+pub const MAX_MESSAGE_BYTES_SETTING: &str = "max_message_bytes";
+
+/// Setting that stops all pixel injection when `false`, whatever the tracking
+/// rules say.  Absent means enabled.
+pub const TRACKING_KILL_SWITCH_SETTING: &str = "tracking_globally_enabled";
+/// How long a process reuses the kill switch value it last read.
+const KILL_SWITCH_CACHE_TTL: Duration = Duration::from_secs(5);
+
+/// Header a sender can set (e.g. `X-No-Track: 1`) to turn off tracking.
+pub const DEFAULT_OPT_OUT_HEADER: &str = "X-No-Track";
+
+/// Where the tracking pixel is placed in the HTML body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PixelPosition {
+    /// Right after the opening `<body>` tag.
+    Top,
+    /// Just before `</body>` (the default).
+    Bottom,
+}
+
+impl PixelPosition {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "top" => Some(PixelPosition::Top),
+            "bottom" => Some(PixelPosition::Bottom),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PixelPosition::Top => "top",
+            PixelPosition::Bottom => "bottom",
+        }
+    }
+
+    /// The `pixel_position` setting, else the `PIXEL_POSITION` env var, else bottom.
+    pub fn configured(db: &Database) -> Self {
+        db.get_setting("pixel_position")
+            .or_else(|| std::env::var("PIXEL_POSITION").ok())
+            .and_then(|v| PixelPosition::parse(&v))
+            .unwrap_or(PixelPosition::Bottom)
+    }
+}
+
+/// Whether each recipient of a multi-recipient tracked message gets its own
+/// copy with a distinct pixel id: the `pixel_per_recipient` setting, else
+/// the `PIXEL_PER_RECIPIENT` env var.  Off by default because it turns one
+/// delivery into one per tracked recipient.
+pub fn per_recipient_pixels(db: &Database) -> bool {
+    db.get_setting("pixel_per_recipient")
+        .or_else(|| std::env::var("PIXEL_PER_RECIPIENT").ok())
+        .map(|v| is_truthy(&v))
+        .unwrap_or(false)
+}
+
+/// Longest accepted pixel id prefix.
+const MAX_PIXEL_ID_PREFIX_LEN: usize = 32;
+
+/// Whether `prefix` may start pixel ids: letters, digits and `-_.`, short
+/// enough to leave room for the UUID.
+pub fn valid_pixel_id_prefix(prefix: &str) -> bool {
+    prefix.len() <= MAX_PIXEL_ID_PREFIX_LEN
+        && prefix
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Namespace put in front of the pixel ids this server issues, so that a
+/// `prefix*` pixel id allowlist entry matches them: the `pixel_id_prefix`
+/// setting, else the `PIXEL_ID_PREFIX` env var.  Empty by default; an
+/// invalid value is ignored.
+pub fn pixel_id_prefix(db: &Database) -> String {
+    let prefix = db
+        .get_setting("pixel_id_prefix")
+        .or_else(|| std::env::var("PIXEL_ID_PREFIX").ok())
+        .unwrap_or_default();
+    let prefix = prefix.trim();
+    if valid_pixel_id_prefix(prefix) {
+        prefix.to_string()
+    } else {
+        warn!("[filter] ignoring invalid pixel id prefix {:?}", prefix);
+        String::new()
+    }
+}
+
+/// A fresh pixel id in the `prefix` namespace.
+pub fn new_pixel_id(prefix: &str) -> String {
+    format!("{}{}", prefix, uuid::Uuid::new_v4())
+}
+
+/// Whether tracking pixels may be injected at all (the
+/// `tracking_globally_enabled` kill switch).  The value is cached for a few
+/// seconds, so a long-lived process reads it at most that often and the
+/// filter reads it once per message.
+pub fn tracking_globally_enabled(db: &Database) -> bool {
+    static CACHE: Mutex<Option<(Instant, bool)>> = Mutex::new(None);
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some((read_at, enabled)) = *cache {
+        if read_at.elapsed() < KILL_SWITCH_CACHE_TTL {
+            return enabled;
+        }
+    }
+    let enabled = db
+        .get_setting(TRACKING_KILL_SWITCH_SETTING)
+        .map(|v| v != "false")
+        .unwrap_or(true);
+    *cache = Some((Instant::now(), enabled));
+    enabled
+}
+
+/// Assign a fresh pixel id to every recipient `tracked` accepts.  Returns the
+/// `(recipient, id)` pairs and the recipients left untracked.
+fn assign_pixel_ids(
+    recipients: &[String],
+    mut tracked: impl FnMut(&str) -> bool,
+    mut new_id: impl FnMut() -> String,
+) -> (Vec<(String, String)>, Vec<String>) {
+    let mut assigned = Vec::new();
+    let mut untracked = Vec::new();
+    for rcpt in recipients {
+        if tracked(rcpt) {
+            assigned.push((rcpt.clone(), new_id()));
+        } else {
+            untracked.push(rcpt.clone());
+        }
+    }
+    (assigned, untracked)
+}
+
+/// Verify the DKIM signatures of `raw_message`, fetching keys from DNS.
+pub fn verify_dkim(raw_message: &str) -> Vec<crate::dkim::DkimResult> {
+    crate::dkim::verify(raw_message, &crate::spf::SystemResolver::default())
+}
+
+/// Insert `headers` at the top of a raw message, matching its line endings.
+fn prepend_headers(message: &mut Vec<u8>, headers: &[String]) {
+    let eol = if message.windows(2).any(|w| w == b"\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let block: String = headers.iter().map(|h| format!("{}{}", h, eol)).collect();
+    message.splice(0..0, block.into_bytes());
+}
+
+fn pixel_tag(pixel_base_url: &str, message_id: &str) -> String {
+    format!(
+        r#"<img src="{}{}" width="1" height="1" style="display:none" alt="" />"#,
+        pixel_base_url, message_id
+    )
+}
+
+/// `List-Unsubscribe` (RFC 2369) and `List-Unsubscribe-Post` (RFC 8058)
+/// header lines for one recipient's unsubscribe `token`.  The HTTPS link
+/// comes first; `mailto` adds a mail link whose subject carries the token.
+fn list_unsubscribe_headers(base_url: &str, token: &str, mailto: Option<&str>) -> String {
+    let mut links = format!(
+        "<{}/unsubscribe?token={}>",
+        base_url.trim_end_matches('/'),
+        token
+    );
+    if let Some(address) = mailto {
+        links.push_str(&format!(
+            ", <mailto:{}?subject=unsubscribe%20{}>",
+            address, token
+        ));
+    }
+    format!(
+        "List-Unsubscribe: {}\r\nList-Unsubscribe-Post: List-Unsubscribe=One-Click",
+        links
+    )
+}
+
+/// The token in the subject of a mail sent from a `List-Unsubscribe` mailto
+/// link, `unsubscribe <token>`, ignoring any `Re:`-style prefix.  Tokens
+/// are UUIDs.
+fn unsubscribe_token(subject: &str) -> Option<&str> {
+    let mut words = subject.split_whitespace();
+    words.find(|w| w.eq_ignore_ascii_case("unsubscribe"))?;
+    words.next().filter(|t| uuid::Uuid::parse_str(t).is_ok())
+}
+
+/// Record the unsubscribe requested by mail to the `unsubscribe_mailto`
+/// address.  The message itself is still delivered.
+fn record_mailto_unsubscribe(db: &Database, recipients: &[String], subject: &str) {
+    let Some(address) = db.get_setting("unsubscribe_mailto") else {
+        return;
+    };
+    let address = address.trim();
+    if address.is_empty() || !recipients.iter().any(|r| r.eq_ignore_ascii_case(address)) {
+        return;
+    }
+    let Some(token) = unsubscribe_token(subject) else {
+        debug!("[filter] mail to {} has no unsubscribe token", address);
+        return;
+    };
+    match db.get_unsubscribe_by_token(token) {
+        Some((email, domain)) => {
+            db.record_unsubscribe(&email, &domain);
+            info!(
+                "[filter] unsubscribe by mail recorded: email={} domain={}",
+                email, domain
+            );
+        }
+        None => warn!("[filter] unknown unsubscribe token in mail to {}", address),
+    }
+}
+
+/// Per-invocation options from the `filter` command line.
+pub struct FilterOptions {
+    pub incoming: bool,
+    pub max_body_bytes: usize,
+    pub opt_out_header: String,
+    /// Connecting client address and HELO name, for the SPF check.
+    pub client_address: String,
+    pub helo: String,
+    /// SASL login of the submitting client, for the sending limits.
+    pub sasl_username: String,
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        FilterOptions {
+            incoming: false,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            opt_out_header: DEFAULT_OPT_OUT_HEADER.to_string(),
+            client_address: String::new(),
+            helo: String::new(),
+            sasl_username: String::new(),
+        }
+    }
+}
 
 pub fn run_filter(
     db_url: &str,
@@ -14,28 +260,225 @@ pub fn run_filter(
     recipients: &[String],
     pixel_base_url: &str,
     unsubscribe_base_url: &str,
-    incoming: bool,
+    options: &FilterOptions,
 ) {
+    let incoming = options.incoming;
+    let max_body_bytes = options.max_body_bytes;
     info!(
         "[filter] starting content filter sender={}, recipients={}",
         sender,
         recipients.join(", ")
     );
 
+    let mut spf_policy = crate::spf::AuthPolicy::Tag;
+    let mut dkim_policy = crate::spf::AuthPolicy::Tag;
+    let mut attachment_policy = crate::attachment_policy::AttachmentPolicy::default();
+    let mut max_message_bytes = None;
+    let mut hooks = FilterHooks::default();
+
+    // One connection serves every step for this message.  Fail fast when
+    // PostgreSQL is unavailable so SMTP delivery is never blocked; the
+    // database steps are then skipped.
+    let db = Database::try_open_with_options(
+        db_url,
+        1,
+        std::time::Duration::from_millis(100),
+        std::time::Duration::from_millis(500),
+    );
+
+    // Never relay to remote recipients for senders outside the accepted
+    // domains, whatever let the message in.  Without a database, Postfix's own
+    // relay restrictions are the only check.
+    match &db {
+        Ok(db) => {
+            if !crate::relay_guard::check_message(db, sender, recipients) {
+                std::process::exit(EX_NOPERM);
+            }
+            attachment_policy = crate::attachment_policy::AttachmentPolicy::load(db);
+            hooks = FilterHooks::load(db);
+            // Over-quota mailboxes defer or bounce per the delivery policy.
+            if incoming {
+                if let Some((code, reason)) = crate::delivery_policy::check_quota(db, recipients) {
+                    println!("{}", reason);
+                    std::process::exit(code);
+                }
+                // New (client, sender, recipient) triplets are deferred.
+                if let Some(ip) = crate::spf::parse_client_address(&options.client_address) {
+                    if let Some(wait) = crate::greylist::check(db, ip, sender, recipients) {
+                        info!(
+                            "[filter] greylisting sender={} client={} for another {}s",
+                            sender, ip, wait
+                        );
+                        println!("4.7.1 Greylisted, please try again later");
+                        std::process::exit(EX_TEMPFAIL);
+                    }
+                }
+                spf_policy = crate::spf::AuthPolicy::load(db, crate::spf::POLICY_SETTING);
+                dkim_policy = crate::spf::AuthPolicy::load(db, crate::dkim::POLICY_SETTING);
+            } else {
+                if let Err(reason) =
+                    crate::send_limits::check(db, &options.sasl_username, sender, recipients.len())
+                {
+                    println!("{}", reason);
+                    std::process::exit(EX_NOPERM);
+                }
+                let setting = db.get_setting(MAX_MESSAGE_BYTES_SETTING);
+                max_message_bytes = parse_size_limit(setting.as_deref());
+            }
+        }
+        Err(e) => warn!("[filter] relay guard skipped, database unavailable: {}", e),
+    }
+
+    // SPF check of the connecting client against the envelope sender.
+    let authserv_id = std::env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    let client_ip = crate::spf::parse_client_address(&options.client_address);
+    let spf = match client_ip {
+        Some(ip) if incoming && spf_policy != crate::spf::AuthPolicy::Off => {
+            let resolver = crate::spf::SystemResolver::default();
+            let result = crate::spf::check(ip, sender, &options.helo, &resolver);
+            if result == crate::spf::SpfResult::Fail
+                && spf_policy == crate::spf::AuthPolicy::Reject
+            {
+                warn!(
+                    "[filter] rejecting message from sender={} client={}: SPF fail",
+                    sender, ip
+                );
+                std::process::exit(EX_NOPERM);
+            }
+            let header =
+                crate::spf::received_spf_header(result, &authserv_id, ip, sender, &options.helo);
+            Some((result, ip, header))
+        }
+        _ => None,
+    };
+
     let mut target_recipients = recipients.to_vec();
+    // Per-recipient tracked copies `(recipient, message)`; those recipients are
+    // removed from `target_recipients`.
+    let mut tracked_copies: Vec<(String, String)> = Vec::new();
 
-    // 1. Read entire email from stdin
+    // 1. Read the email from stdin, buffering at most `max_body_bytes`
     debug!("[filter] reading email from stdin");
-    let mut email_data = String::new();
-    if let Err(e) = io::stdin().read_to_string(&mut email_data) {
-        error!("[filter] failed to read email from stdin: {}", e);
+    let stdin = io::stdin();
+    let mut stdin = stdin.lock();
+    let (mut buffered, truncated) = match read_capped(&mut stdin, max_body_bytes) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("[filter] failed to read email from stdin: {}", e);
+            return;
+        }
+    };
+    // Outbound mail over the size limit bounces back to the sender.
+    if let Some(limit) = max_message_bytes {
+        match measure_size(&mut buffered, &mut stdin, limit) {
+            Ok(None) => {}
+            Ok(Some(size)) => {
+                let reason = size_rejection(size, limit);
+                warn!(
+                    "[filter] rejecting message from sender={}: {} bytes exceeds max_message_bytes={}",
+                    sender, size, limit
+                );
+                println!("{}", reason);
+                std::process::exit(EX_NOPERM);
+            }
+            Err(e) => {
+                error!("[filter] failed to read email from stdin: {}", e);
+                std::process::exit(EX_TEMPFAIL);
+            }
+        }
+    }
+    let mut auth_headers: Vec<String> = spf.iter().map(|(_, _, h)| h.clone()).collect();
+    if incoming && !truncated && dkim_policy != crate::spf::AuthPolicy::Off {
+        if let Ok(text) = std::str::from_utf8(&buffered) {
+            let results = verify_dkim(text);
+            let message_id = extract_header(text, "Message-ID").unwrap_or_default();
+            for r in &results {
+                info!(
+                    "[filter] DKIM {} for message_id={} d={} s={} {}",
+                    r.outcome.as_str(),
+                    message_id,
+                    r.domain,
+                    r.selector,
+                    r.reason
+                );
+            }
+            if dkim_policy == crate::spf::AuthPolicy::Reject && crate::dkim::should_reject(&results) {
+                warn!(
+                    "[filter] rejecting message_id={} from sender={}: DKIM fail",
+                    message_id, sender
+                );
+                std::process::exit(EX_NOPERM);
+            }
+            auth_headers.push(crate::dkim::authentication_results_header(
+                &authserv_id,
+                &results,
+            ));
+        }
+    }
+    // Above every Received: header, where later checks trust them.
+    prepend_headers(&mut buffered, &auth_headers);
+    if truncated {
+        // Too large to filter safely in memory.  A configured attachment
+        // policy or hook would be bypassed, so refuse the message instead.
+        if let Some(reason) = oversized_rejection(&attachment_policy, &hooks, max_body_bytes) {
+            warn!(
+                "[filter] rejecting message from sender={}: over max body size of {} bytes with an attachment policy or hook configured",
+                sender, max_body_bytes
+            );
+            let _ = io::copy(&mut stdin, &mut io::sink());
+            println!("{}", reason);
+            std::process::exit(EX_NOPERM);
+        }
+        // Otherwise stream it through untouched.
+        pass_through_oversized(
+            db.as_ref().ok(),
+            sender,
+            recipients,
+            incoming,
+            max_body_bytes,
+            buffered,
+            stdin,
+        );
         return;
     }
+    let email_data = match String::from_utf8(buffered) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("[filter] failed to read email from stdin: {}", e);
+            return;
+        }
+    };
     info!(
         "[filter] read email from stdin ({} bytes)",
         email_data.len()
     );
 
+    // Blocked attachments bounce the message or are replaced by a note.
+    let message_id = extract_header(&email_data, "Message-ID").unwrap_or_default();
+    let scanned = crate::attachment_policy::scan(&email_data, &attachment_policy, &message_id);
+    let email_data = match scanned {
+        crate::attachment_policy::Verdict::Clean => email_data,
+        crate::attachment_policy::Verdict::Stripped(stripped) => stripped,
+        crate::attachment_policy::Verdict::Rejected(reason) => {
+            warn!(
+                "[filter] rejecting message_id={} from sender={}: {}",
+                message_id, sender, reason
+            );
+            // Postfix quotes the pipe command's output in the bounce.
+            println!("5.7.1 Message rejected: {}", reason);
+            std::process::exit(EX_NOPERM);
+        }
+    };
+
+    let hook_ctx = HookContext {
+        sender,
+        recipients,
+        incoming,
+    };
+    let mut email_data = email_data;
+    // A rewrite here invalidates any DKIM signature just like our own changes.
+    let rewritten_by_hook = run_hook(&hooks, Stage::PreModify, &mut email_data, &hook_ctx, sender);
+
     // Extract headers early for use in webhook payload
     let subject = extract_header(&email_data, "Subject").unwrap_or_default();
     let from_header = extract_header(&email_data, "From").unwrap_or_default();
@@ -44,22 +487,26 @@ pub fn run_filter(
     let date_header = extract_header(&email_data, "Date").unwrap_or_default();
     let message_id_header = extract_header(&email_data, "Message-ID").unwrap_or_default();
     let size_bytes = email_data.len();
+    if let Some((result, ip, _)) = &spf {
+        info!(
+            "[filter] SPF {} for message_id={} sender={} client={}",
+            result.as_str(),
+            message_id_header,
+            sender,
+            ip
+        );
+    }
 
     // 2. Check if the content filter feature is enabled
     let mut modified = email_data.clone();
     let mut webhook_url = String::new();
     let mut suppressed = false;
     let mut spambl_hit = false;
+    let mut account_rules = Vec::new();
+    let mut deferred_until: Option<chrono::DateTime<chrono::Utc>> = None;
 
-    // Try to retrieve webhook URL first (before other database operations).
-    // If the database fails to open, we try again just for the webhook URL.
-    // Fail fast when PostgreSQL is unavailable so SMTP delivery is never blocked.
-    match Database::try_open_with_options(
-        db_url,
-        1,
-        std::time::Duration::from_millis(100),
-        std::time::Duration::from_millis(500),
-    ) {
+    // Retrieve the webhook URL first (before other database operations).
+    match &db {
         Ok(db) => {
             // Check feature toggle — if disabled, bypass all filter logic
             let filter_enabled = db
@@ -69,6 +516,13 @@ pub fn run_filter(
 
             webhook_url = db.get_setting("webhook_url").unwrap_or_default();
 
+            // Feed the DMARC aggregate reports we send about the sender's domain.
+            if incoming {
+                crate::dmarc_reporter::record_inbound(db, &email_data, sender, recipients);
+                account_rules = crate::delivery_rules::load_for_recipients(db, recipients);
+                record_mailto_unsubscribe(db, recipients, &subject);
+            }
+
             if !filter_enabled {
                 info!("[filter] content filter feature is disabled, bypassing");
             } else {
@@ -83,7 +537,7 @@ pub fn run_filter(
                     std::process::exit(EX_TEMPFAIL);
                 }
 
-                let tracking = db.is_tracking_enabled(sender, primary_recipient, &subject, size_bytes);
+                let opted_in = db.is_tracking_enabled(sender, primary_recipient, &subject, size_bytes);
                 let footer_enabled = db.is_footer_enabled(sender, primary_recipient, &subject, size_bytes);
                 let footer_html = if footer_enabled {
                     db.get_setting("footer_html").unwrap_or_default()
@@ -97,90 +551,139 @@ pub fn run_filter(
                     .map(|v| v != "false")
                     .unwrap_or(true);
                 let sender_domain = sender.split('@').nth(1).unwrap_or("").to_lowercase();
+
+                // Hold outbound marketing mail submitted during the sender domain's quiet hours.
+                if !incoming && !sender_domain.is_empty() {
+                    let quiet = db
+                        .get_quiet_hours_for_domain(&sender_domain)
+                        .and_then(|(start, end, tz)| QuietHours::parse(&start, &end, &tz));
+                    deferred_until =
+                        quiet_hours::defer_until(quiet.as_ref(), &email_data, chrono::Utc::now());
+                }
                 let unsubscribe_domain = if unsubscribe_global && !sender_domain.is_empty() {
                     db.is_unsubscribe_enabled_for_domain(&sender_domain)
                 } else {
                     false
                 };
 
+                // Sender opt-out and the unsubscribe list override the tracking rules.
+                let opt_out = if options.opt_out_header.is_empty() {
+                    None
+                } else {
+                    extract_header(&email_data, &options.opt_out_header)
+                };
+                let unsubscribed = opted_in
+                    && !sender_domain.is_empty()
+                    && !primary_recipient.is_empty()
+                    && db.is_unsubscribed(primary_recipient, &sender_domain);
+                // The kill switch overrides every tracking rule and opt-in.
+                let kill_switch = !tracking_globally_enabled(db);
+                let (tracking, rule) = if kill_switch {
+                    (false, "kill-switch")
+                } else {
+                    tracking_decision(opted_in, opt_out.as_deref(), unsubscribed)
+                };
                 info!(
-                    "[filter] tracking enabled for sender={}: {}",
-                    sender, tracking
+                    "[filter] tracking for sender={} recipient={}: {} (rule={})",
+                    sender, primary_recipient, tracking, rule
                 );
                 if !footer_html.is_empty() {
                     debug!("[filter] injecting footer for sender={}", sender);
                     modified = inject_footer(&modified, &footer_html);
                 }
-
-                if unsubscribe_domain && !unsubscribe_base_url.is_empty() {
-                    // Inject a single List-Unsubscribe header for the primary recipient (RFC 8058).
-                    // The content filter reinjects one message, so we use the first recipient's token.
-                    // Only send to recipients who have not unsubscribed — suppress promotional emails
-                    // for unsubscribed recipients while leaving transactional emails untouched.
-                    if let Some(primary_recipient) = recipients.first() {
-                        if db.is_unsubscribed(primary_recipient, &sender_domain) {
-                            info!("[filter] recipient={} has unsubscribed from domain={}, suppressing promotional email", primary_recipient, sender_domain);
-                            suppressed = true;
-                        } else {
-                            let token = uuid::Uuid::new_v4().to_string();
-                            let unsub_url = format!(
-                                "{}/unsubscribe?token={}",
-                                unsubscribe_base_url.trim_end_matches('/'),
-                                token
-                            );
-                            db.create_unsubscribe_token(&token, primary_recipient, &sender_domain);
-                            let headers = format!(
-                                "List-Unsubscribe: <{}>\r\nList-Unsubscribe-Post: List-Unsubscribe=One-Click",
-                                unsub_url
-                            );
-                            modified = inject_headers(&modified, &headers);
-                            info!("[filter] injected List-Unsubscribe header for recipient={} token={}", primary_recipient, token);
+                // The compliance disclaimer goes after the marketing footer,
+                // so at the bottom it is the last thing in the message.
+                if !incoming {
+                    if let Some(compliance) = ComplianceFooter::load(db) {
+                        let domains = crate::relay_guard::RelayDomains::load(db);
+                        if compliance.applies(sender, recipients, |d| domains.is_local(d)) {
+                            debug!("[filter] adding compliance disclaimer for sender={}", sender);
+                            modified = compliance.apply(&modified);
                         }
                     }
                 }
 
-                // Check sender IP against enabled RBL hostnames and flag if listed
+                // Check sender IP against enabled RBL hostnames and flag if listed.
+                // Verdicts are cached per IP so bursts from one client cost one lookup.
                 let rbl_hostnames = db.list_enabled_spambl_hostnames();
                 if !rbl_hostnames.is_empty() {
                     if let Some(ip) = extract_sender_ip(&email_data) {
-                        for rbl_host in &rbl_hostnames {
-                            if check_rbl(&ip, rbl_host) {
-                                modified = inject_headers(&modified, "X-Spam-Flag: YES");
-                                spambl_hit = true;
-                                info!(
-                                    "[filter] RBL hit for ip={} on {}, flagged as spam",
-                                    ip, rbl_host
-                                );
-                                break;
+                        let key = format!("rbl:{}", ip);
+                        let verdict = decision_cache::cached(db, &key, decision_cache::ttl_secs(db), || {
+                            match rbl_hostnames.iter().find(|host| check_rbl(&ip, host)) {
+                                Some(rbl_host) => {
+                                    info!("[filter] RBL hit for ip={} on {}", ip, rbl_host);
+                                    Verdict::FlagSpam
+                                }
+                                None => Verdict::Accept,
                             }
+                        });
+                        if verdict == Verdict::FlagSpam {
+                            modified = inject_headers(&modified, "X-Spam-Flag: YES");
+                            spambl_hit = true;
+                            info!("[filter] ip={} is RBL-listed, flagged as spam", ip);
                         }
                     }
                 }
 
-                if tracking {
-                    let message_id = uuid::Uuid::new_v4().to_string();
-                    let pixel_url = format!("{}{}", pixel_base_url, message_id);
-                    let pixel_tag = format!(
-                        r#"<img src="{}" width="1" height="1" style="display:none" alt="" />"#,
-                        pixel_url
+                let per_recipient =
+                    !kill_switch && recipients.len() > 1 && per_recipient_pixels(db);
+                if kill_switch {
+                    info!(
+                        "[filter] tracking pixel injection suppressed by the kill switch ({}=false)",
+                        TRACKING_KILL_SWITCH_SETTING
                     );
+                } else if per_recipient && opt_out.as_deref().map(is_truthy).unwrap_or(false) {
+                    info!("[filter] per-recipient tracking skipped: sender opted out");
+                } else if per_recipient {
+                    // Tracking rules and unsubscribes are evaluated for every recipient.
+                    let position = PixelPosition::configured(db);
+                    let id_prefix = pixel_id_prefix(db);
+                    let (assigned, untracked) = assign_pixel_ids(
+                        recipients,
+                        |rcpt| {
+                            let opted_in = db.is_tracking_enabled(sender, rcpt, &subject, size_bytes);
+                            let unsubscribed = opted_in
+                                && !sender_domain.is_empty()
+                                && db.is_unsubscribed(rcpt, &sender_domain);
+                            tracking_decision(opted_in, None, unsubscribed).0
+                        },
+                        || new_pixel_id(&id_prefix),
+                    );
+                    for (rcpt, message_id) in assigned {
+                        let Some(copy) =
+                            inject_pixel(&modified, &pixel_tag(pixel_base_url, &message_id), position)
+                        else {
+                            debug!("[filter] email is not HTML — skipping per-recipient pixels");
+                            break;
+                        };
+                        db.create_tracked_message(&message_id, sender, &rcpt, &subject, None);
+                        info!(
+                            "[filter] tracked copy for recipient={} message_id={}",
+                            rcpt, message_id
+                        );
+                        target_recipients.retain(|r| r != &rcpt);
+                        tracked_copies.push((rcpt, copy));
+                    }
+                    debug!(
+                        "[filter] {} tracked copies, {} untracked recipients",
+                        tracked_copies.len(),
+                        untracked.len()
+                    );
+                } else if tracking {
+                    let position = PixelPosition::configured(db);
+                    let message_id = new_pixel_id(&pixel_id_prefix(db));
+                    let pixel_tag = pixel_tag(pixel_base_url, &message_id);
                     debug!(
                         "[filter] generated tracking pixel message_id={}",
                         message_id
                     );
 
-                    // Try to inject before </body>
-                    if let Some(pos) = modified.to_lowercase().rfind("</body>") {
-                        modified.insert_str(pos, &pixel_tag);
-                        info!(
-                            "[filter] injected tracking pixel before </body> for message_id={}",
-                            message_id
-                        );
-                    } else if modified.contains("<html") || modified.contains("<HTML") {
-                        // Append to end if HTML but no </body>
-                        modified.push_str(&pixel_tag);
+                    if let Some(with_pixel) = inject_pixel(&modified, &pixel_tag, position) {
+                        modified = with_pixel;
                         info!(
-                            "[filter] appended tracking pixel to HTML email for message_id={}",
+                            "[filter] injected tracking pixel at {} of HTML body for message_id={}",
+                            position.as_str(),
                             message_id
                         );
                     } else {
@@ -204,6 +707,51 @@ pub fn run_filter(
                 } else {
                     debug!("[filter] no tracking — passing email through unmodified");
                 }
+
+                if !incoming && unsubscribe_domain && !unsubscribe_base_url.is_empty() {
+                    // List-Unsubscribe headers (RFC 8058) carry the token of the one recipient
+                    // they were made for: the first recipient of the shared message, and each
+                    // per-recipient copy its own.  Only send to recipients who have not
+                    // unsubscribed — suppress promotional emails for unsubscribed recipients
+                    // while leaving transactional emails untouched.
+                    let mailto = db
+                        .get_setting("unsubscribe_mailto")
+                        .filter(|a| !a.trim().is_empty());
+                    let headers_for = |rcpt: &str| {
+                        let token = uuid::Uuid::new_v4().to_string();
+                        db.create_unsubscribe_token(&token, rcpt, &sender_domain);
+                        info!(
+                            "[filter] injected List-Unsubscribe header for recipient={} token={}",
+                            rcpt, token
+                        );
+                        list_unsubscribe_headers(
+                            unsubscribe_base_url,
+                            &token,
+                            mailto.as_deref().map(str::trim),
+                        )
+                    };
+                    let mut dropped = false;
+                    tracked_copies.retain_mut(|(rcpt, copy)| {
+                        if db.is_unsubscribed(rcpt, &sender_domain) {
+                            info!("[filter] recipient={} has unsubscribed from domain={}, suppressing their copy", rcpt, sender_domain);
+                            dropped = true;
+                            return false;
+                        }
+                        *copy = inject_headers(copy, &headers_for(rcpt));
+                        true
+                    });
+                    if let Some(primary_recipient) = target_recipients.first() {
+                        if db.is_unsubscribed(primary_recipient, &sender_domain) {
+                            info!("[filter] recipient={} has unsubscribed from domain={}, suppressing promotional email", primary_recipient, sender_domain);
+                            target_recipients.clear();
+                            dropped = true;
+                        } else {
+                            modified = inject_headers(&modified, &headers_for(primary_recipient));
+                        }
+                    }
+                    suppressed =
+                        dropped && target_recipients.is_empty() && tracked_copies.is_empty();
+                }
             }
         }
         Err(e) => {
@@ -211,15 +759,6 @@ pub fn run_filter(
                 "[filter] failed to open database ({}), falling back to unmodified email",
                 e
             );
-            // Even if the database failed, try to retrieve just the webhook URL for event logging.
-            if let Ok(db) = Database::try_open_with_options(
-                db_url,
-                1,
-                std::time::Duration::from_millis(100),
-                std::time::Duration::from_millis(500),
-            ) {
-                webhook_url = db.get_setting("webhook_url").unwrap_or_default();
-            }
         }
     }
 
@@ -227,37 +766,72 @@ pub fn run_filter(
     //    (auto-creating it if necessary) instead of the Inbox.
     if incoming && spambl_hit {
         let mail_root = maildir_root();
-        let mut junk_recipients = Vec::new();
-        for rcpt in recipients {
+        let to_junk = |rcpt: &String| {
             if let Some(junk_rcpt) = move_recipient_to_junk(rcpt, &mail_root) {
                 info!(
                     "[filter] spambl hit — delivering {} to Junk folder as {}",
                     rcpt, junk_rcpt
                 );
-                junk_recipients.push(junk_rcpt);
+                junk_rcpt
             } else {
                 warn!(
                     "[filter] spambl hit for {}, but failed to prepare Junk folder; delivering normally",
                     rcpt
                 );
-                junk_recipients.push(rcpt.clone());
+                rcpt.clone()
             }
+        };
+        target_recipients = target_recipients.iter().map(to_junk).collect();
+        for (rcpt, _) in tracked_copies.iter_mut() {
+            *rcpt = to_junk(rcpt);
         }
-        target_recipients = junk_recipients;
     }
 
+    run_hook(&hooks, Stage::PostModify, &mut modified, &hook_ctx, sender);
+
     // 5. Strip invalid DKIM-Signature headers when email was modified, so OpenDKIM
     //    can re-sign the modified content cleanly on the reinject port.
-    if modified != email_data {
+    if modified != email_data || rewritten_by_hook {
         debug!("[filter] email was modified, stripping DKIM-Signature headers before reinjection");
         modified = strip_dkim_signatures(&modified);
     }
+    for (_, copy) in tracked_copies.iter_mut() {
+        *copy = strip_dkim_signatures(copy);
+    }
 
-    // 6. Prepare email metadata for the webhook (shared by suppressed and normal code paths).
-    let email_was_modified = modified != email_data;
+    // 6. Per-account delivery rules file the message into a folder or add
+    //    headers.  A recipient that gets headers is sent its own copy.  Spam
+    //    already headed for Junk stays there.
+    let mut rule_copies: Vec<(String, String)> = Vec::new();
+    if incoming && !spambl_hit && !account_rules.is_empty() {
+        apply_delivery_rules(
+            &account_rules,
+            &modified,
+            &maildir_root(),
+            &mut target_recipients,
+            &mut tracked_copies,
+            &mut rule_copies,
+        );
+    }
+
+    // Each delivery is one message and its envelope recipients: the shared
+    // message, plus one tracked copy per recipient in per-recipient mode.
+    let email_was_modified =
+        modified != email_data || !tracked_copies.is_empty() || !rule_copies.is_empty();
+    let mut all_recipients = target_recipients.clone();
+    all_recipients.extend(tracked_copies.iter().map(|(rcpt, _)| rcpt.clone()));
+    all_recipients.extend(rule_copies.iter().map(|(rcpt, _)| rcpt.clone()));
+    let mut deliveries: Vec<(String, Vec<String>)> = Vec::new();
+    if !target_recipients.is_empty() {
+        deliveries.push((modified, target_recipients));
+    }
+    deliveries.extend(tracked_copies.into_iter().map(|(rcpt, copy)| (copy, vec![rcpt])));
+    deliveries.extend(rule_copies.into_iter().map(|(rcpt, copy)| (copy, vec![rcpt])));
+
+    // 7. Prepare email metadata for the webhook (shared by suppressed and normal code paths).
     let meta = EmailMetadata {
         sender: sender.to_string(),
-        recipients: target_recipients.clone(),
+        recipients: all_recipients,
         subject: subject.clone(),
         from: from_header.clone(),
         to: to_header.clone(),
@@ -270,16 +844,17 @@ pub fn run_filter(
         } else {
             "outgoing".to_string()
         },
+        tracking_skipped: None,
     };
 
-    // 7. If the email was suppressed because the recipient has unsubscribed, drop
+    // 8. If the email was suppressed because the recipient has unsubscribed, drop
     //    the message here (do not reinject) without an error so Postfix discards it.
     //    Fire the webhook so the event is still visible to the caller.
     if suppressed {
         info!("[filter] email suppressed — not reinjecting (see earlier log for recipient/domain)");
         send_webhook(
             &webhook_url,
-            db_url,
+            db.as_ref().ok(),
             &meta,
             email_was_modified,
             sender,
@@ -288,7 +863,58 @@ pub fn run_filter(
         return;
     }
 
-    // 8. Reinject via SMTP to 127.0.0.1:10025
+    // 9. Marketing mail submitted during quiet hours is held for the scheduler,
+    //    which reinjects it when the window closes.  If it cannot be stored,
+    //    fall through and send it now rather than risk losing it.
+    if let Some(send_at) = deferred_until {
+        let send_at = send_at.format("%Y-%m-%d %H:%M:%S").to_string();
+        match &db {
+            Ok(db) => {
+                deliveries.retain(|(message, rcpts)| {
+                    match db.create_scheduled_message(sender, rcpts, message, &send_at) {
+                        Ok(id) => {
+                            info!(
+                                "[filter] quiet hours — held marketing email id={} until {} UTC",
+                                id, send_at
+                            );
+                            false
+                        }
+                        Err(e) => {
+                            warn!(
+                                "[filter] failed to hold email for quiet hours ({}), sending immediately",
+                                e
+                            );
+                            true
+                        }
+                    }
+                });
+            }
+            Err(e) => {
+                warn!(
+                    "[filter] failed to hold email for quiet hours ({}), sending immediately",
+                    e
+                );
+            }
+        }
+        if deliveries.is_empty() {
+            send_webhook(
+                &webhook_url,
+                db.as_ref().ok(),
+                &meta,
+                email_was_modified,
+                sender,
+                &subject,
+            );
+            return;
+        }
+    }
+
+    // Every delivery passes the last hook before any of them is sent.
+    for (message, _) in deliveries.iter_mut() {
+        run_hook(&hooks, Stage::PreReinject, message, &hook_ctx, sender);
+    }
+
+    // 10. Reinject via SMTP to 127.0.0.1:10025
     info!("[filter] reinjecting email via SMTP to 127.0.0.1:10025");
 
     // Spawn the webhook thread early so it can start in parallel with the reinject.
@@ -296,7 +922,7 @@ pub fn run_filter(
     let (modified_tx, modified_rx) = mpsc::channel::<Option<bool>>();
     let webhook_handle = {
         let url = webhook_url.clone();
-        let db_url_owned = db_url.to_string();
+        let webhook_db = db.as_ref().ok().cloned();
         let sender_owned = sender.to_string();
         let subject_owned = subject.clone();
         std::thread::spawn(move || {
@@ -305,7 +931,7 @@ pub fn run_filter(
                 Ok(Some(was_modified)) => {
                     send_webhook(
                         &url,
-                        &db_url_owned,
+                        webhook_db.as_ref(),
                         &meta,
                         was_modified,
                         &sender_owned,
@@ -318,84 +944,539 @@ pub fn run_filter(
         })
     };
 
-    if let Err(e) = reinject_smtp(&modified, sender, &target_recipients) {
+    let outcome = reinject_deliveries(deliveries, &email_data, |message, rcpts| {
+        reinject_smtp(message, sender, rcpts)
+    });
+    if outcome.sent == 0 {
+        // Signal the webhook thread to not fire (nothing was reinjected).
+        let _ = modified_tx.send(None);
+        let _ = webhook_handle.join();
+        // Tell Postfix to retry delivery rather than silently dropping the message.
+        std::process::exit(EX_TEMPFAIL);
+    }
+    if outcome.failed.is_empty() {
+        info!("[filter] email reinjected successfully");
+    } else {
+        // Some recipients already have the message, so a retry by Postfix
+        // would send it to them twice.  The scheduler retries the rest.
+        hold_failed_deliveries(db.as_ref().ok(), sender, &outcome.failed);
+    }
+
+    // Signal webhook thread with the actual modified flag; it will fire the
+    // HTTP call.  After a fallback the email sent is the original.
+    let _ = modified_tx.send(Some(email_was_modified && !outcome.fell_back));
+    // Wait for the webhook thread to complete before the process exits.
+    let _ = webhook_handle.join();
+}
+
+/// What became of the deliveries of one message.
+#[derive(Debug, Default)]
+struct ReinjectOutcome {
+    /// Deliveries accepted, modified or as the unmodified fallback.
+    sent: usize,
+    /// Whether any delivery was sent as the unmodified fallback.
+    fell_back: bool,
+    /// Deliveries refused both ways, with the message meant for them.
+    failed: Vec<(String, Vec<String>)>,
+}
+
+/// Send every delivery with `send`, falling back to the unmodified
+/// `original` when a modified message is refused.  A failed delivery does
+/// not stop the ones after it.
+fn reinject_deliveries(
+    deliveries: Vec<(String, Vec<String>)>,
+    original: &str,
+    mut send: impl FnMut(&str, &[String]) -> io::Result<()>,
+) -> ReinjectOutcome {
+    let mut outcome = ReinjectOutcome::default();
+    for (message, rcpts) in deliveries {
+        let Err(e) = send(&message, &rcpts) else {
+            outcome.sent += 1;
+            continue;
+        };
         warn!(
             "[filter] failed to reinject modified email: {}. attempting unmodified fallback",
             e
         );
-        if let Err(e) = reinject_smtp(&email_data, sender, &target_recipients) {
-            error!(
-                "[filter] failed to reinject unmodified fallback email: {}",
+        match send(original, &rcpts) {
+            Ok(()) => {
+                info!("[filter] unmodified fallback email reinjected successfully");
+                outcome.sent += 1;
+                outcome.fell_back = true;
+            }
+            Err(e) => {
+                error!(
+                    "[filter] failed to reinject unmodified fallback email to {}: {}",
+                    rcpts.join(", "),
+                    e
+                );
+                outcome.failed.push((message, rcpts));
+            }
+        }
+    }
+    outcome
+}
+
+/// Hold deliveries that could not be reinjected in `scheduled_messages`,
+/// where the scheduler started by `serve` retries them until they are sent.
+fn hold_failed_deliveries(db: Option<&Database>, sender: &str, failed: &[(String, Vec<String>)]) {
+    let now = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    for (message, rcpts) in failed {
+        let held = match db {
+            Some(db) => db.create_scheduled_message(sender, rcpts, message, &now),
+            None => Err("database unavailable".to_string()),
+        };
+        match held {
+            Ok(id) => warn!(
+                "[filter] held undelivered copy id={} for {} to retry later",
+                id,
+                rcpts.join(", ")
+            ),
+            Err(e) => error!(
+                "[filter] could not hold undelivered copy for {}, it is lost: {}",
+                rcpts.join(", "),
                 e
+            ),
+        }
+    }
+}
+
+/// Run the hook for `stage` on `message`.  A rejecting hook bounces the
+/// message and a deferring one asks Postfix to retry; neither returns.
+/// Returns whether the hook rewrote the message.
+fn run_hook(
+    hooks: &FilterHooks,
+    stage: Stage,
+    message: &mut String,
+    ctx: &HookContext,
+    sender: &str,
+) -> bool {
+    match hooks.run(stage, message, ctx) {
+        HookVerdict::Accept(Some(rewritten)) => {
+            *message = rewritten;
+            true
+        }
+        HookVerdict::Accept(None) => false,
+        HookVerdict::Reject(reason) => {
+            warn!(
+                "[filter] {} hook rejected message from sender={}: {}",
+                stage.as_str(),
+                sender,
+                reason
             );
-            // Signal the webhook thread to not fire (both injects failed).
-            let _ = modified_tx.send(None);
-            let _ = webhook_handle.join();
-            // Tell Postfix to retry delivery rather than silently dropping the message.
+            println!("5.7.1 Message rejected: {}", reason);
+            std::process::exit(EX_NOPERM);
+        }
+        HookVerdict::Defer(reason) => {
+            warn!(
+                "[filter] {} hook deferred message from sender={}: {}",
+                stage.as_str(),
+                sender,
+                reason
+            );
+            println!("4.7.1 Message deferred: {}", reason);
+            std::process::exit(EX_TEMPFAIL);
+        }
+    }
+}
+
+/// Read at most `max` bytes from `reader`.  The flag is set when the input
+/// is longer; the returned buffer then holds the first `max + 1` bytes and
+/// the remainder is still unread.
+fn read_capped(reader: &mut impl Read, max: usize) -> io::Result<(Vec<u8>, bool)> {
+    let mut buf = Vec::new();
+    reader
+        .by_ref()
+        .take(max as u64 + 1)
+        .read_to_end(&mut buf)?;
+    let truncated = buf.len() > max;
+    Ok((buf, truncated))
+}
+
+/// `max_message_bytes` as a limit; zero, absent or malformed is unlimited.
+fn parse_size_limit(value: Option<&str>) -> Option<usize> {
+    value
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&limit| limit > 0)
+}
+
+/// Whether the message, of which `buffered` is the start and `rest` the
+/// remainder, is larger than `limit`.  Buffers at most `limit + 1` bytes;
+/// when the message is too large, the rest is counted and discarded and the
+/// total size returned.
+fn measure_size(
+    buffered: &mut Vec<u8>,
+    rest: &mut impl Read,
+    limit: usize,
+) -> io::Result<Option<usize>> {
+    if buffered.len() <= limit {
+        let wanted = (limit + 1 - buffered.len()) as u64;
+        rest.by_ref().take(wanted).read_to_end(buffered)?;
+    }
+    if buffered.len() <= limit {
+        return Ok(None);
+    }
+    let remaining = io::copy(rest, &mut io::sink())?;
+    Ok(Some(buffered.len() + remaining as usize))
+}
+
+/// Bounce text for a message over the outbound size limit.
+fn size_rejection(size: usize, limit: usize) -> String {
+    format!(
+        "5.3.4 Message size {} bytes exceeds the outbound limit of {} bytes",
+        size, limit
+    )
+}
+
+/// Bounce text for a message over the buffering cap when `policy` or `hooks`
+/// would have to inspect it; `None` when it may be passed through.
+fn oversized_rejection(
+    policy: &crate::attachment_policy::AttachmentPolicy,
+    hooks: &FilterHooks,
+    max_body_bytes: usize,
+) -> Option<String> {
+    let scanned = policy.action != crate::attachment_policy::AttachmentAction::Off;
+    if !scanned && !hooks.is_configured() {
+        return None;
+    }
+    Some(format!(
+        "5.3.4 Message too large to scan: the limit is {} bytes",
+        max_body_bytes
+    ))
+}
+
+/// Deliver a message that exceeds the buffering cap without filtering it.
+///
+/// The buffered prefix and the rest of stdin are streamed straight to the
+/// reinjection port; the RBL check, footers, List-Unsubscribe, tracking and
+/// delivery rules are skipped and the webhook records why.  Only called when
+/// no attachment policy or hook is configured, see [`oversized_rejection`].
+fn pass_through_oversized(
+    db: Option<&Database>,
+    sender: &str,
+    recipients: &[String],
+    incoming: bool,
+    max_body_bytes: usize,
+    buffered: Vec<u8>,
+    rest: impl io::BufRead,
+) {
+    warn!(
+        "[filter] message from sender={} exceeds max body size of {} bytes, skipping RBL, footers, List-Unsubscribe, tracking and delivery rules",
+        sender, max_body_bytes
+    );
+    let prefix = String::from_utf8_lossy(&buffered).into_owned();
+    let header = |name: &str| extract_header(&prefix, name).unwrap_or_default();
+    let subject = header("Subject");
+    let mut meta = EmailMetadata {
+        sender: sender.to_string(),
+        recipients: recipients.to_vec(),
+        subject: subject.clone(),
+        from: header("From"),
+        to: header("To"),
+        cc: header("Cc"),
+        date: header("Date"),
+        message_id: header("Message-ID"),
+        size_bytes: buffered.len(),
+        direction: if incoming {
+            "incoming".to_string()
+        } else {
+            "outgoing".to_string()
+        },
+        tracking_skipped: Some("message_too_large".to_string()),
+    };
+
+    match reinject_smtp_from(io::Cursor::new(buffered).chain(rest), sender, recipients) {
+        Ok(size) => {
+            info!("[filter] oversized email reinjected unmodified ({} bytes)", size);
+            meta.size_bytes = size;
+        }
+        Err(e) => {
+            // stdin has been consumed, so there is no fallback: let Postfix retry.
+            error!("[filter] failed to reinject oversized email: {}", e);
             std::process::exit(EX_TEMPFAIL);
         }
-        info!("[filter] unmodified fallback email reinjected successfully");
-        // Fallback succeeded: the email sent is the original (unmodified).
-        let _ = modified_tx.send(Some(false));
-        let _ = webhook_handle.join();
-        return;
     }
-    info!("[filter] email reinjected successfully");
 
-    // Signal webhook thread with the actual modified flag; it will fire the HTTP call.
-    let _ = modified_tx.send(Some(email_was_modified));
-    // Wait for the webhook thread to complete before the process exits.
-    let _ = webhook_handle.join();
+    let webhook_url = db
+        .and_then(|db| db.get_setting("webhook_url"))
+        .unwrap_or_default();
+    send_webhook(&webhook_url, db, &meta, false, sender, &subject);
+}
+
+fn inject_headers(email: &str, headers: &str) -> String {
+    // Detect line-ending style
+    let eol = if email.contains("\r\n") { "\r\n" } else { "\n" };
+    let sep = if eol == "\r\n" { "\r\n\r\n" } else { "\n\n" };
+    // Find end of header section
+    if let Some(pos) = email.find(sep) {
+        // Keep the last header's line ending so the new ones start a line.
+        let mut result = email[..pos + eol.len()].to_string();
+        // Append new headers before the blank line
+        for line in headers.lines() {
+            result.push_str(line);
+            result.push_str(eol);
+        }
+        result.push_str(eol);
+        result.push_str(&email[pos + sep.len()..]);
+        result
+    } else {
+        email.to_string()
+    }
+}
+
+fn inject_footer(email: &str, footer_html: &str) -> String {
+    if footer_html.trim().is_empty() {
+        return email.to_string();
+    }
+    let mut output = email.to_string();
+    let lower = output.to_ascii_lowercase();
+    let footer_block = format!(
+        r#"<div class="domain-footer" style="margin-top:24px;border-top:1px solid #e2e8f0;padding-top:12px;font-size:0.9em;color:#475569;line-height:1.4;">{}</div>"#,
+        footer_html
+    );
+    if let Some(pos) = lower.rfind("</body>") {
+        output.insert_str(pos, &footer_block);
+        return output;
+    }
+    if lower.contains("<html") {
+        output.push_str(&footer_block);
+        return output;
+    }
+    let plain = strip_html_tags(footer_html);
+    if plain.is_empty() {
+        return output;
+    }
+    output.push_str("\n\n-- \n");
+    output.push_str(&plain);
+    output
+}
+
+/// Insert the tracking `pixel_tag` into the HTML body of `email`.
+///
+/// For multipart messages the first `text/html` part (at any depth) is
+/// decoded from its transfer encoding, the pixel is added, and the part is
+/// re-encoded in place so the surrounding boundaries stay intact.  A
+/// single-part HTML message is decoded and re-encoded the same way when it
+/// uses base64 or quoted-printable.  Returns `None` when the message has no
+/// HTML body to inject into.
+fn inject_pixel(email: &str, pixel_tag: &str, position: PixelPosition) -> Option<String> {
+    let parsed = match mailparse::parse_mail(email.as_bytes()) {
+        Ok(p) => p,
+        Err(e) => {
+            debug!("[filter] failed to parse MIME structure ({}), using raw body", e);
+            return inject_pixel_raw(email, pixel_tag, position);
+        }
+    };
+    if !parsed.ctype.mimetype.starts_with("multipart/") {
+        // Encoded single-part HTML must be decoded first; inserting into the
+        // encoded text would corrupt it.  7bit/8bit/binary bodies are plain.
+        let encoding = transfer_encoding(&parsed);
+        if parsed.ctype.mimetype.eq_ignore_ascii_case("text/html")
+            && (encoding == "base64" || encoding == "quoted-printable")
+        {
+            return rebuild_html_part(email, &parsed, pixel_tag, position);
+        }
+        return inject_pixel_raw(email, pixel_tag, position);
+    }
+    let part = find_html_part(&parsed)?;
+    // `raw_bytes` borrows from `email`, so its offset locates the part in the original text.
+    let start = part.raw_bytes.as_ptr() as usize - email.as_ptr() as usize;
+    let end = start + part.raw_bytes.len();
+    let rebuilt = rebuild_html_part(email.get(start..end)?, part, pixel_tag, position)?;
+    debug!(
+        "[filter] injected pixel into {} part of {} message",
+        part.ctype.mimetype, parsed.ctype.mimetype
+    );
+    Some(format!("{}{}{}", &email[..start], rebuilt, &email[end..]))
+}
+
+/// Naive injection into an undecoded single-part message body.
+fn inject_pixel_raw(email: &str, pixel_tag: &str, position: PixelPosition) -> Option<String> {
+    let output = insert_pixel_bytes(email.as_bytes(), pixel_tag, position)?;
+    String::from_utf8(output).ok()
+}
+
+/// Depth-first search for the first non-attachment `text/html` part.
+fn find_html_part<'a>(mail: &'a mailparse::ParsedMail<'a>) -> Option<&'a mailparse::ParsedMail<'a>> {
+    if mail.ctype.mimetype.starts_with("multipart/") {
+        return mail.subparts.iter().find_map(find_html_part);
+    }
+    let is_attachment =
+        mail.get_content_disposition().disposition == mailparse::DispositionType::Attachment;
+    if mail.ctype.mimetype.eq_ignore_ascii_case("text/html") && !is_attachment {
+        Some(mail)
+    } else {
+        None
+    }
+}
+
+/// Rebuild one MIME part with the pixel added to its decoded HTML body.
+fn rebuild_html_part(
+    raw: &str,
+    part: &mailparse::ParsedMail,
+    pixel_tag: &str,
+    position: PixelPosition,
+) -> Option<String> {
+    rebuild_part(raw, part, |decoded| insert_pixel_bytes(decoded, pixel_tag, position))
+}
+
+/// Rebuild one MIME part, `raw` being its headers and body, with `edit`
+/// applied to its decoded body.
+///
+/// The part's headers are kept verbatim and the body is re-encoded with the
+/// part's original `Content-Transfer-Encoding`.
+pub(crate) fn rebuild_part(
+    raw: &str,
+    part: &mailparse::ParsedMail,
+    edit: impl FnOnce(&[u8]) -> Option<Vec<u8>>,
+) -> Option<String> {
+    let eol = if raw.contains("\r\n") { "\r\n" } else { "\n" };
+    let sep = if eol == "\r\n" { "\r\n\r\n" } else { "\n\n" };
+    let header_end = raw.find(sep)? + sep.len();
+    let (headers, body) = raw.split_at(header_end);
+
+    let decoded = part.get_body_raw().ok()?;
+    let edited = edit(&decoded)?;
+
+    let mut encoded = match transfer_encoding(part).as_str() {
+        "base64" => {
+            use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+            let b64 = BASE64.encode(&edited);
+            b64.as_bytes()
+                .chunks(76)
+                .map(|line| String::from_utf8_lossy(line).into_owned())
+                .collect::<Vec<_>>()
+                .join(eol)
+        }
+        "quoted-printable" => {
+            // The encoder emits CRLF for hard and soft line breaks; normalise
+            // bare LF input first and then match the message's line endings.
+            let mut crlf = Vec::with_capacity(edited.len());
+            for (i, &b) in edited.iter().enumerate() {
+                if b == b'\n' && (i == 0 || edited[i - 1] != b'\r') {
+                    crlf.push(b'\r');
+                }
+                crlf.push(b);
+            }
+            quoted_printable::encode_to_str(&crlf).replace("\r\n", eol)
+        }
+        _ => String::from_utf8(edited).ok()?,
+    };
+    // Keep the line break that separated the body from the next boundary.
+    if body.ends_with(eol) && !encoded.ends_with(eol) {
+        encoded.push_str(eol);
+    }
+    Some(format!("{}{}", headers, encoded))
+}
+
+/// Lower-cased `Content-Transfer-Encoding` of a part, empty when absent.
+fn transfer_encoding(part: &mailparse::ParsedMail) -> String {
+    part.headers
+        .iter()
+        .find(|h| h.get_key().eq_ignore_ascii_case("Content-Transfer-Encoding"))
+        .map(|h| h.get_value().trim().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// Insert `pixel_tag` before the last `</body>` (or right after the opening
+/// `<body>` tag for [`PixelPosition::Top`]), or append it to an HTML document
+/// without a body.  Returns `None` for content that is not HTML.
+fn insert_pixel_bytes(html: &[u8], pixel_tag: &str, position: PixelPosition) -> Option<Vec<u8>> {
+    let lower = html.to_ascii_lowercase();
+    if position == PixelPosition::Top {
+        if let Some(pos) = body_content_start(&lower) {
+            let mut output = Vec::with_capacity(html.len() + pixel_tag.len());
+            output.extend_from_slice(&html[..pos]);
+            output.extend_from_slice(pixel_tag.as_bytes());
+            output.extend_from_slice(&html[pos..]);
+            return Some(output);
+        }
+    }
+    let closing = b"</body>";
+    if let Some(pos) = lower.windows(closing.len()).rposition(|w| w == closing) {
+        let mut output = Vec::with_capacity(html.len() + pixel_tag.len());
+        output.extend_from_slice(&html[..pos]);
+        output.extend_from_slice(pixel_tag.as_bytes());
+        output.extend_from_slice(&html[pos..]);
+        return Some(output);
+    }
+    if lower.windows(5).any(|w| w == b"<html") {
+        let mut output = html.to_vec();
+        output.extend_from_slice(pixel_tag.as_bytes());
+        return Some(output);
+    }
+    None
+}
+
+/// Offset just past the first `<body ...>` tag in lower-cased HTML.
+pub(crate) fn body_content_start(lower: &[u8]) -> Option<usize> {
+    let mut from = 0;
+    while let Some(rel) = lower[from..].windows(5).position(|w| w == b"<body") {
+        let tag = from + rel;
+        match lower.get(tag + 5) {
+            Some(b'>') | Some(b' ') | Some(b'\t') | Some(b'\r') | Some(b'\n') | Some(b'/') => {
+                let close = lower[tag..].iter().position(|&b| b == b'>')?;
+                return Some(tag + close + 1);
+            }
+            _ => from = tag + 5,
+        }
+    }
+    None
+}
+
+/// Whether an `<img src>` points at our pixel endpoint: it starts with the
+/// configured pixel base URL, or has the `/pixel?id=` path the pixel server
+/// answers on (so pixels from before a base URL change still match).
+pub fn is_tracking_pixel_src(src: &str, pixel_base_url: &str) -> bool {
+    let src = src.trim();
+    (!pixel_base_url.is_empty() && src.starts_with(pixel_base_url)) || src.contains("/pixel?id=")
 }
 
-fn inject_headers(email: &str, headers: &str) -> String {
-    // Detect line-ending style
-    let eol = if email.contains("\r\n") { "\r\n" } else { "\n" };
-    let sep = if eol == "\r\n" { "\r\n\r\n" } else { "\n\n" };
-    // Find end of header section
-    if let Some(pos) = email.find(sep) {
-        let mut result = email[..pos].to_string();
-        // Append new headers before the blank line
-        for line in headers.lines() {
-            result.push_str(line);
-            result.push_str(eol);
+/// Value of the `src` attribute of one `<img ...>` tag.
+fn img_src(tag: &str) -> Option<&str> {
+    let lower = tag.to_ascii_lowercase();
+    let mut from = 0;
+    while let Some(pos) = lower[from..].find("src") {
+        let start = from + pos;
+        from = start + 3;
+        let preceded_by_space = lower[..start].ends_with(|c: char| c.is_ascii_whitespace());
+        let rest = lower[from..].trim_start();
+        if !preceded_by_space || !rest.starts_with('=') {
+            continue;
         }
-        result.push_str(eol);
-        result.push_str(&email[pos + sep.len()..]);
-        result
-    } else {
-        email.to_string()
+        let value_start = tag.len() - rest.len() + 1;
+        let value = tag[value_start..].trim_start();
+        return match value.chars().next()? {
+            q @ ('"' | '\'') => value[1..].split(q).next(),
+            _ => value.split(|c: char| c.is_ascii_whitespace() || c == '>').next(),
+        };
     }
+    None
 }
 
-fn inject_footer(email: &str, footer_html: &str) -> String {
-    if footer_html.trim().is_empty() {
-        return email.to_string();
-    }
-    let mut output = email.to_string();
-    let lower = output.to_ascii_lowercase();
-    let footer_block = format!(
-        r#"<div class="domain-footer" style="margin-top:24px;border-top:1px solid #e2e8f0;padding-top:12px;font-size:0.9em;color:#475569;line-height:1.4;">{}</div>"#,
-        footer_html
-    );
-    if let Some(pos) = lower.rfind("</body>") {
-        output.insert_str(pos, &footer_block);
-        return output;
-    }
-    if lower.contains("<html") {
-        output.push_str(&footer_block);
-        return output;
-    }
-    let plain = strip_html_tags(footer_html);
-    if plain.is_empty() {
-        return output;
+/// Remove `<img>` tags whose `src` is one of our tracking pixels.
+pub fn strip_tracking_pixels(html: &str, pixel_base_url: &str) -> String {
+    let lower = html.to_ascii_lowercase();
+    let mut out = String::with_capacity(html.len());
+    let mut pos = 0;
+    while let Some(found) = lower[pos..].find("<img") {
+        let start = pos + found;
+        let Some(len) = lower[start..].find('>') else {
+            break;
+        };
+        let end = start + len + 1;
+        out.push_str(&html[pos..start]);
+        let tag = &html[start..end];
+        if !img_src(tag).is_some_and(|src| is_tracking_pixel_src(src, pixel_base_url)) {
+            out.push_str(tag);
+        }
+        pos = end;
     }
-    output.push_str("\n\n-- \n");
-    output.push_str(&plain);
-    output
+    out.push_str(&html[pos..]);
+    out
 }
 
-fn strip_html_tags(input: &str) -> String {
+pub(crate) fn strip_html_tags(input: &str) -> String {
     let mut output = String::with_capacity(input.len());
     let mut in_tag = false;
     for c in input.chars() {
@@ -411,6 +1492,34 @@ fn strip_html_tags(input: &str) -> String {
     output.trim().to_string()
 }
 
+/// Whether a header value reads as "on" (`1`, `yes`, `true`, `on`).
+fn is_truthy(value: &str) -> bool {
+    matches!(
+        value.trim().to_ascii_lowercase().as_str(),
+        "1" | "yes" | "true" | "on"
+    )
+}
+
+/// Final tracking decision and the name of the rule that produced it.
+///
+/// A truthy opt-out header wins over everything, then the recipient being on
+/// the sender domain's unsubscribe list, then the tracking rules (`opted_in`).
+pub fn tracking_decision(
+    opted_in: bool,
+    opt_out_header: Option<&str>,
+    unsubscribed: bool,
+) -> (bool, &'static str) {
+    if opt_out_header.is_some_and(is_truthy) {
+        (false, "opt-out-header")
+    } else if unsubscribed {
+        (false, "unsubscribe-list")
+    } else if opted_in {
+        (true, "tracking-rule")
+    } else {
+        (false, "no-tracking-rule")
+    }
+}
+
 fn extract_header(email: &str, header_name: &str) -> Option<String> {
     debug!("[filter] extracting header: {}", header_name);
     let prefix = format!("{}:", header_name.to_lowercase());
@@ -428,7 +1537,17 @@ fn extract_header(email: &str, header_name: &str) -> Option<String> {
     None
 }
 
-fn reinject_smtp(email: &str, sender: &str, recipients: &[String]) -> io::Result<()> {
+pub(crate) fn reinject_smtp(email: &str, sender: &str, recipients: &[String]) -> io::Result<()> {
+    reinject_smtp_from(email.as_bytes(), sender, recipients).map(|_| ())
+}
+
+/// Reinject a message read line by line from `email`, so that large messages
+/// need not be held in memory.  Returns the number of message bytes sent.
+fn reinject_smtp_from(
+    mut email: impl io::BufRead,
+    sender: &str,
+    recipients: &[String],
+) -> io::Result<usize> {
     use std::io::{BufReader, Write};
     use std::net::TcpStream;
 
@@ -474,14 +1593,29 @@ fn reinject_smtp(email: &str, sender: &str, recipients: &[String]) -> io::Result
     smtp_expect(&resp, "354")?;
 
     // Send email body (dot-stuff lines starting with .)
-    debug!("[filter] sending email body ({} bytes)", email.len());
-    for line in email.lines() {
-        if line.starts_with('.') {
-            writer.write_all(format!(".{}\r\n", line).as_bytes())?;
-        } else {
-            writer.write_all(format!("{}\r\n", line).as_bytes())?;
+    debug!("[filter] sending email body");
+    let mut size = 0usize;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let n = email.read_until(b'\n', &mut line)?;
+        if n == 0 {
+            break;
+        }
+        size += n;
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        if line.starts_with(b".") {
+            writer.write_all(b".")?;
         }
+        writer.write_all(&line)?;
+        writer.write_all(b"\r\n")?;
     }
+    debug!("[filter] sent email body ({} bytes)", size);
 
     // End DATA
     writer.write_all(b".\r\n")?;
@@ -495,7 +1629,7 @@ fn reinject_smtp(email: &str, sender: &str, recipients: &[String]) -> io::Result
     debug!("[filter] QUIT response: {}", resp.trim());
 
     info!("[filter] SMTP reinjection completed for sender={}", sender);
-    Ok(())
+    Ok(size)
 }
 
 /// Read a complete SMTP response (possibly multi-line) from a buffered reader.
@@ -551,11 +1685,13 @@ struct EmailMetadata {
     message_id: String,
     size_bytes: usize,
     direction: String,
+    /// Why tracking and other content changes were skipped, if they were.
+    tracking_skipped: Option<String>,
 }
 
 fn send_webhook(
     webhook_url: &str,
-    db_url: &str,
+    db: Option<&Database>,
     meta: &EmailMetadata,
     modified: bool,
     sender: &str,
@@ -576,72 +1712,82 @@ fn send_webhook(
         "message_id": meta.message_id,
         "size_bytes": meta.size_bytes,
         "modified": modified,
+        "tracking_skipped": meta.tracking_skipped,
     });
     let request_body = payload.to_string();
+    if webhook_url.is_empty() {
+        return;
+    }
 
-    let (response_status, response_body, error, duration_ms) = if webhook_url.is_empty() {
-        (None, String::new(), String::new(), 0i64)
-    } else {
-        debug!("[filter] sending webhook to {}", webhook_url);
-        let start = std::time::Instant::now();
+    // Best-effort: the secret for signing comes from the database, which is
+    // also where the attempt is logged.
+    let secret = db
+        .and_then(|db| db.get_setting("webhook_secret"))
+        .unwrap_or_default();
+    let signature = crate::web::WebhookSignature::sign(&secret, &request_body);
 
-        let (response_status, response_body, error) = match reqwest::blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
-            .build()
+    debug!("[filter] sending webhook to {}", webhook_url);
+    let start = std::time::Instant::now();
+
+    let (response_status, response_body, error) = match reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => match crate::web::webhook_request(
+            &client,
+            webhook_url,
+            &request_body,
+            signature.as_ref(),
+        )
+        .send()
         {
-            Ok(client) => match client.post(webhook_url).json(&payload).send() {
-                Ok(resp) => {
-                    let status = resp.status().as_u16() as i32;
-                    let body = resp.text().unwrap_or_default();
-                    // Truncate response body to 2 KB for storage (char-boundary safe)
-                    let body_truncated = if body.len() > 2048 {
-                        let mut end = 2048;
-                        while !body.is_char_boundary(end) {
-                            end -= 1;
-                        }
-                        body[..end].to_string()
-                    } else {
-                        body
-                    };
-                    info!(
-                        "[filter] webhook delivered to {} status={}",
-                        webhook_url, status
-                    );
-                    (Some(status), body_truncated, String::new())
-                }
-                Err(e) => {
-                    warn!("[filter] webhook delivery failed to {}: {}", webhook_url, e);
-                    (None, String::new(), e.to_string())
-                }
-            },
+            Ok(resp) => {
+                let status = resp.status().as_u16() as i32;
+                let body = resp.text().unwrap_or_default();
+                // Truncate response body to 2 KB for storage (char-boundary safe)
+                let body_truncated = if body.len() > 2048 {
+                    let mut end = 2048;
+                    while !body.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    body[..end].to_string()
+                } else {
+                    body
+                };
+                info!(
+                    "[filter] webhook delivered to {} status={}",
+                    webhook_url, status
+                );
+                (Some(status), body_truncated, String::new())
+            }
             Err(e) => {
-                warn!("[filter] failed to build HTTP client for webhook: {}", e);
+                warn!("[filter] webhook delivery failed to {}: {}", webhook_url, e);
                 (None, String::new(), e.to_string())
             }
-        };
-
-        let duration_ms = start.elapsed().as_millis() as i64;
-        (response_status, response_body, error, duration_ms)
+        },
+        Err(e) => {
+            warn!("[filter] failed to build HTTP client for webhook: {}", e);
+            (None, String::new(), e.to_string())
+        }
     };
 
-    // Log to the database when a webhook was actually dispatched (best-effort).
-    if !webhook_url.is_empty() {
-        if let Ok(db) = Database::try_open_with_options(
-            db_url,
-            1,
-            std::time::Duration::from_millis(100),
-            std::time::Duration::from_millis(500),
-        ) {
-            db.log_webhook(
-                webhook_url,
-                &request_body,
-                response_status,
-                &response_body,
-                &error,
-                duration_ms,
-                sender,
-                subject,
-            );
+    let duration_ms = start.elapsed().as_millis() as i64;
+
+    // Log the attempt, and queue transient failures for the webhook worker to
+    // retry (best-effort).
+    if let Some(db) = db {
+        db.log_webhook(
+            webhook_url,
+            &request_body,
+            response_status,
+            &response_body,
+            &error,
+            duration_ms,
+            sender,
+            subject,
+        );
+        if crate::webhook_queue::should_retry(response_status, &error) {
+            crate::webhook_queue::enqueue(db, webhook_url, &request_body, sender, subject, 1);
         }
     }
 }
@@ -774,7 +1920,79 @@ fn maildir_root() -> String {
     "/data/mail".to_string()
 }
 
+/// Apply each recipient's delivery rules to `message`: a folder rewrites the
+/// recipient to `user+Folder@domain`, and added headers move the recipient
+/// from `target_recipients` to a copy of its own in `rule_copies`.
+fn apply_delivery_rules(
+    account_rules: &[(String, Vec<crate::db::DeliveryRule>)],
+    message: &str,
+    mail_root: &str,
+    target_recipients: &mut Vec<String>,
+    tracked_copies: &mut [(String, String)],
+    rule_copies: &mut Vec<(String, String)>,
+) {
+    let parsed = match mailparse::parse_mail(message.as_bytes()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            warn!("[filter] cannot parse message for delivery rules: {}", e);
+            return;
+        }
+    };
+    let deliver_to = |rcpt: &str, folder: Option<&str>| match folder {
+        Some(folder) => match move_recipient_to_folder(rcpt, folder, mail_root) {
+            Some(address) => {
+                info!(
+                    "[filter] delivery rule — delivering {} to folder {} as {}",
+                    rcpt, folder, address
+                );
+                address
+            }
+            None => {
+                warn!(
+                    "[filter] delivery rule for {} names folder {}, but it could not be prepared; delivering to the Inbox",
+                    rcpt, folder
+                );
+                rcpt.to_string()
+            }
+        },
+        None => rcpt.to_string(),
+    };
+    let rules_for = |rcpt: &str| {
+        account_rules
+            .iter()
+            .find(|(r, _)| r == rcpt)
+            .map(|(_, rules)| crate::delivery_rules::evaluate(&parsed, rules))
+    };
+
+    let mut kept = Vec::new();
+    for rcpt in target_recipients.drain(..) {
+        match rules_for(&rcpt) {
+            Some(outcome) if !outcome.headers.is_empty() => {
+                let copy = inject_headers(message, &outcome.headers.join("\r\n"));
+                rule_copies.push((deliver_to(&rcpt, outcome.folder.as_deref()), copy));
+            }
+            Some(outcome) => kept.push(deliver_to(&rcpt, outcome.folder.as_deref())),
+            None => kept.push(rcpt),
+        }
+    }
+    *target_recipients = kept;
+    for (rcpt, copy) in tracked_copies.iter_mut() {
+        if let Some(outcome) = rules_for(rcpt) {
+            if !outcome.headers.is_empty() {
+                *copy = inject_headers(copy, &outcome.headers.join("\r\n"));
+            }
+            *rcpt = deliver_to(rcpt, outcome.folder.as_deref());
+        }
+    }
+}
+
 fn move_recipient_to_junk(recipient: &str, mail_root: &str) -> Option<String> {
+    move_recipient_to_folder(recipient, "Junk", mail_root)
+}
+
+/// Create the Maildir++ `folder` of `recipient` if needed and return the
+/// address that delivers into it (`user+folder@domain`).
+fn move_recipient_to_folder(recipient: &str, folder: &str, mail_root: &str) -> Option<String> {
     let mut parts = recipient.split('@');
     let local = parts.next()?.trim();
     let domain = parts.next()?.trim();
@@ -788,17 +2006,17 @@ fn move_recipient_to_junk(recipient: &str, mail_root: &str) -> Option<String> {
     let base_local = local.split('+').next().unwrap_or(local);
     let root = mail_root.trim_end_matches('/');
     let maildir_base = format!("{}/{}/{}/Maildir", root, domain, base_local);
-    let junk_root = format!("{}/.Junk", maildir_base);
+    let folder_root = format!("{}/.{}", maildir_base, folder);
 
     for dir in [
         maildir_base.as_str(),
         &format!("{}/new", maildir_base),
         &format!("{}/cur", maildir_base),
         &format!("{}/tmp", maildir_base),
-        junk_root.as_str(),
-        &format!("{}/new", junk_root),
-        &format!("{}/cur", junk_root),
-        &format!("{}/tmp", junk_root),
+        folder_root.as_str(),
+        &format!("{}/new", folder_root),
+        &format!("{}/cur", folder_root),
+        &format!("{}/tmp", folder_root),
     ] {
         if let Err(e) = fs::create_dir_all(dir) {
             warn!("[filter] failed to create maildir directory {}: {}", dir, e);
@@ -816,7 +2034,7 @@ fn move_recipient_to_junk(recipient: &str, mail_root: &str) -> Option<String> {
         return None;
     }
 
-    Some(format!("{}+Junk@{}", base_local, domain))
+    Some(format!("{}+{}@{}", base_local, folder, domain))
 }
 
 #[cfg(test)]
@@ -968,6 +2186,47 @@ mod tests {
         assert_eq!(result, "From: a@b.com\r\nTo: c@d.com\r\n\r\nHello.\r\n");
     }
 
+    #[test]
+    fn list_unsubscribe_headers_carry_the_token() {
+        let token = "3f2b8c1e-0d4a-4e6b-9a7c-5d1e2f3a4b5c";
+        let headers = list_unsubscribe_headers(
+            "https://mail.example.com/",
+            token,
+            Some("unsub@example.com"),
+        );
+        assert_eq!(
+            headers,
+            "List-Unsubscribe: <https://mail.example.com/unsubscribe?token=3f2b8c1e-0d4a-4e6b-9a7c-5d1e2f3a4b5c>, \
+             <mailto:unsub@example.com?subject=unsubscribe%203f2b8c1e-0d4a-4e6b-9a7c-5d1e2f3a4b5c>\r\n\
+             List-Unsubscribe-Post: List-Unsubscribe=One-Click"
+        );
+        assert_eq!(
+            list_unsubscribe_headers("https://mail.example.com", "abc", None),
+            "List-Unsubscribe: <https://mail.example.com/unsubscribe?token=abc>\r\n\
+             List-Unsubscribe-Post: List-Unsubscribe=One-Click"
+        );
+
+        // The subject a mail client fills in from the mailto link gives the
+        // token back.
+        use mailparse::MailHeaderMap;
+        let email = inject_headers("Subject: Offer\r\n\r\nBody\r\n", &headers);
+        let parsed = mailparse::parse_mail(email.as_bytes()).unwrap();
+        let value = parsed.headers.get_first_value("List-Unsubscribe").unwrap();
+        let subject = value
+            .split("subject=")
+            .nth(1)
+            .unwrap()
+            .trim_end_matches('>')
+            .replace("%20", " ");
+        assert_eq!(unsubscribe_token(&subject), Some(token));
+        assert_eq!(
+            unsubscribe_token(&format!("Re: UNSUBSCRIBE {}", token)),
+            Some(token)
+        );
+        assert_eq!(unsubscribe_token("unsubscribe be"), None);
+        assert_eq!(unsubscribe_token("hello"), None);
+    }
+
     #[test]
     fn inject_headers_inserts_before_body() {
         let email = concat!(
@@ -980,6 +2239,8 @@ mod tests {
         let result = inject_headers(email, headers);
         assert!(result.contains("List-Unsubscribe: <https://example.com/unsubscribe?token=abc>"));
         assert!(result.contains("List-Unsubscribe-Post: List-Unsubscribe=One-Click"));
+        assert!(result.contains("Subject: Test\r\nList-Unsubscribe: <"));
+        assert!(result.contains("One-Click\r\n\r\nBody text."));
         assert!(result.contains("From: sender@example.com"));
         assert!(result.contains("Subject: Test"));
         assert!(result.contains("Body text."));
@@ -1011,6 +2272,398 @@ mod tests {
         assert_eq!(result, email);
     }
 
+    #[test]
+    fn delivery_rules_rewrite_recipients_and_split_header_copies() {
+        let rule = |id: i64, pattern: &str, action: &str, arg: &str| crate::db::DeliveryRule {
+            id,
+            account_id: id,
+            position: 1,
+            match_field: "subject".to_string(),
+            match_op: "contains".to_string(),
+            pattern: pattern.to_string(),
+            action: action.to_string(),
+            target_folder: if action == "move" { arg.to_string() } else { String::new() },
+            header: if action == "add_header" { arg.to_string() } else { String::new() },
+            created_at: String::new(),
+        };
+        let account_rules = vec![
+            (
+                "alice@example.com".to_string(),
+                vec![rule(1, "report", "move", "Reports")],
+            ),
+            (
+                "bob@example.com".to_string(),
+                vec![
+                    rule(2, "weekly", "add_header", "X-Label: weekly"),
+                    rule(2, "report", "move", "Reports.Weekly"),
+                ],
+            ),
+        ];
+        let temp = std::env::temp_dir().join(format!("rules_test_{}", uuid::Uuid::new_v4()));
+        let root = temp.to_string_lossy().to_string();
+        let message = "From: a@example.net\r\nSubject: Weekly report\r\n\r\nBody.\r\n";
+        let mut targets = vec![
+            "alice@example.com".to_string(),
+            "bob@example.com".to_string(),
+            "carol@example.com".to_string(),
+        ];
+        let mut rule_copies = Vec::new();
+        apply_delivery_rules(&account_rules, message, &root, &mut targets, &mut [], &mut rule_copies);
+
+        assert_eq!(targets, vec!["alice+Reports@example.com", "carol@example.com"]);
+        assert_eq!(rule_copies.len(), 1);
+        assert_eq!(rule_copies[0].0, "bob+Reports.Weekly@example.com");
+        assert!(rule_copies[0].1.contains("Subject: Weekly report\r\nX-Label: weekly\r\n\r\n"));
+        assert!(temp.join("example.com/bob/Maildir/.Reports.Weekly/new").exists());
+
+        let _ = std::fs::remove_dir_all(&temp);
+    }
+
+    // ── reinject_deliveries tests ──
+
+    #[test]
+    fn a_failed_copy_does_not_stop_or_undo_the_others() {
+        let deliveries = vec![
+            ("shared".to_string(), vec!["a@example.com".to_string()]),
+            ("copy-b".to_string(), vec!["b@example.com".to_string()]),
+            ("copy-c".to_string(), vec!["c@example.com".to_string()]),
+        ];
+        let mut attempts = Vec::new();
+        let outcome = reinject_deliveries(deliveries, "original", |message, rcpts| {
+            attempts.push(format!("{} {}", message, rcpts[0]));
+            if rcpts[0].starts_with('b') {
+                Err(io::Error::other("refused"))
+            } else {
+                Ok(())
+            }
+        });
+        assert_eq!(
+            attempts,
+            [
+                "shared a@example.com",
+                "copy-b b@example.com",
+                "original b@example.com",
+                "copy-c c@example.com"
+            ]
+        );
+        assert_eq!(outcome.sent, 2);
+        assert!(!outcome.fell_back);
+        assert_eq!(
+            outcome.failed,
+            [("copy-b".to_string(), vec!["b@example.com".to_string()])]
+        );
+
+        let outcome = reinject_deliveries(
+            vec![("copy".to_string(), vec!["a@example.com".to_string()])],
+            "original",
+            |message, _| {
+                if message == "original" {
+                    Ok(())
+                } else {
+                    Err(io::Error::other("refused"))
+                }
+            },
+        );
+        assert_eq!((outcome.sent, outcome.fell_back), (1, true));
+
+        let outcome = reinject_deliveries(
+            vec![("copy".to_string(), vec!["a@example.com".to_string()])],
+            "original",
+            |_, _| Err(io::Error::other("refused")),
+        );
+        assert_eq!(outcome.sent, 0);
+        assert_eq!(outcome.failed.len(), 1);
+    }
+
+    // ── read_capped tests ──
+
+    #[test]
+    fn read_capped_reads_small_message_fully() {
+        let mut input: &[u8] = b"Subject: hi\r\n\r\nbody\r\n";
+        let (buf, truncated) = read_capped(&mut input, 1024).unwrap();
+        assert!(!truncated);
+        assert_eq!(buf, b"Subject: hi\r\n\r\nbody\r\n");
+    }
+
+    #[test]
+    fn read_capped_stops_buffering_past_limit() {
+        let data = vec![b'a'; 100];
+        let mut input: &[u8] = &data;
+        let (buf, truncated) = read_capped(&mut input, 10).unwrap();
+        assert!(truncated);
+        assert_eq!(buf.len(), 11);
+        // The rest stays unread so it can be streamed through.
+        assert_eq!(input.len(), 89);
+
+        let mut exact: &[u8] = &data[..10];
+        assert!(!read_capped(&mut exact, 10).unwrap().1);
+    }
+
+    #[test]
+    fn oversized_messages_are_rejected_when_they_cannot_be_scanned() {
+        use crate::attachment_policy::{AttachmentAction, AttachmentPolicy};
+        let off = AttachmentPolicy::default();
+        let hooks = FilterHooks::default();
+        assert_eq!(oversized_rejection(&off, &hooks, 1024), None);
+
+        let strip = AttachmentPolicy {
+            action: AttachmentAction::Strip,
+            ..AttachmentPolicy::default()
+        };
+        let reason = oversized_rejection(&strip, &hooks, 1024).unwrap();
+        assert!(reason.starts_with("5.3.4 "));
+        assert!(reason.contains("1024 bytes"));
+
+        let hooked = FilterHooks {
+            pre_reinject: Some("/usr/local/bin/scan".to_string()),
+            ..FilterHooks::default()
+        };
+        assert!(oversized_rejection(&off, &hooked, 1024).is_some());
+    }
+
+    // ── outbound size limit tests ──
+
+    #[test]
+    fn size_limit_zero_or_absent_is_unlimited() {
+        assert_eq!(parse_size_limit(None), None);
+        assert_eq!(parse_size_limit(Some("0")), None);
+        assert_eq!(parse_size_limit(Some("junk")), None);
+        assert_eq!(parse_size_limit(Some(" 1048576 ")), Some(1_048_576));
+    }
+
+    #[test]
+    fn measure_size_counts_past_the_buffering_cap() {
+        let data = vec![b'a'; 100];
+        let mut input: &[u8] = &data;
+        let (mut buf, truncated) = read_capped(&mut input, 10).unwrap();
+        assert!(truncated);
+
+        // Within the limit: the whole message ends up buffered.
+        let mut within: &[u8] = input;
+        let mut copy = buf.clone();
+        assert_eq!(measure_size(&mut copy, &mut within, 100).unwrap(), None);
+        assert_eq!(copy.len(), 100);
+
+        let size = measure_size(&mut buf, &mut input, 50).unwrap();
+        assert_eq!(size, Some(100));
+        assert!(buf.len() <= 51);
+        assert_eq!(
+            size_rejection(100, 50),
+            "5.3.4 Message size 100 bytes exceeds the outbound limit of 50 bytes"
+        );
+    }
+
+    // ── inject_pixel tests ──
+
+    const PIXEL: &str = r#"<img src="https://t.example.com/pixel?id=abc" width="1" height="1" style="display:none" alt="" />"#;
+
+    fn html_part_body(email: &str) -> String {
+        let parsed = mailparse::parse_mail(email.as_bytes()).unwrap();
+        find_html_part(&parsed).unwrap().get_body().unwrap()
+    }
+
+    #[test]
+    fn inject_pixel_into_base64_html_alternative() {
+        use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+        let html = "<html><body><p>Hello there</p></body></html>";
+        let email = format!(
+            concat!(
+                "From: a@example.com\r\n",
+                "Content-Type: multipart/alternative; boundary=\"ALT\"\r\n",
+                "\r\n",
+                "--ALT\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\n",
+                "\r\n",
+                "Hello there\r\n",
+                "--ALT\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+                "Content-Transfer-Encoding: base64\r\n",
+                "\r\n",
+                "{}\r\n",
+                "--ALT--\r\n"
+            ),
+            BASE64.encode(html)
+        );
+
+        let result = inject_pixel(&email, PIXEL, PixelPosition::Bottom).unwrap();
+        let parsed = mailparse::parse_mail(result.as_bytes()).unwrap();
+        assert_eq!(parsed.subparts.len(), 2);
+        assert_eq!(parsed.subparts[0].get_body().unwrap().trim(), "Hello there");
+        assert_eq!(
+            html_part_body(&result),
+            format!("<html><body><p>Hello there</p>{}</body></html>", PIXEL)
+        );
+        assert!(!result.contains("<img"), "pixel must be base64-encoded, not raw");
+        assert!(result.ends_with("--ALT--\r\n"));
+    }
+
+    #[test]
+    fn inject_pixel_into_quoted_printable_html_in_nested_multipart() {
+        let email = concat!(
+            "From: a@example.com\n",
+            "Content-Type: multipart/mixed; boundary=\"MIX\"\n",
+            "\n",
+            "--MIX\n",
+            "Content-Type: multipart/alternative; boundary=\"ALT\"\n",
+            "\n",
+            "--ALT\n",
+            "Content-Type: text/plain\n",
+            "\n",
+            "Caf=C3=A9 menu\n",
+            "--ALT\n",
+            "Content-Type: text/html; charset=utf-8\n",
+            "Content-Transfer-Encoding: quoted-printable\n",
+            "\n",
+            "<html><body style=3D\"margin:0\"><p>Caf=C3=A9 menu</p></body></html>\n",
+            "--ALT--\n",
+            "--MIX\n",
+            "Content-Type: application/pdf\n",
+            "Content-Disposition: attachment; filename=\"menu.pdf\"\n",
+            "\n",
+            "JVBERi0=\n",
+            "--MIX--\n"
+        );
+
+        let result = inject_pixel(email, PIXEL, PixelPosition::Bottom).unwrap();
+        let body = html_part_body(&result);
+        assert!(body.contains("<p>Café menu</p>"));
+        assert!(body.contains(&format!("{}</body>", PIXEL)));
+        assert!(!result.contains(PIXEL), "pixel must be quoted-printable encoded");
+        assert!(!result.contains("\r\n"), "line endings must match the message");
+        // The attachment and outer structure are untouched.
+        assert!(result.ends_with("--ALT--\n--MIX\nContent-Type: application/pdf\nContent-Disposition: attachment; filename=\"menu.pdf\"\n\nJVBERi0=\n--MIX--\n"));
+    }
+
+    #[test]
+    fn inject_pixel_leaves_multipart_without_html_unchanged() {
+        let email = concat!(
+            "Content-Type: multipart/mixed; boundary=\"B\"\r\n",
+            "\r\n",
+            "--B\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "<html> is mentioned but this is plain text\r\n",
+            "--B--\r\n"
+        );
+        assert_eq!(inject_pixel(email, PIXEL, PixelPosition::Bottom), None);
+    }
+
+    #[test]
+    fn inject_pixel_round_trips_single_part_quoted_printable_html() {
+        let long_line = "x".repeat(70);
+        let email = format!(
+            concat!(
+                "From: a@example.com\r\n",
+                "Content-Type: text/html; charset=utf-8\r\n",
+                "Content-Transfer-Encoding: quoted-printable\r\n",
+                "\r\n",
+                "<html><body><p class=3D\"intro\">{}=\r\n",
+                "{}</p>\r\n",
+                "<p>Gr=C3=BC=C3=9Fe</p></body></html>\r\n"
+            ),
+            long_line, long_line
+        );
+
+        let result = inject_pixel(&email, PIXEL, PixelPosition::Bottom).unwrap();
+        assert!(result.starts_with(
+            "From: a@example.com\r\nContent-Type: text/html; charset=utf-8\r\nContent-Transfer-Encoding: quoted-printable\r\n\r\n"
+        ));
+        assert!(result.lines().all(|l| l.len() <= 76), "QP lines must stay within 76 chars");
+        let body = mailparse::parse_mail(result.as_bytes()).unwrap().get_body().unwrap();
+        assert_eq!(
+            body,
+            format!(
+                "<html><body><p class=\"intro\">{}{}</p>\r\n<p>Grüße</p>{}</body></html>\r\n",
+                long_line, long_line, PIXEL
+            )
+        );
+    }
+
+    #[test]
+    fn inject_pixel_single_part_html_before_closing_body() {
+        let email = "Content-Type: text/html\r\n\r\n<html><body>Hi</body></html>\r\n";
+        assert_eq!(
+            inject_pixel(email, PIXEL, PixelPosition::Bottom).unwrap(),
+            format!("Content-Type: text/html\r\n\r\n<html><body>Hi{}</body></html>\r\n", PIXEL)
+        );
+    }
+
+    #[test]
+    fn pixel_position_top_follows_opening_body_tag() {
+        let html = "<html><head><body-style></body-style></head><body class=\"x\">\n<p>Hi</p></body></html>";
+        let top = String::from_utf8(insert_pixel_bytes(html.as_bytes(), PIXEL, PixelPosition::Top).unwrap()).unwrap();
+        assert_eq!(
+            top,
+            format!("<html><head><body-style></body-style></head><body class=\"x\">{}\n<p>Hi</p></body></html>", PIXEL)
+        );
+        let bottom = String::from_utf8(insert_pixel_bytes(html.as_bytes(), PIXEL, PixelPosition::Bottom).unwrap()).unwrap();
+        assert!(bottom.ends_with(&format!("<p>Hi</p>{}</body></html>", PIXEL)));
+
+        // Without a <body> tag, top placement falls back to appending.
+        let bare = "<html><p>Hi</p></html>";
+        let appended = insert_pixel_bytes(bare.as_bytes(), PIXEL, PixelPosition::Top).unwrap();
+        assert_eq!(String::from_utf8(appended).unwrap(), format!("{}{}", bare, PIXEL));
+        assert_eq!(PixelPosition::parse(" TOP "), Some(PixelPosition::Top));
+        assert_eq!(PixelPosition::parse("middle"), None);
+    }
+
+    #[test]
+    fn per_recipient_tracking_assigns_distinct_ids() {
+        let recipients = vec![
+            "alice@example.com".to_string(),
+            "bob@example.org".to_string(),
+            "carol@example.net".to_string(),
+        ];
+        let mut next = 0;
+        let (assigned, untracked) = assign_pixel_ids(
+            &recipients,
+            |rcpt| rcpt != "carol@example.net",
+            || {
+                next += 1;
+                format!("id-{}", next)
+            },
+        );
+        assert_eq!(
+            assigned,
+            vec![
+                ("alice@example.com".to_string(), "id-1".to_string()),
+                ("bob@example.org".to_string(), "id-2".to_string()),
+            ]
+        );
+        assert_eq!(untracked, vec!["carol@example.net".to_string()]);
+
+        let html = "Content-Type: text/html\r\n\r\n<html><body>Hi</body></html>";
+        let copy_for = |id: &str| {
+            inject_pixel(html, &pixel_tag("https://t/pixel?id=", id), PixelPosition::Bottom).unwrap()
+        };
+        let (alice, bob) = (copy_for("id-1"), copy_for("id-2"));
+        assert!(alice.contains("pixel?id=id-1") && !alice.contains("id-2"));
+        assert!(bob.contains("pixel?id=id-2") && !bob.contains("id-1"));
+    }
+
+    #[test]
+    fn strip_tracking_pixels_keeps_other_images() {
+        let base = "https://mail.example.com/pixel?id=";
+        let html = concat!(
+            r#"<p>Hi</p><IMG SRC="https://mail.example.com/pixel?id=abc" width="1" height="1" />"#,
+            r#"<img alt="logo" src='https://cdn.example.com/logo.png'>"#,
+            r#"<img src=https://old.example.net/pixel?id=def>"#,
+        );
+        assert_eq!(
+            strip_tracking_pixels(html, base),
+            r#"<p>Hi</p><img alt="logo" src='https://cdn.example.com/logo.png'>"#
+        );
+    }
+
+    #[test]
+    fn opt_out_header_overrides_tracking_rules() {
+        assert_eq!(tracking_decision(true, Some("1"), false), (false, "opt-out-header"));
+        assert_eq!(tracking_decision(true, Some(" Yes "), false), (false, "opt-out-header"));
+        assert_eq!(tracking_decision(true, Some("0"), false), (true, "tracking-rule"));
+        assert_eq!(tracking_decision(true, Some("false"), true), (false, "unsubscribe-list"));
+        assert_eq!(tracking_decision(false, None, false), (false, "no-tracking-rule"));
+    }
+
     #[test]
     fn extract_header_returns_correct_values() {
         let email = concat!(
@@ -1050,6 +2703,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn prepend_headers_matches_line_endings() {
+        let headers = vec![
+            "Received-SPF: Pass".to_string(),
+            "Authentication-Results: mx; dkim=none".to_string(),
+        ];
+        let mut lf = b"From: a@example.com\n\nbody\n".to_vec();
+        prepend_headers(&mut lf, &headers);
+        assert_eq!(
+            String::from_utf8(lf).unwrap(),
+            "Received-SPF: Pass\nAuthentication-Results: mx; dkim=none\nFrom: a@example.com\n\nbody\n"
+        );
+        let mut crlf = b"From: a@example.com\r\n\r\nbody\r\n".to_vec();
+        prepend_headers(&mut crlf, &headers[..1]);
+        assert!(crlf.starts_with(b"Received-SPF: Pass\r\nFrom:"));
+    }
+
     #[test]
     fn extract_sender_ip_returns_public_ipv4() {
         let email = concat!(
@@ -1168,6 +2838,7 @@ mod tests {
             message_id: String::new(),
             size_bytes: 0,
             direction: "outgoing".to_string(),
+            tracking_skipped: None,
         };
         assert_eq!(meta.direction, "outgoing");
     }
@@ -1185,6 +2856,7 @@ mod tests {
             message_id: String::new(),
             size_bytes: 0,
             direction: "incoming".to_string(),
+            tracking_skipped: None,
         };
         assert_eq!(meta.direction, "incoming");
     }
@@ -1222,6 +2894,7 @@ mod tests {
             } else {
                 "outgoing".to_string()
             },
+            tracking_skipped: None,
         };
 
         // Verify all fields are set as expected so send_webhook would receive complete data.
@@ -1256,6 +2929,7 @@ mod tests {
             } else {
                 "outgoing".to_string()
             },
+            tracking_skipped: None,
         };
         assert_eq!(meta.direction, "incoming");
         assert_eq!(meta.size_bytes, 256);
@@ -0,0 +1,350 @@
+//! External hook programs for the content filter.
+//!
+//! Operators can plug their own checks into the filter without forking it,
+//! in the spirit of Postfix content filters.  A hook is an executable run at
+//! one of three stages of [`crate::filter::run_filter`]:
+//!
+//! - `pre_modify`: the message as received, before tracking, footers and
+//!   headers are added;
+//! - `post_modify`: the message after the filter's own changes;
+//! - `pre_reinject`: each message just before it is handed back to Postfix.
+//!
+//! The message is written to the hook's stdin and the envelope is passed in
+//! `MAIL_HOOK_STAGE`, `MAIL_SENDER`, `MAIL_RECIPIENTS` (comma-separated) and
+//! `MAIL_DIRECTION` (`incoming` or `outgoing`).  Exit status 0 accepts the
+//! message; anything printed to stdout then replaces it.  Status 77
+//! (`EX_NOPERM`) rejects and 75 (`EX_TEMPFAIL`) defers, with the first line
+//! of stderr as the reason.  Any other status, a crash, or running past the
+//! timeout is a hook failure, handled per [`FAIL_MODE_SETTING`].
+
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+
+use crate::db::Database;
+
+pub const PRE_MODIFY_SETTING: &str = "filter_hook_pre_modify";
+pub const POST_MODIFY_SETTING: &str = "filter_hook_post_modify";
+pub const PRE_REINJECT_SETTING: &str = "filter_hook_pre_reinject";
+/// Seconds a hook may run before it is killed.
+pub const TIMEOUT_SETTING: &str = "filter_hook_timeout_secs";
+/// `open` (default) passes the message on when a hook fails; `closed`
+/// defers it so Postfix retries later.
+pub const FAIL_MODE_SETTING: &str = "filter_hook_fail_mode";
+
+pub const DEFAULT_TIMEOUT_SECS: u64 = 10;
+
+const EXIT_ACCEPT: i32 = 0;
+const EXIT_TEMPFAIL: i32 = 75;
+const EXIT_NOPERM: i32 = 77;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    PreModify,
+    PostModify,
+    PreReinject,
+}
+
+impl Stage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Stage::PreModify => "pre_modify",
+            Stage::PostModify => "post_modify",
+            Stage::PreReinject => "pre_reinject",
+        }
+    }
+}
+
+/// Envelope details passed to hooks.
+pub struct HookContext<'a> {
+    pub sender: &'a str,
+    pub recipients: &'a [String],
+    pub incoming: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum HookVerdict {
+    /// Carry on, with the hook's rewritten message if it printed one.
+    Accept(Option<String>),
+    Reject(String),
+    Defer(String),
+}
+
+/// The configured hooks.  Stages without a program are skipped.
+#[derive(Clone, Debug, Default)]
+pub struct FilterHooks {
+    pub pre_modify: Option<String>,
+    pub post_modify: Option<String>,
+    pub pre_reinject: Option<String>,
+    pub timeout: Duration,
+    pub fail_closed: bool,
+}
+
+impl FilterHooks {
+    pub fn load(db: &Database) -> Self {
+        let path = |key: &str| {
+            db.get_setting(key)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+        };
+        let timeout = db
+            .get_setting(TIMEOUT_SETTING)
+            .and_then(|v| v.trim().parse::<u64>().ok())
+            .filter(|&secs| secs > 0)
+            .unwrap_or(DEFAULT_TIMEOUT_SECS);
+        FilterHooks {
+            pre_modify: path(PRE_MODIFY_SETTING),
+            post_modify: path(POST_MODIFY_SETTING),
+            pre_reinject: path(PRE_REINJECT_SETTING),
+            timeout: Duration::from_secs(timeout),
+            fail_closed: db
+                .get_setting(FAIL_MODE_SETTING)
+                .map(|v| v.trim().eq_ignore_ascii_case("closed"))
+                .unwrap_or(false),
+        }
+    }
+
+    /// Whether a hook is set for any stage.
+    pub fn is_configured(&self) -> bool {
+        self.pre_modify.is_some() || self.post_modify.is_some() || self.pre_reinject.is_some()
+    }
+
+    fn program(&self, stage: Stage) -> Option<&str> {
+        match stage {
+            Stage::PreModify => self.pre_modify.as_deref(),
+            Stage::PostModify => self.post_modify.as_deref(),
+            Stage::PreReinject => self.pre_reinject.as_deref(),
+        }
+    }
+
+    /// Run the hook for `stage` on `message`, if one is configured.
+    pub fn run(&self, stage: Stage, message: &str, ctx: &HookContext) -> HookVerdict {
+        let Some(program) = self.program(stage) else {
+            return HookVerdict::Accept(None);
+        };
+        match execute(program, stage, message, ctx, self.timeout) {
+            Ok((EXIT_ACCEPT, stdout, _)) => {
+                debug!(
+                    "[filter] {} hook {} accepted the message",
+                    stage.as_str(),
+                    program
+                );
+                if stdout.is_empty() {
+                    return HookVerdict::Accept(None);
+                }
+                match String::from_utf8(stdout) {
+                    Ok(rewritten) => {
+                        info!(
+                            "[filter] {} hook {} rewrote the message",
+                            stage.as_str(),
+                            program
+                        );
+                        HookVerdict::Accept(Some(rewritten))
+                    }
+                    Err(_) => self.failed(stage, program, "rewritten message is not valid UTF-8"),
+                }
+            }
+            Ok((EXIT_NOPERM, _, stderr)) => {
+                HookVerdict::Reject(reason(&stderr, "rejected by policy hook"))
+            }
+            Ok((EXIT_TEMPFAIL, _, stderr)) => {
+                HookVerdict::Defer(reason(&stderr, "deferred by policy hook"))
+            }
+            Ok((code, _, stderr)) => self.failed(
+                stage,
+                program,
+                &format!("exited with status {}: {}", code, reason(&stderr, "")),
+            ),
+            Err(e) => self.failed(stage, program, &e),
+        }
+    }
+
+    fn failed(&self, stage: Stage, program: &str, error: &str) -> HookVerdict {
+        if self.fail_closed {
+            warn!(
+                "[filter] {} hook {} failed ({}); deferring message",
+                stage.as_str(),
+                program,
+                error
+            );
+            HookVerdict::Defer("policy hook unavailable".to_string())
+        } else {
+            warn!(
+                "[filter] {} hook {} failed ({}); passing message on",
+                stage.as_str(),
+                program,
+                error
+            );
+            HookVerdict::Accept(None)
+        }
+    }
+}
+
+/// First non-empty line of a hook's stderr, or `default`.
+fn reason(stderr: &[u8], default: &str) -> String {
+    String::from_utf8_lossy(stderr)
+        .lines()
+        .map(str::trim)
+        .find(|l| !l.is_empty())
+        .unwrap_or(default)
+        .to_string()
+}
+
+/// Run `program` with the message on stdin and return its exit status,
+/// stdout and stderr.  It is killed once `timeout` has passed.
+fn execute(
+    program: &str,
+    stage: Stage,
+    message: &str,
+    ctx: &HookContext,
+    timeout: Duration,
+) -> Result<(i32, Vec<u8>, Vec<u8>), String> {
+    let mut child = Command::new(program)
+        .env("MAIL_HOOK_STAGE", stage.as_str())
+        .env("MAIL_SENDER", ctx.sender)
+        .env("MAIL_RECIPIENTS", ctx.recipients.join(","))
+        .env(
+            "MAIL_DIRECTION",
+            if ctx.incoming { "incoming" } else { "outgoing" },
+        )
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to start: {}", e))?;
+
+    // Feed stdin and drain the outputs on their own threads so a hook that
+    // writes before reading all its input cannot deadlock the filter.
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = message.as_bytes().to_vec();
+    let writer = std::thread::spawn(move || {
+        // A hook may exit without reading its input; that is not an error.
+        let _ = stdin.write_all(&input);
+    });
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let out_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        stdout.read_to_end(&mut buf).map(|_| buf)
+    });
+    let mut stderr = child.stderr.take().expect("stderr is piped");
+    let err_reader = std::thread::spawn(move || {
+        let mut buf = Vec::new();
+        stderr.read_to_end(&mut buf).map(|_| buf)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() >= deadline => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("timed out after {}s", timeout.as_secs()));
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(10)),
+            Err(e) => return Err(format!("failed to wait: {}", e)),
+        }
+    };
+    let _ = writer.join();
+    let stdout = out_reader
+        .join()
+        .map_err(|_| "stdout reader panicked".to_string())?
+        .map_err(|e| format!("failed to read stdout: {}", e))?;
+    let stderr = err_reader
+        .join()
+        .map_err(|_| "stderr reader panicked".to_string())?
+        .unwrap_or_default();
+    match status.code() {
+        Some(code) => Ok((code, stdout, stderr)),
+        None => Err("killed by a signal".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FilterHooks, HookContext, HookVerdict, Stage};
+    use std::os::unix::fs::PermissionsExt;
+    use std::time::Duration;
+
+    fn script(body: &str) -> String {
+        let path = std::env::temp_dir().join(format!("filter_hook_{}.sh", uuid::Uuid::new_v4()));
+        std::fs::write(&path, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn hooks(program: &str, fail_closed: bool) -> FilterHooks {
+        FilterHooks {
+            pre_modify: Some(program.to_string()),
+            timeout: Duration::from_secs(5),
+            fail_closed,
+            ..FilterHooks::default()
+        }
+    }
+
+    const MESSAGE: &str = "From: a@example.com\r\nSubject: hi\r\n\r\nbody\r\n";
+
+    #[test]
+    fn hook_exiting_with_reject_code_rejects_the_message() {
+        let recipients = vec!["bob@remote.test".to_string()];
+        let ctx = HookContext {
+            sender: "a@example.com",
+            recipients: &recipients,
+            incoming: false,
+        };
+        let reject = script(
+            "cat >/dev/null\n[ \"$MAIL_SENDER\" = a@example.com ] || exit 1\necho \"blocked for $MAIL_RECIPIENTS\" >&2\nexit 77",
+        );
+        assert_eq!(
+            hooks(&reject, false).run(Stage::PreModify, MESSAGE, &ctx),
+            HookVerdict::Reject("blocked for bob@remote.test".to_string())
+        );
+        // Only the configured stage runs the hook.
+        assert_eq!(
+            hooks(&reject, false).run(Stage::PreReinject, MESSAGE, &ctx),
+            HookVerdict::Accept(None)
+        );
+
+        let rewrite = script("sed 's/^Subject: hi/Subject: [ext] hi/'");
+        assert_eq!(
+            hooks(&rewrite, false).run(Stage::PreModify, MESSAGE, &ctx),
+            HookVerdict::Accept(Some(MESSAGE.replace("Subject: hi", "Subject: [ext] hi")))
+        );
+
+        // A crashing hook passes the message on or defers it, per the fail mode.
+        let broken = script("exit 3");
+        assert_eq!(
+            hooks(&broken, false).run(Stage::PreModify, MESSAGE, &ctx),
+            HookVerdict::Accept(None)
+        );
+        assert!(matches!(
+            hooks(&broken, true).run(Stage::PreModify, MESSAGE, &ctx),
+            HookVerdict::Defer(_)
+        ));
+
+        for path in [reject, rewrite, broken] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn hook_running_past_the_timeout_is_killed() {
+        let slow = script("sleep 5");
+        let mut config = hooks(&slow, true);
+        config.timeout = Duration::from_millis(200);
+        let ctx = HookContext {
+            sender: "",
+            recipients: &[],
+            incoming: true,
+        };
+        let started = std::time::Instant::now();
+        assert!(matches!(
+            config.run(Stage::PreModify, MESSAGE, &ctx),
+            HookVerdict::Defer(_)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(3));
+        std::fs::remove_file(slow).unwrap();
+    }
+}
@@ -0,0 +1,352 @@
+//! Firewall enforcement for fail2ban bans.
+//!
+//! The fail2ban tables on their own only record bans.  When the
+//! `fail2ban_firewall` setting names a backend (`nft` or `ipset`), every new
+//! ban is added to a firewall set and every unban removes it again.  A
+//! background reconciler compares the sets with the active bans every
+//! minute, deleting expired bans and restoring entries lost to a manual
+//! flush.  The sets are never emptied to do so, and are left alone when the
+//! bans cannot be read.
+//!
+//! Commands are whitespace-separated templates run without a shell.  `{ip}`
+//! expands to the banned address or CIDR range and `{family}` to `ipv4` or
+//! `ipv6`, so the default sets are `mailserver_ipv4` and `mailserver_ipv6`.
+
+use std::collections::HashSet;
+use std::process::Command;
+use std::time::Duration;
+
+use ipnetwork::IpNetwork;
+use log::{debug, error, info, warn};
+
+use crate::db::Database;
+
+pub const BACKEND_SETTING: &str = "fail2ban_firewall";
+pub const ADD_CMD_SETTING: &str = "fail2ban_firewall_add_cmd";
+pub const DEL_CMD_SETTING: &str = "fail2ban_firewall_del_cmd";
+pub const FLUSH_CMD_SETTING: &str = "fail2ban_firewall_flush_cmd";
+pub const LIST_CMD_SETTING: &str = "fail2ban_firewall_list_cmd";
+
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+const FAMILIES: [&str; 2] = ["ipv4", "ipv6"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Nft,
+    Ipset,
+}
+
+impl Backend {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "nft" | "nftables" => Some(Backend::Nft),
+            "ipset" => Some(Backend::Ipset),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Backend::Nft => "nft",
+            Backend::Ipset => "ipset",
+        }
+    }
+
+    pub fn default_add_cmd(self) -> &'static str {
+        match self {
+            Backend::Nft => "nft add element inet filter mailserver_{family} { {ip} }",
+            Backend::Ipset => "ipset add mailserver_{family} {ip} -exist",
+        }
+    }
+
+    pub fn default_del_cmd(self) -> &'static str {
+        match self {
+            Backend::Nft => "nft delete element inet filter mailserver_{family} { {ip} }",
+            Backend::Ipset => "ipset del mailserver_{family} {ip} -exist",
+        }
+    }
+
+    pub fn default_flush_cmd(self) -> &'static str {
+        match self {
+            Backend::Nft => "nft flush set inet filter mailserver_{family}",
+            Backend::Ipset => "ipset flush mailserver_{family}",
+        }
+    }
+
+    pub fn default_list_cmd(self) -> &'static str {
+        match self {
+            Backend::Nft => "nft list set inet filter mailserver_{family}",
+            Backend::Ipset => "ipset list mailserver_{family}",
+        }
+    }
+}
+
+/// The enabled backend and its command templates.
+#[derive(Clone, Debug)]
+pub struct Firewall {
+    pub backend: Backend,
+    pub add_cmd: String,
+    pub del_cmd: String,
+    pub flush_cmd: String,
+    pub list_cmd: String,
+}
+
+impl Firewall {
+    /// The configured firewall, or `None` when enforcement is off.
+    pub fn load(db: &Database) -> Option<Self> {
+        let backend = Backend::parse(&db.get_setting(BACKEND_SETTING)?)?;
+        let template = |key: &str, default: &str| {
+            db.get_setting(key)
+                .map(|v| v.trim().to_string())
+                .filter(|v| !v.is_empty())
+                .unwrap_or_else(|| default.to_string())
+        };
+        Some(Firewall {
+            backend,
+            add_cmd: template(ADD_CMD_SETTING, backend.default_add_cmd()),
+            del_cmd: template(DEL_CMD_SETTING, backend.default_del_cmd()),
+            flush_cmd: template(FLUSH_CMD_SETTING, backend.default_flush_cmd()),
+            list_cmd: template(LIST_CMD_SETTING, backend.default_list_cmd()),
+        })
+    }
+
+    /// Empty the sets of both address families.
+    pub fn flush(&self) {
+        for family in FAMILIES {
+            run(&render_command(&self.flush_cmd, "", family));
+        }
+    }
+
+    /// Entries in the set of `family`, or `None` when it cannot be listed.
+    fn members(&self, family: &str) -> Option<HashSet<String>> {
+        output(&render_command(&self.list_cmd, "", family)).map(|out| parse_members(&out))
+    }
+}
+
+/// Addresses and CIDR ranges in the output of the list command, in their
+/// canonical form: everything after nft's `elements =` or ipset's
+/// `Members:` that parses as one.
+fn parse_members(listing: &str) -> HashSet<String> {
+    let start = ["elements", "Members:"]
+        .iter()
+        .filter_map(|marker| listing.find(marker))
+        .min();
+    let Some(start) = start else {
+        return HashSet::new();
+    };
+    listing[start..]
+        .split(|c: char| c.is_whitespace() || matches!(c, ',' | '{' | '}' | '='))
+        .filter_map(crate::fail2ban::normalize_ip_or_cidr)
+        .collect()
+}
+
+/// Address family placeholder value for a ban entry.
+fn family_of(entry: &str) -> Option<&'static str> {
+    match crate::fail2ban::parse_ip_or_cidr(entry)? {
+        IpNetwork::V4(_) => Some("ipv4"),
+        IpNetwork::V6(_) => Some("ipv6"),
+    }
+}
+
+/// Expand a command template into program and arguments.
+pub fn render_command(template: &str, ip: &str, family: &str) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|arg| arg.replace("{ip}", ip).replace("{family}", family))
+        .collect()
+}
+
+/// Run a rendered command and return its stdout, logging its stderr on
+/// failure.
+fn output(args: &[String]) -> Option<String> {
+    let Some((program, rest)) = args.split_first() else {
+        warn!("[firewall] empty command template, nothing to run");
+        return None;
+    };
+    debug!("[firewall] running {}", args.join(" "));
+    match Command::new(program).args(rest).output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        Ok(output) => {
+            error!(
+                "[firewall] `{}` exited with {}: {}",
+                args.join(" "),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            None
+        }
+        Err(e) => {
+            error!("[firewall] failed to run `{}`: {}", args.join(" "), e);
+            None
+        }
+    }
+}
+
+fn run(args: &[String]) -> bool {
+    output(args).is_some()
+}
+
+fn apply(template: &str, entry: &str) -> bool {
+    let ip = crate::fail2ban::normalize_ip_or_cidr(entry).unwrap_or_else(|| entry.to_string());
+    match family_of(&ip) {
+        Some(family) => run(&render_command(template, &ip, family)),
+        None => {
+            warn!("[firewall] ignoring invalid ban entry {}", entry);
+            false
+        }
+    }
+}
+
+/// Add a newly banned address to the firewall set.
+pub fn block(db: &Database, ip: &str) {
+    if let Some(fw) = Firewall::load(db) {
+        if apply(&fw.add_cmd, ip) {
+            info!("[firewall] blocked {} via {}", ip, fw.backend.as_str());
+        }
+    }
+}
+
+/// Remove an unbanned address from the firewall set.
+pub fn unblock(db: &Database, ip: &str) {
+    if let Some(fw) = Firewall::load(db) {
+        if apply(&fw.del_cmd, ip) {
+            info!("[firewall] unblocked {} via {}", ip, fw.backend.as_str());
+        }
+    }
+}
+
+/// Bring the firewall sets in line with the active bans: add the missing
+/// ones and delete entries that are no longer banned.  Nothing is deleted
+/// from a set that cannot be listed, and nothing at all changes when the
+/// bans cannot be read.
+pub fn reconcile(db: &Database) {
+    let Some(fw) = Firewall::load(db) else {
+        return;
+    };
+    let banned = match db.active_fail2ban_bans() {
+        Ok(banned) => banned,
+        Err(e) => {
+            warn!(
+                "[firewall] cannot read active bans, leaving sets as they are: {}",
+                e
+            );
+            return;
+        }
+    };
+    let wanted: HashSet<String> = banned
+        .iter()
+        .filter_map(|b| crate::fail2ban::normalize_ip_or_cidr(&b.ip_address))
+        .collect();
+    let (mut added, mut removed) = (0, 0);
+    for family in FAMILIES {
+        let wanted_here = wanted
+            .iter()
+            .filter(|entry| family_of(entry) == Some(family));
+        let Some(present) = fw.members(family) else {
+            // Without a listing, re-adding is all that is safe.
+            added += wanted_here
+                .filter(|entry| apply(&fw.add_cmd, entry))
+                .count();
+            continue;
+        };
+        added += wanted_here
+            .filter(|entry| !present.contains(*entry))
+            .filter(|entry| apply(&fw.add_cmd, entry))
+            .count();
+        removed += present
+            .iter()
+            .filter(|entry| family_of(entry) == Some(family) && !wanted.contains(*entry))
+            .filter(|entry| apply(&fw.del_cmd, entry))
+            .count();
+    }
+    debug!(
+        "[firewall] reconciled {} sets with {} bans: {} added, {} removed",
+        fw.backend.as_str(),
+        wanted.len(),
+        added,
+        removed
+    );
+}
+
+/// Start the background reconciler thread.
+pub fn start_reconciler(db: Database) {
+    info!(
+        "[firewall] starting ban reconciler (every {}s)",
+        RECONCILE_INTERVAL.as_secs()
+    );
+    crate::shutdown::spawn("ban reconciler", move || loop {
+        reconcile(&db);
+        if crate::shutdown::wait(RECONCILE_INTERVAL) {
+            break;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates_expand_ip_and_family() {
+        let nft = Backend::parse("nftables").unwrap();
+        assert_eq!(
+            render_command(nft.default_add_cmd(), "203.0.113.0/24", "ipv4"),
+            vec![
+                "nft",
+                "add",
+                "element",
+                "inet",
+                "filter",
+                "mailserver_ipv4",
+                "{",
+                "203.0.113.0/24",
+                "}"
+            ]
+        );
+        assert_eq!(
+            render_command(Backend::Ipset.default_flush_cmd(), "", "ipv6"),
+            vec!["ipset", "flush", "mailserver_ipv6"]
+        );
+        assert_eq!(family_of("2001:db8::1"), Some("ipv6"));
+        assert_eq!(family_of("10.0.0.1"), Some("ipv4"));
+        assert_eq!(family_of("not-an-ip"), None);
+        assert_eq!(Backend::parse("off"), None);
+    }
+
+    #[test]
+    fn set_listings_are_parsed() {
+        let nft = "table inet filter {
+	set mailserver_ipv6 {
+		type ipv6_addr
+		flags interval
+		elements = { 2001:db8::1,
+			     2001:db8:1::/48 }
+	}
+}
+";
+        let members = parse_members(nft);
+        assert_eq!(members.len(), 2);
+        assert!(members.contains("2001:db8::1"));
+        assert!(members.contains("2001:db8:1::/48"));
+
+        let ipset = "Name: mailserver_ipv4
+Type: hash:net
+Revision: 7
+Header: family inet hashsize 1024 maxelem 65536 bucketsize 12 initval 0x5c3e8a61
+Size in memory: 552
+References: 1
+Number of entries: 2
+Members:
+203.0.113.0/24
+192.0.2.7 timeout 120
+";
+        let members = parse_members(ipset);
+        assert_eq!(members.len(), 2);
+        assert!(members.contains("203.0.113.0/24"));
+        assert!(members.contains("192.0.2.7"));
+
+        assert!(parse_members("table inet filter {\n\tset mailserver_ipv4 {\n\t}\n}").is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+//! Optional GeoIP enrichment for tracking-pixel opens.
+//!
+//! When built with the `geoip` cargo feature and pointed at a MaxMind
+//! GeoLite2-City database, client IPs are resolved to a country code and city
+//! name.  Without the feature, or when the database is missing, lookups simply
+//! return no location and pixel recording continues unchanged.
+
+#[cfg(feature = "geoip")]
+use log::info;
+use log::warn;
+use std::net::IpAddr;
+
+/// Resolved location for a client IP.  Either field may be absent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GeoLocation {
+    pub country: Option<String>,
+    pub city: Option<String>,
+}
+
+pub struct GeoIp {
+    #[cfg(feature = "geoip")]
+    reader: maxminddb::Reader<Vec<u8>>,
+}
+
+impl GeoIp {
+    /// Load the database at `path`.  Returns `None` (and logs why) if the
+    /// file cannot be read or GeoIP support was not compiled in.
+    pub fn open(path: &str) -> Option<Self> {
+        #[cfg(feature = "geoip")]
+        {
+            match maxminddb::Reader::open_readfile(path) {
+                Ok(reader) => {
+                    info!("[geoip] loaded GeoIP database from {}", path);
+                    Some(GeoIp { reader })
+                }
+                Err(e) => {
+                    warn!("[geoip] failed to load GeoIP database {}: {}", path, e);
+                    None
+                }
+            }
+        }
+        #[cfg(not(feature = "geoip"))]
+        {
+            warn!(
+                "[geoip] GeoIP database {} ignored: built without the `geoip` feature",
+                path
+            );
+            None
+        }
+    }
+
+    /// Resolve `ip` to a location.  Private, loopback and unparseable
+    /// addresses, as well as addresses missing from the database, yield `None`.
+    pub fn lookup(&self, ip: &str) -> Option<GeoLocation> {
+        let addr = parse_public_ip(ip)?;
+        self.lookup_addr(addr)
+    }
+
+    #[cfg(feature = "geoip")]
+    fn lookup_addr(&self, addr: IpAddr) -> Option<GeoLocation> {
+        let city: maxminddb::geoip2::City = self.reader.lookup(addr).ok()?;
+        let location = GeoLocation {
+            country: city
+                .country
+                .and_then(|c| c.iso_code)
+                .map(|code| code.to_string()),
+            city: city
+                .city
+                .and_then(|c| c.names)
+                .and_then(|names| names.get("en").map(|n| n.to_string())),
+        };
+        if location == GeoLocation::default() {
+            None
+        } else {
+            Some(location)
+        }
+    }
+
+    #[cfg(not(feature = "geoip"))]
+    fn lookup_addr(&self, _addr: IpAddr) -> Option<GeoLocation> {
+        None
+    }
+}
+
+/// Parse `ip` and reject addresses that can never resolve to a location.
+pub(crate) fn parse_public_ip(ip: &str) -> Option<IpAddr> {
+    let addr: IpAddr = ip.trim().parse().ok()?;
+    let public = match addr {
+        IpAddr::V4(v4) => {
+            !(v4.is_private()
+                || v4.is_loopback()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast())
+        }
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            // fc00::/7 unique-local and fe80::/10 link-local
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    };
+    if public {
+        Some(addr)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_public_ip;
+
+    #[test]
+    fn private_and_loopback_addresses_are_skipped() {
+        assert!(parse_public_ip("10.1.2.3").is_none());
+        assert!(parse_public_ip("192.168.1.100").is_none());
+        assert!(parse_public_ip("127.0.0.1").is_none());
+        assert!(parse_public_ip("::1").is_none());
+        assert!(parse_public_ip("fd00::1").is_none());
+        assert!(parse_public_ip("fe80::1").is_none());
+    }
+
+    #[test]
+    fn public_addresses_are_accepted() {
+        assert!(parse_public_ip("8.8.8.8").is_some());
+        assert!(parse_public_ip("2001:4860:4860::8888").is_some());
+    }
+
+    #[test]
+    fn garbage_is_rejected() {
+        assert!(parse_public_ip("").is_none());
+        assert!(parse_public_ip("not-an-ip").is_none());
+        assert!(parse_public_ip("192.168.1.x").is_none());
+    }
+}
@@ -0,0 +1,152 @@
+//! Greylisting of inbound mail.
+//!
+//! With `greylist_enabled` on, the incoming content filter temporarily
+//! defers mail from a (client IP, envelope sender, recipient) triplet it has
+//! not seen before.  Once `greylist_delay_seconds` have passed, the next
+//! retry is accepted and the triplet is remembered, so later mail from the
+//! same sender and server is not delayed again.  Passed triplets expire after
+//! `greylist_lifetime_days` without mail; triplets that were never retried
+//! expire a day after the delay.
+//!
+//! Clients on the fail2ban whitelist are never greylisted.  Authenticated
+//! submission goes through the outgoing filter and is not affected.
+
+use std::net::IpAddr;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use log::{debug, info};
+
+use crate::db::Database;
+
+pub const ENABLED_SETTING: &str = "greylist_enabled";
+pub const DELAY_SETTING: &str = "greylist_delay_seconds";
+pub const DEFAULT_DELAY_SECS: i64 = 300;
+pub const MAX_DELAY_SECS: i64 = 86400;
+pub const LIFETIME_SETTING: &str = "greylist_lifetime_days";
+pub const DEFAULT_LIFETIME_DAYS: i64 = 36;
+pub const MAX_LIFETIME_DAYS: i64 = 365;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(3600);
+
+pub fn enabled(db: &Database) -> bool {
+    db.get_setting(ENABLED_SETTING).as_deref() == Some("true")
+}
+
+/// Seconds a new triplet is deferred for.
+pub fn delay_secs(db: &Database) -> i64 {
+    db.get_setting(DELAY_SETTING)
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|d| (0..=MAX_DELAY_SECS).contains(d))
+        .unwrap_or(DEFAULT_DELAY_SECS)
+}
+
+/// Days a passed triplet is remembered without new mail.
+pub fn lifetime_days(db: &Database) -> i64 {
+    db.get_setting(LIFETIME_SETTING)
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|d| (1..=MAX_LIFETIME_DAYS).contains(d))
+        .unwrap_or(DEFAULT_LIFETIME_DAYS)
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Status {
+    Passed,
+    /// Still inside the delay, with the seconds left.
+    Waiting(i64),
+}
+
+/// Status of a triplet first seen at `first_seen`.  An unreadable timestamp
+/// passes rather than deferring mail forever.
+pub fn status(first_seen: &str, passed: bool, now: NaiveDateTime, delay_secs: i64) -> Status {
+    if passed {
+        return Status::Passed;
+    }
+    let Ok(first) = NaiveDateTime::parse_from_str(first_seen, "%Y-%m-%d %H:%M:%S") else {
+        return Status::Passed;
+    };
+    let waited = (now - first).num_seconds();
+    if waited >= delay_secs {
+        Status::Passed
+    } else {
+        Status::Waiting(delay_secs - waited)
+    }
+}
+
+/// Record the attempt for every recipient and return the longest wait left
+/// when any triplet is still greylisted.  Database errors let mail through.
+pub fn check(db: &Database, client_ip: IpAddr, sender: &str, recipients: &[String]) -> Option<i64> {
+    if !enabled(db) {
+        return None;
+    }
+    let ip = client_ip.to_string();
+    if db.is_ip_whitelisted(&ip) {
+        debug!("[greylist] client {} is whitelisted", ip);
+        return None;
+    }
+    let delay = delay_secs(db);
+    let sender = sender.to_lowercase();
+    let now = Utc::now().naive_utc();
+    let mut wait = None;
+    for rcpt in recipients {
+        let rcpt = rcpt.to_lowercase();
+        let Some((first_seen, passed)) = db.greylist_touch(&ip, &sender, &rcpt) else {
+            continue;
+        };
+        match status(&first_seen, passed, now, delay) {
+            Status::Passed if !passed => {
+                debug!(
+                    "[greylist] client={} sender={} recipient={} passed",
+                    ip, sender, rcpt
+                );
+                db.greylist_pass(&ip, &sender, &rcpt);
+            }
+            Status::Passed => {}
+            Status::Waiting(secs) => wait = wait.max(Some(secs)),
+        }
+    }
+    wait
+}
+
+fn timestamp_before(age: chrono::Duration) -> String {
+    (Utc::now() - age).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Forget triplets that were never retried or have not been used for the
+/// configured lifetime.
+fn prune(db: &Database) {
+    let pending = chrono::Duration::seconds(delay_secs(db)) + chrono::Duration::days(1);
+    let passed = chrono::Duration::days(lifetime_days(db));
+    db.prune_greylist(&timestamp_before(pending), &timestamp_before(passed));
+}
+
+/// Start the background thread that expires stale greylist entries.
+pub fn start_cleanup(db: Database) {
+    info!(
+        "[greylist] starting greylist cleanup (every {}s)",
+        CLEANUP_INTERVAL.as_secs()
+    );
+    crate::shutdown::spawn("greylist cleanup", move || loop {
+        prune(&db);
+        if crate::shutdown::wait(CLEANUP_INTERVAL) {
+            break;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triplets_wait_for_the_delay_then_pass() {
+        let now = NaiveDateTime::parse_from_str("2026-01-02 12:00:00", "%Y-%m-%d %H:%M:%S");
+        let at = |first_seen, passed, delay| status(first_seen, passed, now.unwrap(), delay);
+        assert_eq!(at("2026-01-02 12:00:00", false, 300), Status::Waiting(300));
+        assert_eq!(at("2026-01-02 11:58:00", false, 300), Status::Waiting(180));
+        assert_eq!(at("2026-01-02 11:55:00", false, 300), Status::Passed);
+        assert_eq!(at("2026-01-02 12:00:00", true, 300), Status::Passed);
+        assert_eq!(at("2026-01-02 12:00:00", false, 0), Status::Passed);
+        assert_eq!(at("garbage", false, 300), Status::Passed);
+    }
+}
@@ -0,0 +1,132 @@
+//! Import accounts with pre-hashed passwords from another mail system.
+//!
+//! The input uses the Dovecot passwd-file layout, one account per line:
+//!
+//! ```text
+//! user@example.com:{SSHA512}base64...:::Jane Doe::
+//! ```
+//!
+//! Only the address, the password hash and the optional GECOS (display name)
+//! field are used.  Hashes are validated and normalized by
+//! [`crate::auth::normalize_imported_hash`], so users keep their existing
+//! passwords for IMAP/SMTP and the web services.
+
+use log::{info, warn};
+
+use crate::db::Database;
+
+#[derive(Debug, PartialEq)]
+pub struct ImportedAccount {
+    pub username: String,
+    pub domain: String,
+    pub password_hash: String,
+    pub name: String,
+}
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub created: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// Parse one passwd-file line.  Returns `Ok(None)` for blank lines and comments.
+pub fn parse_line(line: &str) -> Result<Option<ImportedAccount>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let mut fields = line.split(':');
+    let address = fields.next().unwrap_or("").trim();
+    let hash = fields.next().ok_or("missing password hash")?;
+    // uid, gid, then GECOS
+    let name = fields.nth(2).unwrap_or("").trim().to_string();
+
+    let (username, domain) = address
+        .split_once('@')
+        .filter(|(u, d)| !u.is_empty() && !d.is_empty() && !d.contains('@'))
+        .ok_or_else(|| format!("invalid address '{}'", address))?;
+    let password_hash = crate::auth::normalize_imported_hash(hash)?;
+    Ok(Some(ImportedAccount {
+        username: username.to_lowercase(),
+        domain: domain.to_lowercase(),
+        password_hash,
+        name,
+    }))
+}
+
+/// Create every valid account in `input` whose domain exists.  Existing
+/// accounts are left untouched.
+pub fn import_accounts(db: &Database, input: &str) -> ImportSummary {
+    let mut summary = ImportSummary::default();
+    for (lineno, line) in input.lines().enumerate() {
+        let account = match parse_line(line) {
+            Ok(Some(a)) => a,
+            Ok(None) => continue,
+            Err(e) => {
+                warn!("[import] line {}: {}", lineno + 1, e);
+                summary.failed += 1;
+                continue;
+            }
+        };
+        let email = format!("{}@{}", account.username, account.domain);
+        if db.get_account_by_email(&email).is_some() {
+            info!("[import] line {}: {} already exists, skipping", lineno + 1, email);
+            summary.skipped += 1;
+            continue;
+        }
+        let domain = match db.get_domain_by_name(&account.domain) {
+            Some(d) => d,
+            None => {
+                warn!(
+                    "[import] line {}: domain {} does not exist",
+                    lineno + 1,
+                    account.domain
+                );
+                summary.failed += 1;
+                continue;
+            }
+        };
+        match db.create_account(
+            domain.id,
+            &account.username,
+            &account.password_hash,
+            &account.name,
+            0,
+        ) {
+            Ok(id) => {
+                info!("[import] created {} (id={})", email, id);
+                summary.created += 1;
+            }
+            Err(e) => {
+                warn!("[import] line {}: failed to create {}: {}", lineno + 1, email, e);
+                summary.failed += 1;
+            }
+        }
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_dovecot_passwd_line() {
+        let line = "Jane@Example.com:{SHA512-CRYPT}$6$salt$abcdef:1000:1000:Jane Doe:/home/jane::";
+        let account = parse_line(line).unwrap().unwrap();
+        assert_eq!(account.username, "jane");
+        assert_eq!(account.domain, "example.com");
+        assert_eq!(account.password_hash, "{SHA512-CRYPT}$6$salt$abcdef");
+        assert_eq!(account.name, "Jane Doe");
+    }
+
+    #[test]
+    fn skips_comments_and_rejects_bad_lines() {
+        assert_eq!(parse_line("# exported 2024-01-01"), Ok(None));
+        assert_eq!(parse_line("   "), Ok(None));
+        assert!(parse_line("no-at-sign:{SSHA}abc").is_err());
+        assert!(parse_line("user@example.com").is_err());
+        assert!(parse_line("user@example.com:{PLAIN}secret").is_err());
+    }
+}
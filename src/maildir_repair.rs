@@ -0,0 +1,259 @@
+//! Normalize Maildir state after bulk moves, migrations or crashes.
+//!
+//! For the INBOX and every `.Folder` below it this:
+//!
+//! - creates missing `new/`, `cur/` and `tmp/` directories,
+//! - moves messages that carry an info suffix (`:2,FLAGS`) out of `new/`,
+//!   where only unseen, flag-less messages belong, into `cur/`,
+//! - rewrites malformed info suffixes in `cur/` to `:2,` followed by the
+//!   sorted, de-duplicated flag letters.
+//!
+//! Dovecot's own index can then be rebuilt with `doveadm force-resync`.
+
+use log::{info, warn};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::db::Database;
+
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    pub created_dirs: Vec<String>,
+    pub moved: Vec<String>,
+    pub renamed: Vec<String>,
+    pub skipped: Vec<String>,
+}
+
+impl RepairReport {
+    pub fn is_clean(&self) -> bool {
+        self.created_dirs.is_empty()
+            && self.moved.is_empty()
+            && self.renamed.is_empty()
+            && self.skipped.is_empty()
+    }
+}
+
+/// Canonical form of a Maildir filename: the unique base name, then
+/// `:2,` and the flags in ASCII order.  Returns `None` for names without an
+/// info suffix.
+pub fn normalize_filename(name: &str) -> Option<String> {
+    let (base, info) = name.split_once(':')?;
+    let flags = info.strip_prefix("2,").unwrap_or("");
+    let mut flags: Vec<char> = flags.chars().filter(|c| c.is_ascii_alphabetic()).collect();
+    flags.sort_unstable();
+    flags.dedup();
+    Some(format!(
+        "{}:2,{}",
+        base,
+        flags.into_iter().collect::<String>()
+    ))
+}
+
+fn ensure_subdirs(folder: &Path, report: &mut RepairReport) -> io::Result<()> {
+    for sub in ["new", "cur", "tmp"] {
+        let dir = folder.join(sub);
+        if !dir.is_dir() {
+            fs::create_dir_all(&dir)?;
+            report.created_dirs.push(dir.display().to_string());
+        }
+    }
+    Ok(())
+}
+
+fn rename_unless_exists(from: &Path, to: &Path, report: &mut RepairReport) -> io::Result<bool> {
+    if to.exists() {
+        report.skipped.push(format!(
+            "{} (target {} exists)",
+            from.display(),
+            to.display()
+        ));
+        return Ok(false);
+    }
+    fs::rename(from, to)?;
+    Ok(true)
+}
+
+fn repair_folder(folder: &Path, report: &mut RepairReport) -> io::Result<()> {
+    ensure_subdirs(folder, report)?;
+
+    for entry in fs::read_dir(folder.join("new"))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if let Some(normalized) = normalize_filename(&name) {
+            let target = folder.join("cur").join(&normalized);
+            if rename_unless_exists(&entry.path(), &target, report)? {
+                report.moved.push(format!(
+                    "{} -> {}",
+                    entry.path().display(),
+                    target.display()
+                ));
+            }
+        }
+    }
+
+    for entry in fs::read_dir(folder.join("cur"))? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let normalized = normalize_filename(&name).unwrap_or_else(|| format!("{}:2,", name));
+        if normalized != name {
+            let target = folder.join("cur").join(&normalized);
+            if rename_unless_exists(&entry.path(), &target, report)? {
+                report.renamed.push(format!("{} -> {}", name, normalized));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Repair the Maildir rooted at `root` and all of its `.Folder` subfolders.
+pub fn repair_maildir(root: &Path) -> io::Result<RepairReport> {
+    let mut report = RepairReport::default();
+    repair_folder(root, &mut report)?;
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() && crate::web::routes::webmail::is_safe_folder(&name) {
+            repair_folder(&entry.path(), &mut report)?;
+        }
+    }
+    Ok(report)
+}
+
+/// Ask Dovecot to rebuild its index for `email` after the files changed.
+fn dovecot_resync(email: &str) {
+    match std::process::Command::new("doveadm")
+        .args(["force-resync", "-u", email, "*"])
+        .status()
+    {
+        Ok(status) if status.success() => {
+            info!("[maildir-repair] {}: dovecot index rebuilt", email)
+        }
+        Ok(status) => warn!(
+            "[maildir-repair] {}: doveadm force-resync exited with {}",
+            email, status
+        ),
+        Err(e) => warn!("[maildir-repair] {}: failed to run doveadm: {}", email, e),
+    }
+}
+
+/// Repair one account (`user@domain`) or, with `None`, every account.
+/// Returns the number of accounts that could not be repaired.
+pub fn repair_accounts(db: &Database, email: Option<&str>, reindex: bool) -> usize {
+    let accounts: Vec<(String, String)> = match email {
+        Some(email) => match db.get_account_by_email(email) {
+            Some(acct) => vec![(
+                acct.username,
+                email
+                    .rsplit_once('@')
+                    .map(|(_, d)| d)
+                    .unwrap_or("")
+                    .to_lowercase(),
+            )],
+            None => {
+                warn!("[maildir-repair] account {} not found", email);
+                return 1;
+            }
+        },
+        None => db
+            .list_all_accounts_with_domain()
+            .into_iter()
+            .filter_map(|a| a.domain_name.map(|d| (a.username, d)))
+            .collect(),
+    };
+
+    let mut failed = 0;
+    for (username, domain) in accounts {
+        let email = format!("{}@{}", username, domain);
+        let root = crate::web::routes::webmail::maildir_path(&domain, &username);
+        if !Path::new(&root).is_dir() {
+            info!(
+                "[maildir-repair] {}: no Maildir at {}, skipping",
+                email, root
+            );
+            continue;
+        }
+        match repair_maildir(Path::new(&root)) {
+            Ok(report) => {
+                for dir in &report.created_dirs {
+                    info!("[maildir-repair] {}: created {}", email, dir);
+                }
+                for m in &report.moved {
+                    info!("[maildir-repair] {}: moved {}", email, m);
+                }
+                for r in &report.renamed {
+                    info!("[maildir-repair] {}: renamed {}", email, r);
+                }
+                for s in &report.skipped {
+                    warn!("[maildir-repair] {}: skipped {}", email, s);
+                }
+                if report.is_clean() {
+                    info!("[maildir-repair] {}: nothing to fix", email);
+                } else if reindex {
+                    dovecot_resync(&email);
+                }
+            }
+            Err(e) => {
+                warn!("[maildir-repair] {}: repair failed: {}", email, e);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_info_suffix() {
+        assert_eq!(normalize_filename("123.abc.host"), None);
+        assert_eq!(
+            normalize_filename("123.abc.host:2,SFS").as_deref(),
+            Some("123.abc.host:2,FS")
+        );
+        assert_eq!(
+            normalize_filename("123.abc.host:2,S!R").as_deref(),
+            Some("123.abc.host:2,RS")
+        );
+        assert_eq!(
+            normalize_filename("123.abc.host:1garbage").as_deref(),
+            Some("123.abc.host:2,")
+        );
+    }
+
+    #[test]
+    fn repairs_misplaced_and_malformed_files() {
+        let root = std::env::temp_dir().join(format!("maildir-repair-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(root.join("new")).unwrap();
+        fs::create_dir_all(root.join("cur")).unwrap();
+        fs::create_dir_all(root.join(".Sent/cur")).unwrap();
+        fs::write(root.join("new/1.a.host:2,S"), "seen but in new").unwrap();
+        fs::write(root.join("new/2.b.host"), "unseen").unwrap();
+        fs::write(root.join("cur/3.c.host:2,TS,S"), "malformed flags").unwrap();
+        fs::write(root.join(".Sent/cur/4.d.host"), "missing info").unwrap();
+
+        let report = repair_maildir(&root).unwrap();
+
+        assert!(root.join("tmp").is_dir());
+        assert!(root.join(".Sent/new").is_dir());
+        assert!(root.join(".Sent/tmp").is_dir());
+        assert!(root.join("cur/1.a.host:2,S").is_file());
+        assert!(!root.join("new/1.a.host:2,S").exists());
+        assert!(root.join("new/2.b.host").is_file());
+        assert!(root.join("cur/3.c.host:2,ST").is_file());
+        assert!(root.join(".Sent/cur/4.d.host:2,").is_file());
+        assert_eq!(report.moved.len(), 1);
+        assert_eq!(report.renamed.len(), 2);
+
+        assert!(repair_maildir(&root).unwrap().is_clean());
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
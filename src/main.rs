@@ -1,10 +1,41 @@
+mod attachment_policy;
 mod auth;
+mod backup;
+mod compliance_footer;
 mod config;
+mod config_diff;
+mod csv_transfer;
 mod db;
+mod decision_cache;
+mod delivery_policy;
+mod delivery_rules;
+mod dnscheck;
+mod dkim;
+mod dkim_rotation;
+mod dns_setup;
+mod dmarc_reporter;
 mod fail2ban;
 mod filter;
+mod filter_hooks;
+mod firewall;
+mod geoip;
+mod greylist;
+mod import;
+mod maildir_repair;
 mod provision;
+mod queue_flush;
+mod quiet_hours;
+mod relay_guard;
+mod relay_health;
+mod reserved_mailboxes;
+mod send_limits;
+mod shutdown;
+mod spf;
+#[cfg(test)]
+mod test_support;
+mod validation;
 mod web;
+mod webhook_queue;
 
 use log::{debug, error, info, warn};
 use std::env;
@@ -42,6 +73,19 @@ fn main() {
                 port, hostname, db_url
             );
 
+            let geoip_db = args
+                .iter()
+                .position(|a| a == "--geoip-db")
+                .and_then(|i| args.get(i + 1).cloned())
+                .or_else(|| env::var("GEOIP_DB").ok())
+                .filter(|p| !p.is_empty());
+            let open_webhook_url = args
+                .iter()
+                .position(|a| a == "--open-webhook-url")
+                .and_then(|i| args.get(i + 1).cloned())
+                .or_else(|| env::var("OPEN_WEBHOOK_URL").ok())
+                .filter(|u| !u.is_empty());
+
             let database = db::Database::open(&db_url);
 
             info!("[main] generating initial mail service configs");
@@ -57,12 +101,44 @@ fn main() {
                 idle_registry: std::sync::Arc::new(std::sync::Mutex::new(
                     std::collections::HashMap::new(),
                 )),
+                geoip: geoip_db
+                    .as_deref()
+                    .and_then(geoip::GeoIp::open)
+                    .map(std::sync::Arc::new),
+                open_webhook_url,
+                dns_check_cache: std::sync::Arc::new(std::sync::Mutex::new(
+                    dnscheck::CheckCache::new(dnscheck::CACHE_TTL),
+                )),
             };
 
             // Start fail2ban log watcher in a background thread
             info!("[main] starting fail2ban log watcher");
             fail2ban::start_watcher(database.clone());
 
+            // Keep firewall ban sets in sync with the fail2ban tables
+            firewall::start_reconciler(database.clone());
+
+            // Send marketing mail held back during per-domain quiet hours
+            quiet_hours::start_scheduler(database.clone());
+
+            // Send daily DMARC aggregate reports for opted-in domains
+            dmarc_reporter::start_scheduler(database.clone());
+
+            // Check that outbound relays are reachable
+            relay_health::start_prober(database.clone());
+
+            // Deliver queued webhooks and retry failed ones
+            webhook_queue::start_worker(database.clone());
+
+            // Retire previous DKIM selectors once their grace period ends
+            dkim_rotation::start_cleanup(database.clone());
+
+            // Flush the mail queue on the configured schedule
+            queue_flush::start_scheduler(database.clone());
+
+            // Expire stale greylist entries
+            greylist::start_cleanup(database.clone());
+
             // Start Tokio runtime only for the HTTP server
             let rt = tokio::runtime::Builder::new_multi_thread()
                 .enable_all()
@@ -71,6 +147,12 @@ fn main() {
             rt.block_on(async move {
                 web::start_server(state).await;
             });
+
+            let running = shutdown::join(shutdown::DRAIN_TIMEOUT);
+            if !running.is_empty() {
+                warn!("[main] exiting while still running: {}", running.join(", "));
+            }
+            info!("[main] shutdown complete");
         }
         "filter" => {
             let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
@@ -103,10 +185,42 @@ fn main() {
             let mut recipients = Vec::new();
             let mut after_separator = false;
             let mut incoming = false;
+            let mut max_body_bytes = env::var("MAX_BODY_BYTES")
+                .ok()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or(filter::DEFAULT_MAX_BODY_BYTES);
+            let mut opt_out_header = env::var("OPT_OUT_HEADER")
+                .unwrap_or_else(|_| filter::DEFAULT_OPT_OUT_HEADER.to_string());
+            let mut client_address = String::new();
+            let mut helo = String::new();
+            let mut sasl_username = String::new();
             let mut i = 2;
             while i < args.len() {
                 if args[i] == "--incoming" {
                     incoming = true;
+                } else if let Some(v) = args[i]
+                    .strip_prefix("--client-address=")
+                    .filter(|_| !after_separator)
+                {
+                    client_address = v.to_string();
+                } else if let Some(v) = args[i].strip_prefix("--helo=").filter(|_| !after_separator) {
+                    helo = v.to_string();
+                } else if let Some(v) = args[i]
+                    .strip_prefix("--sasl-username=")
+                    .filter(|_| !after_separator)
+                {
+                    sasl_username = v.to_string();
+                } else if args[i] == "--max-body-bytes" && !after_separator {
+                    i += 1;
+                    match args.get(i).and_then(|v| v.parse::<usize>().ok()) {
+                        Some(n) => max_body_bytes = n,
+                        None => warn!("[filter] invalid --max-body-bytes value, using {}", max_body_bytes),
+                    }
+                } else if args[i] == "--opt-out-header" && !after_separator {
+                    i += 1;
+                    if let Some(name) = args.get(i) {
+                        opt_out_header = name.trim().to_string();
+                    }
                 } else if args[i] == "-f" {
                     i += 1;
                     if i < args.len() {
@@ -149,7 +263,14 @@ fn main() {
                 &recipients,
                 &pixel_base_url,
                 &unsubscribe_base_url,
-                incoming,
+                &filter::FilterOptions {
+                    incoming,
+                    max_body_bytes,
+                    opt_out_header,
+                    client_address,
+                    helo,
+                    sasl_username,
+                },
             );
             info!("[filter] content filter completed");
         }
@@ -206,6 +327,215 @@ fn main() {
             database.update_admin_password(admin.id, &hash);
             info!("[reset-password] password updated for admin user: {}", username);
         }
+        "import-accounts" => {
+            let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
+                error!("[import] DATABASE_URL not set; ensure it is provided via environment");
+                std::process::exit(1);
+            });
+            let path = args.get(2).cloned().unwrap_or_else(|| {
+                error!("[import] usage: mailserver import-accounts <passwd-file | ->");
+                std::process::exit(1);
+            });
+            let input = if path == "-" {
+                let mut buf = String::new();
+                std::io::Read::read_to_string(&mut std::io::stdin(), &mut buf).map(|_| buf)
+            } else {
+                std::fs::read_to_string(&path)
+            }
+            .unwrap_or_else(|e| {
+                error!("[import] failed to read {}: {}", path, e);
+                std::process::exit(1);
+            });
+
+            let database = db::Database::open(&db_url);
+            let summary = import::import_accounts(&database.without_statement_timeout(), &input);
+            info!(
+                "[import] done: {} created, {} skipped, {} failed",
+                summary.created, summary.skipped, summary.failed
+            );
+            if summary.created > 0 {
+                let hostname = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+                config::generate_all_configs(&database, &hostname);
+            }
+            if summary.failed > 0 {
+                std::process::exit(1);
+            }
+        }
+        "maildir-repair" => {
+            let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
+                error!("[maildir-repair] DATABASE_URL not set; ensure it is provided via environment");
+                std::process::exit(1);
+            });
+            let reindex = args.iter().any(|a| a == "--reindex");
+            let target = args
+                .get(2)
+                .filter(|a| !a.starts_with("--") || a.as_str() == "--all")
+                .cloned()
+                .unwrap_or_else(|| {
+                    error!("[maildir-repair] usage: mailserver maildir-repair <user@domain | --all> [--reindex]");
+                    std::process::exit(1);
+                });
+            let email = Some(target.as_str()).filter(|t| *t != "--all");
+
+            let database = db::Database::open(&db_url);
+            let failed = maildir_repair::repair_accounts(&database, email, reindex);
+            if failed > 0 {
+                error!("[maildir-repair] {} account(s) could not be repaired", failed);
+                std::process::exit(1);
+            }
+        }
+        "replicate" => {
+            let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
+                error!("[replicate] DATABASE_URL not set; ensure it is provided via environment");
+                std::process::exit(1);
+            });
+            let primary_url = args
+                .get(2)
+                .filter(|a| !a.starts_with("--"))
+                .cloned()
+                .or_else(|| env::var("PRIMARY_DATABASE_URL").ok())
+                .filter(|u| !u.is_empty())
+                .unwrap_or_else(|| {
+                    error!("[replicate] usage: mailserver replicate <primary-database-url> [--interval <secs>]");
+                    std::process::exit(1);
+                });
+            let interval = args
+                .iter()
+                .position(|a| a == "--interval")
+                .and_then(|i| args.get(i + 1))
+                .map(|v| {
+                    v.parse::<u64>().ok().filter(|&s| s > 0).unwrap_or_else(|| {
+                        error!("[replicate] --interval needs a number of seconds, got {}", v);
+                        std::process::exit(1);
+                    })
+                });
+            let hostname = env::var("HOSTNAME").unwrap_or_else(|_| {
+                warn!("[replicate] HOSTNAME not set, defaulting to localhost");
+                "localhost".to_string()
+            });
+
+            let database = db::Database::open(&db_url);
+            loop {
+                match database.replicate_from(&primary_url) {
+                    Ok(reports) => {
+                        let mut changed = false;
+                        for r in &reports {
+                            info!(
+                                "[replicate] {}: {} changed, {} deleted",
+                                r.table, r.changed, r.deleted
+                            );
+                            changed |= r.changed + r.deleted > 0;
+                        }
+                        if changed {
+                            config::generate_all_configs(&database, &hostname);
+                        }
+                    }
+                    Err(e) => {
+                        error!("[replicate] replication failed: {}", e);
+                        if interval.is_none() {
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                let Some(secs) = interval else { break };
+                std::thread::sleep(std::time::Duration::from_secs(secs));
+            }
+        }
+        "backup" => {
+            let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
+                error!("[backup] DATABASE_URL not set; ensure it is provided via environment");
+                std::process::exit(1);
+            });
+            let database = db::Database::open(&db_url);
+            let json = backup::export(&database).unwrap_or_else(|e| {
+                error!("[backup] failed to read the configuration: {}", e);
+                std::process::exit(1);
+            });
+            match args.get(2).filter(|p| p.as_str() != "-") {
+                None => println!("{}", json),
+                Some(path) => {
+                    use std::io::Write;
+                    use std::os::unix::fs::OpenOptionsExt;
+
+                    // The backup holds private keys, so only the owner may read it.
+                    let written = std::fs::OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .mode(0o600)
+                        .open(path)
+                        .and_then(|mut f| f.write_all(json.as_bytes()));
+                    if let Err(e) = written {
+                        error!("[backup] failed to write {}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                    info!("[backup] wrote {} ({} bytes); it contains DKIM private keys and password hashes", path, json.len());
+                }
+            }
+        }
+        "restore" => {
+            let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
+                error!("[restore] DATABASE_URL not set; ensure it is provided via environment");
+                std::process::exit(1);
+            });
+            let Some(path) = args.get(2) else {
+                error!("[restore] usage: mailserver restore <backup.json | ->");
+                std::process::exit(1);
+            };
+            let input = if path == "-" {
+                std::io::read_to_string(std::io::stdin())
+            } else {
+                std::fs::read_to_string(path)
+            };
+            let input = input.unwrap_or_else(|e| {
+                error!("[restore] failed to read {}: {}", path, e);
+                std::process::exit(1);
+            });
+            let hostname = env::var("HOSTNAME").unwrap_or_else(|_| {
+                warn!("[restore] HOSTNAME not set, defaulting to localhost");
+                "localhost".to_string()
+            });
+
+            let database = db::Database::open(&db_url);
+            match backup::restore(&database, &input) {
+                Ok(tables) => {
+                    for t in &tables {
+                        info!("[restore] {}: {} rows changed", t.table, t.changed);
+                    }
+                    if tables.iter().any(|t| t.changed > 0) {
+                        config::generate_all_configs(&database, &hostname);
+                    }
+                }
+                Err(e) => {
+                    error!("[restore] nothing was restored: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        "dump-templates" | "--dump-templates" => {
+            let Some(dir) = args.get(2) else {
+                error!("[dump-templates] usage: mailserver dump-templates <dir>");
+                std::process::exit(1);
+            };
+            match config::dump_templates(std::path::Path::new(dir)) {
+                Ok(results) => {
+                    let written = results.iter().filter(|(_, written)| *written).count();
+                    info!(
+                        "[dump-templates] wrote {} of {} templates to {}",
+                        written,
+                        results.len(),
+                        dir
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "[dump-templates] failed to write templates to {}: {}",
+                        dir, e
+                    );
+                    std::process::exit(1);
+                }
+            }
+        }
         "genconfig" => {
             let db_url = env::var("DATABASE_URL").unwrap_or_else(|_| {
                 error!("[genconfig] DATABASE_URL not set; ensure it is provided via environment");
@@ -216,10 +546,30 @@ fn main() {
                 "localhost".to_string()
             });
 
-            info!("[genconfig] generating configs for hostname={}", hostname);
             let database = db::Database::open(&db_url);
-            config::generate_all_configs(&database, &hostname);
-            info!("[genconfig] configuration files generated successfully");
+            if args.iter().skip(2).any(|a| a == "--dry-run") {
+                // Print what would change and exit 1 if anything would, so the
+                // check can gate a deployment; nothing is written.
+                let changes = config::preview_all_configs(&database, &hostname);
+                for change in &changes {
+                    print!("{}", change.diff);
+                }
+                if changes.is_empty() {
+                    info!("[genconfig] dry run: configuration files are up to date");
+                } else {
+                    let paths: Vec<&str> = changes.iter().map(|c| c.path.as_str()).collect();
+                    info!(
+                        "[genconfig] dry run: {} configuration files would change: {}",
+                        changes.len(),
+                        paths.join(", ")
+                    );
+                    std::process::exit(1);
+                }
+            } else {
+                info!("[genconfig] generating configs for hostname={}", hostname);
+                config::generate_all_configs(&database, &hostname);
+                info!("[genconfig] configuration files generated successfully");
+            }
         }
         "gencerts" => {
             let hostname = env::var("HOSTNAME").unwrap_or_else(|_| {
@@ -268,10 +618,23 @@ fn main() {
             println!();
             println!("Usage:");
             println!("  mailserver serve      Start admin dashboard and pixel server");
+            println!("                          [--geoip-db <path>] GeoLite2-City database for pixel opens");
+            println!("                          [--open-webhook-url <url>] notify on first open of a tracked message");
             println!("  mailserver filter     Run as Postfix content filter");
+            println!("                          [--max-body-bytes <n>] pass larger messages through unfiltered");
+            println!("                          [--opt-out-header <name>] header that disables tracking (default: X-No-Track)");
             println!("  mailserver seed           Seed default admin user");
             println!("  mailserver reset-password Reset an admin user's password");
+            println!("  mailserver import-accounts <file>  Import accounts with existing password hashes");
+            println!("  mailserver maildir-repair <user@domain | --all>  Normalize Maildir new/cur/tmp and flags");
+            println!("                          [--reindex] rebuild Dovecot indexes for repaired accounts");
             println!("  mailserver genconfig      Generate mail service configs");
+            println!("                          [--dry-run] print a diff of what would change, exit 1 if anything would");
+            println!("  mailserver replicate <primary-url>  Mirror domains, accounts, aliases, relays and settings from a primary");
+            println!("                          [--interval <secs>] keep running, replicating every <secs> seconds");
+            println!("  mailserver backup [<file>]  Write all configuration, including DKIM keys, as JSON (stdout by default)");
+            println!("  mailserver restore <file>   Upsert the configuration in a backup file (- reads stdin)");
+            println!("  mailserver dump-templates <dir>  Write the built-in config templates for customizing");
             println!("  mailserver gencerts   Generate TLS certificates and DH parameters");
             println!("  mailserver provision  Auto-provision a remote server via SSH");
             println!();
@@ -279,7 +642,13 @@ fn main() {
             println!("  ADMIN_PORT       Dashboard port (default: 8080)");
             println!("  HOSTNAME         Mail server hostname (default: localhost)");
             println!("  DATABASE_URL    PostgreSQL connection string (required)");
+            println!("  DB_POOL_SIZE     Maximum pooled database connections (default: 8)");
+            println!("  PRIMARY_DATABASE_URL Primary to mirror when `replicate` gets no URL");
             println!("  PIXEL_BASE_URL   Base URL for tracking pixels");
+            println!("  GEOIP_DB         GeoLite2-City .mmdb path (needs the `geoip` feature)");
+            println!("  OPEN_WEBHOOK_URL URL to POST when a tracked message is first opened");
+            println!("  MAX_BODY_BYTES   Largest message the filter buffers (default: 26214400)");
+            println!("  OPT_OUT_HEADER   Header whose truthy value disables tracking (default: X-No-Track)");
             println!("  SEED_USER        Default admin username (default: admin)");
             println!("  SEED_PASS        Default admin password (default: admin)");
             println!("  RESET_USER       Admin username to reset (default: admin)");
@@ -0,0 +1,151 @@
+//! Scheduled flushing of the Postfix queue.
+//!
+//! When the `queue_auto_flush_minutes` setting is above zero, a background
+//! thread runs `postqueue -f` that often so deferred mail is retried without
+//! an admin pressing Flush.  A flush is skipped when nothing is deferred and
+//! when the active queue already holds [`BUSY_ACTIVE_MESSAGES`] messages,
+//! since forcing every deferred message back in would only pile retries onto
+//! a queue manager that is still working through a backlog.  Flushes from
+//! the admin pages count towards the interval, and the two never overlap,
+//! see [`crate::web::routes::queue::flush_queue`].
+
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, log, warn};
+
+use crate::db::Database;
+use crate::web::routes::queue::{self, QueueMetrics};
+
+pub const INTERVAL_SETTING: &str = "queue_auto_flush_minutes";
+/// One week.
+pub const MAX_INTERVAL_MINUTES: i64 = 10080;
+/// Active queue size at which an automatic flush is skipped.
+pub const BUSY_ACTIVE_MESSAGES: u64 = 200;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+/// Wait before measuring the queue again after a flush.
+const SETTLE_TIME: Duration = Duration::from_secs(30);
+
+/// Minutes between automatic flushes; 0 when they are disabled.
+pub fn interval_minutes(db: &Database) -> i64 {
+    db.get_setting(INTERVAL_SETTING)
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|m| (0..=MAX_INTERVAL_MINUTES).contains(m))
+        .unwrap_or(0)
+}
+
+/// Why a scheduled flush should not run with the queue in this state.
+fn skip_reason(metrics: &QueueMetrics) -> Option<&'static str> {
+    if metrics.deferred == 0 {
+        Some("nothing is deferred")
+    } else if metrics.active >= BUSY_ACTIVE_MESSAGES {
+        Some("the active queue is busy")
+    } else {
+        None
+    }
+}
+
+fn depth(metrics: &QueueMetrics) -> String {
+    format!(
+        "{} message(s), {} active, {} deferred",
+        metrics.total, metrics.active, metrics.deferred
+    )
+}
+
+/// Flush the queue unless its state says not to, logging the depth before
+/// and after.
+fn auto_flush(interval: Duration) {
+    let before = match queue::read_queue_metrics() {
+        Ok(m) => m,
+        Err(e) => {
+            warn!("[queue-flush] cannot read the queue, skipping: {}", e);
+            return;
+        }
+    };
+    if let Some(reason) = skip_reason(&before) {
+        // An idle queue is the common case and not worth a line every interval.
+        let level = if before.deferred == 0 {
+            log::Level::Debug
+        } else {
+            log::Level::Info
+        };
+        log!(
+            level,
+            "[queue-flush] skipping automatic flush, {} ({})",
+            reason,
+            depth(&before)
+        );
+        return;
+    }
+    match queue::flush_queue(interval) {
+        Ok(true) => {}
+        Ok(false) => {
+            debug!("[queue-flush] queue was flushed by an admin within the interval");
+            return;
+        }
+        Err(e) => {
+            error!("[queue-flush] automatic flush failed: {}", e);
+            return;
+        }
+    }
+    std::thread::sleep(SETTLE_TIME);
+    let after = match queue::read_queue_metrics() {
+        Ok(m) => depth(&m),
+        Err(e) => format!("unknown: {}", e),
+    };
+    info!(
+        "[queue-flush] flushed the queue: before {}; {}s later {}",
+        depth(&before),
+        SETTLE_TIME.as_secs(),
+        after
+    );
+}
+
+/// Start the background thread that flushes the queue on schedule.  The
+/// setting is read every minute, so changes apply without a restart.
+pub fn start_scheduler(db: Database) {
+    info!(
+        "[queue-flush] starting scheduler (checks every {}s)",
+        CHECK_INTERVAL.as_secs()
+    );
+    crate::shutdown::spawn("queue flush scheduler", move || {
+        let mut last_run = Instant::now();
+        loop {
+            if crate::shutdown::wait(CHECK_INTERVAL) {
+                break;
+            }
+            let minutes = interval_minutes(&db);
+            if minutes == 0 {
+                continue;
+            }
+            let interval = Duration::from_secs(minutes as u64 * 60);
+            let since = queue::last_flush().map_or(last_run, |t| t.max(last_run));
+            if since.elapsed() < interval {
+                continue;
+            }
+            last_run = Instant::now();
+            auto_flush(interval);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flushes_are_skipped_when_idle_or_busy() {
+        let metrics = |active, deferred| QueueMetrics {
+            active,
+            deferred,
+            total: active + deferred,
+            ..Default::default()
+        };
+        assert_eq!(skip_reason(&metrics(0, 0)), Some("nothing is deferred"));
+        assert_eq!(skip_reason(&metrics(3, 12)), None);
+        assert_eq!(
+            skip_reason(&metrics(BUSY_ACTIVE_MESSAGES, 12)),
+            Some("the active queue is busy")
+        );
+    }
+}
@@ -0,0 +1,214 @@
+//! Per-domain quiet hours for marketing mail.
+//!
+//! A domain may define a daily local-time window (in its own IANA timezone)
+//! during which marketing mail is not sent.  The content filter holds such
+//! mail in `scheduled_messages` with a `send_at` at the end of the window, and
+//! the scheduler thread started by `serve` reinjects it once it is due.
+//! Transactional mail is never held.
+
+use chrono::{DateTime, Duration, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use log::{debug, error, info};
+
+use crate::db::Database;
+
+/// How often the scheduler checks for due messages.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// A daily quiet window, e.g. 21:00–08:00 in Europe/Berlin.
+#[derive(Clone, Debug, PartialEq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+    tz: Tz,
+}
+
+impl QuietHours {
+    /// Parse `"HH:MM"` start/end times and an IANA timezone name.  Returns
+    /// `None` when any part is invalid or the window is empty.
+    pub fn parse(start: &str, end: &str, timezone: &str) -> Option<Self> {
+        let start = NaiveTime::parse_from_str(start.trim(), "%H:%M").ok()?;
+        let end = NaiveTime::parse_from_str(end.trim(), "%H:%M").ok()?;
+        let tz: Tz = timezone.trim().parse().ok()?;
+        if start == end {
+            return None;
+        }
+        Some(QuietHours { start, end, tz })
+    }
+
+    /// When `now` falls inside the quiet window, the UTC instant at which the
+    /// window closes; otherwise `None`.  Windows may wrap past midnight.
+    pub fn release_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = now.with_timezone(&self.tz);
+        let time = local.time();
+        let today = local.date_naive();
+        let end_date = if self.start < self.end {
+            if time < self.start || time >= self.end {
+                return None;
+            }
+            today
+        } else if time >= self.start {
+            today + Duration::days(1)
+        } else if time < self.end {
+            today
+        } else {
+            return None;
+        };
+        let end_local = end_date.and_time(self.end);
+        // A window end inside a DST gap does not exist locally; release at
+        // the first valid instant after it.
+        let end = self
+            .tz
+            .from_local_datetime(&end_local)
+            .earliest()
+            .or_else(|| {
+                self.tz
+                    .from_local_datetime(&(end_local + Duration::hours(1)))
+                    .earliest()
+            })?;
+        Some(end.with_timezone(&Utc))
+    }
+}
+
+/// Whether `email` is marketing (bulk/list) mail rather than transactional.
+///
+/// An explicit `X-Mail-Category` header wins; otherwise `Precedence: bulk`
+/// or list headers mark a message as marketing.
+pub fn is_marketing(email: &str) -> bool {
+    let headers = match mailparse::parse_headers(email.as_bytes()) {
+        Ok((headers, _)) => headers,
+        Err(_) => return false,
+    };
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|h| h.get_key().eq_ignore_ascii_case(name))
+            .map(|h| h.get_value().trim().to_ascii_lowercase())
+    };
+    if let Some(category) = header("X-Mail-Category") {
+        return matches!(category.as_str(), "marketing" | "promotional" | "bulk");
+    }
+    if let Some(precedence) = header("Precedence") {
+        if matches!(precedence.as_str(), "bulk" | "list" | "junk") {
+            return true;
+        }
+    }
+    header("List-Unsubscribe").is_some() || header("List-Id").is_some()
+}
+
+/// When `email` must be held back by `quiet`, the UTC instant to send it at.
+pub fn defer_until(
+    quiet: Option<&QuietHours>,
+    email: &str,
+    now: DateTime<Utc>,
+) -> Option<DateTime<Utc>> {
+    let quiet = quiet?;
+    let release = quiet.release_at(now)?;
+    if is_marketing(email) {
+        Some(release)
+    } else {
+        None
+    }
+}
+
+/// Start the background thread that reinjects held messages once due.
+pub fn start_scheduler(db: Database) {
+    info!("[quiet-hours] starting scheduled message sender");
+    crate::shutdown::spawn("quiet hours scheduler", move || loop {
+        send_due_messages(&db);
+        if crate::shutdown::wait(POLL_INTERVAL) {
+            break;
+        }
+    });
+}
+
+fn send_due_messages(db: &Database) {
+    let due = db.list_due_scheduled_messages();
+    if due.is_empty() {
+        return;
+    }
+    debug!("[quiet-hours] {} scheduled message(s) due", due.len());
+    for msg in due {
+        match crate::filter::reinject_smtp(&msg.body, &msg.sender, &msg.recipients) {
+            Ok(()) => {
+                info!(
+                    "[quiet-hours] sent scheduled message id={} sender={} (held since {})",
+                    msg.id, msg.sender, msg.created_at
+                );
+                db.delete_scheduled_message(msg.id);
+            }
+            Err(e) => {
+                // Leave the row in place so the next poll retries it.
+                error!(
+                    "[quiet-hours] failed to send scheduled message id={}: {}",
+                    msg.id, e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MARKETING: &str = "From: news@example.com\r\nTo: a@b.com\r\nSubject: Sale\r\nPrecedence: bulk\r\nList-Unsubscribe: <https://example.com/u>\r\n\r\n<html><body>50% off</body></html>\r\n";
+    const TRANSACTIONAL: &str = "From: billing@example.com\r\nTo: a@b.com\r\nSubject: Your receipt\r\n\r\nThanks for your order.\r\n";
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn marketing_in_quiet_hours_is_deferred_to_window_start() {
+        // 21:00–08:00 Berlin; 23:30 local (CEST, UTC+2) on 2024-06-10.
+        let quiet = QuietHours::parse("21:00", "08:00", "Europe/Berlin").unwrap();
+        let now = utc("2024-06-10T21:30:00Z");
+
+        assert_eq!(
+            defer_until(Some(&quiet), MARKETING, now),
+            Some(utc("2024-06-11T06:00:00Z"))
+        );
+        assert_eq!(defer_until(Some(&quiet), TRANSACTIONAL, now), None);
+    }
+
+    #[test]
+    fn outside_quiet_hours_nothing_is_deferred() {
+        let quiet = QuietHours::parse("21:00", "08:00", "Europe/Berlin").unwrap();
+        let now = utc("2024-06-10T10:00:00Z");
+        assert_eq!(defer_until(Some(&quiet), MARKETING, now), None);
+        assert_eq!(defer_until(None, MARKETING, now), None);
+    }
+
+    #[test]
+    fn same_day_window_and_early_morning_wrap() {
+        let lunch = QuietHours::parse("12:00", "13:00", "UTC").unwrap();
+        assert_eq!(
+            lunch.release_at(utc("2024-01-01T12:15:00Z")),
+            Some(utc("2024-01-01T13:00:00Z"))
+        );
+        assert_eq!(lunch.release_at(utc("2024-01-01T13:00:00Z")), None);
+
+        let night = QuietHours::parse("22:00", "06:00", "UTC").unwrap();
+        assert_eq!(
+            night.release_at(utc("2024-01-02T03:00:00Z")),
+            Some(utc("2024-01-02T06:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn invalid_schedules_are_rejected() {
+        assert!(QuietHours::parse("", "08:00", "UTC").is_none());
+        assert!(QuietHours::parse("21:00", "08:00", "Mars/Olympus").is_none());
+        assert!(QuietHours::parse("08:00", "08:00", "UTC").is_none());
+    }
+
+    #[test]
+    fn explicit_category_overrides_list_headers() {
+        let email = "From: a@b.com\r\nList-Id: <news.b.com>\r\nX-Mail-Category: transactional\r\n\r\nbody\r\n";
+        assert!(!is_marketing(email));
+        assert!(is_marketing(
+            "From: a@b.com\r\nX-Mail-Category: Marketing\r\n\r\nbody\r\n"
+        ));
+    }
+}
@@ -0,0 +1,271 @@
+//! Open-relay safeguards.
+//!
+//! Mail is only relayed to remote recipients when the envelope sender belongs
+//! to an accepted domain: an active local domain, or one listed in the
+//! `relay_accepted_domains` setting.  Local recipients are always accepted, and
+//! so are the remote destinations of active aliases and forwardings (and the
+//! disabled-account forward address), because Postfix expands those before
+//! the content filter sees the message.
+//!
+//! The same policy is applied when configs are generated: relay assignments
+//! for unknown domains are left out of the transport map, and a `main.cf`
+//! whose restrictions would let unauthenticated clients relay is refused.
+
+use log::warn;
+
+use crate::db::Database;
+
+/// Sender domains allowed to relay besides the active local domains.
+pub const ACCEPTED_DOMAINS_SETTING: &str = "relay_accepted_domains";
+
+/// Domains that may relay, split into the active local domains and the
+/// extra accepted sender domains from settings, plus the addresses local
+/// aliases and forwardings deliver to.
+#[derive(Debug, Default)]
+pub struct RelayDomains {
+    pub local: Vec<String>,
+    pub extra: Vec<String>,
+    pub forward_targets: Vec<String>,
+}
+
+impl RelayDomains {
+    pub fn load(db: &Database) -> Self {
+        let local = db
+            .list_domains()
+            .into_iter()
+            .filter(|d| d.active)
+            .map(|d| d.domain.to_lowercase())
+            .collect();
+        let extra =
+            parse_domain_list(&db.get_setting(ACCEPTED_DOMAINS_SETTING).unwrap_or_default());
+        let alias_targets = db
+            .list_all_aliases_with_domain()
+            .into_iter()
+            .filter(|a| a.active)
+            .map(|a| a.destination);
+        let forward_targets = db
+            .list_all_forwardings_with_domain()
+            .into_iter()
+            .filter(|f| f.active)
+            .map(|f| f.destination);
+        let disabled_target = db.get_setting(crate::delivery_policy::DISABLED_FORWARD_SETTING);
+        let forward_targets = alias_targets
+            .chain(forward_targets)
+            .chain(disabled_target)
+            .flat_map(|d| parse_domain_list(&d))
+            .collect();
+        RelayDomains {
+            local,
+            extra,
+            forward_targets,
+        }
+    }
+
+    pub fn is_local(&self, domain: &str) -> bool {
+        self.local.iter().any(|d| d.eq_ignore_ascii_case(domain))
+    }
+
+    pub fn is_accepted(&self, domain: &str) -> bool {
+        self.is_local(domain) || self.extra.iter().any(|d| d.eq_ignore_ascii_case(domain))
+    }
+
+    fn is_forward_target(&self, address: &str) -> bool {
+        self.forward_targets
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(address))
+    }
+}
+
+/// Parse a comma/whitespace separated list of domains (or addresses),
+/// lower-cased.
+pub fn parse_domain_list(input: &str) -> Vec<String> {
+    input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|d| d.trim().trim_start_matches('@').to_lowercase())
+        .filter(|d| !d.is_empty())
+        .collect()
+}
+
+fn domain_of(address: &str) -> &str {
+    address.rsplit_once('@').map(|(_, d)| d).unwrap_or("")
+}
+
+/// Split `recipients` into those the message may be delivered to and those
+/// refused because the sender is not allowed to relay to them.
+pub fn split_recipients(
+    sender: &str,
+    recipients: &[String],
+    domains: &RelayDomains,
+) -> (Vec<String>, Vec<String>) {
+    let sender_accepted = domains.is_accepted(domain_of(sender));
+    recipients.iter().cloned().partition(|rcpt| {
+        sender_accepted || domains.is_local(domain_of(rcpt)) || domains.is_forward_target(rcpt)
+    })
+}
+
+/// Whether a relay assignment pattern (`example.com` or `user@example.com`)
+/// belongs to an accepted domain.
+pub fn assignment_allowed(pattern: &str, domains: &RelayDomains) -> bool {
+    let pattern = pattern.trim();
+    let domain = if pattern.contains('@') {
+        domain_of(pattern)
+    } else {
+        pattern
+    };
+    domains.is_accepted(domain.trim_start_matches('.'))
+}
+
+fn setting_value<'a>(main_cf: &'a str, key: &str) -> Option<&'a str> {
+    main_cf.lines().rev().find_map(|line| {
+        let (k, v) = line.split_once('=')?;
+        (!line.trim_start().starts_with('#') && k.trim() == key).then(|| v.trim())
+    })
+}
+
+/// Problems in a rendered `main.cf` that would allow unauthenticated relay.
+pub fn audit_main_cf(main_cf: &str) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let restrictions = ["smtpd_relay_restrictions", "smtpd_recipient_restrictions"];
+    for key in restrictions {
+        let Some(value) = setting_value(main_cf, key) else {
+            continue;
+        };
+        let items: Vec<&str> = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let guard = items.iter().position(|i| {
+            matches!(
+                *i,
+                "reject_unauth_destination" | "defer_unauth_destination" | "reject"
+            )
+        });
+        match guard {
+            None => problems.push(format!("{} does not end in reject_unauth_destination", key)),
+            Some(pos) => {
+                if items[..pos].contains(&"permit") {
+                    problems.push(format!(
+                        "{} permits every client before reject_unauth_destination",
+                        key
+                    ));
+                }
+            }
+        }
+    }
+    if setting_value(main_cf, "smtpd_relay_restrictions").is_none()
+        && setting_value(main_cf, "smtpd_recipient_restrictions").is_none()
+    {
+        problems.push("no relay restrictions are configured".to_string());
+    }
+
+    if let Some(networks) = setting_value(main_cf, "mynetworks") {
+        for net in networks.split(|c: char| c == ',' || c.is_whitespace()) {
+            let net = net.replace(['[', ']'], "");
+            if matches!(net.as_str(), "0.0.0.0/0" | "::/0" | "0/0") {
+                problems.push(format!("mynetworks trusts every address ({})", net));
+            }
+        }
+    }
+    if setting_value(main_cf, "mynetworks_style") == Some("class") {
+        problems.push("mynetworks_style = class trusts whole address classes".to_string());
+    }
+
+    problems
+}
+
+/// Log and refuse a message whose recipients the sender may not relay to.
+/// Returns `false` if any recipient was refused.
+pub fn check_message(db: &Database, sender: &str, recipients: &[String]) -> bool {
+    let domains = RelayDomains::load(db);
+    let (_, refused) = split_recipients(sender, recipients, &domains);
+    if refused.is_empty() {
+        return true;
+    }
+    warn!(
+        "[relay-guard] refusing to relay for sender={} — its domain is not an accepted relay domain (refused recipients: {})",
+        sender,
+        refused.join(", ")
+    );
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn domains() -> RelayDomains {
+        RelayDomains {
+            local: vec!["example.com".to_string()],
+            extra: parse_domain_list("app.example.net, @partner.org"),
+            forward_targets: parse_domain_list("me@gmail.test, Team@Other.test"),
+        }
+    }
+
+    fn rcpts(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn unknown_sender_domain_is_not_relayed() {
+        let (allowed, refused) = split_recipients(
+            "spammer@unknown.test",
+            &rcpts(&["victim@remote.test", "user@example.com"]),
+            &domains(),
+        );
+        assert_eq!(allowed, rcpts(&["user@example.com"]));
+        assert_eq!(refused, rcpts(&["victim@remote.test"]));
+    }
+
+    #[test]
+    fn forwarded_mail_from_unknown_sender_is_delivered() {
+        let (allowed, refused) = split_recipients(
+            "newsletter@unknown.test",
+            &rcpts(&["me@gmail.test", "team@other.test"]),
+            &domains(),
+        );
+        assert_eq!(allowed.len(), 2);
+        assert!(refused.is_empty());
+    }
+
+    #[test]
+    fn local_and_accepted_sender_domains_are_relayed() {
+        for sender in ["user@EXAMPLE.com", "robot@app.example.net", "x@partner.org"] {
+            let (allowed, refused) =
+                split_recipients(sender, &rcpts(&["friend@remote.test"]), &domains());
+            assert_eq!(allowed, rcpts(&["friend@remote.test"]), "{}", sender);
+            assert!(refused.is_empty());
+        }
+        let (_, refused) = split_recipients("", &rcpts(&["friend@remote.test"]), &domains());
+        assert_eq!(refused, rcpts(&["friend@remote.test"]));
+    }
+
+    #[test]
+    fn assignments_must_belong_to_accepted_domains() {
+        assert!(assignment_allowed("example.com", &domains()));
+        assert!(assignment_allowed("user@example.com", &domains()));
+        assert!(assignment_allowed("partner.org", &domains()));
+        assert!(!assignment_allowed("gmail.com", &domains()));
+        assert!(!assignment_allowed("someone@gmail.com", &domains()));
+    }
+
+    #[test]
+    fn audit_flags_open_relay_configs() {
+        let safe = "mynetworks_style = host\n\
+                    smtpd_relay_restrictions = permit_mynetworks, permit_sasl_authenticated, reject_unauth_destination\n\
+                    smtpd_recipient_restrictions = permit_sasl_authenticated, permit_mynetworks, reject_rbl_client zen.example, reject_unauth_destination\n";
+        assert!(audit_main_cf(safe).is_empty());
+
+        let open =
+            "smtpd_recipient_restrictions = permit_mynetworks, permit, reject_unauth_destination\n";
+        assert_eq!(audit_main_cf(open).len(), 1);
+
+        let missing = "smtpd_recipient_restrictions = permit_sasl_authenticated, permit\n";
+        assert_eq!(audit_main_cf(missing).len(), 1);
+
+        let trusting = format!("{}mynetworks = 127.0.0.0/8, 0.0.0.0/0\n", safe);
+        assert_eq!(audit_main_cf(&trusting).len(), 1);
+
+        assert_eq!(audit_main_cf("# nothing here\n").len(), 1);
+    }
+}
@@ -0,0 +1,222 @@
+//! Reserved role mailboxes of each domain (RFC 2142).
+//!
+//! Every domain has a row per role in `domain_reserved_mailboxes` that maps
+//! `role@domain` to a target: a mailbox name on the same domain or a full
+//! address elsewhere.  Config generation adds the enabled roles with a
+//! target to the virtual alias map, unless an account, alias or forwarding
+//! already answers the address, so an explicit mailbox always wins.
+//!
+//! New domains send every role to postmaster, which has no target until the
+//! admin sets one on the domain edit page; the DNS page warns while
+//! postmaster or abuse reach nobody.
+
+use crate::db::{Database, Domain, ReservedMailbox};
+
+pub struct Role {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Required by RFC 2142 for a domain that offers mail.
+    pub required: bool,
+}
+
+pub const ROLES: &[Role] = &[
+    Role {
+        name: "postmaster",
+        description: "Mail delivery problems",
+        required: true,
+    },
+    Role {
+        name: "abuse",
+        description: "Spam and abuse complaints",
+        required: true,
+    },
+    Role {
+        name: "hostmaster",
+        description: "DNS problems",
+        required: false,
+    },
+    Role {
+        name: "webmaster",
+        description: "Web site problems",
+        required: false,
+    },
+    Role {
+        name: "dmarc",
+        description: "DMARC reports",
+        required: false,
+    },
+];
+
+/// Target `role` starts with on a new domain.
+pub fn default_target(role: &str) -> &'static str {
+    if role == "postmaster" {
+        ""
+    } else {
+        "postmaster"
+    }
+}
+
+/// The address mail for `target` goes to: a bare mailbox name is on
+/// `domain`.
+pub fn expand_target(target: &str, domain: &str) -> String {
+    if target.contains('@') {
+        target.to_string()
+    } else {
+        format!("{}@{}", target, domain)
+    }
+}
+
+/// Check a target from the edit form, returning a message for the admin.
+/// An empty target is allowed and leaves the role unanswered.
+pub fn validate_target(role: &str, target: &str, domain: &str) -> Result<(), String> {
+    if target.is_empty() {
+        return Ok(());
+    }
+    let valid = if target.contains('@') {
+        crate::validation::is_valid_destination(target)
+    } else {
+        crate::validation::is_valid_localpart(target)
+    };
+    if !valid {
+        return Err(format!(
+            "The {} target '{}' must be a mailbox name on this domain or a full address such as ops@example.com.",
+            role, target
+        ));
+    }
+    if expand_target(target, domain) == format!("{}@{}", role, domain) {
+        return Err(format!("{}@{} cannot be its own target.", role, domain));
+    }
+    Ok(())
+}
+
+/// Virtual alias entries for the enabled reserved mailboxes with a target.
+pub fn alias_entries(mailboxes: &[ReservedMailbox]) -> Vec<(String, String)> {
+    mailboxes
+        .iter()
+        .filter(|m| m.enabled && !m.target.is_empty())
+        .map(|m| {
+            (
+                format!("{}@{}", m.role, m.domain_name),
+                expand_target(&m.target, &m.domain_name),
+            )
+        })
+        .collect()
+}
+
+/// Whether `role` has an enabled target, following targets that name
+/// another role of the same domain.
+fn has_target(role: &str, mailboxes: &[ReservedMailbox], domain: &str) -> bool {
+    let mut role = role.to_string();
+    for _ in 0..ROLES.len() {
+        let Some(m) = mailboxes
+            .iter()
+            .find(|m| m.role == role && m.enabled && !m.target.is_empty())
+        else {
+            return false;
+        };
+        match expand_target(&m.target, domain).strip_suffix(&format!("@{}", domain)) {
+            Some(local) if mailboxes.iter().any(|m| m.role == local) => role = local.to_string(),
+            _ => return true,
+        }
+    }
+    false
+}
+
+/// Addresses of the required roles of `domain` that nothing answers: no
+/// enabled reserved mailbox with a target, account, alias, forwarding or
+/// catch-all.
+pub fn unanswered_required(db: &Database, domain: &Domain) -> Vec<String> {
+    let reserved = db.list_reserved_mailboxes(domain.id);
+    let aliases = db.list_all_aliases_with_domain();
+    let forwardings = db.list_all_forwardings_with_domain();
+    let catch_all = [
+        format!("*@{}", domain.domain),
+        format!("@{}", domain.domain),
+    ];
+    let answered = |address: &str| {
+        let is_source = |source: &str| {
+            let source = source.trim().to_lowercase();
+            source == address || catch_all.contains(&source)
+        };
+        db.get_account_by_email(address).is_some()
+            || aliases.iter().any(|a| a.active && is_source(&a.source))
+            || forwardings.iter().any(|f| f.active && is_source(&f.source))
+    };
+    ROLES
+        .iter()
+        .filter(|role| role.required)
+        .filter(|role| !has_target(role.name, &reserved, &domain.domain))
+        .map(|role| format!("{}@{}", role.name, domain.domain))
+        .filter(|address| !answered(address))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mailbox(role: &str, target: &str, enabled: bool) -> ReservedMailbox {
+        ReservedMailbox {
+            domain_id: 1,
+            domain_name: "example.com".to_string(),
+            role: role.to_string(),
+            target: target.to_string(),
+            enabled,
+        }
+    }
+
+    #[test]
+    fn enabled_roles_with_a_target_become_aliases() {
+        let mailboxes = [
+            mailbox("postmaster", "jane", true),
+            mailbox("abuse", "postmaster", true),
+            mailbox("hostmaster", "ops@example.net", true),
+            mailbox("webmaster", "postmaster", false),
+            mailbox("dmarc", "", true),
+        ];
+        assert_eq!(
+            alias_entries(&mailboxes),
+            vec![
+                (
+                    "postmaster@example.com".to_string(),
+                    "jane@example.com".to_string()
+                ),
+                (
+                    "abuse@example.com".to_string(),
+                    "postmaster@example.com".to_string()
+                ),
+                (
+                    "hostmaster@example.com".to_string(),
+                    "ops@example.net".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn roles_pointing_at_an_unset_postmaster_have_no_target() {
+        let mut mailboxes = vec![
+            mailbox("postmaster", "", true),
+            mailbox("abuse", "postmaster", true),
+            mailbox("hostmaster", "webmaster", true),
+            mailbox("webmaster", "hostmaster", true),
+        ];
+        assert!(!has_target("abuse", &mailboxes, "example.com"));
+        assert!(!has_target("hostmaster", &mailboxes, "example.com"));
+        mailboxes[0].target = "jane".to_string();
+        assert!(has_target("abuse", &mailboxes, "example.com"));
+        mailboxes[1].enabled = false;
+        assert!(!has_target("abuse", &mailboxes, "example.com"));
+    }
+
+    #[test]
+    fn targets_are_validated() {
+        assert!(validate_target("abuse", "", "example.com").is_ok());
+        assert!(validate_target("abuse", "postmaster", "example.com").is_ok());
+        assert!(validate_target("abuse", "Ops+Abuse@example.net", "example.com").is_ok());
+        assert!(validate_target("abuse", "abuse", "example.com").is_err());
+        assert!(validate_target("abuse", "abuse@example.com", "example.com").is_err());
+        assert!(validate_target("abuse", "two words", "example.com").is_err());
+        assert!(validate_target("abuse", "ops@example.net.", "example.com").is_err());
+    }
+}
@@ -0,0 +1,137 @@
+//! Per-account sending limits.
+//!
+//! The outgoing content filter counts the messages and recipients each
+//! account submits over a rolling window (`send_limit_window_minutes`) and
+//! bounces mail once the account's limit is exceeded, so a compromised
+//! account cannot send unchecked.  The global limits come from the
+//! `send_limit_messages` and `send_limit_recipients` settings; an account can
+//! override either on its edit form.  A limit of 0 means unlimited.
+//!
+//! The account is the SASL login passed by Postfix, or the envelope sender
+//! when the message was not submitted with authentication.
+
+use log::{debug, warn};
+
+use crate::db::Database;
+
+pub const MESSAGES_SETTING: &str = "send_limit_messages";
+pub const RECIPIENTS_SETTING: &str = "send_limit_recipients";
+pub const WINDOW_SETTING: &str = "send_limit_window_minutes";
+pub const DEFAULT_WINDOW_MINUTES: i64 = 60;
+pub const MAX_WINDOW_MINUTES: i64 = 10080;
+
+/// Limits over one window; 0 means unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Limits {
+    pub messages: i64,
+    pub recipients: i64,
+}
+
+impl Limits {
+    /// The global limits from settings.
+    pub fn load(db: &Database) -> Self {
+        let get = |key| {
+            db.get_setting(key)
+                .and_then(|v| v.trim().parse().ok())
+                .filter(|n: &i64| *n >= 0)
+                .unwrap_or(0)
+        };
+        Limits {
+            messages: get(MESSAGES_SETTING),
+            recipients: get(RECIPIENTS_SETTING),
+        }
+    }
+
+    /// These limits with an account's overrides applied.
+    pub fn with_overrides(self, messages: Option<i32>, recipients: Option<i32>) -> Self {
+        Limits {
+            messages: messages.map_or(self.messages, i64::from),
+            recipients: recipients.map_or(self.recipients, i64::from),
+        }
+    }
+
+    /// Which limit a message to `recipients` more recipients exceeds, given
+    /// what was sent in the window so far.
+    pub fn exceeded(&self, sent: (i64, i64), recipients: i64) -> Option<&'static str> {
+        let (sent_messages, sent_recipients) = sent;
+        if self.messages > 0 && sent_messages + 1 > self.messages {
+            Some("messages")
+        } else if self.recipients > 0 && sent_recipients + recipients > self.recipients {
+            Some("recipients")
+        } else {
+            None
+        }
+    }
+}
+
+pub fn window_minutes(db: &Database) -> i64 {
+    db.get_setting(WINDOW_SETTING)
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|m| (1..=MAX_WINDOW_MINUTES).contains(m))
+        .unwrap_or(DEFAULT_WINDOW_MINUTES)
+}
+
+/// Count a message from `login` (or `sender` without one) to `recipients`
+/// recipients, or return the SMTP error to bounce it with when the account
+/// is over its limit.  Senders that are not local accounts are not limited.
+pub fn check(db: &Database, login: &str, sender: &str, recipients: usize) -> Result<(), String> {
+    let address = if login.is_empty() { sender } else { login };
+    let Some(account) = db.get_account_by_email(address) else {
+        debug!("[send-limits] {} is not a local account", address);
+        return Ok(());
+    };
+    let (messages, rcpts) = db.get_account_send_limits(account.id);
+    let limits = Limits::load(db).with_overrides(messages, rcpts);
+    if limits == Limits::default() {
+        return Ok(());
+    }
+    let window = window_minutes(db);
+    let sent = db.count_recent_sends(account.id, window);
+    let recipients = recipients as i64;
+    if let Some(kind) = limits.exceeded(sent, recipients) {
+        let (limit, verb) = if kind == "messages" {
+            (limits.messages, "send")
+        } else {
+            (limits.recipients, "send to")
+        };
+        warn!(
+            "[send-limits] account {} hit its sending limit of {} {} per {} min \
+             (sent {} messages to {} recipients, this message has {}): possible compromised account",
+            address, limit, kind, window, sent.0, sent.1, recipients
+        );
+        return Err(format!(
+            "5.7.1 Sending limit exceeded: {} may {} at most {} {} per {} minutes",
+            address, verb, limit, kind, window
+        ));
+    }
+    db.record_send(account.id, recipients as i32);
+    let cutoff = (chrono::Utc::now() - chrono::Duration::minutes(MAX_WINDOW_MINUTES))
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string();
+    db.prune_send_counters(&cutoff);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_apply_overrides_and_count_the_new_message() {
+        let global = Limits {
+            messages: 10,
+            recipients: 50,
+        };
+        assert_eq!(global.exceeded((9, 40), 10), None);
+        assert_eq!(global.exceeded((10, 40), 1), Some("messages"));
+        assert_eq!(global.exceeded((5, 45), 6), Some("recipients"));
+
+        let unlimited_messages = global.with_overrides(Some(0), None);
+        assert_eq!(unlimited_messages.exceeded((500, 0), 1), None);
+        assert_eq!(
+            unlimited_messages.exceeded((500, 50), 1),
+            Some("recipients")
+        );
+        assert_eq!(Limits::default().exceeded((1000, 1000), 1000), None);
+    }
+}
@@ -0,0 +1,135 @@
+//! Graceful shutdown of `serve`.
+//!
+//! SIGTERM or SIGINT sets a process-wide flag.  The admin server stops
+//! accepting connections and finishes the requests in flight, background
+//! threads started with [`spawn`] notice the flag at their next pause and
+//! return, and `main` waits for them before the process exits, so a config
+//! generation that is under way is never cut off halfway.
+
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+
+/// How long to wait for requests and background threads to finish.
+pub const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct State {
+    requested: bool,
+    /// Names of the threads started with [`spawn`] that are still running.
+    running: Vec<&'static str>,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    requested: false,
+    running: Vec::new(),
+});
+static CHANGED: Condvar = Condvar::new();
+
+/// Ask everything to stop.
+pub fn request() {
+    STATE.lock().unwrap().requested = true;
+    CHANGED.notify_all();
+}
+
+/// Whether shutdown was requested.
+pub fn requested() -> bool {
+    STATE.lock().unwrap().requested
+}
+
+/// Sleep for `duration`, waking early on shutdown.  Returns `true` when the
+/// caller should stop.
+pub fn wait(duration: Duration) -> bool {
+    let state = STATE.lock().unwrap();
+    let (state, _) = CHANGED
+        .wait_timeout_while(state, duration, |s| !s.requested)
+        .unwrap();
+    state.requested
+}
+
+/// Removes its thread from the running list, even when the thread panics.
+struct Running(&'static str);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let mut state = STATE.lock().unwrap();
+        if let Some(i) = state.running.iter().position(|name| *name == self.0) {
+            state.running.swap_remove(i);
+        }
+        if state.requested {
+            info!("[shutdown] {} stopped", self.0);
+        }
+        CHANGED.notify_all();
+    }
+}
+
+/// Start a thread that shutdown waits for.  Long-running loops should pause
+/// with [`wait`] and return when it says so.
+pub fn spawn(name: &'static str, f: impl FnOnce() + Send + 'static) {
+    STATE.lock().unwrap().running.push(name);
+    std::thread::spawn(move || {
+        let _running = Running(name);
+        f();
+    });
+}
+
+/// Wait up to `timeout` for the threads started with [`spawn`] to return.
+/// Returns the names of those still running.
+pub fn join(timeout: Duration) -> Vec<&'static str> {
+    let deadline = Instant::now() + timeout;
+    let mut state = STATE.lock().unwrap();
+    while !state.running.is_empty() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            break;
+        }
+        state = CHANGED.wait_timeout(state, left).unwrap().0;
+    }
+    state.running.clone()
+}
+
+/// Resolve on the first SIGTERM or SIGINT, after setting the flag.
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("[shutdown] cannot listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!("[shutdown] cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    let name = tokio::select! {
+        _ = ctrl_c => "SIGINT",
+        _ = terminate => "SIGTERM",
+    };
+    info!(
+        "[shutdown] received {}, finishing in-flight work (up to {}s)",
+        name,
+        DRAIN_TIMEOUT.as_secs()
+    );
+    request();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawned_threads_stop_and_are_joined() {
+        spawn("test worker", || while !wait(Duration::from_secs(60)) {});
+        assert_eq!(join(Duration::from_millis(50)), vec!["test worker"]);
+        request();
+        assert!(requested());
+        assert!(wait(Duration::from_secs(60)));
+        assert!(join(Duration::from_secs(5)).is_empty());
+    }
+}
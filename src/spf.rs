@@ -0,0 +1,731 @@
+//! SPF verification of inbound mail (RFC 7208).
+//!
+//! The incoming content filter checks the connecting client address, passed
+//! by Postfix as `--client-address`, against the SPF record of the envelope
+//! sender's domain (or the HELO name for bounces) and stamps the message with
+//! a `Received-SPF` header.  The `spf_policy` setting selects `tag` (the
+//! default), `reject` to bounce hard failures, or `off`.
+//!
+//! DNS answers are cached for the lifetime of a [`SystemResolver`], i.e. one
+//! filter run, so records shared by several `include:` chains are fetched
+//! once.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::process::Command;
+
+use ipnetwork::IpNetwork;
+use log::{debug, warn};
+
+use crate::db::Database;
+
+pub const POLICY_SETTING: &str = "spf_policy";
+
+/// RFC 7208 §4.6.4: at most 10 terms that cause DNS lookups.
+const MAX_DNS_LOOKUPS: usize = 10;
+/// RFC 7208 §4.6.4: at most 2 lookups that return no records.
+const MAX_VOID_LOOKUPS: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpfResult {
+    Pass,
+    Fail,
+    SoftFail,
+    Neutral,
+    None,
+    TempError,
+    PermError,
+}
+
+impl SpfResult {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SpfResult::Pass => "pass",
+            SpfResult::Fail => "fail",
+            SpfResult::SoftFail => "softfail",
+            SpfResult::Neutral => "neutral",
+            SpfResult::None => "none",
+            SpfResult::TempError => "temperror",
+            SpfResult::PermError => "permerror",
+        }
+    }
+
+    /// Capitalised form used in the `Received-SPF` header.
+    fn header_name(self) -> &'static str {
+        match self {
+            SpfResult::Pass => "Pass",
+            SpfResult::Fail => "Fail",
+            SpfResult::SoftFail => "SoftFail",
+            SpfResult::Neutral => "Neutral",
+            SpfResult::None => "None",
+            SpfResult::TempError => "TempError",
+            SpfResult::PermError => "PermError",
+        }
+    }
+
+    fn from_qualifier(q: char) -> Self {
+        match q {
+            '-' => SpfResult::Fail,
+            '~' => SpfResult::SoftFail,
+            '?' => SpfResult::Neutral,
+            _ => SpfResult::Pass,
+        }
+    }
+}
+
+/// What the filter does with a failed SPF or DKIM check.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuthPolicy {
+    Off,
+    Tag,
+    Reject,
+}
+
+impl AuthPolicy {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "off" => Some(AuthPolicy::Off),
+            "tag" => Some(AuthPolicy::Tag),
+            "reject" => Some(AuthPolicy::Reject),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AuthPolicy::Off => "off",
+            AuthPolicy::Tag => "tag",
+            AuthPolicy::Reject => "reject",
+        }
+    }
+
+    /// The policy stored under `setting`, tagging by default.
+    pub fn load(db: &Database, setting: &str) -> Self {
+        db.get_setting(setting)
+            .and_then(|v| AuthPolicy::parse(&v))
+            .unwrap_or(AuthPolicy::Tag)
+    }
+}
+
+// ── DNS ──
+
+/// DNS lookups needed by SPF, DKIM and the domain setup check.  `Err` means a
+/// temporary failure; a name without records is `Ok` with an empty list.
+pub trait Resolver {
+    fn txt(&self, name: &str) -> Result<Vec<String>, String>;
+    fn addrs(&self, name: &str) -> Result<Vec<IpAddr>, String>;
+    fn mx(&self, name: &str) -> Result<Vec<String>, String>;
+    fn ptr(&self, ip: IpAddr) -> Result<Vec<String>, String>;
+}
+
+/// Resolver backed by the system `nslookup` and `getaddrinfo`, caching every
+/// answer for its own lifetime.
+#[derive(Default)]
+pub struct SystemResolver {
+    cache: RefCell<HashMap<String, Result<Vec<String>, String>>>,
+}
+
+impl SystemResolver {
+    fn cached(
+        &self,
+        key: String,
+        lookup: impl FnOnce() -> Result<Vec<String>, String>,
+    ) -> Result<Vec<String>, String> {
+        if let Some(hit) = self.cache.borrow().get(&key) {
+            debug!("[spf] DNS cache hit for {}", key);
+            return hit.clone();
+        }
+        let answer = lookup();
+        self.cache.borrow_mut().insert(key, answer.clone());
+        answer
+    }
+}
+
+fn nslookup(kind: &str, name: &str) -> Result<String, String> {
+    let output = Command::new("nslookup")
+        .args([format!("-type={}", kind), name.to_string()])
+        .output()
+        .map_err(|e| format!("nslookup failed: {}", e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if stdout.contains("timed out") || stdout.contains("SERVFAIL") {
+        return Err(format!("{} lookup for {} failed", kind, name));
+    }
+    Ok(stdout)
+}
+
+/// The TXT strings of an `nslookup` answer, joining the quoted chunks of
+/// each record (RFC 7208 §3.3).
+fn parse_nslookup_txt(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("text = ").map(|(_, rest)| rest))
+        .map(|rest| {
+            rest.split('"')
+                .skip(1)
+                .step_by(2)
+                .collect::<Vec<_>>()
+                .concat()
+        })
+        .collect()
+}
+
+fn parse_nslookup_mx(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("mail exchanger = ").map(|(_, rest)| rest))
+        .filter_map(|rest| rest.split_whitespace().nth(1))
+        .map(|host| host.trim_end_matches('.').to_lowercase())
+        .collect()
+}
+
+fn parse_nslookup_ptr(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .filter_map(|line| line.split_once("name = ").map(|(_, rest)| rest))
+        .map(|name| name.trim().trim_end_matches('.').to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+impl Resolver for SystemResolver {
+    fn txt(&self, name: &str) -> Result<Vec<String>, String> {
+        self.cached(format!("txt:{}", name), || {
+            nslookup("TXT", name).map(|out| parse_nslookup_txt(&out))
+        })
+    }
+
+    fn addrs(&self, name: &str) -> Result<Vec<IpAddr>, String> {
+        use std::net::ToSocketAddrs;
+        let answer = self.cached(format!("addr:{}", name), || {
+            // getaddrinfo cannot tell NXDOMAIN from a timeout: both are "no address".
+            Ok((name, 0u16)
+                .to_socket_addrs()
+                .map(|addrs| addrs.map(|a| a.ip().to_string()).collect())
+                .unwrap_or_default())
+        })?;
+        Ok(answer.iter().filter_map(|a| a.parse().ok()).collect())
+    }
+
+    fn mx(&self, name: &str) -> Result<Vec<String>, String> {
+        self.cached(format!("mx:{}", name), || {
+            nslookup("MX", name).map(|out| parse_nslookup_mx(&out))
+        })
+    }
+
+    fn ptr(&self, ip: IpAddr) -> Result<Vec<String>, String> {
+        self.cached(format!("ptr:{}", ip), || {
+            nslookup("PTR", &ip.to_string()).map(|out| parse_nslookup_ptr(&out))
+        })
+    }
+}
+
+// ── Evaluation ──
+
+/// Inputs of one check, used for macro expansion (RFC 7208 §7).
+struct Context<'a> {
+    ip: IpAddr,
+    sender: &'a str,
+    helo: &'a str,
+    lookups: usize,
+    void_lookups: usize,
+}
+
+impl Context<'_> {
+    fn local_part(&self) -> &str {
+        self.sender
+            .rsplit_once('@')
+            .map(|(l, _)| l)
+            .filter(|l| !l.is_empty())
+            .unwrap_or("postmaster")
+    }
+
+    fn sender_domain(&self) -> &str {
+        self.sender
+            .rsplit_once('@')
+            .map(|(_, d)| d)
+            .unwrap_or(self.helo)
+    }
+
+    fn count_lookup(&mut self) -> Result<(), SpfResult> {
+        self.lookups += 1;
+        if self.lookups > MAX_DNS_LOOKUPS {
+            return Err(SpfResult::PermError);
+        }
+        Ok(())
+    }
+
+    fn count_void(&mut self, empty: bool) -> Result<(), SpfResult> {
+        if empty {
+            self.void_lookups += 1;
+            if self.void_lookups > MAX_VOID_LOOKUPS {
+                return Err(SpfResult::PermError);
+            }
+        }
+        Ok(())
+    }
+
+    /// Expand the macros of a domain-spec.
+    fn expand(&self, spec: &str, domain: &str) -> Result<String, SpfResult> {
+        let mut out = String::new();
+        let mut chars = spec.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => out.push('%'),
+                Some('_') => out.push(' '),
+                Some('-') => out.push_str("%20"),
+                Some('{') => {
+                    let body: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    out.push_str(&self.expand_macro(&body, domain)?);
+                }
+                _ => return Err(SpfResult::PermError),
+            }
+        }
+        Ok(out)
+    }
+
+    fn expand_macro(&self, body: &str, domain: &str) -> Result<String, SpfResult> {
+        let mut chars = body.chars();
+        let letter = chars.next().ok_or(SpfResult::PermError)?;
+        let rest: String = chars.collect();
+        let digits: String = rest.chars().take_while(|c| c.is_ascii_digit()).collect();
+        let rest = &rest[digits.len()..];
+        let reverse = rest.starts_with(['r', 'R']);
+        let delimiters: Vec<char> = rest.trim_start_matches(['r', 'R']).chars().collect();
+        let value = match letter.to_ascii_lowercase() {
+            's' => self.sender.to_string(),
+            'l' => self.local_part().to_string(),
+            'o' => self.sender_domain().to_string(),
+            'd' => domain.to_string(),
+            'h' => self.helo.to_string(),
+            'i' => match self.ip {
+                IpAddr::V4(v4) => v4.to_string(),
+                IpAddr::V6(v6) => v6
+                    .octets()
+                    .iter()
+                    .flat_map(|b| [b >> 4, b & 0xf])
+                    .map(|n| format!("{:x}", n))
+                    .collect::<Vec<_>>()
+                    .join("."),
+            },
+            'v' => match self.ip {
+                IpAddr::V4(_) => "in-addr".to_string(),
+                IpAddr::V6(_) => "ip6".to_string(),
+            },
+            // `p` needs validated PTR names; RFC 7208 §7.3 allows "unknown".
+            'p' => "unknown".to_string(),
+            _ => return Err(SpfResult::PermError),
+        };
+        let delimiters = if delimiters.is_empty() {
+            vec!['.']
+        } else {
+            delimiters
+        };
+        let mut parts: Vec<&str> = value.split(|c| delimiters.contains(&c)).collect();
+        if reverse {
+            parts.reverse();
+        }
+        if let Ok(keep) = digits.parse::<usize>() {
+            if keep == 0 {
+                return Err(SpfResult::PermError);
+            }
+            let skip = parts.len().saturating_sub(keep);
+            parts.drain(..skip);
+        }
+        Ok(parts.join("."))
+    }
+}
+
+/// Split `a:example.com/24//64` style arguments into domain and CIDR lengths.
+fn split_cidr(arg: &str) -> (&str, Option<u8>, Option<u8>) {
+    let (rest, v6) = match arg.split_once("//") {
+        Some((r, v6)) => (r, v6.parse().ok()),
+        None => (arg, None),
+    };
+    match rest.rsplit_once('/') {
+        Some((d, v4)) => (d, v4.parse().ok(), v6),
+        None => (rest, None, v6),
+    }
+}
+
+fn in_network(ip: IpAddr, addr: IpAddr, v4_len: Option<u8>, v6_len: Option<u8>) -> bool {
+    let prefix = match addr {
+        IpAddr::V4(_) => v4_len.unwrap_or(32),
+        IpAddr::V6(_) => v6_len.unwrap_or(128),
+    };
+    IpNetwork::new(addr, prefix)
+        .map(|net| net.contains(ip))
+        .unwrap_or(false)
+}
+
+/// The SPF record of `domain`: `Ok(None)` when it publishes none.
+fn spf_record(resolver: &dyn Resolver, domain: &str) -> Result<Option<String>, SpfResult> {
+    let records: Vec<String> = resolver
+        .txt(domain)
+        .map_err(|_| SpfResult::TempError)?
+        .into_iter()
+        .filter(|t| {
+            let lower = t.to_ascii_lowercase();
+            lower == "v=spf1" || lower.starts_with("v=spf1 ")
+        })
+        .collect();
+    match records.len() {
+        0 => Ok(None),
+        1 => Ok(records.into_iter().next()),
+        _ => Err(SpfResult::PermError),
+    }
+}
+
+/// RFC 7208 §4 `check_host()`.
+fn check_host(ctx: &mut Context, resolver: &dyn Resolver, domain: &str) -> SpfResult {
+    match evaluate(ctx, resolver, domain) {
+        Ok(result) | Err(result) => result,
+    }
+}
+
+fn evaluate(
+    ctx: &mut Context,
+    resolver: &dyn Resolver,
+    domain: &str,
+) -> Result<SpfResult, SpfResult> {
+    if domain.is_empty() || !domain.contains('.') || domain.len() > 253 {
+        return Ok(SpfResult::None);
+    }
+    let Some(record) = spf_record(resolver, domain)? else {
+        return Ok(SpfResult::None);
+    };
+    debug!("[spf] {} publishes \"{}\"", domain, record);
+
+    let mut redirect = None;
+    for term in record.split_whitespace().skip(1) {
+        if let Some((name, value)) = term.split_once('=') {
+            if name.eq_ignore_ascii_case("redirect") {
+                redirect = Some(value.to_string());
+            }
+            // `exp=` and unknown modifiers are ignored.
+            continue;
+        }
+        let (qualifier, mechanism) = match term.chars().next() {
+            Some(q @ ('+' | '-' | '~' | '?')) => (q, &term[1..]),
+            _ => ('+', term),
+        };
+        let (name, arg) = match mechanism.split_once([':', '/']) {
+            Some((n, _)) => (n, &mechanism[n.len()..]),
+            None => (mechanism, ""),
+        };
+        let arg = arg.strip_prefix(':').unwrap_or(arg);
+        let matched = match name.to_ascii_lowercase().as_str() {
+            "all" => true,
+            "include" => {
+                ctx.count_lookup()?;
+                let target = ctx.expand(arg, domain)?;
+                match check_host(ctx, resolver, &target) {
+                    SpfResult::Pass => true,
+                    SpfResult::Fail | SpfResult::SoftFail | SpfResult::Neutral => false,
+                    SpfResult::TempError => return Err(SpfResult::TempError),
+                    SpfResult::PermError | SpfResult::None => return Err(SpfResult::PermError),
+                }
+            }
+            "a" => {
+                ctx.count_lookup()?;
+                let (target, v4, v6) = split_cidr(arg);
+                let target = if target.is_empty() {
+                    domain.to_string()
+                } else {
+                    ctx.expand(target, domain)?
+                };
+                let addrs = resolver.addrs(&target).map_err(|_| SpfResult::TempError)?;
+                ctx.count_void(addrs.is_empty())?;
+                addrs.iter().any(|&a| in_network(ctx.ip, a, v4, v6))
+            }
+            "mx" => {
+                ctx.count_lookup()?;
+                let (target, v4, v6) = split_cidr(arg);
+                let target = if target.is_empty() {
+                    domain.to_string()
+                } else {
+                    ctx.expand(target, domain)?
+                };
+                let hosts = resolver.mx(&target).map_err(|_| SpfResult::TempError)?;
+                ctx.count_void(hosts.is_empty())?;
+                let mut found = false;
+                // RFC 7208 §4.6.4: no more than 10 MX names are looked up.
+                for host in hosts.iter().take(MAX_DNS_LOOKUPS) {
+                    let addrs = resolver.addrs(host).map_err(|_| SpfResult::TempError)?;
+                    if addrs.iter().any(|&a| in_network(ctx.ip, a, v4, v6)) {
+                        found = true;
+                        break;
+                    }
+                }
+                found
+            }
+            "ptr" => {
+                // Deprecated (RFC 7208 §5.5) and not supported: never matches.
+                ctx.count_lookup()?;
+                false
+            }
+            "ip4" | "ip6" => {
+                let network: IpNetwork = arg.parse().map_err(|_| SpfResult::PermError)?;
+                network.contains(ctx.ip)
+            }
+            "exists" => {
+                ctx.count_lookup()?;
+                let target = ctx.expand(arg, domain)?;
+                let addrs = resolver.addrs(&target).map_err(|_| SpfResult::TempError)?;
+                ctx.count_void(addrs.is_empty())?;
+                addrs.iter().any(|a| a.is_ipv4())
+            }
+            _ => return Err(SpfResult::PermError),
+        };
+        if matched {
+            return Ok(SpfResult::from_qualifier(qualifier));
+        }
+    }
+
+    match redirect {
+        Some(target) => {
+            ctx.count_lookup()?;
+            let target = ctx.expand(&target, domain)?;
+            match check_host(ctx, resolver, &target) {
+                SpfResult::None => Ok(SpfResult::PermError),
+                result => Ok(result),
+            }
+        }
+        None => Ok(SpfResult::Neutral),
+    }
+}
+
+/// Check `ip` against the SPF policy of the envelope `sender`, falling back to
+/// `postmaster@<helo>` for the null reverse-path (RFC 7208 §2.4).
+pub fn check(ip: IpAddr, sender: &str, helo: &str, resolver: &dyn Resolver) -> SpfResult {
+    let sender = if sender.contains('@') {
+        sender.to_lowercase()
+    } else {
+        format!("postmaster@{}", helo.to_lowercase())
+    };
+    let mut ctx = Context {
+        ip,
+        sender: &sender,
+        helo,
+        lookups: 0,
+        void_lookups: 0,
+    };
+    let domain = ctx.sender_domain().to_string();
+    check_host(&mut ctx, resolver, &domain)
+}
+
+/// The `Received-SPF` header (RFC 7208 §9.1) for a result.
+pub fn received_spf_header(
+    result: SpfResult,
+    receiver: &str,
+    ip: IpAddr,
+    sender: &str,
+    helo: &str,
+) -> String {
+    let identity = if sender.contains('@') {
+        "mailfrom"
+    } else {
+        "helo"
+    };
+    let subject = if sender.contains('@') { sender } else { helo };
+    let comment = match result {
+        SpfResult::Pass => format!(
+            "domain of {} designates {} as permitted sender",
+            subject, ip
+        ),
+        SpfResult::Fail | SpfResult::SoftFail => format!(
+            "domain of {} does not designate {} as permitted sender",
+            subject, ip
+        ),
+        SpfResult::Neutral => format!("{} is neither permitted nor denied by {}", ip, subject),
+        SpfResult::None => format!("{} does not publish an SPF record", subject),
+        SpfResult::TempError => format!("temporary DNS error checking {}", subject),
+        SpfResult::PermError => format!("the SPF record of {} is invalid", subject),
+    };
+    let clean = |s: &str| s.replace(['\r', '\n', ';', '"'], "");
+    format!(
+        "Received-SPF: {} ({}: {}) client-ip={}; envelope-from=\"{}\"; helo={}; receiver={}; identity={};",
+        result.header_name(),
+        clean(receiver),
+        clean(&comment),
+        ip,
+        clean(sender),
+        clean(helo),
+        clean(receiver),
+        identity
+    )
+}
+
+/// Parse the client address Postfix passed, ignoring `unknown` and junk.
+pub fn parse_client_address(value: &str) -> Option<IpAddr> {
+    let value = value.trim().trim_start_matches("IPv6:");
+    match value.parse() {
+        Ok(ip) => Some(ip),
+        Err(_) => {
+            if !value.is_empty() && value != "unknown" {
+                warn!("[spf] ignoring invalid client address {}", value);
+            }
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct FakeResolver {
+        txt: HashMap<&'static str, &'static str>,
+        addrs: HashMap<&'static str, &'static str>,
+        mx: HashMap<&'static str, &'static str>,
+    }
+
+    impl Resolver for FakeResolver {
+        fn txt(&self, name: &str) -> Result<Vec<String>, String> {
+            if name == "broken.example" {
+                return Err("timeout".to_string());
+            }
+            Ok(self
+                .txt
+                .get(name)
+                .map(|t| vec![t.to_string()])
+                .unwrap_or_default())
+        }
+
+        fn addrs(&self, name: &str) -> Result<Vec<IpAddr>, String> {
+            Ok(self
+                .addrs
+                .get(name)
+                .map(|a| a.split(',').map(|ip| ip.parse().unwrap()).collect())
+                .unwrap_or_default())
+        }
+
+        fn mx(&self, name: &str) -> Result<Vec<String>, String> {
+            Ok(self
+                .mx
+                .get(name)
+                .map(|h| h.split(',').map(String::from).collect())
+                .unwrap_or_default())
+        }
+
+        fn ptr(&self, _: IpAddr) -> Result<Vec<String>, String> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn resolver() -> FakeResolver {
+        FakeResolver {
+            txt: HashMap::from([
+                (
+                    "sender.example",
+                    "v=spf1 mx include:_spf.provider.example ~all",
+                ),
+                (
+                    "_spf.provider.example",
+                    "v=spf1 ip4:203.0.113.0/24 ip6:2001:db8::/32 -all",
+                ),
+                ("strict.example", "v=spf1 a:mail.strict.example -all"),
+                ("redirected.example", "v=spf1 redirect=strict.example"),
+                (
+                    "macro.example",
+                    "v=spf1 exists:%{ir}.%{l1r-}.allow.macro.example -all",
+                ),
+                ("loop.example", "v=spf1 include:loop.example -all"),
+                (
+                    "include-none.example",
+                    "v=spf1 include:nothing.example -all",
+                ),
+            ]),
+            addrs: HashMap::from([
+                ("mx1.sender.example", "198.51.100.10"),
+                ("mail.strict.example", "192.0.2.25"),
+                ("1.2.0.192.alice.allow.macro.example", "127.0.0.2"),
+            ]),
+            mx: HashMap::from([("sender.example", "mx1.sender.example")]),
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn mechanisms_and_qualifiers_decide_the_result() {
+        let r = resolver();
+        let check = |addr: &str, sender: &str| check(ip(addr), sender, "helo.example", &r);
+        assert_eq!(check("198.51.100.10", "a@sender.example"), SpfResult::Pass);
+        assert_eq!(check("203.0.113.77", "a@sender.example"), SpfResult::Pass);
+        assert_eq!(check("2001:db8::1", "a@sender.example"), SpfResult::Pass);
+        assert_eq!(check("192.0.2.99", "a@sender.example"), SpfResult::SoftFail);
+        assert_eq!(check("192.0.2.25", "a@strict.example"), SpfResult::Pass);
+        assert_eq!(check("192.0.2.26", "a@strict.example"), SpfResult::Fail);
+        assert_eq!(check("192.0.2.26", "a@redirected.example"), SpfResult::Fail);
+        assert_eq!(
+            check("192.0.2.26", "a@unpublished.example"),
+            SpfResult::None
+        );
+        assert_eq!(
+            check("192.0.2.26", "a@broken.example"),
+            SpfResult::TempError
+        );
+    }
+
+    #[test]
+    fn macros_limits_and_null_sender() {
+        let r = resolver();
+        assert_eq!(
+            check(ip("192.0.2.1"), "alice-smith@macro.example", "h", &r),
+            SpfResult::Pass
+        );
+        assert_eq!(
+            check(ip("192.0.2.2"), "alice@macro.example", "h", &r),
+            SpfResult::Fail
+        );
+        // Recursion runs into the DNS lookup limit.
+        assert_eq!(
+            check(ip("192.0.2.1"), "a@loop.example", "h", &r),
+            SpfResult::PermError
+        );
+        assert_eq!(
+            check(ip("192.0.2.1"), "a@include-none.example", "h", &r),
+            SpfResult::PermError
+        );
+        // Bounces are checked against the HELO name.
+        assert_eq!(
+            check(ip("192.0.2.25"), "", "strict.example", &r),
+            SpfResult::Pass
+        );
+    }
+
+    #[test]
+    fn header_and_dns_output_parsing() {
+        let header = received_spf_header(
+            SpfResult::SoftFail,
+            "mx.example.net",
+            ip("192.0.2.99"),
+            "a@sender.example",
+            "out.sender.example",
+        );
+        assert_eq!(
+            header,
+            "Received-SPF: SoftFail (mx.example.net: domain of a@sender.example does not designate 192.0.2.99 as permitted sender) client-ip=192.0.2.99; envelope-from=\"a@sender.example\"; helo=out.sender.example; receiver=mx.example.net; identity=mailfrom;"
+        );
+        let txt = "example.com\ttext = \"v=spf1 ip4:192.0.2.0/24 \" \"-all\"\nexample.com\ttext = \"google-site-verification=x\"\n";
+        assert_eq!(
+            parse_nslookup_txt(txt),
+            vec!["v=spf1 ip4:192.0.2.0/24 -all", "google-site-verification=x"]
+        );
+        let mx = "example.com\tmail exchanger = 10 mx1.example.com.\n";
+        assert_eq!(parse_nslookup_mx(mx), vec!["mx1.example.com"]);
+        assert_eq!(parse_client_address("unknown"), None);
+        assert_eq!(
+            parse_client_address("IPv6:2001:db8::1"),
+            Some(ip("2001:db8::1"))
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! Helpers shared by unit tests.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// In-memory key/value store standing in for a database table.  Modules
+/// implement their store trait on the instantiation they need.
+pub(crate) struct MemoryStore<K, V> {
+    entries: RefCell<HashMap<K, V>>,
+}
+
+impl<K, V> Default for MemoryStore<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: RefCell::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: Clone> MemoryStore<K, V> {
+    pub fn entry(&self, key: &K) -> Option<V> {
+        self.entries.borrow().get(key).cloned()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.borrow().is_empty()
+    }
+
+    pub fn insert(&self, key: K, value: V) {
+        self.entries.borrow_mut().insert(key, value);
+    }
+
+    /// Insert `value` unless `key` is present; returns whether it was inserted.
+    pub fn insert_new(&self, key: K, value: V) -> bool {
+        let mut entries = self.entries.borrow_mut();
+        if entries.contains_key(&key) {
+            return false;
+        }
+        entries.insert(key, value);
+        true
+    }
+
+    pub fn remove(&self, key: &K) {
+        self.entries.borrow_mut().remove(key);
+    }
+}
@@ -0,0 +1,133 @@
+//! The canonical checks for domain names and mailbox names.
+//!
+//! Domains, accounts, aliases, forwardings, self-registration, the REST API
+//! and CSV import all validate with these before anything reaches the
+//! database, so a typo cannot end up in the generated Postfix and Dovecot
+//! maps.  Both checks expect lowercase input: the maps match addresses
+//! case-insensitively, so a mixed-case entry would only shadow its lowercase
+//! twin.
+
+/// Shown when [`is_valid_domain`] rejects a name.
+pub const DOMAIN_HINT: &str = "Domain names must be lowercase, such as example.com, \
+     with letters, digits and hyphens between the dots and no spaces or trailing dot.";
+/// Shown when [`is_valid_localpart`] rejects a name.
+pub const LOCALPART_HINT: &str = "Mailbox names may contain lowercase letters, digits, \
+     '.', '_' and '-', up to 64 characters, and cannot start or end with a dot or contain '..'.";
+
+/// A fully qualified domain name: two or more dot-separated labels of
+/// lowercase letters, digits and inner hyphens, at most 63 characters each
+/// and 253 in total, with a top-level label that is not all digits.
+pub fn is_valid_domain(name: &str) -> bool {
+    let labels: Vec<&str> = name.split('.').collect();
+    name.len() <= 253
+        && labels.len() >= 2
+        && labels.iter().all(|l| {
+            !l.is_empty()
+                && l.len() <= 63
+                && !l.starts_with('-')
+                && !l.ends_with('-')
+                && l.bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-')
+        })
+        && labels
+            .last()
+            .is_some_and(|tld| !tld.bytes().all(|b| b.is_ascii_digit()))
+}
+
+/// A mailbox name, the part of an address before `@`: lowercase letters,
+/// digits, `.`, `_` and `-`, at most 64 characters, with dots only between
+/// other characters.  `+` is reserved for `user+Folder` addressing.
+pub fn is_valid_localpart(local: &str) -> bool {
+    !local.is_empty()
+        && local.len() <= 64
+        && local.bytes().all(|b| {
+            b.is_ascii_lowercase() || b.is_ascii_digit() || matches!(b, b'.' | b'_' | b'-')
+        })
+        && !local.starts_with('.')
+        && !local.ends_with('.')
+        && !local.contains("..")
+}
+
+/// `local@domain` with a valid mailbox name and domain.
+pub fn is_valid_address(address: &str) -> bool {
+    address
+        .split_once('@')
+        .is_some_and(|(local, domain)| is_valid_localpart(local) && is_valid_domain(domain))
+}
+
+/// An address mail may be redirected to, possibly on another server.  Its
+/// local part follows that server's rules, so it is only checked for
+/// characters that would break the generated maps; the domain must be valid
+/// in any case.
+pub fn is_valid_destination(address: &str) -> bool {
+    address.rsplit_once('@').is_some_and(|(local, domain)| {
+        !local.is_empty()
+            && local.len() <= 64
+            && local.bytes().all(|b| {
+                b.is_ascii_graphic() && !matches!(b, b'@' | b',' | b'"' | b'<' | b'>' | b'\\')
+            })
+            && is_valid_domain(&domain.to_ascii_lowercase())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn domains_must_be_lowercase_fqdns() {
+        for name in ["example.com", "mail-1.example.co.uk", "xn--p1ai.xn--p1ai"] {
+            assert!(is_valid_domain(name), "{:?}", name);
+        }
+        for name in [
+            "",
+            "localhost",
+            "example.com.",
+            ".example.com",
+            "example..com",
+            " example.com",
+            "exa mple.com",
+            "Example.com",
+            "-example.com",
+            "example-.com",
+            "example.123",
+            "exa_mple.com",
+            "example.com\n",
+        ] {
+            assert!(!is_valid_domain(name), "{:?}", name);
+        }
+        assert!(!is_valid_domain(&format!("{}.com", "a".repeat(64))));
+    }
+
+    #[test]
+    fn localparts_reject_reserved_characters() {
+        for local in ["jane", "jane.doe", "j_doe-1", "0"] {
+            assert!(is_valid_localpart(local), "{:?}", local);
+        }
+        for local in [
+            "",
+            "Jane",
+            "jane doe",
+            "jane ",
+            ".jane",
+            "jane.",
+            "ja..ne",
+            "jane+news",
+            "jane@x",
+            "ja:ne",
+            "ja/ne",
+            "*",
+        ] {
+            assert!(!is_valid_localpart(local), "{:?}", local);
+        }
+        assert!(!is_valid_localpart(&"a".repeat(65)));
+        assert!(is_valid_address("jane.doe@example.com"));
+        assert!(!is_valid_address("jane.doe@example.com."));
+        assert!(!is_valid_address("jane@doe@example.com"));
+        assert!(!is_valid_address("example.com"));
+        assert!(is_valid_destination("John.Smith+list@Example.NET"));
+        assert!(!is_valid_destination("john smith@example.net"));
+        assert!(!is_valid_destination("a,b@example.net"));
+        assert!(!is_valid_destination("@example.net"));
+    }
+}
@@ -1,9 +1,13 @@
 use axum::{
-    extract::{FromRef, FromRequestParts},
-    http::{header, request::Parts, StatusCode},
+    extract::{ConnectInfo, FromRef, FromRequestParts},
+    http::{header, request::Parts, HeaderMap, StatusCode},
     response::Response,
 };
+use ipnetwork::IpNetwork;
 use log::{debug, error, info, warn};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
 
 use super::AppState;
 use crate::web::errors::render_error_page;
@@ -29,6 +33,282 @@ fn unauthorized() -> Response {
         .expect("Failed to build unauthorized response")
 }
 
+fn too_many_attempts(window_minutes: i32) -> Response {
+    let body = render_error_page(
+        StatusCode::TOO_MANY_REQUESTS,
+        "Too Many Login Attempts",
+        "Too many failed logins. Wait a few minutes before trying again.",
+        "/",
+        "Dashboard",
+    );
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(
+            header::RETRY_AFTER,
+            (window_minutes.max(1) * 60).to_string(),
+        )
+        .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(axum::body::Body::from(body.0))
+        .expect("Failed to build rate-limit response")
+}
+
+/// fail2ban service name that admin login attempts are logged under.
+const LOGIN_SERVICE: &str = "admin";
+
+/// Used when the `admin` row is missing from `fail2ban_settings`.
+const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+const DEFAULT_FIND_TIME_MINUTES: i32 = 10;
+const DEFAULT_BAN_MINUTES: i32 = 60;
+
+/// Proxies whose `X-Forwarded-For` and `X-Real-IP` headers are believed:
+/// the comma-separated addresses or CIDR ranges in `TRUSTED_PROXIES`.
+fn trusted_proxies() -> &'static [IpNetwork] {
+    static TRUSTED_PROXIES: OnceLock<Vec<IpNetwork>> = OnceLock::new();
+    TRUSTED_PROXIES.get_or_init(|| {
+        std::env::var("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let net = crate::fail2ban::parse_ip_or_cidr(entry);
+                if net.is_none() {
+                    warn!("[web] ignoring invalid TRUSTED_PROXIES entry {:?}", entry);
+                }
+                net
+            })
+            .collect()
+    })
+}
+
+/// Source address of the request.  This is the peer address unless the
+/// peer is a trusted proxy (see [`trusted_proxies`]); then it is the
+/// nearest `X-Forwarded-For` hop that is not one of our proxies, or
+/// `X-Real-IP`.  Addresses are returned in canonical form, see
+/// [`crate::fail2ban::canonical_ip`].
+pub(crate) fn client_ip(headers: &HeaderMap, peer: Option<IpAddr>) -> String {
+    client_ip_behind(headers, peer, trusted_proxies())
+}
+
+fn client_ip_behind(headers: &HeaderMap, peer: Option<IpAddr>, trusted: &[IpNetwork]) -> String {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+    let Some(peer) = peer.and_then(|ip| crate::fail2ban::canonical_ip(&ip.to_string())) else {
+        return "unknown".to_string();
+    };
+    if !is_trusted(peer) {
+        if headers.contains_key("x-forwarded-for") || headers.contains_key("x-real-ip") {
+            warn_untrusted_forwarding(peer);
+        }
+        return peer.to_string();
+    }
+    let forwarded: Vec<IpAddr> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(crate::fail2ban::canonical_ip)
+        .collect();
+    forwarded
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(**ip))
+        .copied()
+        .or_else(|| {
+            headers
+                .get("x-real-ip")
+                .and_then(|v| v.to_str().ok())
+                .and_then(crate::fail2ban::canonical_ip)
+        })
+        .or_else(|| forwarded.first().copied())
+        .unwrap_or(peer)
+        .to_string()
+}
+
+/// Warn, once per process, that forwarding headers from `peer` are ignored.
+/// Behind a proxy missing from `TRUSTED_PROXIES`, every client appears as the
+/// proxy's address.
+fn warn_untrusted_forwarding(peer: IpAddr) {
+    static WARNED: AtomicBool = AtomicBool::new(false);
+    if !WARNED.swap(true, Ordering::Relaxed) {
+        warn!(
+            "[web] ignoring X-Forwarded-For/X-Real-IP from untrusted peer {}; list the reverse proxy in TRUSTED_PROXIES (logged once)",
+            peer
+        );
+    }
+}
+
+/// Whether failed logins from `ip` may ban it.  Without trusted proxies a
+/// loopback or private address is most likely a reverse proxy that every
+/// client shares, and banning it would lock everyone out.
+fn may_ban(ip: &str, trusted: &[IpNetwork]) -> bool {
+    if !trusted.is_empty() {
+        return true;
+    }
+    match ip.parse::<IpAddr>() {
+        Ok(IpAddr::V4(v4)) => !(v4.is_loopback() || v4.is_private()),
+        Ok(IpAddr::V6(v6)) => !(v6.is_loopback() || v6.is_unique_local()),
+        Err(_) => false,
+    }
+}
+
+/// The username is kept in the `details` column so attempts can be counted
+/// per account as well as per source IP.
+fn attempt_details(username: &str) -> String {
+    format!("username={}", username)
+}
+
+/// Failed-login counters for one request, read from `fail2ban_log`.
+struct LoginThrottle {
+    enabled: bool,
+    max_attempts: i32,
+    window_minutes: i32,
+    ban_minutes: i32,
+    ip_attempts: i64,
+    user_attempts: i64,
+    ban_enabled: bool,
+}
+
+impl LoginThrottle {
+    fn load(db: &crate::db::Database, ip: &str, username: &str) -> Self {
+        let setting = db.get_fail2ban_setting_by_service(LOGIN_SERVICE);
+        let window_minutes = setting
+            .as_ref()
+            .map(|s| s.find_time_minutes)
+            .unwrap_or(DEFAULT_FIND_TIME_MINUTES);
+        LoginThrottle {
+            enabled: setting.as_ref().map(|s| s.enabled).unwrap_or(true),
+            max_attempts: setting
+                .as_ref()
+                .map(|s| s.max_attempts)
+                .unwrap_or(DEFAULT_MAX_ATTEMPTS),
+            window_minutes,
+            ban_minutes: setting
+                .as_ref()
+                .map(|s| s.ban_duration_minutes)
+                .unwrap_or(DEFAULT_BAN_MINUTES),
+            ip_attempts: db.count_recent_attempts(ip, LOGIN_SERVICE, window_minutes),
+            user_attempts: db.count_recent_attempts_with_details(
+                LOGIN_SERVICE,
+                &attempt_details(username),
+                window_minutes,
+            ),
+            ban_enabled: db.is_fail2ban_enabled(),
+        }
+    }
+
+    fn is_blocked(&self) -> bool {
+        self.enabled && self.ip_attempts.max(self.user_attempts) >= self.max_attempts as i64
+    }
+
+    fn has_attempts(&self) -> bool {
+        self.ip_attempts > 0 || self.user_attempts > 0
+    }
+
+    /// Log a failed login and ban the source IP once it reaches the limit.
+    fn record_failure(&self, db: &crate::db::Database, ip: &str, username: &str) {
+        db.record_fail2ban_attempt(ip, LOGIN_SERVICE, &attempt_details(username));
+        if self.enabled && self.ban_enabled && self.ip_attempts + 1 >= self.max_attempts as i64 {
+            let reason = format!(
+                "{} failed admin logins within {} minutes",
+                self.ip_attempts + 1,
+                self.window_minutes
+            );
+            if !may_ban(ip, trusted_proxies()) {
+                warn!(
+                    "[web] not banning ip={} ({}): it may be a reverse proxy, set TRUSTED_PROXIES",
+                    ip, reason
+                );
+                return;
+            }
+            match db.ban_ip(ip, LOGIN_SERVICE, &reason, self.ban_minutes, false) {
+                Ok(_) => crate::firewall::block(db, ip),
+                Err(e) => error!("[web] failed to ban ip={} after failed logins: {}", ip, e),
+            }
+        }
+    }
+}
+
+/// fail2ban service name that wrong or reused TOTP codes are logged under.
+const TOTP_SERVICE: &str = "totp";
+
+/// Used when the `totp` row is missing from `fail2ban_settings`.
+const DEFAULT_TOTP_MAX_ATTEMPTS: i32 = 5;
+const DEFAULT_TOTP_LOCK_MINUTES: i32 = 15;
+
+/// Result of checking a TOTP code with [`check_totp`].
+pub(crate) enum TotpCheck {
+    Valid,
+    /// Wrong, expired or already used; callers do not say which.
+    Invalid,
+    /// Too many bad codes; verification is refused for this many minutes.
+    Locked(i32),
+}
+
+/// Minutes TOTP verification stays locked for `username` after too many bad
+/// codes, or `None` when it is open.
+pub(crate) fn totp_lock(db: &crate::db::Database, username: &str) -> Option<i32> {
+    let setting = db.get_fail2ban_setting_by_service(TOTP_SERVICE);
+    let enabled = setting.as_ref().map(|s| s.enabled).unwrap_or(true);
+    let max_attempts = setting
+        .as_ref()
+        .map(|s| s.max_attempts)
+        .unwrap_or(DEFAULT_TOTP_MAX_ATTEMPTS);
+    let minutes = setting
+        .as_ref()
+        .map(|s| s.find_time_minutes)
+        .unwrap_or(DEFAULT_TOTP_LOCK_MINUTES);
+    let attempts =
+        db.count_recent_attempts_with_details(TOTP_SERVICE, &attempt_details(username), minutes);
+    (enabled && attempts >= max_attempts as i64).then_some(minutes)
+}
+
+/// Check `code` against `secret` for the admin `admin_id` signing in from
+/// `ip`.  A valid code claims its time step, so it cannot be used again
+/// from another client or once a newer code was used; wrong and reused
+/// codes count towards the lock.
+pub(crate) fn check_totp(
+    db: &crate::db::Database,
+    admin_id: i64,
+    username: &str,
+    secret: &str,
+    code: &str,
+    ip: &str,
+) -> TotpCheck {
+    if let Some(minutes) = totp_lock(db, username) {
+        warn!(
+            "[web] TOTP verification locked for username={} after too many bad codes",
+            username
+        );
+        return TotpCheck::Locked(minutes);
+    }
+    let details = attempt_details(username);
+    match crate::auth::verify_totp(secret, code) {
+        Some(step) if db.claim_totp_step(admin_id, step, ip) => {
+            db.reset_fail2ban_attempts(ip, TOTP_SERVICE, &details);
+            TotpCheck::Valid
+        }
+        Some(_) => {
+            warn!(
+                "[web] reused TOTP code refused for username={} from ip={}",
+                username, ip
+            );
+            db.record_fail2ban_attempt(ip, TOTP_SERVICE, &details);
+            TotpCheck::Invalid
+        }
+        None => {
+            db.record_fail2ban_attempt(ip, TOTP_SERVICE, &details);
+            TotpCheck::Invalid
+        }
+    }
+}
+
+/// Why a login was refused.
+enum LoginFailure {
+    Invalid,
+    /// TOTP verification is locked for this many minutes.
+    TotpLocked(i32),
+}
+
 #[axum::async_trait]
 impl<S> FromRequestParts<S> for AuthAdmin
 where
@@ -99,61 +379,287 @@ where
 
         debug!("[web] auth attempt for username={}", username);
 
-        let username_for_db = username.to_string();
-        let admin = app_state
-            .blocking_db(move |db| db.get_admin_by_username(&username_for_db))
-            .await
-            .ok_or_else(|| {
-                warn!(
-                    "[web] authentication failed — unknown username={}",
-                    username
-                );
-                unauthorized()
-            })?;
-
-        if admin.totp_enabled {
-            debug!(
-                "[web] TOTP enabled for username={}, verifying password+TOTP",
-                username
+        let ip = client_ip(
+            &parts.headers,
+            parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|c| c.0.ip()),
+        );
+        let throttle = {
+            let (ip, username) = (ip.clone(), username.to_string());
+            app_state
+                .blocking_db(move |db| LoginThrottle::load(db, &ip, &username))
+                .await
+        };
+        if throttle.is_blocked() {
+            warn!(
+                "[web] too many failed logins from ip={} or for username={}",
+                ip, username
             );
-            if password.len() < 6 {
-                warn!(
-                    "[web] authentication failed — password too short for TOTP for username={}",
-                    username
-                );
-                return Err(unauthorized());
-            }
-            let (base_password, totp_code) = password.split_at(password.len() - 6);
-            if !crate::auth::verify_password(base_password, &admin.password_hash) {
-                warn!(
-                    "[web] authentication failed — wrong password for username={}",
-                    username
-                );
-                return Err(unauthorized());
-            }
-            let secret = admin.totp_secret.as_deref().ok_or_else(|| {
-                error!(
-                    "[web] TOTP enabled but no secret stored for username={}",
-                    username
-                );
-                unauthorized()
-            })?;
-            if !crate::auth::verify_totp(secret, totp_code) {
-                warn!(
-                    "[web] authentication failed — invalid TOTP code for username={}",
-                    username
-                );
+            return Err(too_many_attempts(throttle.window_minutes));
+        }
+
+        let admin = match check_credentials(&app_state, username, password, &ip).await {
+            Ok(admin) => admin,
+            Err(LoginFailure::TotpLocked(minutes)) => return Err(too_many_attempts(minutes)),
+            Err(LoginFailure::Invalid) => {
+                let (ip, username) = (ip.clone(), username.to_string());
+                app_state
+                    .blocking_db(move |db| throttle.record_failure(db, &ip, &username))
+                    .await;
                 return Err(unauthorized());
             }
-        } else if !crate::auth::verify_password(password, &admin.password_hash) {
+        };
+
+        if throttle.has_attempts() {
+            let (ip, username) = (ip.clone(), username.to_string());
+            app_state
+                .blocking_db(move |db| {
+                    db.reset_fail2ban_attempts(&ip, LOGIN_SERVICE, &attempt_details(&username))
+                })
+                .await;
+        }
+
+        info!("[web] authentication succeeded for username={}", username);
+        Ok(AuthAdmin { admin })
+    }
+}
+
+/// How long a redeemed recovery code keeps working.  Basic auth repeats the
+/// credentials on every request, so a strictly single-use code would only
+/// ever load one page.
+const RECOVERY_CODE_GRACE_MINUTES: i64 = 10;
+
+/// Password followed by the current six-digit TOTP code.
+async fn totp_login(
+    state: &AppState,
+    admin: &crate::db::Admin,
+    username: &str,
+    password: &str,
+    ip: &str,
+) -> Result<(), LoginFailure> {
+    let Some(at) = password
+        .len()
+        .checked_sub(6)
+        .filter(|&at| password.is_char_boundary(at))
+    else {
+        warn!(
+            "[web] authentication failed — password too short for TOTP for username={}",
+            username
+        );
+        return Err(LoginFailure::Invalid);
+    };
+    let (base_password, totp_code) = password.split_at(at);
+    if !crate::auth::verify_password(base_password, &admin.password_hash) {
+        warn!(
+            "[web] authentication failed — wrong password for username={}",
+            username
+        );
+        return Err(LoginFailure::Invalid);
+    }
+    let Some(secret) = admin.totp_secret.clone() else {
+        error!(
+            "[web] TOTP enabled but no secret stored for username={}",
+            username
+        );
+        return Err(LoginFailure::Invalid);
+    };
+    let (admin_id, username, code, ip) = (
+        admin.id,
+        username.to_string(),
+        totp_code.to_string(),
+        ip.to_string(),
+    );
+    let check = state
+        .blocking_db(move |db| check_totp(db, admin_id, &username, &secret, &code, &ip))
+        .await;
+    match check {
+        TotpCheck::Valid => Ok(()),
+        TotpCheck::Invalid => {
             warn!(
-                "[web] authentication failed — wrong password for username={}",
+                "[web] authentication failed — invalid TOTP code for admin id={}",
+                admin_id
+            );
+            Err(LoginFailure::Invalid)
+        }
+        TotpCheck::Locked(minutes) => Err(LoginFailure::TotpLocked(minutes)),
+    }
+}
+
+/// Password followed by one of the admin's recovery codes instead of a TOTP
+/// code.  The code is marked used on success.
+async fn recovery_login_ok(
+    state: &AppState,
+    admin: &crate::db::Admin,
+    username: &str,
+    password: &str,
+) -> bool {
+    let Some((base_password, code)) = crate::auth::split_recovery_code(password) else {
+        return false;
+    };
+    if !crate::auth::verify_password(base_password, &admin.password_hash) {
+        return false;
+    }
+    let (admin_id, code_hash) = (admin.id, crate::auth::hash_recovery_code(code));
+    let redeemed = state
+        .blocking_db(move |db| {
+            db.redeem_recovery_code(admin_id, &code_hash, RECOVERY_CODE_GRACE_MINUTES)
+        })
+        .await;
+    if redeemed {
+        warn!("[web] recovery code used to log in username={}", username);
+    } else {
+        warn!(
+            "[web] authentication failed — invalid or used recovery code for username={}",
+            username
+        );
+    }
+    redeemed
+}
+
+/// Verify a Basic auth username/password pair (with the TOTP code or a
+/// recovery code appended to the password when 2FA is on).  Returns the admin
+/// on success.
+///
+/// While TOTP verification is locked for the admin only a recovery code gets
+/// in; the lock is checked before the password so the answer does not
+/// reveal whether the password was right.
+async fn check_credentials(
+    state: &AppState,
+    username: &str,
+    password: &str,
+    ip: &str,
+) -> Result<crate::db::Admin, LoginFailure> {
+    let username_for_db = username.to_string();
+    let admin = state
+        .blocking_db(move |db| db.get_admin_by_username(&username_for_db))
+        .await
+        .ok_or_else(|| {
+            warn!(
+                "[web] authentication failed — unknown username={}",
                 username
             );
-            return Err(unauthorized());
+            LoginFailure::Invalid
+        })?;
+
+    if admin.totp_enabled {
+        debug!(
+            "[web] TOTP enabled for username={}, verifying password+TOTP",
+            username
+        );
+        let lock_username = username.to_string();
+        let result = match state
+            .blocking_db(move |db| totp_lock(db, &lock_username))
+            .await
+        {
+            Some(minutes) => Err(LoginFailure::TotpLocked(minutes)),
+            None => totp_login(state, &admin, username, password, ip).await,
+        };
+        if let Err(failure) = result {
+            if !recovery_login_ok(state, &admin, username, password).await {
+                return Err(failure);
+            }
         }
+    } else if !crate::auth::verify_password(password, &admin.password_hash) {
+        warn!(
+            "[web] authentication failed — wrong password for username={}",
+            username
+        );
+        return Err(LoginFailure::Invalid);
+    }
 
-        info!("[web] authentication succeeded for username={}", username);
-        Ok(AuthAdmin { admin })
+    Ok(admin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forged_forwarded_headers_from_untrusted_peers_are_ignored() {
+        let peer = Some("192.0.2.7".parse().unwrap());
+        let mut headers = HeaderMap::new();
+        headers.insert("x-forwarded-for", "203.0.113.9".parse().unwrap());
+        headers.insert("x-real-ip", "198.51.100.2".parse().unwrap());
+        assert_eq!(client_ip_behind(&headers, peer, &[]), "192.0.2.7");
+        assert_eq!(client_ip_behind(&headers, None, &[]), "unknown");
+        assert_eq!(
+            client_ip_behind(
+                &HeaderMap::new(),
+                Some("::ffff:192.0.2.7".parse().unwrap()),
+                &[]
+            ),
+            "192.0.2.7"
+        );
+
+        // Behind our own proxies, the nearest hop they did not add counts;
+        // whatever the client put in front of it is ignored.
+        let proxies = ["10.0.0.0/8".parse().unwrap()];
+        let proxy = Some("10.0.0.1".parse().unwrap());
+        assert_eq!(client_ip_behind(&headers, proxy, &proxies), "203.0.113.9");
+        headers.insert(
+            "x-forwarded-for",
+            "192.0.2.1, [2001:DB8:0:0::1]:443, 10.0.0.2"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(client_ip_behind(&headers, proxy, &proxies), "2001:db8::1");
+        headers.remove("x-forwarded-for");
+        assert_eq!(client_ip_behind(&headers, proxy, &proxies), "198.51.100.2");
+        assert_eq!(
+            client_ip_behind(&HeaderMap::new(), proxy, &proxies),
+            "10.0.0.1"
+        );
+    }
+
+    #[test]
+    fn private_peers_are_not_banned_without_trusted_proxies() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "192.168.0.5",
+            "::1",
+            "fd00::1",
+            "unknown",
+        ] {
+            assert!(!may_ban(ip, &[]), "{}", ip);
+        }
+        assert!(may_ban("203.0.113.9", &[]));
+        assert!(may_ban("2001:db8::1", &[]));
+
+        let proxies = ["10.0.0.1/32".parse().unwrap()];
+        assert!(may_ban("10.1.2.3", &proxies));
+    }
+
+    #[test]
+    fn throttle_blocks_on_ip_or_username_count() {
+        let throttle = LoginThrottle {
+            enabled: true,
+            max_attempts: 3,
+            window_minutes: 5,
+            ban_minutes: 120,
+            ip_attempts: 0,
+            user_attempts: 0,
+            ban_enabled: false,
+        };
+        assert!(!throttle.is_blocked());
+        assert!(!throttle.has_attempts());
+        assert!(LoginThrottle {
+            ip_attempts: 3,
+            ..throttle
+        }
+        .is_blocked());
+        assert!(LoginThrottle {
+            user_attempts: 4,
+            ..throttle
+        }
+        .is_blocked());
+        assert!(!LoginThrottle {
+            enabled: false,
+            user_attempts: 4,
+            ..throttle
+        }
+        .is_blocked());
     }
 }
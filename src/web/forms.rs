@@ -19,9 +19,33 @@ pub struct DomainEditForm {
     #[serde(default)]
     pub unsubscribe_enabled: Option<String>,
     #[serde(default)]
-    pub registration_enabled: Option<String>,
+    pub quiet_hours_start: String,
     #[serde(default)]
-    pub registration_username_regex: String,
+    pub quiet_hours_end: String,
+    #[serde(default)]
+    pub quiet_hours_timezone: String,
+    #[serde(default)]
+    pub helo_name: String,
+    #[serde(default)]
+    pub dmarc_reports_enabled: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RegistrationSettingsForm {
+    #[serde(default)]
+    pub enabled: Option<String>,
+    #[serde(default)]
+    pub username_regex: String,
+    #[serde(default)]
+    pub min_password_length: String,
+    #[serde(default)]
+    pub invite_only: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RegistrationInviteForm {
+    #[serde(default)]
+    pub note: String,
 }
 
 #[derive(Deserialize)]
@@ -45,6 +69,30 @@ pub struct AccountEditForm {
     pub active: Option<String>,
     #[serde(default)]
     pub quota: Option<i64>,
+    #[serde(default)]
+    pub disabled_policy: String,
+    #[serde(default)]
+    pub send_limit_messages: String,
+    #[serde(default)]
+    pub send_limit_recipients: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryRuleForm {
+    pub match_field: String,
+    pub match_op: String,
+    #[serde(default)]
+    pub pattern: String,
+    pub action: String,
+    #[serde(default)]
+    pub target_folder: String,
+    #[serde(default)]
+    pub header: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryRuleMoveForm {
+    pub direction: String,
 }
 
 #[derive(Deserialize)]
@@ -53,6 +101,12 @@ pub struct AliasForm {
     pub destination: String,
 }
 
+#[derive(Deserialize)]
+pub struct AliasResolveQuery {
+    #[serde(default)]
+    pub address: String,
+}
+
 #[derive(Deserialize)]
 pub struct AliasEditForm {
     pub source: String,
@@ -92,15 +146,45 @@ pub struct TotpEnableForm {
     pub code: String,
 }
 
+#[derive(Deserialize)]
+pub struct ApiKeyForm {
+    pub name: String,
+    #[serde(default)]
+    pub read_write: Option<String>,
+}
+
 #[derive(Deserialize)]
 pub struct PixelQuery {
     #[serde(default)]
     pub id: String,
 }
 
+/// Optional `YYYY-MM-DD` bounds of a tracking export, both inclusive.
+#[derive(Deserialize)]
+pub struct TrackingExportQuery {
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub to: String,
+}
+
 #[derive(Deserialize)]
 pub struct PixelSettingsForm {
     pub pixel_base_url: String,
+    #[serde(default)]
+    pub pixel_position: String,
+    #[serde(default)]
+    pub pixel_id_prefix: Option<String>,
+    #[serde(default)]
+    pub pixel_id_allowlist: Option<String>,
+    #[serde(default)]
+    pub pixel_id_validation: String,
+    #[serde(default)]
+    pub pixel_per_recipient: Option<String>,
+    #[serde(default)]
+    pub strip_pixels_on_reply: Option<String>,
+    #[serde(default)]
+    pub pixel_dedup_window_seconds: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -132,6 +216,27 @@ pub struct Fail2banListForm {
     pub description: String,
 }
 
+#[derive(Deserialize)]
+pub struct Fail2banRuleForm {
+    pub name: String,
+    pub service: String,
+    pub regex: String,
+}
+
+#[derive(Deserialize)]
+pub struct Fail2banFirewallForm {
+    #[serde(default)]
+    pub backend: String,
+    #[serde(default)]
+    pub add_cmd: String,
+    #[serde(default)]
+    pub del_cmd: String,
+    #[serde(default)]
+    pub flush_cmd: String,
+    #[serde(default)]
+    pub list_cmd: String,
+}
+
 #[derive(Deserialize)]
 pub struct Fail2banGlobalToggleForm {
     #[serde(default)]
@@ -151,10 +256,30 @@ pub struct UnsubscribeQuery {
     pub token: String,
 }
 
+/// Body of a POST to `/unsubscribe`.  One-click requests from mail clients
+/// send `List-Unsubscribe=One-Click` and no `action`.
+#[derive(Deserialize)]
+pub struct UnsubscribeForm {
+    #[serde(default)]
+    pub action: String,
+}
+
 #[derive(Deserialize)]
 pub struct WebhookSettingsForm {
     #[serde(default)]
     pub webhook_url: String,
+    #[serde(default)]
+    pub webhook_secret: String,
+}
+
+#[derive(Deserialize)]
+pub struct TrackingKillSwitchForm {
+    pub enabled: String,
+}
+
+#[derive(Deserialize)]
+pub struct MaintenanceModeForm {
+    pub enabled: String,
 }
 
 #[derive(Deserialize)]
@@ -166,6 +291,84 @@ pub struct FeatureToggleForm {
 #[derive(Deserialize)]
 pub struct MailSettingsForm {
     pub message_size_limit: u64,
+    #[serde(default)]
+    pub subscribed_folders: Option<String>,
+    #[serde(default)]
+    pub filter_cache_ttl_seconds: Option<i64>,
+    #[serde(default)]
+    pub max_message_bytes: Option<u64>,
+    #[serde(default)]
+    pub queue_auto_flush_minutes: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct DeliveryPolicyForm {
+    pub disabled_account_policy: String,
+    #[serde(default)]
+    pub disabled_account_message: String,
+    #[serde(default)]
+    pub disabled_account_forward_to: String,
+    pub over_quota_policy: String,
+}
+
+#[derive(Deserialize)]
+pub struct SenderAuthForm {
+    pub spf_policy: String,
+    pub dkim_policy: String,
+}
+
+#[derive(Deserialize)]
+pub struct GreylistSettingsForm {
+    pub enabled: Option<String>,
+    pub delay_seconds: String,
+    pub lifetime_days: String,
+}
+
+#[derive(Deserialize)]
+pub struct SendLimitsForm {
+    pub messages: String,
+    pub recipients: String,
+    pub window_minutes: String,
+}
+
+#[derive(Deserialize)]
+pub struct ApprovalSettingsForm {
+    pub enabled: Option<String>,
+    pub ttl_hours: String,
+}
+
+#[derive(Deserialize)]
+pub struct PublicIpForm {
+    pub public_ip_method: String,
+}
+
+#[derive(Deserialize)]
+pub struct DkimSettingsForm {
+    pub header_canonicalization: String,
+    pub body_canonicalization: String,
+    #[serde(default)]
+    pub sign_headers: String,
+    #[serde(default)]
+    pub rotation_grace_days: String,
+}
+
+/// Column and direction of the queue table.
+#[derive(Deserialize, Default)]
+pub struct QueueSortQuery {
+    #[serde(default)]
+    pub sort: String,
+    #[serde(default)]
+    pub desc: bool,
+}
+
+#[derive(Deserialize)]
+pub struct QueueSelectForm {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub sender: String,
+    #[serde(default)]
+    pub recipient: String,
 }
 
 #[derive(Deserialize)]
@@ -180,6 +383,10 @@ pub struct RelayForm {
     pub username: Option<String>,
     #[serde(default)]
     pub password: Option<String>,
+    #[serde(default)]
+    pub tls_mode: String,
+    #[serde(default)]
+    pub priority: Option<i32>,
 }
 
 #[derive(Deserialize)]
@@ -195,15 +402,32 @@ pub struct RelayEditForm {
     #[serde(default)]
     pub password: Option<String>,
     #[serde(default)]
+    pub tls_mode: String,
+    #[serde(default)]
+    pub priority: Option<i32>,
+    #[serde(default)]
     pub active: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct RelayTestForm {
+    pub to: String,
+    #[serde(default)]
+    pub from: String,
+}
+
 #[derive(Deserialize)]
 pub struct RelayAssignmentForm {
     pub assignment_type: String,
     pub pattern: String,
 }
 
+#[derive(Deserialize)]
+pub struct RelayPolicyForm {
+    #[serde(default)]
+    pub relay_accepted_domains: String,
+}
+
 #[derive(Deserialize)]
 pub struct WebDavSettingsForm {
     #[serde(default)]
@@ -212,6 +436,16 @@ pub struct WebDavSettingsForm {
     pub webdav_max_file_size_mb: Option<i64>,
     #[serde(default)]
     pub webdav_quota_mb: Option<i64>,
+    #[serde(default)]
+    pub webdav_total_quota_mb: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct WebDavQuotaForm {
+    pub owner: String,
+    /// Empty returns the user to the default quota.
+    #[serde(default)]
+    pub quota_mb: String,
 }
 
 #[derive(Deserialize)]
@@ -258,3 +492,15 @@ pub struct FooterContentForm {
     #[serde(default)]
     pub footer_html: String,
 }
+
+#[derive(Deserialize)]
+pub struct ComplianceFooterForm {
+    pub enabled: Option<String>,
+    #[serde(default)]
+    pub html: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub position: String,
+    pub skip_internal: Option<String>,
+}
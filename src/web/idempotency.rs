@@ -0,0 +1,379 @@
+//! `Idempotency-Key` support for the JSON API create endpoints.
+//!
+//! A client that retries a POST (for example after a timeout) can send the
+//! same `Idempotency-Key` header again.  The key, scoped to the caller's
+//! credentials, is reserved before the create runs, so a concurrent retry
+//! cannot create a second entity; once the create finishes its response is
+//! stored and any retry within [`IDEMPOTENCY_WINDOW_HOURS`] replays it.  A key
+//! reused with a different request body is rejected with 422.
+
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{debug, info, warn};
+use serde::Serialize;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+/// Request header carrying the client-chosen idempotency key.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// How long a processed key is remembered.
+pub const IDEMPOTENCY_WINDOW_HOURS: i64 = 24;
+/// How long a reservation may wait for its response before a retry may run
+/// the request again.
+const PENDING_TIMEOUT_MINUTES: i64 = 10;
+/// Longest accepted idempotency key.
+const MAX_KEY_LEN: usize = 255;
+
+/// A JSON response that can be recorded and replayed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub body: serde_json::Value,
+}
+
+impl StoredResponse {
+    pub fn new(status: StatusCode, body: serde_json::Value) -> Self {
+        Self { status, body }
+    }
+}
+
+impl IntoResponse for StoredResponse {
+    fn into_response(self) -> Response {
+        (self.status, Json(self.body)).into_response()
+    }
+}
+
+/// A reserved idempotency key as held by the store.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StoredEntry {
+    pub request_hash: String,
+    /// `None` while the first request is still running.
+    pub response: Option<StoredResponse>,
+}
+
+/// Storage backend for idempotency keys.
+pub trait IdempotencyStore {
+    /// Claim `key` for a new request.  Returns `Some(false)` when the key is
+    /// already taken, and `None` when the store is unavailable.
+    fn reserve(&self, key: &IdempotencyKey) -> Option<bool>;
+    fn lookup(&self, key: &IdempotencyKey) -> Option<StoredEntry>;
+    /// Record the response of the request that reserved `key`.
+    fn complete(&self, key: &IdempotencyKey, response: &StoredResponse);
+    /// Drop the reservation so that a retry runs the request again.
+    fn release(&self, key: &IdempotencyKey);
+}
+
+impl IdempotencyStore for crate::db::Database {
+    fn reserve(&self, key: &IdempotencyKey) -> Option<bool> {
+        self.reserve_idempotency_key(
+            &key.token,
+            &key.key,
+            &key.request_hash,
+            IDEMPOTENCY_WINDOW_HOURS,
+            PENDING_TIMEOUT_MINUTES,
+        )
+    }
+
+    fn lookup(&self, key: &IdempotencyKey) -> Option<StoredEntry> {
+        let (request_hash, status, body) = self.get_idempotent_response(&key.token, &key.key)?;
+        let response = match (status, body) {
+            (Some(status), Some(body)) => Some(StoredResponse {
+                status: StatusCode::from_u16(status as u16).ok()?,
+                body: serde_json::from_str(&body).ok()?,
+            }),
+            _ => None,
+        };
+        Some(StoredEntry {
+            request_hash,
+            response,
+        })
+    }
+
+    fn complete(&self, key: &IdempotencyKey, response: &StoredResponse) {
+        self.store_idempotent_response(
+            &key.token,
+            &key.key,
+            response.status.as_u16() as i32,
+            &response.body.to_string(),
+        );
+    }
+
+    fn release(&self, key: &IdempotencyKey) {
+        self.release_idempotency_key(&key.token, &key.key);
+    }
+}
+
+/// Caller scope and key under which a create request is deduplicated.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdempotencyKey {
+    /// SHA-256 of the `Authorization` header, so keys from different
+    /// credentials never collide and no secret is stored in the database.
+    pub token: String,
+    pub key: String,
+    /// SHA-256 of the `Authorization` header and the JSON request body.  The
+    /// credentials are mixed in so that a stored hash cannot be used to guess
+    /// secrets in the body, such as a new account's password.
+    pub request_hash: String,
+}
+
+/// Extract the idempotency key for a request with the given `body`.
+///
+/// Returns `Ok(None)` when the header is absent, and `Err` with a message
+/// suitable for a 400 response when it is present but unusable.
+pub fn request_key<B: Serialize>(
+    headers: &HeaderMap,
+    body: &B,
+) -> Result<Option<IdempotencyKey>, &'static str> {
+    let raw = match headers.get(IDEMPOTENCY_KEY_HEADER) {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+    let key = raw
+        .to_str()
+        .map_err(|_| "Idempotency-Key must be visible ASCII")?
+        .trim();
+    if key.is_empty() {
+        return Err("Idempotency-Key must not be empty");
+    }
+    if key.len() > MAX_KEY_LEN {
+        return Err("Idempotency-Key is too long (max 255 characters)");
+    }
+    let auth = headers
+        .get(header::AUTHORIZATION)
+        .map(|v| v.as_bytes())
+        .unwrap_or_default();
+    let body = serde_json::to_vec(body).unwrap_or_default();
+    let request_hash = Sha256::new()
+        .chain_update(auth)
+        .chain_update(b"\n")
+        .chain_update(&body)
+        .finalize();
+    Ok(Some(IdempotencyKey {
+        token: hex::encode(Sha256::digest(auth)),
+        key: key.to_string(),
+        request_hash: hex::encode(request_hash),
+    }))
+}
+
+/// Run `create` at most once per idempotency key.
+///
+/// Without a key the operation always runs.  With a key, `create` runs only
+/// if the key could be reserved, and its response is stored.  Otherwise the
+/// stored response is replayed, or 409 is returned while the first request
+/// is still running.  Server errors (5xx) release the key so that a retry can
+/// still succeed.
+pub fn run<S, F>(store: &S, key: Option<&IdempotencyKey>, create: F) -> StoredResponse
+where
+    S: IdempotencyStore,
+    F: FnOnce() -> StoredResponse,
+{
+    let key = match key {
+        Some(k) => k,
+        None => return create(),
+    };
+    match store.reserve(key) {
+        Some(true) => {}
+        Some(false) => return replay(store, key),
+        None => {
+            warn!("[api] could not reserve idempotency key={}", key.key);
+            return StoredResponse::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                json!({"error": "service unavailable"}),
+            );
+        }
+    }
+    let response = create();
+    if response.status.is_server_error() {
+        warn!(
+            "[api] not storing idempotency key={} for server error status={}",
+            key.key, response.status
+        );
+        store.release(key);
+    } else {
+        debug!("[api] storing response for idempotency key={}", key.key);
+        store.complete(key, &response);
+    }
+    response
+}
+
+/// The response for a key that is already reserved.
+fn replay<S: IdempotencyStore>(store: &S, key: &IdempotencyKey) -> StoredResponse {
+    match store.lookup(key) {
+        Some(entry) if entry.request_hash != key.request_hash => {
+            warn!(
+                "[api] idempotency key={} reused with a different request body",
+                key.key
+            );
+            StoredResponse::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                json!({"error": "Idempotency-Key was already used with a different request"}),
+            )
+        }
+        Some(StoredEntry {
+            response: Some(previous),
+            ..
+        }) => {
+            info!(
+                "[api] replaying stored response for idempotency key={} status={}",
+                key.key, previous.status
+            );
+            previous
+        }
+        _ => StoredResponse::new(
+            StatusCode::CONFLICT,
+            json!({"error": "a request with this Idempotency-Key is still in progress"}),
+        ),
+    }
+}
+
+/// In-memory [`IdempotencyStore`] for tests.
+#[cfg(test)]
+pub(crate) type MemoryStore = crate::test_support::MemoryStore<(String, String), StoredEntry>;
+
+#[cfg(test)]
+impl IdempotencyStore for MemoryStore {
+    fn reserve(&self, key: &IdempotencyKey) -> Option<bool> {
+        let entry = StoredEntry {
+            request_hash: key.request_hash.clone(),
+            response: None,
+        };
+        Some(self.insert_new((key.token.clone(), key.key.clone()), entry))
+    }
+
+    fn lookup(&self, key: &IdempotencyKey) -> Option<StoredEntry> {
+        self.entry(&(key.token.clone(), key.key.clone()))
+    }
+
+    fn complete(&self, key: &IdempotencyKey, response: &StoredResponse) {
+        let entry = StoredEntry {
+            request_hash: key.request_hash.clone(),
+            response: Some(response.clone()),
+        };
+        self.insert((key.token.clone(), key.key.clone()), entry);
+    }
+
+    fn release(&self, key: &IdempotencyKey) {
+        self.remove(&(key.token.clone(), key.key.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use serde_json::json;
+    use std::cell::Cell;
+
+    fn headers(auth: &str, key: Option<&str>) -> HeaderMap {
+        let mut h = HeaderMap::new();
+        h.insert(header::AUTHORIZATION, HeaderValue::from_str(auth).unwrap());
+        if let Some(k) = key {
+            h.insert(IDEMPOTENCY_KEY_HEADER, HeaderValue::from_str(k).unwrap());
+        }
+        h
+    }
+
+    fn body() -> serde_json::Value {
+        json!({"to": "bob@example.com"})
+    }
+
+    #[test]
+    fn same_key_creates_one_entity_and_replays_response() {
+        let store = MemoryStore::default();
+        let created = Cell::new(0);
+        let create = || {
+            created.set(created.get() + 1);
+            StoredResponse::new(StatusCode::OK, json!({"status": "sent", "n": created.get()}))
+        };
+        let key = request_key(&headers("Bearer abc", Some("retry-1")), &body()).unwrap();
+
+        let first = run(&store, key.as_ref(), create);
+        let second = run(&store, key.as_ref(), create);
+
+        assert_eq!(created.get(), 1);
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn missing_key_always_creates() {
+        let store = MemoryStore::default();
+        let created = Cell::new(0);
+        let create = || {
+            created.set(created.get() + 1);
+            StoredResponse::new(StatusCode::OK, json!({"status": "sent"}))
+        };
+        run(&store, None, create);
+        run(&store, None, create);
+        assert_eq!(created.get(), 2);
+    }
+
+    #[test]
+    fn server_errors_are_not_stored() {
+        let store = MemoryStore::default();
+        let key = request_key(&headers("Bearer abc", Some("k")), &body()).unwrap();
+        run(&store, key.as_ref(), || {
+            StoredResponse::new(StatusCode::BAD_GATEWAY, json!({"error": "SMTP error"}))
+        });
+        let retried = run(&store, key.as_ref(), || {
+            StoredResponse::new(StatusCode::OK, json!({"status": "sent"}))
+        });
+        assert_eq!(retried.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn reused_key_with_a_different_body_is_rejected() {
+        let store = MemoryStore::default();
+        let h = headers("Bearer abc", Some("k"));
+        let first = request_key(&h, &body()).unwrap();
+        run(&store, first.as_ref(), || {
+            StoredResponse::new(StatusCode::OK, json!({"status": "sent"}))
+        });
+        let other = request_key(&h, &json!({"to": "eve@example.com"})).unwrap();
+        let created = Cell::new(false);
+        let response = run(&store, other.as_ref(), || {
+            created.set(true);
+            StoredResponse::new(StatusCode::OK, json!({"status": "sent"}))
+        });
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(!created.get());
+    }
+
+    #[test]
+    fn concurrent_retry_does_not_run_the_create_again() {
+        let store = MemoryStore::default();
+        let key = request_key(&headers("Bearer abc", Some("k")), &body()).unwrap();
+        let retried = run(&store, key.as_ref(), || {
+            let retry = run(&store, key.as_ref(), || unreachable!("created twice"));
+            StoredResponse::new(StatusCode::OK, json!({"retry": retry.status.as_u16()}))
+        });
+        assert_eq!(retried.body, json!({"retry": 409}));
+    }
+
+    #[test]
+    fn keys_are_scoped_to_credentials() {
+        let a = request_key(&headers("Bearer one", Some("k")), &body())
+            .unwrap()
+            .unwrap();
+        let b = request_key(&headers("Bearer two", Some("k")), &body())
+            .unwrap()
+            .unwrap();
+        assert_ne!(a.token, b.token);
+        assert_eq!(a.key, b.key);
+        assert!(!a.token.contains("one"));
+    }
+
+    #[test]
+    fn absent_header_yields_no_key() {
+        assert_eq!(request_key(&headers("Bearer abc", None), &body()), Ok(None));
+    }
+
+    #[test]
+    fn empty_or_oversized_key_is_rejected() {
+        assert!(request_key(&headers("Bearer abc", Some("  ")), &body()).is_err());
+        let long = "x".repeat(MAX_KEY_LEN + 1);
+        assert!(request_key(&headers("Bearer abc", Some(&long)), &body()).is_err());
+    }
+}
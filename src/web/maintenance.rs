@@ -0,0 +1,104 @@
+//! Maintenance mode: a read-only lock on the admin panel and the
+//! provisioning API while backups or migrations run.
+//!
+//! While the `maintenance_mode` setting is `true`, [`read_only`] answers
+//! every request that could change something with `503 Service
+//! Unavailable`; pages and GET endpoints keep working and show a banner.
+//! Only the toggle on the settings page is let through, so the lock can be
+//! lifted again.  Mail delivery and the public endpoints are not affected.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use axum::{
+    extract::{OriginalUri, Request, State},
+    http::{Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use log::{info, warn};
+
+use crate::db::Database;
+use crate::web::AppState;
+
+pub const MAINTENANCE_MODE_SETTING: &str = "maintenance_mode";
+/// The settings page form that turns maintenance mode on and off.
+pub const TOGGLE_PATH: &str = "/settings/maintenance";
+
+/// The setting as last read, for the banner.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Whether maintenance mode is on, as last read from the database.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Read the setting from `db`, remembering it for [`enabled`].
+pub fn load(db: &Database) -> bool {
+    let on = db
+        .get_setting(MAINTENANCE_MODE_SETTING)
+        .is_some_and(|v| v == "true");
+    ENABLED.store(on, Ordering::Relaxed);
+    on
+}
+
+/// Turn maintenance mode on or off.
+pub fn set(db: &Database, on: bool) {
+    db.set_setting(MAINTENANCE_MODE_SETTING, if on { "true" } else { "false" });
+    ENABLED.store(on, Ordering::Relaxed);
+    info!("[web] maintenance mode {}", if on { "on" } else { "off" });
+}
+
+fn changes_state(method: &Method) -> bool {
+    !matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+/// Refuse requests that change state while maintenance mode is on.
+pub(crate) async fn read_only(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    if !changes_state(req.method()) || req.uri().path() == TOGGLE_PATH {
+        return next.run(req).await;
+    }
+    if !state.blocking_db(load).await {
+        return next.run(req).await;
+    }
+    warn!(
+        "[web] refusing {} {} during maintenance mode",
+        req.method(),
+        req.uri()
+    );
+    let message = "The server is in maintenance mode, so nothing can be changed right now. Try again once maintenance is over; an administrator can turn it off on the Settings page.";
+    // Nested routers see their path without the prefix.
+    let path = req
+        .extensions()
+        .get::<OriginalUri>()
+        .map_or(req.uri().path(), |uri| uri.path());
+    if path.starts_with("/api/") {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(serde_json::json!({"error": "maintenance mode is on; changes are refused"})),
+        )
+            .into_response();
+    }
+    crate::web::errors::status_response(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "Maintenance Mode",
+        message,
+        "/",
+        "Back to Dashboard",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_reads_pass_during_maintenance() {
+        assert!(!changes_state(&Method::GET));
+        assert!(!changes_state(&Method::HEAD));
+        assert!(!changes_state(&Method::OPTIONS));
+        assert!(changes_state(&Method::POST));
+        assert!(changes_state(&Method::DELETE));
+        assert!(changes_state(&Method::PUT));
+    }
+}
@@ -1,6 +1,8 @@
 mod auth;
 mod errors;
 mod forms;
+mod idempotency;
+mod maintenance;
 pub mod routes;
 
 use axum::http::{StatusCode, Uri};
@@ -143,6 +145,12 @@ pub struct AppState {
     pub mcp_guard: Arc<Mutex<McpGuard>>,
     /// Registry of active webmail IMAP-IDLE (SSE) sessions.
     pub idle_registry: ImapIdleRegistry,
+    /// GeoIP database for enriching pixel opens; `None` when not configured.
+    pub geoip: Option<Arc<crate::geoip::GeoIp>>,
+    /// URL notified when a tracked message is opened for the first time.
+    pub open_webhook_url: Option<String>,
+    /// Recent DNS record checks shown on the domain DNS pages.
+    pub dns_check_cache: Arc<Mutex<crate::dnscheck::CheckCache>>,
 }
 
 impl AppState {
@@ -173,15 +181,28 @@ pub async fn start_server(state: AppState) {
 
     info!("[web] initializing admin web server on port {}", port);
 
+    let idle_registry = state.idle_registry.clone();
+    routes::webdav::start_lock_sweeper(state.clone());
+    if state.blocking_db(maintenance::load).await {
+        warn!("[web] maintenance mode is on; admin changes are refused until it is turned off");
+    }
+
     let static_dir = find_static_dir();
 
+    let health_routes = routes::health::routes();
     let pixel_routes = routes::pixel::routes();
     let bimi_routes = routes::bimi::routes();
     let unsubscribe_routes = routes::unsubscribe::public_routes();
     let webdav_routes = routes::webdav::public_routes();
     let registration_routes = routes::registration_routes();
     let jmap_routes = routes::jmap::jmap_routes();
-    let auth_routes = routes::auth_routes();
+    let read_only =
+        axum::middleware::from_fn_with_state(state.clone(), maintenance::read_only);
+    let auth_routes = routes::auth_routes().route_layer(read_only.clone());
+    let api_v1_routes = routes::api_v1::routes(state.clone()).route_layer(read_only);
+    let queue_metrics_routes = routes::queue::metrics_routes(state.clone());
+    let account_usage_routes = routes::accounts::usage_routes(state.clone());
+    let stats_routes = routes::dashboard::stats_routes(state.clone());
 
     let static_routes: Router<AppState> = match static_dir {
         Some(ref dir) => {
@@ -197,6 +218,7 @@ pub async fn start_server(state: AppState) {
     };
 
     let app = Router::new()
+        .merge(health_routes)
         .merge(pixel_routes)
         .merge(bimi_routes)
         .merge(unsubscribe_routes)
@@ -205,6 +227,10 @@ pub async fn start_server(state: AppState) {
         .merge(jmap_routes)
         .merge(static_routes)
         .merge(auth_routes)
+        .nest("/api/v1", api_v1_routes)
+        .merge(queue_metrics_routes)
+        .merge(account_usage_routes)
+        .merge(stats_routes)
         // CalDAV protocol handler — handles all HTTP methods on /caldav/{email}/...
         .route("/caldav/*path", axum::routing::any(routes::caldav::protocol_handler))
         // RFC 6764 well-known redirect for CalDAV auto-discovery
@@ -231,7 +257,48 @@ pub async fn start_server(state: AppState) {
         .await
         .unwrap_or_else(|e| panic!("Failed to bind address {}: {}", addr, e));
     info!("[web] admin dashboard listening on {}", addr);
-    axum::serve(listener, app).await.expect("Server error");
+
+    let stopping = Arc::new(tokio::sync::Notify::new());
+    let stopped = stopping.clone();
+    let server = axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<std::net::SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move {
+        crate::shutdown::signal().await;
+        info!("[web] no longer accepting connections, draining requests");
+        close_idle_sessions(&idle_registry);
+        stopped.notify_one();
+    });
+    tokio::select! {
+        result = server => {
+            result.expect("Server error");
+            info!("[web] all requests finished");
+        }
+        _ = async {
+            stopping.notified().await;
+            tokio::time::sleep(crate::shutdown::DRAIN_TIMEOUT).await;
+        } => {
+            warn!(
+                "[web] requests still running after {}s, closing them",
+                crate::shutdown::DRAIN_TIMEOUT.as_secs()
+            );
+        }
+    }
+}
+
+/// Signal every IDLE polling task to stop so their event streams end and
+/// the server can finish draining.
+fn close_idle_sessions(registry: &ImapIdleRegistry) {
+    let reg = registry.lock().unwrap();
+    for session in reg.values() {
+        session
+            .shutdown
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+    if !reg.is_empty() {
+        info!("[idle] closing {} session(s) for shutdown", reg.len());
+    }
 }
 
 async fn handle_not_found(uri: Uri) -> Response {
@@ -281,7 +348,8 @@ pub(crate) async fn regen_configs(state: &AppState) {
     let hostname = state.hostname.clone();
     let (tx, rx) = tokio::sync::oneshot::channel();
 
-    std::thread::spawn(move || {
+    // Tracked so shutdown waits for it even if the request is dropped.
+    crate::shutdown::spawn("config generation", move || {
         crate::config::generate_all_configs(&db, &hostname);
         let _ = tx.send(());
     });
@@ -289,24 +357,63 @@ pub(crate) async fn regen_configs(state: &AppState) {
     let _ = rx.await;
 }
 
+/// Record an admin mutation in the audit log, attributed to the signed-in admin.
+pub(crate) async fn audit(
+    state: &AppState,
+    auth: &auth::AuthAdmin,
+    action: &str,
+    target_type: &str,
+    target_id: Option<i64>,
+    details: serde_json::Value,
+) {
+    audit_as(
+        state,
+        auth.admin.username.clone(),
+        action,
+        target_type,
+        target_id,
+        details,
+    )
+    .await;
+}
+
+/// Record a mutation in the audit log under an explicit actor name.
+pub(crate) async fn audit_as(
+    state: &AppState,
+    actor: String,
+    action: &str,
+    target_type: &str,
+    target_id: Option<i64>,
+    details: serde_json::Value,
+) {
+    let action = action.to_string();
+    let target_type = target_type.to_string();
+    let details = details.to_string();
+    state
+        .blocking_db(move |db| db.log_audit(&actor, &action, &target_type, target_id, &details))
+        .await;
+}
+
 /// Fire a webhook notification for a system activity event.
 ///
-/// This sends a POST request with a JSON payload to the configured webhook URL.
+/// This queues a POST request with a JSON payload to the configured webhook URL;
+/// the worker in [`crate::webhook_queue`] delivers it and retries failures.
 /// The call is non-blocking — it spawns a background thread so the HTTP response
-/// to the admin is not delayed by the webhook delivery.
+/// to the admin is not delayed by the database writes.
 ///
 /// `event` — short event identifier (e.g. "domain.created", "account.deleted")
 /// `details` — a JSON-serialisable value with event-specific information
 pub(crate) fn fire_webhook(state: &AppState, event: &str, details: serde_json::Value) {
     let db = state.db.clone();
-    let webhook_url = db.get_setting("webhook_url").unwrap_or_default();
-    if webhook_url.is_empty() {
-        return;
-    }
-
     let event = event.to_string();
 
-    std::thread::spawn(move || {
+    // The settings lookup uses the blocking client, so it must not run on an
+    // async worker thread either.
+    crate::shutdown::spawn("webhook enqueue", move || {
+        let webhook_url = db.get_setting("webhook_url").unwrap_or_default();
+        if webhook_url.is_empty() {
+            return;
+        }
 
         let timestamp = chrono::Utc::now().to_rfc3339();
         let payload = serde_json::json!({
@@ -316,58 +423,141 @@ pub(crate) fn fire_webhook(state: &AppState, event: &str, details: serde_json::V
         });
         let request_body = payload.to_string();
 
-        debug!("[webhook] firing {} to {}", event, webhook_url);
-        let start = std::time::Instant::now();
-
-        let (response_status, response_body, error) = match reqwest::blocking::Client::builder()
-            .timeout(std::time::Duration::from_secs(10))
-            .build()
-        {
-            Ok(client) => match client.post(&webhook_url).json(&payload).send() {
-                Ok(resp) => {
-                    let status = resp.status().as_u16() as i32;
-                    let body = resp.text().unwrap_or_default();
-                    let body_truncated = if body.len() > 2048 {
-                        let mut end = 2048;
-                        while !body.is_char_boundary(end) {
-                            end -= 1;
-                        }
-                        body[..end].to_string()
-                    } else {
-                        body
-                    };
-                    info!(
-                        "[webhook] {} delivered to {} status={}",
-                        event, webhook_url, status
-                    );
-                    (Some(status), body_truncated, String::new())
-                }
-                Err(e) => {
-                    warn!(
-                        "[webhook] {} delivery failed to {}: {}",
-                        event, webhook_url, e
-                    );
-                    (None, String::new(), e.to_string())
-                }
-            },
+        debug!("[webhook] queueing {} to {}", event, webhook_url);
+        crate::webhook_queue::enqueue(&db, &webhook_url, &request_body, &event, "", 0);
+    });
+}
+
+/// HMAC signature of one webhook request, sent as the `X-Webhook-Timestamp`
+/// and `X-Webhook-Signature` headers.
+///
+/// The signed (canonical) string is the timestamp in Unix seconds, a `.`, and
+/// the exact request body bytes, with nothing trimmed or re-encoded:
+///
+/// ```text
+/// 1767225600.{"event":"test",...}
+/// ```
+///
+/// It is signed with HMAC-SHA256 keyed by the `webhook_secret` setting and the
+/// signature header is `sha256=` followed by the lowercase hex digest.
+/// Receivers recompute it over the raw body they received, compare it in
+/// constant time, and reject requests whose timestamp is more than a few
+/// minutes old so a captured request cannot be replayed later.
+pub(crate) struct WebhookSignature {
+    pub timestamp: i64,
+    pub signature: String,
+}
+
+impl WebhookSignature {
+    /// Sign `body` at `timestamp`.
+    pub(crate) fn new(secret: &str, timestamp: i64, body: &str) -> Self {
+        use hmac::{Hmac, Mac};
+
+        let mut mac = Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC accepts any key length");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body.as_bytes());
+        let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        WebhookSignature {
+            timestamp,
+            signature,
+        }
+    }
+
+    /// Sign `body` now, or `None` when no secret is configured.
+    pub(crate) fn sign(secret: &str, body: &str) -> Option<Self> {
+        if secret.is_empty() {
+            return None;
+        }
+        Some(Self::new(secret, chrono::Utc::now().timestamp(), body))
+    }
+}
+
+/// POST the JSON `body` to `url` exactly as given, with a 10-second timeout
+/// and the signature headers when `signature` is set.
+///
+/// Returns `(status, truncated response body, error)` for logging; failures
+/// are logged as warnings.  Retrying is up to the caller, usually via
+/// [`crate::webhook_queue`].
+pub(crate) fn post_json_webhook(
+    url: &str,
+    body: &str,
+    signature: Option<&WebhookSignature>,
+    event: &str,
+) -> (Option<i32>, String, String) {
+    match reqwest::blocking::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+    {
+        Ok(client) => match webhook_request(&client, url, body, signature).send() {
+            Ok(resp) => {
+                let status = resp.status().as_u16() as i32;
+                let body = resp.text().unwrap_or_default();
+                let body_truncated = if body.len() > 2048 {
+                    let mut end = 2048;
+                    while !body.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    body[..end].to_string()
+                } else {
+                    body
+                };
+                info!("[webhook] {} delivered to {} status={}", event, url, status);
+                (Some(status), body_truncated, String::new())
+            }
             Err(e) => {
-                warn!("[webhook] failed to build HTTP client: {}", e);
+                warn!("[webhook] {} delivery failed to {}: {}", event, url, e);
                 (None, String::new(), e.to_string())
             }
-        };
-
-        let duration_ms = start.elapsed().as_millis() as i64;
-
-        // Log the webhook execution (best-effort)
-        db.log_webhook(
-            &webhook_url,
-            &request_body,
-            response_status,
-            &response_body,
-            &error,
-            duration_ms,
-            &event,
-            "",
+        },
+        Err(e) => {
+            warn!("[webhook] failed to build HTTP client: {}", e);
+            (None, String::new(), e.to_string())
+        }
+    }
+}
+
+/// A JSON POST of `body` to `url`, with the signature headers when `signature`
+/// is set.
+pub(crate) fn webhook_request(
+    client: &reqwest::blocking::Client,
+    url: &str,
+    body: &str,
+    signature: Option<&WebhookSignature>,
+) -> reqwest::blocking::RequestBuilder {
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .body(body.to_string());
+    if let Some(sig) = signature {
+        request = request
+            .header("X-Webhook-Timestamp", sig.timestamp.to_string())
+            .header("X-Webhook-Signature", &sig.signature);
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn webhook_signature_covers_timestamp_and_exact_body() {
+        let sig = WebhookSignature::new("secret", 1700000000, r#"{"event":"test"}"#);
+        // echo -n '1700000000.{"event":"test"}' | openssl dgst -sha256 -hmac secret
+        assert_eq!(
+            sig.signature,
+            "sha256=e6a22eb66e93669c75e7a035a110d9a2ccfa7cdef62d0ecb361671b92718ee9f"
         );
-    });
+        assert_ne!(
+            WebhookSignature::new("secret", 1700000001, r#"{"event":"test"}"#).signature,
+            sig.signature
+        );
+        assert_ne!(
+            WebhookSignature::new("secret", 1700000000, r#"{"event": "test"}"#).signature,
+            sig.signature
+        );
+        assert!(WebhookSignature::sign("", "{}").is_none());
+    }
 }
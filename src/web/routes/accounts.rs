@@ -1,24 +1,29 @@
 use askama::Template;
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Multipart, Path, Query, State},
+    http::StatusCode,
+    middleware,
     response::{Html, IntoResponse, Redirect, Response},
-    Form,
+    routing::get,
+    Form, Json, Router,
 };
 use log::{debug, error, info, warn};
 use serde::Deserialize;
 
-use crate::db::{Account, Alias, Domain};
+use crate::db::{Account, Alias, DeliveryRule, Domain};
 use crate::web::auth::AuthAdmin;
-use crate::web::fire_webhook;
-use crate::web::forms::{AccountEditForm, AccountForm};
+use crate::web::forms::{AccountEditForm, AccountForm, DeliveryRuleForm, DeliveryRuleMoveForm};
 use crate::web::regen_configs;
 use crate::web::AppState;
+use crate::web::{audit, fire_webhook};
 
 // ── Query parameters ──
 
 #[derive(Deserialize)]
 pub struct PaginationQuery {
     pub page: Option<i64>,
+    #[serde(default)]
+    pub q: String,
 }
 
 // ── View models ──
@@ -29,8 +34,14 @@ struct AccountListRow {
     name: String,
     active: bool,
     quota_display: String,
+    usage_display: String,
+    /// Share of the quota in use; `None` for unlimited accounts.
+    usage_percent: Option<u64>,
+    over_quota: bool,
     mailbox_path: String,
     is_system: bool,
+    /// Last IMAP or POP3 login within the login retention period.
+    last_login: Option<String>,
 }
 
 // ── Templates ──
@@ -44,6 +55,7 @@ struct ListTemplate<'a> {
     current_page: u32,
     total_pages: u32,
     total_count: u32,
+    search: String,
 }
 
 #[derive(Template)]
@@ -61,6 +73,10 @@ struct EditTemplate<'a> {
     flash: Option<&'a str>,
     account: Account,
     send_as_aliases: Vec<Alias>,
+    disabled_policy: String,
+    send_limit_messages: Option<i32>,
+    send_limit_recipients: Option<i32>,
+    rules: Vec<DeliveryRule>,
 }
 
 #[derive(Template)]
@@ -83,29 +99,64 @@ pub async fn list(
     State(state): State<AppState>,
     Query(params): Query<PaginationQuery>,
 ) -> Html<String> {
-    info!("[web] GET /accounts — listing accounts");
-    let all_accounts = state
-        .blocking_db(|db| db.list_all_accounts_with_domain())
-        .await;
-    debug!("[web] found {} accounts", all_accounts.len());
+    let search = params.q.trim().to_string();
+    info!("[web] GET /accounts — listing accounts q={:?}", search);
 
-    let total_count = all_accounts.len() as u32;
+    let count_search = search.clone();
+    let total_count = state
+        .blocking_db(move |db| {
+            db.count_accounts(Some(count_search.as_str()).filter(|s| !s.is_empty()))
+        })
+        .await as u32;
     let page_num = std::cmp::max(params.page.unwrap_or(1), 1) as u32;
     let per_page = 20u32;
-    let total_pages = (total_count + per_page - 1) / per_page;
+    let total_pages = total_count.div_ceil(per_page);
     let page_num = std::cmp::min(page_num, std::cmp::max(total_pages, 1));
-    let offset = ((page_num - 1) * per_page) as usize;
-    let limit = per_page as usize;
+    let offset = ((page_num - 1) * per_page) as i64;
+    let limit = per_page as i64;
 
-    let account_rows: Vec<AccountListRow> = all_accounts
+    let page_search = search.clone();
+    let (accounts, last_logins) = state
+        .blocking_db(move |db| {
+            let accounts = db
+                .list_accounts_paged(
+                    limit,
+                    offset,
+                    Some(page_search.as_str()).filter(|s| !s.is_empty()),
+                )
+                .into_iter()
+                .map(|a| {
+                    let used = a.domain_name.as_deref().map_or(0, |domain| {
+                        crate::delivery_policy::cached_mailbox_usage(db, domain, &a.username, false)
+                    });
+                    (a, used)
+                })
+                .collect::<Vec<_>>();
+            let emails: Vec<String> = accounts
+                .iter()
+                .filter_map(|(a, _)| {
+                    a.domain_name
+                        .as_ref()
+                        .map(|d| format!("{}@{}", a.username, d))
+                })
+                .collect();
+            let last_logins = db.last_logins(&emails);
+            (accounts, last_logins)
+        })
+        .await;
+    debug!(
+        "[web] found {} accounts, showing {} on page {}",
+        total_count,
+        accounts.len(),
+        page_num
+    );
+
+    let account_rows: Vec<AccountListRow> = accounts
         .iter()
-        .skip(offset)
-        .take(limit)
-        .map(|a| {
+        .map(|(a, used)| {
             let email = format!("{}@{}", a.username, a.domain_name.as_deref().unwrap_or("?"));
             let quota_display = if a.quota > 0 {
-                let quota_gb = a.quota as f64 / 1_000_000_000.0;
-                format!("{:.2} GB", quota_gb)
+                format!("{} MB", a.quota)
             } else {
                 "∞".to_string()
             };
@@ -114,14 +165,19 @@ pub async fn list(
                 a.domain_name.as_deref().unwrap_or("?"),
                 a.username
             );
+            let last_login = last_logins.get(&email.to_lowercase()).cloned();
             AccountListRow {
                 id: a.id,
                 email,
                 name: a.name.clone(),
                 active: a.active,
                 quota_display,
+                usage_display: format_usage(*used),
+                usage_percent: usage_percent(*used, a.quota),
+                over_quota: usage_percent(*used, a.quota).is_some_and(|p| p >= 100),
                 mailbox_path,
                 is_system: a.is_system,
+                last_login,
             }
         })
         .collect();
@@ -133,6 +189,7 @@ pub async fn list(
         current_page: page_num,
         total_pages,
         total_count,
+        search,
     };
     Html(tmpl.render().unwrap())
 }
@@ -149,7 +206,7 @@ pub async fn new_form(_auth: AuthAdmin, State(state): State<AppState>) -> Html<S
 }
 
 pub async fn create(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Form(form): Form<AccountForm>,
 ) -> Response {
@@ -157,6 +214,20 @@ pub async fn create(
         "[web] POST /accounts — creating account username={}, domain_id={}",
         form.username, form.domain_id
     );
+    if !crate::validation::is_valid_localpart(&form.username) {
+        warn!("[web] invalid account username: {:?}", form.username);
+        let tmpl = ErrorTemplate {
+            nav_active: "Accounts",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Username",
+            message: crate::validation::LOCALPART_HINT,
+            back_url: "/accounts/new",
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
     let db_hash = match crate::auth::hash_password(&form.password) {
         Ok(h) => h,
         Err(e) => {
@@ -191,6 +262,15 @@ pub async fn create(
                 form.username, id
             );
             regen_configs(&state).await;
+            audit(
+                &state,
+                &auth,
+                "account.created",
+                "account",
+                Some(id),
+                serde_json::json!({"username": form.username, "domain_id": form.domain_id}),
+            )
+            .await;
             fire_webhook(
                 &state,
                 "account.created",
@@ -215,6 +295,51 @@ pub async fn create(
     }
 }
 
+pub async fn export(_auth: AuthAdmin, State(state): State<AppState>) -> Response {
+    info!("[web] GET /accounts/export — exporting accounts as CSV");
+    let accounts = state
+        .blocking_db(|db| db.list_all_accounts_with_domain())
+        .await;
+    super::csv_transfer::csv_download(
+        "accounts.csv",
+        crate::csv_transfer::export_accounts(&accounts),
+    )
+}
+
+pub async fn import(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Response {
+    info!("[web] POST /accounts/import — importing accounts from CSV");
+    let report = match super::csv_transfer::read_upload(multipart).await {
+        Ok(input) => {
+            state
+                .blocking_db(move |db| crate::csv_transfer::import_accounts(db, &input))
+                .await
+        }
+        Err(e) => crate::csv_transfer::ImportReport {
+            error: Some(e),
+            ..Default::default()
+        },
+    };
+    if report.created > 0 {
+        regen_configs(&state).await;
+        let details = serde_json::json!({"count": report.created});
+        audit(
+            &state,
+            &auth,
+            "account.imported",
+            "account",
+            None,
+            details.clone(),
+        )
+        .await;
+        fire_webhook(&state, "account.imported", details);
+    }
+    super::csv_transfer::report_response("Accounts", "Account Import", "/accounts", report)
+}
+
 pub async fn edit_form(
     _auth: AuthAdmin,
     State(state): State<AppState>,
@@ -238,17 +363,31 @@ pub async fn edit_form(
         .filter(|a| a.domain_id == account.domain_id && a.active)
         .collect();
 
+    let (disabled_policy, (send_limit_messages, send_limit_recipients), rules) = state
+        .blocking_db(move |db| {
+            (
+                db.get_account_disabled_policy(id),
+                db.get_account_send_limits(id),
+                db.list_delivery_rules(id),
+            )
+        })
+        .await;
+
     let tmpl = EditTemplate {
         nav_active: "Accounts",
         flash: None,
         account,
         send_as_aliases,
+        disabled_policy,
+        send_limit_messages,
+        send_limit_recipients,
+        rules,
     };
     Html(tmpl.render().unwrap()).into_response()
 }
 
 pub async fn update(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Form(form): Form<AccountEditForm>,
@@ -260,8 +399,20 @@ pub async fn update(
         id, active, quota
     );
     let name = form.name.clone();
+    // Empty falls back to the global policy; anything unknown does too.
+    let disabled_policy = crate::delivery_policy::DisabledPolicy::parse(&form.disabled_policy)
+        .map(|p| p.as_str())
+        .unwrap_or("");
+    // Blank (or anything but a whole number) uses the global sending limit.
+    let send_limit = |v: &str| v.trim().parse::<i32>().ok().filter(|n| *n >= 0);
+    let send_limit_messages = send_limit(&form.send_limit_messages);
+    let send_limit_recipients = send_limit(&form.send_limit_recipients);
     state
-        .blocking_db(move |db| db.update_account(id, &name, active, quota))
+        .blocking_db(move |db| {
+            db.update_account(id, &name, active, quota);
+            db.set_account_disabled_policy(id, disabled_policy);
+            db.set_account_send_limits(id, send_limit_messages, send_limit_recipients);
+        })
         .await;
 
     // Only update password if field is not empty
@@ -282,18 +433,264 @@ pub async fn update(
     }
 
     regen_configs(&state).await;
+    let password_changed = form.password.as_deref().is_some_and(|pw| !pw.is_empty());
+    audit(
+        &state,
+        &auth,
+        "account.updated",
+        "account",
+        Some(id),
+        serde_json::json!({
+            "active": active,
+            "quota": quota,
+            "password_changed": password_changed,
+            "send_limit_messages": send_limit_messages,
+            "send_limit_recipients": send_limit_recipients,
+        }),
+    )
+    .await;
     fire_webhook(&state, "account.updated", serde_json::json!({"id": id}));
     Redirect::to("/accounts").into_response()
 }
 
 pub async fn delete(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Response {
     warn!("[web] POST /accounts/{}/delete — deleting account", id);
     state.blocking_db(move |db| db.delete_account(id)).await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "account.deleted",
+        "account",
+        Some(id),
+        serde_json::json!({}),
+    )
+    .await;
     fire_webhook(&state, "account.deleted", serde_json::json!({"id": id}));
     Redirect::to("/accounts").into_response()
 }
+
+// ── Delivery rules ──
+
+pub async fn create_rule(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(form): Form<DeliveryRuleForm>,
+) -> Response {
+    info!(
+        "[web] POST /accounts/{}/rules — creating delivery rule {} {} -> {}",
+        id, form.match_field, form.match_op, form.action
+    );
+    let match_field = form.match_field.trim().to_string();
+    // Known fields are stored lower-case; header names keep their case.
+    let match_field = if crate::delivery_rules::FIELDS.contains(&match_field.to_lowercase().as_str()) {
+        match_field.to_lowercase()
+    } else {
+        match_field
+    };
+    let (target_folder, header) = match form.action.as_str() {
+        "move" => (form.target_folder.trim().to_string(), String::new()),
+        _ => (String::new(), form.header.trim().to_string()),
+    };
+    let back_url = format!("/accounts/{}/edit#rules", id);
+    if let Err(message) = crate::delivery_rules::validate(
+        &match_field,
+        &form.match_op,
+        &form.action,
+        &target_folder,
+        &header,
+    ) {
+        warn!("[web] rejected delivery rule for account id={}: {}", id, message);
+        let tmpl = ErrorTemplate {
+            nav_active: "Accounts",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Rule",
+            message,
+            back_url: &back_url,
+            back_label: "Back",
+        };
+        return (
+            StatusCode::BAD_REQUEST,
+            Html(tmpl.render().unwrap()),
+        )
+            .into_response();
+    }
+
+    let details = serde_json::json!({
+        "match_field": match_field,
+        "match_op": form.match_op,
+        "pattern": form.pattern,
+        "action": form.action,
+        "target_folder": target_folder,
+        "header": header,
+    });
+    let created = state
+        .blocking_db(move |db| {
+            db.get_account(id)?;
+            db.create_delivery_rule(
+                id,
+                &match_field,
+                &form.match_op,
+                &form.pattern,
+                &form.action,
+                &target_folder,
+                &header,
+            )
+        })
+        .await;
+    if created.is_some() {
+        audit(
+            &state,
+            &auth,
+            "account.rule_created",
+            "account",
+            Some(id),
+            details,
+        )
+        .await;
+    }
+    Redirect::to(&back_url).into_response()
+}
+
+pub async fn delete_rule(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path((id, rule_id)): Path<(i64, i64)>,
+) -> Response {
+    warn!(
+        "[web] POST /accounts/{}/rules/{}/delete — deleting delivery rule",
+        id, rule_id
+    );
+    state
+        .blocking_db(move |db| db.delete_delivery_rule(id, rule_id))
+        .await;
+    audit(
+        &state,
+        &auth,
+        "account.rule_deleted",
+        "account",
+        Some(id),
+        serde_json::json!({"rule_id": rule_id}),
+    )
+    .await;
+    Redirect::to(&format!("/accounts/{}/edit#rules", id)).into_response()
+}
+
+pub async fn move_rule(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path((id, rule_id)): Path<(i64, i64)>,
+    Form(form): Form<DeliveryRuleMoveForm>,
+) -> Response {
+    info!(
+        "[web] POST /accounts/{}/rules/{}/move — moving delivery rule {}",
+        id, rule_id, form.direction
+    );
+    let up = form.direction == "up";
+    if let Err(e) = state
+        .blocking_db(move |db| db.move_delivery_rule(id, rule_id, up))
+        .await
+    {
+        error!("[web] failed to move delivery rule id={}: {}", rule_id, e);
+    }
+    Redirect::to(&format!("/accounts/{}/edit#rules", id)).into_response()
+}
+
+// ── Mailbox usage ──
+
+#[derive(Deserialize)]
+pub struct UsageQuery {
+    #[serde(default)]
+    pub refresh: bool,
+}
+
+/// `/accounts/:id/usage`, guarded by the same API keys as `/api/v1`.
+pub fn usage_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/accounts/:id/usage", get(usage))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            super::api_v1::require_api_key,
+        ))
+}
+
+/// Current Maildir size of an account as JSON for scripts.  Measurements
+/// are cached briefly; `?refresh=true` rescans the Maildir.
+pub async fn usage(
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Query(params): Query<UsageQuery>,
+) -> Response {
+    debug!("[web] GET /accounts/{}/usage — mailbox usage", id);
+    let result = state
+        .blocking_db(move |db| {
+            let account = db.get_account(id)?;
+            let domain = db.get_domain(account.domain_id)?.domain;
+            let used = crate::delivery_policy::cached_mailbox_usage(
+                db,
+                &domain,
+                &account.username,
+                params.refresh,
+            );
+            Some((account, domain, used))
+        })
+        .await;
+    let Some((account, domain, used)) = result else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({"error": "account not found"})),
+        )
+            .into_response();
+    };
+    let quota_bytes = (account.quota > 0).then(|| account.quota as u64 * 1024 * 1024);
+    Json(serde_json::json!({
+        "id": account.id,
+        "email": format!("{}@{}", account.username, domain),
+        "bytes": used,
+        "quota_bytes": quota_bytes,
+        "percent": usage_percent(used, account.quota),
+        "over_quota": quota_bytes.is_some_and(|q| used >= q),
+    }))
+    .into_response()
+}
+
+fn format_usage(bytes: u64) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+/// Whole percent of a `quota_mb` quota that `bytes` uses, or `None` when the
+/// quota is unlimited.
+fn usage_percent(bytes: u64, quota_mb: i64) -> Option<u64> {
+    if quota_mb <= 0 {
+        return None;
+    }
+    Some(bytes.saturating_mul(100) / (quota_mb as u64 * 1024 * 1024))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage_is_shown_against_the_quota() {
+        assert_eq!(format_usage(512), "512 B");
+        assert_eq!(format_usage(1536), "1.5 KB");
+        assert_eq!(format_usage(5 * 1024 * 1024 + 104858), "5.1 MB");
+        assert_eq!(usage_percent(5 * 1024 * 1024, 20), Some(25));
+        assert_eq!(usage_percent(30 * 1024 * 1024, 20), Some(150));
+        assert_eq!(usage_percent(1024, 0), None);
+    }
+}
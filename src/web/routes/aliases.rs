@@ -1,6 +1,6 @@
 use askama::Template;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::{Html, IntoResponse, Redirect, Response},
     Form,
 };
@@ -8,10 +8,10 @@ use log::{debug, error, info, warn};
 use std::collections::HashMap;
 
 use crate::web::auth::AuthAdmin;
-use crate::web::fire_webhook;
-use crate::web::forms::{AliasEditForm, AliasForm};
+use crate::web::forms::{AliasEditForm, AliasForm, AliasResolveQuery};
 use crate::web::regen_configs;
 use crate::web::AppState;
+use crate::web::{audit, fire_webhook};
 
 fn is_catch_all(source: &str, domain: Option<&str>) -> bool {
     let normalized = source.trim().to_ascii_lowercase();
@@ -27,6 +27,15 @@ fn is_catch_all(source: &str, domain: Option<&str>) -> bool {
     false
 }
 
+/// An alias source: a mailbox address, or `*@domain` (or `@domain`) for a
+/// catch-all.
+fn is_valid_source(source: &str) -> bool {
+    match source.split_once('@') {
+        Some(("" | "*", domain)) => crate::validation::is_valid_domain(domain),
+        _ => crate::validation::is_valid_address(source),
+    }
+}
+
 // ── View models ──
 
 struct AliasRow {
@@ -38,6 +47,15 @@ struct AliasRow {
     active_label: String,
 }
 
+/// One alias the resolver considered for an address.
+struct CandidateRow {
+    source: String,
+    destination: String,
+    matched_as: &'static str,
+    sort_order: i64,
+    outcome: &'static str,
+}
+
 // ── Templates ──
 
 #[derive(Template)]
@@ -48,6 +66,8 @@ struct ListTemplate<'a> {
     alias_rows: Vec<AliasRow>,
     coverage_copy: String,
     coverage_pct: f64,
+    loops: Vec<crate::db::ForwardingLoop>,
+    max_depth: usize,
 }
 
 #[derive(Template)]
@@ -65,6 +85,18 @@ struct EditTemplate<'a> {
     alias: crate::db::Alias,
 }
 
+#[derive(Template)]
+#[template(path = "aliases/resolve.html")]
+struct ResolveTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    address: String,
+    candidates: Vec<CandidateRow>,
+    /// Where mail for the address ends up, if anywhere.
+    destination: Option<String>,
+    note: Option<&'a str>,
+}
+
 #[derive(Template)]
 #[template(path = "error.html")]
 struct ErrorTemplate<'a> {
@@ -78,6 +110,24 @@ struct ErrorTemplate<'a> {
     back_label: &'a str,
 }
 
+/// The page refusing an alias that would make mail loop.
+fn loop_error(cycle: &crate::db::ForwardingLoop, back_url: &str) -> Response {
+    let tmpl = ErrorTemplate {
+        nav_active: "Aliases",
+        flash: None,
+        status_code: 400,
+        status_text: "Forwarding Loop",
+        title: "Forwarding Loop",
+        message: &format!(
+            "Saving this alias would send mail round in a circle: {}. Change the destination, or the alias or forwarding that leads back here.",
+            cycle.route()
+        ),
+        back_url,
+        back_label: "Back",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
 // ── Handlers ──
 
 pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
@@ -87,6 +137,13 @@ pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
         .await;
     debug!("[web] found {} aliases", aliases.len());
     let domains = state.blocking_db(|db| db.list_domains()).await;
+    let loops = state.blocking_db(|db| db.detect_forwarding_loops()).await;
+    if !loops.is_empty() {
+        warn!(
+            "[web] {} forwarding loops or long chains found",
+            loops.len()
+        );
+    }
 
     let mut catch_ready: HashMap<i64, bool> = HashMap::new();
     for a in &aliases {
@@ -140,10 +197,112 @@ pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
         alias_rows,
         coverage_copy,
         coverage_pct,
+        loops,
+        max_depth: crate::db::MAX_FORWARDING_DEPTH,
     };
     Html(tmpl.render().unwrap())
 }
 
+/// Show which alias mail for `?address=` follows, with every alias that was
+/// considered in lookup order.  Uses [`crate::config::resolve_alias`], the
+/// same resolution that writes the Postfix alias map.
+pub async fn resolve(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Query(query): Query<AliasResolveQuery>,
+) -> Html<String> {
+    let address = query.address.trim().to_lowercase();
+    debug!(
+        "[web] GET /aliases/resolve — resolving address={:?}",
+        address
+    );
+    let mut tmpl = ResolveTemplate {
+        nav_active: "Aliases",
+        flash: None,
+        address: address.clone(),
+        candidates: Vec::new(),
+        destination: None,
+        note: None,
+    };
+    if address.is_empty() {
+        return Html(tmpl.render().unwrap());
+    }
+    let Some((local, domain)) = address
+        .rsplit_once('@')
+        .filter(|(l, d)| !l.is_empty() && !d.is_empty())
+    else {
+        tmpl.flash = Some("Enter a full email address such as info@example.com.");
+        return Html(tmpl.render().unwrap());
+    };
+
+    let mailbox_address = format!("{}@{}", local.split('+').next().unwrap_or(local), domain);
+    let lookup = mailbox_address.clone();
+    let (aliases, mailbox) = state
+        .blocking_db(move |db| {
+            let mailbox = db.get_account_by_email(&lookup).filter(|a| a.active);
+            (db.list_all_aliases_with_domain(), mailbox)
+        })
+        .await;
+
+    let candidates = crate::config::alias_candidates(&address, &aliases);
+    let winner = crate::config::resolve_alias(&address, &aliases);
+    let winner_is_catch_all = winner
+        .as_ref()
+        .is_some_and(|w| crate::config::alias_key(w).starts_with('@'));
+    // Generated configs give mailboxes in a catch-all domain an identity
+    // entry, so the mailbox beats the catch-all.
+    let mailbox_wins = mailbox.is_some() && (winner.is_none() || winner_is_catch_all);
+    let (destination, note) = match &winner {
+        _ if mailbox_wins => (
+            Some(mailbox_address),
+            Some(if winner.is_some() {
+                "The address is a mailbox, so mail is delivered there instead of to the catch-all."
+            } else {
+                "No alias matches; mail is delivered to the mailbox."
+            }),
+        ),
+        Some(w) => (Some(w.destination.clone()), None),
+        None => (
+            None,
+            Some("No alias or mailbox matches; Postfix rejects mail for this address."),
+        ),
+    };
+
+    tmpl.candidates = candidates
+        .into_iter()
+        .map(|a| {
+            let key = crate::config::alias_key(&a);
+            let outcome = if !a.active {
+                "Disabled"
+            } else if winner.as_ref().is_some_and(|w| w.id == a.id) {
+                if mailbox_wins {
+                    "Not used"
+                } else {
+                    "Used"
+                }
+            } else {
+                "Shadowed"
+            };
+            CandidateRow {
+                source: a.source,
+                destination: a.destination,
+                matched_as: if key == address {
+                    "Address"
+                } else if key.starts_with('@') {
+                    "Catch-all"
+                } else {
+                    "Without +detail"
+                },
+                sort_order: a.sort_order,
+                outcome,
+            }
+        })
+        .collect();
+    tmpl.destination = destination;
+    tmpl.note = note;
+    Html(tmpl.render().unwrap())
+}
+
 pub async fn new_form(_auth: AuthAdmin, State(_state): State<AppState>) -> Html<String> {
     debug!("[web] GET /aliases/new — new alias form");
     let tmpl = NewTemplate {
@@ -154,7 +313,7 @@ pub async fn new_form(_auth: AuthAdmin, State(_state): State<AppState>) -> Html<
 }
 
 pub async fn create(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Form(form): Form<AliasForm>,
 ) -> Response {
@@ -165,11 +324,8 @@ pub async fn create(
 
     // Extract domain from source email
     let source_parts: Vec<&str> = form.source.split('@').collect();
-    if source_parts.len() != 2 {
-        warn!(
-            "[web] invalid source email format (no @ or multiple @): {}",
-            form.source
-        );
+    if !is_valid_source(&form.source) {
+        warn!("[web] invalid alias source: {:?}", form.source);
         let tmpl = ErrorTemplate {
             nav_active: "Aliases",
             flash: None,
@@ -177,8 +333,9 @@ pub async fn create(
             status_text: "Invalid Source Email",
             title: "Invalid Source Email",
             message: &format!(
-                "The source email '{}' is not in a valid format. It must be in the format 'user@domain.com' or '*@domain.com'.",
-                form.source
+                "The source email '{}' is not in a valid format. It must be in the format 'user@domain.com' or '*@domain.com'. {}",
+                form.source,
+                crate::validation::LOCALPART_HINT
             ),
             back_url: "/aliases/new",
             back_label: "Back",
@@ -247,6 +404,25 @@ pub async fn create(
         return Html(tmpl.render().unwrap()).into_response();
     }
 
+    let proposed = crate::db::Alias {
+        id: i64::MAX,
+        domain_id,
+        source: form.source.clone(),
+        destination: form.destination.clone(),
+        active: true,
+        sort_order: 0,
+        domain_name: Some(domain.domain.clone()),
+    };
+    if let Some(cycle) = state.blocking_db(move |db| db.alias_cycle(proposed)).await {
+        warn!(
+            "[web] refusing alias {} -> {}: forwarding loop {}",
+            form.source,
+            form.destination,
+            cycle.route()
+        );
+        return loop_error(&cycle, "/aliases/new");
+    }
+
     let source = form.source.clone();
     let destination = form.destination.clone();
     let create_result = state
@@ -259,6 +435,15 @@ pub async fn create(
                 form.source, form.destination, id, domain_id
             );
             regen_configs(&state).await;
+            audit(
+                &state,
+                &auth,
+                "alias.created",
+                "alias",
+                Some(id),
+                serde_json::json!({"source": form.source, "destination": form.destination}),
+            )
+            .await;
             fire_webhook(
                 &state,
                 "alias.created",
@@ -308,7 +493,7 @@ pub async fn edit_form(
 }
 
 pub async fn update(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Form(form): Form<AliasEditForm>,
@@ -318,24 +503,86 @@ pub async fn update(
         "[web] POST /aliases/{} — updating alias source={}, destination={}, active={}",
         id, form.source, form.destination, active
     );
+    if !is_valid_source(&form.source) {
+        warn!(
+            "[web] invalid alias source for alias id={}: {:?}",
+            id, form.source
+        );
+        let back_url = format!("/aliases/{}/edit", id);
+        let tmpl = ErrorTemplate {
+            nav_active: "Aliases",
+            flash: None,
+            status_code: 400,
+            status_text: "Invalid Source Email",
+            title: "Invalid Source Email",
+            message: &format!(
+                "The source email '{}' is not in a valid format. It must be in the format 'user@domain.com' or '*@domain.com'. {}",
+                form.source,
+                crate::validation::LOCALPART_HINT
+            ),
+            back_url: &back_url,
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    if active {
+        let source = form.source.clone();
+        let destination = form.destination.clone();
+        let cycle = state
+            .blocking_db(move |db| {
+                let mut alias = db.get_alias(id)?;
+                alias.domain_name = db.get_domain(alias.domain_id).map(|d| d.domain);
+                alias.source = source;
+                alias.destination = destination;
+                alias.active = true;
+                db.alias_cycle(alias)
+            })
+            .await;
+        if let Some(cycle) = cycle {
+            warn!(
+                "[web] refusing update of alias id={}: forwarding loop {}",
+                id,
+                cycle.route()
+            );
+            return loop_error(&cycle, &format!("/aliases/{}/edit", id));
+        }
+    }
     let source = form.source.clone();
     let destination = form.destination.clone();
     state
         .blocking_db(move |db| db.update_alias(id, &source, &destination, active))
         .await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "alias.updated",
+        "alias",
+        Some(id),
+        serde_json::json!({"source": form.source, "destination": form.destination, "active": active}),
+    )
+    .await;
     fire_webhook(&state, "alias.updated", serde_json::json!({"id": id}));
     Redirect::to("/aliases").into_response()
 }
 
 pub async fn delete(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Response {
     warn!("[web] POST /aliases/{}/delete — deleting alias", id);
     state.blocking_db(move |db| db.delete_alias(id)).await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "alias.deleted",
+        "alias",
+        Some(id),
+        serde_json::json!({}),
+    )
+    .await;
     fire_webhook(&state, "alias.deleted", serde_json::json!({"id": id}));
     Redirect::to("/aliases").into_response()
 }
@@ -1,9 +1,15 @@
 use askama::Template;
-use axum::{extract::State, response::{Html, IntoResponse, Redirect, Response}};
-use log::info;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use log::{error, info};
 
+use crate::db::ApiKey;
 use crate::web::auth::AuthAdmin;
-use crate::web::AppState;
+use crate::web::forms::ApiKeyForm;
+use crate::web::{audit, AppState};
 
 // ── Templates ──
 
@@ -14,18 +20,25 @@ struct ApiDocsTemplate<'a> {
     flash: Option<String>,
     hostname: &'a str,
     api_token: Option<String>,
+    api_keys: Vec<ApiKey>,
+    /// Plaintext of a key created by this request; never shown again.
+    new_api_key: Option<String>,
 }
 
 // ── Handler ──
 
 pub async fn page(_auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
     info!("[web] GET /api — REST documentation requested");
-    let api_token = state.blocking_db(|db| db.get_api_token()).await;
+    let (api_token, api_keys) = state
+        .blocking_db(|db| (db.get_api_token(), db.list_api_keys()))
+        .await;
     let tmpl = ApiDocsTemplate {
         nav_active: "API",
         flash: None,
         hostname: &state.hostname,
         api_token,
+        api_keys,
+        new_api_key: None,
     };
     Html(tmpl.render().unwrap())
 }
@@ -45,11 +58,14 @@ pub async fn generate_token(
         .await;
     info!("[web] API token generated by username={}", auth.admin.username);
     let api_token = Some(token);
+    let api_keys = state.blocking_db(|db| db.list_api_keys()).await;
     let tmpl = ApiDocsTemplate {
         nav_active: "API",
         flash: Some("New API token generated. Copy it now — it will not be shown again in full.".to_string()),
         hostname: &state.hostname,
         api_token,
+        api_keys,
+        new_api_key: None,
     };
     Html(tmpl.render().unwrap()).into_response()
 }
@@ -69,6 +85,87 @@ pub async fn revoke_token(
     Redirect::to("/api").into_response()
 }
 
+pub async fn create_key(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<ApiKeyForm>,
+) -> Response {
+    let name = form.name.trim().to_string();
+    let read_only = form.read_write.is_none();
+    info!(
+        "[web] POST /api/keys — creating API key name={} read_only={} for username={}",
+        name, read_only, auth.admin.username
+    );
+    if name.is_empty() {
+        return Redirect::to("/api").into_response();
+    }
+    let (key, prefix) = super::api_v1::generate_key();
+    let hash = match crate::auth::hash_password(&key) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("[web] failed to hash API key: {}", e);
+            return Redirect::to("/api").into_response();
+        }
+    };
+    let created_by = auth.admin.username.clone();
+    let db_name = name.clone();
+    let created = state
+        .blocking_db(move |db| db.create_api_key(&db_name, &prefix, &hash, read_only, &created_by))
+        .await;
+    let (flash, new_api_key) = match created {
+        Ok(id) => {
+            audit(
+                &state,
+                &auth,
+                "api_key.created",
+                "api_key",
+                Some(id),
+                serde_json::json!({"name": name, "read_only": read_only}),
+            )
+            .await;
+            (
+                "API key created. Copy it now — it will not be shown again.".to_string(),
+                Some(key),
+            )
+        }
+        Err(e) => (format!("Failed to create API key: {}", e), None),
+    };
+    let (api_token, api_keys) = state
+        .blocking_db(|db| (db.get_api_token(), db.list_api_keys()))
+        .await;
+    let tmpl = ApiDocsTemplate {
+        nav_active: "API",
+        flash: Some(flash),
+        hostname: &state.hostname,
+        api_token,
+        api_keys,
+        new_api_key,
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+pub async fn revoke_key(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    info!(
+        "[web] POST /api/keys/{}/revoke — revoking API key for username={}",
+        id, auth.admin.username
+    );
+    state.blocking_db(move |db| db.revoke_api_key(id)).await;
+    audit(
+        &state,
+        &auth,
+        "api_key.revoked",
+        "api_key",
+        Some(id),
+        serde_json::json!({}),
+    )
+    .await;
+    Redirect::to("/api").into_response()
+}
+
 // ── Tests ──
 
 #[cfg(test)]
@@ -82,6 +179,8 @@ mod tests {
             flash: None,
             hostname: "mail.example.com",
             api_token: None,
+            api_keys: Vec::new(),
+            new_api_key: None,
         };
         let rendered = tmpl.render().unwrap();
         assert!(rendered.contains("REST"));
@@ -96,6 +195,8 @@ mod tests {
             flash: Some("Token generated".to_string()),
             hostname: "mail.example.com",
             api_token: Some("abc123token".to_string()),
+            api_keys: Vec::new(),
+            new_api_key: None,
         };
         let rendered = tmpl.render().unwrap();
         assert!(rendered.contains("abc123token"));
@@ -5,12 +5,12 @@
 //! Endpoints:
 //!   `GET  /api/emails`           — List emails in an account's inbox or folder
 //!   `GET  /api/emails/:filename` — Read a single email
-//!   `POST /api/emails`           — Send an email
+//!   `POST /api/emails`           — Send an email (honours `Idempotency-Key`)
 //!   `DELETE /api/emails/:filename` — Delete an email
 
 use axum::{
     extract::{Path, Query, State},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     Json,
 };
@@ -19,7 +19,9 @@ use log::info;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 
+use crate::db::Account;
 use crate::web::auth::AuthAdmin;
+use crate::web::idempotency::{self, StoredResponse};
 use crate::web::AppState;
 
 use super::webmail::{
@@ -135,7 +137,8 @@ pub async fn list_emails(
                 "to": e.to,
                 "date": e.date,
                 "is_new": e.is_new,
-                "is_spam": e.is_spam
+                "is_spam": e.is_spam,
+                "unreadable": e.unreadable
             })
         })
         .collect();
@@ -285,6 +288,7 @@ pub async fn get_email(
 pub async fn send_email(
     _auth: AuthAdmin,
     State(state): State<AppState>,
+    headers: HeaderMap,
     Json(body): Json<SendEmailBody>,
 ) -> impl IntoResponse {
     info!(
@@ -292,6 +296,11 @@ pub async fn send_email(
         body.account_id, body.to
     );
 
+    let idempotency_key = match idempotency::request_key(&headers, &body) {
+        Ok(k) => k,
+        Err(msg) => return json_error(StatusCode::BAD_REQUEST, msg).into_response(),
+    };
+
     let account_id = body.account_id;
     let acct = match state
         .blocking_db(move |db| db.get_account_with_domain(account_id))
@@ -301,6 +310,20 @@ pub async fn send_email(
         None => return json_error(StatusCode::NOT_FOUND, "Account not found").into_response(),
     };
 
+    state
+        .blocking_db(move |db| {
+            idempotency::run(db, idempotency_key.as_ref(), || deliver_email(&acct, &body))
+        })
+        .await
+        .into_response()
+}
+
+fn stored_error(status: StatusCode, message: &str) -> StoredResponse {
+    StoredResponse::new(status, json!({"error": message}))
+}
+
+/// Build and submit the message to the local MTA.
+fn deliver_email(acct: &Account, body: &SendEmailBody) -> StoredResponse {
     let domain = acct.domain_name.as_deref().unwrap_or("unknown");
     let email_addr = format!("{}@{}", acct.username, domain);
 
@@ -318,21 +341,19 @@ pub async fn send_email(
     let from_mb = match from_addr.parse() {
         Ok(a) => a,
         Err(e) => {
-            return json_error(
+            return stored_error(
                 StatusCode::BAD_REQUEST,
                 &format!("Invalid from address: {}", e),
             )
-            .into_response()
         }
     };
     let to_mb = match body.to.parse() {
         Ok(a) => a,
         Err(e) => {
-            return json_error(
+            return stored_error(
                 StatusCode::BAD_REQUEST,
                 &format!("Invalid to address: {}", e),
             )
-            .into_response()
         }
     };
 
@@ -369,11 +390,10 @@ pub async fn send_email(
     let email = match email {
         Ok(e) => e,
         Err(e) => {
-            return json_error(
+            return stored_error(
                 StatusCode::INTERNAL_SERVER_ERROR,
                 &format!("Failed to build email: {}", e),
             )
-            .into_response()
         }
     };
 
@@ -389,13 +409,9 @@ pub async fn send_email(
     {
         Ok(_) => {
             info!("[api] email sent to {}", body.to);
-            (StatusCode::OK, Json(json!({"status": "sent"}))).into_response()
+            StoredResponse::new(StatusCode::OK, json!({"status": "sent"}))
         }
-        Err(e) => json_error(
-            StatusCode::BAD_GATEWAY,
-            &format!("SMTP error: {}", e),
-        )
-        .into_response(),
+        Err(e) => stored_error(StatusCode::BAD_GATEWAY, &format!("SMTP error: {}", e)),
     }
 }
 
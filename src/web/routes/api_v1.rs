@@ -0,0 +1,535 @@
+//! JSON REST API for scripting domain and account provisioning.
+//!
+//! Every route is nested under `/api/v1` and requires an API key in
+//! `Authorization: Bearer <key>`.  Keys are created on the `/api` admin page;
+//! only a prefix and a hash of each key are stored.  Read-only keys get
+//! `403 Forbidden` on write endpoints.
+//!
+//! Endpoints:
+//!   `GET  /api/v1/domains`      — List domains
+//!   `GET  /api/v1/domains/:id`  — Get a domain
+//!   `POST /api/v1/domains`      — Create a domain
+//!   `GET  /api/v1/accounts`     — List accounts (`?page=&per_page=&q=`)
+//!   `GET  /api/v1/accounts/:id` — Get an account
+//!   `POST /api/v1/accounts`     — Create an account
+//!
+//! Both create endpoints honour `Idempotency-Key`, scoped to the API key (see
+//! [`crate::web::idempotency`]).
+
+use axum::{
+    extract::{rejection::JsonRejection, Path, Query, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Extension, Json, Router,
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::db::{Account, Domain};
+use crate::web::idempotency::{self, IdempotencyKey, IdempotencyStore, StoredResponse};
+use crate::web::{audit_as, fire_webhook, regen_configs, AppState};
+
+const KEY_PREFIX: &str = "msk_";
+const DEFAULT_PER_PAGE: i64 = 50;
+const MAX_PER_PAGE: i64 = 200;
+
+/// The API key that authenticated the current request.
+#[derive(Clone)]
+pub struct ApiKeyAuth {
+    pub name: String,
+    pub read_only: bool,
+}
+
+impl ApiKeyAuth {
+    fn actor(&self) -> String {
+        format!("api-key:{}", self.name)
+    }
+}
+
+/// Generate a new key as `(plaintext, prefix)`.  The plaintext has the form
+/// `msk_<prefix>_<secret>`; the prefix identifies the key row.
+pub fn generate_key() -> (String, String) {
+    let prefix = uuid::Uuid::new_v4().simple().to_string()[..8].to_string();
+    let secret = uuid::Uuid::new_v4().simple().to_string();
+    (format!("{}{}_{}", KEY_PREFIX, prefix, secret), prefix)
+}
+
+/// Lookup prefix of a presented key, or `None` if it is not shaped like one.
+fn key_prefix(key: &str) -> Option<&str> {
+    let (prefix, secret) = key.strip_prefix(KEY_PREFIX)?.split_once('_')?;
+    if prefix.len() == 8 && !secret.is_empty() {
+        Some(prefix)
+    } else {
+        None
+    }
+}
+
+fn json_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({"error": message}))).into_response()
+}
+
+fn unauthorized(message: &str) -> Response {
+    let mut resp = json_error(StatusCode::UNAUTHORIZED, message);
+    resp.headers_mut().insert(
+        header::WWW_AUTHENTICATE,
+        header::HeaderValue::from_static("Bearer realm=\"mailserver-api\""),
+    );
+    resp
+}
+
+/// `403` response for write attempts with a read-only key.
+fn forbid_read_only(key: &ApiKeyAuth) -> Option<Response> {
+    if key.read_only {
+        warn!("[api] read-only key {} attempted a write", key.name);
+        return Some(json_error(StatusCode::FORBIDDEN, "API key is read-only"));
+    }
+    None
+}
+
+pub(crate) async fn require_api_key(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim().to_string());
+    let Some(presented) = presented else {
+        warn!("[api] missing bearer API key for {}", req.uri());
+        return unauthorized("missing bearer API key");
+    };
+    let Some(prefix) = key_prefix(&presented).map(str::to_string) else {
+        warn!("[api] malformed API key for {}", req.uri());
+        return unauthorized("invalid API key");
+    };
+    let key = state
+        .blocking_db(move |db| {
+            let key = db.get_active_api_key(&prefix)?;
+            if !crate::auth::verify_password(&presented, &key.key_hash) {
+                return None;
+            }
+            db.touch_api_key(key.id);
+            Some(key)
+        })
+        .await;
+    let Some(key) = key else {
+        warn!("[api] rejected API key for {}", req.uri());
+        return unauthorized("invalid API key");
+    };
+    info!(
+        "[api] {} {} authenticated as key={}",
+        req.method(),
+        req.uri(),
+        key.name
+    );
+    req.extensions_mut().insert(ApiKeyAuth {
+        name: key.name,
+        read_only: key.read_only,
+    });
+    next.run(req).await
+}
+
+pub fn routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/domains", get(list_domains).post(create_domain))
+        .route("/domains/:id", get(get_domain))
+        .route("/accounts", get(list_accounts).post(create_account))
+        .route("/accounts/:id", get(get_account))
+        .route_layer(middleware::from_fn_with_state(state, require_api_key))
+}
+
+// ── JSON views ──
+
+#[derive(Serialize)]
+struct DomainJson {
+    id: i64,
+    domain: String,
+    active: bool,
+    dkim_selector: String,
+    dkim_public_key: Option<String>,
+    unsubscribe_enabled: bool,
+    registration_enabled: bool,
+}
+
+impl From<Domain> for DomainJson {
+    fn from(d: Domain) -> Self {
+        DomainJson {
+            id: d.id,
+            domain: d.domain,
+            active: d.active,
+            dkim_selector: d.dkim_selector,
+            dkim_public_key: d.dkim_public_key,
+            unsubscribe_enabled: d.unsubscribe_enabled,
+            registration_enabled: d.registration_enabled,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AccountJson {
+    id: i64,
+    domain_id: i64,
+    email: String,
+    username: String,
+    name: String,
+    active: bool,
+    quota: i64,
+}
+
+impl From<Account> for AccountJson {
+    fn from(a: Account) -> Self {
+        AccountJson {
+            id: a.id,
+            domain_id: a.domain_id,
+            email: format!("{}@{}", a.username, a.domain_name.as_deref().unwrap_or("?")),
+            username: a.username,
+            name: a.name,
+            active: a.active,
+            quota: a.quota,
+        }
+    }
+}
+
+// ── Request bodies ──
+
+#[derive(Deserialize, Serialize)]
+pub struct CreateDomainBody {
+    pub domain: String,
+    #[serde(default)]
+    pub bimi_svg: String,
+    #[serde(default)]
+    pub unsubscribe_enabled: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct CreateAccountBody {
+    pub domain_id: i64,
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub quota: i64,
+}
+
+#[derive(Deserialize)]
+pub struct AccountListQuery {
+    #[serde(default)]
+    pub page: Option<i64>,
+    #[serde(default)]
+    pub per_page: Option<i64>,
+    #[serde(default)]
+    pub q: String,
+}
+
+/// Response for a failed create.  Database errors are logged, not returned:
+/// their text names tables and constraints.
+fn create_error(e: &str) -> StoredResponse {
+    if e.contains("duplicate key") {
+        StoredResponse::new(StatusCode::CONFLICT, json!({"error": "already exists"}))
+    } else if e.contains(crate::db::IN_RECYCLE_BIN) {
+        StoredResponse::new(StatusCode::CONFLICT, json!({"error": e}))
+    } else {
+        error!("[web] API create failed: {}", e);
+        StoredResponse::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            json!({"error": "internal error"}),
+        )
+    }
+}
+
+/// Run `create` at most once per idempotency key.  Returns the response,
+/// and the new entity when `create` ran and succeeded so the caller can
+/// announce it; a replayed response returns none.
+fn create_once<S, T>(
+    store: &S,
+    key: Option<&IdempotencyKey>,
+    create: impl FnOnce() -> Result<T, String>,
+) -> (StoredResponse, Option<T>)
+where
+    S: IdempotencyStore,
+    T: Serialize,
+{
+    let mut created = None;
+    let response = idempotency::run(store, key, || match create() {
+        Ok(entity) => {
+            let body = serde_json::to_value(&entity).unwrap_or_default();
+            created = Some(entity);
+            StoredResponse::new(StatusCode::CREATED, body)
+        }
+        Err(e) => create_error(&e),
+    });
+    (response, created)
+}
+
+// ── Handlers ──
+
+async fn list_domains(State(state): State<AppState>) -> Response {
+    let domains = state.blocking_db(|db| db.list_domains()).await;
+    let domains: Vec<DomainJson> = domains.into_iter().map(DomainJson::from).collect();
+    Json(json!({ "domains": domains })).into_response()
+}
+
+async fn get_domain(State(state): State<AppState>, Path(id): Path<i64>) -> Response {
+    match state.blocking_db(move |db| db.get_domain(id)).await {
+        Some(d) => Json(DomainJson::from(d)).into_response(),
+        None => json_error(StatusCode::NOT_FOUND, "domain not found"),
+    }
+}
+
+async fn create_domain(
+    State(state): State<AppState>,
+    Extension(key): Extension<ApiKeyAuth>,
+    headers: HeaderMap,
+    body: Result<Json<CreateDomainBody>, JsonRejection>,
+) -> Response {
+    if let Some(resp) = forbid_read_only(&key) {
+        return resp;
+    }
+    let Json(body) = match body {
+        Ok(b) => b,
+        Err(e) => return json_error(StatusCode::BAD_REQUEST, &e.body_text()),
+    };
+    let idempotency_key = match idempotency::request_key(&headers, &body) {
+        Ok(k) => k,
+        Err(msg) => return json_error(StatusCode::BAD_REQUEST, msg),
+    };
+    let domain = body.domain.trim().to_lowercase();
+    if domain.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "domain is required");
+    }
+    if !crate::validation::is_valid_domain(&domain) {
+        return json_error(StatusCode::BAD_REQUEST, crate::validation::DOMAIN_HINT);
+    }
+    info!("[api] creating domain={} via key={}", domain, key.name);
+    let name = domain.clone();
+    let (response, created) = state
+        .blocking_db(move |db| {
+            create_once(db, idempotency_key.as_ref(), || {
+                db.create_domain(&name, &body.bimi_svg, body.unsubscribe_enabled)
+                    .and_then(|id| {
+                        db.get_domain(id)
+                            .ok_or_else(|| "domain vanished after insert".to_string())
+                    })
+                    .map(DomainJson::from)
+            })
+        })
+        .await;
+    if let Some(d) = created {
+        regen_configs(&state).await;
+        audit_as(
+            &state,
+            key.actor(),
+            "domain.created",
+            "domain",
+            Some(d.id),
+            json!({"domain": domain}),
+        )
+        .await;
+        fire_webhook(&state, "domain.created", json!({"domain": domain}));
+    }
+    response.into_response()
+}
+
+async fn list_accounts(
+    State(state): State<AppState>,
+    Query(params): Query<AccountListQuery>,
+) -> Response {
+    let per_page = params
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let page = params.page.unwrap_or(1).max(1);
+    let search = Some(params.q.trim().to_string()).filter(|s| !s.is_empty());
+    let (total, accounts) = state
+        .blocking_db(move |db| {
+            let search = search.as_deref();
+            (
+                db.count_accounts(search),
+                db.list_accounts_paged(per_page, (page - 1) * per_page, search),
+            )
+        })
+        .await;
+    let accounts: Vec<AccountJson> = accounts.into_iter().map(AccountJson::from).collect();
+    Json(json!({
+        "accounts": accounts,
+        "page": page,
+        "per_page": per_page,
+        "total": total,
+    }))
+    .into_response()
+}
+
+async fn get_account(State(state): State<AppState>, Path(id): Path<i64>) -> Response {
+    match state
+        .blocking_db(move |db| db.get_account_with_domain(id))
+        .await
+    {
+        Some(a) => Json(AccountJson::from(a)).into_response(),
+        None => json_error(StatusCode::NOT_FOUND, "account not found"),
+    }
+}
+
+async fn create_account(
+    State(state): State<AppState>,
+    Extension(key): Extension<ApiKeyAuth>,
+    headers: HeaderMap,
+    body: Result<Json<CreateAccountBody>, JsonRejection>,
+) -> Response {
+    if let Some(resp) = forbid_read_only(&key) {
+        return resp;
+    }
+    let Json(body) = match body {
+        Ok(b) => b,
+        Err(e) => return json_error(StatusCode::BAD_REQUEST, &e.body_text()),
+    };
+    let idempotency_key = match idempotency::request_key(&headers, &body) {
+        Ok(k) => k,
+        Err(msg) => return json_error(StatusCode::BAD_REQUEST, msg),
+    };
+    let username = body.username.trim().to_lowercase();
+    if username.is_empty() || body.password.is_empty() {
+        return json_error(
+            StatusCode::BAD_REQUEST,
+            "username and password are required",
+        );
+    }
+    if !crate::validation::is_valid_localpart(&username) {
+        return json_error(StatusCode::BAD_REQUEST, crate::validation::LOCALPART_HINT);
+    }
+    let domain_id = body.domain_id;
+    if state
+        .blocking_db(move |db| db.get_domain(domain_id))
+        .await
+        .is_none()
+    {
+        return json_error(StatusCode::NOT_FOUND, "domain not found");
+    }
+    let hash = match crate::auth::hash_password(&body.password) {
+        Ok(h) => h,
+        Err(e) => {
+            error!("[api] failed to hash password for {}: {}", username, e);
+            return json_error(StatusCode::INTERNAL_SERVER_ERROR, "failed to hash password");
+        }
+    };
+    info!(
+        "[api] creating account username={} domain_id={} via key={}",
+        username, domain_id, key.name
+    );
+    let name = body.name;
+    let quota = body.quota.max(0);
+    let db_username = username.clone();
+    let (response, created) = state
+        .blocking_db(move |db| {
+            create_once(db, idempotency_key.as_ref(), || {
+                db.create_account(domain_id, &db_username, &hash, &name, quota)
+                    .and_then(|id| {
+                        db.get_account_with_domain(id)
+                            .ok_or_else(|| "account vanished after insert".to_string())
+                    })
+                    .map(AccountJson::from)
+            })
+        })
+        .await;
+    if let Some(a) = created {
+        regen_configs(&state).await;
+        let details = json!({"username": username, "domain_id": domain_id});
+        audit_as(
+            &state,
+            key.actor(),
+            "account.created",
+            "account",
+            Some(a.id),
+            details.clone(),
+        )
+        .await;
+        fire_webhook(&state, "account.created", details);
+    }
+    response.into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_keys_round_trip_their_prefix() {
+        let (key, prefix) = generate_key();
+        assert!(key.starts_with("msk_"));
+        assert_eq!(key_prefix(&key), Some(prefix.as_str()));
+        assert_ne!(generate_key().0, key);
+    }
+
+    #[test]
+    fn retried_domain_create_with_the_same_key_creates_one_domain() {
+        let store = idempotency::MemoryStore::default();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            "Bearer msk_abcdef12_secret".parse().unwrap(),
+        );
+        headers.insert(
+            idempotency::IDEMPOTENCY_KEY_HEADER,
+            "create-example".parse().unwrap(),
+        );
+        let body = json!({"domain": "example.com"});
+        let key = idempotency::request_key(&headers, &body).unwrap();
+        let created = std::cell::Cell::new(0);
+        let create = || {
+            created.set(created.get() + 1);
+            Ok(json!({"id": created.get(), "domain": "example.com"}))
+        };
+
+        let (first, new) = create_once(&store, key.as_ref(), create);
+        assert_eq!(first.status, StatusCode::CREATED);
+        assert!(new.is_some());
+        let (second, new) = create_once(&store, key.as_ref(), create);
+        assert_eq!(created.get(), 1);
+        assert_eq!(second, first);
+        assert!(new.is_none(), "a replay must not announce the domain again");
+
+        // Another API key with the same idempotency key is a new request.
+        headers.insert(
+            header::AUTHORIZATION,
+            "Bearer msk_12345678_other".parse().unwrap(),
+        );
+        let other = idempotency::request_key(&headers, &body).unwrap();
+        let (_, new) = create_once(&store, other.as_ref(), create);
+        assert!(new.is_some());
+        assert_eq!(created.get(), 2);
+
+        // A duplicate is a stored 409, not a server error.
+        let (conflict, _) = create_once::<_, serde_json::Value>(&store, None, || {
+            Err("duplicate key value violates unique constraint".to_string())
+        });
+        assert_eq!(conflict.status, StatusCode::CONFLICT);
+
+        // A name held by the recycle bin says how to free it.
+        let binned = "domain example.com is in the recycle bin: restore or purge it there first";
+        let (conflict, _) =
+            create_once::<_, serde_json::Value>(&store, None, || Err(binned.to_string()));
+        assert_eq!(conflict.status, StatusCode::CONFLICT);
+        assert_eq!(conflict.body, json!({"error": binned}));
+
+        // Other failures do not reveal the database error.
+        let (failed, _) = create_once::<_, serde_json::Value>(&store, None, || {
+            Err("db error: ERROR: relation \"domains\" does not exist".to_string())
+        });
+        assert_eq!(failed.status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(failed.body, json!({"error": "internal error"}));
+    }
+
+    #[test]
+    fn malformed_keys_have_no_prefix() {
+        assert_eq!(key_prefix("abc123token"), None);
+        assert_eq!(key_prefix("msk_short_secret"), None);
+        assert_eq!(key_prefix("msk_abcdef12_"), None);
+        assert_eq!(key_prefix("msk_abcdef12"), None);
+    }
+}
@@ -0,0 +1,422 @@
+//! Two-person approval for destructive admin actions.
+//!
+//! When the `two_person_approval` setting is on, deleting or purging a
+//! domain, purging an account, and purging the mail queue only record a
+//! pending approval.  A different admin must approve it from the Approvals
+//! page before it runs; requests expire after `approval_ttl_hours`.
+
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use log::{info, warn};
+
+use crate::db::{Database, PendingApproval};
+use crate::web::auth::AuthAdmin;
+use crate::web::{audit, audit_as, fire_webhook, regen_configs, AppState};
+
+pub(crate) const ENABLED_SETTING: &str = "two_person_approval";
+pub(crate) const TTL_SETTING: &str = "approval_ttl_hours";
+pub(crate) const DEFAULT_TTL_HOURS: i64 = 24;
+
+/// An action that needs a second admin when two-person approval is on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DestructiveAction {
+    DeleteDomain(i64),
+    PurgeDomain(i64),
+    PurgeAccount(i64),
+    PurgeQueue,
+}
+
+impl DestructiveAction {
+    fn name(self) -> &'static str {
+        match self {
+            DestructiveAction::DeleteDomain(_) => "domain.delete",
+            DestructiveAction::PurgeDomain(_) => "domain.purge",
+            DestructiveAction::PurgeAccount(_) => "account.purge",
+            DestructiveAction::PurgeQueue => "queue.purge",
+        }
+    }
+
+    fn target_id(self) -> Option<i64> {
+        match self {
+            DestructiveAction::DeleteDomain(id)
+            | DestructiveAction::PurgeDomain(id)
+            | DestructiveAction::PurgeAccount(id) => Some(id),
+            DestructiveAction::PurgeQueue => None,
+        }
+    }
+
+    fn from_parts(name: &str, target_id: Option<i64>) -> Option<Self> {
+        match (name, target_id) {
+            ("domain.delete", Some(id)) => Some(DestructiveAction::DeleteDomain(id)),
+            ("domain.purge", Some(id)) => Some(DestructiveAction::PurgeDomain(id)),
+            ("account.purge", Some(id)) => Some(DestructiveAction::PurgeAccount(id)),
+            ("queue.purge", None) => Some(DestructiveAction::PurgeQueue),
+            _ => None,
+        }
+    }
+
+    /// Audit action and target type recorded once the action has run.
+    fn audit_event(self) -> (&'static str, &'static str) {
+        match self {
+            DestructiveAction::DeleteDomain(_) => ("domain.deleted", "domain"),
+            DestructiveAction::PurgeDomain(_) => ("domain.purged", "domain"),
+            DestructiveAction::PurgeAccount(_) => ("account.purged", "account"),
+            DestructiveAction::PurgeQueue => ("queue.purged", "queue"),
+        }
+    }
+
+    /// Human-readable name of what the action destroys, stored with a
+    /// pending request so the approver sees it even if ids are reused.
+    fn target_name(self, db: &Database) -> String {
+        let find = |items: Vec<crate::db::RecycleBinItem>, id: i64| {
+            items
+                .into_iter()
+                .find(|i| i.id == id)
+                .map(|i| i.name)
+                .unwrap_or_else(|| format!("#{}", id))
+        };
+        match self {
+            DestructiveAction::DeleteDomain(id) => db
+                .get_domain(id)
+                .map(|d| d.domain)
+                .unwrap_or_else(|| format!("#{}", id)),
+            DestructiveAction::PurgeDomain(id) => find(db.list_deleted_domains(), id),
+            DestructiveAction::PurgeAccount(id) => find(db.list_deleted_accounts(), id),
+            DestructiveAction::PurgeQueue => "all queued messages".to_string(),
+        }
+    }
+
+    /// Carry out the action's database or queue change.
+    fn run(self, db: &Database) {
+        warn!(
+            "[approvals] running {} target_id={:?}",
+            self.name(),
+            self.target_id()
+        );
+        match self {
+            DestructiveAction::DeleteDomain(id) => db.delete_domain(id),
+            DestructiveAction::PurgeDomain(id) => db.purge_domain(id),
+            DestructiveAction::PurgeAccount(id) => db.purge_account(id),
+            DestructiveAction::PurgeQueue => super::queue::purge_all(),
+        }
+    }
+}
+
+pub(crate) fn required(db: &Database) -> bool {
+    db.get_setting(ENABLED_SETTING).as_deref() == Some("true")
+}
+
+pub(crate) fn ttl_hours(db: &Database) -> i64 {
+    db.get_setting(TTL_SETTING)
+        .and_then(|v| v.parse().ok())
+        .filter(|h: &i64| *h > 0)
+        .unwrap_or(DEFAULT_TTL_HOURS)
+}
+
+/// Record `action` as waiting for approval, returning the request id.
+fn request(db: &Database, action: DestructiveAction, requested_by: &str) -> Option<i64> {
+    let params = serde_json::json!({ "target": action.target_name(db) }).to_string();
+    db.create_pending_approval(
+        action.name(),
+        action.target_id(),
+        &params,
+        requested_by,
+        ttl_hours(db),
+    )
+}
+
+/// Approve request `id` as `approver` and run it.  Fails, without running
+/// anything, for the requester, or once the request is decided or expired.
+fn approve_and_run(
+    db: &Database,
+    id: i64,
+    approver: &str,
+) -> Result<(DestructiveAction, PendingApproval), String> {
+    let approval = db.approve_pending_approval(id, approver)?;
+    let action = DestructiveAction::from_parts(&approval.action, approval.target_id)
+        .ok_or_else(|| format!("Unknown action \"{}\".", approval.action))?;
+    action.run(db);
+    Ok((action, approval))
+}
+
+/// When two-person approval is on, hold `action` for a second admin and
+/// return the response to send instead of running it.
+pub(crate) async fn hold(
+    state: &AppState,
+    auth: &AuthAdmin,
+    action: DestructiveAction,
+) -> Option<Response> {
+    let requested_by = auth.admin.username.clone();
+    let id = state
+        .blocking_db(move |db| {
+            if required(db) {
+                Some(request(db, action, &requested_by))
+            } else {
+                None
+            }
+        })
+        .await?;
+    let Some(id) = id else {
+        let tmpl = ErrorTemplate {
+            nav_active: "Approvals",
+            flash: None,
+            status_code: 500,
+            status_text: "Error",
+            title: "Approval Request Failed",
+            message:
+                "The action needs a second admin's approval, but the request could not be recorded.",
+            back_url: "/approvals",
+            back_label: "Back to Approvals",
+        };
+        return Some(Html(tmpl.render().unwrap()).into_response());
+    };
+    info!(
+        "[web] {} held for approval id={} requested by {}",
+        action.name(),
+        id,
+        auth.admin.username
+    );
+    audit(
+        state,
+        auth,
+        "approval.requested",
+        "approval",
+        Some(id),
+        serde_json::json!({ "action": action.name(), "target_id": action.target_id() }),
+    )
+    .await;
+    Some(Redirect::to("/approvals").into_response())
+}
+
+/// Run `action` right away on behalf of `actor`.
+pub(crate) async fn perform(
+    state: &AppState,
+    actor: String,
+    action: DestructiveAction,
+    details: serde_json::Value,
+) {
+    state.blocking_db(move |db| action.run(db)).await;
+    finish(state, actor, action, details).await;
+}
+
+/// Config regeneration, audit entry and webhook that follow an action.
+async fn finish(
+    state: &AppState,
+    actor: String,
+    action: DestructiveAction,
+    details: serde_json::Value,
+) {
+    if let DestructiveAction::DeleteDomain(_) = action {
+        regen_configs(state).await;
+    }
+    let (event, target_type) = action.audit_event();
+    audit_as(
+        state,
+        actor,
+        event,
+        target_type,
+        action.target_id(),
+        details,
+    )
+    .await;
+    if let Some(id) = action.target_id() {
+        fire_webhook(state, event, serde_json::json!({ "id": id }));
+    }
+}
+
+// ── Templates ──
+
+struct ApprovalRow {
+    id: i64,
+    action: String,
+    target: String,
+    requested_by: String,
+    created_at: String,
+    expires_at: String,
+    own: bool,
+}
+
+#[derive(Template)]
+#[template(path = "approvals/list.html")]
+struct ListTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    enabled: bool,
+    approvals: Vec<ApprovalRow>,
+}
+
+#[derive(Template)]
+#[template(path = "error.html")]
+struct ErrorTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    status_code: u16,
+    status_text: &'a str,
+    title: &'a str,
+    message: &'a str,
+    back_url: &'a str,
+    back_label: &'a str,
+}
+
+// ── Handlers ──
+
+pub async fn list(auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
+    info!("[web] GET /approvals — listing pending approvals");
+    let (enabled, pending) = state
+        .blocking_db(|db| (required(db), db.list_pending_approvals()))
+        .await;
+    let approvals = pending
+        .into_iter()
+        .map(|a| {
+            let target = serde_json::from_str::<serde_json::Value>(&a.params)
+                .ok()
+                .and_then(|p| p["target"].as_str().map(String::from))
+                .unwrap_or_default();
+            ApprovalRow {
+                id: a.id,
+                own: a.requested_by == auth.admin.username,
+                action: a.action,
+                target,
+                requested_by: a.requested_by,
+                created_at: a.created_at,
+                expires_at: a.expires_at,
+            }
+        })
+        .collect();
+    let tmpl = ListTemplate {
+        nav_active: "Approvals",
+        flash: None,
+        enabled,
+        approvals,
+    };
+    Html(tmpl.render().unwrap())
+}
+
+pub async fn approve(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    warn!(
+        "[web] POST /approvals/{}/approve — approving by username={}",
+        id, auth.admin.username
+    );
+    let approver = auth.admin.username.clone();
+    let result = state
+        .blocking_db(move |db| approve_and_run(db, id, &approver))
+        .await;
+    let (action, approval) = match result {
+        Ok(done) => done,
+        Err(message) => {
+            warn!("[web] approval id={} refused: {}", id, message);
+            let tmpl = ErrorTemplate {
+                nav_active: "Approvals",
+                flash: None,
+                status_code: 400,
+                status_text: "Bad Request",
+                title: "Cannot Approve",
+                message: &message,
+                back_url: "/approvals",
+                back_label: "Back to Approvals",
+            };
+            return Html(tmpl.render().unwrap()).into_response();
+        }
+    };
+    audit(
+        &state,
+        &auth,
+        "approval.approved",
+        "approval",
+        Some(id),
+        serde_json::json!({ "action": approval.action, "requested_by": approval.requested_by }),
+    )
+    .await;
+    finish(
+        &state,
+        auth.admin.username.clone(),
+        action,
+        serde_json::json!({ "approval_id": id, "requested_by": approval.requested_by }),
+    )
+    .await;
+    Redirect::to("/approvals").into_response()
+}
+
+pub async fn cancel(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    info!(
+        "[web] POST /approvals/{}/cancel — cancelling by username={}",
+        id, auth.admin.username
+    );
+    let by = auth.admin.username.clone();
+    if state
+        .blocking_db(move |db| db.cancel_pending_approval(id, &by))
+        .await
+    {
+        audit(
+            &state,
+            &auth,
+            "approval.cancelled",
+            "approval",
+            Some(id),
+            serde_json::json!({}),
+        )
+        .await;
+    }
+    Redirect::to("/approvals").into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs a reachable server: run with `TEST_DATABASE_URL` set and
+    /// `cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn action_waits_for_a_different_admin() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let db = Database::open(&url);
+        let name = format!("approval-{}.test", std::process::id());
+        let domain_id = db.create_domain(&name, "", false).unwrap();
+        let action = DestructiveAction::DeleteDomain(domain_id);
+
+        let id = request(&db, action, "alice").unwrap();
+        assert!(db.get_domain(domain_id).is_some());
+        let pending = db.list_pending_approvals();
+        let record = pending.iter().find(|a| a.id == id).unwrap();
+        assert_eq!(record.action, "domain.delete");
+        assert!(record.params.contains(&name));
+
+        assert!(approve_and_run(&db, id, "alice").is_err());
+        assert!(db.get_domain(domain_id).is_some());
+
+        let (ran, _) = approve_and_run(&db, id, "bob").unwrap();
+        assert_eq!(ran, action);
+        assert!(db.get_domain(domain_id).is_none());
+        assert!(approve_and_run(&db, id, "carol").is_err());
+
+        db.purge_domain(domain_id);
+    }
+
+    #[test]
+    fn action_names_round_trip() {
+        for action in [
+            DestructiveAction::DeleteDomain(7),
+            DestructiveAction::PurgeDomain(7),
+            DestructiveAction::PurgeAccount(7),
+            DestructiveAction::PurgeQueue,
+        ] {
+            assert_eq!(
+                DestructiveAction::from_parts(action.name(), action.target_id()),
+                Some(action)
+            );
+        }
+        assert_eq!(DestructiveAction::from_parts("queue.purge", Some(1)), None);
+    }
+}
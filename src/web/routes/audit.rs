@@ -0,0 +1,70 @@
+use askama::Template;
+use axum::{
+    extract::{Query, State},
+    response::Html,
+};
+use log::{debug, info};
+use serde::Deserialize;
+
+use crate::db::AuditLogEntry;
+use crate::web::auth::AuthAdmin;
+use crate::web::AppState;
+
+const PAGE_SIZE: i64 = 50;
+
+// ── Query params ──
+
+#[derive(Deserialize)]
+pub struct PageParams {
+    #[serde(default = "default_page")]
+    page: i64,
+}
+
+fn default_page() -> i64 {
+    1
+}
+
+// ── Templates ──
+
+#[derive(Template)]
+#[template(path = "audit/list.html")]
+struct ListTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    entries: Vec<AuditLogEntry>,
+    page: i64,
+    total_pages: i64,
+    total_count: i64,
+}
+
+// ── Handlers ──
+
+pub async fn list(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Query(params): Query<PageParams>,
+) -> Html<String> {
+    let page = params.page.max(1);
+    info!("[web] GET /audit — page={}", page);
+
+    let total_count = state.blocking_db(|db| db.count_audit_log()).await;
+    let total_pages = ((total_count as f64) / (PAGE_SIZE as f64)).ceil() as i64;
+    let total_pages = total_pages.max(1);
+    let page = page.min(total_pages);
+    let offset = (page - 1) * PAGE_SIZE;
+
+    let entries = state
+        .blocking_db(move |db| db.list_audit_log(PAGE_SIZE, offset))
+        .await;
+    debug!("[web] /audit page={} returned {} rows", page, entries.len());
+
+    let tmpl = ListTemplate {
+        nav_active: "Audit Log",
+        flash: None,
+        entries,
+        page,
+        total_pages,
+        total_count,
+    };
+    Html(tmpl.render().unwrap())
+}
@@ -394,8 +394,19 @@ async fn handle_report(
                     };
                     xml_multistatus(report_objects_xml(&filtered, &email, &slug))
                 } else {
-                    let all: Vec<&CardDavObject> = objects.iter().collect();
-                    xml_multistatus(report_objects_xml(&all, &email, &slug))
+                    let query = parse_addressbook_query(report_body);
+                    let matched: Vec<&CardDavObject> = objects
+                        .iter()
+                        .filter(|o| query.matches(&o.data))
+                        .take(query.limit.unwrap_or(usize::MAX))
+                        .collect();
+                    debug!(
+                        "[carddav] addressbook-query on {} matched {} of {} cards",
+                        slug,
+                        matched.len(),
+                        objects.len()
+                    );
+                    xml_multistatus(report_objects_xml(&matched, &email, &slug))
                 }
             }
         }
@@ -846,6 +857,212 @@ fn report_objects_xml(objects: &[&CardDavObject], email: &str, slug: &str) -> St
     )
 }
 
+// ── CardDAV Protocol: addressbook-query Filters ──
+
+/// The `<filter>` and `<limit>` of an addressbook-query REPORT (RFC 6352
+/// §8.6).  Property filters with `text-match` and `is-not-defined` are
+/// applied; `param-filter` elements are ignored.
+#[derive(Debug, Default)]
+struct AddressBookQuery {
+    all_of: bool,
+    prop_filters: Vec<PropFilter>,
+    limit: Option<usize>,
+}
+
+#[derive(Debug, Default)]
+struct PropFilter {
+    name: String,
+    all_of: bool,
+    is_not_defined: bool,
+    text_matches: Vec<TextMatch>,
+}
+
+#[derive(Debug)]
+struct TextMatch {
+    text: String,
+    match_type: MatchType,
+    negate: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MatchType {
+    Equals,
+    Contains,
+    StartsWith,
+    EndsWith,
+}
+
+fn parse_addressbook_query(body: &str) -> AddressBookQuery {
+    use quick_xml::events::{BytesStart, Event};
+    use quick_xml::Reader;
+
+    fn attr(e: &BytesStart, name: &str) -> Option<String> {
+        e.attributes()
+            .flatten()
+            .find(|a| a.key.local_name().as_ref() == name.as_bytes())
+            .map(|a| String::from_utf8_lossy(&a.value).into_owned())
+    }
+    fn is_all_of(e: &BytesStart) -> bool {
+        attr(e, "test").is_some_and(|t| t.eq_ignore_ascii_case("allof"))
+    }
+
+    let mut reader = Reader::from_reader(body.as_bytes());
+    reader.config_mut().trim_text(true);
+    let mut query = AddressBookQuery::default();
+    let mut prop_filter: Option<PropFilter> = None;
+    let mut text_match: Option<TextMatch> = None;
+    let mut in_param_filter = false;
+    let mut in_nresults = false;
+    let mut buf = Vec::new();
+
+    loop {
+        let (e, empty) = match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) => (e, false),
+            Ok(Event::Empty(e)) => (e, true),
+            Ok(Event::Text(t)) => {
+                let text = String::from_utf8_lossy(t.as_ref()).into_owned();
+                if let Some(tm) = text_match.as_mut() {
+                    tm.text.push_str(&text);
+                } else if in_nresults {
+                    query.limit = text.trim().parse().ok();
+                }
+                buf.clear();
+                continue;
+            }
+            Ok(Event::End(e)) => {
+                match e.local_name().as_ref() {
+                    b"text-match" => {
+                        if let (Some(pf), Some(tm)) = (prop_filter.as_mut(), text_match.take()) {
+                            pf.text_matches.push(tm);
+                        }
+                    }
+                    b"param-filter" => in_param_filter = false,
+                    b"prop-filter" => query.prop_filters.extend(prop_filter.take()),
+                    b"nresults" => in_nresults = false,
+                    _ => {}
+                }
+                buf.clear();
+                continue;
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                warn!("[carddav] malformed addressbook-query: {}", e);
+                break;
+            }
+            _ => {
+                buf.clear();
+                continue;
+            }
+        };
+        match e.local_name().as_ref() {
+            b"filter" => query.all_of = is_all_of(&e),
+            b"prop-filter" => {
+                let pf = PropFilter {
+                    name: attr(&e, "name").unwrap_or_default(),
+                    all_of: is_all_of(&e),
+                    ..Default::default()
+                };
+                if empty {
+                    query.prop_filters.push(pf);
+                } else {
+                    prop_filter = Some(pf);
+                }
+            }
+            b"param-filter" => in_param_filter = !empty,
+            b"is-not-defined" if !in_param_filter => {
+                if let Some(pf) = prop_filter.as_mut() {
+                    pf.is_not_defined = true;
+                }
+            }
+            b"text-match" if !in_param_filter && !empty && prop_filter.is_some() => {
+                let match_type = match attr(&e, "match-type").as_deref() {
+                    Some("equals") => MatchType::Equals,
+                    Some("starts-with") => MatchType::StartsWith,
+                    Some("ends-with") => MatchType::EndsWith,
+                    _ => MatchType::Contains,
+                };
+                text_match = Some(TextMatch {
+                    text: String::new(),
+                    match_type,
+                    negate: attr(&e, "negate-condition").as_deref() == Some("yes"),
+                });
+            }
+            b"nresults" => in_nresults = !empty,
+            _ => {}
+        }
+        buf.clear();
+    }
+    query
+}
+
+/// Values of every `name` property in a vCard, after unfolding continuation
+/// lines.  Property groups (`item1.EMAIL`) and parameters are ignored.
+fn vcard_property_values(vcard: &str, name: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in vcard.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+        .into_iter()
+        .filter_map(|line| {
+            let (head, value) = line.split_once(':')?;
+            let prop = head.split(';').next()?;
+            let prop = prop.rsplit('.').next()?;
+            prop.eq_ignore_ascii_case(name).then(|| value.to_string())
+        })
+        .collect()
+}
+
+impl TextMatch {
+    /// Case-insensitive comparison, approximating the default
+    /// `i;unicode-casemap` collation.
+    fn matches(&self, value: &str) -> bool {
+        let value = value.to_lowercase();
+        let text = self.text.trim().to_lowercase();
+        let hit = match self.match_type {
+            MatchType::Equals => value == text,
+            MatchType::Contains => value.contains(&text),
+            MatchType::StartsWith => value.starts_with(&text),
+            MatchType::EndsWith => value.ends_with(&text),
+        };
+        hit != self.negate
+    }
+}
+
+impl PropFilter {
+    fn matches(&self, vcard: &str) -> bool {
+        let values = vcard_property_values(vcard, &self.name);
+        if self.is_not_defined {
+            return values.is_empty();
+        }
+        if self.text_matches.is_empty() {
+            return !values.is_empty();
+        }
+        let test = |tm: &TextMatch| values.iter().any(|v| tm.matches(v));
+        if self.all_of {
+            self.text_matches.iter().all(test)
+        } else {
+            self.text_matches.iter().any(test)
+        }
+    }
+}
+
+impl AddressBookQuery {
+    fn matches(&self, vcard: &str) -> bool {
+        if self.prop_filters.is_empty() {
+            return true;
+        }
+        if self.all_of {
+            self.prop_filters.iter().all(|pf| pf.matches(vcard))
+        } else {
+            self.prop_filters.iter().any(|pf| pf.matches(vcard))
+        }
+    }
+}
+
 // ── Utility Helpers ──
 
 fn xml_escape(s: &str) -> String {
@@ -923,3 +1140,65 @@ fn extract_hrefs_from_multiget(body: &str, email: &str, slug: &str) -> Vec<Strin
     }
     filenames
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JANE: &str = "BEGIN:VCARD\r\nVERSION:3.0\r\nUID:jane\r\nFN:Jane Doe\r\nitem1.EMAIL;TYPE=work:jane@exam\r\n ple.com\r\nEND:VCARD\r\n";
+    const BOB: &str = "BEGIN:VCARD\r\nVERSION:3.0\r\nUID:bob\r\nFN:Bob Smith\r\nTEL:+1 555 0100\r\nEND:VCARD\r\n";
+
+    #[test]
+    fn vcard_property_values_unfolds_and_strips_groups() {
+        assert_eq!(vcard_property_values(JANE, "email"), vec!["jane@example.com".to_string()]);
+        assert!(vcard_property_values(BOB, "EMAIL").is_empty());
+    }
+
+    #[test]
+    fn addressbook_query_text_match() {
+        let query = parse_addressbook_query(
+            r#"<C:addressbook-query xmlns:D="DAV:" xmlns:C="urn:ietf:params:xml:ns:carddav">
+  <D:prop><D:getetag/><C:address-data/></D:prop>
+  <C:filter test="anyof">
+    <C:prop-filter name="FN">
+      <C:text-match collation="i;unicode-casemap" match-type="starts-with">jane</C:text-match>
+    </C:prop-filter>
+    <C:prop-filter name="EMAIL">
+      <C:text-match match-type="ends-with">@bob.test</C:text-match>
+    </C:prop-filter>
+  </C:filter>
+  <C:limit><C:nresults>5</C:nresults></C:limit>
+</C:addressbook-query>"#,
+        );
+        assert_eq!(query.prop_filters.len(), 2);
+        assert_eq!(query.limit, Some(5));
+        assert!(query.matches(JANE));
+        assert!(!query.matches(BOB));
+    }
+
+    #[test]
+    fn addressbook_query_allof_negate_and_is_not_defined() {
+        let query = parse_addressbook_query(
+            r#"<C:addressbook-query xmlns:C="urn:ietf:params:xml:ns:carddav">
+  <C:filter test="allof">
+    <C:prop-filter name="EMAIL"><C:is-not-defined/></C:prop-filter>
+    <C:prop-filter name="FN">
+      <C:text-match negate-condition="yes">jane</C:text-match>
+    </C:prop-filter>
+  </C:filter>
+</C:addressbook-query>"#,
+        );
+        assert!(query.all_of);
+        assert!(!query.matches(JANE));
+        assert!(query.matches(BOB));
+    }
+
+    #[test]
+    fn addressbook_query_without_filter_matches_everything() {
+        let query = parse_addressbook_query(
+            r#"<C:addressbook-query xmlns:C="urn:ietf:params:xml:ns:carddav"><C:filter/></C:addressbook-query>"#,
+        );
+        assert!(query.matches(JANE) && query.matches(BOB));
+        assert_eq!(query.limit, None);
+    }
+}
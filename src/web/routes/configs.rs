@@ -1,10 +1,17 @@
 use askama::Template;
-use axum::{extract::State, response::Html};
-use log::{debug, error};
+use axum::{
+    extract::State,
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use log::{debug, error, info, warn};
 use std::fs;
 
+use crate::relay_guard::{self, RelayDomains};
 use crate::web::auth::AuthAdmin;
+use crate::web::forms::RelayPolicyForm;
 use crate::web::AppState;
+use crate::web::{audit, fire_webhook};
 
 #[derive(Template)]
 #[template(path = "configs/view.html")]
@@ -12,6 +19,19 @@ struct ConfigsTemplate<'a> {
     nav_active: &'a str,
     flash: Option<&'a str>,
     config_files: Vec<ConfigFile>,
+    policy: RelayPolicy,
+}
+
+/// The relay authorization policy as currently applied, shown above the
+/// generated files so an admin can see who may relay and why.
+#[derive(Debug)]
+struct RelayPolicy {
+    local_domains: Vec<String>,
+    accepted_domains: String,
+    forward_targets: usize,
+    restrictions: Vec<String>,
+    skipped_assignments: Vec<String>,
+    problems: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -22,7 +42,7 @@ struct ConfigFile {
     error: Option<String>,
 }
 
-pub async fn page(auth: AuthAdmin, State(_state): State<AppState>) -> Html<String> {
+pub async fn page(auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
     debug!(
         "[web] GET /configs — config files page for username={}",
         auth.admin.username
@@ -35,6 +55,7 @@ pub async fn page(auth: AuthAdmin, State(_state): State<AppState>) -> Html<Strin
         ("Virtual Mailboxes", "/etc/postfix/vmailbox"),
         ("Virtual Aliases", "/etc/postfix/virtual_aliases"),
         ("Sender Login Maps", "/etc/postfix/sender_login_maps"),
+        ("Sender Transport (HELO identities)", "/etc/postfix/sender_transport"),
         ("Dovecot Config", "/etc/dovecot/dovecot.conf"),
         ("OpenDKIM Config", "/etc/opendkim/opendkim.conf"),
         ("OpenDKIM KeyTable", "/etc/opendkim/KeyTable"),
@@ -58,10 +79,19 @@ pub async fn page(auth: AuthAdmin, State(_state): State<AppState>) -> Html<Strin
         });
     }
 
+    let main_cf = config_files
+        .first()
+        .map(|f| f.content.clone())
+        .unwrap_or_default();
+    let policy = state
+        .blocking_db(move |db| relay_policy(db, &main_cf))
+        .await;
+
     let tmpl = ConfigsTemplate {
         nav_active: "Configs",
         flash: None,
         config_files,
+        policy,
     };
 
     match tmpl.render() {
@@ -78,3 +108,87 @@ pub async fn page(auth: AuthAdmin, State(_state): State<AppState>) -> Html<Strin
         }
     }
 }
+
+fn relay_policy(db: &crate::db::Database, main_cf: &str) -> RelayPolicy {
+    let domains = RelayDomains::load(db);
+    let skipped_assignments = db
+        .get_active_relay_assignments_with_relay()
+        .into_iter()
+        .filter(|(_, a)| !relay_guard::assignment_allowed(&a.pattern, &domains))
+        .map(|(r, a)| format!("{} -> {}", a.pattern, r.name))
+        .collect();
+    let restrictions = main_cf
+        .lines()
+        .filter(|l| {
+            let l = l.trim_start();
+            l.starts_with("smtpd_relay_restrictions")
+                || l.starts_with("smtpd_recipient_restrictions")
+                || l.starts_with("mynetworks")
+        })
+        .map(|l| l.to_string())
+        .collect();
+    let problems = if main_cf.is_empty() {
+        Vec::new()
+    } else {
+        relay_guard::audit_main_cf(main_cf)
+    };
+    RelayPolicy {
+        local_domains: domains.local,
+        accepted_domains: domains.extra.join(", "),
+        forward_targets: domains.forward_targets.len(),
+        restrictions,
+        skipped_assignments,
+        problems,
+    }
+}
+
+pub async fn update_relay_policy(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<RelayPolicyForm>,
+) -> Response {
+    info!(
+        "[web] POST /configs/relay-policy — update accepted relay domains by username={}",
+        auth.admin.username
+    );
+
+    let domains = relay_guard::parse_domain_list(&form.relay_accepted_domains);
+    let invalid: Vec<&String> = domains
+        .iter()
+        .filter(|d| !d.contains('.') || d.contains('@'))
+        .collect();
+    if !invalid.is_empty() {
+        warn!("[web] rejected invalid relay domains: {:?}", invalid);
+        return crate::web::errors::render_error_page(
+            axum::http::StatusCode::BAD_REQUEST,
+            "Invalid Domain",
+            "Accepted relay domains must be plain domain names such as example.com.",
+            "/configs",
+            "Back to Configs",
+        )
+        .into_response();
+    }
+
+    let value = domains.join(", ");
+    let saved = value.clone();
+    state
+        .blocking_db(move |db| db.set_setting(relay_guard::ACCEPTED_DOMAINS_SETTING, &saved))
+        .await;
+    crate::web::regen_configs(&state).await;
+
+    audit(
+        &state,
+        &auth,
+        "settings.relay_policy_updated",
+        "setting",
+        None,
+        serde_json::json!({"relay_accepted_domains": value}),
+    )
+    .await;
+    fire_webhook(
+        &state,
+        "settings.relay_policy_updated",
+        serde_json::json!({"relay_accepted_domains": value}),
+    );
+    Redirect::to("/configs").into_response()
+}
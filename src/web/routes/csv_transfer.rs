@@ -0,0 +1,93 @@
+//! Shared pieces of the domain and account CSV import/export handlers.
+
+use askama::Template;
+use axum::{
+    body::Body,
+    extract::Multipart,
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+use log::{error, warn};
+
+use crate::csv_transfer::{ImportReport, RowResult};
+
+#[derive(Template)]
+#[template(path = "import_result.html")]
+struct ImportResultTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    title: &'a str,
+    back_url: &'a str,
+    created: usize,
+    error: Option<String>,
+    rows: Vec<RowResult>,
+}
+
+/// Read the `file` field of a multipart upload as text.
+pub async fn read_upload(mut multipart: Multipart) -> Result<String, String> {
+    while let Ok(Some(field)) = multipart.next_field().await {
+        if field.name() != Some("file") {
+            continue;
+        }
+        return field.text().await.map_err(|e| {
+            warn!("[web] failed to read upload: {}", e);
+            format!("could not read the uploaded file: {}", e)
+        });
+    }
+    Err("no file was uploaded".to_string())
+}
+
+/// Per-row import results page: `200` when everything was created, `400`
+/// when validation failed and `500` when the database rolled back.
+pub fn report_response(
+    nav_active: &str,
+    title: &str,
+    back_url: &str,
+    report: ImportReport,
+) -> Response {
+    let status = match &report.error {
+        None => StatusCode::OK,
+        Some(_) if report.rows.iter().any(|r| r.error.is_some()) || report.rows.is_empty() => {
+            StatusCode::BAD_REQUEST
+        }
+        Some(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let tmpl = ImportResultTemplate {
+        nav_active,
+        flash: None,
+        title,
+        back_url,
+        created: report.created,
+        error: report.error,
+        rows: report.rows,
+    };
+    match tmpl.render() {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => {
+            error!("[web] failed to render import result template: {}", e);
+            crate::web::errors::status_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Template Error",
+                "Failed to render import results.",
+                back_url,
+                "Back",
+            )
+        }
+    }
+}
+
+/// Stream `records` as a CSV attachment named `filename`.
+pub fn csv_download(filename: &str, records: Vec<String>) -> Response {
+    let stream = tokio_stream::iter(records.into_iter().map(Ok::<_, std::convert::Infallible>));
+    (
+        [
+            (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
@@ -1,10 +1,14 @@
 use askama::Template;
-use axum::{extract::State, response::Html};
+use axum::{extract::State, middleware, response::Html, routing::get, Json, Router};
 use log::{debug, info};
+use serde::Serialize;
 
 use crate::web::auth::AuthAdmin;
 use crate::web::AppState;
 
+/// Days covered by the series in `/api/stats`.
+const SERIES_DAYS: i64 = 30;
+
 // ── Templates ──
 
 #[derive(Template)]
@@ -27,7 +31,7 @@ pub async fn page(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
     };
 
     debug!(
-        "[web] dashboard stats: domains={}, accounts={}, aliases={}, forwarding={}, tracked={}, opens={}, banned={}, webhooks={}, unsubs={}, dkim_ready={}, idle_sessions={}",
+        "[web] dashboard stats: domains={}, accounts={}, aliases={}, forwarding={}, tracked={}, opens={}, banned={}, webhooks={}, unsubs={}, dkim_ready={}, logins={}, dormant={}, idle_sessions={}",
         stats.domain_count,
         stats.account_count,
         stats.alias_count,
@@ -38,6 +42,8 @@ pub async fn page(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
         stats.webhook_count,
         stats.unsubscribe_count,
         stats.dkim_ready_count,
+        stats.login_count,
+        stats.dormant_account_count,
         idle_session_count,
     );
 
@@ -50,3 +56,44 @@ pub async fn page(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
     };
     Html(tmpl.render().unwrap())
 }
+
+// ── JSON stats ──
+
+#[derive(Serialize)]
+pub struct StatsSeries {
+    opens_per_day: Vec<crate::db::DailyCount>,
+    bans_per_day: Vec<crate::db::DailyCount>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    #[serde(flatten)]
+    stats: crate::db::Stats,
+    series: StatsSeries,
+}
+
+/// `/api/stats`, guarded by the same API keys as `/api/v1`.
+pub fn stats_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/api/stats", get(stats_json))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            super::api_v1::require_api_key,
+        ))
+}
+
+/// The dashboard figures as JSON, with opens and bans per day for the last
+/// [`SERIES_DAYS`] days, for external dashboards.
+pub async fn stats_json(State(state): State<AppState>) -> Json<StatsResponse> {
+    debug!("[web] GET /api/stats — aggregate stats");
+    let response = state
+        .blocking_db(|db| StatsResponse {
+            stats: db.get_stats(),
+            series: StatsSeries {
+                opens_per_day: db.opens_per_day(SERIES_DAYS),
+                bans_per_day: db.bans_per_day(SERIES_DAYS),
+            },
+        })
+        .await;
+    Json(response)
+}
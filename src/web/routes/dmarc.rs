@@ -9,7 +9,7 @@ use serde::Deserialize;
 use std::collections::HashSet;
 use std::io::Read;
 
-use crate::db::DmarcInbox;
+use crate::db::{DmarcInbox, DmarcReportRow, DmarcSourceSummary};
 use crate::web::{auth::AuthAdmin, fire_webhook, AppState};
 
 // ── Constants ──
@@ -66,48 +66,118 @@ pub struct DmarcReport {
     pub email_subject: String,
     pub email_date: String,
     pub email_timestamp: i64,
-    #[allow(dead_code)]
     pub email_filename: String,
     pub meta: DmarcReportMeta,
     pub policy: DmarcPolicy,
     pub records: Vec<DmarcRecord>,
 }
 
-// ── DMARC XML parsing ──
+/// A DMARC aggregate (RUA) report as parsed from its XML.
+#[derive(Default)]
+pub struct AggregateReport {
+    pub meta: DmarcReportMeta,
+    pub policy: DmarcPolicy,
+    pub records: Vec<DmarcRecord>,
+}
 
-/// Extract and decompress a DMARC XML report from email attachment bytes.
-/// Returns the raw XML bytes on success.
-fn decompress_dmarc_attachment(name: &str, data: &[u8]) -> Option<Vec<u8>> {
-    let lower = name.to_lowercase();
-    if lower.ends_with(".zip") {
-        // ZIP archive
-        let cursor = std::io::Cursor::new(data);
-        let mut archive = zip::ZipArchive::new(cursor).ok()?;
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i).ok()?;
-            let fname = file.name().to_lowercase();
-            if fname.ends_with(".xml") {
-                let mut buf = Vec::new();
-                file.read_to_end(&mut buf).ok()?;
-                return Some(buf);
-            }
+impl DmarcRecord {
+    /// Number of messages the row stands for.
+    pub fn messages(&self) -> i64 {
+        self.count.trim().parse().unwrap_or(0)
+    }
+
+    /// DMARC passes when either aligned identifier passed (RFC 7489 §4.2).
+    pub fn passed(&self) -> bool {
+        self.dkim_result == "pass" || self.spf_result == "pass"
+    }
+}
+
+impl DmarcReport {
+    pub fn message_count(&self) -> i64 {
+        self.records.iter().map(DmarcRecord::messages).sum()
+    }
+
+    pub fn passed_count(&self) -> i64 {
+        self.records
+            .iter()
+            .filter(|r| r.passed())
+            .map(DmarcRecord::messages)
+            .sum()
+    }
+
+    pub fn failed_count(&self) -> i64 {
+        self.message_count() - self.passed_count()
+    }
+
+    /// Key the stored rows are replaced under: the report ID, or the message
+    /// filename for reporters that leave it empty.
+    fn key(&self) -> String {
+        if self.meta.report_id.is_empty() {
+            self.email_filename.clone()
+        } else {
+            self.meta.report_id.clone()
         }
-        None
-    } else if lower.ends_with(".gz") || lower.ends_with(".xml.gz") {
-        // Gzip compressed
-        let mut decoder = flate2::read::GzDecoder::new(data);
+    }
+
+    fn rows(&self) -> Vec<DmarcReportRow> {
+        self.records
+            .iter()
+            .map(|r| DmarcReportRow {
+                org_name: self.meta.org_name.clone(),
+                policy_domain: self.policy.domain.clone(),
+                date_begin: self.meta.date_begin.clone(),
+                date_end: self.meta.date_end.clone(),
+                source_ip: r.source_ip.clone(),
+                message_count: r.messages(),
+                disposition: r.disposition.clone(),
+                dkim_result: r.dkim_result.clone(),
+                spf_result: r.spf_result.clone(),
+                header_from: r.header_from.clone(),
+            })
+            .collect()
+    }
+}
+
+// ── DMARC XML parsing ──
+
+/// Parse an aggregate report attachment.  Reporters send the XML bare,
+/// gzip-compressed or inside a ZIP archive; the wrapping is detected from the
+/// content rather than the (often misleading) attachment name.
+pub fn parse_aggregate_report(bytes: &[u8]) -> Result<AggregateReport, String> {
+    let xml = if bytes.starts_with(&[0x1f, 0x8b]) {
         let mut buf = Vec::new();
-        decoder.read_to_end(&mut buf).ok()?;
-        Some(buf)
-    } else if lower.ends_with(".xml") {
-        Some(data.to_vec())
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_end(&mut buf)
+            .map_err(|e| format!("invalid gzip data: {}", e))?;
+        buf
+    } else if bytes.starts_with(b"PK\x03\x04") {
+        unzip_report(bytes)?
     } else {
-        None
+        bytes.to_vec()
+    };
+    parse_dmarc_xml(&xml)
+}
+
+/// The first `.xml` member of a ZIP archive.
+fn unzip_report(data: &[u8]) -> Result<Vec<u8>, String> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(data))
+        .map_err(|e| format!("invalid zip archive: {}", e))?;
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .map_err(|e| format!("invalid zip archive: {}", e))?;
+        if file.name().to_lowercase().ends_with(".xml") {
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)
+                .map_err(|e| format!("failed to extract {}: {}", file.name(), e))?;
+            return Ok(buf);
+        }
     }
+    Err("zip archive contains no .xml file".to_string())
 }
 
 /// Parse DMARC aggregate report XML into structured data.
-fn parse_dmarc_xml(xml: &[u8]) -> Option<(DmarcReportMeta, DmarcPolicy, Vec<DmarcRecord>)> {
+fn parse_dmarc_xml(xml: &[u8]) -> Result<AggregateReport, String> {
     use quick_xml::events::Event;
     use quick_xml::Reader;
 
@@ -118,6 +188,7 @@ fn parse_dmarc_xml(xml: &[u8]) -> Option<(DmarcReportMeta, DmarcPolicy, Vec<Dmar
     let mut policy = DmarcPolicy::default();
     let mut records: Vec<DmarcRecord> = Vec::new();
     let mut current_record: Option<DmarcRecord> = None;
+    let mut seen_root = false;
 
     // Track element path as a stack of tag names
     let mut path: Vec<String> = Vec::new();
@@ -127,6 +198,12 @@ fn parse_dmarc_xml(xml: &[u8]) -> Option<(DmarcReportMeta, DmarcPolicy, Vec<Dmar
         match reader.read_event_into(&mut buf) {
             Ok(Event::Start(e)) => {
                 let tag = String::from_utf8_lossy(e.name().as_ref()).to_string();
+                if path.is_empty() {
+                    if seen_root || tag != "feedback" {
+                        return Err(format!("unexpected root element <{}>", tag));
+                    }
+                    seen_root = true;
+                }
                 path.push(tag.clone());
                 if tag == "record" {
                     current_record = Some(DmarcRecord::default());
@@ -216,15 +293,29 @@ fn parse_dmarc_xml(xml: &[u8]) -> Option<(DmarcReportMeta, DmarcPolicy, Vec<Dmar
             }
             Ok(Event::Eof) => break,
             Err(e) => {
-                warn!("[dmarc] XML parse error: {}", e);
-                break;
+                return Err(format!(
+                    "XML parse error at byte {}: {}",
+                    reader.buffer_position(),
+                    e
+                ));
             }
             _ => {}
         }
         buf.clear();
     }
 
-    Some((meta, policy, records))
+    if !seen_root {
+        return Err("no <feedback> element found".to_string());
+    }
+    if let Some(open) = path.last() {
+        return Err(format!("document ends inside <{}>", open));
+    }
+
+    Ok(AggregateReport {
+        meta,
+        policy,
+        records,
+    })
 }
 
 fn unix_ts_to_date(ts_str: &str) -> String {
@@ -361,33 +452,31 @@ where
                             "[dmarc] found attachment '{}' in email '{}'",
                             att_name, subject
                         );
-                        if let Some(xml_data) = decompress_dmarc_attachment(&att_name, &att_data) {
-                            match parse_dmarc_xml(&xml_data) {
-                                Some((meta, policy, records)) => {
-                                    let report = DmarcReport {
-                                        email_subject: subject,
-                                        email_date: date,
-                                        email_timestamp,
-                                        email_filename: encoded,
-                                        meta,
-                                        policy,
-                                        records,
-                                    };
-                                    on_report(&report);
-                                    reports.push(report);
-                                }
-                                None => {
-                                    logs.push(format!(
-                                        "Could not parse DMARC XML in: {}",
-                                        fname
-                                    ));
-                                }
+                        match parse_aggregate_report(&att_data) {
+                            Ok(parsed_report) => {
+                                let report = DmarcReport {
+                                    email_subject: subject,
+                                    email_date: date,
+                                    email_timestamp,
+                                    email_filename: encoded,
+                                    meta: parsed_report.meta,
+                                    policy: parsed_report.policy,
+                                    records: parsed_report.records,
+                                };
+                                on_report(&report);
+                                reports.push(report);
+                            }
+                            Err(e) => {
+                                // One broken attachment must not hide the rest of the inbox.
+                                warn!(
+                                    "[dmarc] skipping attachment '{}' in {}: {}",
+                                    att_name, fname, e
+                                );
+                                logs.push(format!(
+                                    "Skipped attachment '{}' in {}: {}",
+                                    att_name, fname, e
+                                ));
                             }
-                        } else {
-                            logs.push(format!(
-                                "Could not decompress attachment '{}' in: {}",
-                                att_name, fname
-                            ));
                         }
                     }
                     // else: not a DMARC email, skip silently
@@ -457,6 +546,9 @@ struct ReportsTemplate<'a> {
     nav_active: &'a str,
     flash: Option<&'a str>,
     inbox: DmarcInbox,
+    summary: Vec<DmarcSourceSummary>,
+    total_messages: i64,
+    total_passed: i64,
     reports: Vec<DmarcReport>,
     logs: Vec<String>,
     page: usize,
@@ -496,12 +588,7 @@ pub async fn reports(
     let reports = if is_safe_path_component(&domain) && is_safe_path_component(&username) {
         let maildir_base = maildir_path(&domain, &username);
         read_dmarc_reports(&maildir_base, &mut logs, |report| {
-            let key = if report.meta.report_id.is_empty() {
-                report.email_filename.clone()
-            } else {
-                report.meta.report_id.clone()
-            };
-            if seen_report_ids.insert(key) {
+            if seen_report_ids.insert(report.key()) {
                 fire_webhook(
                     &webhook_state,
                     "dmarc.report.parsed",
@@ -537,12 +624,30 @@ pub async fn reports(
         Vec::new()
     };
 
+    let stored: Vec<(String, Vec<DmarcReportRow>)> =
+        reports.iter().map(|r| (r.key(), r.rows())).collect();
+    let inbox_id = inbox.id;
+    let summary = state
+        .blocking_db(move |db| {
+            for (key, rows) in &stored {
+                // Failures are logged by the database layer; the page still renders.
+                let _ = db.replace_dmarc_report_rows(inbox_id, key, rows);
+            }
+            db.dmarc_source_summary(inbox_id)
+        })
+        .await;
+
+    let total_messages = summary.iter().map(|s| s.messages).sum();
+    let total_passed = summary.iter().map(|s| s.passed).sum();
     let pagination = paginate_reports(reports, page, REPORTS_PER_PAGE);
 
     let tmpl = ReportsTemplate {
         nav_active: "DMARC",
         flash: None,
         inbox,
+        summary,
+        total_messages,
+        total_passed,
         reports: pagination.reports,
         logs,
         page: pagination.page,
@@ -599,6 +704,74 @@ mod tests {
         assert_eq!(page_two.reports[0].email_subject, "first");
     }
 
+    const SAMPLE_REPORT: &str = "<?xml version=\"1.0\"?>
+<feedback>
+  <report_metadata>
+    <org_name>google.com</org_name>
+    <report_id>1234</report_id>
+    <date_range><begin>1708387200</begin><end>1708473599</end></date_range>
+  </report_metadata>
+  <policy_published><domain>example.com</domain><p>reject</p></policy_published>
+  <record>
+    <row>
+      <source_ip>192.0.2.1</source_ip>
+      <count>7</count>
+      <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>fail</spf></policy_evaluated>
+    </row>
+    <identifiers><header_from>example.com</header_from></identifiers>
+  </record>
+  <record>
+    <row>
+      <source_ip>198.51.100.9</source_ip>
+      <count>3</count>
+      <policy_evaluated><disposition>reject</disposition><dkim>fail</dkim><spf>fail</spf></policy_evaluated>
+    </row>
+    <identifiers><header_from>example.com</header_from></identifiers>
+  </record>
+</feedback>";
+
+    #[test]
+    fn parse_aggregate_report_unwraps_gzip_and_zip() {
+        use std::io::Write;
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(SAMPLE_REPORT.as_bytes()).unwrap();
+        let gzipped = gz.finish().unwrap();
+
+        let mut zipped = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        zipped
+            .start_file(
+                "google.com!example.com!1708387200!1708473599.xml",
+                zip::write::SimpleFileOptions::default(),
+            )
+            .unwrap();
+        zipped.write_all(SAMPLE_REPORT.as_bytes()).unwrap();
+        let zipped = zipped.finish().unwrap().into_inner();
+
+        for bytes in [SAMPLE_REPORT.as_bytes(), &gzipped, &zipped] {
+            let report = parse_aggregate_report(bytes).unwrap();
+            assert_eq!(report.meta.org_name, "google.com");
+            assert_eq!(report.meta.date_begin, "2024-02-20");
+            assert_eq!(report.policy.domain, "example.com");
+            assert_eq!(report.records.len(), 2);
+            assert_eq!(report.records[0].source_ip, "192.0.2.1");
+            assert_eq!(report.records[0].messages(), 7);
+            assert!(report.records[0].passed());
+            assert_eq!(report.records[1].disposition, "reject");
+            assert!(!report.records[1].passed());
+        }
+    }
+
+    #[test]
+    fn parse_aggregate_report_rejects_malformed_xml() {
+        let truncated = &SAMPLE_REPORT[..SAMPLE_REPORT.len() / 2];
+        assert!(parse_aggregate_report(truncated.as_bytes()).is_err());
+        assert!(parse_aggregate_report(b"<feedback><record></row></feedback>").is_err());
+        assert!(parse_aggregate_report(b"<html><body>hello</body></html>").is_err());
+        assert!(parse_aggregate_report(b"not xml at all").is_err());
+        assert!(parse_aggregate_report(&[0x1f, 0x8b, 0x00, 0x01]).is_err());
+    }
+
     #[test]
     fn read_dmarc_reports_triggers_callback() {
         use std::fs;
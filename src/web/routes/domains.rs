@@ -1,6 +1,6 @@
 use askama::Template;
 use axum::{
-    extract::{Path, Query, State},
+    extract::{Multipart, Path, Query, State},
     response::{Html, IntoResponse, Redirect, Response},
     Form,
 };
@@ -8,10 +8,17 @@ use log::{debug, error, info, warn};
 use serde::Deserialize;
 
 use crate::db::{AbuseInbox, Account, BounceInbox};
+use crate::dns_setup::{PublicIpMethod, SetupCheck};
+use crate::dnscheck::RecordCheck;
+use crate::quiet_hours::QuietHours;
 use crate::web::auth::AuthAdmin;
-use crate::web::fire_webhook;
-use crate::web::forms::{DomainEditForm, DomainForm};
+use crate::web::{audit, fire_webhook};
+use crate::web::forms::{
+    DomainEditForm, DomainForm, PublicIpForm, RegistrationInviteForm, RegistrationSettingsForm,
+};
 use crate::web::regen_configs;
+use crate::web::routes::approvals::{self, DestructiveAction};
+use crate::web::routes::registration;
 use crate::web::AppState;
 
 // ── View models ──
@@ -23,6 +30,35 @@ struct DomainRow {
     dkim_label: String,
 }
 
+struct ReservedRow {
+    role: &'static str,
+    description: &'static str,
+    required: bool,
+    target: String,
+    enabled: bool,
+}
+
+/// One row per reserved role, with the defaults for roles the domain has no
+/// row for yet.
+fn reserved_rows(mailboxes: &[crate::db::ReservedMailbox]) -> Vec<ReservedRow> {
+    crate::reserved_mailboxes::ROLES
+        .iter()
+        .map(|role| {
+            let row = mailboxes.iter().find(|m| m.role == role.name);
+            ReservedRow {
+                role: role.name,
+                description: role.description,
+                required: role.required,
+                target: row.map_or_else(
+                    || crate::reserved_mailboxes::default_target(role.name).to_string(),
+                    |m| m.target.clone(),
+                ),
+                enabled: row.is_none_or(|m| m.enabled),
+            }
+        })
+        .collect()
+}
+
 // ── DNS check structures ──
 
 struct SpfRecord {
@@ -38,13 +74,16 @@ struct DnsCheckResult {
     ptr_status: String,
     spf_chain: Vec<SpfRecord>,
     spf_error: String,
+    public_ip_method: String,
+    public_ip: String,
+    setup_checks: Vec<SetupCheck>,
 }
 
 // ── DNS helpers ──
 
 const MAX_SPF_RECURSION: usize = 10;
 
-fn query_txt_records(domain: &str) -> Vec<String> {
+pub(crate) fn query_txt_records(domain: &str) -> Vec<String> {
     let output = match std::process::Command::new("nslookup")
         .args(["-type=TXT", domain])
         .output()
@@ -136,13 +175,16 @@ struct EditTemplate<'a> {
     nav_active: &'a str,
     flash: Option<&'a str>,
     domain: crate::db::Domain,
+    reserved: Vec<ReservedRow>,
+    registration: crate::db::RegistrationSettings,
+    invites: Vec<crate::db::RegistrationInvite>,
 }
 
 /// View-model for the DNS runbook page.
 ///
-/// `dmarc_rua` and `dmarc_ruf` are the fully-qualified RFC 5321 mailbox addresses
-/// (`local-part@domain`, §4.1.2) that will be embedded as `mailto:` URIs in the
-/// `_dmarc` TXT record (RFC 7489 §6.3).  When `None` the record falls back to
+/// `dmarc_record` embeds the fully-qualified RFC 5321 mailbox addresses
+/// (`local-part@domain`, §4.1.2) of the report inboxes as `rua`/`ruf` `mailto:`
+/// URIs (RFC 7489 §6.3).  Without an inbox the record falls back to
 /// `postmaster@<domain>` (RFC 5321 §4.5.1).
 #[derive(Template)]
 #[template(path = "domains/dns.html")]
@@ -154,18 +196,31 @@ struct DnsTemplate<'a> {
     dkim_selector: String,
     hostname: &'a str,
     dkim_record: String,
+    /// Selector and `p=` value replaced by the last rotation, while its grace
+    /// period lasts.
+    dkim_prev: Option<(String, String)>,
+    dkim_prev_until: String,
     bimi_logo_url: String,
     has_bimi: bool,
-    /// `rua=mailto:<rua>` aggregate-report destination (RFC 7489 §6.3).
-    dmarc_rua: Option<String>,
-    /// `ruf=mailto:<ruf>` failure-report destination (RFC 7489 §6.3 / RFC 6591).
-    dmarc_ruf: Option<String>,
+    dmarc_record: String,
+    record_checks: Vec<RecordCheck>,
+    /// Seconds since `record_checks` were looked up.
+    checked_secs_ago: u64,
+    /// Required role addresses (postmaster@, abuse@) that reach nobody.
+    unanswered_roles: Vec<String>,
     dmarc_inbox: Option<crate::db::DmarcInbox>,
     abuse_inbox: Option<AbuseInbox>,
     bounce_inbox: Option<BounceInbox>,
     domain_accounts: Vec<Account>,
 }
 
+#[derive(Deserialize)]
+pub struct DnsInfoQuery {
+    /// Skip the cached record checks and look them up again.
+    #[serde(default)]
+    pub refresh: bool,
+}
+
 #[derive(Deserialize)]
 pub struct DnsCheckQuery {
     #[serde(rename = "type")]
@@ -242,11 +297,25 @@ pub async fn new_form(_auth: AuthAdmin) -> Html<String> {
 }
 
 pub async fn create(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Form(form): Form<DomainForm>,
 ) -> Response {
     info!("[web] POST /domains — creating domain={}", form.domain);
+    if !crate::validation::is_valid_domain(&form.domain) {
+        warn!("[web] invalid domain name: {:?}", form.domain);
+        let tmpl = ErrorTemplate {
+            nav_active: "Domains",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Domain Name",
+            message: crate::validation::DOMAIN_HINT,
+            back_url: "/domains/new",
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
     let domain = form.domain.clone();
     let bimi_svg = form.bimi_svg.clone();
     let unsubscribe_enabled = form.unsubscribe_enabled.is_some();
@@ -262,6 +331,15 @@ pub async fn create(
                 form.domain, id
             );
             regen_configs(&state).await;
+            audit(
+                &state,
+                &auth,
+                "domain.created",
+                "domain",
+                Some(id),
+                serde_json::json!({"domain": form.domain}),
+            )
+            .await;
             fire_webhook(
                 &state,
                 "domain.created",
@@ -286,6 +364,49 @@ pub async fn create(
     }
 }
 
+pub async fn export(_auth: AuthAdmin, State(state): State<AppState>) -> Response {
+    info!("[web] GET /domains/export — exporting domains as CSV");
+    let domains = state.blocking_db(|db| db.list_domains()).await;
+    super::csv_transfer::csv_download(
+        "domains.csv",
+        crate::csv_transfer::export_domains(&domains),
+    )
+}
+
+pub async fn import(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Response {
+    info!("[web] POST /domains/import — importing domains from CSV");
+    let report = match super::csv_transfer::read_upload(multipart).await {
+        Ok(input) => {
+            state
+                .blocking_db(move |db| crate::csv_transfer::import_domains(db, &input))
+                .await
+        }
+        Err(e) => crate::csv_transfer::ImportReport {
+            error: Some(e),
+            ..Default::default()
+        },
+    };
+    if report.created > 0 {
+        regen_configs(&state).await;
+        let details = serde_json::json!({"count": report.created});
+        audit(
+            &state,
+            &auth,
+            "domain.imported",
+            "domain",
+            None,
+            details.clone(),
+        )
+        .await;
+        fire_webhook(&state, "domain.imported", details);
+    }
+    super::csv_transfer::report_response("Domains", "Domain Import", "/domains", report)
+}
+
 pub async fn edit_form(
     _auth: AuthAdmin,
     State(state): State<AppState>,
@@ -299,16 +420,28 @@ pub async fn edit_form(
             return Redirect::to("/domains").into_response();
         }
     };
+    let (mailboxes, registration, invites) = state
+        .blocking_db(move |db| {
+            (
+                db.list_reserved_mailboxes(id),
+                db.get_registration_settings(id),
+                db.list_registration_invites(id),
+            )
+        })
+        .await;
     let tmpl = EditTemplate {
         nav_active: "Domains",
         flash: None,
         domain,
+        reserved: reserved_rows(&mailboxes),
+        registration,
+        invites,
     };
     Html(tmpl.render().unwrap()).into_response()
 }
 
 pub async fn update(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Form(form): Form<DomainEditForm>,
@@ -318,25 +451,87 @@ pub async fn update(
         "[web] POST /domains/{} — updating domain={}, active={}",
         id, form.domain, active
     );
+    if !crate::validation::is_valid_domain(&form.domain) {
+        warn!(
+            "[web] invalid domain name for domain id={}: {:?}",
+            id, form.domain
+        );
+        let back_url = format!("/domains/{}/edit", id);
+        let tmpl = ErrorTemplate {
+            nav_active: "Domains",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Domain Name",
+            message: crate::validation::DOMAIN_HINT,
+            back_url: &back_url,
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    let quiet_start = form.quiet_hours_start.trim().to_string();
+    let quiet_end = form.quiet_hours_end.trim().to_string();
+    let quiet_tz = match form.quiet_hours_timezone.trim() {
+        "" => "UTC".to_string(),
+        tz => tz.to_string(),
+    };
+    let quiet_set = !quiet_start.is_empty() || !quiet_end.is_empty();
+    if quiet_set && QuietHours::parse(&quiet_start, &quiet_end, &quiet_tz).is_none() {
+        warn!(
+            "[web] invalid quiet hours for domain id={}: start={}, end={}, timezone={}",
+            id, quiet_start, quiet_end, quiet_tz
+        );
+        let back_url = format!("/domains/{}/edit", id);
+        let tmpl = ErrorTemplate {
+            nav_active: "Domains",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Quiet Hours",
+            message: "Quiet hours need a start and end time (HH:MM) that differ, and a valid IANA timezone such as Europe/Berlin.",
+            back_url: &back_url,
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    let helo_name = form.helo_name.trim().trim_end_matches('.').to_lowercase();
+    if !helo_name.is_empty() && !crate::config::is_valid_hostname(&helo_name) {
+        warn!("[web] invalid HELO name for domain id={}: {}", id, helo_name);
+        let back_url = format!("/domains/{}/edit", id);
+        let tmpl = ErrorTemplate {
+            nav_active: "Domains",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid HELO Name",
+            message: "The HELO name must be a fully qualified hostname such as mail.example.com.",
+            back_url: &back_url,
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
     let domain = form.domain.clone();
     let bimi_svg = form.bimi_svg.clone();
     let unsubscribe_enabled = form.unsubscribe_enabled.is_some();
-    let registration_enabled = form.registration_enabled.is_some();
-    let registration_username_regex = form.registration_username_regex.clone();
+    let dmarc_reports_enabled = form.dmarc_reports_enabled.is_some();
     state
         .blocking_db(move |db| {
-            db.update_domain(
-                id,
-                &domain,
-                active,
-                &bimi_svg,
-                unsubscribe_enabled,
-                registration_enabled,
-                &registration_username_regex,
-            )
+            db.update_domain(id, &domain, active, &bimi_svg, unsubscribe_enabled);
+            db.update_domain_quiet_hours(id, &quiet_start, &quiet_end, &quiet_tz);
+            db.update_domain_helo_name(id, &helo_name);
+            db.update_domain_dmarc_reports(id, dmarc_reports_enabled);
         })
         .await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "domain.updated",
+        "domain",
+        Some(id),
+        serde_json::json!({"domain": form.domain, "active": active}),
+    )
+    .await;
     fire_webhook(
         &state,
         "domain.updated",
@@ -345,20 +540,241 @@ pub async fn update(
     Redirect::to("/domains").into_response()
 }
 
+pub async fn update_reserved_mailboxes(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Response {
+    info!("[web] POST /domains/{}/reserved — updating reserved mailboxes", id);
+    let domain = match state.blocking_db(move |db| db.get_domain(id)).await {
+        Some(d) => d,
+        None => {
+            warn!("[web] domain id={} not found for reserved mailboxes", id);
+            return Redirect::to("/domains").into_response();
+        }
+    };
+    let field = |name: String| {
+        fields
+            .iter()
+            .find(|(k, _)| *k == name)
+            .map(|(_, v)| v.trim().to_string())
+    };
+    let mut mailboxes = Vec::new();
+    for role in crate::reserved_mailboxes::ROLES {
+        let target = field(format!("target_{}", role.name)).unwrap_or_default();
+        let target = match target.rsplit_once('@') {
+            Some((local, dom)) => format!("{}@{}", local, dom.to_lowercase()),
+            None => target.to_lowercase(),
+        };
+        if let Err(message) =
+            crate::reserved_mailboxes::validate_target(role.name, &target, &domain.domain)
+        {
+            warn!(
+                "[web] invalid reserved mailbox target for domain id={}: {}",
+                id, message
+            );
+            let back_url = format!("/domains/{}/edit#reserved", id);
+            let tmpl = ErrorTemplate {
+                nav_active: "Domains",
+                flash: None,
+                status_code: 400,
+                status_text: "Bad Request",
+                title: "Invalid Reserved Mailbox",
+                message: &message,
+                back_url: &back_url,
+                back_label: "Back",
+            };
+            return Html(tmpl.render().unwrap()).into_response();
+        }
+        let enabled = field(format!("enabled_{}", role.name)).is_some();
+        mailboxes.push((role.name, target, enabled));
+    }
+    let details = serde_json::json!({
+        "domain": domain.domain,
+        "reserved_mailboxes": mailboxes
+            .iter()
+            .map(|(role, target, enabled)| {
+                serde_json::json!({"role": role, "target": target, "enabled": enabled})
+            })
+            .collect::<Vec<_>>(),
+    });
+    state
+        .blocking_db(move |db| {
+            for (role, target, enabled) in &mailboxes {
+                db.set_reserved_mailbox(id, role, target, *enabled);
+            }
+        })
+        .await;
+    regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "domain.reserved_mailboxes_updated",
+        "domain",
+        Some(id),
+        details,
+    )
+    .await;
+    Redirect::to(&format!("/domains/{}/edit", id)).into_response()
+}
+
+pub async fn update_registration(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(form): Form<RegistrationSettingsForm>,
+) -> Response {
+    info!("[web] POST /domains/{}/registration — updating registration settings", id);
+    let username_regex = form.username_regex.trim().to_string();
+    let min_password_length = form
+        .min_password_length
+        .trim()
+        .parse::<i32>()
+        .ok()
+        .filter(|n| {
+            (registration::MIN_PASSWORD_LENGTH..=registration::MAX_PASSWORD_LENGTH).contains(n)
+        });
+    let problem = if !username_regex.is_empty() && regex::Regex::new(&username_regex).is_err() {
+        Some(format!(
+            "The username regex '{}' is not a valid regular expression.",
+            username_regex
+        ))
+    } else if min_password_length.is_none() {
+        Some(format!(
+            "The minimum password length must be a number from {} to {}.",
+            registration::MIN_PASSWORD_LENGTH,
+            registration::MAX_PASSWORD_LENGTH
+        ))
+    } else {
+        None
+    };
+    if let Some(message) = problem {
+        warn!("[web] invalid registration settings for domain id={}: {}", id, message);
+        let back_url = format!("/domains/{}/edit#registration", id);
+        let tmpl = ErrorTemplate {
+            nav_active: "Domains",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Registration Settings",
+            message: &message,
+            back_url: &back_url,
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    let settings = crate::db::RegistrationSettings {
+        domain_id: id,
+        enabled: form.enabled.is_some(),
+        username_regex,
+        min_password_length: min_password_length.unwrap_or(registration::MIN_PASSWORD_LENGTH),
+        invite_only: form.invite_only.is_some(),
+    };
+    let details = serde_json::json!({
+        "enabled": settings.enabled,
+        "username_regex": settings.username_regex,
+        "min_password_length": settings.min_password_length,
+        "invite_only": settings.invite_only,
+    });
+    let found = state
+        .blocking_db(move |db| {
+            let found = db.get_domain(id).is_some();
+            if found {
+                db.update_registration_settings(&settings);
+            }
+            found
+        })
+        .await;
+    if !found {
+        warn!("[web] domain id={} not found for registration settings", id);
+        return Redirect::to("/domains").into_response();
+    }
+    audit(
+        &state,
+        &auth,
+        "domain.registration_updated",
+        "domain",
+        Some(id),
+        details,
+    )
+    .await;
+    Redirect::to(&format!("/domains/{}/edit#registration", id)).into_response()
+}
+
+pub async fn create_registration_invite(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(form): Form<RegistrationInviteForm>,
+) -> Response {
+    info!("[web] POST /domains/{}/registration/invites — creating invite", id);
+    let note = form.note.trim().to_string();
+    let code = crate::auth::generate_invite_code();
+    let created = state
+        .blocking_db(move |db| {
+            db.get_domain(id)
+                .ok_or_else(|| "domain not found".to_string())
+                .and_then(|_| db.create_registration_invite(id, &code, &note))
+        })
+        .await;
+    match created {
+        Ok(invite_id) => {
+            audit(
+                &state,
+                &auth,
+                "domain.registration_invite_created",
+                "domain",
+                Some(id),
+                serde_json::json!({"invite_id": invite_id}),
+            )
+            .await;
+        }
+        Err(e) => error!("[web] failed to create invite for domain id={}: {}", id, e),
+    }
+    Redirect::to(&format!("/domains/{}/edit#registration", id)).into_response()
+}
+
+pub async fn delete_registration_invite(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path((id, invite_id)): Path<(i64, i64)>,
+) -> Response {
+    info!(
+        "[web] POST /domains/{}/registration/invites/{}/delete — deleting invite",
+        id, invite_id
+    );
+    state
+        .blocking_db(move |db| db.delete_registration_invite(id, invite_id))
+        .await;
+    audit(
+        &state,
+        &auth,
+        "domain.registration_invite_deleted",
+        "domain",
+        Some(id),
+        serde_json::json!({"invite_id": invite_id}),
+    )
+    .await;
+    Redirect::to(&format!("/domains/{}/edit#registration", id)).into_response()
+}
+
 pub async fn delete(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Response {
     warn!("[web] POST /domains/{}/delete — deleting domain", id);
-    state.blocking_db(move |db| db.delete_domain(id)).await;
-    regen_configs(&state).await;
-    fire_webhook(&state, "domain.deleted", serde_json::json!({"id": id}));
+    let action = DestructiveAction::DeleteDomain(id);
+    if let Some(held) = approvals::hold(&state, &auth, action).await {
+        return held;
+    }
+    approvals::perform(&state, auth.admin.username.clone(), action, serde_json::json!({})).await;
     Redirect::to("/domains").into_response()
 }
 
 pub async fn generate_dkim(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Response {
@@ -371,51 +787,16 @@ pub async fn generate_dkim(
         }
     };
 
-    debug!(
-        "[web] generating RSA 2048 private key for domain={}",
-        domain.domain
-    );
-    let priv_output = std::process::Command::new("openssl")
-        .args(["genrsa", "2048"])
-        .output();
-    let private_key = match priv_output {
-        Ok(o) if o.status.success() => {
-            debug!(
-                "[web] DKIM private key generated for domain={}",
-                domain.domain
-            );
-            String::from_utf8_lossy(&o.stdout).to_string()
-        }
-        Ok(o) => {
-            error!(
-                "[web] openssl genrsa failed for domain={}: {}",
-                domain.domain,
-                String::from_utf8_lossy(&o.stderr)
-            );
+    let (private_key, public_key) = match crate::dkim_rotation::generate_keypair(&domain.domain) {
+        Ok(keys) => keys,
+        Err(message) => {
             let tmpl = ErrorTemplate {
                 nav_active: "Domains",
                 flash: None,
                 status_code: 500,
                 status_text: "Error",
                 title: "Error",
-                message: "Failed to generate DKIM private key.",
-                back_url: "/domains",
-                back_label: "Back",
-            };
-            return Html(tmpl.render().unwrap()).into_response();
-        }
-        Err(e) => {
-            error!(
-                "[web] failed to run openssl genrsa for domain={}: {}",
-                domain.domain, e
-            );
-            let tmpl = ErrorTemplate {
-                nav_active: "Domains",
-                flash: None,
-                status_code: 500,
-                status_text: "Error",
-                title: "Error",
-                message: "Failed to generate DKIM private key.",
+                message,
                 back_url: "/domains",
                 back_label: "Back",
             };
@@ -423,86 +804,99 @@ pub async fn generate_dkim(
         }
     };
 
-    debug!("[web] extracting public key for domain={}", domain.domain);
-    let pub_output = std::process::Command::new("openssl")
-        .args(["rsa", "-pubout"])
-        .stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped())
-        .spawn()
-        .and_then(|mut child| {
-            use std::io::Write;
-            if let Some(ref mut stdin) = child.stdin {
-                stdin.write_all(private_key.as_bytes()).ok();
-            }
-            child.wait_with_output()
-        });
-    let public_key = match pub_output {
-        Ok(o) if o.status.success() => {
-            debug!(
-                "[web] DKIM public key extracted for domain={}",
-                domain.domain
-            );
-            String::from_utf8_lossy(&o.stdout).to_string()
-        }
-        Ok(o) => {
-            error!(
-                "[web] openssl rsa -pubout failed for domain={}: {}",
-                domain.domain,
-                String::from_utf8_lossy(&o.stderr)
-            );
-            let tmpl = ErrorTemplate {
-                nav_active: "Domains",
-                flash: None,
-                status_code: 500,
-                status_text: "Error",
-                title: "Error",
-                message: "Failed to extract DKIM public key.",
-                back_url: "/domains",
-                back_label: "Back",
-            };
-            return Html(tmpl.render().unwrap()).into_response();
+    info!(
+        "[web] DKIM keys generated successfully for domain={}",
+        domain.domain
+    );
+    let selector = domain.dkim_selector.clone();
+    state
+        .blocking_db(move |db| db.update_domain_dkim(id, &selector, &private_key, &public_key))
+        .await;
+    regen_configs(&state).await;
+    audit(&state, &auth, "domain.dkim_generated", "domain", Some(id), serde_json::json!({})).await;
+    fire_webhook(
+        &state,
+        "domain.dkim_generated",
+        serde_json::json!({"id": id}),
+    );
+    Redirect::to(&format!("/domains/{}/dns", id)).into_response()
+}
+
+/// Switch the domain to a new DKIM selector and key pair.  The previous
+/// selector stays published until the rotation grace period ends.
+pub async fn rotate_dkim(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    info!(
+        "[web] POST /domains/{}/dkim/rotate — rotating DKIM keys by username={}",
+        id, auth.admin.username
+    );
+    let domain = match state.blocking_db(move |db| db.get_domain(id)).await {
+        Some(d) => d,
+        None => {
+            warn!("[web] domain id={} not found for DKIM rotation", id);
+            return Redirect::to("/domains").into_response();
         }
-        Err(e) => {
-            error!(
-                "[web] failed to run openssl rsa -pubout for domain={}: {}",
-                domain.domain, e
-            );
+    };
+    let back_url = format!("/domains/{}/dns#dkim", id);
+    if domain.dkim_private_key.is_none() {
+        let tmpl = ErrorTemplate {
+            nav_active: "Domains",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "No DKIM Key",
+            message: "This domain has no DKIM key to rotate. Generate one first.",
+            back_url: &back_url,
+            back_label: "Back to DNS",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+
+    let (private_key, public_key) = match crate::dkim_rotation::generate_keypair(&domain.domain) {
+        Ok(keys) => keys,
+        Err(message) => {
             let tmpl = ErrorTemplate {
                 nav_active: "Domains",
                 flash: None,
                 status_code: 500,
                 status_text: "Error",
                 title: "Error",
-                message: "Failed to extract DKIM public key.",
-                back_url: "/domains",
-                back_label: "Back",
+                message,
+                back_url: &back_url,
+                back_label: "Back to DNS",
             };
             return Html(tmpl.render().unwrap()).into_response();
         }
     };
-
+    let previous = domain.dkim_selector.clone();
+    let selector = crate::dkim_rotation::next_selector(
+        &previous,
+        domain.dkim_selector_prev.as_deref(),
+        chrono::Utc::now(),
+    );
     info!(
-        "[web] DKIM keys generated successfully for domain={}",
-        domain.domain
+        "[web] DKIM for domain={} rotating from selector={} to selector={}",
+        domain.domain, previous, selector
     );
-    let selector = domain.dkim_selector.clone();
+    let new_selector = selector.clone();
     state
-        .blocking_db(move |db| db.update_domain_dkim(id, &selector, &private_key, &public_key))
+        .blocking_db(move |db| db.rotate_domain_dkim(id, &new_selector, &private_key, &public_key))
         .await;
     regen_configs(&state).await;
-    fire_webhook(
-        &state,
-        "domain.dkim_generated",
-        serde_json::json!({"id": id}),
-    );
-    Redirect::to(&format!("/domains/{}/dns", id)).into_response()
+    let details = serde_json::json!({"selector": selector, "previous_selector": previous});
+    audit(&state, &auth, "domain.dkim_rotated", "domain", Some(id), details.clone()).await;
+    fire_webhook(&state, "domain.dkim_rotated", details);
+    Redirect::to(&back_url).into_response()
 }
 
 pub async fn dns_info(
     _auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
+    Query(query): Query<DnsInfoQuery>,
 ) -> Response {
     debug!("[web] GET /domains/{}/dns — DNS info requested", id);
     let domain = match state.blocking_db(move |db| db.get_domain(id)).await {
@@ -515,15 +909,29 @@ pub async fn dns_info(
 
     let dkim_record = domain
         .dkim_public_key
-        .as_ref()
-        .map(|pub_key| {
-            pub_key
-                .lines()
-                .filter(|l| !l.starts_with("-----"))
-                .collect::<Vec<_>>()
-                .join("")
-        })
+        .as_deref()
+        .map(crate::dkim_rotation::dns_public_key)
         .unwrap_or_default();
+    let dkim_prev = domain
+        .dkim_selector_prev
+        .clone()
+        .zip(domain.dkim_public_key_prev.as_deref())
+        .map(|(selector, key)| (selector, crate::dkim_rotation::dns_public_key(key)));
+    let dkim_prev_until = match &domain.dkim_rotated_at {
+        Some(rotated_at) => {
+            let rotated_at = rotated_at.clone();
+            state
+                .blocking_db(move |db| {
+                    crate::dkim_rotation::grace_ends(
+                        &rotated_at,
+                        crate::dkim_rotation::grace_days(db),
+                    )
+                })
+                .await
+                .unwrap_or_default()
+        }
+        None => String::new(),
+    };
 
     let has_bimi = domain
         .bimi_svg
@@ -557,6 +965,54 @@ pub async fn dns_info(
     let domain_accounts = state
         .blocking_db(move |db| db.list_accounts_by_domain(domain_id_for_accounts))
         .await;
+    let domain_for_roles = domain.clone();
+    let unanswered_roles = state
+        .blocking_db(move |db| {
+            crate::reserved_mailboxes::unanswered_required(db, &domain_for_roles)
+        })
+        .await;
+
+    let dmarc_record = format!(
+        "v=DMARC1; p=reject; adkim=s; aspf=s; fo=1; rua=mailto:{}; ruf=mailto:{}",
+        dmarc_rua.unwrap_or_else(|| format!("postmaster@{}", domain.domain)),
+        dmarc_ruf.unwrap_or_else(|| format!("postmaster@{}", domain.domain)),
+    );
+    let expected = crate::dnscheck::Expected {
+        domain: domain.domain.clone(),
+        hostname: state.hostname.clone(),
+        dkim_selector: domain.dkim_selector.clone(),
+        dkim_public_key: (!dkim_record.is_empty()).then(|| dkim_record.clone()),
+        dmarc_record: dmarc_record.clone(),
+        bimi_logo_url: has_bimi.then(|| bimi_logo_url.clone()),
+    };
+    let now = std::time::Instant::now();
+    let cached = if query.refresh {
+        None
+    } else {
+        state.dns_check_cache.lock().unwrap().get(&expected, now)
+    };
+    let (record_checks, checked_at) = match cached {
+        Some(hit) => {
+            debug!("[web] using cached DNS checks for domain={}", domain.domain);
+            hit
+        }
+        None => {
+            // nslookup blocks, so the lookups run off the async workers.
+            let (tx, rx) = tokio::sync::oneshot::channel();
+            let exp = expected.clone();
+            std::thread::spawn(move || {
+                let resolver = crate::spf::SystemResolver::default();
+                let _ = tx.send(crate::dnscheck::check(&exp, &resolver));
+            });
+            let checks = rx.await.unwrap_or_default();
+            state
+                .dns_check_cache
+                .lock()
+                .unwrap()
+                .put(&expected, checks.clone(), now);
+            (checks, now)
+        }
+    };
 
     let tmpl = DnsTemplate {
         nav_active: "Domains",
@@ -566,10 +1022,14 @@ pub async fn dns_info(
         dkim_selector: domain.dkim_selector.clone(),
         hostname: &state.hostname,
         dkim_record,
+        dkim_prev,
+        dkim_prev_until,
         bimi_logo_url,
         has_bimi,
-        dmarc_rua,
-        dmarc_ruf,
+        dmarc_record,
+        record_checks,
+        checked_secs_ago: now.duration_since(checked_at).as_secs(),
+        unanswered_roles,
         dmarc_inbox,
         abuse_inbox,
         bounce_inbox,
@@ -798,6 +1258,37 @@ pub async fn dns_check_run(
                 ptr_status: String::new(),
                 spf_chain,
                 spf_error,
+                public_ip_method: String::new(),
+                public_ip: String::new(),
+                setup_checks: Vec::new(),
+            }
+        }
+        "setup" => {
+            let domain_name = domain.domain.clone();
+            let hostname = state.hostname.clone();
+            let (method, public_ip, setup_checks) = state
+                .blocking_db(move |db| {
+                    let method = PublicIpMethod::configured(db);
+                    let public_ip = method.discover();
+                    let resolver = crate::spf::SystemResolver::default();
+                    let checks =
+                        crate::dns_setup::check(&domain_name, &hostname, &public_ip, &resolver);
+                    (method, public_ip, checks)
+                })
+                .await;
+            DnsCheckResult {
+                resolved_ip: String::new(),
+                ptr_hostname: String::new(),
+                ptr_matches: false,
+                ptr_status: String::new(),
+                spf_chain: Vec::new(),
+                spf_error: String::new(),
+                public_ip_method: method.to_string(),
+                public_ip: match public_ip {
+                    Ok(ip) => ip.to_string(),
+                    Err(e) => format!("unknown — {}", e),
+                },
+                setup_checks,
             }
         }
         _ => {
@@ -849,6 +1340,9 @@ pub async fn dns_check_run(
                 ptr_status,
                 spf_chain: Vec::new(),
                 spf_error: String::new(),
+                public_ip_method: String::new(),
+                public_ip: String::new(),
+                setup_checks: Vec::new(),
             }
         }
     };
@@ -864,3 +1358,42 @@ pub async fn dns_check_run(
     };
     Html(tmpl.render().unwrap()).into_response()
 }
+
+pub async fn set_public_ip_method(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(form): Form<PublicIpForm>,
+) -> Response {
+    info!(
+        "[web] POST /domains/{}/check/public-ip — update public IP method by username={}",
+        id, auth.admin.username
+    );
+    let back_url = format!("/domains/{}/check?type=setup", id);
+    let Some(method) = PublicIpMethod::parse(&form.public_ip_method) else {
+        warn!(
+            "[web] rejected public IP method: {}",
+            form.public_ip_method
+        );
+        let tmpl = ErrorTemplate {
+            nav_active: "Domains",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Public IP Method",
+            message: "Use \"interface\", an IP address, or an http(s):// URL that returns the address.",
+            back_url: &back_url,
+            back_label: "Back to DNS Check",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    };
+    let value = method.to_string();
+    state
+        .blocking_db(move |db| db.set_setting(crate::dns_setup::PUBLIC_IP_SETTING, &value))
+        .await;
+    info!(
+        "[web] public IP method set to {} by user={}",
+        method, auth.admin.username
+    );
+    Redirect::to(&back_url).into_response()
+}
@@ -7,10 +7,12 @@ use axum::{
 };
 use log::{debug, error, info, warn};
 
+use crate::firewall;
 use crate::web::auth::AuthAdmin;
 use crate::web::fire_webhook;
 use crate::web::forms::{
-    Fail2banBanForm, Fail2banGlobalToggleForm, Fail2banListForm, Fail2banSettingForm,
+    Fail2banBanForm, Fail2banFirewallForm, Fail2banGlobalToggleForm, Fail2banListForm,
+    Fail2banRuleForm, Fail2banSettingForm,
 };
 use crate::web::AppState;
 
@@ -39,36 +41,7 @@ fn same_origin(headers: &HeaderMap) -> bool {
 }
 
 fn is_valid_ip_or_cidr(input: &str) -> bool {
-    let trimmed = input.trim();
-    if trimmed.is_empty() {
-        return false;
-    }
-    // Allow IP addresses and CIDR notation (e.g., 192.168.1.0/24)
-    let ip_part = if let Some((ip, prefix)) = trimmed.split_once('/') {
-        if let Ok(p) = prefix.parse::<u8>() {
-            if p > 128 {
-                return false;
-            }
-        } else {
-            return false;
-        }
-        ip
-    } else {
-        trimmed
-    };
-
-    // Validate IPv4
-    let parts: Vec<&str> = ip_part.split('.').collect();
-    if parts.len() == 4 {
-        return parts.iter().all(|p| p.parse::<u8>().is_ok());
-    }
-
-    // Validate IPv6 (basic check)
-    if ip_part.contains(':') {
-        return ip_part.split(':').count() >= 2;
-    }
-
-    false
+    crate::fail2ban::parse_ip_or_cidr(input).is_some()
 }
 
 // ── Templates ──
@@ -83,12 +56,39 @@ struct Fail2banOverviewTemplate<'a> {
     banned: Vec<crate::db::Fail2banBanned>,
     whitelist: Vec<crate::db::Fail2banWhitelist>,
     blacklist: Vec<crate::db::Fail2banBlacklist>,
+    rules: Vec<crate::db::Fail2banRule>,
+    firewall: FirewallSettings,
     log_entries: Vec<crate::db::Fail2banLogEntry>,
     banned_count: i64,
     whitelist_count: usize,
     blacklist_count: usize,
 }
 
+/// Raw firewall settings as shown in the form; empty templates use the
+/// backend defaults.
+struct FirewallSettings {
+    backend: String,
+    add_cmd: String,
+    del_cmd: String,
+    flush_cmd: String,
+    list_cmd: String,
+}
+
+impl FirewallSettings {
+    fn load(db: &crate::db::Database) -> Self {
+        let get = |key: &str| db.get_setting(key).unwrap_or_default();
+        FirewallSettings {
+            backend: firewall::Backend::parse(&get(firewall::BACKEND_SETTING))
+                .map(|b| b.as_str().to_string())
+                .unwrap_or_default(),
+            add_cmd: get(firewall::ADD_CMD_SETTING),
+            del_cmd: get(firewall::DEL_CMD_SETTING),
+            flush_cmd: get(firewall::FLUSH_CMD_SETTING),
+            list_cmd: get(firewall::LIST_CMD_SETTING),
+        }
+    }
+}
+
 #[derive(Template)]
 #[template(path = "fail2ban/edit_setting.html")]
 struct EditSettingTemplate<'a> {
@@ -109,14 +109,18 @@ pub async fn overview(auth: AuthAdmin, State(state): State<AppState>) -> Html<St
     let banned_fut = state.blocking_db(|db| db.list_fail2ban_banned());
     let whitelist_fut = state.blocking_db(|db| db.list_fail2ban_whitelist());
     let blacklist_fut = state.blocking_db(|db| db.list_fail2ban_blacklist());
+    let rules_fut = state.blocking_db(|db| db.list_fail2ban_rules());
+    let firewall_fut = state.blocking_db(FirewallSettings::load);
     let log_fut = state.blocking_db(|db| db.list_fail2ban_log(50));
     let enabled_fut = state.blocking_db(|db| db.is_fail2ban_enabled());
 
-    let (settings, banned, whitelist, blacklist, log_entries, fail2ban_enabled) = tokio::join!(
+    let (settings, banned, whitelist, blacklist, rules, firewall, log_entries, fail2ban_enabled) = tokio::join!(
         settings_fut,
         banned_fut,
         whitelist_fut,
         blacklist_fut,
+        rules_fut,
+        firewall_fut,
         log_fut,
         enabled_fut
     );
@@ -133,6 +137,8 @@ pub async fn overview(auth: AuthAdmin, State(state): State<AppState>) -> Html<St
         banned,
         whitelist,
         blacklist,
+        rules,
+        firewall,
         log_entries,
         banned_count,
         whitelist_count,
@@ -184,6 +190,73 @@ pub async fn toggle_system(
     Redirect::to("/fail2ban").into_response()
 }
 
+pub async fn update_firewall(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<Fail2banFirewallForm>,
+) -> Response {
+    info!(
+        "[web] POST /fail2ban/firewall — update firewall enforcement for username={}",
+        auth.admin.username
+    );
+
+    if !same_origin(&headers) {
+        warn!("[web] fail2ban firewall update blocked: non same-origin request");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let backend = form.backend.trim().to_string();
+    let backend = if backend.is_empty() || backend == "off" {
+        String::new()
+    } else {
+        match firewall::Backend::parse(&backend) {
+            Some(b) => b.as_str().to_string(),
+            None => {
+                return crate::web::errors::status_response(
+                    StatusCode::BAD_REQUEST,
+                    "Invalid Firewall Backend",
+                    "Choose nftables, ipset or off.",
+                    "/fail2ban",
+                    "Back",
+                );
+            }
+        }
+    };
+
+    let backend_for_webhook = backend.clone();
+    state
+        .blocking_db(move |db| {
+            // Empty the old sets when enforcement is switched off.
+            let previous = firewall::Firewall::load(db);
+            db.set_setting(firewall::BACKEND_SETTING, &backend);
+            db.set_setting(firewall::ADD_CMD_SETTING, form.add_cmd.trim());
+            db.set_setting(firewall::DEL_CMD_SETTING, form.del_cmd.trim());
+            db.set_setting(firewall::FLUSH_CMD_SETTING, form.flush_cmd.trim());
+            db.set_setting(firewall::LIST_CMD_SETTING, form.list_cmd.trim());
+            match previous {
+                Some(fw) if backend.is_empty() => fw.flush(),
+                _ => firewall::reconcile(db),
+            }
+        })
+        .await;
+
+    info!(
+        "[web] fail2ban firewall enforcement set to: {}",
+        if backend_for_webhook.is_empty() {
+            "off"
+        } else {
+            &backend_for_webhook
+        }
+    );
+    fire_webhook(
+        &state,
+        "fail2ban.firewall_updated",
+        serde_json::json!({"backend": backend_for_webhook}),
+    );
+    Redirect::to("/fail2ban").into_response()
+}
+
 pub async fn edit_setting_form(
     auth: AuthAdmin,
     State(state): State<AppState>,
@@ -309,9 +382,15 @@ pub async fn ban_ip(
     let ip_for_webhook = form.ip_address.trim().to_string();
     let service_for_webhook = service.clone();
     state
-        .blocking_db(move |db| db.ban_ip(&ip, &service, &reason, duration, permanent))
-        .await
-        .ok();
+        .blocking_db(move |db| {
+            if db
+                .ban_ip(&ip, &service, &reason, duration, permanent)
+                .is_ok()
+            {
+                firewall::block(db, &ip);
+            }
+        })
+        .await;
 
     fire_webhook(
         &state,
@@ -337,7 +416,13 @@ pub async fn unban_ip(
         return StatusCode::FORBIDDEN.into_response();
     }
 
-    state.blocking_db(move |db| db.unban_ip(id)).await;
+    state
+        .blocking_db(move |db| {
+            if let Some(ip) = db.unban_ip(id) {
+                firewall::unblock(db, &ip);
+            }
+        })
+        .await;
     fire_webhook(
         &state,
         "fail2ban.ip_unbanned",
@@ -462,6 +547,120 @@ pub async fn remove_blacklist(
     Redirect::to("/fail2ban").into_response()
 }
 
+pub async fn add_rule(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Form(form): Form<Fail2banRuleForm>,
+) -> Response {
+    info!(
+        "[web] POST /fail2ban/rules — add custom rule for username={}",
+        auth.admin.username
+    );
+
+    if !same_origin(&headers) {
+        warn!("[web] fail2ban rule add blocked: non same-origin request");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let name = form.name.trim().to_string();
+    let service = form.service.trim().to_string();
+    let regex = form.regex.trim().to_string();
+    if name.is_empty() || regex.is_empty() {
+        return crate::web::errors::status_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid Rule",
+            "A rule needs a name and a regular expression.",
+            "/fail2ban",
+            "Back",
+        );
+    }
+    if let Err(e) = crate::fail2ban::compile_rule_regex(&regex) {
+        warn!("[web] rejected fail2ban rule {}: {}", name, e);
+        return crate::web::errors::status_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid Regular Expression",
+            &e,
+            "/fail2ban",
+            "Back",
+        );
+    }
+    let known_service = {
+        let service = service.clone();
+        state
+            .blocking_db(move |db| db.get_fail2ban_setting_by_service(&service).is_some())
+            .await
+    };
+    if !known_service {
+        return crate::web::errors::status_response(
+            StatusCode::BAD_REQUEST,
+            "Unknown Service",
+            "Matches are counted against a service's thresholds; pick one from Service Settings.",
+            "/fail2ban",
+            "Back",
+        );
+    }
+
+    let name_for_webhook = name.clone();
+    state
+        .blocking_db(move |db| db.create_fail2ban_rule(&name, &service, &regex))
+        .await
+        .ok();
+
+    fire_webhook(
+        &state,
+        "fail2ban.rule_added",
+        serde_json::json!({"name": name_for_webhook}),
+    );
+    Redirect::to("/fail2ban").into_response()
+}
+
+pub async fn toggle_rule(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+    Form(form): Form<Fail2banGlobalToggleForm>,
+) -> Response {
+    info!(
+        "[web] POST /fail2ban/rules/{}/toggle for username={}",
+        id, auth.admin.username
+    );
+
+    if !same_origin(&headers) {
+        warn!("[web] fail2ban rule toggle blocked: non same-origin request");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let enabled = form.enabled.as_deref() == Some("on");
+    state
+        .blocking_db(move |db| db.set_fail2ban_rule_enabled(id, enabled))
+        .await;
+    Redirect::to("/fail2ban").into_response()
+}
+
+pub async fn delete_rule(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    headers: HeaderMap,
+) -> Response {
+    info!(
+        "[web] POST /fail2ban/rules/{}/delete for username={}",
+        id, auth.admin.username
+    );
+
+    if !same_origin(&headers) {
+        warn!("[web] fail2ban rule delete blocked: non same-origin request");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    state
+        .blocking_db(move |db| db.delete_fail2ban_rule(id))
+        .await;
+    Redirect::to("/fail2ban").into_response()
+}
+
 #[cfg(test)]
 mod tests {
     use super::is_valid_ip_or_cidr;
@@ -494,6 +693,9 @@ mod tests {
         assert!(!is_valid_ip_or_cidr("not-an-ip"));
         assert!(!is_valid_ip_or_cidr("256.1.1.1"));
         assert!(!is_valid_ip_or_cidr("192.168.1.0/129"));
+        assert!(!is_valid_ip_or_cidr("192.168.1.0/33"));
+        assert!(!is_valid_ip_or_cidr("1.2.3"));
+        assert!(!is_valid_ip_or_cidr("fe80:zz::1"));
     }
 
     #[test]
@@ -6,8 +6,10 @@ use axum::{
 };
 use log::{info, warn};
 
+use crate::compliance_footer::{self, Position};
+use crate::web::audit;
 use crate::web::auth::AuthAdmin;
-use crate::web::forms::{FooterContentForm, TrackingPatternForm, TrackingRuleForm};
+use crate::web::forms::{ComplianceFooterForm, FooterContentForm, TrackingPatternForm, TrackingRuleForm};
 use crate::web::AppState;
 
 use serde_json;
@@ -20,10 +22,20 @@ struct ListTemplate<'a> {
     nav_active: &'a str,
     flash: Option<&'a str>,
     footer_html: String,
+    compliance: ComplianceSettings,
     patterns: Vec<crate::db::FooterPattern>,
     rules: Vec<crate::db::FooterRule>,
 }
 
+/// Compliance disclaimer settings as entered, before sanitising.
+struct ComplianceSettings {
+    enabled: bool,
+    html: String,
+    text: String,
+    position: Position,
+    skip_internal: bool,
+}
+
 // ── Handlers ──
 
 pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
@@ -31,12 +43,26 @@ pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
     let footer_html = state
         .blocking_db(|db| db.get_setting("footer_html").unwrap_or_default())
         .await;
+    let compliance = state
+        .blocking_db(|db| ComplianceSettings {
+            enabled: db.get_setting(compliance_footer::ENABLED_SETTING).as_deref() == Some("true"),
+            html: db.get_setting(compliance_footer::HTML_SETTING).unwrap_or_default(),
+            text: db.get_setting(compliance_footer::TEXT_SETTING).unwrap_or_default(),
+            position: db
+                .get_setting(compliance_footer::POSITION_SETTING)
+                .and_then(|v| Position::parse(&v))
+                .unwrap_or(Position::Bottom),
+            skip_internal: db.get_setting(compliance_footer::SKIP_INTERNAL_SETTING).as_deref()
+                != Some("false"),
+        })
+        .await;
     let patterns = state.blocking_db(|db| db.list_footer_patterns()).await;
     let rules = state.blocking_db(|db| db.list_footer_rules()).await;
     let tmpl = ListTemplate {
         nav_active: "Footer",
         flash: None,
         footer_html,
+        compliance,
         patterns,
         rules,
     };
@@ -56,6 +82,49 @@ pub async fn update_content(
     Redirect::to("/footer").into_response()
 }
 
+pub async fn update_compliance(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<ComplianceFooterForm>,
+) -> Response {
+    info!(
+        "[web] POST /footer/compliance — updating compliance disclaimer by username={}",
+        auth.admin.username
+    );
+    let enabled = form.enabled.is_some();
+    let skip_internal = form.skip_internal.is_some();
+    let position = Position::parse(&form.position).unwrap_or(Position::Bottom);
+    // Stored sanitised, so the page shows exactly what is sent.
+    let html = compliance_footer::sanitize_html(&form.html);
+    let text = compliance_footer::sanitize_text(&form.text);
+    state
+        .blocking_db(move |db| {
+            db.set_setting(compliance_footer::ENABLED_SETTING, if enabled { "true" } else { "false" });
+            db.set_setting(compliance_footer::HTML_SETTING, html.trim());
+            db.set_setting(compliance_footer::TEXT_SETTING, text.trim());
+            db.set_setting(compliance_footer::POSITION_SETTING, position.as_str());
+            db.set_setting(
+                compliance_footer::SKIP_INTERNAL_SETTING,
+                if skip_internal { "true" } else { "false" },
+            );
+        })
+        .await;
+    audit(
+        &state,
+        &auth,
+        "compliance_footer.updated",
+        "settings",
+        None,
+        serde_json::json!({
+            "enabled": enabled,
+            "position": position.as_str(),
+            "skip_internal": skip_internal,
+        }),
+    )
+    .await;
+    Redirect::to("/footer").into_response()
+}
+
 pub async fn create_pattern(
     _auth: AuthAdmin,
     State(state): State<AppState>,
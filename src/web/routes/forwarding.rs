@@ -7,10 +7,10 @@ use axum::{
 use log::{debug, error, info, warn};
 
 use crate::web::auth::AuthAdmin;
-use crate::web::fire_webhook;
 use crate::web::forms::{ForwardingEditForm, ForwardingForm};
 use crate::web::regen_configs;
 use crate::web::AppState;
+use crate::web::{audit, fire_webhook};
 
 // ── Templates ──
 
@@ -20,6 +20,8 @@ struct ListTemplate<'a> {
     nav_active: &'a str,
     flash: Option<&'a str>,
     forwardings: Vec<crate::db::Forwarding>,
+    loops: Vec<crate::db::ForwardingLoop>,
+    max_depth: usize,
 }
 
 #[derive(Template)]
@@ -50,6 +52,24 @@ struct ErrorTemplate<'a> {
     back_label: &'a str,
 }
 
+/// The page refusing a forwarding that would make mail loop.
+fn loop_error(cycle: &crate::db::ForwardingLoop, back_url: &str) -> Response {
+    let tmpl = ErrorTemplate {
+        nav_active: "Forwarding",
+        flash: None,
+        status_code: 400,
+        status_text: "Forwarding Loop",
+        title: "Forwarding Loop",
+        message: &format!(
+            "Saving this forwarding would send mail round in a circle: {}. Change the destination, or the alias or forwarding that leads back here.",
+            cycle.route()
+        ),
+        back_url,
+        back_label: "Back",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
 // ── Handlers ──
 
 pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
@@ -58,10 +78,19 @@ pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
         .blocking_db(|db| db.list_all_forwardings_with_domain())
         .await;
     debug!("[web] found {} forwardings", forwardings.len());
+    let loops = state.blocking_db(|db| db.detect_forwarding_loops()).await;
+    if !loops.is_empty() {
+        warn!(
+            "[web] {} forwarding loops or long chains found",
+            loops.len()
+        );
+    }
     let tmpl = ListTemplate {
         nav_active: "Forwarding",
         flash: None,
         forwardings,
+        loops,
+        max_depth: crate::db::MAX_FORWARDING_DEPTH,
     };
     Html(tmpl.render().unwrap())
 }
@@ -76,7 +105,7 @@ pub async fn new_form(_auth: AuthAdmin, State(_state): State<AppState>) -> Html<
 }
 
 pub async fn create(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Form(form): Form<ForwardingForm>,
 ) -> Response {
@@ -88,8 +117,8 @@ pub async fn create(
 
     // Extract domain from source email
     let source_parts: Vec<&str> = form.source.split('@').collect();
-    if source_parts.len() != 2 {
-        warn!("[web] invalid source email format: {}", form.source);
+    if !crate::validation::is_valid_address(&form.source) {
+        warn!("[web] invalid source email format: {:?}", form.source);
         let tmpl = ErrorTemplate {
             nav_active: "Forwarding",
             flash: None,
@@ -97,8 +126,9 @@ pub async fn create(
             status_text: "Invalid Source Email",
             title: "Invalid Source Email",
             message: &format!(
-                "The source email '{}' is not valid. Use the format 'user@domain.com'.",
-                form.source
+                "The source email '{}' is not valid. Use the format 'user@domain.com'. {}",
+                form.source,
+                crate::validation::LOCALPART_HINT
             ),
             back_url: "/forwarding/new",
             back_label: "Back",
@@ -137,6 +167,28 @@ pub async fn create(
     };
 
     let domain_id = domain.id;
+    let proposed = crate::db::Forwarding {
+        id: i64::MAX,
+        domain_id,
+        source: form.source.clone(),
+        destination: form.destination.clone(),
+        active: true,
+        keep_copy,
+        domain_name: Some(domain.domain.clone()),
+    };
+    if let Some(cycle) = state
+        .blocking_db(move |db| db.forwarding_cycle(proposed))
+        .await
+    {
+        warn!(
+            "[web] refusing forwarding {} -> {}: forwarding loop {}",
+            form.source,
+            form.destination,
+            cycle.route()
+        );
+        return loop_error(&cycle, "/forwarding/new");
+    }
+
     let source = form.source.clone();
     let destination = form.destination.clone();
     let create_result = state
@@ -150,6 +202,15 @@ pub async fn create(
                 form.source, form.destination, id, keep_copy
             );
             regen_configs(&state).await;
+            audit(
+                &state,
+                &auth,
+                "forwarding.created",
+                "forwarding",
+                Some(id),
+                serde_json::json!({"source": form.source, "destination": form.destination}),
+            )
+            .await;
             fire_webhook(
                 &state,
                 "forwarding.created",
@@ -199,7 +260,7 @@ pub async fn edit_form(
 }
 
 pub async fn update(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Form(form): Form<ForwardingEditForm>,
@@ -210,24 +271,86 @@ pub async fn update(
         "[web] POST /forwarding/{} — updating forwarding source={}, destination={}, active={}, keep_copy={}",
         id, form.source, form.destination, active, keep_copy
     );
+    if !crate::validation::is_valid_address(&form.source) {
+        warn!(
+            "[web] invalid source email format for forwarding id={}: {:?}",
+            id, form.source
+        );
+        let back_url = format!("/forwarding/{}/edit", id);
+        let tmpl = ErrorTemplate {
+            nav_active: "Forwarding",
+            flash: None,
+            status_code: 400,
+            status_text: "Invalid Source Email",
+            title: "Invalid Source Email",
+            message: &format!(
+                "The source email '{}' is not valid. Use the format 'user@domain.com'. {}",
+                form.source,
+                crate::validation::LOCALPART_HINT
+            ),
+            back_url: &back_url,
+            back_label: "Back",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    if active {
+        let source = form.source.clone();
+        let destination = form.destination.clone();
+        let cycle = state
+            .blocking_db(move |db| {
+                let mut forwarding = db.get_forwarding(id)?;
+                forwarding.source = source;
+                forwarding.destination = destination;
+                forwarding.active = true;
+                forwarding.keep_copy = keep_copy;
+                db.forwarding_cycle(forwarding)
+            })
+            .await;
+        if let Some(cycle) = cycle {
+            warn!(
+                "[web] refusing update of forwarding id={}: forwarding loop {}",
+                id,
+                cycle.route()
+            );
+            return loop_error(&cycle, &format!("/forwarding/{}/edit", id));
+        }
+    }
     let source = form.source.clone();
     let destination = form.destination.clone();
     state
         .blocking_db(move |db| db.update_forwarding(id, &source, &destination, active, keep_copy))
         .await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "forwarding.updated",
+        "forwarding",
+        Some(id),
+        serde_json::json!({"source": form.source, "destination": form.destination, "active": active}),
+    )
+    .await;
     fire_webhook(&state, "forwarding.updated", serde_json::json!({"id": id}));
     Redirect::to("/forwarding").into_response()
 }
 
 pub async fn delete(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Response {
     warn!("[web] POST /forwarding/{}/delete — deleting forwarding", id);
     state.blocking_db(move |db| db.delete_forwarding(id)).await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "forwarding.deleted",
+        "forwarding",
+        Some(id),
+        serde_json::json!({}),
+    )
+    .await;
     fire_webhook(&state, "forwarding.deleted", serde_json::json!({"id": id}));
     Redirect::to("/forwarding").into_response()
 }
@@ -0,0 +1,66 @@
+//! Liveness and readiness probes for orchestrators.
+//!
+//! `/healthz` answers as long as the web server runs, with `status`
+//! `degraded` while `/pixel` keeps failing to store opens; `/readyz` also
+//! checks that PostgreSQL answers a trivial query and returns `503` when it
+//! does not.  Neither needs authentication, so probes never count as failed
+//! logins.
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, routing::get, Json, Router};
+use log::{debug, warn};
+
+use crate::web::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+}
+
+async fn healthz() -> impl IntoResponse {
+    Json(health_body(&crate::web::routes::pixel::STORE_HEALTH))
+}
+
+/// The `/healthz` response.  Degraded pixel storage does not fail the probe:
+/// the server still runs and still serves the pixel.
+pub(crate) fn health_body(
+    pixel_store: &crate::web::routes::pixel::StoreHealth,
+) -> serde_json::Value {
+    let pixel_store = if pixel_store.is_degraded() {
+        "degraded"
+    } else {
+        "ok"
+    };
+    serde_json::json!({
+        "status": pixel_store,
+        "version": env!("CARGO_PKG_VERSION"),
+        "pixel_store": pixel_store,
+    })
+}
+
+async fn readyz(State(state): State<AppState>) -> impl IntoResponse {
+    match state.blocking_db(|db| db.ping()).await {
+        Ok(()) => {
+            debug!("[web] GET /readyz — database reachable");
+            (
+                StatusCode::OK,
+                Json(serde_json::json!({
+                    "status": "ok",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "database": "ok",
+                })),
+            )
+        }
+        Err(e) => {
+            warn!("[web] GET /readyz — database unreachable: {}", e);
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                Json(serde_json::json!({
+                    "status": "unavailable",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "database": "unreachable",
+                })),
+            )
+        }
+    }
+}
@@ -556,7 +556,8 @@ async fn tool_list_emails(state: &AppState, args: &Value) -> Result<Value, Strin
                 "to": e.to,
                 "date": e.date,
                 "is_new": e.is_new,
-                "is_spam": e.is_spam
+                "is_spam": e.is_spam,
+                "unreadable": e.unreadable
             })
         })
         .collect();
@@ -4,22 +4,28 @@ pub mod aliases;
 pub mod api_docs;
 pub mod api_email;
 pub mod api_soap;
+pub mod api_v1;
+pub mod approvals;
+pub mod audit;
 pub mod bimi;
 pub mod bounce;
 pub mod caldav;
 pub mod carddav;
 pub mod configs;
+pub mod csv_transfer;
 pub mod dashboard;
 pub mod dmarc;
 pub mod domains;
 pub mod fail2ban;
 pub mod footer;
 pub mod forwarding;
+pub mod health;
 pub mod imap_idle;
 pub mod mcp;
 pub mod pixel;
 pub mod queue;
 pub mod rate_limits;
+pub mod recycle_bin;
 pub mod registration;
 pub mod relays;
 pub mod settings;
@@ -33,6 +39,7 @@ pub mod webmail;
 
 use super::AppState;
 use axum::{
+    extract::DefaultBodyLimit,
     routing::{get, post},
     Router,
 };
@@ -42,9 +49,12 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/", get(dashboard::page))
         .route("/domains", get(domains::list).post(domains::create))
         .route("/domains/new", get(domains::new_form))
+        .route("/domains/export", get(domains::export))
+        .route("/domains/import", post(domains::import))
         .route("/domains/:id/edit", get(domains::edit_form))
         .route("/domains/:id/delete", post(domains::delete))
         .route("/domains/:id/dkim", post(domains::generate_dkim))
+        .route("/domains/:id/dkim/rotate", post(domains::rotate_dkim))
         .route("/domains/:id/dmarc", post(domains::set_dmarc_inbox))
         .route("/domains/:id/dmarc/delete", post(domains::remove_dmarc_inbox))
         .route("/domains/:id/dmarc/ruf", post(domains::set_dmarc_ruf_inbox))
@@ -53,15 +63,35 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/domains/:id/abuse/delete", post(domains::remove_abuse_inbox))
         .route("/domains/:id/bounce", post(domains::set_bounce_inbox))
         .route("/domains/:id/bounce/delete", post(domains::remove_bounce_inbox))
+        .route("/domains/:id/reserved", post(domains::update_reserved_mailboxes))
+        .route("/domains/:id/registration", post(domains::update_registration))
+        .route(
+            "/domains/:id/registration/invites",
+            post(domains::create_registration_invite),
+        )
+        .route(
+            "/domains/:id/registration/invites/:invite_id/delete",
+            post(domains::delete_registration_invite),
+        )
         .route("/domains/:id/dns", get(domains::dns_info))
         .route("/domains/:id/check", get(domains::dns_check_run))
+        .route("/domains/:id/check/public-ip", post(domains::set_public_ip_method))
         .route("/domains/:id", post(domains::update))
         .route("/accounts/new", get(accounts::new_form))
         .route("/accounts", get(accounts::list).post(accounts::create))
+        .route("/accounts/export", get(accounts::export))
+        .route("/accounts/import", post(accounts::import))
         .route("/accounts/:id/edit", get(accounts::edit_form))
         .route("/accounts/:id/delete", post(accounts::delete))
+        .route("/accounts/:id/rules", post(accounts::create_rule))
+        .route(
+            "/accounts/:id/rules/:rule_id/delete",
+            post(accounts::delete_rule),
+        )
+        .route("/accounts/:id/rules/:rule_id/move", post(accounts::move_rule))
         .route("/accounts/:id", post(accounts::update))
         .route("/aliases/new", get(aliases::new_form))
+        .route("/aliases/resolve", get(aliases::resolve))
         .route("/aliases", get(aliases::list).post(aliases::create))
         .route("/aliases/:id/edit", get(aliases::edit_form))
         .route("/aliases/:id/delete", post(aliases::delete))
@@ -76,6 +106,8 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/forwarding/:id", post(forwarding::update))
         .route("/tracking", get(tracking::list))
         .route("/tracking/pixel", post(tracking::update_pixel_settings))
+        .route("/tracking/export.csv", get(tracking::export_csv))
+        .route("/tracking/export.json", get(tracking::export_json))
         .route("/tracking/patterns", post(tracking::create_pattern))
         .route(
             "/tracking/patterns/:id/delete",
@@ -86,6 +118,7 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/tracking/:msg_id", get(tracking::detail))
         .route("/footer", get(footer::list))
         .route("/footer/content", post(footer::update_content))
+        .route("/footer/compliance", post(footer::update_compliance))
         .route("/footer/patterns", post(footer::create_pattern))
         .route("/footer/patterns/:id/delete", post(footer::delete_pattern))
         .route("/footer/rules", post(footer::create_rule))
@@ -93,13 +126,24 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/queue", get(queue::list))
         .route("/queue/flush", post(queue::flush))
         .route("/queue/purge", post(queue::purge))
+        .route("/queue/hold", post(queue::hold))
+        .route("/queue/release", post(queue::release))
+        .route("/queue/:id", get(queue::detail))
         .route("/queue/:id/delete", post(queue::delete_message))
         .route("/queue/:id/flush", post(queue::flush_message))
+        .route("/queue/deferred", get(queue::deferred))
+        .route("/queue/deferred/retry", post(queue::retry_all_deferred))
+        .route("/queue/deferred/:id/retry", post(queue::retry_deferred))
         .route("/webmail", get(webmail::inbox))
         .route("/webmail/view/:filename", get(webmail::view_email))
         .route("/webmail/download/:filename", get(webmail::download_email))
+        .route("/webmail/attachment/:filename/:index", get(webmail::download_attachment))
         .route("/webmail/reply/:filename", get(webmail::reply_email))
         .route("/webmail/delete/:filename", post(webmail::delete_email))
+        .route("/webmail/mark/:filename", post(webmail::mark_email))
+        .route("/webmail/move/:filename", post(webmail::move_email))
+        .route("/webmail/folder/create", post(webmail::create_folder))
+        .route("/webmail/folder/delete", post(webmail::delete_folder))
         .route("/webmail/compose", get(webmail::compose))
         .route("/webmail/send", post(webmail::send_email))
         .route("/webmail/idle", get(webmail::idle_stream))
@@ -117,11 +161,38 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/settings/2fa", get(settings::setup_2fa))
         .route("/settings/2fa/enable", post(settings::enable_2fa))
         .route("/settings/2fa/disable", post(settings::disable_2fa))
+        .route(
+            "/settings/2fa/recovery-codes",
+            post(settings::regenerate_recovery_codes),
+        )
         .route("/settings/features", post(settings::update_features))
+        .route(
+            "/settings/tracking-kill-switch",
+            post(settings::update_tracking_kill_switch),
+        )
+        .route(
+            crate::web::maintenance::TOGGLE_PATH,
+            post(settings::update_maintenance_mode),
+        )
         .route("/settings/mail", post(settings::update_mail_settings))
+        .route("/settings/dkim", post(settings::update_dkim_settings))
+        .route(
+            "/settings/delivery-policy",
+            post(settings::update_delivery_policy),
+        )
+        .route("/settings/sender-auth", post(settings::update_sender_auth))
+        .route("/settings/greylist", post(settings::update_greylist_settings))
+        .route("/settings/send-limits", post(settings::update_send_limits))
+        .route("/settings/approvals", post(settings::update_approval_settings))
         .route("/settings/tls/regenerate", post(settings::regenerate_tls))
         .route("/settings/tls/cert.pem", get(settings::download_cert))
         .route("/settings/tls/key.pem", get(settings::download_key))
+        .route("/settings/backup.json", get(settings::download_backup))
+        .route(
+            "/settings/restore",
+            post(settings::restore_backup)
+                .layer(DefaultBodyLimit::max(settings::BACKUP_UPLOAD_LIMIT)),
+        )
         .route(
             "/settings/restart-services",
             post(settings::restart_services),
@@ -131,9 +202,33 @@ pub fn auth_routes() -> Router<AppState> {
             post(settings::restart_container),
         )
         .route("/configs", get(configs::page))
+        .route("/configs/relay-policy", post(configs::update_relay_policy))
+        .route("/audit", get(audit::list))
+        .route("/approvals", get(approvals::list))
+        .route("/approvals/:id/approve", post(approvals::approve))
+        .route("/approvals/:id/cancel", post(approvals::cancel))
+        .route("/recycle-bin", get(recycle_bin::list))
+        .route(
+            "/recycle-bin/domains/:id/restore",
+            post(recycle_bin::restore_domain),
+        )
+        .route(
+            "/recycle-bin/domains/:id/purge",
+            post(recycle_bin::purge_domain),
+        )
+        .route(
+            "/recycle-bin/accounts/:id/restore",
+            post(recycle_bin::restore_account),
+        )
+        .route(
+            "/recycle-bin/accounts/:id/purge",
+            post(recycle_bin::purge_account),
+        )
         .route("/api", get(api_docs::page))
         .route("/api/token/generate", post(api_docs::generate_token))
         .route("/api/token/revoke", post(api_docs::revoke_token))
+        .route("/api/keys", post(api_docs::create_key))
+        .route("/api/keys/:id/revoke", post(api_docs::revoke_key))
         .route("/api/emails", get(api_email::list_emails).post(api_email::send_email))
         .route(
             "/api/emails/:filename",
@@ -142,6 +237,7 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/api/soap", get(api_soap::wsdl).post(api_soap::handle))
         .route("/fail2ban", get(fail2ban::overview))
         .route("/fail2ban/toggle", post(fail2ban::toggle_system))
+        .route("/fail2ban/firewall", post(fail2ban::update_firewall))
         .route("/fail2ban/ban", post(fail2ban::ban_ip))
         .route("/fail2ban/unban/:id", post(fail2ban::unban_ip))
         .route(
@@ -154,6 +250,9 @@ pub fn auth_routes() -> Router<AppState> {
             "/fail2ban/whitelist/:id/delete",
             post(fail2ban::remove_whitelist),
         )
+        .route("/fail2ban/rules", post(fail2ban::add_rule))
+        .route("/fail2ban/rules/:id/toggle", post(fail2ban::toggle_rule))
+        .route("/fail2ban/rules/:id/delete", post(fail2ban::delete_rule))
         .route("/fail2ban/blacklist", post(fail2ban::add_blacklist))
         .route(
             "/fail2ban/blacklist/:id/delete",
@@ -178,6 +277,7 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/relays", get(relays::list).post(relays::create))
         .route("/relays/:id/edit", get(relays::edit_form))
         .route("/relays/:id/delete", post(relays::delete))
+        .route("/relays/:id/test", post(relays::test_send))
         .route("/relays/:id", post(relays::update))
         .route("/relays/:id/assignments", post(relays::add_assignment))
         .route("/relays/:id/assignments/:aid/delete",
@@ -212,6 +312,7 @@ pub fn auth_routes() -> Router<AppState> {
         .route("/mcp", get(mcp::page).post(mcp::handle))
         .route("/webdav", get(webdav::list))
         .route("/webdav/settings", post(webdav::update_settings))
+        .route("/webdav/quota", post(webdav::update_user_quota))
         .route("/webdav/:id/delete", post(webdav::admin_delete_file))
         .route("/rate-limits", get(rate_limits::list))
         .route("/rate-limits/rules", post(rate_limits::create_rule))
@@ -227,4 +328,8 @@ pub fn registration_routes() -> Router<AppState> {
             "/register/:domain",
             get(registration::show_form).post(registration::handle_form),
         )
+        .route(
+            "/api/register/:domain",
+            get(registration::api_rules).post(registration::api_register),
+        )
 }
@@ -1,11 +1,13 @@
 use axum::{
-    extract::{Query, State},
+    extract::{ConnectInfo, Query, State},
     http::{header, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     Router,
 };
-use log::{debug, info};
+use log::{debug, error, info, warn};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering};
 
 use crate::web::forms::PixelQuery;
 use crate::web::AppState;
@@ -32,6 +34,238 @@ fn mask_ip(ip: &str) -> String {
     ip.to_string()
 }
 
+/// Notify `url` that a tracked message was opened for the first time.
+///
+/// The notification is queued on a background thread so the pixel response is
+/// not delayed; the webhook worker delivers and retries it.
+fn fire_open_webhook(
+    state: &AppState,
+    url: String,
+    message_id: String,
+    client_ip: String,
+    user_agent: String,
+) {
+    let db = state.db.clone();
+    std::thread::spawn(move || {
+        let payload = open_webhook_payload(
+            &message_id,
+            &client_ip,
+            &user_agent,
+            &chrono::Utc::now().to_rfc3339(),
+        );
+        crate::webhook_queue::enqueue(
+            &db,
+            &url,
+            &payload.to_string(),
+            "tracking.first_open",
+            &message_id,
+            0,
+        );
+    });
+}
+
+fn open_webhook_payload(
+    message_id: &str,
+    client_ip: &str,
+    user_agent: &str,
+    opened_at: &str,
+) -> serde_json::Value {
+    serde_json::json!({
+        "message_id": message_id,
+        "client_ip": client_ip,
+        "user_agent": user_agent,
+        "opened_at": opened_at,
+    })
+}
+
+/// Whether opens for `message_id` may be recorded under `allowlist`, a list
+/// of ids or `prefix*` patterns separated by commas or whitespace.  An empty
+/// allowlist accepts every id.
+fn id_allowed(message_id: &str, allowlist: &str) -> bool {
+    let mut patterns = allowlist
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+        .peekable();
+    if patterns.peek().is_none() {
+        return true;
+    }
+    patterns.any(|p| match p.strip_suffix('*') {
+        Some(prefix) => message_id.starts_with(prefix),
+        None => message_id == p,
+    })
+}
+
+/// Configured allowlist: the `pixel_id_allowlist` setting, else `PIXEL_ID_ALLOWLIST`.
+pub(crate) fn id_allowlist(db: &crate::db::Database) -> String {
+    db.get_setting("pixel_id_allowlist")
+        .or_else(|| std::env::var("PIXEL_ID_ALLOWLIST").ok())
+        .unwrap_or_default()
+}
+
+/// How pixel message IDs are checked before an open is recorded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum IdValidation {
+    /// Letters, digits and `-_.@`, e.g. allowlisted `acme-4f1c` style ids.
+    Lenient,
+    /// Only the lowercase hyphenated UUIDs the content filter generates,
+    /// behind the configured pixel id prefix.
+    Strict,
+}
+
+impl IdValidation {
+    pub(crate) fn parse(s: &str) -> Option<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "lenient" => Some(IdValidation::Lenient),
+            "strict" => Some(IdValidation::Strict),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            IdValidation::Lenient => "lenient",
+            IdValidation::Strict => "strict",
+        }
+    }
+
+    /// The `pixel_id_validation` setting, else `PIXEL_ID_VALIDATION`, else lenient.
+    pub(crate) fn configured(db: &crate::db::Database) -> Self {
+        db.get_setting("pixel_id_validation")
+            .or_else(|| std::env::var("PIXEL_ID_VALIDATION").ok())
+            .and_then(|v| IdValidation::parse(&v))
+            .unwrap_or(IdValidation::Lenient)
+    }
+}
+
+pub(crate) const DEDUP_WINDOW_SETTING: &str = "pixel_dedup_window_seconds";
+pub(crate) const DEFAULT_DEDUP_WINDOW_SECS: i64 = 10;
+pub(crate) const MAX_DEDUP_WINDOW_SECS: i64 = 86_400;
+
+/// Seconds within which repeat opens of a message from the same client are
+/// ignored: the `pixel_dedup_window_seconds` setting, else 10.  0 records
+/// every open.
+pub(crate) fn dedup_window_secs(db: &crate::db::Database) -> i64 {
+    db.get_setting(DEDUP_WINDOW_SETTING)
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|secs| (0..=MAX_DEDUP_WINDOW_SECS).contains(secs))
+        .unwrap_or(DEFAULT_DEDUP_WINDOW_SECS)
+}
+
+/// Longest message ID accepted, and stored in a rejection record.
+const MAX_ID_LEN: usize = 128;
+/// How long daily rejection counts are kept.
+const REJECTION_RETENTION_DAYS: i64 = 90;
+
+/// Why `message_id` is malformed under `mode`, if it is.  `prefix` is the
+/// namespace the content filter puts in front of the ids it issues.
+fn validate_id(message_id: &str, mode: IdValidation, prefix: &str) -> Result<(), &'static str> {
+    if message_id.len() > MAX_ID_LEN {
+        return Err("id too long");
+    }
+    match mode {
+        IdValidation::Strict => {
+            let id = message_id.strip_prefix(prefix).ok_or("not a UUID")?;
+            match uuid::Uuid::parse_str(id) {
+                Ok(uuid) if uuid.hyphenated().to_string() == id => Ok(()),
+                _ => Err("not a UUID"),
+            }
+        }
+        IdValidation::Lenient => {
+            if message_id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@'))
+            {
+                Ok(())
+            } else {
+                Err("invalid characters")
+            }
+        }
+    }
+}
+
+/// Check `message_id` against the configured validation and allowlist,
+/// counting the request in `pixel_rejection_counts` when it fails either.
+fn screen_id(
+    db: &crate::db::Database,
+    message_id: &str,
+    client_ip: &str,
+) -> Result<(), &'static str> {
+    let prefix = crate::filter::pixel_id_prefix(db);
+    let result = validate_id(message_id, IdValidation::configured(db), &prefix).and_then(|()| {
+        if id_allowed(message_id, &id_allowlist(db)) {
+            Ok(())
+        } else {
+            Err("outside allowlist")
+        }
+    });
+    if let Err(reason) = result {
+        let stored: String = message_id.chars().take(MAX_ID_LEN).collect();
+        db.record_pixel_rejection(&stored, client_ip, reason, REJECTION_RETENTION_DAYS);
+    }
+    result
+}
+
+/// Consecutive failed writes after which pixel storage counts as degraded.
+const DEGRADED_AFTER_FAILURES: u32 = 5;
+
+/// Whether opens are being stored, judged by the outcome of recent writes.
+/// The pixel is served either way, so without this a broken database would
+/// only show up as opens that are silently missing.
+pub(crate) struct StoreHealth {
+    failures: AtomicU32,
+}
+
+impl StoreHealth {
+    pub(crate) const fn new() -> Self {
+        StoreHealth {
+            failures: AtomicU32::new(0),
+        }
+    }
+
+    /// Count the outcome of one write, logging when the state changes.
+    fn record(&self, stored: bool) {
+        if stored {
+            let failures = self.failures.swap(0, Ordering::Relaxed);
+            if failures >= DEGRADED_AFTER_FAILURES {
+                info!(
+                    "[web] pixel open storage recovered after {} failed writes",
+                    failures
+                );
+            }
+            return;
+        }
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures == DEGRADED_AFTER_FAILURES {
+            error!(
+                "[web] PIXEL OPENS ARE NOT BEING STORED: {} writes in a row failed; /healthz reports degraded until one succeeds",
+                failures
+            );
+        }
+    }
+
+    pub(crate) fn is_degraded(&self) -> bool {
+        self.failures.load(Ordering::Relaxed) >= DEGRADED_AFTER_FAILURES
+    }
+}
+
+/// Storage health of the `/pixel` route, reported by `/healthz`.
+pub(crate) static STORE_HEALTH: StoreHealth = StoreHealth::new();
+
+fn gif_response() -> Response {
+    let gif: &[u8] = &[
+        0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0xff, 0xff,
+        0xff, 0x00, 0x00, 0x00, 0x21, 0xf9, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
+        0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
+    ];
+
+    (
+        StatusCode::OK,
+        [(header::CONTENT_TYPE, "image/gif")],
+        gif.to_vec(),
+    )
+        .into_response()
+}
+
 async fn pixel_handler(
     State(state): State<AppState>,
     Query(params): Query<PixelQuery>,
@@ -46,17 +280,17 @@ async fn pixel_handler(
         }
     );
     if !params.id.is_empty() {
-        let client_ip = req
-            .headers()
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-            .map(|s| s.split(',').next().unwrap_or("").trim().to_string())
-            .or_else(|| {
-                req.headers()
-                    .get("x-real-ip")
-                    .and_then(|v| v.to_str().ok())
-                    .map(|s| s.to_string())
-            })
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|c| c.0.ip());
+        let client_ip = crate::web::auth::client_ip(req.headers(), peer);
+
+        // Resolve location from the full address before it is masked
+        let location = state
+            .geoip
+            .as_ref()
+            .and_then(|g| g.lookup(&client_ip))
             .unwrap_or_default();
 
         // Mask last segment of IP for geo-location while preserving privacy
@@ -75,34 +309,234 @@ async fn pixel_handler(
         let db_client_ip = client_ip.clone();
         let db_user_agent = user_agent.clone();
 
-        state
+        let country = location.country.clone();
+        let city = location.city.clone();
+
+        // `Err` when the id is malformed or outside the allowlist,
+        // `Ok(Err)` when the open could not be stored and `Ok(Ok(None))` for
+        // a repeat open inside the dedup window: the GIF is still served.
+        let recorded: Result<Result<Option<bool>, String>, &str> = state
             .blocking_db(move |db| {
-                db.record_pixel_open(&db_message_id, &db_client_ip, &db_user_agent)
+                screen_id(db, &db_message_id, &db_client_ip)?;
+                Ok(db.record_pixel_open(
+                    &db_message_id,
+                    &db_client_ip,
+                    &db_user_agent,
+                    country.as_deref(),
+                    city.as_deref(),
+                    dedup_window_secs(db),
+                ))
             })
             .await;
+        if let Ok(stored) = &recorded {
+            STORE_HEALTH.record(stored.is_ok());
+        }
+        let is_first_open = match recorded {
+            Ok(Ok(Some(first))) => first,
+            Ok(Ok(None)) => {
+                debug!(
+                    "[web] duplicate pixel open ignored: message_id={}, client_ip={}, user_agent={}",
+                    message_id, client_ip, user_agent
+                );
+                return gif_response();
+            }
+            Ok(Err(e)) => {
+                warn!(
+                    "[web] pixel open not stored: message_id={}, client_ip={}: {}",
+                    message_id, client_ip, e
+                );
+                return gif_response();
+            }
+            Err(reason) => {
+                warn!(
+                    "[web] pixel open rejected: message_id={:?}, client_ip={}, reason={}",
+                    message_id, client_ip, reason
+                );
+                return gif_response();
+            }
+        };
         info!(
-            "[web] pixel open recorded: message_id={}, client_ip={}, user_agent={}",
-            message_id, client_ip, user_agent
+            "[web] pixel open recorded: message_id={}, client_ip={}, user_agent={}, country={}, city={}",
+            message_id,
+            client_ip,
+            user_agent,
+            location.country.as_deref().unwrap_or("-"),
+            location.city.as_deref().unwrap_or("-")
         );
-    }
 
-    let gif: &[u8] = &[
-        0x47, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00, 0xff, 0xff,
-        0xff, 0x00, 0x00, 0x00, 0x21, 0xf9, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0x2c, 0x00, 0x00,
-        0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x02, 0x02, 0x44, 0x01, 0x00, 0x3b,
-    ];
+        if is_first_open {
+            if let Some(url) = state.open_webhook_url.clone() {
+                fire_open_webhook(&state, url, message_id, client_ip, user_agent);
+            }
+        }
+    }
 
-    (
-        StatusCode::OK,
-        [(header::CONTENT_TYPE, "image/gif")],
-        gif.to_vec(),
-    )
-        .into_response()
+    gif_response()
 }
 
 #[cfg(test)]
 mod tests {
-    use super::mask_ip;
+    use super::{
+        gif_response, id_allowed, mask_ip, open_webhook_payload, screen_id, validate_id,
+        IdValidation, StoreHealth, DEGRADED_AFTER_FAILURES,
+    };
+
+    #[test]
+    fn open_webhook_payload_has_expected_fields() {
+        let p = open_webhook_payload("abc", "10.0.0.x", "Mail/1.0", "2026-01-01T00:00:00+00:00");
+        assert_eq!(p["message_id"], "abc");
+        assert_eq!(p["client_ip"], "10.0.0.x");
+        assert_eq!(p["user_agent"], "Mail/1.0");
+        assert_eq!(p["opened_at"], "2026-01-01T00:00:00+00:00");
+        assert_eq!(p.as_object().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn id_outside_allowlist_is_not_recorded() {
+        let allowlist = "acme-*, globex-*\nexact-id";
+        assert!(id_allowed("acme-4f1c", allowlist));
+        assert!(id_allowed("exact-id", allowlist));
+        assert!(!id_allowed("initech-4f1c", allowlist));
+        assert!(!id_allowed("exact-id-2", allowlist));
+        assert!(id_allowed("anything", ""));
+        assert!(id_allowed("anything", " , "));
+
+        // Ignored ids still get the transparent GIF.
+        let resp = gif_response();
+        assert_eq!(resp.status(), axum::http::StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "image/gif");
+    }
+
+    #[test]
+    fn prefixed_ids_issued_by_the_filter_match_a_prefix_allowlist() {
+        let id = crate::filter::new_pixel_id("acme-");
+        assert!(id_allowed(&id, "acme-*"));
+        assert!(!id_allowed(&crate::filter::new_pixel_id(""), "acme-*"));
+        assert_eq!(validate_id(&id, IdValidation::Strict, "acme-"), Ok(()));
+        assert_eq!(
+            validate_id(&id, IdValidation::Strict, "globex-"),
+            Err("not a UUID")
+        );
+        assert_eq!(
+            validate_id(&id, IdValidation::Strict, ""),
+            Err("not a UUID")
+        );
+        assert!(crate::filter::valid_pixel_id_prefix("acme-"));
+        assert!(!crate::filter::valid_pixel_id_prefix("acme*"));
+    }
+
+    #[test]
+    fn strict_validation_rejects_what_lenient_accepts() {
+        let uuid = "0b6f3c4e-2a8d-4f5b-9c1e-7d2a3b4c5d6e";
+        assert_eq!(validate_id(uuid, IdValidation::Strict, ""), Ok(()));
+        assert_eq!(validate_id(uuid, IdValidation::Lenient, ""), Ok(()));
+
+        let malformed = "acme-4f1c";
+        assert_eq!(
+            validate_id(malformed, IdValidation::Strict, ""),
+            Err("not a UUID")
+        );
+        assert_eq!(validate_id(malformed, IdValidation::Lenient, ""), Ok(()));
+        assert_eq!(
+            validate_id(&uuid.to_uppercase(), IdValidation::Strict, ""),
+            Err("not a UUID")
+        );
+        assert_eq!(
+            validate_id("<script>", IdValidation::Lenient, ""),
+            Err("invalid characters")
+        );
+        assert_eq!(
+            validate_id(&"a".repeat(129), IdValidation::Lenient, ""),
+            Err("id too long")
+        );
+        assert_eq!(IdValidation::parse(" Strict "), Some(IdValidation::Strict));
+        assert_eq!(IdValidation::parse("paranoid"), None);
+    }
+
+    /// Needs a reachable server: run with `TEST_DATABASE_URL` set and
+    /// `cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn malformed_id_is_counted_under_strict_mode_only() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let db = crate::db::Database::open(&url);
+        let previous = db.get_setting("pixel_id_validation");
+        let id = format!("malformed-{}", std::process::id());
+        let not_a_uuid = |db: &crate::db::Database| {
+            db.list_pixel_rejections(1)
+                .into_iter()
+                .find(|r| r.reason == "not a UUID")
+        };
+        let before = not_a_uuid(&db).map_or(0, |r| r.count);
+
+        db.set_setting("pixel_id_validation", "strict");
+        assert_eq!(screen_id(&db, &id, "192.0.2.x"), Err("not a UUID"));
+        assert_eq!(screen_id(&db, &id, "192.0.2.x"), Err("not a UUID"));
+        let rejection = not_a_uuid(&db).unwrap();
+        assert_eq!(rejection.count, before + 2);
+        assert_eq!(rejection.last_message_id, id);
+        assert_eq!(rejection.last_client_ip, "192.0.2.x");
+
+        db.set_setting("pixel_id_validation", "lenient");
+        assert_eq!(screen_id(&db, &id, "192.0.2.x"), Ok(()));
+        assert_eq!(not_a_uuid(&db).unwrap().count, before + 2);
+
+        db.set_setting(
+            "pixel_id_validation",
+            previous.as_deref().unwrap_or("lenient"),
+        );
+    }
+
+    /// Needs a reachable server: run with `TEST_DATABASE_URL` set and
+    /// `cargo test -- --ignored`.
+    #[test]
+    #[ignore = "needs TEST_DATABASE_URL"]
+    fn concurrent_opens_report_one_first_open() {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL is not set");
+        let db = crate::db::Database::open(&url);
+        let id = uuid::Uuid::new_v4().to_string();
+        db.create_tracked_message(&id, "a@example.com", "b@example.com", "race", None);
+
+        let threads: Vec<_> = (0..8)
+            .map(|n| {
+                let db = db.clone();
+                let id = id.clone();
+                std::thread::spawn(move || {
+                    let ip = format!("192.0.2.{}", n);
+                    db.record_pixel_open(&id, &ip, "Mail/1.0", None, None, 0)
+                })
+            })
+            .collect();
+        let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        assert!(results.iter().all(|r| matches!(r, Ok(Some(_)))));
+        assert_eq!(results.iter().filter(|r| **r == Ok(Some(true))).count(), 1);
+    }
+
+    #[test]
+    fn repeated_write_failures_degrade_health_while_the_pixel_serves() {
+        let health = StoreHealth::new();
+        for _ in 1..DEGRADED_AFTER_FAILURES {
+            health.record(false);
+        }
+        assert!(!health.is_degraded(), "a few failures are tolerated");
+        health.record(false);
+        assert!(health.is_degraded());
+        let body = crate::web::routes::health::health_body(&health);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["pixel_store"], "degraded");
+
+        // Opens are not stored, but the image is still served.
+        let resp = gif_response();
+        assert_eq!(resp.status(), axum::http::StatusCode::OK);
+        assert_eq!(resp.headers()["content-type"], "image/gif");
+
+        health.record(true);
+        assert!(!health.is_degraded());
+        assert_eq!(
+            crate::web::routes::health::health_body(&health)["status"],
+            "ok"
+        );
+    }
 
     #[test]
     fn mask_ip_ipv4_last_octet() {
@@ -1,18 +1,38 @@
 use askama::Template;
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     http::{header, HeaderMap, StatusCode},
+    middleware,
     response::{Html, IntoResponse, Redirect, Response},
+    routing::get,
+    Form, Json, Router,
 };
-use log::{debug, error, warn};
+use log::{debug, error, info, warn};
+use serde::Serialize;
 use std::path::Path as FsPath;
 use std::process::Command;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 
 use crate::web::auth::AuthAdmin;
+use crate::web::forms::{QueueSelectForm, QueueSortQuery};
+use crate::web::routes::approvals::{self, DestructiveAction};
 use crate::web::AppState;
 
 const POSTQUEUE_PATHS: [&str; 2] = ["/usr/sbin/postqueue", "/usr/bin/postqueue"];
 const POSTSUPER_PATHS: [&str; 2] = ["/usr/sbin/postsuper", "/usr/bin/postsuper"];
+const POSTCAT_PATHS: [&str; 2] = ["/usr/sbin/postcat", "/usr/bin/postcat"];
+/// Upper bound on how long `postqueue -j` may run for the metrics endpoint.
+const METRICS_TIMEOUT: Duration = Duration::from_secs(10);
+const POSTFIX_SPOOL_DIR: &str = "/var/spool/postfix";
+const POSTFIX_MAIN_CF: &str = "/etc/postfix/main.cf";
+/// How many upcoming delivery attempts the retry schedule lists.
+const SCHEDULE_ATTEMPTS: usize = 12;
+
+/// When the whole queue was last flushed, by an admin or by
+/// [`crate::queue_flush`].  Held while `postqueue -f` runs so flushes never
+/// overlap.
+static LAST_FLUSH: Mutex<Option<Instant>> = Mutex::new(None);
 
 fn find_postqueue_bin() -> Option<&'static str> {
     POSTQUEUE_PATHS
@@ -26,13 +46,81 @@ fn find_postsuper_bin() -> Option<&'static str> {
         .find(|path| FsPath::new(path).exists())
 }
 
-/// A single entry parsed from the `postqueue -p` output.
+fn find_postcat_bin() -> Option<&'static str> {
+    POSTCAT_PATHS
+        .into_iter()
+        .find(|path| FsPath::new(path).exists())
+}
+
+/// A single entry parsed from the `postqueue -j` or `postqueue -p` output.
 pub struct QueueEntry {
     pub id: String,
     pub size: u64,
     pub arrival_time: String,
+    /// Arrival as Unix time; only `postqueue -j` reports it.
+    pub arrival: Option<i64>,
     pub sender: String,
     pub recipients: Vec<String>,
+    /// Marked `!`: on hold until released.
+    pub held: bool,
+    /// Marked `*`: being delivered right now.
+    pub active: bool,
+    /// Reason of the last failed delivery attempt, shown in parentheses
+    /// above the recipients it applies to.
+    pub last_error: Option<String>,
+    /// Each recipient with a delay reason and that reason in full, from
+    /// `postqueue -j`.
+    pub delay_reasons: Vec<(String, String)>,
+    /// When the queue manager will next try a deferred message; filled in
+    /// from the queue file by [`set_next_retry`].
+    pub next_retry: Option<i64>,
+}
+
+impl QueueEntry {
+    /// Deferred after a failed attempt and waiting for the next retry.
+    pub fn is_deferred(&self) -> bool {
+        !self.held && !self.active && self.last_error.is_some()
+    }
+
+    pub fn status(&self) -> &'static str {
+        if self.held {
+            "Held"
+        } else if self.active {
+            "Active"
+        } else if self.is_deferred() {
+            "Deferred"
+        } else {
+            "Queued"
+        }
+    }
+
+    /// Seconds since the message arrived, when the arrival time is known.
+    pub fn age(&self, now: i64) -> Option<i64> {
+        self.arrival.map(|at| (now - at).max(0))
+    }
+
+    pub fn age_display(&self) -> String {
+        self.age(chrono::Utc::now().timestamp())
+            .map(|secs| format_interval(secs as u64))
+            .unwrap_or_default()
+    }
+
+    pub fn next_retry_display(&self) -> String {
+        match self.next_retry {
+            Some(at) => {
+                let when = chrono::DateTime::from_timestamp(at, 0)
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_default();
+                let wait = at - chrono::Utc::now().timestamp();
+                if wait > 0 {
+                    format!("{} (in {})", when, format_interval(wait as u64))
+                } else {
+                    format!("{} (due at next queue run)", when)
+                }
+            }
+            None => "unknown".to_string(),
+        }
+    }
 }
 
 /// Parse the text output of `postqueue -p` into a list of [`QueueEntry`] values.
@@ -43,6 +131,8 @@ pub struct QueueEntry {
 ///                                          <Recipient1>
 ///                                          <Recipient2>
 /// ```
+/// Deferred messages carry a `(<reason>)` line before the recipients it
+/// applies to; the first one becomes [`QueueEntry::last_error`].
 /// Entries are separated by blank lines. The header line starts with `-` and
 /// the summary line starts with `--`.
 pub fn parse_queue_output(output: &str) -> Vec<QueueEntry> {
@@ -50,7 +140,7 @@ pub fn parse_queue_output(output: &str) -> Vec<QueueEntry> {
     // Queue ID: alphanumeric, optionally followed by * or !
     // Arrival time: "DayAbbr MonAbbr D HH:MM:SS" (day may be 1 or 2 digits)
     let entry_re = Regex::new(
-        r"^([A-F0-9a-f]+)[*!]?\s+(\d+)\s+(\w{3}\s+\w{3}\s+\d{1,2}\s+\d{2}:\d{2}:\d{2})\s+(\S+)$",
+        r"^([A-F0-9a-f]+)([*!]?)\s+(\d+)\s+(\w{3}\s+\w{3}\s+\d{1,2}\s+\d{2}:\d{2}:\d{2})\s+(\S+)$",
     )
     .expect("queue entry regex is valid");
 
@@ -70,7 +160,18 @@ pub fn parse_queue_output(output: &str) -> Vec<QueueEntry> {
             continue;
         }
 
-        if line.starts_with(|c: char| c.is_ascii_whitespace()) {
+        let reason = line
+            .trim()
+            .strip_prefix('(')
+            .and_then(|l| l.strip_suffix(')'));
+        if let Some(reason) = reason {
+            // Delay reason for the recipients that follow
+            if let Some(ref mut entry) = current {
+                if entry.last_error.is_none() {
+                    entry.last_error = Some(reason.to_string());
+                }
+            }
+        } else if line.starts_with(|c: char| c.is_ascii_whitespace()) {
             // Recipient continuation line
             if let Some(ref mut entry) = current {
                 entry.recipients.push(line.trim().to_string());
@@ -82,16 +183,22 @@ pub fn parse_queue_output(output: &str) -> Vec<QueueEntry> {
             }
             current = Some(QueueEntry {
                 id: caps[1].to_string(),
-                size: caps[2].parse().unwrap_or_else(|_| {
+                size: caps[3].parse().unwrap_or_else(|_| {
                     warn!(
                         "[queue] failed to parse size for queue entry '{}'; defaulting to 0",
                         &caps[1]
                     );
                     0
                 }),
-                arrival_time: caps[3].to_string(),
-                sender: caps[4].to_string(),
+                arrival_time: caps[4].to_string(),
+                sender: caps[5].to_string(),
                 recipients: Vec::new(),
+                arrival: None,
+                held: &caps[2] == "!",
+                active: &caps[2] == "*",
+                last_error: None,
+                delay_reasons: Vec::new(),
+                next_retry: None,
             });
         }
     }
@@ -103,6 +210,338 @@ pub fn parse_queue_output(output: &str) -> Vec<QueueEntry> {
     entries
 }
 
+/// Parse the JSON-lines output of `postqueue -j` (Postfix 3.1 and later)
+/// into a list of [`QueueEntry`] values.  See [`parse_queue_metrics`] for
+/// the format.  The first recipient's delay reason becomes
+/// [`QueueEntry::last_error`], as in the text listing.
+pub fn parse_queue_json(output: &str) -> Vec<QueueEntry> {
+    output
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .filter_map(|line| {
+            let msg: serde_json::Value = serde_json::from_str(line)
+                .map_err(|e| warn!("[queue] skipping unparsable postqueue -j line: {}", e))
+                .ok()?;
+            let queue_name = msg["queue_name"].as_str().unwrap_or("");
+            let arrival = msg["arrival_time"].as_i64();
+            let recipients = msg["recipients"]
+                .as_array()
+                .map_or(&[][..], |r| r.as_slice());
+            let delay_reasons: Vec<(String, String)> = recipients
+                .iter()
+                .filter_map(|r| {
+                    Some((
+                        r["address"].as_str()?.to_string(),
+                        r["delay_reason"].as_str()?.to_string(),
+                    ))
+                })
+                .collect();
+            let sender = msg["sender"].as_str().unwrap_or("");
+            Some(QueueEntry {
+                id: msg["queue_id"].as_str()?.to_string(),
+                size: msg["message_size"].as_u64().unwrap_or(0),
+                arrival_time: arrival
+                    .and_then(|at| chrono::DateTime::from_timestamp(at, 0))
+                    .map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                    .unwrap_or_default(),
+                arrival,
+                // `postqueue -p` shows the null sender of bounces this way.
+                sender: if sender.is_empty() {
+                    "MAILER-DAEMON".to_string()
+                } else {
+                    sender.to_string()
+                },
+                recipients: recipients
+                    .iter()
+                    .filter_map(|r| r["address"].as_str())
+                    .map(str::to_string)
+                    .collect(),
+                held: queue_name == "hold",
+                active: queue_name == "active",
+                last_error: delay_reasons.first().map(|(_, reason)| reason.clone()),
+                delay_reasons,
+                next_retry: None,
+            })
+        })
+        .collect()
+}
+
+/// `postqueue -p` style summary line for `entries`.
+fn queue_summary(entries: &[QueueEntry]) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+    let kbytes = entries.iter().map(|e| e.size).sum::<u64>().div_ceil(1024);
+    format!(
+        "{} Kbytes in {} Request{}.",
+        kbytes,
+        entries.len(),
+        if entries.len() == 1 { "" } else { "s" }
+    )
+}
+
+/// Upper bounds, in seconds, of the queue age histogram buckets.
+const AGE_BUCKETS: [(&str, i64); 6] = [
+    ("under 5 min", 300),
+    ("5–60 min", 3600),
+    ("1–6 hours", 6 * 3600),
+    ("6–24 hours", 86400),
+    ("1–3 days", 3 * 86400),
+    ("over 3 days", i64::MAX),
+];
+
+/// One bar of the queue age histogram.
+#[derive(Debug, PartialEq)]
+pub struct AgeBucket {
+    pub label: &'static str,
+    pub count: usize,
+}
+
+/// How many messages of `entries` fall into each age bucket.  Messages
+/// without a known arrival time are left out.
+pub fn age_histogram(entries: &[QueueEntry], now: i64) -> Vec<AgeBucket> {
+    let mut buckets: Vec<AgeBucket> = AGE_BUCKETS
+        .iter()
+        .map(|&(label, _)| AgeBucket { label, count: 0 })
+        .collect();
+    for age in entries.iter().filter_map(|e| e.age(now)) {
+        if let Some(i) = AGE_BUCKETS.iter().position(|&(_, max)| age < max) {
+            buckets[i].count += 1;
+        }
+    }
+    buckets
+}
+
+/// Sort the queue table by `column`: `size`, `sender`, `recipient`,
+/// `status`, or arrival (the default, oldest first).
+pub fn sort_entries(entries: &mut [QueueEntry], column: &str, desc: bool) {
+    match column {
+        "size" => entries.sort_by_key(|e| e.size),
+        "sender" => entries.sort_by_key(|e| e.sender.to_lowercase()),
+        "recipient" => entries.sort_by_key(|e| e.recipients.first().map(|r| r.to_lowercase())),
+        "status" => entries.sort_by_key(|e| e.status()),
+        _ => entries.sort_by_key(|e| e.arrival.unwrap_or(i64::MAX)),
+    }
+    if desc {
+        entries.reverse();
+    }
+}
+
+/// Fill in [`QueueEntry::next_retry`] for deferred messages.  Postfix sets
+/// the modification time of a deferred queue file to the time of its next
+/// delivery attempt; the file sits in `deferred/` or a hashed subdirectory.
+pub fn set_next_retry(entries: &mut [QueueEntry], spool_dir: &FsPath) {
+    let deferred = spool_dir.join("deferred");
+    for entry in entries.iter_mut().filter(|e| e.is_deferred()) {
+        let mut candidates = vec![deferred.join(&entry.id)];
+        if let Ok(dirs) = std::fs::read_dir(&deferred) {
+            candidates.extend(dirs.flatten().map(|d| d.path().join(&entry.id)));
+        }
+        entry.next_retry = candidates.iter().find_map(|path| {
+            let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+            let secs = modified
+                .duration_since(std::time::UNIX_EPOCH)
+                .ok()?
+                .as_secs();
+            Some(secs as i64)
+        });
+    }
+}
+
+/// Postfix retry timing, in seconds, as set in `main.cf` or by Postfix's
+/// defaults for parameters the generated config leaves out.
+#[derive(Debug, PartialEq)]
+pub struct RetrySchedule {
+    pub queue_run_delay: u64,
+    pub minimal_backoff_time: u64,
+    pub maximal_backoff_time: u64,
+    pub maximal_queue_lifetime: u64,
+    pub bounce_queue_lifetime: u64,
+}
+
+impl RetrySchedule {
+    /// Read the schedule from the text of `main.cf`.  Later assignments win,
+    /// as they do in Postfix.
+    pub fn from_main_cf(config: &str) -> Self {
+        let value = |name: &str, default: &str, default_unit: char| {
+            let configured = config.lines().rev().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (!line.trim_start().starts_with('#') && key.trim() == name).then(|| value.trim())
+            });
+            configured
+                .and_then(|v| parse_postfix_time(v, default_unit))
+                .or_else(|| parse_postfix_time(default, default_unit))
+                .unwrap_or(0)
+        };
+        RetrySchedule {
+            queue_run_delay: value("queue_run_delay", "300s", 's'),
+            minimal_backoff_time: value("minimal_backoff_time", "300s", 's'),
+            maximal_backoff_time: value("maximal_backoff_time", "4000s", 's'),
+            maximal_queue_lifetime: value("maximal_queue_lifetime", "5d", 'd'),
+            bounce_queue_lifetime: value("bounce_queue_lifetime", "5d", 'd'),
+        }
+    }
+
+    /// Offsets from arrival of the first `limit` retries of a message that
+    /// keeps failing.  The queue manager waits as long as the message has
+    /// been queued, clamped to the backoff bounds, so the gap doubles until
+    /// it reaches `maximal_backoff_time`; attempts stop at the queue lifetime.
+    pub fn attempt_offsets(&self, limit: usize) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        let mut age = 0u64;
+        while offsets.len() < limit {
+            let delay = age
+                .max(self.minimal_backoff_time)
+                .min(self.maximal_backoff_time.max(self.minimal_backoff_time))
+                .max(1);
+            age += delay;
+            if age > self.maximal_queue_lifetime {
+                break;
+            }
+            offsets.push(age);
+        }
+        offsets
+    }
+}
+
+/// Parse a Postfix time value such as `300s`, `1h` or `5` (in `default_unit`).
+pub fn parse_postfix_time(value: &str, default_unit: char) -> Option<u64> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last()? {
+        (i, c) if c.is_ascii_alphabetic() => (&value[..i], c.to_ascii_lowercase()),
+        _ => (value, default_unit),
+    };
+    let multiplier = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3600,
+        'd' => 86_400,
+        'w' => 604_800,
+        _ => return None,
+    };
+    number.trim().parse::<u64>().ok().map(|n| n * multiplier)
+}
+
+/// Render seconds as e.g. `5m`, `1h 20m` or `2d 3h`.
+pub fn format_interval(secs: u64) -> String {
+    let (days, hours) = (secs / 86_400, secs % 86_400 / 3600);
+    let (minutes, seconds) = (secs % 3600 / 60, secs % 60);
+    if days > 0 {
+        if hours > 0 {
+            format!("{}d {}h", days, hours)
+        } else {
+            format!("{}d", days)
+        }
+    } else if hours > 0 {
+        if minutes > 0 {
+            format!("{}h {}m", hours, minutes)
+        } else {
+            format!("{}h", hours)
+        }
+    } else if minutes > 0 {
+        if seconds > 0 {
+            format!("{}m {}s", minutes, seconds)
+        } else {
+            format!("{}m", minutes)
+        }
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// Queue counts and totals for monitoring, built from `postqueue -j`.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct QueueMetrics {
+    pub active: u64,
+    pub deferred: u64,
+    pub hold: u64,
+    pub incoming: u64,
+    pub maildrop: u64,
+    pub total: u64,
+    pub total_size_bytes: u64,
+    pub oldest_age_seconds: Option<i64>,
+}
+
+/// Parse the JSON-lines output of `postqueue -j` into [`QueueMetrics`].
+///
+/// Each line is one message, e.g.
+/// `{"queue_name": "deferred", "queue_id": "8389B9CA3B", "arrival_time": 1700000000, "message_size": 2048, ...}`.
+/// `now` is the current Unix time, used for the age of the oldest message.
+pub fn parse_queue_metrics(output: &str, now: i64) -> QueueMetrics {
+    let mut metrics = QueueMetrics::default();
+    for line in output.lines().filter(|l| !l.trim().is_empty()) {
+        let msg: serde_json::Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("[queue] skipping unparsable postqueue -j line: {}", e);
+                continue;
+            }
+        };
+        match msg["queue_name"].as_str().unwrap_or("") {
+            "active" => metrics.active += 1,
+            "deferred" => metrics.deferred += 1,
+            "hold" => metrics.hold += 1,
+            "incoming" => metrics.incoming += 1,
+            "maildrop" => metrics.maildrop += 1,
+            other => debug!("[queue] message in unknown queue {:?}", other),
+        }
+        metrics.total += 1;
+        metrics.total_size_bytes += msg["message_size"].as_u64().unwrap_or(0);
+        if let Some(arrival) = msg["arrival_time"].as_i64() {
+            let age = (now - arrival).max(0);
+            metrics.oldest_age_seconds =
+                Some(metrics.oldest_age_seconds.map_or(age, |a| a.max(age)));
+        }
+    }
+    metrics
+}
+
+/// Current queue counts from `postqueue -j`, for callers outside a request.
+pub fn read_queue_metrics() -> Result<QueueMetrics, String> {
+    let postqueue_bin =
+        find_postqueue_bin().ok_or("postqueue binary not found in /usr/sbin or /usr/bin")?;
+    match Command::new(postqueue_bin).arg("-j").output() {
+        Ok(output) if output.status.success() => Ok(parse_queue_metrics(
+            &String::from_utf8_lossy(&output.stdout),
+            chrono::Utc::now().timestamp(),
+        )),
+        Ok(output) => Err(format!(
+            "postqueue -j failed with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(e) => Err(format!("failed to run postqueue -j: {}", e)),
+    }
+}
+
+/// Ask Postfix to retry all queued mail now (`postqueue -f`) unless the
+/// queue was flushed less than `min_gap` ago.  Returns whether it flushed.
+pub fn flush_queue(min_gap: Duration) -> Result<bool, String> {
+    let mut last = LAST_FLUSH.lock().unwrap_or_else(|e| e.into_inner());
+    if last.is_some_and(|t| t.elapsed() < min_gap) {
+        return Ok(false);
+    }
+    let postqueue_bin =
+        find_postqueue_bin().ok_or("postqueue binary not found in /usr/sbin or /usr/bin")?;
+    match Command::new(postqueue_bin).arg("-f").output() {
+        Ok(output) if output.status.success() => {
+            *last = Some(Instant::now());
+            Ok(true)
+        }
+        Ok(output) => Err(format!(
+            "postqueue -f failed with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )),
+        Err(e) => Err(format!("failed to run postqueue -f: {}", e)),
+    }
+}
+
+/// When the queue was last flushed, if it has been since startup.
+pub fn last_flush() -> Option<Instant> {
+    *LAST_FLUSH.lock().unwrap_or_else(|e| e.into_inner())
+}
+
 /// Returns `true` only when the queue ID is a valid Postfix hex queue ID
 /// (alphanumeric, max 20 chars) to prevent command injection.
 fn is_valid_queue_id(id: &str) -> bool {
@@ -141,17 +580,57 @@ struct QueueTemplate<'a> {
     entries: Vec<QueueEntry>,
     queue_summary: String,
     error: Option<String>,
+    histogram: Vec<AgeBucket>,
+    /// Messages counted in the histogram, the `max` of its bars.
+    histogram_total: usize,
+    sort: &'a str,
+    desc: bool,
 }
 
-pub async fn list(auth: AuthAdmin, State(_state): State<AppState>) -> Html<String> {
-    debug!(
-        "[web] GET /queue — queue page for username={}",
-        auth.admin.username
-    );
+impl QueueTemplate<'_> {
+    fn sorted_by(&self, column: &str) -> bool {
+        self.sort == column || (column == "arrival" && self.sort.is_empty())
+    }
 
-    let postqueue_bin = find_postqueue_bin();
+    /// Link that sorts the table by `column`, reversing the current order
+    /// when it is already sorted by it.
+    fn sort_link(&self, column: &str) -> String {
+        if self.sorted_by(column) && !self.desc {
+            format!("/queue?sort={}&desc=true", column)
+        } else {
+            format!("/queue?sort={}", column)
+        }
+    }
 
-    let (entries, queue_summary, error) = match postqueue_bin {
+    fn sort_mark(&self, column: &str) -> &'static str {
+        match (self.sorted_by(column), self.desc) {
+            (true, false) => " ▲",
+            (true, true) => " ▼",
+            _ => "",
+        }
+    }
+}
+
+/// Read the queue with `postqueue -j`, or `postqueue -p` on Postfix
+/// versions without JSON output: entries, the summary line, or an error
+/// message for the page.
+fn read_queue() -> (Vec<QueueEntry>, String, Option<String>) {
+    if let Some(postqueue_bin) = find_postqueue_bin() {
+        match Command::new(postqueue_bin).arg("-j").output() {
+            Ok(output) if output.status.success() => {
+                let entries = parse_queue_json(&String::from_utf8_lossy(&output.stdout));
+                let summary = queue_summary(&entries);
+                return (entries, summary, None);
+            }
+            Ok(output) => debug!(
+                "[web] postqueue -j failed with status {}, falling back to postqueue -p: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(e) => debug!("[web] failed to run postqueue -j: {}", e),
+        }
+    }
+    match find_postqueue_bin() {
         Some(postqueue_bin) => match Command::new(postqueue_bin).arg("-p").output() {
             Ok(output) if output.status.success() => {
                 let raw = String::from_utf8_lossy(&output.stdout).to_string();
@@ -190,14 +669,24 @@ pub async fn list(auth: AuthAdmin, State(_state): State<AppState>) -> Html<Strin
             String::new(),
             Some("postqueue binary not found in /usr/sbin or /usr/bin.".to_string()),
         ),
-    };
+    }
+}
 
+fn render_queue(flash: Option<&str>, sort: &QueueSortQuery) -> Html<String> {
+    let (mut entries, queue_summary, error) = read_queue();
+    let histogram = age_histogram(&entries, chrono::Utc::now().timestamp());
+    let histogram_total = histogram.iter().map(|b| b.count).sum();
+    sort_entries(&mut entries, &sort.sort, sort.desc);
     let tmpl = QueueTemplate {
         nav_active: "Queue",
-        flash: None,
+        flash,
         entries,
         queue_summary,
         error,
+        histogram,
+        histogram_total,
+        sort: &sort.sort,
+        desc: sort.desc,
     };
 
     match tmpl.render() {
@@ -215,40 +704,455 @@ pub async fn list(auth: AuthAdmin, State(_state): State<AppState>) -> Html<Strin
     }
 }
 
-pub async fn flush(auth: AuthAdmin, headers: HeaderMap) -> Response {
+#[derive(Template)]
+#[template(path = "queue/deferred.html")]
+struct DeferredTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    entries: Vec<QueueEntry>,
+    error: Option<String>,
+    schedule: Vec<(&'static str, String)>,
+    attempts: Vec<String>,
+    schedule_note: Option<String>,
+}
+
+/// The retry schedule from the generated `main.cf`, as labelled rows and the
+/// upcoming attempt offsets, plus a note when the file could not be read.
+fn read_retry_schedule() -> (Vec<(&'static str, String)>, Vec<String>, Option<String>) {
+    let (config, note) = match std::fs::read_to_string(POSTFIX_MAIN_CF) {
+        Ok(config) => (config, None),
+        Err(e) => {
+            warn!("[web] failed to read {}: {}", POSTFIX_MAIN_CF, e);
+            (
+                String::new(),
+                Some(format!(
+                    "{} could not be read; showing Postfix defaults.",
+                    POSTFIX_MAIN_CF
+                )),
+            )
+        }
+    };
+    let schedule = RetrySchedule::from_main_cf(&config);
+    let rows = vec![
+        ("queue_run_delay", format_interval(schedule.queue_run_delay)),
+        (
+            "minimal_backoff_time",
+            format_interval(schedule.minimal_backoff_time),
+        ),
+        (
+            "maximal_backoff_time",
+            format_interval(schedule.maximal_backoff_time),
+        ),
+        (
+            "maximal_queue_lifetime",
+            format_interval(schedule.maximal_queue_lifetime),
+        ),
+        (
+            "bounce_queue_lifetime",
+            format_interval(schedule.bounce_queue_lifetime),
+        ),
+    ];
+    let offsets = schedule.attempt_offsets(SCHEDULE_ATTEMPTS + 1);
+    let mut attempts: Vec<String> = offsets
+        .iter()
+        .take(SCHEDULE_ATTEMPTS)
+        .map(|&secs| format_interval(secs))
+        .collect();
+    if offsets.len() > SCHEDULE_ATTEMPTS {
+        attempts.push("…".to_string());
+    }
+    (rows, attempts, note)
+}
+
+fn render_deferred(flash: Option<&str>) -> Html<String> {
+    let (mut entries, _, error) = read_queue();
+    entries.retain(QueueEntry::is_deferred);
+    set_next_retry(&mut entries, FsPath::new(POSTFIX_SPOOL_DIR));
+    entries.sort_by_key(|e| e.next_retry.unwrap_or(i64::MAX));
+    let (schedule, attempts, schedule_note) = read_retry_schedule();
+    let tmpl = DeferredTemplate {
+        nav_active: "Queue",
+        flash,
+        entries,
+        error,
+        schedule,
+        attempts,
+        schedule_note,
+    };
+
+    match tmpl.render() {
+        Ok(html) => Html(html),
+        Err(e) => {
+            error!("[web] failed to render deferred queue template: {}", e);
+            crate::web::errors::render_error_page(
+                axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                "Template Error",
+                "Failed to render deferred mail page. Please try again.",
+                "/queue",
+                "Back to Queue",
+            )
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "queue/detail.html")]
+struct DetailTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    entry: QueueEntry,
+    /// Envelope and headers from `postcat`, or why they are missing.
+    queue_file: Result<String, String>,
+}
+
+/// The envelope and headers of queued message `id` from `postcat -q`.  The
+/// body is left out.
+fn read_queue_file(id: &str) -> Result<String, String> {
+    let Some(postcat_bin) = find_postcat_bin() else {
+        return Err("postcat binary not found in /usr/sbin or /usr/bin.".to_string());
+    };
+    match Command::new(postcat_bin)
+        .args(["-e", "-h", "-q", id])
+        .output()
+    {
+        Ok(output) if output.status.success() => {
+            Ok(String::from_utf8_lossy(&output.stdout).to_string())
+        }
+        Ok(output) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            warn!(
+                "[web] postcat -q {} failed with status {}: {}",
+                id, output.status, stderr
+            );
+            Err(format!("postcat failed: {}", stderr.trim()))
+        }
+        Err(e) => {
+            error!("[web] failed to run postcat for message {}: {}", id, e);
+            Err("Failed to run postcat.".to_string())
+        }
+    }
+}
+
+/// One queued message with every recipient's delay reason and its envelope
+/// and headers.
+pub async fn detail(auth: AuthAdmin, Path(id): Path<String>) -> Response {
     debug!(
-        "[web] POST /queue/flush — flush queue for username={}",
+        "[web] GET /queue/{} — queue message for username={}",
+        id, auth.admin.username
+    );
+    if !is_valid_queue_id(&id) {
+        warn!("[web] queue detail rejected invalid queue id: {:?}", id);
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let (entries, _, error) = read_queue();
+    let Some(mut entry) = entries.into_iter().find(|e| e.id == id) else {
+        let message = error.unwrap_or_else(|| format!("Message {} is no longer in the queue.", id));
+        return crate::web::errors::status_response(
+            StatusCode::NOT_FOUND,
+            "Message Not Found",
+            &message,
+            "/queue",
+            "Back to Queue",
+        );
+    };
+    set_next_retry(
+        std::slice::from_mut(&mut entry),
+        FsPath::new(POSTFIX_SPOOL_DIR),
+    );
+    let tmpl = DetailTemplate {
+        nav_active: "Queue",
+        flash: None,
+        queue_file: read_queue_file(&id),
+        entry,
+    };
+    match tmpl.render() {
+        Ok(html) => Html(html).into_response(),
+        Err(e) => {
+            error!("[web] failed to render queue detail template: {}", e);
+            crate::web::errors::status_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Template Error",
+                "Failed to render the queued message.",
+                "/queue",
+                "Back to Queue",
+            )
+        }
+    }
+}
+
+pub async fn list(
+    auth: AuthAdmin,
+    State(_state): State<AppState>,
+    Query(sort): Query<QueueSortQuery>,
+) -> Html<String> {
+    debug!(
+        "[web] GET /queue — queue page for username={}",
         auth.admin.username
     );
+    render_queue(None, &sort)
+}
+
+/// Queue IDs of `entries` whose sender matches `sender` and which have a
+/// recipient matching `recipient`.  Patterns use the shared sender matcher
+/// (`*`, `*@domain` or an exact address); an empty pattern matches anything.
+pub fn select_queue_ids(entries: &[QueueEntry], sender: &str, recipient: &str) -> Vec<String> {
+    let matches = |pattern: &str, address: &str| {
+        pattern.is_empty()
+            || crate::db::matches_from_pattern(pattern, &address.to_ascii_lowercase())
+    };
+    entries
+        .iter()
+        .filter(|e| matches(sender, &e.sender))
+        .filter(|e| e.recipients.iter().any(|r| matches(recipient, r)))
+        .map(|e| e.id.clone())
+        .collect()
+}
+
+/// Put messages on hold (`postsuper -h`) or release them (`postsuper -H`),
+/// selected by queue ID or by sender/recipient pattern.
+async fn set_hold(
+    auth: AuthAdmin,
+    headers: HeaderMap,
+    form: QueueSelectForm,
+    hold: bool,
+) -> Response {
+    let action = if hold { "hold" } else { "release" };
+    debug!(
+        "[web] POST /queue/{} — id={:?} sender={:?} recipient={:?} for username={}",
+        action, form.id, form.sender, form.recipient, auth.admin.username
+    );
 
     if !same_origin(&headers) {
-        warn!("[web] queue flush blocked due to non same-origin request");
+        warn!(
+            "[web] queue {} blocked due to non same-origin request",
+            action
+        );
         return StatusCode::FORBIDDEN.into_response();
     }
 
-    match find_postqueue_bin() {
-        Some(postqueue_bin) => match Command::new(postqueue_bin).arg("-f").output() {
-            Ok(output) if output.status.success() => {
-                debug!("[web] queue flush command completed successfully");
+    let id = form.id.trim();
+    let sender = form.sender.trim();
+    let recipient = form.recipient.trim();
+    let invalid = |message: &str| {
+        crate::web::errors::status_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid Selection",
+            message,
+            "/queue",
+            "Back to Queue",
+        )
+    };
+    if id.is_empty() && sender.is_empty() && recipient.is_empty() {
+        return invalid("Give a queue ID or a sender or recipient pattern.");
+    }
+    if !id.is_empty() && (!sender.is_empty() || !recipient.is_empty()) {
+        return invalid("Give either a queue ID or patterns, not both.");
+    }
+    if !id.is_empty() && !is_valid_queue_id(id) {
+        warn!("[web] queue {} rejected invalid queue id: {:?}", action, id);
+        return invalid("That is not a valid queue ID.");
+    }
+    let valid_pattern = |p: &str| {
+        p.is_empty() || (!p.contains(char::is_whitespace) && (p == "*" || p.contains('@')))
+    };
+    if !valid_pattern(sender) || !valid_pattern(recipient) {
+        return invalid("Patterns must be *, *@domain or a full address.");
+    }
+
+    let ids = if id.is_empty() {
+        let (entries, _, error) = read_queue();
+        if let Some(e) = error {
+            return crate::web::errors::status_response(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Queue Unavailable",
+                &e,
+                "/queue",
+                "Back to Queue",
+            );
+        }
+        select_queue_ids(&entries, sender, recipient)
+    } else {
+        vec![id.to_string()]
+    };
+
+    let flag = if hold { "-h" } else { "-H" };
+    let affected = if ids.is_empty() {
+        0
+    } else {
+        match find_postsuper_bin() {
+            Some(postsuper_bin) => {
+                let args = ids.iter().flat_map(|id| [flag, id.as_str()]);
+                match Command::new(postsuper_bin).args(args).output() {
+                    Ok(output) if output.status.success() => {
+                        // postsuper reports "postsuper: Placed on hold: N messages"
+                        // (or "Released from hold") on stderr, and nothing when no
+                        // message was affected.
+                        let stderr = String::from_utf8_lossy(&output.stderr);
+                        parse_postsuper_count(&stderr).unwrap_or(0)
+                    }
+                    Ok(output) => {
+                        error!(
+                            "[web] queue {} failed with status {}: {}",
+                            action,
+                            output.status,
+                            String::from_utf8_lossy(&output.stderr)
+                        );
+                        return crate::web::errors::status_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Queue Error",
+                            &format!("postsuper could not {} the messages.", action),
+                            "/queue",
+                            "Back to Queue",
+                        );
+                    }
+                    Err(e) => {
+                        error!("[web] failed to run postsuper {}: {}", flag, e);
+                        return crate::web::errors::status_response(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "Queue Error",
+                            "Failed to run postsuper.",
+                            "/queue",
+                            "Back to Queue",
+                        );
+                    }
+                }
             }
-            Ok(output) => {
+            None => {
                 error!(
-                    "[web] queue flush failed with status {}: {}",
-                    output.status,
-                    String::from_utf8_lossy(&output.stderr)
+                    "[web] postsuper binary not found; queue {} unavailable",
+                    action
+                );
+                return crate::web::errors::status_response(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Queue Unavailable",
+                    "postsuper binary not found in /usr/sbin or /usr/bin.",
+                    "/queue",
+                    "Back to Queue",
                 );
             }
-            Err(e) => {
-                error!("[web] failed to run queue flush command: {}", e);
-            }
-        },
-        None => error!("[web] postqueue binary not found; queue flush unavailable"),
+        }
+    };
+
+    info!(
+        "[web] queue {}: {} of {} selected messages affected",
+        action,
+        affected,
+        ids.len()
+    );
+    let flash = if hold {
+        format!("Placed {} message(s) on hold.", affected)
+    } else {
+        format!("Released {} message(s) from hold.", affected)
+    };
+    render_queue(Some(&flash), &QueueSortQuery::default()).into_response()
+}
+
+/// Count from postsuper's "Placed on hold: N messages" style summary.
+fn parse_postsuper_count(stderr: &str) -> Option<usize> {
+    stderr.lines().find_map(|line| {
+        let (_, rest) = line.rsplit_once(": ")?;
+        rest.split_whitespace().next()?.parse().ok()
+    })
+}
+
+pub async fn hold(
+    auth: AuthAdmin,
+    headers: HeaderMap,
+    Form(form): Form<QueueSelectForm>,
+) -> Response {
+    set_hold(auth, headers, form, true).await
+}
+
+pub async fn release(
+    auth: AuthAdmin,
+    headers: HeaderMap,
+    Form(form): Form<QueueSelectForm>,
+) -> Response {
+    set_hold(auth, headers, form, false).await
+}
+
+/// `/queue/metrics`, guarded by the same API keys as `/api/v1`.
+pub fn metrics_routes(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/queue/metrics", get(metrics))
+        .route_layer(middleware::from_fn_with_state(
+            state,
+            super::api_v1::require_api_key,
+        ))
+}
+
+fn metrics_error(status: StatusCode, message: &str) -> Response {
+    (status, Json(serde_json::json!({"error": message}))).into_response()
+}
+
+/// Queue metrics as JSON for monitoring systems.  Returns `503` when Postfix
+/// is unavailable and `504` when `postqueue` does not answer in time.
+pub async fn metrics() -> Response {
+    debug!("[web] GET /queue/metrics — queue metrics");
+
+    let Some(postqueue_bin) = find_postqueue_bin() else {
+        error!("[web] postqueue binary not found; queue metrics unavailable");
+        return metrics_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "postqueue binary not found",
+        );
+    };
+
+    let output = tokio::process::Command::new(postqueue_bin)
+        .arg("-j")
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(METRICS_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => {
+            let raw = String::from_utf8_lossy(&output.stdout);
+            Json(parse_queue_metrics(&raw, chrono::Utc::now().timestamp())).into_response()
+        }
+        Ok(Ok(output)) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            error!(
+                "[web] postqueue -j failed with status {}: {}",
+                output.status, stderr
+            );
+            metrics_error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                &format!("postqueue failed: {}", stderr.trim()),
+            )
+        }
+        Ok(Err(e)) => {
+            error!("[web] failed to run postqueue -j: {}", e);
+            metrics_error(StatusCode::SERVICE_UNAVAILABLE, "failed to run postqueue")
+        }
+        Err(_) => {
+            error!(
+                "[web] postqueue -j did not finish within {}s",
+                METRICS_TIMEOUT.as_secs()
+            );
+            metrics_error(StatusCode::GATEWAY_TIMEOUT, "postqueue timed out")
+        }
+    }
+}
+
+pub async fn flush(auth: AuthAdmin, headers: HeaderMap) -> Response {
+    debug!(
+        "[web] POST /queue/flush — flush queue for username={}",
+        auth.admin.username
+    );
+
+    if !same_origin(&headers) {
+        warn!("[web] queue flush blocked due to non same-origin request");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    match flush_queue(Duration::ZERO) {
+        Ok(_) => debug!("[web] queue flush command completed successfully"),
+        Err(e) => error!("[web] queue flush failed: {}", e),
     }
 
     Redirect::to("/queue").into_response()
 }
 
-pub async fn purge(auth: AuthAdmin, headers: HeaderMap) -> Response {
+pub async fn purge(auth: AuthAdmin, State(state): State<AppState>, headers: HeaderMap) -> Response {
     debug!(
         "[web] POST /queue/purge — purge entire queue for username={}",
         auth.admin.username
@@ -259,6 +1163,22 @@ pub async fn purge(auth: AuthAdmin, headers: HeaderMap) -> Response {
         return StatusCode::FORBIDDEN.into_response();
     }
 
+    let action = DestructiveAction::PurgeQueue;
+    if let Some(held) = approvals::hold(&state, &auth, action).await {
+        return held;
+    }
+    approvals::perform(
+        &state,
+        auth.admin.username.clone(),
+        action,
+        serde_json::json!({}),
+    )
+    .await;
+    Redirect::to("/queue").into_response()
+}
+
+/// Delete every message in the Postfix queue (`postsuper -d ALL`).
+pub(crate) fn purge_all() {
     match find_postsuper_bin() {
         Some(postsuper_bin) => match Command::new(postsuper_bin).args(["-d", "ALL"]).output() {
             Ok(output) if output.status.success() => {
@@ -277,8 +1197,6 @@ pub async fn purge(auth: AuthAdmin, headers: HeaderMap) -> Response {
         },
         None => error!("[web] postsuper binary not found; queue purge unavailable"),
     }
-
-    Redirect::to("/queue").into_response()
 }
 
 pub async fn delete_message(
@@ -370,9 +1288,107 @@ pub async fn flush_message(
     Redirect::to("/queue").into_response()
 }
 
+pub async fn deferred(auth: AuthAdmin) -> Html<String> {
+    debug!(
+        "[web] GET /queue/deferred — deferred mail for username={}",
+        auth.admin.username
+    );
+    render_deferred(None)
+}
+
+/// Retry one deferred message now (`postqueue -i`).  A message that left
+/// the queue since the page was loaded is reported rather than retried.
+pub async fn retry_deferred(
+    auth: AuthAdmin,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    debug!(
+        "[web] POST /queue/deferred/{}/retry — retry message for username={}",
+        id, auth.admin.username
+    );
+
+    if !same_origin(&headers) {
+        warn!("[web] deferred retry blocked due to non same-origin request");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    if !is_valid_queue_id(&id) {
+        warn!("[web] deferred retry rejected invalid queue id: {:?}", id);
+        return StatusCode::BAD_REQUEST.into_response();
+    }
+
+    let (entries, _, error) = read_queue();
+    if error.is_none() && !entries.iter().any(|e| e.id == id) {
+        info!("[web] deferred retry: message {} is no longer queued", id);
+        let flash = format!(
+            "Message {} is no longer in the queue; it was delivered, bounced or deleted.",
+            id
+        );
+        return render_deferred(Some(&flash)).into_response();
+    }
+
+    let flash = match find_postqueue_bin() {
+        Some(postqueue_bin) => match Command::new(postqueue_bin).args(["-i", &id]).output() {
+            Ok(output) if output.status.success() => {
+                info!("[web] retry requested for deferred message {}", id);
+                format!("Retry requested for message {}.", id)
+            }
+            Ok(output) => {
+                error!(
+                    "[web] deferred retry {} failed with status {}: {}",
+                    id,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                format!("postqueue could not retry message {}.", id)
+            }
+            Err(e) => {
+                error!("[web] failed to run postqueue -i for message {}: {}", id, e);
+                "Failed to run postqueue.".to_string()
+            }
+        },
+        None => {
+            error!("[web] postqueue binary not found; deferred retry unavailable");
+            "postqueue binary not found in /usr/sbin or /usr/bin.".to_string()
+        }
+    };
+    render_deferred(Some(&flash)).into_response()
+}
+
+/// Retry all deferred mail now (`postqueue -f`).
+pub async fn retry_all_deferred(auth: AuthAdmin, headers: HeaderMap) -> Response {
+    debug!(
+        "[web] POST /queue/deferred/retry — retry all deferred mail for username={}",
+        auth.admin.username
+    );
+
+    if !same_origin(&headers) {
+        warn!("[web] deferred retry-all blocked due to non same-origin request");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    let flash = match flush_queue(Duration::ZERO) {
+        Ok(_) => {
+            info!("[web] retry requested for all deferred mail");
+            "Retry requested for all deferred messages.".to_string()
+        }
+        Err(e) => {
+            error!("[web] deferred retry-all failed: {}", e);
+            format!("Could not flush the queue: {}.", e)
+        }
+    };
+    render_deferred(Some(&flash)).into_response()
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{is_valid_queue_id, parse_queue_output, same_origin};
+    use super::{
+        age_histogram, format_interval, is_valid_queue_id, parse_postfix_time,
+        parse_postsuper_count, parse_queue_json, parse_queue_metrics, parse_queue_output,
+        queue_summary, same_origin, select_queue_ids, set_next_retry, sort_entries, AgeBucket,
+        RetrySchedule,
+    };
     use axum::http::{header, HeaderMap, HeaderValue};
 
     #[test]
@@ -479,4 +1495,188 @@ EF7F57AAAD*    1172 Sat Feb 21 17:03:33  m@tyyi.net
         let entries = parse_queue_output("Mail queue is empty\n");
         assert!(entries.is_empty());
     }
+
+    #[test]
+    fn parse_queue_metrics_counts_messages_per_queue() {
+        let output = concat!(
+            r#"{"queue_name": "deferred", "queue_id": "8389B9CA3B", "arrival_time": 1700000000, "message_size": 2048, "forced_expire": false, "sender": "a@example.com", "recipients": [{"address": "x@remote.test", "delay_reason": "connect timed out"}]}"#,
+            "\n",
+            r#"{"queue_name": "active", "queue_id": "EF7F57AAAD", "arrival_time": 1700003000, "message_size": 512, "forced_expire": false, "sender": "b@example.com", "recipients": [{"address": "y@remote.test"}]}"#,
+            "\n",
+            r#"{"queue_name": "deferred", "queue_id": "74C8A7AC47", "arrival_time": 1700002000, "message_size": 100, "forced_expire": false, "sender": "", "recipients": []}"#,
+            "\n",
+            r#"{"queue_name": "hold", "queue_id": "1A2B3C4D5E", "arrival_time": 1700003500, "message_size": 10, "forced_expire": false, "sender": "c@example.com", "recipients": []}"#,
+            "\n",
+            "not json\n",
+        );
+        let metrics = parse_queue_metrics(output, 1700003600);
+        assert_eq!(metrics.active, 1);
+        assert_eq!(metrics.deferred, 2);
+        assert_eq!(metrics.hold, 1);
+        assert_eq!(metrics.incoming, 0);
+        assert_eq!(metrics.total, 4);
+        assert_eq!(metrics.total_size_bytes, 2670);
+        assert_eq!(metrics.oldest_age_seconds, Some(3600));
+
+        let empty = parse_queue_metrics("", 1700003600);
+        assert_eq!(empty.total, 0);
+        assert_eq!(empty.oldest_age_seconds, None);
+    }
+
+    #[test]
+    fn parse_queue_json_keeps_arrival_times_and_delay_reasons() {
+        let output = concat!(
+            r#"{"queue_name": "deferred", "queue_id": "8389B9CA3B", "arrival_time": 1700000000, "message_size": 2048, "forced_expire": false, "sender": "a@example.com", "recipients": [{"address": "x@remote.test", "delay_reason": "connect to mx.remote.test[192.0.2.1]:25: Connection timed out"}, {"address": "y@remote.test", "delay_reason": "host mx.remote.test said: 452 4.2.2 Mailbox full"}]}"#,
+            "\n",
+            r#"{"queue_name": "active", "queue_id": "EF7F57AAAD", "arrival_time": 1700003000, "message_size": 512, "forced_expire": false, "sender": "b@example.com", "recipients": [{"address": "z@remote.test"}]}"#,
+            "\n",
+            r#"{"queue_name": "hold", "queue_id": "1A2B3C4D5E", "arrival_time": 1699000000, "message_size": 10, "forced_expire": false, "sender": "", "recipients": []}"#,
+            "\n",
+            "not json\n",
+        );
+        let mut entries = parse_queue_json(output);
+        assert_eq!(entries.len(), 3);
+
+        let deferred = &entries[0];
+        assert_eq!(deferred.id, "8389B9CA3B");
+        assert_eq!(deferred.arrival, Some(1700000000));
+        assert_eq!(deferred.arrival_time, "2023-11-14 22:13:20 UTC");
+        assert_eq!(deferred.recipients, vec!["x@remote.test", "y@remote.test"]);
+        assert_eq!(deferred.delay_reasons.len(), 2);
+        assert_eq!(
+            deferred.last_error.as_deref(),
+            Some("connect to mx.remote.test[192.0.2.1]:25: Connection timed out")
+        );
+        assert_eq!(deferred.status(), "Deferred");
+        assert_eq!(entries[1].status(), "Active");
+        assert_eq!(entries[2].status(), "Held");
+        assert_eq!(entries[2].sender, "MAILER-DAEMON");
+        assert_eq!(queue_summary(&entries), "3 Kbytes in 3 Requests.");
+
+        let now = 1700003600;
+        let counts: Vec<usize> = age_histogram(&entries, now)
+            .iter()
+            .map(|b| b.count)
+            .collect();
+        assert_eq!(counts, vec![0, 1, 1, 0, 0, 1]);
+        assert_eq!(
+            age_histogram(&entries, now)[5],
+            AgeBucket {
+                label: "over 3 days",
+                count: 1
+            }
+        );
+
+        let ids = |entries: &[super::QueueEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.id.clone()).collect()
+        };
+        sort_entries(&mut entries, "", false);
+        assert_eq!(ids(&entries), ["1A2B3C4D5E", "8389B9CA3B", "EF7F57AAAD"]);
+        sort_entries(&mut entries, "size", true);
+        assert_eq!(ids(&entries), ["8389B9CA3B", "EF7F57AAAD", "1A2B3C4D5E"]);
+        sort_entries(&mut entries, "sender", false);
+        assert_eq!(ids(&entries), ["8389B9CA3B", "EF7F57AAAD", "1A2B3C4D5E"]);
+    }
+
+    #[test]
+    fn holding_by_sender_pattern_selects_only_matching_messages() {
+        let output = "\
+-Queue ID-  --Size-- ----Arrival Time---- -Sender/Recipient-------
+A1B2C3D4E5*    1172 Sat Feb 21 17:03:33  spam@bad.example
+                                         victim@gmail.com
+
+B1B2C3D4E5     1143 Sat Feb 21 17:06:26  alice@example.com
+                                         bob@partner.test
+
+C1B2C3D4E5!    2000 Sun Feb 22 19:18:15  other@Bad.Example
+                                         carol@partner.test
+
+-- 4 Kbytes in 3 Requests.
+";
+        let entries = parse_queue_output(output);
+        assert!(entries[2].held && !entries[0].held);
+
+        assert_eq!(
+            select_queue_ids(&entries, "*@bad.example", ""),
+            vec!["A1B2C3D4E5", "C1B2C3D4E5"]
+        );
+        assert_eq!(
+            select_queue_ids(&entries, "", "bob@partner.test"),
+            vec!["B1B2C3D4E5"]
+        );
+        assert_eq!(
+            select_queue_ids(&entries, "*@bad.example", "*@partner.test"),
+            vec!["C1B2C3D4E5"]
+        );
+        assert!(select_queue_ids(&entries, "nobody@example.com", "").is_empty());
+
+        assert_eq!(
+            parse_postsuper_count("postsuper: Placed on hold: 2 messages\n"),
+            Some(2)
+        );
+        assert_eq!(parse_postsuper_count(""), None);
+    }
+
+    #[test]
+    fn deferred_messages_report_last_error_and_next_retry() {
+        let output = "\
+-Queue ID-  --Size-- ----Arrival Time---- -Sender/Recipient-------
+D1E2F3A4B5     2048 Mon Mar  2 09:15:00  alice@example.com
+  (connect to mx.remote.test[192.0.2.7]:25: Connection timed out)
+                                         bob@remote.test
+(host mx.other.test[198.51.100.2] said: 451 4.7.1 Try again later (in reply to RCPT TO command))
+                                         carol@other.test
+
+A9B8C7D6E5*     512 Mon Mar  2 09:20:00  alice@example.com
+                                         dave@remote.test
+
+-- 3 Kbytes in 2 Requests.
+";
+        let mut entries = parse_queue_output(output);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[0].last_error.as_deref(),
+            Some("connect to mx.remote.test[192.0.2.7]:25: Connection timed out")
+        );
+        assert_eq!(
+            entries[0].recipients,
+            vec!["bob@remote.test", "carol@other.test"]
+        );
+        assert!(entries[0].is_deferred());
+        assert!(entries[1].active && !entries[1].is_deferred());
+
+        // The next attempt is the deferred queue file's modification time.
+        let spool = std::env::temp_dir().join(format!("queue_spool_{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(spool.join("deferred/D")).unwrap();
+        let file = std::fs::File::create(spool.join("deferred/D/D1E2F3A4B5")).unwrap();
+        let retry_at = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_900_000_000);
+        file.set_modified(retry_at).unwrap();
+        set_next_retry(&mut entries, &spool);
+        assert_eq!(entries[0].next_retry, Some(1_900_000_000));
+        assert_eq!(entries[1].next_retry, None);
+        std::fs::remove_dir_all(&spool).unwrap();
+    }
+
+    #[test]
+    fn retry_schedule_comes_from_main_cf_with_postfix_defaults() {
+        let defaults = RetrySchedule::from_main_cf("");
+        assert_eq!(defaults.minimal_backoff_time, 300);
+        assert_eq!(defaults.maximal_queue_lifetime, 5 * 86_400);
+        assert_eq!(
+            defaults.attempt_offsets(6),
+            vec![300, 600, 1200, 2400, 4800, 8800]
+        );
+
+        let schedule = RetrySchedule::from_main_cf(
+            "# maximal_backoff_time = 1s\nminimal_backoff_time = 10m\nmaximal_backoff_time = 1h\nmaximal_queue_lifetime = 1\n",
+        );
+        assert_eq!(schedule.minimal_backoff_time, 600);
+        assert_eq!(schedule.maximal_backoff_time, 3600);
+        assert_eq!(schedule.maximal_queue_lifetime, 86_400);
+        assert_eq!(schedule.attempt_offsets(100).len(), 26);
+
+        assert_eq!(parse_postfix_time("2h", 's'), Some(7200));
+        assert_eq!(parse_postfix_time("bogus", 's'), None);
+        assert_eq!(format_interval(4800), "1h 20m");
+    }
 }
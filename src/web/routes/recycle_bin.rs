@@ -0,0 +1,122 @@
+use askama::Template;
+use axum::{
+    extract::{Path, State},
+    response::{Html, IntoResponse, Redirect, Response},
+};
+use log::{debug, info, warn};
+
+use crate::db::RecycleBinItem;
+use crate::web::auth::AuthAdmin;
+use crate::web::routes::approvals::{self, DestructiveAction};
+use crate::web::regen_configs;
+use crate::web::AppState;
+use crate::web::{audit, fire_webhook};
+
+// ── Templates ──
+
+#[derive(Template)]
+#[template(path = "recycle_bin/list.html")]
+struct ListTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    domains: Vec<RecycleBinItem>,
+    accounts: Vec<RecycleBinItem>,
+}
+
+// ── Handlers ──
+
+pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
+    info!("[web] GET /recycle-bin — listing soft-deleted items");
+    let (domains, accounts) = state
+        .blocking_db(|db| (db.list_deleted_domains(), db.list_deleted_accounts()))
+        .await;
+    debug!(
+        "[web] recycle bin holds {} domains and {} accounts",
+        domains.len(),
+        accounts.len()
+    );
+
+    let tmpl = ListTemplate {
+        nav_active: "Recycle Bin",
+        flash: None,
+        domains,
+        accounts,
+    };
+    Html(tmpl.render().unwrap())
+}
+
+pub async fn restore_domain(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    info!("[web] POST /recycle-bin/domains/{}/restore", id);
+    state.blocking_db(move |db| db.restore_domain(id)).await;
+    regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "domain.restored",
+        "domain",
+        Some(id),
+        serde_json::json!({}),
+    )
+    .await;
+    fire_webhook(&state, "domain.restored", serde_json::json!({"id": id}));
+    Redirect::to("/recycle-bin").into_response()
+}
+
+pub async fn purge_domain(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    warn!(
+        "[web] POST /recycle-bin/domains/{}/purge — permanently deleting",
+        id
+    );
+    let action = DestructiveAction::PurgeDomain(id);
+    if let Some(held) = approvals::hold(&state, &auth, action).await {
+        return held;
+    }
+    approvals::perform(&state, auth.admin.username.clone(), action, serde_json::json!({})).await;
+    Redirect::to("/recycle-bin").into_response()
+}
+
+pub async fn restore_account(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    info!("[web] POST /recycle-bin/accounts/{}/restore", id);
+    state.blocking_db(move |db| db.restore_account(id)).await;
+    regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "account.restored",
+        "account",
+        Some(id),
+        serde_json::json!({}),
+    )
+    .await;
+    fire_webhook(&state, "account.restored", serde_json::json!({"id": id}));
+    Redirect::to("/recycle-bin").into_response()
+}
+
+pub async fn purge_account(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+) -> Response {
+    warn!(
+        "[web] POST /recycle-bin/accounts/{}/purge — permanently deleting",
+        id
+    );
+    let action = DestructiveAction::PurgeAccount(id);
+    if let Some(held) = approvals::hold(&state, &auth, action).await {
+        return held;
+    }
+    approvals::perform(&state, auth.admin.username.clone(), action, serde_json::json!({})).await;
+    Redirect::to("/recycle-bin").into_response()
+}
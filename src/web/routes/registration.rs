@@ -1,23 +1,70 @@
+//! Public self-registration of mailboxes.
+//!
+//! A domain opts in on its edit page, which stores the rules in
+//! `registration_settings`: an optional username regex, the minimum password
+//! length and whether an invite code is required.  People register through
+//! the form at `/register/<domain>`; programs post JSON to
+//! `/api/register/<domain>`.  Both paths apply the same checks.
+//!
+//! Every attempt, successful or not, is recorded in `fail2ban_log` under the
+//! `register` service and a source IP that reaches that service's
+//! `max_attempts` within `find_time_minutes` is turned away with `429` until
+//! the window passes.
+
 use askama::Template;
 use axum::{
-    extract::{Path, State},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
     response::{Html, IntoResponse, Response},
-    Form,
+    Form, Json,
 };
 use log::{info, warn};
 use serde::Deserialize;
+use serde_json::json;
+use std::net::SocketAddr;
 
-use crate::web::fire_webhook;
+use crate::db::{Domain, RegistrationSettings};
 use crate::web::AppState;
+use crate::web::{audit_as, fire_webhook};
+
+/// fail2ban service name that registration attempts are logged under.
+const THROTTLE_SERVICE: &str = "register";
+
+/// Used when the `register` row is missing from `fail2ban_settings`.
+const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+const DEFAULT_FIND_TIME_MINUTES: i32 = 60;
+
+/// Range admins may set the minimum password length to.
+pub const MIN_PASSWORD_LENGTH: i32 = 8;
+pub const MAX_PASSWORD_LENGTH: i32 = 128;
 
 // ── Forms ──
 
 #[derive(Deserialize)]
 pub struct RegisterForm {
     pub username: String,
+    #[serde(default)]
     pub name: String,
     pub password: String,
     pub confirm_password: String,
+    #[serde(default)]
+    pub invite: String,
+}
+
+#[derive(Deserialize)]
+pub struct RegisterBody {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub invite: String,
+}
+
+#[derive(Deserialize)]
+pub struct InviteQuery {
+    #[serde(default)]
+    pub invite: String,
 }
 
 // ── Templates ──
@@ -31,6 +78,9 @@ struct RegisterFormTemplate<'a> {
     username: String,
     username_preview: String,
     name: String,
+    invite: String,
+    invite_only: bool,
+    min_password_length: i32,
     error: Option<String>,
 }
 
@@ -49,24 +99,55 @@ struct ErrorTemplate<'a> {
 
 // ── Helpers ──
 
+/// Why a registration was refused, with the status to answer with.
+struct Rejection {
+    status: StatusCode,
+    message: String,
+    /// Seconds to wait before trying again, for throttled attempts.
+    retry_after: Option<i32>,
+}
+
+impl Rejection {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Rejection {
+            status,
+            message: message.into(),
+            retry_after: None,
+        }
+    }
+}
+
+/// A registration request from the form or the JSON API, trimmed.
+struct Registration {
+    username: String,
+    name: String,
+    password: String,
+    invite: String,
+}
+
 /// Validate a username against optional domain regex and basic sanity rules.
 ///
 /// Rules applied in order:
 /// 1. Must be 3–64 characters.
-/// 2. Must contain only letters, digits, dots, hyphens, and underscores.
-/// 3. If `regex_pattern` is non-empty it must also match the regex.
+/// 2. Must be a valid mailbox name (see [`crate::validation::is_valid_localpart`]).
+/// 3. Must not be a reserved role such as `postmaster`.
+/// 4. If `regex_pattern` is non-empty it must also match the regex.
 fn validate_username(username: &str, regex_pattern: &str) -> Result<(), String> {
     let len = username.len();
     if len < 3 || len > 64 {
         return Err("Username must be between 3 and 64 characters.".into());
     }
-    if !username
-        .chars()
-        .all(|c| c.is_alphanumeric() || c == '.' || c == '-' || c == '_')
+    if !crate::validation::is_valid_localpart(username) {
+        return Err(crate::validation::LOCALPART_HINT.into());
+    }
+    if crate::reserved_mailboxes::ROLES
+        .iter()
+        .any(|role| role.name == username)
     {
-        return Err(
-            "Username may only contain letters, digits, dots, hyphens, and underscores.".into(),
-        );
+        return Err(format!(
+            "{} is reserved and cannot be registered.",
+            username
+        ));
     }
     if !regex_pattern.is_empty() {
         match regex::Regex::new(regex_pattern) {
@@ -89,53 +170,221 @@ fn validate_username(username: &str, regex_pattern: &str) -> Result<(), String>
     Ok(())
 }
 
+/// The domain and its rules when it accepts registrations.
+async fn open_domain(state: &AppState, domain: &str) -> Option<(Domain, RegistrationSettings)> {
+    let domain_lower = domain.to_ascii_lowercase();
+    state
+        .blocking_db(move |db| {
+            let d = db.get_domain_by_name(&domain_lower).filter(|d| d.active)?;
+            let settings = db.get_registration_settings(d.id);
+            settings.enabled.then_some((d, settings))
+        })
+        .await
+}
+
+/// Record an attempt from `ip`, or refuse it when the IP already reached
+/// the `register` limit.
+fn throttle(db: &crate::db::Database, ip: &str) -> Result<(), Rejection> {
+    let setting = db.get_fail2ban_setting_by_service(THROTTLE_SERVICE);
+    let enabled = setting.as_ref().map(|s| s.enabled).unwrap_or(true);
+    let max_attempts = setting
+        .as_ref()
+        .map(|s| s.max_attempts)
+        .unwrap_or(DEFAULT_MAX_ATTEMPTS);
+    let window_minutes = setting
+        .as_ref()
+        .map(|s| s.find_time_minutes)
+        .unwrap_or(DEFAULT_FIND_TIME_MINUTES);
+    if enabled
+        && db.count_recent_attempts(ip, THROTTLE_SERVICE, window_minutes) >= max_attempts as i64
+    {
+        warn!("[register] too many registration attempts from ip={}", ip);
+        return Err(Rejection {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: "Too many registration attempts. Wait a while before trying again.".into(),
+            retry_after: Some(window_minutes.max(1) * 60),
+        });
+    }
+    db.record_fail2ban_attempt(ip, THROTTLE_SERVICE, "");
+    Ok(())
+}
+
+/// Check `reg` against the domain's rules and create the account, returning
+/// its address.
+async fn register(
+    state: &AppState,
+    domain: &Domain,
+    settings: &RegistrationSettings,
+    ip: String,
+    reg: Registration,
+) -> Result<String, Rejection> {
+    state.blocking_db(move |db| throttle(db, &ip)).await?;
+
+    validate_username(&reg.username, &settings.username_regex)
+        .map_err(|reason| Rejection::new(StatusCode::BAD_REQUEST, reason))?;
+    if (reg.password.len() as i32) < settings.min_password_length {
+        return Err(Rejection::new(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Password must be at least {} characters.",
+                settings.min_password_length
+            ),
+        ));
+    }
+
+    let address = format!("{}@{}", reg.username, domain.domain);
+    let invite = Some(reg.invite.clone()).filter(|_| settings.invite_only);
+    let (domain_id, check_address, check_invite) = (domain.id, address.clone(), invite.clone());
+    let (taken, invite_open) = state
+        .blocking_db(move |db| {
+            (
+                db.get_account_by_email(&check_address).is_some()
+                    || db.is_address_routed(&check_address),
+                check_invite.is_none_or(|code| db.is_registration_invite_open(domain_id, &code)),
+            )
+        })
+        .await;
+    if !invite_open {
+        let message = if reg.invite.is_empty() {
+            "Registration on this domain needs an invite code."
+        } else {
+            "That invite code is invalid or has already been used."
+        };
+        return Err(Rejection::new(StatusCode::FORBIDDEN, message));
+    }
+    if taken {
+        return Err(Rejection::new(
+            StatusCode::CONFLICT,
+            "That username is already taken on this domain.",
+        ));
+    }
+
+    let hash = crate::auth::hash_password(&reg.password).map_err(|e| {
+        warn!("[register] failed to hash password: {}", e);
+        Rejection::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to process your registration. Please try again.",
+        )
+    })?;
+    let (username, name, created_address) = (reg.username, reg.name, address.clone());
+    let result = state
+        .blocking_db(move |db| {
+            db.register_account(
+                domain_id,
+                &username,
+                &hash,
+                &name,
+                invite.as_deref(),
+                &created_address,
+            )
+        })
+        .await;
+
+    match result {
+        Ok(id) => {
+            info!("[register] new account created: {}", address);
+            audit_as(
+                state,
+                format!("self-registration ({})", address),
+                "account.registered",
+                "account",
+                Some(id),
+                json!({"email": address, "invite": settings.invite_only}),
+            )
+            .await;
+            fire_webhook(
+                state,
+                "account.registered",
+                json!({
+                    "username": address.split('@').next().unwrap_or_default(),
+                    "domain": domain.domain,
+                }),
+            );
+            // Regenerate configs so the new mailbox is active immediately.
+            crate::web::regen_configs(state).await;
+            Ok(address)
+        }
+        Err(e) if e == crate::db::INVITE_UNAVAILABLE => Err(Rejection::new(
+            StatusCode::FORBIDDEN,
+            "That invite code is invalid or has already been used.",
+        )),
+        Err(e) => {
+            warn!("[register] failed to create account {}: {}", address, e);
+            if e.contains("23505")
+                || e.to_lowercase().contains("unique")
+                || e.to_lowercase().contains("duplicate")
+            {
+                Err(Rejection::new(
+                    StatusCode::CONFLICT,
+                    "That username is already taken on this domain.",
+                ))
+            } else {
+                Err(Rejection::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Registration failed. Please try again.",
+                ))
+            }
+        }
+    }
+}
+
+fn unavailable_page() -> Response {
+    let tmpl = ErrorTemplate {
+        nav_active: "",
+        flash: None,
+        status_code: 404,
+        status_text: "Not Found",
+        title: "Registration Unavailable",
+        message: "Registration is not available for this domain.",
+        back_url: "/",
+        back_label: "Home",
+    };
+    (StatusCode::NOT_FOUND, Html(tmpl.render().unwrap())).into_response()
+}
+
+fn json_error(rejection: Rejection) -> Response {
+    let mut resp = (rejection.status, Json(json!({"error": rejection.message}))).into_response();
+    if let Some(seconds) = rejection.retry_after {
+        resp.headers_mut()
+            .insert(header::RETRY_AFTER, header::HeaderValue::from(seconds));
+    }
+    resp
+}
+
 // ── Handlers ──
 
 /// Show the public registration form for a domain.
 pub async fn show_form(
     State(state): State<AppState>,
     Path(domain): Path<String>,
+    Query(query): Query<InviteQuery>,
 ) -> Response {
     info!("[web] GET /register/{} — registration form", domain);
 
-    let domain_lower = domain.to_ascii_lowercase();
-    let domain_record = state
-        .blocking_db(move |db| db.get_domain_by_name(&domain_lower))
-        .await;
-
-    match domain_record {
-        Some(d) if d.active && d.registration_enabled => {
-            let tmpl = RegisterFormTemplate {
-                nav_active: "",
-                flash: None,
-                domain: d.domain.clone(),
-                username: String::new(),
-                username_preview: format!("@{}", d.domain),
-                name: String::new(),
-                error: None,
-            };
-            Html(tmpl.render().unwrap()).into_response()
-        }
-        _ => {
-            let tmpl = ErrorTemplate {
-                nav_active: "",
-                flash: None,
-                status_code: 404,
-                status_text: "Not Found",
-                title: "Registration Unavailable",
-                message: "Registration is not available for this domain.",
-                back_url: "/",
-                back_label: "Home",
-            };
-            Html(tmpl.render().unwrap()).into_response()
-        }
-    }
+    let Some((d, settings)) = open_domain(&state, &domain).await else {
+        return unavailable_page();
+    };
+    let tmpl = RegisterFormTemplate {
+        nav_active: "",
+        flash: None,
+        domain: d.domain.clone(),
+        username: String::new(),
+        username_preview: format!("@{}", d.domain),
+        name: String::new(),
+        invite: query.invite.trim().to_string(),
+        invite_only: settings.invite_only,
+        min_password_length: settings.min_password_length,
+        error: None,
+    };
+    Html(tmpl.render().unwrap()).into_response()
 }
 
 /// Handle the registration form submission.
 pub async fn handle_form(
     State(state): State<AppState>,
     Path(domain): Path<String>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Form(form): Form<RegisterForm>,
 ) -> Response {
     info!(
@@ -143,33 +392,14 @@ pub async fn handle_form(
         domain, form.username
     );
 
-    let domain_lower = domain.to_ascii_lowercase();
-    let domain_record = state
-        .blocking_db(move |db| db.get_domain_by_name(&domain_lower))
-        .await;
-
-    let domain_obj = match domain_record {
-        Some(d) if d.active && d.registration_enabled => d,
-        _ => {
-            let tmpl = ErrorTemplate {
-                nav_active: "",
-                flash: None,
-                status_code: 404,
-                status_text: "Not Found",
-                title: "Registration Unavailable",
-                message: "Registration is not available for this domain.",
-                back_url: "/",
-                back_label: "Home",
-            };
-            return Html(tmpl.render().unwrap()).into_response();
-        }
+    let Some((domain_obj, settings)) = open_domain(&state, &domain).await else {
+        return unavailable_page();
     };
 
     let username = form.username.trim().to_ascii_lowercase();
     let name = form.name.trim().to_string();
-
-    // Validate username
-    if let Err(reason) = validate_username(&username, &domain_obj.registration_username_regex) {
+    let invite = form.invite.trim().to_string();
+    let form_error = |status: StatusCode, reason: String| {
         let tmpl = RegisterFormTemplate {
             nav_active: "",
             flash: None,
@@ -177,82 +407,27 @@ pub async fn handle_form(
             username: username.clone(),
             username_preview: format!("{}@{}", username, domain_obj.domain),
             name: name.clone(),
+            invite: invite.clone(),
+            invite_only: settings.invite_only,
+            min_password_length: settings.min_password_length,
             error: Some(reason),
         };
-        return Html(tmpl.render().unwrap()).into_response();
-    }
+        (status, Html(tmpl.render().unwrap())).into_response()
+    };
 
-    // Validate password
     if form.password != form.confirm_password {
-        let tmpl = RegisterFormTemplate {
-            nav_active: "",
-            flash: None,
-            domain: domain_obj.domain.clone(),
-            username: username.clone(),
-            username_preview: format!("{}@{}", username, domain_obj.domain),
-            name: name.clone(),
-            error: Some("Passwords do not match.".into()),
-        };
-        return Html(tmpl.render().unwrap()).into_response();
-    }
-    if form.password.len() < 8 {
-        let tmpl = RegisterFormTemplate {
-            nav_active: "",
-            flash: None,
-            domain: domain_obj.domain.clone(),
-            username: username.clone(),
-            username_preview: format!("{}@{}", username, domain_obj.domain),
-            name: name.clone(),
-            error: Some("Password must be at least 8 characters.".into()),
-        };
-        return Html(tmpl.render().unwrap()).into_response();
+        return form_error(StatusCode::BAD_REQUEST, "Passwords do not match.".into());
     }
 
-    // Hash the password
-    let hash = match crate::auth::hash_password(&form.password) {
-        Ok(h) => h,
-        Err(e) => {
-            warn!("[register] failed to hash password: {}", e);
-            let tmpl = ErrorTemplate {
-                nav_active: "",
-                flash: None,
-                status_code: 500,
-                status_text: "Internal Server Error",
-                title: "Error",
-                message: "Failed to process your registration. Please try again.",
-                back_url: &format!("/register/{}", domain_obj.domain),
-                back_label: "Try Again",
-            };
-            return Html(tmpl.render().unwrap()).into_response();
-        }
+    let ip = crate::web::auth::client_ip(&headers, connect_info.map(|c| c.0.ip()));
+    let reg = Registration {
+        username: username.clone(),
+        name: name.clone(),
+        password: form.password,
+        invite: invite.clone(),
     };
-
-    let domain_id = domain_obj.id;
-    let username_clone = username.clone();
-    let name_clone = name.clone();
-    let domain_name = domain_obj.domain.clone();
-
-    let result = state
-        .blocking_db(move |db| db.create_account(domain_id, &username_clone, &hash, &name_clone, 0))
-        .await;
-
-    match result {
-        Ok(_id) => {
-            info!(
-                "[register] new account created: {}@{}",
-                username, domain_name
-            );
-            fire_webhook(
-                &state,
-                "account.registered",
-                serde_json::json!({
-                    "username": username,
-                    "domain": domain_name,
-                }),
-            );
-            // Regenerate configs so the new mailbox is active immediately.
-            crate::web::regen_configs(&state).await;
-
+    match register(&state, &domain_obj, &settings, ip, reg).await {
+        Ok(address) => {
             let tmpl = ErrorTemplate {
                 nav_active: "",
                 flash: None,
@@ -260,34 +435,93 @@ pub async fn handle_form(
                 status_text: "OK",
                 title: "Account Created",
                 message: &format!(
-                    "Your mailbox {}@{} has been created successfully. You can now log in.",
-                    username, domain_name
+                    "Your mailbox {} has been created successfully. You can now log in.",
+                    address
                 ),
                 back_url: "/",
                 back_label: "Home",
             };
             Html(tmpl.render().unwrap()).into_response()
         }
-        Err(e) => {
-            warn!(
-                "[register] failed to create account {}@{}: {}",
-                username, domain_name, e
-            );
-            let reason = if e.contains("23505") || e.to_lowercase().contains("unique") || e.to_lowercase().contains("duplicate") {
-                "That username is already taken on this domain.".to_string()
-            } else {
-                "Registration failed. Please try again.".to_string()
-            };
-            let tmpl = RegisterFormTemplate {
-                nav_active: "",
-                flash: None,
-                domain: domain_name.clone(),
-                username: username.clone(),
-                username_preview: format!("{}@{}", username, domain_name),
-                name,
-                error: Some(reason),
-            };
-            Html(tmpl.render().unwrap()).into_response()
+        Err(rejection) => {
+            let mut resp = form_error(rejection.status, rejection.message);
+            if let Some(seconds) = rejection.retry_after {
+                resp.headers_mut()
+                    .insert(header::RETRY_AFTER, header::HeaderValue::from(seconds));
+            }
+            resp
         }
     }
 }
+
+/// Registration rules of a domain, so clients can check input up front.
+pub async fn api_rules(State(state): State<AppState>, Path(domain): Path<String>) -> Response {
+    info!("[web] GET /api/register/{} — registration rules", domain);
+    match open_domain(&state, &domain).await {
+        Some((d, settings)) => Json(json!({
+            "domain": d.domain,
+            "invite_only": settings.invite_only,
+            "min_password_length": settings.min_password_length,
+            "username_regex": settings.username_regex,
+        }))
+        .into_response(),
+        None => json_error(Rejection::new(
+            StatusCode::NOT_FOUND,
+            "registration is not available for this domain",
+        )),
+    }
+}
+
+/// Create an account from a JSON body `{username, password, name?, invite?}`.
+/// Answers `201` with the new address, or an `error` message.
+pub async fn api_register(
+    State(state): State<AppState>,
+    Path(domain): Path<String>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    body: Result<Json<RegisterBody>, axum::extract::rejection::JsonRejection>,
+) -> Response {
+    let Json(body) = match body {
+        Ok(b) => b,
+        Err(e) => return json_error(Rejection::new(StatusCode::BAD_REQUEST, e.body_text())),
+    };
+    info!(
+        "[web] POST /api/register/{} — registration attempt username={}",
+        domain, body.username
+    );
+    let Some((domain_obj, settings)) = open_domain(&state, &domain).await else {
+        return json_error(Rejection::new(
+            StatusCode::NOT_FOUND,
+            "registration is not available for this domain",
+        ));
+    };
+    let ip = crate::web::auth::client_ip(&headers, connect_info.map(|c| c.0.ip()));
+    let reg = Registration {
+        username: body.username.trim().to_ascii_lowercase(),
+        name: body.name.trim().to_string(),
+        password: body.password,
+        invite: body.invite.trim().to_string(),
+    };
+    match register(&state, &domain_obj, &settings, ip, reg).await {
+        Ok(address) => (StatusCode::CREATED, Json(json!({"email": address}))).into_response(),
+        Err(rejection) => json_error(rejection),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::validate_username;
+
+    #[test]
+    fn usernames_follow_the_shared_rules_and_the_domain_regex() {
+        assert!(validate_username("jane.doe", "").is_ok());
+        assert!(validate_username("jd", "").is_err());
+        assert!(validate_username("Jane", "").is_err());
+        assert!(validate_username("postmaster", "").is_err());
+        assert!(validate_username("abuse", "").is_err());
+        assert!(validate_username("jane", "^[a-z]{2,5}$").is_ok());
+        assert!(validate_username("jane.doe", "^[a-z]{2,5}$").is_err());
+        // An invalid regex does not block registration.
+        assert!(validate_username("jane", "([").is_ok());
+    }
+}
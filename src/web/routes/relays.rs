@@ -7,9 +7,9 @@ use axum::{
 use log::{debug, error, info, warn};
 
 use crate::web::auth::AuthAdmin;
-use crate::web::forms::{RelayAssignmentForm, RelayEditForm, RelayForm};
-use crate::web::regen_configs;
+use crate::web::forms::{RelayAssignmentForm, RelayEditForm, RelayForm, RelayTestForm};
 use crate::web::AppState;
+use crate::web::{audit, regen_configs};
 
 // ── Templates ──
 
@@ -35,6 +35,11 @@ struct EditTemplate<'a> {
     flash: Option<&'a str>,
     relay: crate::db::OutboundRelay,
     assignments: Vec<crate::db::OutboundRelayAssignment>,
+    test_from: String,
+    test_to: String,
+    /// Outcome of a test send, shown after the relay test form is submitted.
+    test_ok: Option<bool>,
+    test_log: Vec<String>,
 }
 
 #[derive(Template)]
@@ -52,6 +57,14 @@ struct ErrorTemplate<'a> {
 
 // ── Handlers ──
 
+/// TLS mode chosen on the relay form; anything unrecognised means STARTTLS.
+fn tls_mode_from_form(mode: &str) -> String {
+    match mode.trim() {
+        "none" | "smtps" => mode.trim().to_string(),
+        _ => "starttls".to_string(),
+    }
+}
+
 pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
     info!("[web] GET /relays — listing outbound relays");
     let relays = state.blocking_db(|db| db.list_outbound_relays()).await;
@@ -74,19 +87,21 @@ pub async fn new_form(_auth: AuthAdmin, State(_state): State<AppState>) -> Html<
 }
 
 pub async fn create(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Form(form): Form<RelayForm>,
 ) -> Response {
     let port = form.port.unwrap_or(587);
+    let priority = form.priority.unwrap_or(0);
     let auth_type = if form.auth_type.is_empty() {
         "none".to_string()
     } else {
         form.auth_type.clone()
     };
+    let tls_mode = tls_mode_from_form(&form.tls_mode);
     info!(
-        "[web] POST /relays — creating relay name={} host={}:{} auth={}",
-        form.name, form.host, port, auth_type
+        "[web] POST /relays — creating relay name={} host={}:{} auth={} tls={}",
+        form.name, form.host, port, auth_type, tls_mode
     );
 
     let name = form.name.clone();
@@ -111,6 +126,8 @@ pub async fn create(
                 &auth_type,
                 username.as_deref(),
                 password.as_deref(),
+                &tls_mode,
+                priority,
             )
         })
         .await;
@@ -119,6 +136,15 @@ pub async fn create(
         Ok(id) => {
             info!("[web] relay created id={}", id);
             regen_configs(&state).await;
+            audit(
+                &state,
+                &auth,
+                "relay.created",
+                "relay",
+                Some(id),
+                serde_json::json!({"name": form.name, "host": form.host, "port": port, "tls_mode": tls_mode_from_form(&form.tls_mode), "priority": priority}),
+            )
+            .await;
             Redirect::to("/relays").into_response()
         }
         Err(e) => {
@@ -154,31 +180,116 @@ pub async fn edit_form(
     let assignments = state
         .blocking_db(move |db| db.list_relay_assignments(id))
         .await;
+    let test_from = default_test_sender(&relay, &state.hostname);
     let tmpl = EditTemplate {
         nav_active: "Relays",
         flash: None,
         relay,
         assignments,
+        test_from,
+        test_to: String::new(),
+        test_ok: None,
+        test_log: Vec::new(),
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+/// Sender for relay test messages: the relay login when it is an address,
+/// since many providers only accept their own senders, else postmaster.
+fn default_test_sender(relay: &crate::db::OutboundRelay, hostname: &str) -> String {
+    match relay.username.as_deref() {
+        Some(user) if user.contains('@') => user.to_string(),
+        _ => format!("postmaster@{}", hostname),
+    }
+}
+
+pub async fn test_send(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(id): Path<i64>,
+    Form(form): Form<RelayTestForm>,
+) -> Response {
+    info!("[web] POST /relays/{}/test — test send to {}", id, form.to);
+    let relay = match state.blocking_db(move |db| db.get_outbound_relay(id)).await {
+        Some(r) => r,
+        None => {
+            warn!("[web] relay id={} not found for test send", id);
+            return Redirect::to("/relays").into_response();
+        }
+    };
+    let assignments = state
+        .blocking_db(move |db| db.list_relay_assignments(id))
+        .await;
+
+    let from = if form.from.trim().is_empty() {
+        default_test_sender(&relay, &state.hostname)
+    } else {
+        form.from.trim().to_string()
+    };
+    let to = form.to.trim().to_string();
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    {
+        let relay = relay.clone();
+        let from = from.clone();
+        let to = to.clone();
+        std::thread::spawn(move || {
+            let _ = tx.send(crate::relay_health::test_send(&relay, &from, &to));
+        });
+    }
+    let (ok, test_log) = rx
+        .await
+        .unwrap_or_else(|_| (false, vec!["Test send thread panicked".to_string()]));
+    if ok {
+        info!("[web] relay id={} accepted test message to {}", id, to);
+    } else {
+        warn!(
+            "[web] relay id={} test send to {} failed: {}",
+            id,
+            to,
+            test_log.last().map(String::as_str).unwrap_or("")
+        );
+    }
+    audit(
+        &state,
+        &auth,
+        "relay.tested",
+        "relay",
+        Some(id),
+        serde_json::json!({"from": from, "to": to, "ok": ok}),
+    )
+    .await;
+
+    let tmpl = EditTemplate {
+        nav_active: "Relays",
+        flash: None,
+        relay,
+        assignments,
+        test_from: from,
+        test_to: to,
+        test_ok: Some(ok),
+        test_log,
     };
     Html(tmpl.render().unwrap()).into_response()
 }
 
 pub async fn update(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Form(form): Form<RelayEditForm>,
 ) -> Response {
     let port = form.port.unwrap_or(587);
+    let priority = form.priority.unwrap_or(0);
     let active = form.active.is_some();
     let auth_type = if form.auth_type.is_empty() {
         "none".to_string()
     } else {
         form.auth_type.clone()
     };
+    let tls_mode = tls_mode_from_form(&form.tls_mode);
     info!(
-        "[web] POST /relays/{} — updating relay name={} host={}:{} auth={} active={}",
-        id, form.name, form.host, port, auth_type, active
+        "[web] POST /relays/{} — updating relay name={} host={}:{} auth={} tls={} active={}",
+        id, form.name, form.host, port, auth_type, tls_mode, active
     );
 
     let name = form.name.clone();
@@ -210,17 +321,28 @@ pub async fn update(
                 &auth_type,
                 username.as_deref(),
                 final_password.as_deref(),
+                &tls_mode,
+                priority,
                 active,
             )
         })
         .await;
 
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "relay.updated",
+        "relay",
+        Some(id),
+        serde_json::json!({"name": form.name, "host": form.host, "port": port, "tls_mode": tls_mode_from_form(&form.tls_mode), "priority": priority, "active": active}),
+    )
+    .await;
     Redirect::to(&format!("/relays/{}/edit", id)).into_response()
 }
 
 pub async fn delete(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Response {
@@ -229,11 +351,20 @@ pub async fn delete(
         .blocking_db(move |db| db.delete_outbound_relay(id))
         .await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "relay.deleted",
+        "relay",
+        Some(id),
+        serde_json::json!({}),
+    )
+    .await;
     Redirect::to("/relays").into_response()
 }
 
 pub async fn add_assignment(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
     Form(form): Form<RelayAssignmentForm>,
@@ -251,8 +382,17 @@ pub async fn add_assignment(
         .await;
 
     match result {
-        Ok(_) => {
+        Ok(assignment_id) => {
             regen_configs(&state).await;
+            audit(
+                &state,
+                &auth,
+                "relay.assignment_added",
+                "relay",
+                Some(id),
+                serde_json::json!({"assignment_id": assignment_id, "type": form.assignment_type, "pattern": form.pattern.trim()}),
+            )
+            .await;
         }
         Err(e) => {
             error!("[web] failed to create relay assignment: {}", e);
@@ -262,7 +402,7 @@ pub async fn add_assignment(
 }
 
 pub async fn remove_assignment(
-    _auth: AuthAdmin,
+    auth: AuthAdmin,
     State(state): State<AppState>,
     Path((relay_id, assignment_id)): Path<(i64, i64)>,
 ) -> Response {
@@ -274,5 +414,14 @@ pub async fn remove_assignment(
         .blocking_db(move |db| db.delete_relay_assignment(assignment_id))
         .await;
     regen_configs(&state).await;
+    audit(
+        &state,
+        &auth,
+        "relay.assignment_removed",
+        "relay",
+        Some(relay_id),
+        serde_json::json!({"assignment_id": assignment_id}),
+    )
+    .await;
     Redirect::to(&format!("/relays/{}/edit", relay_id)).into_response()
 }
@@ -1,17 +1,23 @@
 use askama::Template;
 use axum::{
-    extract::State,
-    http::header,
+    extract::{ConnectInfo, Multipart, State},
+    http::{header, HeaderMap},
     response::{Html, IntoResponse, Response},
     Form,
 };
 use log::{debug, error, info, warn};
 
 use crate::db::Admin;
-use crate::web::auth::AuthAdmin;
-use crate::web::fire_webhook;
-use crate::web::forms::{FeatureToggleForm, MailSettingsForm, PasswordForm, TotpEnableForm};
+use crate::web::auth::{AuthAdmin, TotpCheck};
+use crate::web::{audit, fire_webhook};
+use crate::web::forms::{
+    ApprovalSettingsForm, DeliveryPolicyForm, DkimSettingsForm, FeatureToggleForm, GreylistSettingsForm,
+    MailSettingsForm, MaintenanceModeForm, PasswordForm, SendLimitsForm, SenderAuthForm,
+    TotpEnableForm, TrackingKillSwitchForm,
+};
+use crate::web::routes::approvals;
 use crate::web::AppState;
+use std::net::SocketAddr;
 
 // ── Templates ──
 
@@ -30,7 +36,29 @@ struct SettingsTemplate<'a> {
     milter_enabled: bool,
     filter_healthy: bool,
     milter_healthy: bool,
+    tracking_globally_enabled: bool,
+    maintenance_mode: bool,
     message_size_limit: u64,
+    subscribed_folders: String,
+    filter_cache_ttl_seconds: i64,
+    max_message_bytes: u64,
+    queue_auto_flush_minutes: i64,
+    dkim_header_canonicalization: String,
+    dkim_body_canonicalization: String,
+    dkim_sign_headers: String,
+    dkim_rotation_grace_days: i64,
+    delivery_policy: crate::delivery_policy::DeliveryPolicy,
+    spf_policy: crate::spf::AuthPolicy,
+    dkim_policy: crate::spf::AuthPolicy,
+    greylist_enabled: bool,
+    greylist_delay_seconds: i64,
+    greylist_lifetime_days: i64,
+    send_limits: crate::send_limits::Limits,
+    send_limit_window_minutes: i64,
+    two_person_approval: bool,
+    approval_ttl_hours: i64,
+    admin_count: i64,
+    recovery_codes_left: i64,
 }
 
 #[derive(Template)]
@@ -42,6 +70,14 @@ struct Setup2faTemplate<'a> {
     uri: String,
 }
 
+#[derive(Template)]
+#[template(path = "settings/recovery_codes.html")]
+struct RecoveryCodesTemplate<'a> {
+    nav_active: &'a str,
+    flash: Option<&'a str>,
+    codes: Vec<String>,
+}
+
 #[derive(Template)]
 #[template(path = "error.html")]
 struct ErrorTemplate<'a> {
@@ -150,6 +186,12 @@ pub async fn page(auth: AuthAdmin, State(state): State<AppState>) -> Html<String
 
     let filter_healthy = check_filter_health();
     let milter_healthy = check_milter_health();
+    let tracking_globally_enabled = state
+        .blocking_db(|db| db.get_setting(crate::filter::TRACKING_KILL_SWITCH_SETTING))
+        .await
+        .map(|v| v != "false")
+        .unwrap_or(true);
+    let maintenance_mode = state.blocking_db(crate::web::maintenance::load).await;
 
     let message_size_limit = state
         .blocking_db(|db| db.get_setting("message_size_limit"))
@@ -157,6 +199,77 @@ pub async fn page(auth: AuthAdmin, State(state): State<AppState>) -> Html<String
         .and_then(|v| v.parse::<u64>().ok())
         .unwrap_or(31_457_280);
 
+    let subscribed_folders = state
+        .blocking_db(|db| db.get_setting("subscribed_folders"))
+        .await
+        .unwrap_or_else(|| crate::config::DEFAULT_SUBSCRIBED_FOLDERS.to_string());
+
+    let max_message_bytes = state
+        .blocking_db(|db| db.get_setting(crate::filter::MAX_MESSAGE_BYTES_SETTING))
+        .await
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let filter_cache_ttl_seconds = state
+        .blocking_db(crate::decision_cache::ttl_secs)
+        .await;
+    let queue_auto_flush_minutes = state
+        .blocking_db(crate::queue_flush::interval_minutes)
+        .await;
+
+    let dkim_canonicalization = state
+        .blocking_db(|db| db.get_setting("dkim_canonicalization"))
+        .await
+        .unwrap_or_else(|| crate::config::DEFAULT_DKIM_CANONICALIZATION.to_string());
+    let (dkim_header_canonicalization, dkim_body_canonicalization) = dkim_canonicalization
+        .split_once('/')
+        .map(|(h, b)| (h.to_string(), b.to_string()))
+        .unwrap_or_else(|| ("relaxed".to_string(), "relaxed".to_string()));
+    let dkim_sign_headers = state
+        .blocking_db(|db| db.get_setting("dkim_sign_headers"))
+        .await
+        .unwrap_or_else(|| crate::config::DEFAULT_DKIM_SIGN_HEADERS.to_string());
+    let dkim_rotation_grace_days = state
+        .blocking_db(crate::dkim_rotation::grace_days)
+        .await;
+
+    let delivery_policy = state
+        .blocking_db(crate::delivery_policy::DeliveryPolicy::load)
+        .await;
+    let (spf_policy, dkim_policy) = state
+        .blocking_db(|db| {
+            (
+                crate::spf::AuthPolicy::load(db, crate::spf::POLICY_SETTING),
+                crate::spf::AuthPolicy::load(db, crate::dkim::POLICY_SETTING),
+            )
+        })
+        .await;
+    let (greylist_enabled, greylist_delay_seconds, greylist_lifetime_days) = state
+        .blocking_db(|db| {
+            (
+                crate::greylist::enabled(db),
+                crate::greylist::delay_secs(db),
+                crate::greylist::lifetime_days(db),
+            )
+        })
+        .await;
+    let (send_limits, send_limit_window_minutes) = state
+        .blocking_db(|db| {
+            (
+                crate::send_limits::Limits::load(db),
+                crate::send_limits::window_minutes(db),
+            )
+        })
+        .await;
+
+    let (two_person_approval, approval_ttl_hours, admin_count) = state
+        .blocking_db(|db| (approvals::required(db), approvals::ttl_hours(db), db.count_admins()))
+        .await;
+
+    let admin_id = auth.admin.id;
+    let recovery_codes_left = state
+        .blocking_db(move |db| db.count_unused_recovery_codes(admin_id))
+        .await;
+
     let tmpl = SettingsTemplate {
         nav_active: "Settings",
         flash: None,
@@ -170,7 +283,29 @@ pub async fn page(auth: AuthAdmin, State(state): State<AppState>) -> Html<String
         milter_enabled,
         filter_healthy,
         milter_healthy,
+        tracking_globally_enabled,
+        maintenance_mode,
         message_size_limit,
+        subscribed_folders,
+        filter_cache_ttl_seconds,
+        max_message_bytes,
+        queue_auto_flush_minutes,
+        dkim_header_canonicalization,
+        dkim_body_canonicalization,
+        dkim_sign_headers,
+        dkim_rotation_grace_days,
+        delivery_policy,
+        spf_policy,
+        dkim_policy,
+        greylist_enabled,
+        greylist_delay_seconds,
+        greylist_lifetime_days,
+        send_limits,
+        send_limit_window_minutes,
+        two_person_approval,
+        approval_ttl_hours,
+        admin_count,
+        recovery_codes_left,
     };
     Html(tmpl.render().unwrap())
 }
@@ -221,6 +356,114 @@ pub async fn update_features(
     Html(tmpl.render().unwrap()).into_response()
 }
 
+/// Turn pixel injection on or off everywhere at once.  The filter picks the
+/// change up within seconds, without regenerating configs.
+pub async fn update_tracking_kill_switch(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<TrackingKillSwitchForm>,
+) -> Response {
+    info!(
+        "[web] POST /settings/tracking-kill-switch — set tracking enabled={:?} by username={}",
+        form.enabled, auth.admin.username
+    );
+
+    let enabled = form.enabled != "false";
+    let value = if enabled { "true" } else { "false" };
+    state
+        .blocking_db(move |db| {
+            db.set_setting(crate::filter::TRACKING_KILL_SWITCH_SETTING, value);
+        })
+        .await;
+
+    if enabled {
+        info!(
+            "[web] tracking pixel injection resumed by user={}",
+            auth.admin.username
+        );
+    } else {
+        warn!(
+            "[web] tracking pixel injection stopped by the kill switch, user={}",
+            auth.admin.username
+        );
+    }
+    audit(
+        &state,
+        &auth,
+        "settings.tracking_kill_switch",
+        "setting",
+        None,
+        serde_json::json!({"tracking_globally_enabled": enabled}),
+    )
+    .await;
+    fire_webhook(
+        &state,
+        "settings.tracking_kill_switch",
+        serde_json::json!({"tracking_globally_enabled": enabled}),
+    );
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: if enabled {
+            "Tracking resumed. New messages get tracking pixels again according to the tracking rules."
+        } else {
+            "Tracking stopped. No tracking pixels are injected until it is resumed."
+        },
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+pub async fn update_maintenance_mode(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<MaintenanceModeForm>,
+) -> Response {
+    let enabled = form.enabled == "true";
+    info!(
+        "[web] POST {} — set maintenance mode={} by username={}",
+        crate::web::maintenance::TOGGLE_PATH,
+        enabled,
+        auth.admin.username
+    );
+    state
+        .blocking_db(move |db| crate::web::maintenance::set(db, enabled))
+        .await;
+    audit(
+        &state,
+        &auth,
+        "settings.maintenance_mode",
+        "setting",
+        None,
+        serde_json::json!({"maintenance_mode": enabled}),
+    )
+    .await;
+    fire_webhook(
+        &state,
+        "settings.maintenance_mode",
+        serde_json::json!({"maintenance_mode": enabled}),
+    );
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: if enabled {
+            "Maintenance mode is on. The admin panel and the provisioning API refuse changes until it is turned off."
+        } else {
+            "Maintenance mode is off. Changes are accepted again."
+        },
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
 pub async fn update_mail_settings(
     auth: AuthAdmin,
     State(state): State<AppState>,
@@ -234,16 +477,38 @@ pub async fn update_mail_settings(
     // Enforce a minimum of 1 MiB to avoid breaking Postfix
     let size = form.message_size_limit.max(1_048_576);
     let size_str = size.to_string();
+    let folders = form
+        .subscribed_folders
+        .as_deref()
+        .map(|v| crate::config::parse_folder_list(v).join(","));
+    let folders_for_db = folders.clone();
+    let cache_ttl = form.filter_cache_ttl_seconds.map(|t| t.max(0));
+    let max_message_bytes = form.max_message_bytes;
+    let auto_flush_minutes = form
+        .queue_auto_flush_minutes
+        .map(|m| m.clamp(0, crate::queue_flush::MAX_INTERVAL_MINUTES));
 
     state
         .blocking_db(move |db| {
             db.set_setting("message_size_limit", &size_str);
+            if let Some(ref f) = folders_for_db {
+                db.set_setting("subscribed_folders", f);
+            }
+            if let Some(ttl) = cache_ttl {
+                db.set_setting("filter_cache_ttl_seconds", &ttl.to_string());
+            }
+            if let Some(max) = max_message_bytes {
+                db.set_setting(crate::filter::MAX_MESSAGE_BYTES_SETTING, &max.to_string());
+            }
+            if let Some(minutes) = auto_flush_minutes {
+                db.set_setting(crate::queue_flush::INTERVAL_SETTING, &minutes.to_string());
+            }
         })
         .await;
 
     info!(
-        "[web] message_size_limit set to {} by user={}",
-        size, auth.admin.username
+        "[web] message_size_limit set to {}, subscribed_folders={:?}, filter_cache_ttl_seconds={:?}, max_message_bytes={:?}, queue_auto_flush_minutes={:?} by user={}",
+        size, folders, cache_ttl, max_message_bytes, auto_flush_minutes, auth.admin.username
     );
 
     crate::web::regen_configs(&state).await;
@@ -251,7 +516,13 @@ pub async fn update_mail_settings(
     fire_webhook(
         &state,
         "settings.mail_updated",
-        serde_json::json!({"message_size_limit": size}),
+        serde_json::json!({
+            "message_size_limit": size,
+            "subscribed_folders": folders,
+            "filter_cache_ttl_seconds": cache_ttl,
+            "max_message_bytes": max_message_bytes,
+            "queue_auto_flush_minutes": auto_flush_minutes,
+        }),
     );
     let tmpl = ErrorTemplate {
         nav_active: "Settings",
@@ -266,6 +537,474 @@ pub async fn update_mail_settings(
     Html(tmpl.render().unwrap()).into_response()
 }
 
+pub async fn update_delivery_policy(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<DeliveryPolicyForm>,
+) -> Response {
+    use crate::delivery_policy::{self as policy, DisabledPolicy, OverQuotaPolicy};
+    info!(
+        "[web] POST /settings/delivery-policy — update delivery policy by username={}",
+        auth.admin.username
+    );
+
+    let forward_to = form.disabled_account_forward_to.trim().to_string();
+    let message = policy::single_line(&form.disabled_account_message);
+    let parsed = match (
+        DisabledPolicy::parse(&form.disabled_account_policy),
+        OverQuotaPolicy::parse(&form.over_quota_policy),
+    ) {
+        (Some(DisabledPolicy::Forward), _) if forward_to.is_empty() => {
+            Err("Forwarding mail for disabled accounts needs a forward address.")
+        }
+        _ if !forward_to.is_empty()
+            && (forward_to.contains(|c: char| c.is_whitespace() || c == ',')
+                || !forward_to.contains('@')) =>
+        {
+            Err("The forward address must be a single email address.")
+        }
+        (Some(disabled), Some(over_quota)) => Ok((disabled, over_quota)),
+        _ => Err("Unknown delivery policy."),
+    };
+    let (disabled, over_quota) = match parsed {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("[web] rejected delivery policy: {}", e);
+            let tmpl = ErrorTemplate {
+                nav_active: "Settings",
+                flash: None,
+                status_code: 400,
+                status_text: "Bad Request",
+                title: "Invalid Delivery Policy",
+                message: e,
+                back_url: "/settings",
+                back_label: "Back to Settings",
+            };
+            return Html(tmpl.render().unwrap()).into_response();
+        }
+    };
+
+    let forward_for_db = forward_to.clone();
+    state
+        .blocking_db(move |db| {
+            db.set_setting(policy::DISABLED_POLICY_SETTING, disabled.as_str());
+            db.set_setting(policy::DISABLED_MESSAGE_SETTING, &message);
+            db.set_setting(policy::DISABLED_FORWARD_SETTING, &forward_for_db);
+            db.set_setting(policy::OVER_QUOTA_POLICY_SETTING, over_quota.as_str());
+        })
+        .await;
+
+    info!(
+        "[web] delivery policy set: disabled={}, forward_to={}, over_quota={} by user={}",
+        disabled.as_str(),
+        forward_to,
+        over_quota.as_str(),
+        auth.admin.username
+    );
+
+    crate::web::regen_configs(&state).await;
+
+    fire_webhook(
+        &state,
+        "settings.delivery_policy_updated",
+        serde_json::json!({
+            "disabled_account_policy": disabled.as_str(),
+            "over_quota_policy": over_quota.as_str(),
+        }),
+    );
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: "Delivery policy updated successfully.",
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+pub async fn update_sender_auth(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<SenderAuthForm>,
+) -> Response {
+    use crate::spf::AuthPolicy;
+    info!(
+        "[web] POST /settings/sender-auth — update SPF and DKIM policies by username={}",
+        auth.admin.username
+    );
+
+    let (Some(spf), Some(dkim)) = (
+        AuthPolicy::parse(&form.spf_policy),
+        AuthPolicy::parse(&form.dkim_policy),
+    ) else {
+        warn!(
+            "[web] rejected sender authentication policy: spf={}, dkim={}",
+            form.spf_policy, form.dkim_policy
+        );
+        let tmpl = ErrorTemplate {
+            nav_active: "Settings",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Sender Authentication Policy",
+            message: "Unknown SPF or DKIM policy.",
+            back_url: "/settings",
+            back_label: "Back to Settings",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    };
+
+    state
+        .blocking_db(move |db| {
+            db.set_setting(crate::spf::POLICY_SETTING, spf.as_str());
+            db.set_setting(crate::dkim::POLICY_SETTING, dkim.as_str());
+        })
+        .await;
+    info!(
+        "[web] sender authentication policy set: spf={}, dkim={} by user={}",
+        spf.as_str(),
+        dkim.as_str(),
+        auth.admin.username
+    );
+
+    fire_webhook(
+        &state,
+        "settings.sender_auth_updated",
+        serde_json::json!({ "spf_policy": spf.as_str(), "dkim_policy": dkim.as_str() }),
+    );
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: "Sender authentication policy updated successfully.",
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+pub async fn update_greylist_settings(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<GreylistSettingsForm>,
+) -> Response {
+    use crate::greylist;
+    info!(
+        "[web] POST /settings/greylist — update greylisting by username={}",
+        auth.admin.username
+    );
+
+    let enabled = form.enabled.is_some();
+    let delay = form
+        .delay_seconds
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|d| (0..=greylist::MAX_DELAY_SECS).contains(d));
+    let lifetime = form
+        .lifetime_days
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|d| (1..=greylist::MAX_LIFETIME_DAYS).contains(d));
+    let (Some(delay), Some(lifetime)) = (delay, lifetime) else {
+        warn!(
+            "[web] rejected greylist settings: delay={:?}, lifetime={:?}",
+            form.delay_seconds, form.lifetime_days
+        );
+        let message = format!(
+            "The delay must be 0 to {} seconds and the lifetime 1 to {} days.",
+            greylist::MAX_DELAY_SECS,
+            greylist::MAX_LIFETIME_DAYS
+        );
+        let tmpl = ErrorTemplate {
+            nav_active: "Settings",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Greylisting Settings",
+            message: &message,
+            back_url: "/settings",
+            back_label: "Back to Settings",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    };
+
+    state
+        .blocking_db(move |db| {
+            db.set_setting(greylist::ENABLED_SETTING, if enabled { "true" } else { "false" });
+            db.set_setting(greylist::DELAY_SETTING, &delay.to_string());
+            db.set_setting(greylist::LIFETIME_SETTING, &lifetime.to_string());
+        })
+        .await;
+    info!(
+        "[web] greylisting set: enabled={}, delay={}s, lifetime={}d by user={}",
+        enabled, delay, lifetime, auth.admin.username
+    );
+
+    fire_webhook(
+        &state,
+        "settings.greylist_updated",
+        serde_json::json!({
+            "enabled": enabled,
+            "delay_seconds": delay,
+            "lifetime_days": lifetime,
+        }),
+    );
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: "Greylisting settings updated successfully.",
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+pub async fn update_send_limits(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<SendLimitsForm>,
+) -> Response {
+    use crate::send_limits;
+    info!(
+        "[web] POST /settings/send-limits — update sending limits by username={}",
+        auth.admin.username
+    );
+
+    let limit = |v: &str| v.trim().parse::<i64>().ok().filter(|n| *n >= 0);
+    let window = form
+        .window_minutes
+        .trim()
+        .parse::<i64>()
+        .ok()
+        .filter(|m| (1..=send_limits::MAX_WINDOW_MINUTES).contains(m));
+    let (Some(messages), Some(recipients), Some(window)) =
+        (limit(&form.messages), limit(&form.recipients), window)
+    else {
+        warn!(
+            "[web] rejected sending limits: messages={:?}, recipients={:?}, window={:?}",
+            form.messages, form.recipients, form.window_minutes
+        );
+        let message = format!(
+            "Limits must be whole numbers of 0 or more and the window 1 to {} minutes.",
+            send_limits::MAX_WINDOW_MINUTES
+        );
+        let tmpl = ErrorTemplate {
+            nav_active: "Settings",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Sending Limits",
+            message: &message,
+            back_url: "/settings",
+            back_label: "Back to Settings",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    };
+
+    state
+        .blocking_db(move |db| {
+            db.set_setting(send_limits::MESSAGES_SETTING, &messages.to_string());
+            db.set_setting(send_limits::RECIPIENTS_SETTING, &recipients.to_string());
+            db.set_setting(send_limits::WINDOW_SETTING, &window.to_string());
+        })
+        .await;
+    info!(
+        "[web] sending limits set: messages={}, recipients={}, window={}min by user={}",
+        messages, recipients, window, auth.admin.username
+    );
+
+    let details = serde_json::json!({
+        "messages": messages,
+        "recipients": recipients,
+        "window_minutes": window,
+    });
+    audit(
+        &state,
+        &auth,
+        "settings.send_limits_updated",
+        "setting",
+        None,
+        details.clone(),
+    )
+    .await;
+    fire_webhook(&state, "settings.send_limits_updated", details);
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: "Sending limits updated successfully.",
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+pub async fn update_approval_settings(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<ApprovalSettingsForm>,
+) -> Response {
+    info!(
+        "[web] POST /settings/approvals — update two-person approval by username={}",
+        auth.admin.username
+    );
+
+    let enabled = form.enabled.is_some();
+    let ttl_hours = form.ttl_hours.trim().parse::<i64>().ok().filter(|h| *h > 0);
+    let admin_count = state.blocking_db(|db| db.count_admins()).await;
+    let problem = match ttl_hours {
+        None => Some("Expiry must be a whole number of hours greater than zero."),
+        Some(_) if enabled && admin_count < 2 => {
+            Some("Two-person approval needs at least two admins; add one with the seed command first.")
+        }
+        Some(_) => None,
+    };
+    if let Some(message) = problem {
+        warn!(
+            "[web] rejected approval settings: enabled={}, ttl_hours={:?}, admins={}",
+            enabled, form.ttl_hours, admin_count
+        );
+        let tmpl = ErrorTemplate {
+            nav_active: "Settings",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Invalid Approval Settings",
+            message,
+            back_url: "/settings",
+            back_label: "Back to Settings",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    let ttl_hours = ttl_hours.unwrap_or(approvals::DEFAULT_TTL_HOURS);
+
+    state
+        .blocking_db(move |db| {
+            db.set_setting(approvals::ENABLED_SETTING, if enabled { "true" } else { "false" });
+            db.set_setting(approvals::TTL_SETTING, &ttl_hours.to_string());
+        })
+        .await;
+    info!(
+        "[web] two-person approval set: enabled={}, ttl_hours={} by user={}",
+        enabled, ttl_hours, auth.admin.username
+    );
+
+    fire_webhook(
+        &state,
+        "settings.approvals_updated",
+        serde_json::json!({ "enabled": enabled, "ttl_hours": ttl_hours }),
+    );
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: "Two-person approval settings updated successfully.",
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
+pub async fn update_dkim_settings(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<DkimSettingsForm>,
+) -> Response {
+    info!(
+        "[web] POST /settings/dkim — update DKIM signing options by username={}",
+        auth.admin.username
+    );
+
+    let canonicalization = crate::config::parse_dkim_canonicalization(&format!(
+        "{}/{}",
+        form.header_canonicalization, form.body_canonicalization
+    ));
+    let sign_headers = crate::config::parse_dkim_sign_headers(&form.sign_headers);
+    let grace_days = match form.rotation_grace_days.trim() {
+        "" => Ok(crate::dkim_rotation::DEFAULT_GRACE_DAYS),
+        days => days
+            .parse::<i64>()
+            .ok()
+            .filter(|d| (0..=crate::dkim_rotation::MAX_GRACE_DAYS).contains(d))
+            .ok_or_else(|| {
+                format!(
+                    "Rotation grace period must be a whole number of days from 0 to {}.",
+                    crate::dkim_rotation::MAX_GRACE_DAYS
+                )
+            }),
+    };
+    let (canonicalization, sign_headers, grace_days) =
+        match (canonicalization, sign_headers, grace_days) {
+            (Ok(c), Ok(h), Ok(g)) => (c, h.join(","), g),
+            (Err(e), _, _) | (_, Err(e), _) | (_, _, Err(e)) => {
+                warn!("[web] rejected DKIM settings: {}", e);
+                let tmpl = ErrorTemplate {
+                    nav_active: "Settings",
+                    flash: None,
+                    status_code: 400,
+                    status_text: "Bad Request",
+                    title: "Invalid DKIM Settings",
+                    message: &e,
+                    back_url: "/settings",
+                    back_label: "Back to Settings",
+                };
+                return Html(tmpl.render().unwrap()).into_response();
+            }
+        };
+
+    let canonicalization_for_db = canonicalization.clone();
+    let sign_headers_for_db = sign_headers.clone();
+    state
+        .blocking_db(move |db| {
+            db.set_setting("dkim_canonicalization", &canonicalization_for_db);
+            db.set_setting("dkim_sign_headers", &sign_headers_for_db);
+            db.set_setting(crate::dkim_rotation::GRACE_SETTING, &grace_days.to_string());
+        })
+        .await;
+
+    info!(
+        "[web] DKIM canonicalization={}, signed headers={}, rotation grace={}d by user={}",
+        canonicalization, sign_headers, grace_days, auth.admin.username
+    );
+
+    crate::web::regen_configs(&state).await;
+
+    fire_webhook(
+        &state,
+        "settings.dkim_updated",
+        serde_json::json!({
+            "canonicalization": canonicalization,
+            "sign_headers": sign_headers,
+            "rotation_grace_days": grace_days,
+        }),
+    );
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Success",
+        message: "DKIM signing settings updated successfully.",
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
+
 pub async fn change_password(
     auth: AuthAdmin,
     State(state): State<AppState>,
@@ -374,30 +1113,55 @@ pub async fn setup_2fa(auth: AuthAdmin, State(_state): State<AppState>) -> Html<
 pub async fn enable_2fa(
     auth: AuthAdmin,
     State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     Form(form): Form<TotpEnableForm>,
 ) -> Response {
     info!(
         "[web] POST /settings/2fa/enable — enabling 2FA for username={}",
         auth.admin.username
     );
-    if !crate::auth::verify_totp(&form.secret, &form.code) {
+    let ip = crate::web::auth::client_ip(&headers, connect_info.map(|c| c.0.ip()));
+    let (admin_id, username, secret, code) = (
+        auth.admin.id,
+        auth.admin.username.clone(),
+        form.secret.clone(),
+        form.code.trim().to_string(),
+    );
+    let check = state
+        .blocking_db(move |db| {
+            crate::web::auth::check_totp(db, admin_id, &username, &secret, &code, &ip)
+        })
+        .await;
+    let message = match check {
+        TotpCheck::Valid => None,
+        TotpCheck::Invalid => Some("Invalid verification code. Please try again.".to_string()),
+        TotpCheck::Locked(minutes) => Some(format!(
+            "Too many invalid codes. Wait {} minutes before trying again.",
+            minutes
+        )),
+    };
+    if let Some(message) = message {
         warn!(
-            "[web] 2FA enable failed — invalid verification code for username={}",
+            "[web] 2FA enable failed — verification code refused for username={}",
             auth.admin.username
         );
+        let (status_code, status_text) = match check {
+            TotpCheck::Locked(_) => (429, "Too Many Requests"),
+            _ => (400, "Bad Request"),
+        };
         let tmpl = ErrorTemplate {
             nav_active: "Settings",
             flash: None,
-            status_code: 400,
-            status_text: "Bad Request",
+            status_code,
+            status_text,
             title: "Error",
-            message: "Invalid verification code. Please try again.",
+            message: &message,
             back_url: "/settings/2fa",
             back_label: "Retry",
         };
         return Html(tmpl.render().unwrap()).into_response();
     }
-    let admin_id = auth.admin.id;
     let secret = form.secret.clone();
     state
         .blocking_db(move |db| db.update_admin_totp(admin_id, Some(&secret), true))
@@ -411,19 +1175,86 @@ pub async fn enable_2fa(
         "settings.2fa_enabled",
         serde_json::json!({"username": auth.admin.username}),
     );
-    let tmpl = ErrorTemplate {
+    issue_recovery_codes(
+        &state,
+        admin_id,
+        "Two-factor authentication has been enabled.",
+    )
+    .await
+}
+
+/// Generate a new set of recovery codes for `admin_id`, replacing any old
+/// ones, and show the plaintext codes this one time.
+async fn issue_recovery_codes(state: &AppState, admin_id: i64, flash: &str) -> Response {
+    let codes = crate::auth::generate_recovery_codes();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| crate::auth::hash_recovery_code(c))
+        .collect();
+    if let Err(e) = state
+        .blocking_db(move |db| db.replace_recovery_codes(admin_id, &hashes))
+        .await
+    {
+        error!(
+            "[web] failed to store recovery codes for admin id={}: {}",
+            admin_id, e
+        );
+        let tmpl = ErrorTemplate {
+            nav_active: "Settings",
+            flash: None,
+            status_code: 500,
+            status_text: "Internal Server Error",
+            title: "Error",
+            message:
+                "Recovery codes could not be saved. Generate a new set from the settings page.",
+            back_url: "/settings",
+            back_label: "Back to Settings",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    let tmpl = RecoveryCodesTemplate {
         nav_active: "Settings",
-        flash: None,
-        status_code: 200,
-        status_text: "OK",
-        title: "Success",
-        message: "Two-factor authentication has been enabled.",
-        back_url: "/settings",
-        back_label: "Back to Settings",
+        flash: Some(flash),
+        codes,
     };
     Html(tmpl.render().unwrap()).into_response()
 }
 
+pub async fn regenerate_recovery_codes(auth: AuthAdmin, State(state): State<AppState>) -> Response {
+    info!(
+        "[web] POST /settings/2fa/recovery-codes — regenerating recovery codes for username={}",
+        auth.admin.username
+    );
+    if !auth.admin.totp_enabled {
+        warn!(
+            "[web] recovery codes requested without 2FA for username={}",
+            auth.admin.username
+        );
+        let tmpl = ErrorTemplate {
+            nav_active: "Settings",
+            flash: None,
+            status_code: 400,
+            status_text: "Bad Request",
+            title: "Error",
+            message: "Enable two-factor authentication before generating recovery codes.",
+            back_url: "/settings",
+            back_label: "Back to Settings",
+        };
+        return Html(tmpl.render().unwrap()).into_response();
+    }
+    fire_webhook(
+        &state,
+        "settings.2fa_recovery_codes_regenerated",
+        serde_json::json!({"username": auth.admin.username}),
+    );
+    issue_recovery_codes(
+        &state,
+        auth.admin.id,
+        "New recovery codes generated. The previous codes no longer work.",
+    )
+    .await
+}
+
 pub async fn disable_2fa(auth: AuthAdmin, State(state): State<AppState>) -> Response {
     info!(
         "[web] POST /settings/2fa/disable — disabling 2FA for username={}",
@@ -431,7 +1262,10 @@ pub async fn disable_2fa(auth: AuthAdmin, State(state): State<AppState>) -> Resp
     );
     let admin_id = auth.admin.id;
     state
-        .blocking_db(move |db| db.update_admin_totp(admin_id, None, false))
+        .blocking_db(move |db| {
+            db.update_admin_totp(admin_id, None, false);
+            db.delete_recovery_codes(admin_id);
+        })
         .await;
     info!(
         "[web] 2FA disabled successfully for username={}",
@@ -665,3 +1499,131 @@ pub async fn download_key(auth: AuthAdmin) -> Response {
         }
     }
 }
+
+/// Largest backup file the restore form accepts.
+pub const BACKUP_UPLOAD_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Download the whole configuration as a backup file, see [`crate::backup`].
+pub async fn download_backup(auth: AuthAdmin, State(state): State<AppState>) -> Response {
+    info!(
+        "[web] GET /settings/backup.json — configuration backup by username={}",
+        auth.admin.username
+    );
+    match state.blocking_db(crate::backup::export).await {
+        Ok(json) => {
+            audit(
+                &state,
+                &auth,
+                "settings.backup_downloaded",
+                "settings",
+                None,
+                serde_json::json!({"bytes": json.len()}),
+            )
+            .await;
+            let filename = format!(
+                "mailserver-backup-{}.json",
+                chrono::Utc::now().format("%Y%m%d-%H%M%S")
+            );
+            (
+                [
+                    (header::CONTENT_TYPE, "application/json".to_string()),
+                    (
+                        header::CONTENT_DISPOSITION,
+                        format!("attachment; filename=\"{}\"", filename),
+                    ),
+                ],
+                json,
+            )
+                .into_response()
+        }
+        Err(e) => {
+            error!("[web] failed to export backup: {}", e);
+            let tmpl = ErrorTemplate {
+                nav_active: "Settings",
+                flash: None,
+                status_code: 500,
+                status_text: "Error",
+                title: "Backup Failed",
+                message: &format!("Failed to read the configuration: {}", e),
+                back_url: "/settings",
+                back_label: "Back to Settings",
+            };
+            Html(tmpl.render().unwrap()).into_response()
+        }
+    }
+}
+
+/// Restore an uploaded backup file.  Rows are upserted and nothing is
+/// deleted; a file that cannot be restored changes nothing.
+pub async fn restore_backup(
+    auth: AuthAdmin,
+    State(state): State<AppState>,
+    multipart: Multipart,
+) -> Response {
+    info!(
+        "[web] POST /settings/restore — restoring a backup by username={}",
+        auth.admin.username
+    );
+    let result = match super::csv_transfer::read_upload(multipart).await {
+        Ok(input) => {
+            state
+                .blocking_db(move |db| crate::backup::restore(db, &input))
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    let tables = match result {
+        Ok(tables) => tables,
+        Err(e) => {
+            warn!("[web] backup restore failed: {}", e);
+            let tmpl = ErrorTemplate {
+                nav_active: "Settings",
+                flash: None,
+                status_code: 400,
+                status_text: "Bad Request",
+                title: "Restore Failed",
+                message: &format!("Nothing was restored: {}", e),
+                back_url: "/settings",
+                back_label: "Back to Settings",
+            };
+            return Html(tmpl.render().unwrap()).into_response();
+        }
+    };
+
+    let changed: serde_json::Map<String, serde_json::Value> = tables
+        .iter()
+        .filter(|t| t.changed > 0)
+        .map(|t| (t.table.to_string(), t.changed.into()))
+        .collect();
+    let total: u64 = tables.iter().map(|t| t.changed).sum();
+    if total > 0 {
+        crate::web::regen_configs(&state).await;
+    }
+    let details = serde_json::json!({ "changed": changed });
+    audit(
+        &state,
+        &auth,
+        "settings.restored",
+        "settings",
+        None,
+        details.clone(),
+    )
+    .await;
+    fire_webhook(&state, "settings.restored", details);
+
+    let tmpl = ErrorTemplate {
+        nav_active: "Settings",
+        flash: None,
+        status_code: 200,
+        status_text: "OK",
+        title: "Backup Restored",
+        message: &format!(
+            "The backup was restored: {} rows changed in {} tables.",
+            total,
+            changed.len()
+        ),
+        back_url: "/settings",
+        back_label: "Back to Settings",
+    };
+    Html(tmpl.render().unwrap()).into_response()
+}
@@ -1,14 +1,21 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+
 use askama::Template;
 use axum::{
-    extract::{Path, State},
+    body::Body,
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
     response::{Html, IntoResponse, Redirect, Response},
     Form,
 };
 use log::{debug, info, warn};
+use serde::Serialize;
+use tokio_stream::wrappers::ReceiverStream;
 
-use crate::db::PixelOpen;
+use crate::db::{PixelOpen, TrackedMessage};
 use crate::web::auth::AuthAdmin;
-use crate::web::forms::{TrackingPatternForm, TrackingRuleForm};
+use crate::web::forms::{TrackingExportQuery, TrackingPatternForm, TrackingRuleForm};
 use crate::web::AppState;
 
 // serde_json used for parsing conditions_json from the rule form
@@ -37,6 +44,14 @@ struct ListTemplate<'a> {
     patterns: Vec<crate::db::TrackingPattern>,
     rules: Vec<crate::db::TrackingRule>,
     pixel_base_url: String,
+    pixel_position: &'static str,
+    pixel_id_prefix: String,
+    pixel_id_allowlist: String,
+    pixel_id_validation: &'static str,
+    pixel_rejections: Vec<crate::db::PixelRejection>,
+    pixel_per_recipient: bool,
+    strip_pixels_on_reply: bool,
+    pixel_dedup_window_seconds: i64,
 }
 
 #[derive(Template)]
@@ -93,6 +108,33 @@ pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
     let patterns = state.blocking_db(|db| db.list_tracking_patterns()).await;
     let rules = state.blocking_db(|db| db.list_tracking_rules()).await;
     let pixel_base_url = load_pixel_settings(&state).await;
+    let pixel_position = state
+        .blocking_db(crate::filter::PixelPosition::configured)
+        .await
+        .as_str();
+    let pixel_id_prefix = state.blocking_db(crate::filter::pixel_id_prefix).await;
+    let pixel_id_allowlist = state
+        .blocking_db(|db| db.get_setting("pixel_id_allowlist"))
+        .await
+        .unwrap_or_default();
+    let pixel_id_validation = state
+        .blocking_db(crate::web::routes::pixel::IdValidation::configured)
+        .await
+        .as_str();
+    let pixel_rejections = state
+        .blocking_db(|db| db.list_pixel_rejections(14))
+        .await;
+    let pixel_per_recipient = state
+        .blocking_db(crate::filter::per_recipient_pixels)
+        .await;
+    let strip_pixels_on_reply = state
+        .blocking_db(|db| db.get_setting("strip_pixels_on_reply"))
+        .await
+        .map(|v| v != "false")
+        .unwrap_or(true);
+    let pixel_dedup_window_seconds = state
+        .blocking_db(crate::web::routes::pixel::dedup_window_secs)
+        .await;
 
     let tmpl = ListTemplate {
         nav_active: "Tracking",
@@ -101,11 +143,19 @@ pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<Strin
         patterns,
         rules,
         pixel_base_url,
+        pixel_position,
+        pixel_id_prefix,
+        pixel_id_allowlist,
+        pixel_id_validation,
+        pixel_rejections,
+        pixel_per_recipient,
+        strip_pixels_on_reply,
+        pixel_dedup_window_seconds,
     };
     Html(tmpl.render().unwrap())
 }
 
-async fn load_pixel_settings(state: &AppState) -> String {
+pub(crate) async fn load_pixel_settings(state: &AppState) -> String {
     if let Some(base) = state.blocking_db(|db| db.get_setting("pixel_base_url")).await {
         return base;
     }
@@ -133,6 +183,62 @@ pub async fn update_pixel_settings(
         "[web] POST /tracking/pixel — update pixel base url for username={}",
         auth.admin.username
     );
+    if let Some(position) = crate::filter::PixelPosition::parse(&form.pixel_position) {
+        let flag = |v: &Option<String>| if v.is_some() { "true" } else { "false" };
+        let per_recipient = flag(&form.pixel_per_recipient);
+        let strip_on_reply = flag(&form.strip_pixels_on_reply);
+        state
+            .blocking_db(move |db| {
+                db.set_setting("pixel_position", position.as_str());
+                db.set_setting("pixel_per_recipient", per_recipient);
+                db.set_setting("strip_pixels_on_reply", strip_on_reply);
+            })
+            .await;
+    }
+    if let Some(validation) =
+        crate::web::routes::pixel::IdValidation::parse(&form.pixel_id_validation)
+    {
+        state
+            .blocking_db(move |db| db.set_setting("pixel_id_validation", validation.as_str()))
+            .await;
+    }
+    if let Some(prefix) = form.pixel_id_prefix.as_deref() {
+        let prefix = prefix.trim().to_string();
+        if crate::filter::valid_pixel_id_prefix(&prefix) {
+            state
+                .blocking_db(move |db| db.set_setting("pixel_id_prefix", &prefix))
+                .await;
+        } else {
+            warn!("[web] ignoring invalid pixel id prefix {:?}", prefix);
+        }
+    }
+    if let Some(allowlist) = form.pixel_id_allowlist.as_deref() {
+        let allowlist = allowlist.trim().to_string();
+        state
+            .blocking_db(move |db| db.set_setting("pixel_id_allowlist", &allowlist))
+            .await;
+    }
+    if let Some(window) = form.pixel_dedup_window_seconds.as_deref() {
+        use crate::web::routes::pixel::{DEDUP_WINDOW_SETTING, MAX_DEDUP_WINDOW_SECS};
+        // Blank restores the default.
+        match window.trim() {
+            "" => {
+                state
+                    .blocking_db(|db| db.set_setting(DEDUP_WINDOW_SETTING, ""))
+                    .await
+            }
+            w => match w.parse::<i64>() {
+                Ok(secs) if (0..=MAX_DEDUP_WINDOW_SECS).contains(&secs) => {
+                    state
+                        .blocking_db(move |db| {
+                            db.set_setting(DEDUP_WINDOW_SETTING, &secs.to_string())
+                        })
+                        .await
+                }
+                _ => warn!("[web] ignoring invalid pixel dedup window {:?}", w),
+            },
+        }
+    }
     let base_url = form.pixel_base_url.trim().to_string();
     if base_url.is_empty() {
         return Redirect::to("/tracking").into_response();
@@ -244,3 +350,278 @@ pub async fn delete_rule(
         .await;
     Redirect::to("/tracking").into_response()
 }
+
+// ── Export ──
+
+/// Tracked messages loaded per database round trip while exporting.
+const EXPORT_BATCH: i64 = 200;
+
+const EXPORT_COLUMNS: [&str; 9] = [
+    "message_id",
+    "sender",
+    "recipient",
+    "subject",
+    "alias_source",
+    "created_at",
+    "open_count",
+    "first_opened_at",
+    "last_opened_at",
+];
+
+#[derive(Clone, Copy, PartialEq)]
+enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// One exported tracked message.  The CSV has one row per message and leaves
+/// out the individual opens.
+#[derive(Serialize)]
+struct ExportRecord {
+    message_id: String,
+    sender: String,
+    recipient: String,
+    subject: String,
+    alias_source: Option<String>,
+    created_at: String,
+    open_count: usize,
+    first_opened_at: Option<String>,
+    last_opened_at: Option<String>,
+    opens: Vec<PixelOpen>,
+}
+
+impl ExportRecord {
+    /// `opens` are newest first, as `get_opens_for_message` returns them.
+    fn new(message: TrackedMessage, alias_source: Option<String>, opens: Vec<PixelOpen>) -> Self {
+        ExportRecord {
+            message_id: message.message_id,
+            sender: message.sender,
+            recipient: message.recipient,
+            subject: message.subject,
+            alias_source,
+            created_at: message.created_at,
+            open_count: opens.len(),
+            first_opened_at: opens.last().map(|o| o.opened_at.clone()),
+            last_opened_at: opens.first().map(|o| o.opened_at.clone()),
+            opens,
+        }
+    }
+
+    fn csv(&self) -> String {
+        crate::csv_transfer::csv_record(&[
+            &self.message_id,
+            &self.sender,
+            &self.recipient,
+            &self.subject,
+            self.alias_source.as_deref().unwrap_or_default(),
+            &self.created_at,
+            &self.open_count.to_string(),
+            self.first_opened_at.as_deref().unwrap_or_default(),
+            self.last_opened_at.as_deref().unwrap_or_default(),
+        ])
+    }
+}
+
+/// `created_at` bounds for `list_tracked_messages_after` from the export
+/// query: `to` is inclusive, so it becomes the start of the next day.
+fn export_range(query: &TrackingExportQuery) -> Result<(String, String), String> {
+    let parse = |name: &str, value: &str| {
+        let value = value.trim();
+        if value.is_empty() {
+            return Ok(None);
+        }
+        chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+            .map(Some)
+            .map_err(|_| format!("'{}' must be a date such as 2026-01-31.", name))
+    };
+    let from = parse("from", &query.from)?;
+    let to = parse("to", &query.to)?;
+    if let (Some(from), Some(to)) = (from, to) {
+        if from > to {
+            return Err("'from' must not be after 'to'.".to_string());
+        }
+    }
+    Ok((
+        from.map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+        to.and_then(|d| d.succ_opt())
+            .map(|d| d.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+    ))
+}
+
+pub async fn export_csv(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Query(query): Query<TrackingExportQuery>,
+) -> Response {
+    info!("[web] GET /tracking/export.csv — exporting tracked messages");
+    export(&state, &query, ExportFormat::Csv)
+}
+
+pub async fn export_json(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Query(query): Query<TrackingExportQuery>,
+) -> Response {
+    info!("[web] GET /tracking/export.json — exporting tracked messages");
+    export(&state, &query, ExportFormat::Json)
+}
+
+/// Stream every tracked message in the requested range with its opens.  A
+/// database thread sends one batch at a time, so memory use does not grow
+/// with the size of the history.
+fn export(state: &AppState, query: &TrackingExportQuery, format: ExportFormat) -> Response {
+    let (from, to) = match export_range(query) {
+        Ok(range) => range,
+        Err(message) => {
+            warn!("[web] rejected tracking export: {}", message);
+            let tmpl = ErrorTemplate {
+                nav_active: "Tracking",
+                flash: None,
+                status_code: 400,
+                status_text: "Bad Request",
+                title: "Invalid Date Range",
+                message: &message,
+                back_url: "/tracking",
+                back_label: "Back to Tracking",
+            };
+            return (StatusCode::BAD_REQUEST, Html(tmpl.render().unwrap())).into_response();
+        }
+    };
+
+    let db = state.db.clone();
+    let (tx, rx) = tokio::sync::mpsc::channel::<Result<String, Infallible>>(4);
+    // A plain thread, like `AppState::blocking_db`, because the synchronous
+    // postgres client cannot run on tokio's blocking pool.
+    std::thread::spawn(move || {
+        let mut aliases: HashMap<i64, Option<String>> = HashMap::new();
+        let mut after_id = 0;
+        let mut count = 0;
+        let head = match format {
+            ExportFormat::Csv => crate::csv_transfer::csv_record(&EXPORT_COLUMNS),
+            ExportFormat::Json => "[".to_string(),
+        };
+        if tx.blocking_send(Ok(head)).is_err() {
+            return;
+        }
+        loop {
+            let batch = db.list_tracked_messages_after(after_id, &from, &to, EXPORT_BATCH);
+            let Some(last) = batch.last() else {
+                break;
+            };
+            after_id = last.id;
+            let mut chunk = String::new();
+            for message in batch {
+                let alias_source = message.alias_id.and_then(|id| {
+                    aliases
+                        .entry(id)
+                        .or_insert_with(|| db.get_alias(id).map(|a| a.source))
+                        .clone()
+                });
+                let opens = db.get_opens_for_message(&message.message_id);
+                let record = ExportRecord::new(message, alias_source, opens);
+                match format {
+                    ExportFormat::Csv => chunk.push_str(&record.csv()),
+                    ExportFormat::Json => {
+                        if count > 0 {
+                            chunk.push(',');
+                        }
+                        chunk.push('\n');
+                        chunk.push_str(&serde_json::to_string(&record).unwrap_or_default());
+                    }
+                }
+                count += 1;
+            }
+            if tx.blocking_send(Ok(chunk)).is_err() {
+                debug!("[web] tracking export cancelled by the client");
+                return;
+            }
+        }
+        if format == ExportFormat::Json {
+            let _ = tx.blocking_send(Ok("\n]\n".to_string()));
+        }
+        info!("[web] exported {} tracked message(s)", count);
+    });
+
+    let (content_type, filename) = match format {
+        ExportFormat::Csv => ("text/csv; charset=utf-8", "tracking.csv"),
+        ExportFormat::Json => ("application/json", "tracking.json"),
+    };
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{}\"", filename),
+            ),
+        ],
+        Body::from_stream(ReceiverStream::new(rx)),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(from: &str, to: &str) -> TrackingExportQuery {
+        TrackingExportQuery {
+            from: from.to_string(),
+            to: to.to_string(),
+        }
+    }
+
+    #[test]
+    fn export_range_includes_the_whole_last_day() {
+        assert_eq!(
+            export_range(&query("2026-01-01", "2026-01-31")),
+            Ok(("2026-01-01".to_string(), "2026-02-01".to_string()))
+        );
+        assert_eq!(
+            export_range(&query("", " ")),
+            Ok((String::new(), String::new()))
+        );
+        assert!(export_range(&query("2026-02-01", "2026-01-31")).is_err());
+        assert!(export_range(&query("01/02/2026", "")).is_err());
+    }
+
+    #[test]
+    fn export_record_summarises_opens() {
+        let message = TrackedMessage {
+            id: 1,
+            message_id: "abc".to_string(),
+            sender: "news@example.com".to_string(),
+            recipient: "bob@example.org".to_string(),
+            subject: "Hello, \"world\"".to_string(),
+            alias_id: Some(3),
+            created_at: "2026-01-01 09:00:00".to_string(),
+        };
+        let open = |at: &str| PixelOpen {
+            id: 0,
+            message_id: "abc".to_string(),
+            client_ip: "203.0.113.x".to_string(),
+            user_agent: "Mail/1.0".to_string(),
+            opened_at: at.to_string(),
+            country: None,
+            city: None,
+        };
+        let record = ExportRecord::new(
+            message,
+            Some("news@example.com".to_string()),
+            vec![open("2026-01-02 08:00:00"), open("2026-01-01 10:00:00")],
+        );
+        assert_eq!(
+            crate::csv_transfer::csv_record(&EXPORT_COLUMNS),
+            "message_id,sender,recipient,subject,alias_source,created_at,open_count,first_opened_at,last_opened_at\r\n"
+        );
+        assert_eq!(
+            record.csv(),
+            "abc,news@example.com,bob@example.org,\"Hello, \"\"world\"\"\",news@example.com,\
+             2026-01-01 09:00:00,2,2026-01-01 10:00:00,2026-01-02 08:00:00\r\n"
+        );
+        let json = serde_json::to_value(&record).unwrap();
+        assert_eq!(json["open_count"], 2);
+        assert_eq!(json["opens"].as_array().unwrap().len(), 2);
+    }
+}
@@ -12,7 +12,7 @@ use log::{debug, info, warn};
 use serde_json;
 
 use crate::web::auth::AuthAdmin;
-use crate::web::forms::{TrackingPatternForm, TrackingRuleForm, UnsubscribeQuery};
+use crate::web::forms::{TrackingPatternForm, TrackingRuleForm, UnsubscribeForm, UnsubscribeQuery};
 use crate::web::AppState;
 
 // ── Templates ──
@@ -33,9 +33,22 @@ struct ConfirmTemplate<'a> {
     token: &'a str,
     success: bool,
     requires_confirmation: bool,
+    can_resubscribe: bool,
     message: &'a str,
 }
 
+impl<'a> ConfirmTemplate<'a> {
+    fn error(token: &'a str, message: &'a str) -> Self {
+        ConfirmTemplate {
+            token,
+            success: false,
+            requires_confirmation: false,
+            can_resubscribe: false,
+            message,
+        }
+    }
+}
+
 // ── Public routes (no auth) ──
 
 pub fn public_routes() -> Router<AppState> {
@@ -45,19 +58,16 @@ pub fn public_routes() -> Router<AppState> {
     )
 }
 
-/// GET /unsubscribe?token=xxx — show a confirmation/status page
+/// GET /unsubscribe?token=xxx — show a confirmation/status page.  Nothing is
+/// recorded here, so link scanners and image proxies that follow the URL
+/// cannot unsubscribe anyone.
 async fn unsubscribe_confirm_page(
     State(state): State<AppState>,
     Query(params): Query<UnsubscribeQuery>,
 ) -> Response {
     debug!("[web] GET /unsubscribe token={}", params.token);
     if params.token.is_empty() {
-        let tmpl = ConfirmTemplate {
-            token: "",
-            success: false,
-            requires_confirmation: false,
-            message: "No unsubscribe token provided.",
-        };
+        let tmpl = ConfirmTemplate::error("", "No unsubscribe token provided.");
         return (StatusCode::BAD_REQUEST, Html(tmpl.render().unwrap())).into_response();
     }
     let token = params.token.clone();
@@ -71,8 +81,9 @@ async fn unsubscribe_confirm_page(
                 .await;
             let tmpl = ConfirmTemplate {
                 token: &params.token,
-                success: !already,
+                success: true,
                 requires_confirmation: !already,
+                can_resubscribe: already,
                 message: if already {
                     "You are already unsubscribed."
                 } else {
@@ -83,12 +94,8 @@ async fn unsubscribe_confirm_page(
         }
         None => {
             warn!("[web] unsubscribe token not found: {}", params.token);
-            let tmpl = ConfirmTemplate {
-                token: &params.token,
-                success: false,
-                requires_confirmation: false,
-                message: "Invalid or expired unsubscribe token.",
-            };
+            let tmpl =
+                ConfirmTemplate::error(&params.token, "Invalid or expired unsubscribe token.");
             (StatusCode::NOT_FOUND, Html(tmpl.render().unwrap())).into_response()
         }
     }
@@ -96,19 +103,20 @@ async fn unsubscribe_confirm_page(
 
 /// POST /unsubscribe?token=xxx — RFC 8058 one-click unsubscribe
 /// Accepts both mail-client automated POST (body: List-Unsubscribe=One-Click)
-/// and manual browser form submission.
+/// and manual browser form submission.  `action=resubscribe` from the
+/// confirmation page takes the address off the list again.
 async fn unsubscribe_one_click(
     State(state): State<AppState>,
     Query(params): Query<UnsubscribeQuery>,
+    form: Option<Form<UnsubscribeForm>>,
 ) -> Response {
-    info!("[web] POST /unsubscribe token={}", params.token);
+    let resubscribe = form.is_some_and(|Form(f)| f.action == "resubscribe");
+    info!(
+        "[web] POST /unsubscribe token={} resubscribe={}",
+        params.token, resubscribe
+    );
     if params.token.is_empty() {
-        let tmpl = ConfirmTemplate {
-            token: "",
-            success: false,
-            requires_confirmation: false,
-            message: "No unsubscribe token provided.",
-        };
+        let tmpl = ConfirmTemplate::error("", "No unsubscribe token provided.");
         return (StatusCode::BAD_REQUEST, Html(tmpl.render().unwrap())).into_response();
     }
     let token = params.token.clone();
@@ -116,6 +124,27 @@ async fn unsubscribe_one_click(
         .blocking_db(move |db| db.get_unsubscribe_by_token(&token))
         .await;
     match entry {
+        Some((email, domain)) if resubscribe => {
+            let email_for_db = email.clone();
+            let domain_for_db = domain.clone();
+            state
+                .blocking_db(move |db| {
+                    db.remove_unsubscribe_by_email(&email_for_db, &domain_for_db)
+                })
+                .await;
+            info!(
+                "[web] unsubscribe withdrawn: email={} domain={}",
+                email, domain
+            );
+            let tmpl = ConfirmTemplate {
+                token: &params.token,
+                success: true,
+                requires_confirmation: true,
+                can_resubscribe: false,
+                message: "You have been re-subscribed. You can unsubscribe again below.",
+            };
+            Html(tmpl.render().unwrap()).into_response()
+        }
         Some((email, domain)) => {
             let email_for_db = email.clone();
             let domain_for_db = domain.clone();
@@ -130,18 +159,15 @@ async fn unsubscribe_one_click(
                 token: &params.token,
                 success: true,
                 requires_confirmation: false,
+                can_resubscribe: true,
                 message: "You have been successfully unsubscribed.",
             };
             Html(tmpl.render().unwrap()).into_response()
         }
         None => {
             warn!("[web] unsubscribe token not found: {}", params.token);
-            let tmpl = ConfirmTemplate {
-                token: &params.token,
-                success: false,
-                requires_confirmation: false,
-                message: "Invalid or expired unsubscribe token.",
-            };
+            let tmpl =
+                ConfirmTemplate::error(&params.token, "Invalid or expired unsubscribe token.");
             (StatusCode::NOT_FOUND, Html(tmpl.render().unwrap())).into_response()
         }
     }
@@ -11,7 +11,7 @@ use quick_xml::events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event};
 use quick_xml::Writer;
 
 use crate::web::auth::AuthAdmin;
-use crate::web::forms::WebDavSettingsForm;
+use crate::web::forms::{WebDavQuotaForm, WebDavSettingsForm};
 use crate::web::AppState;
 
 fn webdav_dir() -> &'static str {
@@ -30,6 +30,112 @@ fn file_path(token: &str) -> std::path::PathBuf {
     std::path::Path::new(webdav_dir()).join(token)
 }
 
+// ── Storage limits ──
+//
+// The limits live in `settings`, in MB, where 0 means unlimited:
+// `webdav_max_file_size_mb` per file, `webdav_quota_mb` per user,
+// `webdav_quota_mb:<email>` overriding that for one user, and
+// `webdav_total_quota_mb` for all users together.
+
+const MB: i64 = 1024 * 1024;
+const QUOTA_OVERRIDE_PREFIX: &str = "webdav_quota_mb:";
+
+fn setting_mb(db: &crate::db::Database, key: &str, default: i64) -> i64 {
+    db.get_setting(key)
+        .and_then(|v| v.trim().parse::<i64>().ok())
+        .filter(|mb| *mb >= 0)
+        .unwrap_or(default)
+}
+
+/// The limits that apply to one user's uploads.
+#[derive(Clone, Copy, Debug)]
+struct WebDavLimits {
+    max_file_size_mb: i64,
+    user_quota_mb: i64,
+    total_quota_mb: i64,
+}
+
+impl WebDavLimits {
+    fn load(db: &crate::db::Database, owner: &str) -> Self {
+        let default_quota = setting_mb(db, "webdav_quota_mb", 0);
+        WebDavLimits {
+            max_file_size_mb: setting_mb(db, "webdav_max_file_size_mb", 50),
+            user_quota_mb: setting_mb(
+                db,
+                &format!("{}{}", QUOTA_OVERRIDE_PREFIX, owner.to_lowercase()),
+                default_quota,
+            ),
+            total_quota_mb: setting_mb(db, "webdav_total_quota_mb", 0),
+        }
+    }
+
+    fn max_file_size(&self) -> i64 {
+        self.max_file_size_mb * MB
+    }
+
+    /// Why storing `size` bytes in place of `replaced` bytes is refused,
+    /// given what the user and everyone together already store.
+    fn refuse(
+        &self,
+        size: i64,
+        replaced: i64,
+        user_usage: i64,
+        total_usage: i64,
+    ) -> Option<(StatusCode, &'static str)> {
+        if size > self.max_file_size() {
+            return Some((StatusCode::PAYLOAD_TOO_LARGE, "File exceeds maximum size"));
+        }
+        let growth = size - replaced;
+        if self.user_quota_mb > 0 && user_usage + growth > self.user_quota_mb * MB {
+            return Some((StatusCode::INSUFFICIENT_STORAGE, "Quota exceeded"));
+        }
+        if self.total_quota_mb > 0 && total_usage + growth > self.total_quota_mb * MB {
+            return Some((
+                StatusCode::INSUFFICIENT_STORAGE,
+                "Server storage quota exceeded",
+            ));
+        }
+        None
+    }
+}
+
+/// Everything an upload is checked against.
+struct UploadContext {
+    limits: WebDavLimits,
+    existing: Option<crate::db::WebDavFile>,
+    user_usage: i64,
+    total_usage: i64,
+    locks: Vec<crate::db::WebDavLock>,
+}
+
+impl UploadContext {
+    fn load(db: &crate::db::Database, owner: &str, filename: &str) -> Self {
+        UploadContext {
+            limits: WebDavLimits::load(db, owner),
+            existing: db.get_webdav_file_by_owner_and_name(owner, filename),
+            user_usage: db.count_webdav_usage_for_owner(owner),
+            total_usage: db.count_webdav_usage_total(),
+            locks: db.webdav_locks_on(owner, filename),
+        }
+    }
+
+    fn refuse(&self, size: i64) -> Option<Response> {
+        let replaced = self.existing.as_ref().map_or(0, |f| f.size);
+        self.limits
+            .refuse(size, replaced, self.user_usage, self.total_usage)
+            .map(|(status, message)| (status, message).into_response())
+    }
+}
+
+fn content_length(headers: &HeaderMap) -> Option<i64> {
+    headers
+        .get(header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
 // ── Templates ──
 
 #[derive(Template)]
@@ -38,38 +144,90 @@ struct ListTemplate<'a> {
     nav_active: &'a str,
     flash: Option<&'a str>,
     files: Vec<crate::db::WebDavFile>,
+    usage: Vec<UsageRow>,
+    total_used: String,
     webdav_enabled: bool,
     webdav_max_file_size_mb: i64,
     webdav_quota_mb: i64,
+    webdav_total_quota_mb: i64,
+}
+
+/// One user's storage on the admin page.
+struct UsageRow {
+    owner: String,
+    files: i64,
+    used: String,
+    /// The user's own quota in MB, if it overrides the default.
+    quota_override: Option<i64>,
+    quota_mb: i64,
+    /// Share of the quota in use, when there is one.
+    percent: Option<i64>,
 }
 
 // ── WebDAV storage directory ──
 
 pub async fn list(_auth: AuthAdmin, State(state): State<AppState>) -> Html<String> {
     info!("[web] GET /webdav — listing webdav files");
-    let files = state.blocking_db(|db| db.list_webdav_files()).await;
-    let webdav_enabled = state
-        .blocking_db(|db| db.get_setting("webdav_enabled"))
-        .await
-        .map(|v| v != "false")
-        .unwrap_or(true);
-    let webdav_max_file_size_mb = state
-        .blocking_db(|db| db.get_setting("webdav_max_file_size_mb"))
-        .await
-        .and_then(|v| v.parse::<i64>().ok())
-        .unwrap_or(50);
-    let webdav_quota_mb = state
-        .blocking_db(|db| db.get_setting("webdav_quota_mb"))
-        .await
-        .and_then(|v| v.parse::<i64>().ok())
-        .unwrap_or(0);
+    let (files, usage, overrides, enabled, max_size, quota, total_quota) = state
+        .blocking_db(|db| {
+            (
+                db.list_webdav_files(),
+                db.webdav_usage_by_owner(),
+                db.settings_with_prefix(QUOTA_OVERRIDE_PREFIX),
+                db.get_setting("webdav_enabled"),
+                setting_mb(db, "webdav_max_file_size_mb", 50),
+                setting_mb(db, "webdav_quota_mb", 0),
+                setting_mb(db, "webdav_total_quota_mb", 0),
+            )
+        })
+        .await;
+    let webdav_enabled = enabled.map(|v| v != "false").unwrap_or(true);
+    let overrides: std::collections::HashMap<String, i64> = overrides
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let owner = key.strip_prefix(QUOTA_OVERRIDE_PREFIX)?.to_string();
+            Some((owner, value.trim().parse().ok()?))
+        })
+        .collect();
+    let total: i64 = usage.iter().map(|u| u.bytes).sum();
+    let mut rows: Vec<UsageRow> = usage
+        .into_iter()
+        .map(|u| {
+            let quota_override = overrides.get(&u.owner).copied();
+            let quota_mb = quota_override.unwrap_or(quota);
+            UsageRow {
+                used: super::webmail::format_size(u.bytes as usize),
+                percent: (quota_mb > 0).then(|| u.bytes * 100 / (quota_mb * MB)),
+                owner: u.owner,
+                files: u.files,
+                quota_override,
+                quota_mb,
+            }
+        })
+        .collect();
+    // Users with their own quota are listed even before they store anything.
+    for (owner, quota_mb) in &overrides {
+        if !rows.iter().any(|r| &r.owner == owner) {
+            rows.push(UsageRow {
+                owner: owner.clone(),
+                files: 0,
+                used: super::webmail::format_size(0),
+                quota_override: Some(*quota_mb),
+                quota_mb: *quota_mb,
+                percent: (*quota_mb > 0).then_some(0),
+            });
+        }
+    }
     let tmpl = ListTemplate {
         nav_active: "WebDAV",
         flash: None,
         files,
+        usage: rows,
+        total_used: super::webmail::format_size(total as usize),
         webdav_enabled,
-        webdav_max_file_size_mb,
-        webdav_quota_mb,
+        webdav_max_file_size_mb: max_size,
+        webdav_quota_mb: quota,
+        webdav_total_quota_mb: total_quota,
     };
     Html(tmpl.render().unwrap())
 }
@@ -81,35 +239,72 @@ pub async fn update_settings(
 ) -> Response {
     info!("[web] POST /webdav/settings — updating webdav settings");
     let enabled = form.webdav_enabled.is_some();
-    let max_size = form.webdav_max_file_size_mb.unwrap_or(50);
-    let quota = form.webdav_quota_mb.unwrap_or(0);
+    let max_size = form.webdav_max_file_size_mb.unwrap_or(50).max(1);
+    let quota = form.webdav_quota_mb.unwrap_or(0).max(0);
+    let total_quota = form.webdav_total_quota_mb.unwrap_or(0).max(0);
 
     let enabled_val = if enabled { "true" } else { "false" }.to_string();
     let max_size_val = max_size.to_string();
     let quota_val = quota.to_string();
+    let total_quota_val = total_quota.to_string();
 
     state
         .blocking_db(move |db| {
             db.set_setting("webdav_enabled", &enabled_val);
             db.set_setting("webdav_max_file_size_mb", &max_size_val);
             db.set_setting("webdav_quota_mb", &quota_val);
+            db.set_setting("webdav_total_quota_mb", &total_quota_val);
         })
         .await;
 
     info!(
-        "[web] webdav settings updated: enabled={} max_size_mb={} quota_mb={}",
-        enabled, max_size, quota
+        "[web] webdav settings updated: enabled={} max_size_mb={} quota_mb={} total_quota_mb={}",
+        enabled, max_size, quota, total_quota
     );
     Redirect::to("/webdav").into_response()
 }
 
+/// Give one user their own quota, or return them to the default when the
+/// quota is left empty.
+pub async fn update_user_quota(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<WebDavQuotaForm>,
+) -> Response {
+    let owner = form.owner.trim().to_lowercase();
+    info!(
+        "[web] POST /webdav/quota — setting webdav quota for {}",
+        owner
+    );
+    if !owner.contains('@') {
+        return Redirect::to("/webdav").into_response();
+    }
+    let quota = form.quota_mb.trim().parse::<i64>().ok().map(|mb| mb.max(0));
+    let key = format!("{}{}", QUOTA_OVERRIDE_PREFIX, owner);
+    state
+        .blocking_db(move |db| match quota {
+            Some(mb) => db.set_setting(&key, &mb.to_string()),
+            None => db.delete_setting(&key),
+        })
+        .await;
+    Redirect::to("/webdav").into_response()
+}
+
 pub async fn admin_delete_file(
     _auth: AuthAdmin,
     State(state): State<AppState>,
     Path(id): Path<i64>,
 ) -> Response {
     warn!("[web] POST /webdav/{}/delete — admin deleting file", id);
-    let deleted = state.blocking_db(move |db| db.delete_webdav_file(id)).await;
+    let deleted = state
+        .blocking_db(move |db| {
+            let deleted = db.delete_webdav_file(id);
+            if let Some(f) = &deleted {
+                db.delete_webdav_locks_on(&f.owner, &f.filename);
+            }
+            deleted
+        })
+        .await;
     if let Some(f) = deleted {
         let path = file_path(&f.token);
         if let Err(e) = std::fs::remove_file(&path) {
@@ -168,7 +363,11 @@ fn require_webdav_auth(
 
 // ── WebDAV XML helpers ──
 
-fn propfind_xml_collection(href: &str, entries: &[crate::db::WebDavFile]) -> String {
+fn propfind_xml_collection(
+    href: &str,
+    entries: &[crate::db::WebDavFile],
+    locks: &[crate::db::WebDavLock],
+) -> String {
     let mut buf = Vec::new();
     let mut writer = Writer::new_with_indent(&mut buf, b' ', 2);
 
@@ -184,7 +383,12 @@ fn propfind_xml_collection(href: &str, entries: &[crate::db::WebDavFile]) -> Str
 
     for f in entries {
         let file_href = format!("{}{}", href, f.filename);
-        write_propfind_file_entry(&mut writer, &file_href, f);
+        let file_locks: Vec<crate::db::WebDavLock> = locks
+            .iter()
+            .filter(|l| l.filename == f.filename)
+            .cloned()
+            .collect();
+        write_propfind_file_entry(&mut writer, &file_href, f, &file_locks);
     }
 
     writer
@@ -194,7 +398,11 @@ fn propfind_xml_collection(href: &str, entries: &[crate::db::WebDavFile]) -> Str
     String::from_utf8(buf).unwrap_or_default()
 }
 
-fn propfind_xml_file(href: &str, file: &crate::db::WebDavFile) -> String {
+fn propfind_xml_file(
+    href: &str,
+    file: &crate::db::WebDavFile,
+    locks: &[crate::db::WebDavLock],
+) -> String {
     let mut buf = Vec::new();
     let mut writer = Writer::new_with_indent(&mut buf, b' ', 2);
 
@@ -206,7 +414,7 @@ fn propfind_xml_file(href: &str, file: &crate::db::WebDavFile) -> String {
     ms.push_attribute(("xmlns:D", "DAV:"));
     writer.write_event(Event::Start(ms)).ok();
 
-    write_propfind_file_entry(&mut writer, href, file);
+    write_propfind_file_entry(&mut writer, href, file, locks);
 
     writer
         .write_event(Event::End(BytesEnd::new("D:multistatus")))
@@ -267,6 +475,7 @@ fn write_propfind_file_entry<W: std::io::Write>(
     writer: &mut Writer<W>,
     href: &str,
     file: &crate::db::WebDavFile,
+    locks: &[crate::db::WebDavLock],
 ) {
     writer
         .write_event(Event::Start(BytesStart::new("D:response")))
@@ -326,6 +535,7 @@ fn write_propfind_file_entry<W: std::io::Write>(
     writer
         .write_event(Event::End(BytesEnd::new("D:getlastmodified")))
         .ok();
+    write_lock_properties(writer, href, locks).ok();
     writer
         .write_event(Event::End(BytesEnd::new("D:prop")))
         .ok();
@@ -346,6 +556,473 @@ fn write_propfind_file_entry<W: std::io::Write>(
         .ok();
 }
 
+// ── Locking (RFC 4918 class 2) ──
+//
+// Files can be locked exclusively or shared.  A locked file only accepts
+// PUT and DELETE from clients that name one of its lock tokens in the `If:`
+// header; FileLink cannot, so it cannot change locked files at all.
+
+/// Compliance classes in every `DAV` header.  Clients such as Finder and
+/// davfs2 only send LOCK when class 2 is advertised, some of them on PROPFIND.
+const DAV_CLASSES: &str = "1, 2";
+/// Timeout of a lock whose request names none.
+const DEFAULT_LOCK_TIMEOUT_SECS: i64 = 600;
+/// Longest timeout granted; clients refresh a lock to hold it longer.
+const MAX_LOCK_TIMEOUT_SECS: i64 = 3600;
+/// How often locks past their timeout are deleted.
+const LOCK_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+const LOCK_TOKEN_SCHEME: &str = "opaquelocktoken:";
+const LOCK_BODY_LIMIT: usize = 64 * 1024;
+
+/// Periodically delete locks whose timeout has passed.  Queries already
+/// ignore them; this keeps the table small.
+pub fn start_lock_sweeper(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LOCK_SWEEP_INTERVAL);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let purged = state
+                .blocking_db(|db| db.purge_expired_webdav_locks())
+                .await;
+            if purged > 0 {
+                info!("[webdav] removed {} expired lock(s)", purged);
+            }
+        }
+    });
+}
+
+/// The timeout granted for a `Timeout:` header such as `Second-600, Infinite`.
+fn lock_timeout(headers: &HeaderMap) -> i64 {
+    let Some(value) = headers.get("timeout").and_then(|v| v.to_str().ok()) else {
+        return DEFAULT_LOCK_TIMEOUT_SECS;
+    };
+    value
+        .split(',')
+        .map(str::trim)
+        .find_map(|t| {
+            if t.eq_ignore_ascii_case("infinite") {
+                Some(MAX_LOCK_TIMEOUT_SECS)
+            } else {
+                t.strip_prefix("Second-")?.parse::<i64>().ok()
+            }
+        })
+        .unwrap_or(DEFAULT_LOCK_TIMEOUT_SECS)
+        .clamp(1, MAX_LOCK_TIMEOUT_SECS)
+}
+
+/// Lock tokens named in an `If:` header, e.g. `(<opaquelocktoken:…>)`.
+/// Resource tags and entity tags are not checked.
+fn submitted_lock_tokens(headers: &HeaderMap) -> Vec<String> {
+    let Some(value) = headers.get("if").and_then(|v| v.to_str().ok()) else {
+        return Vec::new();
+    };
+    value
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(token, _)| token.trim())
+        .filter(|token| token.starts_with(LOCK_TOKEN_SCHEME))
+        .map(str::to_string)
+        .collect()
+}
+
+/// The token of a `Lock-Token:` header, without its angle brackets.
+fn lock_token_header(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get("lock-token")?.to_str().ok()?.trim();
+    let token = value
+        .strip_prefix('<')
+        .and_then(|v| v.strip_suffix('>'))
+        .unwrap_or(value);
+    Some(token.to_string()).filter(|t| !t.is_empty())
+}
+
+/// Why a change to a file holding `locks` is refused when the client
+/// submitted `submitted`: `423 Locked` when none of the file's tokens was
+/// submitted, `412 Precondition Failed` when the submitted tokens name no
+/// lock on the file.
+fn lock_refusal(locks: &[crate::db::WebDavLock], submitted: &[String]) -> Option<StatusCode> {
+    let holds_lock = submitted
+        .iter()
+        .any(|token| locks.iter().any(|lock| &lock.token == token));
+    if holds_lock {
+        None
+    } else if !locks.is_empty() {
+        Some(StatusCode::LOCKED)
+    } else if !submitted.is_empty() {
+        Some(StatusCode::PRECONDITION_FAILED)
+    } else {
+        None
+    }
+}
+
+fn refuse_locked(
+    locks: &[crate::db::WebDavLock],
+    submitted: &[String],
+    href: &str,
+) -> Option<Response> {
+    lock_refusal(locks, submitted).map(|status| {
+        if status == StatusCode::LOCKED {
+            dav_error(status, "lock-token-submitted", Some(href))
+        } else {
+            status.into_response()
+        }
+    })
+}
+
+/// A `DAV:error` body naming the precondition that failed.
+fn dav_error(status: StatusCode, condition: &str, href: Option<&str>) -> Response {
+    let mut buf = Vec::new();
+    let mut writer = Writer::new(&mut buf);
+    writer
+        .write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))
+        .ok();
+    writer
+        .create_element("D:error")
+        .with_attribute(("xmlns:D", "DAV:"))
+        .write_inner_content(|w| {
+            let element = w.create_element(format!("D:{}", condition));
+            match href {
+                Some(href) => element.write_inner_content(|w| {
+                    w.create_element("D:href")
+                        .write_text_content(BytesText::new(href))
+                        .map(|_| ())
+                })?,
+                None => element.write_empty()?,
+            };
+            Ok(())
+        })
+        .ok();
+    dav_xml_response(status, String::from_utf8(buf).unwrap_or_default())
+}
+
+fn dav_xml_response(status: StatusCode, xml: String) -> Response {
+    let mut resp = Response::new(Body::from(xml));
+    *resp.status_mut() = status;
+    resp.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/xml; charset=utf-8"),
+    );
+    resp
+}
+
+/// What a `LOCK` request body asks for.
+#[derive(Debug, PartialEq)]
+struct LockRequest {
+    exclusive: bool,
+    /// The `DAV:owner` content, ready to write back.
+    owner: String,
+}
+
+fn parse_lockinfo(body: &str) -> Option<LockRequest> {
+    use quick_xml::Reader;
+
+    let mut reader = Reader::from_reader(body.as_bytes());
+    let mut lockinfo = false;
+    let mut exclusive = None;
+    let mut in_owner = false;
+    let mut owner_href = false;
+    let mut owner_text = String::new();
+    let mut buf = Vec::new();
+    loop {
+        match reader.read_event_into(&mut buf) {
+            Ok(Event::Start(e)) | Ok(Event::Empty(e)) => match e.local_name().as_ref() {
+                b"lockinfo" => lockinfo = true,
+                b"exclusive" => exclusive = Some(true),
+                b"shared" => exclusive = Some(false),
+                b"owner" => in_owner = true,
+                b"href" if in_owner => owner_href = true,
+                _ => {}
+            },
+            Ok(Event::End(e)) if e.local_name().as_ref() == b"owner" => in_owner = false,
+            Ok(Event::Text(t)) if in_owner => {
+                owner_text.push_str(&String::from_utf8_lossy(t.as_ref()));
+            }
+            Ok(Event::GeneralRef(r)) if in_owner => {
+                if let Ok(Some(ch)) = r.resolve_char_ref() {
+                    owner_text.push(ch);
+                } else if let Some(entity) = r
+                    .decode()
+                    .ok()
+                    .and_then(|name| quick_xml::escape::resolve_predefined_entity(&name))
+                {
+                    owner_text.push_str(entity);
+                }
+            }
+            Ok(Event::Eof) => break,
+            Err(e) => {
+                warn!("[webdav] malformed lockinfo: {}", e);
+                return None;
+            }
+            _ => {}
+        }
+        buf.clear();
+    }
+    if !lockinfo {
+        return None;
+    }
+    let owner_text = quick_xml::escape::escape(owner_text.trim()).into_owned();
+    Some(LockRequest {
+        exclusive: exclusive?,
+        owner: if owner_href {
+            format!("<D:href>{}</D:href>", owner_text)
+        } else {
+            owner_text
+        },
+    })
+}
+
+fn write_activelock<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    lock: &crate::db::WebDavLock,
+    href: &str,
+) -> std::io::Result<()> {
+    writer
+        .create_element("D:activelock")
+        .write_inner_content(|w| {
+            w.create_element("D:locktype")
+                .write_inner_content(|w| w.create_element("D:write").write_empty().map(|_| ()))?;
+            w.create_element("D:lockscope").write_inner_content(|w| {
+                let scope = if lock.exclusive {
+                    "D:exclusive"
+                } else {
+                    "D:shared"
+                };
+                w.create_element(scope).write_empty().map(|_| ())
+            })?;
+            w.create_element("D:depth")
+                .write_text_content(BytesText::new("0"))?;
+            if !lock.lock_owner.is_empty() {
+                w.create_element("D:owner")
+                    .write_text_content(BytesText::from_escaped(lock.lock_owner.as_str()))?;
+            }
+            w.create_element("D:timeout")
+                .write_text_content(BytesText::new(&format!("Second-{}", lock.timeout_secs)))?;
+            w.create_element("D:locktoken").write_inner_content(|w| {
+                w.create_element("D:href")
+                    .write_text_content(BytesText::new(&lock.token))
+                    .map(|_| ())
+            })?;
+            w.create_element("D:lockroot").write_inner_content(|w| {
+                w.create_element("D:href")
+                    .write_text_content(BytesText::new(href))
+                    .map(|_| ())
+            })?;
+            Ok(())
+        })
+        .map(|_| ())
+}
+
+/// `DAV:supportedlock` and `DAV:lockdiscovery` of a file with `locks`.
+fn write_lock_properties<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    href: &str,
+    locks: &[crate::db::WebDavLock],
+) -> std::io::Result<()> {
+    writer
+        .create_element("D:supportedlock")
+        .write_inner_content(|w| {
+            for scope in ["D:exclusive", "D:shared"] {
+                w.create_element("D:lockentry").write_inner_content(|w| {
+                    w.create_element("D:lockscope").write_inner_content(|w| {
+                        w.create_element(scope).write_empty().map(|_| ())
+                    })?;
+                    w.create_element("D:locktype").write_inner_content(|w| {
+                        w.create_element("D:write").write_empty().map(|_| ())
+                    })?;
+                    Ok(())
+                })?;
+            }
+            Ok(())
+        })?;
+    writer
+        .create_element("D:lockdiscovery")
+        .write_inner_content(|w| {
+            for lock in locks {
+                write_activelock(w, lock, href)?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+/// The body answering a `LOCK`: the lock as `DAV:lockdiscovery`.
+fn lockdiscovery_xml(lock: &crate::db::WebDavLock, href: &str) -> String {
+    let mut buf = Vec::new();
+    let mut writer = Writer::new_with_indent(&mut buf, b' ', 2);
+    writer
+        .write_event(Event::Decl(BytesDecl::new("1.0", Some("utf-8"), None)))
+        .ok();
+    writer
+        .create_element("D:prop")
+        .with_attribute(("xmlns:D", "DAV:"))
+        .write_inner_content(|w| {
+            w.create_element("D:lockdiscovery")
+                .write_inner_content(|w| write_activelock(w, lock, href))
+                .map(|_| ())
+        })
+        .ok();
+    String::from_utf8(buf).unwrap_or_default()
+}
+
+/// Create `filename` empty unless it exists; whether it was created.
+async fn create_empty_file(
+    state: &AppState,
+    account_id: i64,
+    owner: &str,
+    filename: &str,
+) -> Result<bool, String> {
+    let owner_c = owner.to_string();
+    let fname_c = filename.to_string();
+    let exists = state
+        .blocking_db(move |db| db.get_webdav_file_by_owner_and_name(&owner_c, &fname_c))
+        .await
+        .is_some();
+    if exists {
+        return Ok(false);
+    }
+    ensure_webdav_dir().map_err(|e| e.to_string())?;
+    let token = uuid::Uuid::new_v4().to_string();
+    let fpath = file_path(&token);
+    std::fs::write(&fpath, b"").map_err(|e| e.to_string())?;
+    let owner_c = owner.to_string();
+    let fname_c = filename.to_string();
+    state
+        .blocking_db(move |db| {
+            db.upsert_webdav_file(
+                Some(account_id),
+                &owner_c,
+                &fname_c,
+                "application/octet-stream",
+                0,
+                &token,
+            )
+        })
+        .await
+        .inspect_err(|_| {
+            let _ = std::fs::remove_file(&fpath);
+        })?;
+    Ok(true)
+}
+
+/// `LOCK` a file: take a new lock, or refresh one named in `If:` when the
+/// body is empty.  Locking a name that is not in use creates an empty file.
+async fn lock_file(
+    state: &AppState,
+    account_id: i64,
+    owner: &str,
+    filename: &str,
+    headers: &HeaderMap,
+    request: axum::extract::Request,
+) -> Response {
+    let href = format!("/dav/{}/{}", owner, filename);
+    let timeout_secs = lock_timeout(headers);
+    let body = match axum::body::to_bytes(request.into_body(), LOCK_BODY_LIMIT).await {
+        Ok(b) => String::from_utf8_lossy(&b).into_owned(),
+        Err(_) => return (StatusCode::PAYLOAD_TOO_LARGE, "Lock request too large").into_response(),
+    };
+    let owner_c = owner.to_lowercase();
+    let fname_c = filename.to_string();
+
+    if body.trim().is_empty() {
+        let submitted = submitted_lock_tokens(headers);
+        let refreshed = state
+            .blocking_db(move |db| {
+                submitted.iter().find_map(|token| {
+                    db.refresh_webdav_lock(&owner_c, &fname_c, token, timeout_secs)
+                })
+            })
+            .await;
+        return match refreshed {
+            Some(lock) => {
+                info!("[webdav] LOCK {} refreshed {}", href, lock.token);
+                dav_xml_response(StatusCode::OK, lockdiscovery_xml(&lock, &href))
+            }
+            None => StatusCode::PRECONDITION_FAILED.into_response(),
+        };
+    }
+
+    let Some(lock_request) = parse_lockinfo(&body) else {
+        return (StatusCode::BAD_REQUEST, "Malformed lockinfo").into_response();
+    };
+    let lock = crate::db::WebDavLock {
+        token: format!("{}{}", LOCK_TOKEN_SCHEME, uuid::Uuid::new_v4()),
+        owner: owner_c,
+        filename: fname_c,
+        exclusive: lock_request.exclusive,
+        lock_owner: lock_request.owner,
+        timeout_secs,
+        expires_at: String::new(),
+    };
+    let lock = match state
+        .blocking_db(move |db| db.create_webdav_lock(&lock))
+        .await
+    {
+        Ok(Some(lock)) => lock,
+        Ok(None) => return dav_error(StatusCode::LOCKED, "no-conflicting-lock", Some(&href)),
+        Err(e) => {
+            error!("[webdav] failed to lock {}: {}", href, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let created = match create_empty_file(state, account_id, owner, filename).await {
+        Ok(created) => created,
+        Err(e) => {
+            error!("[webdav] failed to create locked file {}: {}", href, e);
+            let (owner_c, fname_c, token) = (
+                lock.owner.clone(),
+                lock.filename.clone(),
+                lock.token.clone(),
+            );
+            state
+                .blocking_db(move |db| db.delete_webdav_lock(&owner_c, &fname_c, &token))
+                .await;
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    info!(
+        "[webdav] LOCK {} {} timeout={}s",
+        href, lock.token, lock.timeout_secs
+    );
+    let status = if created {
+        StatusCode::CREATED
+    } else {
+        StatusCode::OK
+    };
+    let mut resp = dav_xml_response(status, lockdiscovery_xml(&lock, &href));
+    if let Ok(value) = HeaderValue::from_str(&format!("<{}>", lock.token)) {
+        resp.headers_mut()
+            .insert(HeaderName::from_static("lock-token"), value);
+    }
+    resp
+}
+
+/// `UNLOCK` a file with the lock named in `Lock-Token:`.
+async fn unlock_file(
+    state: &AppState,
+    owner: &str,
+    filename: &str,
+    headers: &HeaderMap,
+) -> Response {
+    let href = format!("/dav/{}/{}", owner, filename);
+    let Some(token) = lock_token_header(headers) else {
+        return (StatusCode::BAD_REQUEST, "Missing Lock-Token header").into_response();
+    };
+    let owner_c = owner.to_string();
+    let fname_c = filename.to_string();
+    let token_c = token.clone();
+    let released = state
+        .blocking_db(move |db| db.delete_webdav_lock(&owner_c, &fname_c, &token_c))
+        .await;
+    if released {
+        info!("[webdav] UNLOCK {} {}", href, token);
+        StatusCode::NO_CONTENT.into_response()
+    } else {
+        dav_error(StatusCode::CONFLICT, "lock-token-matches-request-uri", None)
+    }
+}
+
 // ── WebDAV endpoint ──
 //
 // All requests to /dav/{*path} land here.
@@ -376,12 +1053,12 @@ pub async fn dav_handler(
         resp.headers_mut().insert(
             header::ALLOW,
             HeaderValue::from_static(
-                "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL",
+                "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL, LOCK, UNLOCK",
             ),
         );
         resp.headers_mut().insert(
             HeaderName::from_static("dav"),
-            HeaderValue::from_static("1"),
+            HeaderValue::from_static(DAV_CLASSES),
         );
         resp.headers_mut()
             .insert(header::CONTENT_LENGTH, HeaderValue::from_static("0"));
@@ -424,11 +1101,16 @@ pub async fn dav_handler(
         // PROPFIND on collection (directory listing)
         ("PROPFIND", None) => {
             let owner_c = owner.clone();
-            let files = state
-                .blocking_db(move |db| db.list_webdav_files_for_owner(&owner_c))
+            let (files, locks) = state
+                .blocking_db(move |db| {
+                    (
+                        db.list_webdav_files_for_owner(&owner_c),
+                        db.webdav_locks_for_owner(&owner_c),
+                    )
+                })
                 .await;
             let href = format!("/dav/{}/", owner);
-            let xml = propfind_xml_collection(&href, &files);
+            let xml = propfind_xml_collection(&href, &files, &locks);
             let mut resp = Response::new(Body::from(xml));
             *resp.status_mut() = StatusCode::MULTI_STATUS;
             resp.headers_mut().insert(
@@ -437,7 +1119,7 @@ pub async fn dav_handler(
             );
             resp.headers_mut().insert(
                 HeaderName::from_static("dav"),
-                HeaderValue::from_static("1"),
+                HeaderValue::from_static(DAV_CLASSES),
             );
             resp
         }
@@ -446,13 +1128,18 @@ pub async fn dav_handler(
         ("PROPFIND", Some(fname)) => {
             let fname = fname.clone();
             let owner_c = owner.clone();
-            let file = state
-                .blocking_db(move |db| db.get_webdav_file_by_owner_and_name(&owner_c, &fname))
+            let (file, locks) = state
+                .blocking_db(move |db| {
+                    (
+                        db.get_webdav_file_by_owner_and_name(&owner_c, &fname),
+                        db.webdav_locks_on(&owner_c, &fname),
+                    )
+                })
                 .await;
             match file {
                 Some(f) => {
                     let href = format!("/dav/{}/{}", owner, f.filename);
-                    let xml = propfind_xml_file(&href, &f);
+                    let xml = propfind_xml_file(&href, &f, &locks);
                     let mut resp = Response::new(Body::from(xml));
                     *resp.status_mut() = StatusCode::MULTI_STATUS;
                     resp.headers_mut().insert(
@@ -461,7 +1148,7 @@ pub async fn dav_handler(
                     );
                     resp.headers_mut().insert(
                         HeaderName::from_static("dav"),
-                        HeaderValue::from_static("1"),
+                        HeaderValue::from_static(DAV_CLASSES),
                     );
                     resp
                 }
@@ -485,20 +1172,27 @@ pub async fn dav_handler(
         // PUT — upload file
         ("PUT", Some(fname)) => {
             let fname = fname.clone();
-            let max_size_mb = state
-                .blocking_db(|db| db.get_setting("webdav_max_file_size_mb"))
-                .await
-                .and_then(|v| v.parse::<i64>().ok())
-                .unwrap_or(50);
-            let quota_mb = state
-                .blocking_db(|db| db.get_setting("webdav_quota_mb"))
-                .await
-                .and_then(|v| v.parse::<i64>().ok())
-                .unwrap_or(0);
+            let owner_c = owner.clone();
+            let fname_c = fname.clone();
+            let upload = state
+                .blocking_db(move |db| UploadContext::load(db, &owner_c, &fname_c))
+                .await;
+
+            let href = format!("/dav/{}/{}", owner, fname);
+            if let Some(resp) =
+                refuse_locked(&upload.locks, &submitted_lock_tokens(&headers), &href)
+            {
+                return resp;
+            }
+
+            // Refuse before reading the body when the client announced its size.
+            if let Some(resp) = content_length(&headers).and_then(|len| upload.refuse(len)) {
+                return resp;
+            }
 
             let body_bytes = match axum::body::to_bytes(
                 request.into_body(),
-                (max_size_mb * 1024 * 1024) as usize + 1,
+                upload.limits.max_file_size() as usize + 1,
             )
             .await
             {
@@ -508,20 +1202,8 @@ pub async fn dav_handler(
                 }
             };
 
-            if body_bytes.len() as i64 > max_size_mb * 1024 * 1024 {
-                return (StatusCode::PAYLOAD_TOO_LARGE, "File exceeds maximum size")
-                    .into_response();
-            }
-
-            // Check quota
-            if quota_mb > 0 {
-                let owner_q = owner.clone();
-                let current_usage = state
-                    .blocking_db(move |db| db.count_webdav_usage_for_owner(&owner_q))
-                    .await;
-                if current_usage + body_bytes.len() as i64 > quota_mb * 1024 * 1024 {
-                    return (StatusCode::INSUFFICIENT_STORAGE, "Quota exceeded").into_response();
-                }
+            if let Some(resp) = upload.refuse(body_bytes.len() as i64) {
+                return resp;
             }
 
             let content_type = headers
@@ -536,11 +1218,7 @@ pub async fn dav_handler(
             }
 
             // Reuse existing token if file already exists
-            let owner_c = owner.clone();
-            let fname_c = fname.clone();
-            let existing = state
-                .blocking_db(move |db| db.get_webdav_file_by_owner_and_name(&owner_c, &fname_c))
-                .await;
+            let existing = upload.existing;
             let token = existing
                 .as_ref()
                 .map(|f| f.token.clone())
@@ -593,11 +1271,22 @@ pub async fn dav_handler(
         ("DELETE", Some(fname)) => {
             let fname = fname.clone();
             let owner_c = owner.clone();
+            let fname_c = fname.clone();
+            let locks = state
+                .blocking_db(move |db| db.webdav_locks_on(&owner_c, &fname_c))
+                .await;
+            let href = format!("/dav/{}/{}", owner, fname);
+            if let Some(resp) = refuse_locked(&locks, &submitted_lock_tokens(&headers), &href) {
+                return resp;
+            }
+            let owner_c = owner.clone();
             let fname_log = fname.clone();
             let owner_log = owner.clone();
             let deleted = state
                 .blocking_db(move |db| {
-                    db.delete_webdav_file_by_owner_and_name(&owner_c, &fname)
+                    let deleted = db.delete_webdav_file_by_owner_and_name(&owner_c, &fname);
+                    db.delete_webdav_locks_on(&owner_c, &fname);
+                    deleted
                 })
                 .await;
             match deleted {
@@ -616,6 +1305,12 @@ pub async fn dav_handler(
         // MKCOL on collection root — collection auto-exists per authenticated user
         ("MKCOL", None) => StatusCode::CREATED.into_response(),
 
+        // LOCK/UNLOCK — only files can be locked
+        ("LOCK", Some(fname)) => {
+            lock_file(&state, account_id, &owner, fname, &headers, request).await
+        }
+        ("UNLOCK", Some(fname)) => unlock_file(&state, &owner, fname, &headers).await,
+
         _ => StatusCode::METHOD_NOT_ALLOWED.into_response(),
     }
 }
@@ -676,18 +1371,6 @@ pub async fn filelink_upload(
         Err(resp) => return resp,
     };
 
-    let max_size_mb = state
-        .blocking_db(|db| db.get_setting("webdav_max_file_size_mb"))
-        .await
-        .and_then(|v| v.parse::<i64>().ok())
-        .unwrap_or(50);
-
-    let quota_mb = state
-        .blocking_db(|db| db.get_setting("webdav_quota_mb"))
-        .await
-        .and_then(|v| v.parse::<i64>().ok())
-        .unwrap_or(0);
-
     while let Ok(Some(field)) = multipart.next_field().await {
         let field_name = field.name().unwrap_or("").to_string();
         if field_name != "file" && field_name != "attachment" {
@@ -708,18 +1391,17 @@ pub async fn filelink_upload(
             }
         };
 
-        if data.len() as i64 > max_size_mb * 1024 * 1024 {
-            return (StatusCode::PAYLOAD_TOO_LARGE, "File exceeds maximum size").into_response();
+        let owner_c = owner.clone();
+        let fname_c = original_filename.clone();
+        let upload = state
+            .blocking_db(move |db| UploadContext::load(db, &owner_c, &fname_c))
+            .await;
+        let href = format!("/dav/{}/{}", owner, original_filename);
+        if let Some(resp) = refuse_locked(&upload.locks, &[], &href) {
+            return resp;
         }
-
-        if quota_mb > 0 {
-            let owner_q = owner.clone();
-            let current_usage = state
-                .blocking_db(move |db| db.count_webdav_usage_for_owner(&owner_q))
-                .await;
-            if current_usage + data.len() as i64 > quota_mb * 1024 * 1024 {
-                return (StatusCode::INSUFFICIENT_STORAGE, "Quota exceeded").into_response();
-            }
+        if let Some(resp) = upload.refuse(data.len() as i64) {
+            return resp;
         }
 
         if let Err(e) = ensure_webdav_dir() {
@@ -727,12 +1409,8 @@ pub async fn filelink_upload(
             return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
 
-        let owner_c = owner.clone();
-        let fname_c = original_filename.clone();
-        let existing = state
-            .blocking_db(move |db| db.get_webdav_file_by_owner_and_name(&owner_c, &fname_c))
-            .await;
-        let token = existing
+        let token = upload
+            .existing
             .as_ref()
             .map(|f| f.token.clone())
             .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
@@ -821,9 +1499,23 @@ pub async fn filelink_delete(
 
     match file {
         Some(f) if f.owner.to_lowercase() == owner.to_lowercase() => {
+            let (owner_c, fname_c) = (f.owner.clone(), f.filename.clone());
+            let locks = state
+                .blocking_db(move |db| db.webdav_locks_on(&owner_c, &fname_c))
+                .await;
+            let href = format!("/dav/{}/{}", f.owner, f.filename);
+            if let Some(resp) = refuse_locked(&locks, &[], &href) {
+                return resp;
+            }
             let fid = f.id;
             let deleted = state
-                .blocking_db(move |db| db.delete_webdav_file(fid))
+                .blocking_db(move |db| {
+                    let deleted = db.delete_webdav_file(fid);
+                    if let Some(df) = &deleted {
+                        db.delete_webdav_locks_on(&df.owner, &df.filename);
+                    }
+                    deleted
+                })
                 .await;
             if let Some(df) = deleted {
                 let fpath = file_path(&df.token);
@@ -848,3 +1540,131 @@ pub fn public_routes() -> axum::Router<AppState> {
         .route("/filelink/download/{token}", get(filelink_download))
         .route("/filelink/delete/{token}", delete(filelink_delete))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uploads_are_held_to_size_and_quotas() {
+        let limits = WebDavLimits {
+            max_file_size_mb: 2,
+            user_quota_mb: 5,
+            total_quota_mb: 8,
+        };
+        let refused = |size, replaced, user, total| {
+            limits
+                .refuse(size, replaced, user, total)
+                .map(|(status, _)| status)
+        };
+        assert_eq!(refused(2 * MB, 0, 0, 0), None);
+        assert_eq!(
+            refused(2 * MB + 1, 0, 0, 0),
+            Some(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        assert_eq!(refused(MB, 0, 4 * MB, 4 * MB), None);
+        assert_eq!(
+            refused(MB + 1, 0, 4 * MB, 4 * MB),
+            Some(StatusCode::INSUFFICIENT_STORAGE)
+        );
+        // Replacing a file only counts what it grows by.
+        assert_eq!(refused(2 * MB, 2 * MB, 5 * MB, 5 * MB), None);
+        assert_eq!(
+            refused(MB, 0, 0, 8 * MB),
+            Some(StatusCode::INSUFFICIENT_STORAGE)
+        );
+
+        let unlimited = WebDavLimits {
+            user_quota_mb: 0,
+            total_quota_mb: 0,
+            ..limits
+        };
+        assert_eq!(unlimited.refuse(MB, 0, i64::MAX / 2, i64::MAX / 2), None);
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    fn lock(token: &str, exclusive: bool) -> crate::db::WebDavLock {
+        crate::db::WebDavLock {
+            token: token.to_string(),
+            owner: "jane@example.com".to_string(),
+            filename: "report.docx".to_string(),
+            exclusive,
+            lock_owner: String::new(),
+            timeout_secs: 600,
+            expires_at: String::new(),
+        }
+    }
+
+    #[test]
+    fn lock_requests_are_parsed() {
+        assert_eq!(lock_timeout(&HeaderMap::new()), DEFAULT_LOCK_TIMEOUT_SECS);
+        assert_eq!(lock_timeout(&headers(&[("timeout", "Second-120")])), 120);
+        assert_eq!(
+            lock_timeout(&headers(&[("timeout", "Infinite, Second-4100000000")])),
+            MAX_LOCK_TIMEOUT_SECS
+        );
+        assert_eq!(
+            submitted_lock_tokens(&headers(&[(
+                "if",
+                "<http://host/dav/jane@example.com/a> (<opaquelocktoken:1> [\"etag\"]) (<opaquelocktoken:2>)"
+            )])),
+            vec!["opaquelocktoken:1", "opaquelocktoken:2"]
+        );
+        assert_eq!(
+            lock_token_header(&headers(&[("lock-token", "<opaquelocktoken:1>")])).as_deref(),
+            Some("opaquelocktoken:1")
+        );
+
+        let body = r#"<?xml version="1.0" encoding="utf-8"?>
+            <D:lockinfo xmlns:D="DAV:">
+              <D:lockscope><D:exclusive/></D:lockscope>
+              <D:locktype><D:write/></D:locktype>
+              <D:owner><D:href>mailto:jane@example.com</D:href></D:owner>
+            </D:lockinfo>"#;
+        assert_eq!(
+            parse_lockinfo(body),
+            Some(LockRequest {
+                exclusive: true,
+                owner: "<D:href>mailto:jane@example.com</D:href>".to_string(),
+            })
+        );
+        let shared = r#"<lockinfo xmlns="DAV:"><lockscope><shared/></lockscope>
+            <locktype><write/></locktype><owner>Jane &lt;Office&gt;</owner></lockinfo>"#;
+        assert_eq!(
+            parse_lockinfo(shared),
+            Some(LockRequest {
+                exclusive: false,
+                owner: "Jane &lt;Office&gt;".to_string(),
+            })
+        );
+        assert_eq!(parse_lockinfo("<D:propfind xmlns:D=\"DAV:\"/>"), None);
+    }
+
+    #[test]
+    fn locked_files_need_one_of_their_tokens() {
+        let locks = [lock("opaquelocktoken:1", true)];
+        let token = |t: &str| vec![t.to_string()];
+        assert_eq!(lock_refusal(&[], &[]), None);
+        assert_eq!(lock_refusal(&locks, &[]), Some(StatusCode::LOCKED));
+        assert_eq!(
+            lock_refusal(&locks, &token("opaquelocktoken:2")),
+            Some(StatusCode::LOCKED)
+        );
+        assert_eq!(lock_refusal(&locks, &token("opaquelocktoken:1")), None);
+        assert_eq!(
+            lock_refusal(&[], &token("opaquelocktoken:1")),
+            Some(StatusCode::PRECONDITION_FAILED)
+        );
+
+        assert!(lock("a", true).conflicts_with(&lock("b", false)));
+        assert!(lock("a", false).conflicts_with(&lock("b", true)));
+        assert!(!lock("a", false).conflicts_with(&lock("b", false)));
+    }
+}
@@ -7,10 +7,10 @@ use axum::{
 use log::{debug, info, warn};
 use serde::Deserialize;
 
-use crate::db::WebhookLog;
+use crate::db::{QueuedWebhook, WebhookLog};
 use crate::web::auth::AuthAdmin;
 use crate::web::forms::WebhookSettingsForm;
-use crate::web::AppState;
+use crate::web::{post_json_webhook, AppState, WebhookSignature};
 
 const PAGE_SIZE: i64 = 50;
 
@@ -48,6 +48,9 @@ struct ListTemplate<'a> {
     nav_active: &'a str,
     flash: Option<&'a str>,
     webhook_url: String,
+    webhook_secret: String,
+    queue: Vec<QueuedWebhook>,
+    max_attempts: i32,
     logs: Vec<WebhookLogRow>,
     page: i64,
     total_pages: i64,
@@ -77,11 +80,16 @@ pub async fn list(
     let page = params.page.max(1);
     info!("[web] GET /webhooks — page={}", page);
 
-    let webhook_url = state
-        .blocking_db(|db| db.get_setting("webhook_url"))
-        .await
-        .unwrap_or_default();
+    let (webhook_url, webhook_secret) = state
+        .blocking_db(|db| {
+            (
+                db.get_setting("webhook_url").unwrap_or_default(),
+                db.get_setting("webhook_secret").unwrap_or_default(),
+            )
+        })
+        .await;
 
+    let queue = state.blocking_db(|db| db.list_queued_webhooks()).await;
     let total_count = state.blocking_db(|db| db.count_webhook_logs()).await;
     let total_pages = ((total_count as f64) / (PAGE_SIZE as f64)).ceil() as i64;
     let total_pages = total_pages.max(1);
@@ -125,6 +133,9 @@ pub async fn list(
         nav_active: "Webhooks",
         flash: None,
         webhook_url,
+        webhook_secret,
+        queue,
+        max_attempts: crate::webhook_queue::MAX_ATTEMPTS,
         logs,
         page,
         total_pages,
@@ -139,7 +150,7 @@ pub async fn update_webhook(
     Form(form): Form<WebhookSettingsForm>,
 ) -> Response {
     info!(
-        "[web] POST /webhooks/settings — update webhook URL by username={}",
+        "[web] POST /webhooks/settings — update webhook settings by username={}",
         auth.admin.username
     );
     let url = form.webhook_url.trim().to_string();
@@ -158,17 +169,24 @@ pub async fn update_webhook(
         return Html(tmpl.render().unwrap()).into_response();
     }
     let url_for_db = url.clone();
+    let secret = form.webhook_secret.trim().to_string();
     state
-        .blocking_db(move |db| db.set_setting("webhook_url", &url_for_db))
+        .blocking_db(move |db| {
+            db.set_setting("webhook_url", &url_for_db);
+            db.set_setting("webhook_secret", &secret);
+        })
         .await;
-    info!("[web] webhook_url updated by user={}", auth.admin.username);
+    info!(
+        "[web] webhook_url and webhook_secret updated by user={}",
+        auth.admin.username
+    );
     let tmpl = ErrorTemplate {
         nav_active: "Webhooks",
         flash: None,
         status_code: 200,
         status_text: "OK",
         title: "Success",
-        message: "Webhook URL updated successfully.",
+        message: "Webhook settings updated successfully.",
         back_url: "/webhooks",
         back_label: "Back to Webhooks",
     };
@@ -181,10 +199,14 @@ pub async fn test_webhook(auth: AuthAdmin, State(state): State<AppState>) -> Res
         auth.admin.username
     );
 
-    let webhook_url = state
-        .blocking_db(|db| db.get_setting("webhook_url"))
-        .await
-        .unwrap_or_default();
+    let (webhook_url, webhook_secret) = state
+        .blocking_db(|db| {
+            (
+                db.get_setting("webhook_url").unwrap_or_default(),
+                db.get_setting("webhook_secret").unwrap_or_default(),
+            )
+        })
+        .await;
 
     if webhook_url.is_empty() {
         let tmpl = ErrorTemplate {
@@ -216,45 +238,33 @@ pub async fn test_webhook(auth: AuthAdmin, State(state): State<AppState>) -> Res
         "modified": false,
     });
     let request_body = payload.to_string();
+    let signature = WebhookSignature::sign(&webhook_secret, &request_body);
+    let signature_note = match &signature {
+        Some(sig) => format!(
+            " Signed with X-Webhook-Timestamp: {} and X-Webhook-Signature: {} over the body {}",
+            sig.timestamp, sig.signature, request_body
+        ),
+        None => " Not signed: no webhook secret is set.".to_string(),
+    };
 
-    let start = std::time::Instant::now();
-    let result = reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build()
-        .map_err(|e| e.to_string())
-        .and_then(|client| {
-            client
-                .post(&webhook_url)
-                .json(&payload)
-                .send()
-                .map_err(|e| e.to_string())
+    // The blocking HTTP client must not run on an async worker thread.
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    {
+        let url = webhook_url.clone();
+        let body = request_body.clone();
+        std::thread::spawn(move || {
+            let start = std::time::Instant::now();
+            let result = post_json_webhook(&url, &body, signature.as_ref(), "test");
+            let _ = tx.send((result, start.elapsed().as_millis() as i64));
+        });
+    }
+    let ((response_status, response_body, error_msg), duration_ms) =
+        rx.await.unwrap_or_else(|_| {
+            (
+                (None, String::new(), "webhook thread panicked".to_string()),
+                0,
+            )
         });
-    let duration_ms = start.elapsed().as_millis() as i64;
-
-    let (response_status, response_body, error_msg) = match result {
-        Ok(resp) => {
-            let status = resp.status().as_u16() as i32;
-            let body = resp.text().unwrap_or_default();
-            let body_truncated = if body.len() > 2048 {
-                let mut end = 2048;
-                while !body.is_char_boundary(end) {
-                    end -= 1;
-                }
-                body[..end].to_string()
-            } else {
-                body
-            };
-            info!(
-                "[web] test webhook delivered to {} status={}",
-                webhook_url, status
-            );
-            (Some(status), body_truncated, String::new())
-        }
-        Err(e) => {
-            warn!("[web] test webhook failed to {}: {}", webhook_url, e);
-            (None, String::new(), e.clone())
-        }
-    };
 
     // Log the test execution to the database
     let url_clone = webhook_url.clone();
@@ -278,10 +288,11 @@ pub async fn test_webhook(auth: AuthAdmin, State(state): State<AppState>) -> Res
 
     if error_msg.is_empty() {
         let msg = format!(
-            "Test webhook delivered to {} — HTTP {} in {} ms.",
+            "Test webhook delivered to {} — HTTP {} in {} ms.{}",
             webhook_url,
             response_status.unwrap_or(0),
-            duration_ms
+            duration_ms,
+            signature_note
         );
         let tmpl = ErrorTemplate {
             nav_active: "Webhooks",
@@ -296,8 +307,8 @@ pub async fn test_webhook(auth: AuthAdmin, State(state): State<AppState>) -> Res
         Html(tmpl.render().unwrap()).into_response()
     } else {
         let msg = format!(
-            "Webhook test to {} failed after {} ms: {}",
-            webhook_url, duration_ms, error_msg
+            "Webhook test to {} failed after {} ms: {}.{}",
+            webhook_url, duration_ms, error_msg, signature_note
         );
         let tmpl = ErrorTemplate {
             nav_active: "Webhooks",
@@ -313,7 +324,8 @@ pub async fn test_webhook(auth: AuthAdmin, State(state): State<AppState>) -> Res
     }
 }
 
-/// Retry a previously logged webhook call using the same URL and request body.
+/// Queue a previously logged webhook call again with the same URL and request
+/// body; the delivery worker sends it and retries it like a new event.
 pub async fn retry_webhook(
     auth: AuthAdmin,
     State(state): State<AppState>,
@@ -357,7 +369,7 @@ pub async fn retry_webhook(
         return Html(tmpl.render().unwrap()).into_response();
     }
 
-    // Re-send the original request body, or fall back to an empty JSON object.
+    // Re-queue the original request body, or fall back to an empty JSON object.
     let request_body = if entry.request_body.is_empty() {
         "{}".to_string()
     } else {
@@ -365,102 +377,54 @@ pub async fn retry_webhook(
     };
 
     let url = entry.url.clone();
-    let start = std::time::Instant::now();
-    let payload: serde_json::Value =
-        serde_json::from_str(&request_body).unwrap_or(serde_json::json!({}));
-
-    let result = reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(10))
-        .build()
-        .map_err(|e| e.to_string())
-        .and_then(|client| {
-            client
-                .post(&url)
-                .json(&payload)
-                .send()
-                .map_err(|e| e.to_string())
-        });
-    let duration_ms = start.elapsed().as_millis() as i64;
-
-    let (response_status, response_body, error_msg) = match result {
-        Ok(resp) => {
-            let status = resp.status().as_u16() as i32;
-            let body = resp.text().unwrap_or_default();
-            let body_truncated = if body.len() > 2048 {
-                let mut end = 2048;
-                while !body.is_char_boundary(end) {
-                    end -= 1;
-                }
-                body[..end].to_string()
-            } else {
-                body
-            };
-            info!(
-                "[web] retry webhook delivered to {} status={}",
-                url, status
-            );
-            (Some(status), body_truncated, String::new())
-        }
-        Err(e) => {
-            warn!("[web] retry webhook failed to {}: {}", url, e);
-            (None, String::new(), e.clone())
-        }
-    };
-
-    let url_clone = url.clone();
-    let rb_clone = request_body.clone();
-    let rb2_clone = response_body.clone();
-    let err_clone = error_msg.clone();
-    let sender_clone = entry.sender.clone();
-    let subject_clone = entry.subject.clone();
-    state
+    let queued = state
         .blocking_db(move |db| {
-            db.log_webhook(
-                &url_clone,
-                &rb_clone,
-                response_status,
-                &rb2_clone,
-                &err_clone,
-                duration_ms,
-                &sender_clone,
-                &subject_clone,
+            crate::webhook_queue::enqueue(
+                db,
+                &entry.url,
+                &request_body,
+                &entry.sender,
+                &entry.subject,
+                0,
             )
         })
         .await;
 
-    if error_msg.is_empty() {
-        let msg = format!(
-            "Retry webhook delivered to {} — HTTP {} in {} ms.",
-            url,
-            response_status.unwrap_or(0),
-            duration_ms
-        );
-        let tmpl = ErrorTemplate {
-            nav_active: "Webhooks",
-            flash: None,
-            status_code: 200,
-            status_text: "OK",
-            title: "Retry Successful",
-            message: &msg,
-            back_url: "/webhooks",
-            back_label: "Back to Webhooks",
-        };
-        Html(tmpl.render().unwrap()).into_response()
-    } else {
-        let msg = format!(
-            "Retry to {} failed after {} ms: {}",
-            url, duration_ms, error_msg
-        );
-        let tmpl = ErrorTemplate {
-            nav_active: "Webhooks",
-            flash: None,
-            status_code: 502,
-            status_text: "Bad Gateway",
-            title: "Retry Failed",
-            message: &msg,
-            back_url: "/webhooks",
-            back_label: "Back to Webhooks",
-        };
-        Html(tmpl.render().unwrap()).into_response()
-    }
+    let (status_code, status_text, title, msg) = match queued {
+        Some(queue_id) => {
+            info!(
+                "[web] webhook log id={} re-queued as queue id={} to {}",
+                id, queue_id, url
+            );
+            (
+                200,
+                "OK",
+                "Retry Queued",
+                format!(
+                    "The webhook to {} was queued for delivery. Each attempt is recorded in the webhook log.",
+                    url
+                ),
+            )
+        }
+        None => {
+            warn!("[web] failed to re-queue webhook log id={}", id);
+            (
+                500,
+                "Internal Server Error",
+                "Retry Failed",
+                format!("The webhook to {} could not be queued.", url),
+            )
+        }
+    };
+    let tmpl = ErrorTemplate {
+        nav_active: "Webhooks",
+        flash: None,
+        status_code,
+        status_text,
+        title,
+        message: &msg,
+        back_url: "/webhooks",
+        back_label: "Back to Webhooks",
+    };
+    Html(tmpl.render().unwrap()).into_response()
 }
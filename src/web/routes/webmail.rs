@@ -10,6 +10,7 @@ use axum::{
 };
 use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use log::{debug, error, info, warn};
+use mailparse::MailHeaderMap;
 use serde::Deserialize;
 use std::convert::Infallible;
 use std::time::Duration;
@@ -37,6 +38,8 @@ pub(crate) fn is_safe_folder(s: &str) -> bool {
 
 const MAILDIR_ROOT: &str = "/data/mail";
 const PAGE_SIZE: usize = 20;
+/// Upper bound on the messages a webmail search parses per request.
+const MAX_SEARCH_SCAN: usize = 2000;
 
 pub(crate) fn maildir_path(domain: &str, username: &str) -> String {
     format!("{}/{}/{}/Maildir", MAILDIR_ROOT, domain, username)
@@ -66,8 +69,37 @@ pub struct WebmailEmail {
     pub from: String,
     pub to: String,
     pub date: String,
+    /// Unread: in `new/`, or in `cur/` without the Maildir Seen flag.
     pub is_new: bool,
     pub is_spam: bool,
+    /// The file could not be parsed; only raw download and delete are offered.
+    pub unreadable: bool,
+}
+
+impl WebmailEmail {
+    /// Listing entry for a Maildir file that could not be parsed, so it stays
+    /// visible and can still be downloaded or deleted.
+    fn unreadable(fname: &str, is_new: bool) -> Self {
+        WebmailEmail {
+            filename: URL_SAFE_NO_PAD.encode(fname.as_bytes()),
+            subject: "⚠ unreadable message, raw download available".to_string(),
+            from: String::new(),
+            to: String::new(),
+            date: String::new(),
+            is_new,
+            is_spam: false,
+            unreadable: true,
+        }
+    }
+}
+
+/// An attachment listed on the message view, addressed by its position in
+/// [`attachment_parts`].
+pub struct WebmailAttachment {
+    pub index: usize,
+    pub name: String,
+    pub mime_type: String,
+    pub size: String,
 }
 
 pub struct WebmailFolder {
@@ -92,6 +124,7 @@ pub struct WebmailQuery {
     pub page: Option<usize>,
     pub sort_by: Option<String>,
     pub sort_order: Option<String>,
+    pub search: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -100,6 +133,39 @@ pub struct DeleteForm {
     pub folder: Option<String>,
 }
 
+#[derive(Deserialize)]
+pub struct MoveForm {
+    pub account_id: i64,
+    pub folder: Option<String>,
+    /// Maildir folder name such as `.Archive`, or `INBOX`.
+    pub destination: String,
+    /// Create the destination folder when it does not exist yet.
+    pub create: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateFolderForm {
+    pub account_id: i64,
+    /// Folder name without the leading dot; dots nest, e.g. `Projects.2024`.
+    pub name: String,
+}
+
+#[derive(Deserialize)]
+pub struct DeleteFolderForm {
+    pub account_id: i64,
+    pub folder: String,
+    /// Delete the folder even when it still holds messages.
+    pub force: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct MarkForm {
+    pub account_id: i64,
+    pub folder: Option<String>,
+    /// `read` or `unread`.
+    pub state: String,
+}
+
 #[derive(Deserialize, Default)]
 pub struct ComposePageQuery {
     pub account_id: Option<i64>,
@@ -339,8 +405,6 @@ fn group_folders(folders: Vec<WebmailFolder>, current_folder: &str) -> Vec<Webma
 
 pub(crate) fn read_emails(maildir_base: &str, folder: &str, logs: &mut Vec<String>) -> Vec<WebmailEmail> {
     let root = folder_root(maildir_base, folder);
-    let mut emails = Vec::new();
-
     // Create Maildir directories if they don't exist (INBOX only)
     if folder.is_empty() {
         for subdir in &["new", "cur", "tmp"] {
@@ -367,6 +431,37 @@ pub(crate) fn read_emails(maildir_base: &str, folder: &str, logs: &mut Vec<Strin
         }
     }
 
+    list_emails(&root, None, logs).0
+}
+
+/// Maildir listing filtered to messages whose subject, From, To or decoded
+/// body contains `search` (case-insensitive).  Every candidate has to be
+/// reparsed, so at most [`MAX_SEARCH_SCAN`] files are examined, newest
+/// first; the flag is set when files were left unscanned.
+pub(crate) fn search_emails(
+    maildir_base: &str,
+    folder: &str,
+    search: &str,
+    logs: &mut Vec<String>,
+) -> (Vec<WebmailEmail>, bool) {
+    let root = folder_root(maildir_base, folder);
+    let needle = search.to_lowercase();
+    list_emails(&root, Some(&needle), logs)
+}
+
+/// Uses the same field extraction as the delivery rules.
+fn message_matches(parsed: &mailparse::ParsedMail, needle: &str) -> bool {
+    ["subject", "from", "to", "body"].iter().any(|field| {
+        crate::delivery_rules::field_text(parsed, field)
+            .to_lowercase()
+            .contains(needle)
+    })
+}
+
+/// Parse the messages in `new/` and `cur/` under `root`.  With a lowercased
+/// `search` term only matching messages are kept and the scan is capped.
+fn list_emails(root: &str, search: Option<&str>, logs: &mut Vec<String>) -> (Vec<WebmailEmail>, bool) {
+    let mut files: Vec<(std::path::PathBuf, String, bool)> = Vec::new();
     for (subdir, is_new) in &[("new", true), ("cur", false)] {
         let dir_path = format!("{}/{}", root, subdir);
         logs.push(format!("Scanning directory: {}", dir_path));
@@ -378,60 +473,8 @@ pub(crate) fn read_emails(maildir_base: &str, folder: &str, logs: &mut Vec<Strin
                         continue;
                     }
                     let fname = entry.file_name().to_string_lossy().to_string();
-                    match std::fs::read(&path) {
-                        Ok(data) => match mailparse::parse_mail(&data) {
-                            Ok(parsed) => {
-                                let subject = parsed
-                                    .headers
-                                    .iter()
-                                    .find(|h| h.get_key().eq_ignore_ascii_case("Subject"))
-                                    .map(|h| h.get_value())
-                                    .unwrap_or_default();
-                                let from = parsed
-                                    .headers
-                                    .iter()
-                                    .find(|h| h.get_key().eq_ignore_ascii_case("From"))
-                                    .map(|h| h.get_value())
-                                    .unwrap_or_default();
-                                let to = parsed
-                                    .headers
-                                    .iter()
-                                    .find(|h| h.get_key().eq_ignore_ascii_case("To"))
-                                    .map(|h| h.get_value())
-                                    .unwrap_or_default();
-                                let date = parsed
-                                    .headers
-                                    .iter()
-                                    .find(|h| h.get_key().eq_ignore_ascii_case("Date"))
-                                    .map(|h| h.get_value())
-                                    .unwrap_or_default();
-                                let is_spam = parsed
-                                    .headers
-                                    .iter()
-                                    .find(|h| h.get_key().eq_ignore_ascii_case("X-Spam-Flag"))
-                                    .map(|h| h.get_value().trim().eq_ignore_ascii_case("YES"))
-                                    .unwrap_or(false);
-                                let encoded = URL_SAFE_NO_PAD.encode(fname.as_bytes());
-                                emails.push(WebmailEmail {
-                                    filename: encoded,
-                                    subject,
-                                    from,
-                                    to,
-                                    date,
-                                    is_new: *is_new,
-                                    is_spam,
-                                });
-                            }
-                            Err(e) => {
-                                logs.push(format!("Failed to parse email {}: {}", fname, e));
-                                warn!("[web] failed to parse email {}: {}", fname, e);
-                            }
-                        },
-                        Err(e) => {
-                            logs.push(format!("Failed to read file {}: {}", fname, e));
-                            warn!("[web] failed to read email file {}: {}", fname, e);
-                        }
-                    }
+                    let unread = *is_new || !has_seen_flag(&fname);
+                    files.push((path, fname, unread));
                 }
             }
             Err(e) => {
@@ -443,7 +486,65 @@ pub(crate) fn read_emails(maildir_base: &str, folder: &str, logs: &mut Vec<Strin
             }
         }
     }
-    emails
+
+    let mut truncated = false;
+    if search.is_some() && files.len() > MAX_SEARCH_SCAN {
+        // Maildir file names start with the delivery time, so the newest
+        // messages are the ones searched.
+        files.sort_by(|a, b| b.1.cmp(&a.1));
+        files.truncate(MAX_SEARCH_SCAN);
+        truncated = true;
+        logs.push(format!(
+            "Search limited to the {} newest messages",
+            MAX_SEARCH_SCAN
+        ));
+    }
+
+    let mut emails = Vec::new();
+    for (path, fname, is_new) in files {
+        match std::fs::read(&path) {
+            Ok(data) => match mailparse::parse_mail(&data) {
+                Ok(parsed) => {
+                    let subject = decoded_header(&parsed, "Subject");
+                    let from = decoded_header(&parsed, "From");
+                    let to = decoded_header(&parsed, "To");
+                    let date = decoded_header(&parsed, "Date");
+                    let is_spam = parsed
+                        .headers
+                        .iter()
+                        .find(|h| h.get_key().eq_ignore_ascii_case("X-Spam-Flag"))
+                        .map(|h| h.get_value().trim().eq_ignore_ascii_case("YES"))
+                        .unwrap_or(false);
+                    let encoded = URL_SAFE_NO_PAD.encode(fname.as_bytes());
+                    let email = WebmailEmail {
+                        filename: encoded,
+                        subject,
+                        from,
+                        to,
+                        date,
+                        is_new,
+                        is_spam,
+                        unreadable: false,
+                    };
+                    if search.is_none_or(|needle| message_matches(&parsed, needle)) {
+                        emails.push(email);
+                    }
+                }
+                Err(e) => {
+                    logs.push(format!("Failed to parse email {}: {}", fname, e));
+                    warn!("[web] failed to parse email {}: {}", fname, e);
+                    if search.is_none() {
+                        emails.push(WebmailEmail::unreadable(&fname, is_new));
+                    }
+                }
+            },
+            Err(e) => {
+                logs.push(format!("Failed to read file {}: {}", fname, e));
+                warn!("[web] failed to read email file {}: {}", fname, e);
+            }
+        }
+    }
+    (emails, truncated)
 }
 
 // ── Templates ──
@@ -465,6 +566,9 @@ struct InboxTemplate<'a> {
     next_page: Option<usize>,
     sort_by: String,
     sort_order: String,
+    search: String,
+    search_param: String,
+    search_truncated: bool,
     logs: Vec<String>,
 }
 
@@ -483,6 +587,8 @@ struct ViewTemplate<'a> {
     current_folder_name: String,
     filename_b64: String,
     is_spam: bool,
+    folders: Vec<WebmailFolder>,
+    attachments: Vec<WebmailAttachment>,
 }
 
 #[derive(Template)]
@@ -525,6 +631,9 @@ pub async fn inbox(
         .unwrap_or("")
         .to_string();
 
+    let search = query.search.as_deref().unwrap_or("").trim().to_string();
+    let mut search_truncated = false;
+
     if let Some(account_id) = query.account_id {
         logs.push(format!("Account ID {} selected", account_id));
         let acct = state
@@ -544,7 +653,13 @@ pub async fn inbox(
                 logs.push(format!("Maildir path: {}", maildir_base));
 
                 raw_folders = scan_folders(&maildir_base);
-                all_emails = read_emails(&maildir_base, &current_folder, &mut logs);
+                if search.is_empty() {
+                    all_emails = read_emails(&maildir_base, &current_folder, &mut logs);
+                } else {
+                    logs.push(format!("Searching for \"{}\"", search));
+                    (all_emails, search_truncated) =
+                        search_emails(&maildir_base, &current_folder, &search, &mut logs);
+                }
                 logs.push(format!("Total emails found: {}", all_emails.len()));
 
                 all_emails.sort_by(|a, b| {
@@ -600,6 +715,11 @@ pub async fn inbox(
     };
 
     let folder_groups = group_folders(raw_folders, &current_folder);
+    let search_param = if search.is_empty() {
+        String::new()
+    } else {
+        format!("&search={}", urlencoding_simple(&search))
+    };
 
     let tmpl = InboxTemplate {
         nav_active: "Webmail",
@@ -616,6 +736,9 @@ pub async fn inbox(
         next_page,
         sort_by,
         sort_order,
+        search,
+        search_param,
+        search_truncated,
         logs,
     };
     Html(tmpl.render().unwrap())
@@ -719,30 +842,10 @@ pub async fn view_email(
         }
     };
 
-    let subject = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("Subject"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
-    let from = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("From"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
-    let to = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("To"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
-    let date = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("Date"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
+    let subject = decoded_header(&parsed, "Subject");
+    let from = decoded_header(&parsed, "From");
+    let to = decoded_header(&parsed, "To");
+    let date = decoded_header(&parsed, "Date");
     let is_spam = parsed
         .headers
         .iter()
@@ -750,6 +853,27 @@ pub async fn view_email(
         .map(|h| h.get_value().trim().eq_ignore_ascii_case("YES"))
         .unwrap_or(false);
 
+    // Opening a message marks it read; links on the page use the new name.
+    let filename_b64 = match set_seen(&root, &filename, true) {
+        Ok(name) if name != filename => URL_SAFE_NO_PAD.encode(name.as_bytes()),
+        Ok(_) => filename_b64,
+        Err(e) => {
+            warn!("[web] failed to mark {} as read: {}", filename, e);
+            filename_b64
+        }
+    };
+
+    let attachments = attachment_parts(&parsed)
+        .into_iter()
+        .enumerate()
+        .map(|(index, part)| WebmailAttachment {
+            index,
+            name: attachment_filename(part, index),
+            mime_type: part.ctype.mimetype.to_ascii_lowercase(),
+            size: format_size(part.get_body_raw().map(|b| b.len()).unwrap_or(0)),
+        })
+        .collect();
+
     // Extract body: prefer text/plain, fall back to text/html (escaped)
     let body = extract_body(&parsed);
     debug!(
@@ -776,12 +900,181 @@ pub async fn view_email(
         body,
         current_folder,
         current_folder_name: folder_name,
-        filename_b64: filename_b64.clone(),
+        filename_b64,
         is_spam,
+        folders: scan_folders(&maildir_base),
+        attachments,
     };
     Html(tmpl.render().unwrap()).into_response()
 }
 
+/// First `name` header of a message for display, with RFC 2047 encoded words
+/// (`=?UTF-8?B?...?=`) decoded and folded lines unfolded.
+pub(crate) fn decoded_header(parsed: &mailparse::ParsedMail, name: &str) -> String {
+    parsed.headers.get_first_value(name).unwrap_or_default()
+}
+
+/// Locate a message file by name in a folder's `new/` or `cur/` directory.
+fn find_message_file(root: &str, filename: &str) -> Option<String> {
+    ["new", "cur"]
+        .iter()
+        .map(|subdir| format!("{}/{}/{}", root, subdir, filename))
+        .find(|candidate| std::path::Path::new(candidate).is_file())
+}
+
+/// Split a Maildir file name into its unique base and the flags after `:2,`.
+fn split_maildir_flags(filename: &str) -> (&str, &str) {
+    filename.rsplit_once(":2,").unwrap_or((filename, ""))
+}
+
+fn has_seen_flag(filename: &str) -> bool {
+    split_maildir_flags(filename).1.contains('S')
+}
+
+/// Name for the message in `cur/` with the Seen flag set or cleared, keeping
+/// the unique base and any other flags in the sorted order Maildir expects.
+fn with_seen_flag(filename: &str, seen: bool) -> String {
+    let (base, flags) = split_maildir_flags(filename);
+    let mut flags: Vec<char> = flags.chars().filter(|&c| c != 'S').collect();
+    if seen {
+        flags.push('S');
+    }
+    flags.sort_unstable();
+    flags.dedup();
+    format!("{}:2,{}", base, flags.into_iter().collect::<String>())
+}
+
+/// Move a message file without ever replacing an existing one.  If `target`
+/// is taken by an identical copy the source is dropped; otherwise the
+/// message is left where it is and `AlreadyExists` is returned.
+fn move_message_file(source: &str, target: &str) -> std::io::Result<()> {
+    // hard_link, unlike rename, fails instead of replacing an existing file.
+    match std::fs::hard_link(source, target) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+            if std::fs::read(source)? != std::fs::read(target)? {
+                warn!("[web] cannot move {}: {} already exists", source, target);
+                return Err(e);
+            }
+            debug!("[web] {} already exists with the same content", target);
+        }
+        Err(e) => return Err(e),
+    }
+    std::fs::remove_file(source)?;
+    debug!("[web] moved {} to {}", source, target);
+    Ok(())
+}
+
+/// Mark a message in the folder at `root` read or unread and return its new
+/// file name.  Reading moves a message from `new/` to `cur/` with the Seen
+/// flag; unreading clears the flag in `cur/`.  See [`move_message_file`]
+/// for what happens when the new name is already taken.
+pub(crate) fn set_seen(root: &str, filename: &str, seen: bool) -> std::io::Result<String> {
+    let source = find_message_file(root, filename)
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+    let in_new = source == format!("{}/new/{}", root, filename);
+    let currently_seen = !in_new && has_seen_flag(filename);
+    if currently_seen == seen {
+        return Ok(filename.to_string());
+    }
+
+    let target_name = with_seen_flag(filename, seen);
+    move_message_file(&source, &format!("{}/cur/{}", root, target_name))?;
+    Ok(target_name)
+}
+
+/// Create whatever is missing of the folder at `root` and its `new/`, `cur/`
+/// and `tmp/` directories, handing new directories to the Dovecot mail user.
+fn create_folder_dirs(root: &str) -> std::io::Result<()> {
+    let mut created = Vec::new();
+    if !std::path::Path::new(root).is_dir() {
+        created.push(root.to_string());
+    }
+    for subdir in &["new", "cur", "tmp"] {
+        let dir_path = format!("{}/{}", root, subdir);
+        if !std::path::Path::new(&dir_path).is_dir() {
+            std::fs::create_dir_all(&dir_path)?;
+            created.push(dir_path);
+        }
+    }
+    if !created.is_empty() {
+        if let Err(e) = std::process::Command::new("chown")
+            .arg("vmail:vmail")
+            .args(&created)
+            .status()
+        {
+            warn!("[web] failed to chown maildir {}: {}", root, e);
+        }
+    }
+    Ok(())
+}
+
+/// Maildir++ folder for a name typed by the user: `Projects.2024` becomes
+/// `.Projects.2024`.  `None` for INBOX, empty levels or unsafe characters.
+fn folder_from_name(name: &str) -> Option<String> {
+    let name = name.trim().trim_start_matches('.');
+    let legal = !name.is_empty()
+        && !name.eq_ignore_ascii_case("INBOX")
+        && name
+            .split('.')
+            .all(|level| !level.trim().is_empty() && !level.chars().any(char::is_control));
+    let folder = format!(".{}", name);
+    (legal && is_safe_folder(&folder)).then_some(folder)
+}
+
+/// Remove `folder` and its messages from the Maildir at `maildir_base` and
+/// drop its subscription.  INBOX cannot be removed (`PermissionDenied`), and
+/// a folder still holding messages is only removed with `force`
+/// (`DirectoryNotEmpty`).  Subfolders are separate Maildir++ folders and
+/// are left alone.
+pub(crate) fn remove_folder(maildir_base: &str, folder: &str, force: bool) -> std::io::Result<()> {
+    if folder.is_empty() || !is_safe_folder(folder) {
+        return Err(std::io::ErrorKind::PermissionDenied.into());
+    }
+    let root = folder_root(maildir_base, folder);
+    if !std::path::Path::new(&root).is_dir() {
+        return Err(std::io::ErrorKind::NotFound.into());
+    }
+    let has_messages = ["new", "cur"].iter().any(|subdir| {
+        std::fs::read_dir(format!("{}/{}", root, subdir))
+            .map(|mut entries| entries.next().is_some())
+            .unwrap_or(false)
+    });
+    if has_messages && !force {
+        return Err(std::io::ErrorKind::DirectoryNotEmpty.into());
+    }
+    std::fs::remove_dir_all(&root)?;
+
+    let subscriptions = format!("{}/subscriptions", maildir_base);
+    if let Ok(existing) = std::fs::read_to_string(&subscriptions) {
+        let name = folder.trim_start_matches('.');
+        if existing.lines().any(|l| l.trim() == name) {
+            let kept: String = existing
+                .lines()
+                .filter(|l| l.trim() != name)
+                .map(|l| format!("{}\n", l))
+                .collect();
+            std::fs::write(&subscriptions, kept)?;
+        }
+    }
+    Ok(())
+}
+
+/// Move a message from the folder at `source_root` into `cur/` of the folder
+/// at `dest_root` and return its name there.  A message from `new/` gets an
+/// empty flag list, so it stays unread.
+pub(crate) fn move_to_folder(source_root: &str, filename: &str, dest_root: &str) -> std::io::Result<String> {
+    let source = find_message_file(source_root, filename)
+        .ok_or_else(|| std::io::Error::from(std::io::ErrorKind::NotFound))?;
+    let target_name = if filename.contains(":2,") {
+        filename.to_string()
+    } else {
+        format!("{}:2,", filename)
+    };
+    move_message_file(&source, &format!("{}/cur/{}", dest_root, target_name))?;
+    Ok(target_name)
+}
+
 pub async fn download_email(
     _auth: AuthAdmin,
     State(state): State<AppState>,
@@ -849,16 +1142,7 @@ pub async fn download_email(
     let maildir_base = maildir_path(domain, &acct.username);
     let root = folder_root(&maildir_base, &current_folder);
 
-    let mut file_path = None;
-    for subdir in &["new", "cur"] {
-        let candidate = format!("{}/{}/{}", root, subdir, filename);
-        if std::path::Path::new(&candidate).is_file() {
-            file_path = Some(candidate);
-            break;
-        }
-    }
-
-    let file_path = match file_path {
+    let file_path = match find_message_file(&root, &filename) {
         Some(p) => p,
         None => {
             warn!("[web] email file not found for download: {}", filename);
@@ -992,31 +1276,21 @@ pub async fn reply_email(
         }
     };
 
-    let subject_raw = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("Subject"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
-    let from = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("From"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
-    let reply_to = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("Reply-To"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
-    let message_id = parsed
-        .headers
-        .iter()
-        .find(|h| h.get_key().eq_ignore_ascii_case("Message-ID"))
-        .map(|h| h.get_value())
-        .unwrap_or_default();
-    let body = extract_body(&parsed);
+    let subject_raw = decoded_header(&parsed, "Subject");
+    let from = decoded_header(&parsed, "From");
+    let reply_to = decoded_header(&parsed, "Reply-To");
+    let message_id = decoded_header(&parsed, "Message-ID");
+    let strip_pixels = state
+        .blocking_db(|db| db.get_setting("strip_pixels_on_reply"))
+        .await
+        .map(|v| v != "false")
+        .unwrap_or(true);
+    let pixel_base_url = if strip_pixels {
+        Some(super::tracking::load_pixel_settings(&state).await)
+    } else {
+        None
+    };
+    let body = reply_body(&parsed, pixel_base_url.as_deref());
 
     let mut defaults = ComposeDefaults {
         priority: "normal".to_string(),
@@ -1040,17 +1314,7 @@ pub async fn reply_email(
     defaults.subject = sanitize_header_value(&reply_subject);
     defaults.in_reply_to = sanitize_header_value(&message_id);
     if !body.is_empty() {
-        let quoted = body
-            .lines()
-            .fold(String::new(), |mut acc, line| {
-                if !acc.is_empty() {
-                    acc.push('\n');
-                }
-                acc.push_str("> ");
-                acc.push_str(line);
-                acc
-            });
-        defaults.body = format!("\n\n{}", quoted);
+        defaults.body = format!("\n\n{}", quote_reply(&body));
     }
 
     let accounts = state
@@ -1145,67 +1409,608 @@ pub async fn delete_email(
     Redirect::to(&redirect_url).into_response()
 }
 
-fn urlencoding_simple(s: &str) -> String {
-    s.chars()
-        .flat_map(|c| {
-            if c.is_alphanumeric() || c == '-' || c == '_' || c == '.' {
-                vec![c]
-            } else {
-                format!("%{:02X}", c as u32).chars().collect()
+pub async fn mark_email(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path(filename_b64): Path<String>,
+    Form(form): Form<MarkForm>,
+) -> Response {
+    info!(
+        "[web] POST /webmail/mark/{} — marking email {}",
+        filename_b64, form.state
+    );
+
+    let seen = match form.state.as_str() {
+        "read" => true,
+        "unread" => false,
+        other => {
+            warn!("[web] invalid mark state: {:?}", other);
+            return Html("Invalid state".to_string()).into_response();
+        }
+    };
+
+    let acct = match state
+        .blocking_db(move |db| db.get_account_with_domain(form.account_id))
+        .await
+    {
+        Some(a) => a,
+        None => {
+            warn!("[web] account not found for mark");
+            return Html("Account not found".to_string()).into_response();
+        }
+    };
+
+    let filename = match URL_SAFE_NO_PAD.decode(filename_b64.as_bytes()) {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(_) => {
+                error!("[web] invalid UTF-8 in decoded filename for mark");
+                return Html("Invalid filename encoding".to_string()).into_response();
             }
-        })
-        .collect()
-}
+        },
+        Err(e) => {
+            error!("[web] failed to decode base64 filename for mark: {}", e);
+            return Html("Invalid filename encoding".to_string()).into_response();
+        }
+    };
 
-pub(crate) fn extract_body(parsed: &mailparse::ParsedMail) -> String {
-    // Try to find text/plain part first
-    if let Some(text) = find_body_part(parsed, "text/plain") {
-        return text;
-    }
-    // Fall back to text/html (escape HTML for safe display)
-    if let Some(html) = find_body_part(parsed, "text/html") {
-        return html
-            .replace('&', "&amp;")
-            .replace('<', "&lt;")
-            .replace('>', "&gt;");
+    let domain = acct.domain_name.as_deref().unwrap_or("unknown");
+    let folder = form.folder.as_deref().unwrap_or("");
+    if !is_safe_path_component(domain)
+        || !is_safe_path_component(&acct.username)
+        || !is_safe_path_component(&filename)
+        || !is_safe_folder(folder)
+    {
+        warn!("[web] unsafe path component in mark_email");
+        return Html("Invalid path component".to_string()).into_response();
     }
-    // Last resort: try top-level body
-    parsed.get_body().unwrap_or_default()
-}
 
-pub(crate) fn find_body_part(parsed: &mailparse::ParsedMail, mime_type: &str) -> Option<String> {
-    if parsed.subparts.is_empty() {
-        let ctype = parsed.ctype.mimetype.to_lowercase();
-        if ctype == mime_type {
-            return parsed.get_body().ok();
+    let maildir_base = maildir_path(domain, &acct.username);
+    let root = folder_root(&maildir_base, folder);
+
+    match set_seen(&root, &filename, seen) {
+        Ok(name) => info!("[web] marked {} as {}", name, form.state),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("[web] email file not found for mark: {}", filename);
         }
-        return None;
-    }
-    for part in &parsed.subparts {
-        if let Some(body) = find_body_part(part, mime_type) {
-            return Some(body);
+        Err(e) => {
+            error!("[web] failed to mark email {}: {}", filename, e);
+            return Html(format!("Failed to mark email: {}", e)).into_response();
         }
     }
-    None
+
+    let redirect_url = format!(
+        "/webmail?account_id={}&folder={}",
+        acct.id,
+        urlencoding_simple(folder)
+    );
+    Redirect::to(&redirect_url).into_response()
 }
 
-pub async fn compose(
+pub async fn move_email(
     _auth: AuthAdmin,
     State(state): State<AppState>,
-    Query(query): Query<ComposePageQuery>,
-) -> Html<String> {
-    info!("[web] GET /webmail/compose — compose email form");
+    Path(filename_b64): Path<String>,
+    Form(form): Form<MoveForm>,
+) -> Response {
+    info!(
+        "[web] POST /webmail/move/{} — moving email to {:?}",
+        filename_b64, form.destination
+    );
 
-    let defaults = defaults_from_query(&query);
-    let accounts = state
-        .blocking_db(|db| db.list_all_accounts_with_domain())
-        .await;
+    let acct = match state
+        .blocking_db(move |db| db.get_account_with_domain(form.account_id))
+        .await
+    {
+        Some(a) => a,
+        None => {
+            warn!("[web] account not found for move");
+            return Html("Account not found".to_string()).into_response();
+        }
+    };
 
-    let selected_account = if let Some(account_id) = query.account_id {
-        state
-            .blocking_db(move |db| db.get_account_with_domain(account_id))
-            .await
-    } else {
+    let filename = match URL_SAFE_NO_PAD.decode(filename_b64.as_bytes()) {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(_) => {
+                error!("[web] invalid UTF-8 in decoded filename for move");
+                return Html("Invalid filename encoding".to_string()).into_response();
+            }
+        },
+        Err(e) => {
+            error!("[web] failed to decode base64 filename for move: {}", e);
+            return Html("Invalid filename encoding".to_string()).into_response();
+        }
+    };
+
+    let domain = acct.domain_name.as_deref().unwrap_or("unknown");
+    let folder = form.folder.as_deref().unwrap_or("");
+    // INBOX is the Maildir root, named "" everywhere else.
+    let destination = match form.destination.trim() {
+        d if d.eq_ignore_ascii_case("INBOX") => "",
+        d => d,
+    };
+    if !is_safe_path_component(domain)
+        || !is_safe_path_component(&acct.username)
+        || !is_safe_path_component(&filename)
+        || !is_safe_folder(folder)
+        || !is_safe_folder(destination)
+    {
+        warn!("[web] unsafe path component in move_email");
+        return Html("Invalid path component".to_string()).into_response();
+    }
+    if destination == folder {
+        warn!("[web] move of {} into its own folder refused", filename);
+        return Html("The message is already in that folder".to_string()).into_response();
+    }
+
+    let maildir_base = maildir_path(domain, &acct.username);
+    let root = folder_root(&maildir_base, folder);
+    let dest_root = folder_root(&maildir_base, destination);
+
+    if !std::path::Path::new(&dest_root).is_dir() {
+        if form.create.is_none() {
+            warn!("[web] move destination {} does not exist", dest_root);
+            return Html(format!("Folder {} does not exist", destination)).into_response();
+        }
+        info!("[web] creating folder {} for move", dest_root);
+    }
+    if let Err(e) = create_folder_dirs(&dest_root) {
+        error!("[web] failed to create folder {}: {}", dest_root, e);
+        return Html(format!("Failed to create folder: {}", e)).into_response();
+    }
+
+    match move_to_folder(&root, &filename, &dest_root) {
+        Ok(name) => info!("[web] moved {} to {}", name, dest_root),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("[web] email file not found for move: {}", filename);
+        }
+        Err(e) => {
+            error!("[web] failed to move email {}: {}", filename, e);
+            return Html(format!("Failed to move email: {}", e)).into_response();
+        }
+    }
+
+    let redirect_url = format!(
+        "/webmail?account_id={}&folder={}",
+        acct.id,
+        urlencoding_simple(folder)
+    );
+    Redirect::to(&redirect_url).into_response()
+}
+
+pub async fn create_folder(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<CreateFolderForm>,
+) -> Response {
+    info!("[web] POST /webmail/folder/create — creating folder {:?}", form.name);
+
+    let acct = match state
+        .blocking_db(move |db| db.get_account_with_domain(form.account_id))
+        .await
+    {
+        Some(a) => a,
+        None => {
+            warn!("[web] account not found for folder create");
+            return Html("Account not found".to_string()).into_response();
+        }
+    };
+
+    let domain = acct.domain_name.as_deref().unwrap_or("unknown");
+    if !is_safe_path_component(domain) || !is_safe_path_component(&acct.username) {
+        warn!("[web] unsafe path component in create_folder");
+        return Html("Invalid path component".to_string()).into_response();
+    }
+    let Some(folder) = folder_from_name(&form.name) else {
+        warn!("[web] invalid folder name: {:?}", form.name);
+        return Html("Invalid folder name".to_string()).into_response();
+    };
+
+    let maildir_base = maildir_path(domain, &acct.username);
+    let root = folder_root(&maildir_base, &folder);
+    if std::path::Path::new(&root).is_dir() {
+        warn!("[web] folder {} already exists", root);
+        return Html(format!("Folder {} already exists", folder)).into_response();
+    }
+    if let Err(e) = create_folder_dirs(&root) {
+        error!("[web] failed to create folder {}: {}", root, e);
+        return Html(format!("Failed to create folder: {}", e)).into_response();
+    }
+    // Subscribe it so IMAP clients show the folder too.
+    let name = folder.trim_start_matches('.').to_string();
+    let maildir = std::path::Path::new(&maildir_base);
+    if let Err(e) = crate::config::write_maildir_subscriptions(maildir, &[name]) {
+        warn!("[web] failed to subscribe folder {}: {}", folder, e);
+    } else if let Err(e) = std::process::Command::new("chown")
+        .arg("vmail:vmail")
+        .arg(maildir.join("subscriptions"))
+        .status()
+    {
+        warn!("[web] failed to chown subscriptions of {}: {}", maildir_base, e);
+    }
+    info!("[web] created folder {}", root);
+
+    let redirect_url = format!(
+        "/webmail?account_id={}&folder={}",
+        acct.id,
+        urlencoding_simple(&folder)
+    );
+    Redirect::to(&redirect_url).into_response()
+}
+
+pub async fn delete_folder(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Form(form): Form<DeleteFolderForm>,
+) -> Response {
+    info!(
+        "[web] POST /webmail/folder/delete — deleting folder {:?}",
+        form.folder
+    );
+
+    let acct = match state
+        .blocking_db(move |db| db.get_account_with_domain(form.account_id))
+        .await
+    {
+        Some(a) => a,
+        None => {
+            warn!("[web] account not found for folder delete");
+            return Html("Account not found".to_string()).into_response();
+        }
+    };
+
+    let domain = acct.domain_name.as_deref().unwrap_or("unknown");
+    if !is_safe_path_component(domain)
+        || !is_safe_path_component(&acct.username)
+        || !is_safe_folder(&form.folder)
+    {
+        warn!("[web] unsafe path component in delete_folder");
+        return Html("Invalid path component".to_string()).into_response();
+    }
+
+    let maildir_base = maildir_path(domain, &acct.username);
+    match remove_folder(&maildir_base, &form.folder, form.force.is_some()) {
+        Ok(()) => info!("[web] deleted folder {} of {}", form.folder, maildir_base),
+        Err(e) if e.kind() == std::io::ErrorKind::PermissionDenied => {
+            warn!("[web] refused to delete INBOX of {}", maildir_base);
+            return Html("INBOX cannot be deleted".to_string()).into_response();
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::DirectoryNotEmpty => {
+            warn!("[web] refused to delete non-empty folder {}", form.folder);
+            return Html(format!(
+                "Folder {} still holds messages; tick \"Delete messages too\" to remove it anyway",
+                form.folder
+            ))
+            .into_response();
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            warn!("[web] folder {} not found for delete", form.folder);
+        }
+        Err(e) => {
+            error!("[web] failed to delete folder {}: {}", form.folder, e);
+            return Html(format!("Failed to delete folder: {}", e)).into_response();
+        }
+    }
+
+    Redirect::to(&format!("/webmail?account_id={}", acct.id)).into_response()
+}
+
+/// Leaf parts of a message that are attachments: marked
+/// `Content-Disposition: attachment` or carrying a file name, in MIME tree
+/// order.
+pub(crate) fn attachment_parts<'a>(parsed: &'a mailparse::ParsedMail<'a>) -> Vec<&'a mailparse::ParsedMail<'a>> {
+    fn collect<'a>(part: &'a mailparse::ParsedMail<'a>, out: &mut Vec<&'a mailparse::ParsedMail<'a>>) {
+        if part.ctype.mimetype.starts_with("multipart/") {
+            for sub in &part.subparts {
+                collect(sub, out);
+            }
+            return;
+        }
+        let is_attachment =
+            part.get_content_disposition().disposition == mailparse::DispositionType::Attachment;
+        if is_attachment || crate::attachment_policy::filename(part).is_some() {
+            out.push(part);
+        }
+    }
+    let mut out = Vec::new();
+    collect(parsed, &mut out);
+    out
+}
+
+/// Declared file name of an attachment without path or control characters,
+/// or `attachment-N` when it has none.
+fn attachment_filename(part: &mailparse::ParsedMail, index: usize) -> String {
+    let name = crate::attachment_policy::filename(part).unwrap_or_default();
+    let name = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '"' || c == ':' { '_' } else { c })
+        .collect();
+    let name = name.trim();
+    if name.is_empty() || name == "." || name == ".." {
+        format!("attachment-{}", index + 1)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Content type to serve an attachment with: its declared type when that is
+/// a well-formed `type/subtype`, else `application/octet-stream`.
+fn attachment_content_type(part: &mailparse::ParsedMail) -> String {
+    let mime = part.ctype.mimetype.to_ascii_lowercase();
+    let token = |s: &str| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || "!#$&^_.+-".contains(c))
+    };
+    match mime.split_once('/') {
+        Some((t, sub)) if token(t) && token(sub) => mime,
+        _ => "application/octet-stream".to_string(),
+    }
+}
+
+pub(crate) fn format_size(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} B", bytes)
+    }
+}
+
+pub async fn download_attachment(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Path((filename_b64, index)): Path<(String, usize)>,
+    Query(query): Query<WebmailQuery>,
+) -> Response {
+    info!(
+        "[web] GET /webmail/attachment/{}/{} — downloading attachment",
+        filename_b64, index
+    );
+
+    let account_id = match query.account_id {
+        Some(id) => id,
+        None => {
+            warn!("[web] no account_id provided for attachment download");
+            return Html("Missing account_id parameter".to_string()).into_response();
+        }
+    };
+
+    let acct = match state
+        .blocking_db(move |db| db.get_account_with_domain(account_id))
+        .await
+    {
+        Some(a) => a,
+        None => {
+            warn!(
+                "[web] account id={} not found for attachment download",
+                account_id
+            );
+            return Html("Account not found".to_string()).into_response();
+        }
+    };
+
+    let filename = match URL_SAFE_NO_PAD.decode(filename_b64.as_bytes()) {
+        Ok(bytes) => match String::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(_) => {
+                error!("[web] invalid UTF-8 in decoded filename for attachment");
+                return Html("Invalid filename encoding".to_string()).into_response();
+            }
+        },
+        Err(e) => {
+            error!("[web] failed to decode base64 filename for attachment: {}", e);
+            return Html("Invalid filename encoding".to_string()).into_response();
+        }
+    };
+
+    let domain = acct.domain_name.as_deref().unwrap_or("unknown");
+    let current_folder = query
+        .folder
+        .as_deref()
+        .filter(|f| is_safe_folder(f))
+        .unwrap_or("")
+        .to_string();
+
+    if !is_safe_path_component(domain)
+        || !is_safe_path_component(&acct.username)
+        || !is_safe_path_component(&filename)
+        || !is_safe_folder(&current_folder)
+    {
+        warn!("[web] unsafe path component in download_attachment");
+        return Html("Invalid path component".to_string()).into_response();
+    }
+
+    let maildir_base = maildir_path(domain, &acct.username);
+    let root = folder_root(&maildir_base, &current_folder);
+
+    let file_path = match find_message_file(&root, &filename) {
+        Some(p) => p,
+        None => {
+            warn!("[web] email file not found for attachment: {}", filename);
+            return Html("Email not found".to_string()).into_response();
+        }
+    };
+
+    let data = match std::fs::read(&file_path) {
+        Ok(d) => d,
+        Err(e) => {
+            error!("[web] failed to read email file for attachment: {}", e);
+            return Html("Failed to read email".to_string()).into_response();
+        }
+    };
+
+    let parsed = match mailparse::parse_mail(&data) {
+        Ok(p) => p,
+        Err(e) => {
+            error!("[web] failed to parse email for attachment: {}", e);
+            return Html("Failed to parse email".to_string()).into_response();
+        }
+    };
+
+    let parts = attachment_parts(&parsed);
+    let Some(part) = parts.get(index) else {
+        warn!(
+            "[web] attachment index {} out of range ({} attachments) in {}",
+            index,
+            parts.len(),
+            filename
+        );
+        return Html("Attachment not found".to_string()).into_response();
+    };
+
+    // get_body_raw undoes the Content-Transfer-Encoding.
+    let content = match part.get_body_raw() {
+        Ok(c) => c,
+        Err(e) => {
+            error!(
+                "[web] failed to decode attachment {} of {}: {}",
+                index, filename, e
+            );
+            return Html("Failed to decode attachment".to_string()).into_response();
+        }
+    };
+
+    let name = attachment_filename(part, index);
+    let ascii_name: String = name
+        .chars()
+        .map(|c| if (c.is_ascii_graphic() && c != '\\') || c == ' ' { c } else { '_' })
+        .collect();
+    (
+        [
+            (header::CONTENT_TYPE, attachment_content_type(part)),
+            (
+                header::CONTENT_DISPOSITION,
+                format!(
+                    "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+                    ascii_name,
+                    urlencoding_simple(&name)
+                ),
+            ),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+        ],
+        content,
+    )
+        .into_response()
+}
+
+/// Percent-encode everything but ASCII letters, digits and `-_.`, byte by
+/// byte, so the result is safe in URLs and RFC 5987 header parameters.
+fn urlencoding_simple(s: &str) -> String {
+    s.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.' {
+                (b as char).to_string()
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+/// Body to quote in a reply.  Like [`extract_body`], but with our tracking
+/// pixels removed when `pixel_base_url` is given, so quoting a tracked
+/// message neither records an open nor re-tracks the new recipient.
+fn reply_body(parsed: &mailparse::ParsedMail, pixel_base_url: Option<&str>) -> String {
+    let Some(base) = pixel_base_url else {
+        return extract_body(parsed);
+    };
+    if let Some(text) = find_body_part(parsed, "text/plain") {
+        return crate::filter::strip_tracking_pixels(&text, base);
+    }
+    if let Some(html) = find_body_part(parsed, "text/html") {
+        return crate::filter::strip_tracking_pixels(&html, base)
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+    }
+    crate::filter::strip_tracking_pixels(&decode_part_body(parsed).unwrap_or_default(), base)
+}
+
+/// Prefix every line of `body` with `> `.
+fn quote_reply(body: &str) -> String {
+    body.lines().fold(String::new(), |mut acc, line| {
+        if !acc.is_empty() {
+            acc.push('\n');
+        }
+        acc.push_str("> ");
+        acc.push_str(line);
+        acc
+    })
+}
+
+pub(crate) fn extract_body(parsed: &mailparse::ParsedMail) -> String {
+    // Try to find text/plain part first
+    if let Some(text) = find_body_part(parsed, "text/plain") {
+        return text;
+    }
+    // Fall back to text/html (escape HTML for safe display)
+    if let Some(html) = find_body_part(parsed, "text/html") {
+        return html
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;");
+    }
+    // Last resort: try top-level body
+    decode_part_body(parsed).unwrap_or_default()
+}
+
+/// Decode a leaf part for display: undo its Content-Transfer-Encoding, then
+/// convert from its declared charset.  Bodies with no charset (mailparse
+/// reports `us-ascii`) that are valid UTF-8 are read as UTF-8, and unknown
+/// charsets fall back to lossy UTF-8.
+pub(crate) fn decode_part_body(part: &mailparse::ParsedMail) -> Option<String> {
+    let raw = part.get_body_raw().ok()?;
+    let charset = part.ctype.charset.trim();
+    if charset.eq_ignore_ascii_case("us-ascii") || charset.eq_ignore_ascii_case("utf-8") {
+        return Some(String::from_utf8_lossy(&raw).into_owned());
+    }
+    match encoding_rs::Encoding::for_label(charset.as_bytes()) {
+        Some(encoding) => Some(encoding.decode(&raw).0.into_owned()),
+        None => {
+            debug!("[web] unknown charset '{}', decoding as UTF-8", charset);
+            Some(String::from_utf8_lossy(&raw).into_owned())
+        }
+    }
+}
+
+pub(crate) fn find_body_part(parsed: &mailparse::ParsedMail, mime_type: &str) -> Option<String> {
+    if parsed.subparts.is_empty() {
+        let ctype = parsed.ctype.mimetype.to_lowercase();
+        if ctype == mime_type {
+            return decode_part_body(parsed);
+        }
+        return None;
+    }
+    for part in &parsed.subparts {
+        if let Some(body) = find_body_part(part, mime_type) {
+            return Some(body);
+        }
+    }
+    None
+}
+
+pub async fn compose(
+    _auth: AuthAdmin,
+    State(state): State<AppState>,
+    Query(query): Query<ComposePageQuery>,
+) -> Html<String> {
+    info!("[web] GET /webmail/compose — compose email form");
+
+    let defaults = defaults_from_query(&query);
+    let accounts = state
+        .blocking_db(|db| db.list_all_accounts_with_domain())
+        .await;
+
+    let selected_account = if let Some(account_id) = query.account_id {
+        state
+            .blocking_db(move |db| db.get_account_with_domain(account_id))
+            .await
+    } else {
         None
     };
 
@@ -1734,9 +2539,31 @@ pub async fn idle_stream(
 #[cfg(test)]
 mod tests {
     use super::{
-        defaults_from_form, defaults_from_query, group_folders, is_safe_folder, maildir_path,
-        ComposeForm, ComposePageQuery, WebmailFolder,
+        attachment_content_type, attachment_filename, attachment_parts, create_folder_dirs, urlencoding_simple, decoded_header, defaults_from_form, defaults_from_query, extract_body, find_message_file, folder_from_name, folder_root, group_folders,
+        is_safe_folder, maildir_path, quote_reply, read_emails, reply_body, move_to_folder, remove_folder, scan_folders, search_emails, set_seen, ComposeForm, ComposePageQuery, WebmailFolder,
     };
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+    #[test]
+    fn reply_quote_drops_tracking_pixel() {
+        let raw = concat!(
+            "From: a@example.com\r\n",
+            "Subject: Tracked\r\n",
+            "Content-Type: text/html; charset=utf-8\r\n",
+            "\r\n",
+            "<p>Hello</p>\r\n",
+            "<img src=\"https://mail.example.com/pixel?id=abc\" width=\"1\" height=\"1\" style=\"display:none\" alt=\"\" />\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+
+        let quoted = quote_reply(&reply_body(&parsed, Some("https://mail.example.com/pixel?id=")));
+        assert!(quoted.contains("&lt;p&gt;Hello"));
+        assert!(!quoted.contains("pixel?id="));
+        assert!(!quoted.contains("img"));
+
+        // With stripping disabled the quote keeps the original body.
+        assert!(quote_reply(&reply_body(&parsed, None)).contains("pixel?id=abc"));
+    }
 
     #[test]
     fn maildir_path_uses_data_mail_root() {
@@ -1744,6 +2571,276 @@ mod tests {
         assert_eq!(path, "/data/mail/example.com/alice/Maildir");
     }
 
+    #[test]
+    fn corrupt_message_is_listed_as_unreadable_with_raw_download() {
+        let base = std::env::temp_dir().join(format!("webmail_corrupt_{}", uuid::Uuid::new_v4()));
+        let base = base.to_str().unwrap().to_string();
+        let root = folder_root(&base, ".Test");
+        std::fs::create_dir_all(format!("{}/cur", root)).unwrap();
+        std::fs::create_dir_all(format!("{}/new", root)).unwrap();
+        let fname = "1700000000.corrupt.host:2,S";
+        std::fs::write(format!("{}/cur/{}", root, fname), b" leading space\r\n\r\nbody").unwrap();
+
+        let mut logs = Vec::new();
+        let emails = read_emails(&base, ".Test", &mut logs);
+        assert_eq!(emails.len(), 1);
+        assert!(emails[0].unreadable);
+        assert!(emails[0].subject.contains("unreadable"));
+
+        let decoded = URL_SAFE_NO_PAD.decode(emails[0].filename.as_bytes()).unwrap();
+        let decoded = String::from_utf8(decoded).unwrap();
+        assert_eq!(decoded, fname);
+        let path = find_message_file(&root, &decoded).expect("raw download finds the file");
+        assert_eq!(std::fs::read(path).unwrap(), b" leading space\r\n\r\nbody");
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn marking_read_and_unread_moves_between_new_and_cur() {
+        let base = std::env::temp_dir().join(format!("webmail_seen_{}", uuid::Uuid::new_v4()));
+        let base = base.to_str().unwrap().to_string();
+        let root = folder_root(&base, ".Test");
+        for dir in ["new", "cur", "tmp"] {
+            std::fs::create_dir_all(format!("{}/{}", root, dir)).unwrap();
+        }
+        std::fs::write(format!("{}/new/1700000001.a.host", root), "Subject: a\r\n\r\nA").unwrap();
+        std::fs::write(format!("{}/cur/1700000002.b.host:2,FR", root), "Subject: b\r\n\r\nB").unwrap();
+
+        let mut logs = Vec::new();
+        assert!(read_emails(&base, ".Test", &mut logs).iter().all(|e| e.is_new));
+
+        // Reading moves new/ to cur/ with the Seen flag.
+        let name = set_seen(&root, "1700000001.a.host", true).unwrap();
+        assert_eq!(name, "1700000001.a.host:2,S");
+        assert!(std::path::Path::new(&format!("{}/cur/{}", root, name)).is_file());
+        assert!(!std::path::Path::new(&format!("{}/new/1700000001.a.host", root)).exists());
+        assert_eq!(set_seen(&root, &name, true).unwrap(), name);
+
+        // Other flags are kept in order.
+        let name_b = set_seen(&root, "1700000002.b.host:2,FR", true).unwrap();
+        assert_eq!(name_b, "1700000002.b.host:2,FRS");
+        assert_eq!(set_seen(&root, &name_b, false).unwrap(), "1700000002.b.host:2,FR");
+        let emails = read_emails(&base, ".Test", &mut logs);
+        let unread: Vec<bool> = emails.iter().map(|e| e.is_new).collect();
+        assert_eq!(unread.iter().filter(|u| **u).count(), 1);
+
+        // A different file already holding the target name is not replaced.
+        std::fs::write(format!("{}/new/1700000003.c.host", root), "Subject: c\r\n\r\nC").unwrap();
+        std::fs::write(format!("{}/cur/1700000003.c.host:2,S", root), "other").unwrap();
+        let err = set_seen(&root, "1700000003.c.host", true).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(std::path::Path::new(&format!("{}/new/1700000003.c.host", root)).is_file());
+        assert_eq!(std::fs::read_to_string(format!("{}/cur/1700000003.c.host:2,S", root)).unwrap(), "other");
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn moving_files_into_another_folder_keeps_unread_state() {
+        let base = std::env::temp_dir().join(format!("webmail_move_{}", uuid::Uuid::new_v4()));
+        let base = base.to_str().unwrap().to_string();
+        let inbox = folder_root(&base, ".Test");
+        let archive = folder_root(&base, ".Archive");
+        for root in [&inbox, &archive] {
+            for dir in ["new", "cur", "tmp"] {
+                std::fs::create_dir_all(format!("{}/{}", root, dir)).unwrap();
+            }
+        }
+        std::fs::write(format!("{}/new/1700000001.a.host", inbox), "Subject: a\r\n\r\nA").unwrap();
+        std::fs::write(format!("{}/cur/1700000002.b.host:2,S", inbox), "Subject: b\r\n\r\nB").unwrap();
+
+        assert_eq!(move_to_folder(&inbox, "1700000001.a.host", &archive).unwrap(), "1700000001.a.host:2,");
+        assert_eq!(move_to_folder(&inbox, "1700000002.b.host:2,S", &archive).unwrap(), "1700000002.b.host:2,S");
+        let mut logs = Vec::new();
+        assert!(read_emails(&base, ".Test", &mut logs).is_empty());
+        let mut moved: Vec<(String, bool)> = read_emails(&base, ".Archive", &mut logs)
+            .into_iter()
+            .map(|e| (e.subject, e.is_new))
+            .collect();
+        moved.sort();
+        assert_eq!(moved, vec![("a".to_string(), true), ("b".to_string(), false)]);
+
+        let missing = move_to_folder(&inbox, "1700000001.a.host", &archive).unwrap_err();
+        assert_eq!(missing.kind(), std::io::ErrorKind::NotFound);
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn folders_are_created_and_deleted() {
+        assert_eq!(folder_from_name(" Projects.2024 ").as_deref(), Some(".Projects.2024"));
+        assert_eq!(folder_from_name(".Archive").as_deref(), Some(".Archive"));
+        for bad in ["", "INBOX", "inbox", "a..b", "a.", "../x", "a/b", "tab\there"] {
+            assert_eq!(folder_from_name(bad), None, "{:?}", bad);
+        }
+
+        let base = std::env::temp_dir().join(format!("webmail_folders_{}", uuid::Uuid::new_v4()));
+        let base = base.to_str().unwrap().to_string();
+        for name in ["Projects", "Projects.2024"] {
+            create_folder_dirs(&folder_root(&base, &folder_from_name(name).unwrap())).unwrap();
+        }
+        let groups = group_folders(scan_folders(&base), ".Projects.2024");
+        let projects = groups.iter().find(|g| g.folder.name == ".Projects").unwrap();
+        assert!(projects.open);
+        assert_eq!(projects.children.len(), 1);
+        assert_eq!(projects.children[0].name, ".Projects.2024");
+
+        std::fs::write(format!("{}/subscriptions", base), "Projects\nProjects.2024\n").unwrap();
+        std::fs::write(format!("{}/.Projects.2024/cur/1.a.host:2,S", base), "Subject: a\r\n\r\nA").unwrap();
+        assert_eq!(remove_folder(&base, "", true).unwrap_err().kind(), std::io::ErrorKind::PermissionDenied);
+        let busy = remove_folder(&base, ".Projects.2024", false).unwrap_err();
+        assert_eq!(busy.kind(), std::io::ErrorKind::DirectoryNotEmpty);
+        remove_folder(&base, ".Projects.2024", true).unwrap();
+        remove_folder(&base, ".Projects", false).unwrap();
+        assert!(!std::path::Path::new(&format!("{}/.Projects", base)).exists());
+        assert_eq!(std::fs::read_to_string(format!("{}/subscriptions", base)).unwrap(), "");
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn attachments_are_listed_and_decoded() {
+        let raw = concat!(
+            "From: a@example.com\r\n",
+            "Subject: Files\r\n",
+            "Content-Type: multipart/mixed; boundary=\"b1\"\r\n",
+            "\r\n",
+            "--b1\r\n",
+            "Content-Type: multipart/alternative; boundary=\"b2\"\r\n",
+            "\r\n",
+            "--b2\r\n",
+            "Content-Type: text/plain\r\n\r\nSee attached.\r\n",
+            "--b2--\r\n",
+            "--b1\r\n",
+            "Content-Type: application/pdf; name=\"report.pdf\"\r\n",
+            "Content-Disposition: attachment; filename=\"../report.pdf\"\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "JVBERi0xLjQK\r\n",
+            "--b1\r\n",
+            "Content-Type: bogus\r\n",
+            "Content-Disposition: attachment\r\n",
+            "\r\n",
+            "raw bytes\r\n",
+            "--b1--\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        let parts = attachment_parts(&parsed);
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!(attachment_filename(parts[0], 0), "report.pdf");
+        assert_eq!(attachment_content_type(parts[0]), "application/pdf");
+        assert_eq!(parts[0].get_body_raw().unwrap(), b"%PDF-1.4\n");
+
+        assert_eq!(attachment_filename(parts[1], 1), "attachment-2");
+        assert_eq!(attachment_content_type(parts[1]), "application/octet-stream");
+
+        assert_eq!(urlencoding_simple("résumé 1.pdf"), "r%C3%A9sum%C3%A9%201.pdf");
+    }
+
+    #[test]
+    fn search_matches_headers_and_decoded_body() {
+        let base = std::env::temp_dir().join(format!("webmail_search_{}", uuid::Uuid::new_v4()));
+        let base = base.to_str().unwrap().to_string();
+        let root = folder_root(&base, ".Test");
+        std::fs::create_dir_all(format!("{}/cur", root)).unwrap();
+        std::fs::create_dir_all(format!("{}/new", root)).unwrap();
+        std::fs::write(
+            format!("{}/new/1700000001.a.host", root),
+            "From: Alice <alice@example.com>\r\nTo: bob@example.com\r\nSubject: Quarterly report\r\n\r\nSee attached.\r\n",
+        )
+        .unwrap();
+        std::fs::write(
+            format!("{}/cur/1700000002.b.host:2,S", root),
+            concat!(
+                "From: carol@example.com\r\nTo: bob@example.com\r\nSubject: Lunch\r\n",
+                "Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: base64\r\n\r\n",
+                "TWVldCBhdCB0aGUgQ2Fmw6kgYXQgbm9vbi4=\r\n",
+            ),
+        )
+        .unwrap();
+
+        let mut logs = Vec::new();
+        let subjects = |emails: Vec<super::WebmailEmail>| {
+            let mut s: Vec<String> = emails.into_iter().map(|e| e.subject).collect();
+            s.sort();
+            s
+        };
+        let (found, truncated) = search_emails(&base, ".Test", "QUARTERLY", &mut logs);
+        assert!(!truncated);
+        assert_eq!(subjects(found), vec!["Quarterly report"]);
+        // The base64 body is decoded before matching.
+        let (found, _) = search_emails(&base, ".Test", "café", &mut logs);
+        assert_eq!(subjects(found), vec!["Lunch"]);
+        let (found, _) = search_emails(&base, ".Test", "bob@example", &mut logs);
+        assert_eq!(subjects(found), vec!["Lunch", "Quarterly report"]);
+        let (found, _) = search_emails(&base, ".Test", "invoice", &mut logs);
+        assert!(found.is_empty());
+
+        std::fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn encoded_word_subject_and_from_are_decoded() {
+        let raw = concat!(
+            "From: =?UTF-8?Q?Andr=C3=A9_M=C3=BCller?= <andre@example.com>\r\n",
+            "To: =?iso-8859-1?Q?Jos=E9?= <jose@example.com>\r\n",
+            "Subject: =?UTF-8?B?0J/RgNC40LLQtdGC?=\r\n =?UTF-8?B?LCDQvNC40YA=?=\r\n",
+            "\r\n",
+            "body\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        assert_eq!(decoded_header(&parsed, "Subject"), "Привет, мир");
+        assert_eq!(decoded_header(&parsed, "From"), "André Müller <andre@example.com>");
+        assert_eq!(decoded_header(&parsed, "To"), "José <jose@example.com>");
+        assert_eq!(decoded_header(&parsed, "Cc"), "");
+    }
+
+    #[test]
+    fn quoted_printable_latin1_body_is_decoded() {
+        let raw = concat!(
+            "Subject: t\r\n",
+            "Content-Type: text/plain; charset=iso-8859-1\r\n",
+            "Content-Transfer-Encoding: quoted-printable\r\n",
+            "\r\n",
+            "Gr=FC=DFe, 2 + 2 =3D 4, caf=E9 au =\r\n",
+            "lait\r\n",
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        assert_eq!(extract_body(&parsed).trim_end(), "Grüße, 2 + 2 = 4, café au lait");
+    }
+
+    #[test]
+    fn base64_non_utf8_body_is_decoded() {
+        // "Привет, мир" in windows-1251, inside a multipart message.
+        let body = base64::engine::general_purpose::STANDARD
+            .encode([0xCF, 0xF0, 0xE8, 0xE2, 0xE5, 0xF2, 0x2C, 0x20, 0xEC, 0xE8, 0xF0]);
+        let raw = format!(
+            concat!(
+                "Subject: t\r\n",
+                "Content-Type: multipart/alternative; boundary=\"b\"\r\n",
+                "\r\n",
+                "--b\r\n",
+                "Content-Type: text/plain; charset=windows-1251\r\n",
+                "Content-Transfer-Encoding: base64\r\n",
+                "\r\n",
+                "{}\r\n",
+                "--b--\r\n",
+            ),
+            body
+        );
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        assert_eq!(extract_body(&parsed).trim_end(), "Привет, мир");
+    }
+
+    #[test]
+    fn unknown_charset_falls_back_to_utf8() {
+        let raw = "Subject: t\r\nContent-Type: text/plain; charset=x-made-up\r\n\r\nnaïve\r\n";
+        let parsed = mailparse::parse_mail(raw.as_bytes()).unwrap();
+        assert_eq!(extract_body(&parsed).trim_end(), "naïve");
+    }
+
     #[test]
     fn safe_folder_empty_is_inbox() {
         assert!(is_safe_folder(""));
@@ -0,0 +1,193 @@
+//! Webhook delivery with retries.
+//!
+//! Admin events and tracking opens are queued in `webhook_queue` instead of
+//! being posted once.  A background worker delivers due rows and records
+//! every attempt in `webhook_logs`.  Network errors, timeouts, 408, 429 and
+//! 5xx responses are retried with exponential backoff (30 s, 1 min, 2 min,
+//! … capped at an hour) until `MAX_ATTEMPTS` attempts have been made.
+//! Other responses are final.  Each attempt is signed with the
+//! `webhook_secret` setting, see [`WebhookSignature`].
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+
+use crate::db::{Database, QueuedWebhook};
+use crate::web::WebhookSignature;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const BATCH_SIZE: i64 = 20;
+/// How long a claimed delivery is hidden from other polls; comfortably longer
+/// than a batch of 10-second HTTP timeouts.
+const CLAIM_LEASE_SECS: i64 = 600;
+pub const MAX_ATTEMPTS: i32 = 8;
+const BASE_DELAY_SECS: i64 = 30;
+const MAX_DELAY_SECS: i64 = 3600;
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Delivered,
+    Retry,
+    GiveUp,
+}
+
+/// Classify one attempt from its HTTP status and transport error.
+fn outcome(status: Option<i32>, error: &str) -> Outcome {
+    if !error.is_empty() {
+        return Outcome::Retry;
+    }
+    match status {
+        Some(200..=299) => Outcome::Delivered,
+        Some(408) | Some(429) | Some(500..) | None => Outcome::Retry,
+        Some(_) => Outcome::GiveUp,
+    }
+}
+
+/// Wait before the next try after `attempts` failed attempts.
+fn retry_delay(attempts: i32) -> chrono::Duration {
+    let exponent = attempts.clamp(1, 16) as u32 - 1;
+    chrono::Duration::seconds((BASE_DELAY_SECS << exponent).min(MAX_DELAY_SECS))
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Queue `request_body` for delivery to `url`.  `attempts` is the number of
+/// attempts already made elsewhere: 0 queues it for the next poll, more
+/// schedules it like a retry.
+pub fn enqueue(
+    db: &Database,
+    url: &str,
+    request_body: &str,
+    sender: &str,
+    subject: &str,
+    attempts: i32,
+) -> Option<i64> {
+    let next = if attempts == 0 {
+        Utc::now()
+    } else {
+        Utc::now() + retry_delay(attempts)
+    };
+    db.enqueue_webhook(
+        url,
+        request_body,
+        sender,
+        subject,
+        attempts,
+        &timestamp(next),
+    )
+}
+
+/// Whether a failed attempt with this result is worth queueing for a retry.
+pub fn should_retry(status: Option<i32>, error: &str) -> bool {
+    outcome(status, error) == Outcome::Retry
+}
+
+/// Make one delivery attempt for `item`, log it, and remove or reschedule it.
+fn deliver(db: &Database, item: &QueuedWebhook) {
+    // Signed per attempt so the timestamp header is always fresh.
+    let secret = db.get_setting("webhook_secret").unwrap_or_default();
+    let signature = WebhookSignature::sign(&secret, &item.request_body);
+    let start = std::time::Instant::now();
+    let (status, body, error) = crate::web::post_json_webhook(
+        &item.url,
+        &item.request_body,
+        signature.as_ref(),
+        &item.sender,
+    );
+    db.log_webhook(
+        &item.url,
+        &item.request_body,
+        status,
+        &body,
+        &error,
+        start.elapsed().as_millis() as i64,
+        &item.sender,
+        &item.subject,
+    );
+
+    let attempts = item.attempts + 1;
+    match outcome(status, &error) {
+        Outcome::Delivered => db.delete_queued_webhook(item.id),
+        Outcome::Retry if attempts < MAX_ATTEMPTS => {
+            let next = Utc::now() + retry_delay(attempts);
+            let reason = if error.is_empty() {
+                format!("HTTP {}", status.unwrap_or(0))
+            } else {
+                error
+            };
+            debug!(
+                "[webhook] {} to {} failed (attempt {}/{}), retrying at {}",
+                item.sender, item.url, attempts, MAX_ATTEMPTS, next
+            );
+            db.reschedule_webhook(item.id, attempts, &timestamp(next), &reason);
+        }
+        result => {
+            warn!(
+                "[webhook] giving up on {} to {} after {} attempt(s) ({})",
+                item.sender,
+                item.url,
+                attempts,
+                if result == Outcome::Retry {
+                    "too many failures"
+                } else {
+                    "rejected"
+                }
+            );
+            db.delete_queued_webhook(item.id);
+        }
+    }
+}
+
+fn deliver_due(db: &Database) {
+    loop {
+        let lease = timestamp(Utc::now() + chrono::Duration::seconds(CLAIM_LEASE_SECS));
+        let due = db.claim_due_webhooks(BATCH_SIZE, &lease);
+        if due.is_empty() {
+            return;
+        }
+        debug!("[webhook] delivering {} queued webhook(s)", due.len());
+        for item in &due {
+            deliver(db, item);
+        }
+    }
+}
+
+/// Start the background thread that delivers queued webhooks.
+pub fn start_worker(db: Database) {
+    info!(
+        "[webhook] starting delivery worker (up to {} attempts)",
+        MAX_ATTEMPTS
+    );
+    crate::shutdown::spawn("webhook worker", move || loop {
+        deliver_due(&db);
+        if crate::shutdown::wait(POLL_INTERVAL) {
+            break;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_transient_failures_are_retried() {
+        assert_eq!(outcome(Some(204), ""), Outcome::Delivered);
+        assert_eq!(outcome(None, "connection refused"), Outcome::Retry);
+        assert_eq!(outcome(Some(503), ""), Outcome::Retry);
+        assert_eq!(outcome(Some(429), ""), Outcome::Retry);
+        assert_eq!(outcome(Some(404), ""), Outcome::GiveUp);
+        assert!(should_retry(Some(500), ""));
+        assert!(!should_retry(Some(400), ""));
+    }
+
+    #[test]
+    fn retry_delay_doubles_up_to_an_hour() {
+        let secs: Vec<i64> = (1..=9).map(|n| retry_delay(n).num_seconds()).collect();
+        assert_eq!(secs, vec![30, 60, 120, 240, 480, 960, 1920, 3600, 3600]);
+        assert_eq!(retry_delay(100).num_seconds(), 3600);
+    }
+}